@@ -0,0 +1,57 @@
+//! Microbenchmark of polling a mostly empty `CallbackCell`, comparing `take_call`, which always
+//! swaps, with `take_call_fast`, which loads first.
+//!
+//! A consumer thread polls the cell in a loop, while a producer thread occasionally puts a
+//! callback. Run with `cargo run --release --example empty_take_call`.
+
+use callback_cell::CallbackCell;
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const POLLS: u32 = 50_000_000;
+
+fn bench(name: &str, poll: fn(&CallbackCell) -> bool) {
+    let cell = Arc::new(CallbackCell::new());
+    let done = Arc::new(AtomicBool::new(false));
+    let producer = thread::spawn({
+        let cell = Arc::clone(&cell);
+        let done = Arc::clone(&done);
+        move || {
+            let mut puts = 0u32;
+            while !done.load(Ordering::Relaxed) {
+                cell.put(|| ());
+                puts += 1;
+                thread::sleep(Duration::from_micros(100));
+            }
+            puts
+        }
+    });
+
+    let start = Instant::now();
+    let mut ran = 0u32;
+    for _ in 0..POLLS {
+        ran += poll(&cell) as u32;
+    }
+    let elapsed = start.elapsed();
+    done.store(true, Ordering::Relaxed);
+    let puts = producer.join().unwrap();
+
+    println!(
+        "{}: {:.2} ns per poll, ran {} of {} callbacks",
+        name,
+        elapsed.as_nanos() as f64 / POLLS as f64,
+        ran,
+        puts,
+    );
+}
+
+fn main() {
+    bench("take_call", CallbackCell::take_call);
+    bench("take_call_fast", CallbackCell::take_call_fast);
+}
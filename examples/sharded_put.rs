@@ -0,0 +1,92 @@
+//! Microbenchmark of many producers putting into one logical cell, comparing a `CallbackCell`
+//! with a `ShardedCallbackCell`.
+//!
+//! 32 producer threads put callbacks in a loop, while a consumer thread takes and runs them.
+//! Run with `cargo run --release --example sharded_put`.
+
+use callback_cell::{CallbackCell, ShardedCallbackCell};
+use std::{
+    sync::{
+        Arc,
+        Barrier,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Instant,
+};
+
+const PRODUCERS: usize = 32;
+const PUTS: u32 = 100_000;
+
+fn bench<C: Send + Sync + 'static>(
+    name: &str,
+    cell: C,
+    put: fn(&C, Box<dyn FnOnce() + Send>),
+    take: fn(&C) -> usize,
+) {
+    let cell = Arc::new(cell);
+    let done = Arc::new(AtomicBool::new(false));
+    let barrier = Arc::new(Barrier::new(PRODUCERS + 1));
+    let consumer = thread::spawn({
+        let cell = Arc::clone(&cell);
+        let done = Arc::clone(&done);
+        move || {
+            let mut ran = 0;
+            while !done.load(Ordering::Relaxed) {
+                ran += take(&cell);
+            }
+            ran + take(&cell)
+        }
+    });
+    let producers: Vec<_> = (0..PRODUCERS)
+        .map(|_| {
+            let cell = Arc::clone(&cell);
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                barrier.wait();
+                for _ in 0..PUTS {
+                    put(&cell, Box::new(|| ()));
+                }
+            })
+        })
+        .collect();
+
+    barrier.wait();
+    let start = Instant::now();
+    for producer in producers {
+        producer.join().unwrap();
+    }
+    let elapsed = start.elapsed();
+    done.store(true, Ordering::Relaxed);
+    let ran = consumer.join().unwrap();
+
+    let puts = PRODUCERS as u32 * PUTS;
+    println!(
+        "{}: {:.2} ns per put, ran {} of {} callbacks",
+        name,
+        elapsed.as_nanos() as f64 / puts as f64,
+        ran,
+        puts,
+    );
+}
+
+fn main() {
+    bench(
+        "CallbackCell",
+        CallbackCell::new(),
+        |cell, f| cell.put(f),
+        |cell| cell.take_call() as usize,
+    );
+    bench(
+        "ShardedCallbackCell<8>",
+        ShardedCallbackCell::<8>::new(),
+        |cell, f| cell.put(f),
+        ShardedCallbackCell::take_call_all_shards,
+    );
+    bench(
+        "ShardedCallbackCell<32>",
+        ShardedCallbackCell::<32>::new(),
+        |cell, f| cell.put(f),
+        ShardedCallbackCell::take_call_all_shards,
+    );
+}
@@ -0,0 +1,58 @@
+//! Microbenchmark of completing callbacks in a `CallbackSlotMap`, comparing a loop of `take_call`,
+//! which locks the map once per key, with `take_call_many`, which locks it once per batch.
+//!
+//! Other threads insert and remove callbacks throughout, contending for the lock, as a driver's
+//! submitting threads would. Run with `cargo run --release --example slot_map_batch`.
+
+use callback_cell::{CallbackKey, CallbackSlotMap};
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Instant,
+};
+
+const BATCH: usize = 64;
+const BATCHES: usize = 20_000;
+const CONTENDERS: usize = 2;
+
+type Batch = Vec<(CallbackKey, u64)>;
+
+fn bench(name: &str, complete: fn(&CallbackSlotMap<u64, u64>, Batch) -> u64) {
+    let map = CallbackSlotMap::new();
+    let done = AtomicBool::new(false);
+    let (elapsed, sum) = thread::scope(|s| {
+        for _ in 0..CONTENDERS {
+            s.spawn(|| {
+                while !done.load(Ordering::Relaxed) {
+                    let key = map.insert(|x| x);
+                    map.remove(key);
+                }
+            });
+        }
+        let mut elapsed = 0.0;
+        let mut sum = 0;
+        for _ in 0..BATCHES {
+            let batch = (0..BATCH as u64).map(|i| (map.insert(move |x| x + i), 1)).collect();
+            let start = Instant::now();
+            sum += complete(&map, batch);
+            elapsed += start.elapsed().as_nanos() as f64;
+        }
+        done.store(true, Ordering::Relaxed);
+        (elapsed, sum)
+    });
+    println!(
+        "{}: {:.2} ns per completion (checksum {})",
+        name,
+        elapsed / (BATCH * BATCHES) as f64,
+        sum,
+    );
+}
+
+fn main() {
+    bench("take_call loop", |map, batch| {
+        batch.into_iter().map(|(key, input)| map.take_call(key, input).unwrap()).sum()
+    });
+    bench("take_call_many", |map, batch| {
+        map.take_call_many(batch).outputs.into_iter().map(|(_, output)| output).sum()
+    });
+}
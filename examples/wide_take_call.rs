@@ -0,0 +1,77 @@
+//! Microbenchmark of `take_call` on many cells whose callbacks were put on another thread,
+//! comparing `CallbackCellArgs`, which loads the function pointer from the callback's heap
+//! allocation, with `WideCallbackCellArgs`, which swaps it out of the cell.
+//!
+//! A producer thread fills every cell, then the main thread takes and calls each. There are
+//! enough cells that their allocations are mostly out of cache by the time they're taken. Run
+//! with `cargo run --release --example wide_take_call --features wide`.
+
+use callback_cell::{CallbackCellArgs, WideCallbackCellArgs};
+use std::{thread, time::Instant};
+
+const CELLS: usize = 1 << 20;
+const ROUNDS: u32 = 5;
+
+trait Cell: Default + Sync {
+    fn put<F: FnOnce(u64) -> u64 + Send + 'static>(&self, f: F);
+    fn take_call(&self, input: u64) -> Result<u64, u64>;
+}
+
+impl Cell for CallbackCellArgs<u64, u64> {
+    fn put<F: FnOnce(u64) -> u64 + Send + 'static>(&self, f: F) {
+        CallbackCellArgs::put(self, f)
+    }
+
+    fn take_call(&self, input: u64) -> Result<u64, u64> {
+        CallbackCellArgs::take_call(self, input)
+    }
+}
+
+impl Cell for WideCallbackCellArgs<u64, u64> {
+    fn put<F: FnOnce(u64) -> u64 + Send + 'static>(&self, f: F) {
+        WideCallbackCellArgs::put(self, f)
+    }
+
+    fn take_call(&self, input: u64) -> Result<u64, u64> {
+        WideCallbackCellArgs::take_call(self, input)
+    }
+}
+
+fn bench<C: Cell>(name: &str, capturing: bool) {
+    let cells: Vec<C> = (0..CELLS).map(|_| C::default()).collect();
+    let mut total = 0.0;
+    let mut sum = 0u64;
+    for _ in 0..ROUNDS {
+        thread::scope(|s| {
+            s.spawn(|| {
+                for (i, cell) in cells.iter().enumerate() {
+                    if capturing {
+                        let k = i as u64;
+                        cell.put(move |x| x + k);
+                    } else {
+                        cell.put(|x| x + 1);
+                    }
+                }
+            });
+        });
+        let start = Instant::now();
+        for cell in &cells {
+            sum += cell.take_call(1).unwrap();
+        }
+        total += start.elapsed().as_nanos() as f64;
+    }
+    println!(
+        "{} ({}): {:.2} ns per take_call (checksum {})",
+        name,
+        if capturing { "capturing" } else { "zero-sized" },
+        total / (CELLS as f64 * ROUNDS as f64),
+        sum,
+    );
+}
+
+fn main() {
+    for capturing in [false, true] {
+        bench::<CallbackCellArgs<u64, u64>>("CallbackCellArgs", capturing);
+        bench::<WideCallbackCellArgs<u64, u64>>("WideCallbackCellArgs", capturing);
+    }
+}
@@ -0,0 +1,44 @@
+/*
+ * C API for the callback_cell crate, enabled with its `capi` feature.
+ *
+ * See the documentation of the `callback_cell::capi` module for the precise
+ * ownership rules. In short: `callback_cell_put` transfers ownership of `data`
+ * into the cell, after which exactly one of `f(data)` (when the callback is
+ * taken and run) or `drop_data(data)` (when it is replaced or the cell is
+ * freed, if `drop_data` is not NULL) is called, exactly once.
+ *
+ * `f` and `drop_data` must not unwind or throw.
+ */
+
+#ifndef CALLBACK_CELL_H
+#define CALLBACK_CELL_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct callback_cell callback_cell_t;
+
+/* Construct a new cell with no callback. Never returns NULL. */
+callback_cell_t *callback_cell_new(void);
+
+/* Atomically set the callback, dropping any callback previously present. */
+void callback_cell_put(
+    const callback_cell_t *cell,
+    void (*f)(void *data),
+    void *data,
+    void (*drop_data)(void *data)
+);
+
+/* Atomically take the callback then run it. Returns 1 if a callback was
+ * present, 0 otherwise. */
+int callback_cell_take_call(const callback_cell_t *cell);
+
+/* Free a cell, dropping any callback present. Does nothing if cell is NULL. */
+void callback_cell_free(callback_cell_t *cell);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* CALLBACK_CELL_H */
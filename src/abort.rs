@@ -0,0 +1,33 @@
+
+// internals
+// ---------
+//
+// support for the `take_call_abort_on_panic` methods. a guard is held while
+// the callback runs, and forgotten once it returns. if the callback unwinds
+// instead, the guard is dropped during unwinding, and aborts the process.
+
+struct AbortOnUnwind {
+    kind: &'static str,
+    name: Option<&'static str>,
+}
+
+impl Drop for AbortOnUnwind {
+    fn drop(&mut self) {
+        match self.name {
+            Some(name) => std::eprintln!(
+                "callback_cell: callback in {} {:?} panicked, aborting",
+                self.kind, name,
+            ),
+            None => std::eprintln!("callback_cell: callback in {} panicked, aborting", self.kind),
+        }
+        std::process::abort();
+    }
+}
+
+// run `f`, aborting the process if it unwinds.
+pub(crate) fn call<R>(kind: &'static str, name: Option<&'static str>, f: impl FnOnce() -> R) -> R {
+    let guard = AbortOnUnwind { kind, name };
+    let r = f();
+    core::mem::forget(guard);
+    r
+}
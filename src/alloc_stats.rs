@@ -0,0 +1,35 @@
+//! Process-wide accounting of callback memory, behind the `alloc-stats`
+//! feature.
+//!
+//! While this is enabled, the size of each heap allocation the cells make to
+//! hold a callback (or an async callback and its future) is added to a global
+//! counter when it's made, and subtracted when it's freed.
+
+#[cfg(not(loom))]
+use crate::sync::AtomicUsize;
+#[cfg(loom)]
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+// the counter uses the atomics in `sync`, so it works with the
+// `portable-atomic` feature on targets whose own atomics have no `fetch_add`.
+// under loom, it uses core's, as those in `sync` can't be in a static.
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) fn on_alloc(size: usize) {
+    ALLOCATED.fetch_add(size, Ordering::Relaxed);
+}
+
+pub(crate) fn on_dealloc(size: usize) {
+    ALLOCATED.fetch_sub(size, Ordering::Relaxed);
+}
+
+/// The total size, in bytes, of callback allocations made and not yet freed,
+/// across all cells.
+///
+/// Each allocation is counted exactly once each way, so the total never
+/// drifts, though it may lag behind concurrent puts and takes.
+pub fn allocated_bytes() -> usize {
+    ALLOCATED.load(Ordering::Relaxed)
+}
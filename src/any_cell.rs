@@ -0,0 +1,195 @@
+
+use crate::{
+    sync::{self, AtomicBool},
+    raw,
+};
+use alloc::boxed::Box;
+use core::{
+    sync::atomic::Ordering,
+    cell::UnsafeCell,
+    any::{Any, TypeId},
+    mem::ManuallyDrop,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+
+// internals
+// ---------
+//
+// the callback, as an owned erased callback described in the `raw` module,
+// with the `TypeId` of the input it accepts, guarded by a spin lock. firing
+// checks the type while holding the lock, and only takes the callback if it
+// matches, so a mismatch leaves it in place without a window in which another
+// thread sees the cell empty. the lock is never held while a callback runs or
+// is dropped.
+//
+// the erased callback's input is either the box given to `fire_any`, which the
+// callback, knowing its type, downcasts and unboxes, or a pointer to the value
+// given to `fire`, which the callback reads, taking ownership of it. so the
+// typed path never boxes the value.
+
+enum Input {
+    Boxed(Box<dyn Any + Send>),
+    Value(*mut ()),
+}
+
+struct Entry {
+    type_id: TypeId,
+    callback: raw::Owned<Input, ()>,
+}
+
+/// A cell for a one-shot callback whose input type is chosen when it's
+/// registered, rather than by the cell's type.
+///
+/// For completion slots crossing a boundary which can't be generic over the
+/// payload, such as a plugin API which must stay object-safe:
+/// [`on`][Self::on] registers a callback taking some `T`, and
+/// [`fire_any`][Self::fire_any] fires it with a `Box<dyn Any + Send>`,
+/// running it if the box holds a `T`. If it doesn't, the box is handed back
+/// and the callback stays registered. When the caller does know the type,
+/// [`fire`][Self::fire] skips the box.
+///
+/// The callback and its type are guarded by a spin lock, held only briefly
+/// to check and update them, never while a callback runs or is dropped.
+pub struct AnyCallbackCell {
+    locked: AtomicBool,
+    entry: UnsafeCell<Option<Entry>>,
+}
+
+// safety: the entry is only accessed while holding the lock, and callbacks are
+//         Send, as are their inputs.
+unsafe impl Send for AnyCallbackCell {}
+unsafe impl Sync for AnyCallbackCell {}
+
+// the lock is never held while a callback runs or is dropped, and a callback is
+// taken out of the cell before it runs, so a panicking callback leaves the cell
+// empty.
+impl UnwindSafe for AnyCallbackCell {}
+impl RefUnwindSafe for AnyCallbackCell {}
+
+impl AnyCallbackCell {
+    const_fn! {
+        /// Construct with no callback.
+        ///
+        /// This is a `const fn`, so cells can be placed in statics.
+        pub fn new() -> Self {
+            AnyCallbackCell {
+                locked: AtomicBool::new(false),
+                entry: UnsafeCell::new(None),
+            }
+        }
+    }
+
+    // run the closure on the entry while holding the lock.
+    fn with_lock<R>(&self, f: impl FnOnce(&mut Option<Entry>) -> R) -> R {
+        while self.locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            sync::spin_loop();
+        }
+        let r = f(unsafe { &mut *self.entry.get() });
+        self.locked.store(false, Ordering::Release);
+        r
+    }
+
+    // take the callback if it accepts the given type.
+    fn take_if(&self, type_id: TypeId) -> Option<raw::Owned<Input, ()>> {
+        self.with_lock(|entry| match entry {
+            Some(Entry { type_id: accepts, .. }) if *accepts == type_id => entry.take(),
+            _ => None,
+        })
+        .map(|entry| entry.callback)
+    }
+
+    /// Atomically set the callback, accepting inputs of type `T`.
+    ///
+    /// Makes only one heap allocation. Any callback previously present,
+    /// whatever its type, is dropped.
+    pub fn on<T: Any + Send, F: FnOnce(T) + Send + 'static>(&self, f: F) {
+        let callback = move |input: Input| match input {
+            Input::Boxed(input) => match input.downcast::<T>() {
+                Ok(input) => f(*input),
+                Err(_) => unreachable!(),
+            },
+            // safety: `fire` only passes a pointer to a `T`, which it gives up.
+            Input::Value(input) => f(unsafe { input.cast::<T>().read() }),
+        };
+        let entry = Entry {
+            type_id: TypeId::of::<T>(),
+            callback: unsafe { raw::Owned::from_erased(raw::alloc_raw(callback)) },
+        };
+        drop(self.with_lock(|old| old.replace(entry)));
+    }
+
+    /// Atomically take the callback, if it accepts the type of the boxed
+    /// input, then run it with the unboxed input.
+    ///
+    /// Returns `Ok` if the callback ran. If no callback was present, or it
+    /// accepts another type, returns the box, leaving any callback present.
+    pub fn fire_any(&self, input: Box<dyn Any + Send>) -> Result<(), Box<dyn Any + Send>> {
+        match self.take_if((*input).type_id()) {
+            Some(callback) => {
+                callback.call(Input::Boxed(input));
+                Ok(())
+            }
+            None => Err(input),
+        }
+    }
+
+    /// Atomically take the callback, if it accepts `T`, then run it with the
+    /// given input.
+    ///
+    /// Like [`fire_any`][Self::fire_any], without boxing the input. Returns
+    /// `Ok` if the callback ran. If no callback was present, or it accepts
+    /// another type, returns the original input, leaving any callback
+    /// present.
+    pub fn fire<T: Any + Send>(&self, input: T) -> Result<(), T> {
+        match self.take_if(TypeId::of::<T>()) {
+            Some(callback) => {
+                let mut input = ManuallyDrop::new(input);
+                callback.call(Input::Value(&mut *input as *mut T as *mut ()));
+                Ok(())
+            }
+            None => Err(input),
+        }
+    }
+
+    /// Atomically take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        self.with_lock(|entry| entry.take()).is_some()
+    }
+
+    /// Whether a callback is currently present.
+    ///
+    /// This is only a snapshot: another thread may set or take a callback
+    /// immediately afterwards.
+    pub fn is_set(&self) -> bool {
+        self.with_lock(|entry| entry.is_some())
+    }
+
+    /// Whether a callback accepting `T` is currently present.
+    ///
+    /// This is only a snapshot, as for [`is_set`][Self::is_set].
+    pub fn accepts<T: Any>(&self) -> bool {
+        self.with_lock(|entry| matches!(entry, Some(entry) if entry.type_id == TypeId::of::<T>()))
+    }
+}
+
+impl Default for AnyCallbackCell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for AnyCallbackCell {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_set() {
+            f.write_str("AnyCallbackCell(NOT NULL)")
+        } else {
+            f.write_str("AnyCallbackCell(NULL)")
+        }
+    }
+}
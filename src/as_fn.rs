@@ -0,0 +1,123 @@
+
+use crate::CallbackCellArgs;
+#[cfg(target_has_atomic = "ptr")]
+use crate::MultiCallbackCellArgs;
+#[cfg(feature = "unstable-fn-traits")]
+use core::fmt::{self, Formatter, Debug};
+
+// internals
+// ---------
+//
+// the stable `as_closure` methods return a closure capturing only the
+// reference to the cell. with the `unstable-fn-traits` feature, `as_fn`
+// returns a named wrapper around the same reference, which implements the
+// `Fn` traits itself, so it can be named in types and stored without boxing.
+
+impl<I, O> CallbackCellArgs<I, O> {
+    /// Get a closure which calls [`take_call`][Self::take_call], returning
+    /// `None` and dropping the input if no callback is present.
+    ///
+    /// The closure captures only `&self`, so can be handed to APIs which take
+    /// a callback without any allocation.
+    pub fn as_closure(&self) -> impl Fn(I) -> Option<O> + '_ {
+        move |input| self.take_call(input).ok()
+    }
+}
+
+#[cfg(target_has_atomic = "ptr")]
+impl<I, O> MultiCallbackCellArgs<I, O> {
+    /// Get a closure which calls [`call`][Self::call], returning `None` and
+    /// dropping the input if no handler is present.
+    ///
+    /// The closure captures only `&self`, so can be handed to APIs which take
+    /// a callback without any allocation.
+    pub fn as_closure(&self) -> impl Fn(I) -> Option<O> + '_ {
+        move |input| self.call(input).ok()
+    }
+}
+
+/// A [`CallbackCellArgs`] as a function, returned by
+/// [`CallbackCellArgs::as_fn`].
+///
+/// Implements `Fn(I) -> Option<O>`, by calling
+/// [`take_call`][CallbackCellArgs::take_call]. Behind the unstable
+/// `unstable-fn-traits` feature, which requires nightly.
+#[cfg(feature = "unstable-fn-traits")]
+pub struct CellFn<'a, I, O>(&'a CallbackCellArgs<I, O>);
+
+/// A [`MultiCallbackCellArgs`] as a function, returned by
+/// [`MultiCallbackCellArgs::as_fn`].
+///
+/// Implements `Fn(I) -> Option<O>`, by calling
+/// [`call`][MultiCallbackCellArgs::call]. Behind the unstable
+/// `unstable-fn-traits` feature, which requires nightly.
+#[cfg(all(target_has_atomic = "ptr", feature = "unstable-fn-traits"))]
+pub struct MultiCellFn<'a, I, O>(&'a MultiCallbackCellArgs<I, O>);
+
+#[cfg(feature = "unstable-fn-traits")]
+impl<I, O> CallbackCellArgs<I, O> {
+    /// Get a function which calls [`take_call`][Self::take_call].
+    ///
+    /// Like [`as_closure`][Self::as_closure], but the function has a
+    /// nameable type. See [`CellFn`].
+    pub fn as_fn(&self) -> CellFn<'_, I, O> {
+        CellFn(self)
+    }
+}
+
+#[cfg(all(target_has_atomic = "ptr", feature = "unstable-fn-traits"))]
+impl<I, O> MultiCallbackCellArgs<I, O> {
+    /// Get a function which calls [`call`][Self::call].
+    ///
+    /// Like [`as_closure`][Self::as_closure], but the function has a
+    /// nameable type. See [`MultiCellFn`].
+    pub fn as_fn(&self) -> MultiCellFn<'_, I, O> {
+        MultiCellFn(self)
+    }
+}
+
+// implement the `Fn` traits for a wrapper by calling the given method on the
+// cell.
+#[cfg(feature = "unstable-fn-traits")]
+macro_rules! impl_fn_traits {
+    ($wrapper:ident, $method:ident) => {
+        impl<I, O> FnOnce<(I,)> for $wrapper<'_, I, O> {
+            type Output = Option<O>;
+
+            extern "rust-call" fn call_once(self, (input,): (I,)) -> Option<O> {
+                self.0.$method(input).ok()
+            }
+        }
+
+        impl<I, O> FnMut<(I,)> for $wrapper<'_, I, O> {
+            extern "rust-call" fn call_mut(&mut self, (input,): (I,)) -> Option<O> {
+                self.0.$method(input).ok()
+            }
+        }
+
+        impl<I, O> Fn<(I,)> for $wrapper<'_, I, O> {
+            extern "rust-call" fn call(&self, (input,): (I,)) -> Option<O> {
+                self.0.$method(input).ok()
+            }
+        }
+
+        impl<I, O> Clone for $wrapper<'_, I, O> {
+            fn clone(&self) -> Self {
+                *self
+            }
+        }
+
+        impl<I, O> Copy for $wrapper<'_, I, O> {}
+
+        impl<I, O> Debug for $wrapper<'_, I, O> {
+            fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+                f.debug_tuple(stringify!($wrapper)).field(self.0).finish()
+            }
+        }
+    };
+}
+
+#[cfg(feature = "unstable-fn-traits")]
+impl_fn_traits!(CellFn, take_call);
+#[cfg(all(target_has_atomic = "ptr", feature = "unstable-fn-traits"))]
+impl_fn_traits!(MultiCellFn, call);
@@ -0,0 +1,162 @@
+
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    mem::{self, ManuallyDrop},
+    marker::PhantomData,
+};
+use alloc::boxed::Box;
+
+// internals
+// ---------
+//
+// an erased async callback is a non-null pointer to a heap allocation, which
+// is a `Box<Lazy<F, Fut>>`. a `Lazy` consists of:
+//
+// - an `unsafe fn(*mut u8, Option<I>) -> Option<BoxFuture<O>>`, stored type-
+//   erased, which, when called with the pointer:
+//
+//   - if the option is Some, runs the callback with the input, writes the
+//     returned future into the same heap allocation in place of the
+//     callback, and returns the heap allocation as a `BoxFuture<O>`
+//   - if the option is None, drops the callback without running it and
+//     deallocates the heap allocation
+// - the state, which is either the `F: FnOnce(I) -> Fut` value or the
+//   `Fut: Future<Output = O>` value
+//
+// so the callback and the future it returns share one heap allocation, and
+// taking the callback makes no further allocation.
+//
+// the function pointer is stored type-erased so that `Lazy` doesn't mention
+// `I`, and so is `'static` even if `I` isn't.
+
+/// Boxed future, as returned by the async cells.
+pub type BoxFuture<O> = Pin<Box<dyn Future<Output = O> + Send + 'static>>;
+
+type FnPtrType<I, O> = unsafe fn(*mut u8, Option<I>) -> Option<BoxFuture<O>>;
+
+#[repr(C)]
+struct Lazy<F, Fut> {
+    // must be first
+    fn_ptr: unsafe fn(),
+    state: State<F, Fut>,
+}
+
+enum State<F, Fut> {
+    Callback(F),
+    Future(Fut),
+    Done,
+}
+
+// the heap allocation is freed right after this, whether or not the callback
+// ran.
+#[cfg(any(test, feature = "test-util", feature = "alloc-stats"))]
+impl<F, Fut> Drop for Lazy<F, Fut> {
+    fn drop(&mut self) {
+        #[cfg(any(test, feature = "test-util"))]
+        crate::test_util::on_dealloc();
+        #[cfg(feature = "alloc-stats")]
+        crate::alloc_stats::on_dealloc(mem::size_of::<Self>());
+    }
+}
+
+impl<F, Fut: Future> Future for Lazy<F, Fut> {
+    type Output = Fut::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Fut::Output> {
+        // safety: the future is never moved out of the state, only dropped in
+        //         place
+        let state = unsafe { &mut self.get_unchecked_mut().state };
+        match state {
+            State::Future(fut) => {
+                let poll = unsafe { Pin::new_unchecked(fut) }.poll(cx);
+                if poll.is_ready() {
+                    *state = State::Done;
+                }
+                poll
+            }
+            State::Done => panic!("future polled after completion"),
+            State::Callback(_) => unreachable!(),
+        }
+    }
+}
+
+// allocate and initialize the heap allocation for an async callback. makes exactly one heap
+// allocation. the returned pointer is never null.
+pub(crate) fn alloc_raw<I, O, F, Fut>(f: F) -> *mut u8
+where
+    F: FnOnce(I) -> Fut + Send + 'static,
+    Fut: Future<Output = O> + Send + 'static,
+{
+    let fn_ptr: FnPtrType<I, O> = fn_ptr_impl::<I, O, F, Fut>;
+    let lazy = Lazy {
+        fn_ptr: unsafe { mem::transmute::<FnPtrType<I, O>, unsafe fn()>(fn_ptr) },
+        state: State::<F, Fut>::Callback(f),
+    };
+    #[cfg(any(test, feature = "test-util"))]
+    crate::test_util::on_alloc();
+    #[cfg(feature = "alloc-stats")]
+    crate::alloc_stats::on_alloc(mem::size_of::<Lazy<F, Fut>>());
+    Box::into_raw(Box::new(lazy)) as *mut u8
+}
+
+// implementation for the function pointer for a given callback type F.
+unsafe fn fn_ptr_impl<I, O, F, Fut>(ptr: *mut u8, run: Option<I>) -> Option<BoxFuture<O>>
+where
+    F: FnOnce(I) -> Fut + Send + 'static,
+    Fut: Future<Output = O> + Send + 'static,
+{
+    let mut lazy = Box::from_raw(ptr as *mut Lazy<F, Fut>);
+    let input = run?;
+    let f = match mem::replace(&mut lazy.state, State::Done) {
+        State::Callback(f) => f,
+        _ => unreachable!(),
+    };
+    // if this panics, the heap allocation is freed in state Done
+    lazy.state = State::Future(f(input));
+    Some(Box::into_pin(lazy))
+}
+
+// run the pointed to callback with the given input, returning its future. the pointer must be
+// non-null.
+pub(crate) unsafe fn call_raw<I, O>(ptr: *mut u8, input: I) -> BoxFuture<O> {
+    let fn_ptr = mem::transmute::<unsafe fn(), FnPtrType<I, O>>((ptr as *mut unsafe fn()).read());
+    fn_ptr(ptr, Some(input)).unwrap()
+}
+
+// drop the pointed to data, including freeing the heap allocation, without running the callback,
+// if the pointer is non-null.
+pub(crate) unsafe fn drop_raw<I, O>(ptr: *mut u8) {
+    if !ptr.is_null() {
+        let fn_ptr = mem::transmute::<unsafe fn(), FnPtrType<I, O>>((ptr as *mut unsafe fn()).read());
+        fn_ptr(ptr, None);
+    }
+}
+
+// an owned non-null erased async callback, which is dropped if not run.
+pub(crate) struct Owned<I, O> {
+    ptr: *mut u8,
+    _p: PhantomData<fn(I) -> O>,
+}
+
+// safety: erased async callbacks are Send.
+unsafe impl<I, O> Send for Owned<I, O> {}
+
+impl<I, O> Owned<I, O> {
+    // take ownership of the pointed to callback. the pointer must be non-null.
+    pub(crate) unsafe fn new(ptr: *mut u8) -> Self {
+        Owned { ptr, _p: PhantomData }
+    }
+
+    pub(crate) fn call(self, input: I) -> BoxFuture<O> {
+        let this = ManuallyDrop::new(self);
+        unsafe { call_raw(this.ptr, input) }
+    }
+}
+
+impl<I, O> Drop for Owned<I, O> {
+    fn drop(&mut self) {
+        unsafe { drop_raw::<I, O>(self.ptr) };
+    }
+}
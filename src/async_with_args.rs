@@ -0,0 +1,146 @@
+
+use crate::{
+    sync::{AtomicPtr, LoadMut},
+    async_raw::{self, BoxFuture},
+};
+use core::{
+    sync::atomic::Ordering,
+    ptr,
+    future::Future,
+    marker::PhantomData,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+
+// internals
+// ---------
+//
+// the inner atomic pointer is a nullable pointer to an erased async callback,
+// as described in the `async_raw` module.
+
+/// Like an `Atomic<Option<Box<dyn FnOnce(I) -> impl Future<Output = O> + Send + 'static>>>`.
+///
+/// It's a normal [`AsyncCallbackCell`][crate::AsyncCallbackCell] but with
+/// args.
+pub struct AsyncCallbackCellArgs<I, O> {
+    ptr: AtomicPtr<u8>,
+    // invariant in `I` and `O`, as with `CallbackCellArgs`.
+    _p: PhantomData<dyn FnOnce(I) -> BoxFuture<O> + Send + 'static>,
+}
+
+// safety: as with `CallbackCellArgs`, the callbacks stored in the cell are
+//         Send, and an `I` value never crosses threads through the cell. the
+//         `O` value is produced by the returned future, which is Send.
+unsafe impl<I, O> Send for AsyncCallbackCellArgs<I, O> {}
+unsafe impl<I, O> Sync for AsyncCallbackCellArgs<I, O> {}
+
+// as with `CallbackCellArgs`, a panic in a callback leaves the cell empty.
+impl<I, O> UnwindSafe for AsyncCallbackCellArgs<I, O> {}
+impl<I, O> RefUnwindSafe for AsyncCallbackCellArgs<I, O> {}
+
+impl<I, O> AsyncCallbackCellArgs<I, O> {
+    const_fn! {
+        /// Construct with no callback.
+        pub fn new() -> Self {
+            AsyncCallbackCellArgs {
+                ptr: AtomicPtr::new(ptr::null_mut()),
+                _p: PhantomData,
+            }
+        }
+    }
+
+    /// Atomically set the callback.
+    ///
+    /// Makes only one heap allocation. Any callback previously present is dropped.
+    pub fn put<F, Fut>(&self, f: F)
+    where
+        F: FnOnce(I) -> Fut + Send + 'static,
+        Fut: Future<Output = O> + Send + 'static,
+    {
+        unsafe {
+            // allocate and initialize heap allocation
+            let ptr = async_raw::alloc_raw(f);
+
+            // atomic put
+            let old_ptr = self.ptr.swap(ptr, Ordering::Release);
+
+            // clean up previous value
+            async_raw::drop_raw::<I, O>(old_ptr);
+        }
+    }
+
+    /// Atomically take the callback then run it with the given input,
+    /// returning its future.
+    ///
+    /// If a callback was not present, returns the original input. Dropping
+    /// the returned future before it completes drops the state it captured.
+    pub fn take_call(&self, input: I) -> Result<BoxFuture<O>, I> {
+        unsafe {
+            // atomic take
+            let ptr = self.ptr.swap(ptr::null_mut(), Ordering::Acquire);
+
+            // run it
+            if !ptr.is_null() {
+                Ok(async_raw::call_raw(ptr, input))
+            } else {
+                Err(input)
+            }
+        }
+    }
+
+    /// Whether a callback is currently present.
+    pub fn is_set(&self) -> bool {
+        !self.ptr.load(Ordering::Acquire).is_null()
+    }
+
+    /// Atomically take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        unsafe {
+            let ptr = self.ptr.swap(ptr::null_mut(), Ordering::Acquire);
+            async_raw::drop_raw::<I, O>(ptr);
+            !ptr.is_null()
+        }
+    }
+}
+
+#[cfg(feature = "unstable-async-fn")]
+impl<I, O> AsyncCallbackCellArgs<I, O> {
+    /// Atomically set the callback to an async closure.
+    ///
+    /// Like [`put`][Self::put], makes only one heap allocation, which holds
+    /// the closure and then the future it returns. Behind the unstable
+    /// `unstable-async-fn` feature, which requires nightly.
+    pub fn put_async<F>(&self, f: F)
+    where
+        F: AsyncFnOnce(I) -> O + Send + 'static,
+        F::CallOnceFuture: Send + 'static,
+    {
+        self.put(move |input| f.async_call_once((input,)));
+    }
+}
+
+impl<I, O> Drop for AsyncCallbackCellArgs<I, O> {
+    fn drop(&mut self) {
+        unsafe {
+            async_raw::drop_raw::<I, O>(self.ptr.load_mut());
+        }
+    }
+}
+
+impl<I, O> Default for AsyncCallbackCellArgs<I, O> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I, O> Debug for AsyncCallbackCellArgs<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.ptr.load(Ordering::Relaxed).is_null() {
+            f.write_str("AsyncCallbackCellArgs(NULL)")
+        } else {
+            f.write_str("AsyncCallbackCellArgs(NOT NULL)")
+        }
+    }
+}
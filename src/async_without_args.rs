@@ -0,0 +1,144 @@
+
+use crate::{
+    sync::{AtomicPtr, LoadMut},
+    async_raw::{self, BoxFuture},
+    Spawn,
+};
+use alloc::boxed::Box;
+use core::{
+    sync::atomic::Ordering,
+    ptr,
+    future::Future,
+    fmt::{self, Formatter, Debug},
+};
+
+// internals
+// ---------
+//
+// the inner atomic pointer is a nullable pointer to an erased async callback,
+// as described in the `async_raw` module, with `I = ()` and `O = ()`.
+
+/// Like an `Atomic<Option<Box<dyn FnOnce() -> impl Future<Output = ()> + Send + 'static>>>`.
+///
+/// Taking the callback runs it and returns its future, boxed. The callback and
+/// its future share one heap allocation, which is made by `put`, so taking the
+/// callback makes no further allocation.
+pub struct AsyncCallbackCell(AtomicPtr<u8>);
+
+impl AsyncCallbackCell {
+    const_fn! {
+        /// Construct with no callback.
+        pub fn new() -> Self {
+            AsyncCallbackCell(AtomicPtr::new(ptr::null_mut()))
+        }
+    }
+
+    /// Atomically set the callback.
+    ///
+    /// Makes only one heap allocation. Any callback previously present is dropped.
+    pub fn put<F, Fut>(&self, f: F)
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        unsafe {
+            // allocate and initialize heap allocation
+            let ptr = async_raw::alloc_raw(move |()| f());
+
+            // atomic put
+            let old_ptr = self.0.swap(ptr, Ordering::Release);
+
+            // clean up previous value
+            async_raw::drop_raw::<(), ()>(old_ptr);
+        }
+    }
+
+    /// Atomically take the callback then run it, returning its future.
+    ///
+    /// Returns `None` if no callback was present.
+    pub fn take_call(&self) -> Option<BoxFuture<()>> {
+        unsafe {
+            // atomic take
+            let ptr = self.0.swap(ptr::null_mut(), Ordering::Acquire);
+
+            // run it
+            if !ptr.is_null() {
+                Some(async_raw::call_raw(ptr, ()))
+            } else {
+                None
+            }
+        }
+    }
+
+    /// Atomically take the callback, then hand it to the spawner as a future
+    /// which runs it and awaits its future, rather than running it inline.
+    ///
+    /// Returns true if a callback was present. Since the callback itself also
+    /// runs in the background, a panic in it does not reach the caller.
+    pub fn take_spawn<S: Spawn + ?Sized>(&self, spawner: &S) -> bool {
+        let ptr = self.0.swap(ptr::null_mut(), Ordering::Acquire);
+        if !ptr.is_null() {
+            let callback = unsafe { async_raw::Owned::<(), ()>::new(ptr) };
+            spawner.spawn_future(Box::pin(async move { callback.call(()).await }));
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether a callback is currently present.
+    pub fn is_set(&self) -> bool {
+        !self.0.load(Ordering::Acquire).is_null()
+    }
+
+    /// Atomically take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        unsafe {
+            let ptr = self.0.swap(ptr::null_mut(), Ordering::Acquire);
+            async_raw::drop_raw::<(), ()>(ptr);
+            !ptr.is_null()
+        }
+    }
+}
+
+#[cfg(feature = "unstable-async-fn")]
+impl AsyncCallbackCell {
+    /// Atomically set the callback to an async closure.
+    ///
+    /// Like [`put`][Self::put], makes only one heap allocation, which holds
+    /// the closure and then the future it returns. Behind the unstable
+    /// `unstable-async-fn` feature, which requires nightly.
+    pub fn put_async<F>(&self, f: F)
+    where
+        F: AsyncFnOnce() + Send + 'static,
+        F::CallOnceFuture: Send + 'static,
+    {
+        self.put(move || f.async_call_once(()));
+    }
+}
+
+impl Drop for AsyncCallbackCell {
+    fn drop(&mut self) {
+        unsafe {
+            async_raw::drop_raw::<(), ()>(self.0.load_mut());
+        }
+    }
+}
+
+impl Default for AsyncCallbackCell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for AsyncCallbackCell {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.0.load(Ordering::Relaxed).is_null() {
+            f.write_str("AsyncCallbackCell(NULL)")
+        } else {
+            f.write_str("AsyncCallbackCell(NOT NULL)")
+        }
+    }
+}
@@ -0,0 +1,56 @@
+//! Cells which are thread-safe where threads exist, and local elsewhere.
+//!
+//! On single-threaded WebAssembly (`wasm32` without the `atomics` target
+//! feature), [`CallbackCell`] and [`CallbackCellArgs`] here are
+//! [`LocalCallbackCell`][crate::LocalCallbackCell] and
+//! [`LocalCallbackCellArgs`][crate::LocalCallbackCellArgs], whose callbacks
+//! need not be `Send`. On all other targets, they are the crate's usual
+//! thread-safe cells.
+//!
+//! Code which uses these, and bounds its callbacks with [`MaybeSend`] rather
+//! than `Send`, compiles for both without any cfgs of its own.
+
+/// Whether the target has threads, as far as the cells here are concerned.
+macro_rules! cfg_single_threaded {
+    (if { $($single:item)* } else { $($multi:item)* }) => {
+        $(
+            #[cfg(all(target_family = "wasm", not(target_feature = "atomics")))]
+            $single
+        )*
+        $(
+            #[cfg(not(all(target_family = "wasm", not(target_feature = "atomics"))))]
+            $multi
+        )*
+    };
+}
+
+cfg_single_threaded! {
+    if {
+        /// Alias for [`LocalCallbackCell`][crate::LocalCallbackCell] on this
+        /// target.
+        pub type CallbackCell = crate::LocalCallbackCell;
+
+        /// Alias for [`LocalCallbackCellArgs`][crate::LocalCallbackCellArgs]
+        /// on this target.
+        pub type CallbackCellArgs<I, O> = crate::LocalCallbackCellArgs<I, O>;
+
+        /// `Send` on targets with threads. On this target, implemented for
+        /// all types.
+        pub trait MaybeSend {}
+
+        impl<T: ?Sized> MaybeSend for T {}
+    } else {
+        /// Alias for [`CallbackCell`][crate::CallbackCell] on this target.
+        pub type CallbackCell = crate::CallbackCell;
+
+        /// Alias for [`CallbackCellArgs`][crate::CallbackCellArgs] on this
+        /// target.
+        pub type CallbackCellArgs<I, O> = crate::CallbackCellArgs<I, O>;
+
+        /// `Send` on targets with threads, such as this one. On
+        /// single-threaded targets, implemented for all types.
+        pub trait MaybeSend: Send {}
+
+        impl<T: ?Sized + Send> MaybeSend for T {}
+    }
+}
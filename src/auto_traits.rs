@@ -0,0 +1,97 @@
+
+// compile-time checks that the cells' auto traits are as intended.
+//
+// the thread-safe callback cells are Send, Sync, and unwind safe for any `I`
+// and `O`, since `I` and `O` values never cross threads through them, and a
+// panicking callback leaves its cell empty.
+//
+// `WakerCell` and `WaitableCallbackCell` are Send and Sync but deliberately
+// not `RefUnwindSafe`: if cloning a waker panics while registering it, the
+// waker state is left locked. `StatefulCallbackCell` isn't either, since a
+// panicking callback may leave its state broken.
+//
+// the local cells' lack of Send and Sync is checked by the compile-fail tests
+// in `tests/ui`.
+
+use crate::*;
+use core::panic::{UnwindSafe, RefUnwindSafe};
+
+fn thread_safe<T: Send + Sync + UnwindSafe + RefUnwindSafe>() {}
+
+fn send_sync<T: Send + Sync>() {}
+
+#[allow(dead_code)]
+fn check<I, O>() {
+    thread_safe::<CallbackCell>();
+    thread_safe::<CallbackCellArgs<I, O>>();
+    thread_safe::<TakeGuard<'static, u32, u32>>();
+    thread_safe::<CallbackCellInput<I>>();
+    thread_safe::<CallbackCellOutput<O>>();
+    thread_safe::<AsyncCallbackCell>();
+    thread_safe::<AsyncCallbackCellArgs<I, O>>();
+    thread_safe::<DeferredCallQueue<I, O>>();
+    #[cfg(feature = "futures")]
+    send_sync::<DeferredCallStream<'static, u32, u32>>();
+    #[cfg(feature = "futures")]
+    send_sync::<QueuedCall<O>>();
+    thread_safe::<DoubleBufferedCallbackQueue>();
+    thread_safe::<PriorityCallbackQueue<u32>>();
+    thread_safe::<EventCells>();
+    thread_safe::<CoalescingCell<u32>>();
+    thread_safe::<SealableCallbackCellArgs<I, O>>();
+    thread_safe::<FireOnceCallbackCellArgs<I, O>>();
+    thread_safe::<PausableCallbackCellArgs<I, O>>();
+    thread_safe::<CountdownCallbackCell>();
+    thread_safe::<StatefulCallbackQueue<core::cell::Cell<u32>>>();
+    thread_safe::<StickyEventCell<u32>>();
+    thread_safe::<CallbackSlotMap<I, O>>();
+    thread_safe::<ShutdownHooks>();
+    thread_safe::<AnyCallbackCell>();
+    thread_safe::<InterestCallbackCell>();
+    thread_safe::<MaskedCallbackCell>();
+    thread_safe::<CloneableCallbackCellArgs<I, O>>();
+    thread_safe::<RawCallback>();
+    thread_safe::<RawCallbackArgs<I, O>>();
+    thread_safe::<FutureCell>();
+    thread_safe::<StaticCallback<u32, u32>>();
+    thread_safe::<ArgsSlot<I, O>>();
+    thread_safe::<AtomicOptionBox<u32>>();
+    thread_safe::<Graveyard>();
+    #[cfg(feature = "std")]
+    thread_safe::<ThreadBoundCallbackCell>();
+    #[cfg(all(feature = "std", not(loom)))]
+    thread_safe::<ShardedCallbackBuffer>();
+    #[cfg(all(feature = "std", not(loom)))]
+    thread_safe::<ShardedCallbackCell<4>>();
+    #[cfg(all(feature = "std", not(loom)))]
+    thread_safe::<QuiescentCallbackCellArgs<I, O>>();
+    #[cfg(all(feature = "std", not(loom)))]
+    thread_safe::<BlockingCallbackQueue>();
+    #[cfg(all(feature = "std", not(loom)))]
+    thread_safe::<CallbackQueueSender>();
+    #[cfg(all(feature = "std", not(loom)))]
+    thread_safe::<CallbackQueueReceiver>();
+    #[cfg(feature = "stamped")]
+    thread_safe::<StampedCallbackCellArgs<I, O>>();
+    #[cfg(feature = "wide")]
+    thread_safe::<WideCallbackCellArgs<I, O>>();
+    #[cfg(feature = "crossbeam-epoch")]
+    thread_safe::<EpochCallbackCellFn<I, O>>();
+    #[cfg(target_has_atomic = "ptr")]
+    thread_safe::<MultiCallbackCellArgs<I, O>>();
+    #[cfg(target_has_atomic = "ptr")]
+    thread_safe::<ListenerRegistry<I>>();
+    #[cfg(target_has_atomic = "ptr")]
+    thread_safe::<DefaultingCallbackCellArgs<I, O>>();
+    #[cfg(target_has_atomic = "ptr")]
+    thread_safe::<Gate>();
+    #[cfg(target_has_atomic = "ptr")]
+    thread_safe::<GatedCallbackCell>();
+    #[cfg(target_has_atomic = "ptr")]
+    thread_safe::<GatedCallbackCellArgs<I, O>>();
+    #[cfg(target_has_atomic = "ptr")]
+    thread_safe::<SharedCallback<u32, u32, fn(u32) -> u32>>();
+    send_sync::<WakerCell>();
+    send_sync::<WaitableCallbackCell>();
+    send_sync::<StatefulCallbackCell<u32>>();
+}
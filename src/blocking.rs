@@ -0,0 +1,120 @@
+
+#[cfg(feature = "std")]
+use core::task::Waker;
+
+// internals
+// ---------
+//
+// ways for a thread to block until a cell's state may have changed, for the
+// cells' blocking waits. every wait has the same shape, in `wait_until`:
+// check, register the intent to be woken, check again, and only then block.
+// so a change which the first check misses either happens before the
+// registration, and is seen by the second check, or after, and wakes the
+// block, whichever strategy it is.
+//
+// parking registers a waker which unparks the thread, and parks it. a park
+// returns immediately if the thread was unparked since its last park.
+//
+// spinning needs no registration, since it never sleeps, except with the
+// `wfe` feature on arm and aarch64, where it waits for an event with `wfe`,
+// and `signal`, called after every change a spinning wait may be waiting for,
+// sends one with `sev`. the event register the `sev` sets persists until a
+// `wfe` clears it, so a `sev` between the second check and the `wfe` makes
+// the `wfe` return immediately. the intent to be woken is registered in
+// hardware, from the start.
+
+pub(crate) trait Strategy {
+    // register to be woken by the next change.
+    fn register(&mut self);
+
+    // block until woken, or for a while. returns false to give up instead.
+    fn block(&mut self) -> bool;
+}
+
+// wait until `check` returns a value, blocking with the strategy between
+// checks. returns `None` if the strategy gives up.
+pub(crate) fn wait_until<R>(strategy: &mut impl Strategy, mut check: impl FnMut() -> Option<R>) -> Option<R> {
+    loop {
+        if let Some(r) = check() {
+            return Some(r);
+        }
+        strategy.register();
+        if let Some(r) = check() {
+            return Some(r);
+        }
+        if !strategy.block() {
+            return None;
+        }
+    }
+}
+
+// blocks by parking the thread, having registered a waker which unparks it
+// with the given function.
+#[cfg(feature = "std")]
+pub(crate) struct Park<F: FnMut(&Waker)> {
+    waker: Waker,
+    register: F,
+}
+
+#[cfg(feature = "std")]
+impl<F: FnMut(&Waker)> Park<F> {
+    pub(crate) fn new(register: F) -> Self {
+        Park { waker: crate::waiters::thread_waker(), register }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<F: FnMut(&Waker)> Strategy for Park<F> {
+    fn register(&mut self) {
+        (self.register)(&self.waker);
+    }
+
+    fn block(&mut self) -> bool {
+        std::thread::park();
+        true
+    }
+}
+
+// blocks by spinning, or with the `wfe` feature on arm and aarch64, by waiting
+// for an event, up to the given number of times, if any.
+pub(crate) struct Spin(pub(crate) Option<usize>);
+
+impl Strategy for Spin {
+    fn register(&mut self) {}
+
+    fn block(&mut self) -> bool {
+        if let Some(remaining) = &mut self.0 {
+            if *remaining == 0 {
+                return false;
+            }
+            *remaining -= 1;
+        }
+        wait_for_event();
+        true
+    }
+}
+
+#[cfg(all(feature = "wfe", not(loom), any(target_arch = "arm", target_arch = "aarch64")))]
+fn wait_for_event() {
+    unsafe { core::arch::asm!("wfe", options(nostack, preserves_flags)) };
+}
+
+#[cfg(not(all(feature = "wfe", not(loom), any(target_arch = "arm", target_arch = "aarch64"))))]
+fn wait_for_event() {
+    crate::sync::spin_loop();
+}
+
+// wake spinning waits, after a change they may be waiting for. the barrier
+// makes the change visible to other cores before the event.
+#[cfg(all(feature = "wfe", not(loom), target_arch = "arm"))]
+pub(crate) fn signal() {
+    unsafe { core::arch::asm!("dsb", "sev", options(nostack, preserves_flags)) };
+}
+
+#[cfg(all(feature = "wfe", not(loom), target_arch = "aarch64"))]
+pub(crate) fn signal() {
+    unsafe { core::arch::asm!("dsb ish", "sev", options(nostack, preserves_flags)) };
+}
+
+#[cfg(not(all(feature = "wfe", not(loom), any(target_arch = "arm", target_arch = "aarch64"))))]
+pub(crate) fn signal() {}
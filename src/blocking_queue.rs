@@ -0,0 +1,414 @@
+
+use crate::{
+    sync::{self, AtomicBool, AtomicUsize},
+    raw,
+    RawCallback,
+    Disconnected,
+};
+use alloc::{
+    collections::VecDeque,
+    sync::Arc,
+};
+use core::{
+    sync::atomic::Ordering,
+    cell::UnsafeCell,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+use std::thread::{self, Thread};
+
+// internals
+// ---------
+//
+// a queue of owned erased callbacks, described in the `raw` module, and a queue
+// of parked waiters, both guarded by a spin lock. the lock is never held while
+// a callback runs or is dropped, nor while a thread is unparked.
+//
+// a waiter only joins the waiter queue while holding the lock and finding the
+// callback queue empty. a push, while holding the lock, pushes its callback,
+// then pops the longest waiting waiter, if any, and marks it notified. so each
+// push wakes at most one waiter, and a waiter is only woken by a push. after
+// unlocking, it unparks the waiter's thread, which parks until it's marked
+// notified, then tries again from the start. if a `take_call` took the
+// callback first, it finds the queue empty, and joins the waiter queue again.
+//
+// nothing is stranded: while any waiter is queued, every callback in the queue
+// was pushed since the queue was last empty, and each push since then either
+// woke a waiter, which is on its way to take a callback, or found the waiter
+// queue empty, in which case every waiter now queued joined later, which it
+// only does finding the callback queue empty. so there are at least as many
+// notified waiters on their way as callbacks in the queue.
+//
+// a queue split into senders and a receiver, by `callback_queue`, also tracks,
+// under the same lock, whether all senders are gone, and whether the receiver
+// is. the last sender to drop marks the former, and wakes every waiter, which
+// then finds the callback queue empty and the senders gone, and stops waiting.
+// since that's under the lock, every push by a sender is ordered before it, so
+// the receiver takes every callback pushed before it stops. once the receiver
+// is gone, pushes hand their callbacks back rather than queueing them.
+
+struct Waiter {
+    thread: Thread,
+    notified: AtomicBool,
+}
+
+struct Inner {
+    callbacks: VecDeque<raw::Owned<(), ()>>,
+    waiters: VecDeque<Arc<Waiter>>,
+    senders_gone: bool,
+    receiver_gone: bool,
+}
+
+/// A queue of callbacks for a pool of worker threads, each of which blocks
+/// until it can take one and run it.
+///
+/// Each [`push`][Self::push] wakes exactly one thread blocked in
+/// [`wait_call`][Self::wait_call], if any, so there's no thundering herd,
+/// and each callback is run by exactly one thread. Threads which aren't
+/// workers can take callbacks without blocking, with
+/// [`take_call`][Self::take_call]. If one takes the callback a worker was
+/// woken for, the worker goes back to waiting.
+///
+/// Callbacks run in the order they were pushed, though on many threads,
+/// they may finish in any order. Each callback makes one heap allocation,
+/// and the queue and waiters are guarded by a spin lock, held only briefly
+/// to update them.
+pub struct BlockingCallbackQueue {
+    locked: AtomicBool,
+    inner: UnsafeCell<Inner>,
+}
+
+// safety: the inner data is only accessed while holding the lock, and
+//         callbacks are Send.
+unsafe impl Send for BlockingCallbackQueue {}
+unsafe impl Sync for BlockingCallbackQueue {}
+
+// the lock is never held while a callback runs or is dropped, and a callback is
+// taken out of the queue before it runs, so a panicking callback leaves the
+// queue consistent.
+impl UnwindSafe for BlockingCallbackQueue {}
+impl RefUnwindSafe for BlockingCallbackQueue {}
+
+impl BlockingCallbackQueue {
+    /// Construct with no callbacks.
+    ///
+    /// This is a `const fn`, so queues can be placed in statics.
+    pub const fn new() -> Self {
+        BlockingCallbackQueue {
+            locked: AtomicBool::new(false),
+            inner: UnsafeCell::new(Inner {
+                callbacks: VecDeque::new(),
+                waiters: VecDeque::new(),
+                senders_gone: false,
+                receiver_gone: false,
+            }),
+        }
+    }
+
+    // run the closure on the inner data while holding the lock.
+    fn with_lock<R>(&self, f: impl FnOnce(&mut Inner) -> R) -> R {
+        while self.locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            sync::spin_loop();
+        }
+        let r = f(unsafe { &mut *self.inner.get() });
+        self.locked.store(false, Ordering::Release);
+        r
+    }
+
+    /// Push a callback onto the back of the queue, then wake one thread
+    /// waiting in [`wait_call`][Self::wait_call], if any.
+    ///
+    /// Makes one heap allocation, plus one to grow the queue if it's full.
+    pub fn push<F: FnOnce() + Send + 'static>(&self, f: F) {
+        // only a sender's queue has a receiver to lose.
+        let _ = self.push_raw(raw::Owned::new(move |()| f()));
+    }
+
+    // push, unless the receiver is gone, in which case hand the callback back.
+    fn push_raw(&self, callback: raw::Owned<(), ()>) -> Result<(), raw::Owned<(), ()>> {
+        let woken = self.with_lock(|inner| {
+            if inner.receiver_gone {
+                return Err(callback);
+            }
+            inner.callbacks.push_back(callback);
+            let Some(waiter) = inner.waiters.pop_front() else {
+                return Ok(None);
+            };
+            waiter.notified.store(true, Ordering::Release);
+            Ok(Some(waiter))
+        })?;
+        if let Some(waiter) = woken {
+            waiter.thread.unpark();
+        }
+        Ok(())
+    }
+
+    /// Take the callback at the front of the queue, without blocking, then
+    /// run it.
+    ///
+    /// Returns true if a callback was present.
+    pub fn take_call(&self) -> bool {
+        match self.with_lock(|inner| inner.callbacks.pop_front()) {
+            Some(callback) => {
+                callback.call(());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Block until a callback is present, then take the callback at the
+    /// front of the queue, and run it.
+    ///
+    /// Parks the thread while waiting. Any number of threads may wait at
+    /// once, and each push wakes the one which has waited longest. If
+    /// another thread takes the callback first, this keeps waiting.
+    pub fn wait_call(&self) {
+        if let Some(callback) = self.wait_take() {
+            callback.call(());
+        }
+    }
+
+    // block until a callback is present, and take it, or until the queue is
+    // empty and all senders are gone, returning None.
+    fn wait_take(&self) -> Option<raw::Owned<(), ()>> {
+        loop {
+            let waiter = self.with_lock(|inner| match inner.callbacks.pop_front() {
+                Some(callback) => Err(Some(callback)),
+                None if inner.senders_gone => Err(None),
+                None => {
+                    let waiter = Arc::new(Waiter {
+                        thread: thread::current(),
+                        notified: AtomicBool::new(false),
+                    });
+                    inner.waiters.push_back(Arc::clone(&waiter));
+                    Ok(waiter)
+                }
+            });
+            match waiter {
+                Err(callback) => return callback,
+                Ok(waiter) => {
+                    while !waiter.notified.load(Ordering::Acquire) {
+                        thread::park();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Drop every callback in the queue without running it.
+    ///
+    /// Returns the number of callbacks dropped.
+    pub fn clear(&self) -> usize {
+        let callbacks = self.with_lock(|inner| core::mem::take(&mut inner.callbacks));
+        callbacks.len()
+    }
+
+    /// The number of callbacks in the queue.
+    ///
+    /// This is only a snapshot: another thread may push or take a callback
+    /// immediately afterwards.
+    pub fn len(&self) -> usize {
+        self.with_lock(|inner| inner.callbacks.len())
+    }
+
+    /// Whether the queue is empty.
+    ///
+    /// This is only a snapshot, as for [`len`][Self::len].
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of threads currently waiting in
+    /// [`wait_call`][Self::wait_call], not counting those woken but not yet
+    /// running.
+    ///
+    /// This is only a snapshot, as for [`len`][Self::len].
+    pub fn waiting(&self) -> usize {
+        self.with_lock(|inner| inner.waiters.len())
+    }
+}
+
+impl Default for BlockingCallbackQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for BlockingCallbackQueue {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "BlockingCallbackQueue({} PENDING)", self.len())
+    }
+}
+
+// the queue shared by the senders and receiver of a `callback_queue`.
+struct Shared {
+    queue: BlockingCallbackQueue,
+    senders: AtomicUsize,
+}
+
+/// Handle which can push callbacks onto a queue shared with a
+/// [`CallbackQueueReceiver`].
+///
+/// Created with [`callback_queue`]. Senders can be cloned, to push from
+/// several threads. Once all are dropped, and the receiver has taken every
+/// callback pushed, its iterator ends.
+pub struct CallbackQueueSender(Arc<Shared>);
+
+/// Handle which takes callbacks from a queue shared with
+/// [`CallbackQueueSender`]s, blocking while it's empty.
+///
+/// Created with [`callback_queue`]. There's only one receiver per queue.
+/// Dropping it drops every callback in the queue, and further pushes report
+/// disconnection.
+pub struct CallbackQueueReceiver(Arc<Shared>);
+
+/// Create a new callback queue, split into a connected
+/// [`CallbackQueueSender`] and [`CallbackQueueReceiver`].
+///
+/// Like an mpsc channel of callbacks, built on a [`BlockingCallbackQueue`]:
+///
+/// ```
+/// use callback_cell::callback_queue;
+///
+/// let (sender, receiver) = callback_queue();
+/// let worker = std::thread::spawn(move || {
+///     for callback in receiver.iter_blocking() {
+///         callback.call();
+///     }
+/// });
+/// sender.push(|| println!("hello from the worker")).unwrap();
+/// drop(sender);
+/// worker.join().unwrap();
+/// ```
+pub fn callback_queue() -> (CallbackQueueSender, CallbackQueueReceiver) {
+    let shared = Arc::new(Shared {
+        queue: BlockingCallbackQueue::new(),
+        senders: AtomicUsize::new(1),
+    });
+    (CallbackQueueSender(Arc::clone(&shared)), CallbackQueueReceiver(shared))
+}
+
+impl CallbackQueueSender {
+    /// Push a callback onto the back of the queue, waking the receiver if
+    /// it's waiting.
+    ///
+    /// Makes one heap allocation, plus one to grow the queue if it's full. If
+    /// the receiver has been dropped, the callback is dropped without
+    /// running, and this returns an error.
+    pub fn push<F: FnOnce() + Send + 'static>(&self, f: F) -> Result<(), Disconnected> {
+        self.0.queue.push_raw(raw::Owned::new(move |()| f())).map_err(|_| Disconnected)
+    }
+}
+
+impl Clone for CallbackQueueSender {
+    fn clone(&self) -> Self {
+        self.0.senders.fetch_add(1, Ordering::Relaxed);
+        CallbackQueueSender(Arc::clone(&self.0))
+    }
+}
+
+impl Drop for CallbackQueueSender {
+    fn drop(&mut self) {
+        if self.0.senders.fetch_sub(1, Ordering::AcqRel) != 1 {
+            return;
+        }
+        let waiters = self.0.queue.with_lock(|inner| {
+            inner.senders_gone = true;
+            core::mem::take(&mut inner.waiters)
+        });
+        for waiter in waiters {
+            waiter.notified.store(true, Ordering::Release);
+            waiter.thread.unpark();
+        }
+    }
+}
+
+impl CallbackQueueReceiver {
+    /// Block until a callback is present, then take the callback at the
+    /// front of the queue, without running it.
+    ///
+    /// Returns `None` once the queue is empty and all senders have been
+    /// dropped, so that no callback will ever be pushed again.
+    pub fn recv(&self) -> Option<RawCallback> {
+        self.0.queue.wait_take().map(RawCallback::from)
+    }
+
+    /// Take the callback at the front of the queue, without blocking or
+    /// running it.
+    ///
+    /// Returns `None` if the queue is empty.
+    pub fn try_recv(&self) -> Option<RawCallback> {
+        self.0.queue.with_lock(|inner| inner.callbacks.pop_front()).map(RawCallback::from)
+    }
+
+    /// An iterator which takes callbacks in the order they were pushed,
+    /// blocking while the queue is empty, as for [`recv`][Self::recv].
+    ///
+    /// It ends once the queue is empty and all senders have been dropped.
+    /// Callbacks run from within the loop may push more, through senders
+    /// they hold, and since those senders are still live, the iterator
+    /// yields what they push too.
+    pub fn iter_blocking(&self) -> IterBlocking<'_> {
+        IterBlocking(self)
+    }
+
+    /// The number of callbacks in the queue.
+    ///
+    /// This is only a snapshot: a sender may push a callback immediately
+    /// afterwards.
+    pub fn len(&self) -> usize {
+        self.0.queue.len()
+    }
+
+    /// Whether the queue is empty.
+    ///
+    /// This is only a snapshot, as for [`len`][Self::len].
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Drop for CallbackQueueReceiver {
+    fn drop(&mut self) {
+        let callbacks = self.0.queue.with_lock(|inner| {
+            inner.receiver_gone = true;
+            core::mem::take(&mut inner.callbacks)
+        });
+        drop(callbacks);
+    }
+}
+
+/// Blocking iterator over the callbacks of a [`CallbackQueueReceiver`].
+///
+/// Created with [`CallbackQueueReceiver::iter_blocking`].
+pub struct IterBlocking<'a>(&'a CallbackQueueReceiver);
+
+impl Iterator for IterBlocking<'_> {
+    type Item = RawCallback;
+
+    fn next(&mut self) -> Option<RawCallback> {
+        self.0.recv()
+    }
+}
+
+impl Debug for CallbackQueueSender {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("CallbackQueueSender")
+    }
+}
+
+impl Debug for CallbackQueueReceiver {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "CallbackQueueReceiver({} PENDING)", self.len())
+    }
+}
+
+impl Debug for IterBlocking<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("IterBlocking")
+    }
+}
@@ -0,0 +1,431 @@
+
+use crate::sync::AtomicBool;
+use core::{
+    sync::atomic::Ordering,
+    cell::UnsafeCell,
+    hint,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+    boxed::Box,
+};
+use core::any::Any;
+
+// internals
+// ---------
+//
+// the listeners are a copy-on-write list: an `Arc` of a vec of `(id, Arc<dyn
+// Fn>)` entries, guarded by a spin lock. the lock is never held while calling
+// or dropping listeners.
+//
+// a dispatch takes a snapshot by cloning the outer `Arc`, then calls the
+// listeners in it without the lock. subscribing and unsubscribing edit the
+// list in place, or first replace it with a copy if a dispatch holds it. so a
+// listener subscribed during a dispatch isn't in its snapshot, and a listener
+// unsubscribed during a dispatch is only dropped once every snapshot holding
+// it is gone, on whichever thread releases the last one.
+//
+// each listener has a flag, set when it's removed from the list, which a
+// dispatch checks before calling it. so a dispatch in progress skips a
+// listener unsubscribed before reaching it, as though it were dead.
+//
+// a weak listener is dead once its `Weak` can't be upgraded. a dispatch skips
+// dead listeners, then removes them from the list, the same way unsubscribing
+// does, so the snapshot it iterated is unaffected.
+//
+// with `std`, a dispatch catches each listener's panic, so a panicking
+// listener is only reported, and the listeners after it still called. the
+// list isn't touched while calling listeners, so a panic can't leave it half
+// edited.
+
+// a listener, strong or weak.
+trait Listen<I>: Send + Sync {
+    // call the listener, returning false without calling it if it's dead.
+    fn call(&self, event: &I) -> bool;
+
+    fn is_alive(&self) -> bool;
+}
+
+struct Strong<F>(F);
+
+impl<I, F: Fn(&I) + Send + Sync> Listen<I> for Strong<F> {
+    fn call(&self, event: &I) -> bool {
+        (self.0)(event);
+        true
+    }
+
+    fn is_alive(&self) -> bool {
+        true
+    }
+}
+
+struct WeakListener<T, F> {
+    weak: Weak<T>,
+    f: F,
+}
+
+impl<I, T: Send + Sync, F: Fn(&Arc<T>, &I) + Send + Sync> Listen<I> for WeakListener<T, F> {
+    fn call(&self, event: &I) -> bool {
+        match self.weak.upgrade() {
+            Some(target) => {
+                (self.f)(&target, event);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        self.weak.strong_count() > 0
+    }
+}
+
+// a listener in the list, with whether it's been removed from it.
+struct Registered<L: ?Sized> {
+    removed: AtomicBool,
+    listener: L,
+}
+
+impl<I> Registered<dyn Listen<I> + 'static> {
+    // call the listener, returning false without calling it if it's dead or
+    // removed.
+    fn call(&self, event: &I) -> bool {
+        !self.removed.load(Ordering::Acquire) && self.listener.call(event)
+    }
+
+    fn is_alive(&self) -> bool {
+        self.listener.is_alive()
+    }
+}
+
+fn registered<L>(listener: L) -> Arc<Registered<L>> {
+    Arc::new(Registered {
+        removed: AtomicBool::new(false),
+        listener,
+    })
+}
+
+type Listener<I> = Arc<Registered<dyn Listen<I> + 'static>>;
+
+// call the listener, catching its panic with `std`.
+fn call_isolated<I>(listener: &Listener<I>, event: &I) -> Result<bool, Box<dyn Any + Send + 'static>> {
+    #[cfg(feature = "std")]
+    {
+        std::panic::catch_unwind(core::panic::AssertUnwindSafe(|| listener.call(event)))
+    }
+    #[cfg(not(feature = "std"))]
+    {
+        Ok(listener.call(event))
+    }
+}
+
+type List<I> = Arc<Vec<(u64, Listener<I>)>>;
+
+/// A registry of any number of listeners, each called by reference with
+/// every event dispatched.
+///
+/// Where [`MultiCallbackCellArgs`][crate::MultiCallbackCellArgs] holds one
+/// handler, this holds a list of them, identified by the
+/// [`ListenerId`]s returned from [`subscribe`][Self::subscribe]. Dispatching
+/// calls the listeners registered when the dispatch started, in the order
+/// they were subscribed.
+///
+/// Listeners may subscribe and unsubscribe listeners, including themselves,
+/// while being called. A listener subscribed during a dispatch is first
+/// called by the next dispatch. A listener unsubscribed during a dispatch
+/// isn't called by it if it hasn't been called yet.
+pub struct ListenerRegistry<I> {
+    locked: AtomicBool,
+    inner: UnsafeCell<Inner<I>>,
+}
+
+struct Inner<I> {
+    next_id: u64,
+    listeners: List<I>,
+}
+
+/// Identifies a listener in a [`ListenerRegistry`], for unsubscribing it.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ListenerId(u64);
+
+// safety: the inner data is only accessed while holding the lock, and the
+//         listeners are Send and Sync. `I` values never cross threads through
+//         the registry except by reference in `dispatch_par`, which requires
+//         `I: Sync`.
+unsafe impl<I> Send for ListenerRegistry<I> {}
+unsafe impl<I> Sync for ListenerRegistry<I> {}
+
+// the list is replaced whole while holding the lock, so a panic leaves it
+// either before or after the change.
+impl<I> UnwindSafe for ListenerRegistry<I> {}
+impl<I> RefUnwindSafe for ListenerRegistry<I> {}
+
+impl<I> ListenerRegistry<I> {
+    /// Construct with no listeners.
+    pub fn new() -> Self {
+        ListenerRegistry {
+            locked: AtomicBool::new(false),
+            inner: UnsafeCell::new(Inner {
+                next_id: 0,
+                listeners: Arc::new(Vec::new()),
+            }),
+        }
+    }
+
+    // run the closure on the inner data while holding the lock.
+    fn with_lock<R>(&self, f: impl FnOnce(&mut Inner<I>) -> R) -> R {
+        while self.locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            hint::spin_loop();
+        }
+        let r = f(unsafe { &mut *self.inner.get() });
+        self.locked.store(false, Ordering::Release);
+        r
+    }
+
+    // clone the current list.
+    fn snapshot(&self) -> List<I> {
+        self.with_lock(|inner| Arc::clone(&inner.listeners))
+    }
+
+    // run the closure on the list while holding the lock, copying the list
+    // first if a dispatch holds it. returns the closure's result and the old
+    // list if it was copied, to be dropped after unlocking.
+    fn edit<R>(&self, f: impl FnOnce(&mut Vec<(u64, Listener<I>)>, &mut u64) -> R) -> (R, Option<List<I>>) {
+        self.with_lock(|inner| {
+            let old = match Arc::get_mut(&mut inner.listeners) {
+                Some(_) => None,
+                None => {
+                    let copy = Arc::new(Vec::clone(&inner.listeners));
+                    Some(core::mem::replace(&mut inner.listeners, copy))
+                }
+            };
+            let r = f(Arc::get_mut(&mut inner.listeners).unwrap(), &mut inner.next_id);
+            (r, old)
+        })
+    }
+
+    /// Add a listener, which is called with every event dispatched after this
+    /// returns.
+    ///
+    /// A dispatch already in progress doesn't call it.
+    pub fn subscribe<F: Fn(&I) + Send + Sync + 'static>(&self, f: F) -> ListenerId {
+        self.subscribe_listener(registered(Strong(f)))
+    }
+
+    /// Add a listener which lives only as long as the target of `weak`.
+    ///
+    /// The listener is called, with the target, with every event dispatched
+    /// after this returns, until the target is dropped. Then it's skipped,
+    /// and removed from the registry by the next dispatch, or by
+    /// [`prune`][Self::prune], as though unsubscribed.
+    pub fn subscribe_weak<T, F>(&self, weak: Weak<T>, f: F) -> ListenerId
+    where
+        T: Send + Sync + 'static,
+        F: Fn(&Arc<T>, &I) + Send + Sync + 'static,
+    {
+        self.subscribe_listener(registered(WeakListener { weak, f }))
+    }
+
+    fn subscribe_listener(&self, listener: Listener<I>) -> ListenerId {
+        let (id, old) = self.edit(|listeners, next_id| {
+            let id = *next_id;
+            *next_id += 1;
+            listeners.push((id, listener));
+            id
+        });
+        drop(old);
+        ListenerId(id)
+    }
+
+    /// Remove a listener.
+    ///
+    /// Returns true if it was present. Dispatches already in progress skip it
+    /// from then on, unless they're already calling it: so a listener may
+    /// unsubscribe itself, or one after it, and a listener unsubscribed
+    /// during a dispatch on this thread isn't called again. The listener is
+    /// dropped once the last such dispatch finishes, or before this returns
+    /// if there are none.
+    pub fn unsubscribe(&self, id: ListenerId) -> bool {
+        let (removed, old) = self.edit(|listeners, _| {
+            let i = listeners.iter().position(|e| e.0 == id.0)?;
+            let removed = listeners.remove(i);
+            removed.1.removed.store(true, Ordering::Release);
+            Some(removed)
+        });
+        let present = removed.is_some();
+        drop(old);
+        drop(removed);
+        present
+    }
+
+    // remove the listeners with the given ids, if still present.
+    fn remove_all(&self, ids: &[u64]) {
+        let (removed, old) = self.edit(|listeners, _| {
+            let mut removed = Vec::new();
+            listeners.retain(|e| {
+                if ids.contains(&e.0) {
+                    e.1.removed.store(true, Ordering::Release);
+                    removed.push(Arc::clone(&e.1));
+                    false
+                } else {
+                    true
+                }
+            });
+            removed
+        });
+        drop(old);
+        drop(removed);
+    }
+
+    /// Remove every weak listener whose target has been dropped.
+    ///
+    /// Returns the number removed. Dispatching removes them too, so this is
+    /// only needed for a registry which isn't dispatched to often.
+    pub fn prune(&self) -> usize {
+        let dead = self.snapshot()
+            .iter()
+            .filter(|e| !e.1.is_alive())
+            .map(|e| e.0)
+            .collect::<Vec<_>>();
+        if !dead.is_empty() {
+            self.remove_all(&dead);
+        }
+        dead.len()
+    }
+
+    /// Number of listeners currently subscribed, including weak listeners
+    /// whose target has been dropped but which haven't yet been removed.
+    pub fn len(&self) -> usize {
+        self.with_lock(|inner| inner.listeners.len())
+    }
+
+    /// Whether no listeners are currently subscribed.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Call each listener with the event, one after another on this thread.
+    ///
+    /// Listeners subscribed or unsubscribed while this runs, including by the
+    /// listeners themselves, don't change which listeners it calls.
+    ///
+    /// With the `std` feature, a panic in one listener doesn't stop the
+    /// others: panics are caught and reported in the returned
+    /// [`DispatchReport`], which [`resume_unwind`][DispatchReport::resume_unwind]
+    /// can re-throw. Without it, the panic propagates and the remaining
+    /// listeners are not called. Either way, the registry is unaffected.
+    ///
+    /// Weak listeners found dead are removed once every listener has been
+    /// called. Listeners unsubscribed before being reached are skipped, as
+    /// for dead weak listeners.
+    pub fn dispatch(&self, event: &I) -> DispatchReport {
+        let snapshot = self.snapshot();
+        let mut delivered = 0;
+        let mut dead = Vec::new();
+        let mut panicked = Vec::new();
+        for (id, listener) in snapshot.iter() {
+            match call_isolated(listener, event) {
+                Ok(true) => delivered += 1,
+                Ok(false) => dead.push(*id),
+                Err(payload) => panicked.push(payload),
+            }
+        }
+        drop(snapshot);
+        if !dead.is_empty() {
+            self.remove_all(&dead);
+        }
+        DispatchReport {
+            delivered,
+            panicked,
+        }
+    }
+}
+
+/// Outcome of a [`ListenerRegistry::dispatch`], or `dispatch_par`.
+pub struct DispatchReport {
+    /// Number of listeners which returned normally, not counting dead weak
+    /// listeners, or listeners unsubscribed during the dispatch, which are
+    /// skipped.
+    pub delivered: usize,
+    /// Panic payloads of the listeners which panicked, in the order they
+    /// panicked.
+    pub panicked: Vec<Box<dyn Any + Send + 'static>>,
+}
+
+impl DispatchReport {
+    /// Re-throw the first panic caught, if any, dropping the rest.
+    ///
+    /// For callers which would rather a panicking listener panic the
+    /// dispatcher, once every listener has been called.
+    #[cfg(feature = "std")]
+    pub fn resume_unwind(self) {
+        if let Some(payload) = self.panicked.into_iter().next() {
+            std::panic::resume_unwind(payload);
+        }
+    }
+}
+
+impl Debug for DispatchReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("DispatchReport")
+            .field("delivered", &self.delivered)
+            .field("panicked", &self.panicked.len())
+            .finish()
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<I: Sync> ListenerRegistry<I> {
+    /// Call each listener with the event, in parallel on the rayon thread
+    /// pool, behind the `rayon` feature.
+    ///
+    /// Calls the same listeners as [`dispatch`][Self::dispatch], in no
+    /// particular order, and returns once all have finished. Panics are
+    /// caught and reported as for `dispatch`.
+    pub fn dispatch_par(&self, event: &I) -> DispatchReport {
+        use rayon::prelude::*;
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let snapshot = self.snapshot();
+        let results = snapshot
+            .par_iter()
+            .map(|(id, listener)| (*id, catch_unwind(AssertUnwindSafe(|| listener.call(event)))))
+            .collect::<Vec<_>>();
+        drop(snapshot);
+        let mut delivered = 0;
+        let mut dead = Vec::new();
+        let mut panicked = Vec::new();
+        for (id, result) in results {
+            match result {
+                Ok(true) => delivered += 1,
+                Ok(false) => dead.push(id),
+                Err(payload) => panicked.push(payload),
+            }
+        }
+        if !dead.is_empty() {
+            self.remove_all(&dead);
+        }
+        DispatchReport {
+            delivered,
+            panicked,
+        }
+    }
+}
+
+impl<I> Default for ListenerRegistry<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I> Debug for ListenerRegistry<I> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("ListenerRegistry").field("len", &self.len()).finish()
+    }
+}
@@ -0,0 +1,122 @@
+//! C API, behind the `capi` feature.
+//!
+//! The functions here are exported unmangled, for use from C or C++ when this
+//! crate is linked into a `staticlib` or `cdylib`. The corresponding header is
+//! `include/callback_cell.h` in the crate source.
+//!
+//! ## Ownership
+//!
+//! - `callback_cell_new` returns a cell owned by the caller, which must
+//!   eventually be passed to `callback_cell_free` exactly once.
+//! - `callback_cell_put` transfers ownership of `data` into the cell. From then
+//!   on, exactly one of the following happens, exactly once:
+//!
+//!   - `f(data)` is called, by the thread which calls `callback_cell_take_call`.
+//!     `f` then owns `data`, and `drop_data` is not called.
+//!   - `drop_data(data)` is called, if `drop_data` is not NULL, because the
+//!     callback was replaced by another `callback_cell_put` or the cell was
+//!     freed. This happens on the thread which does the replacing or freeing.
+//!
+//!   Since these may happen on a different thread than the one which called
+//!   `callback_cell_put`, `data` must be safe to send between threads.
+//!
+//! ## Panics and exceptions
+//!
+//! None of these functions unwind. `f` and `drop_data` must not unwind either
+//! (for example, by throwing a C++ exception); if they do, the process is
+//! aborted.
+
+use crate::CallbackCell;
+use core::{
+    ffi::{c_int, c_void},
+    mem::ManuallyDrop,
+};
+use alloc::boxed::Box;
+
+/// Opaque cell type, `callback_cell_t` in C.
+#[allow(non_camel_case_types)]
+pub type callback_cell_t = CallbackCell;
+
+// a callback registered from C.
+struct CCallback {
+    f: unsafe extern "C" fn(*mut c_void),
+    data: *mut c_void,
+    drop_data: Option<unsafe extern "C" fn(*mut c_void)>,
+}
+
+// safety: the C API requires that data be safe to send between threads.
+unsafe impl Send for CCallback {}
+
+impl CCallback {
+    fn call(self) {
+        let this = ManuallyDrop::new(self);
+        unsafe {
+            (this.f)(this.data);
+        }
+    }
+}
+
+impl Drop for CCallback {
+    fn drop(&mut self) {
+        if let Some(drop_data) = self.drop_data {
+            unsafe {
+                drop_data(self.data);
+            }
+        }
+    }
+}
+
+/// Construct a new cell with no callback.
+///
+/// The returned pointer is never NULL, and must be freed with
+/// [`callback_cell_free`].
+#[no_mangle]
+pub extern "C" fn callback_cell_new() -> *mut callback_cell_t {
+    Box::into_raw(Box::new(CallbackCell::new()))
+}
+
+/// Atomically set the callback, dropping any callback previously present.
+///
+/// See the [module-level docs][self] for the ownership of `data`.
+///
+/// # Safety
+///
+/// `cell` must be a live pointer returned by [`callback_cell_new`]. `f` and
+/// `drop_data` (if not NULL) must be safe to call with `data`.
+#[no_mangle]
+pub unsafe extern "C" fn callback_cell_put(
+    cell: *const callback_cell_t,
+    f: unsafe extern "C" fn(*mut c_void),
+    data: *mut c_void,
+    drop_data: Option<unsafe extern "C" fn(*mut c_void)>,
+) {
+    let callback = CCallback { f, data, drop_data };
+    (*cell).put(move || callback.call());
+}
+
+/// Atomically take the callback then run it.
+///
+/// Returns 1 if a callback was present, 0 otherwise.
+///
+/// # Safety
+///
+/// `cell` must be a live pointer returned by [`callback_cell_new`].
+#[no_mangle]
+pub unsafe extern "C" fn callback_cell_take_call(cell: *const callback_cell_t) -> c_int {
+    (*cell).take_call() as c_int
+}
+
+/// Free a cell, dropping any callback present.
+///
+/// Does nothing if `cell` is NULL.
+///
+/// # Safety
+///
+/// `cell` must be NULL or a live pointer returned by [`callback_cell_new`],
+/// which must not be used again afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn callback_cell_free(cell: *mut callback_cell_t) {
+    if !cell.is_null() {
+        drop(Box::from_raw(cell));
+    }
+}
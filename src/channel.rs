@@ -0,0 +1,325 @@
+
+use crate::{
+    sync::{AtomicUsize, AtomicBool, fence},
+    CallbackCell,
+    CallbackCellArgs,
+};
+use core::{
+    sync::atomic::Ordering,
+    fmt::{self, Formatter, Debug, Display},
+};
+use alloc::sync::Arc;
+
+// internals
+// ---------
+//
+// both halves share an `Arc`'d cell, along with the number of live setters and
+// whether the invoker is live.
+//
+// the last setter to drop decrements the count with release ordering, after
+// any put it made. so if the invoker finds the cell empty, then finds no live
+// setters with acquire ordering, any last put is visible to it, and it takes
+// from the cell once more before reporting disconnection.
+//
+// when the invoker drops, it marks itself dead and then clears the cell. when
+// a setter puts, it puts and then checks whether the invoker is dead, and if
+// so clears the cell. both sides issue a SeqCst fence in between, so at least
+// one of them sees the other's write, and a callback is never left in the
+// cell with no invoker to run it.
+
+struct Shared<C> {
+    cell: C,
+    setters: AtomicUsize,
+    invoker: AtomicBool,
+}
+
+impl<C> Shared<C> {
+    fn new(cell: C) -> Arc<Self> {
+        Arc::new(Shared {
+            cell,
+            setters: AtomicUsize::new(1),
+            invoker: AtomicBool::new(true),
+        })
+    }
+
+    // call after putting. returns whether the invoker is live.
+    fn check_invoker(&self) -> bool {
+        fence(Ordering::SeqCst);
+        self.invoker.load(Ordering::Relaxed)
+    }
+
+    // call before clearing on invoker drop.
+    fn drop_invoker(&self) {
+        self.invoker.store(false, Ordering::Relaxed);
+        fence(Ordering::SeqCst);
+    }
+
+    // call after finding the cell empty. returns whether all setters have
+    // been dropped.
+    fn setters_dropped(&self) -> bool {
+        self.setters.load(Ordering::Acquire) == 0
+    }
+
+    fn clone_setter(self: &Arc<Self>) -> Arc<Self> {
+        self.setters.fetch_add(1, Ordering::Relaxed);
+        Arc::clone(self)
+    }
+
+    fn drop_setter(&self) {
+        self.setters.fetch_sub(1, Ordering::Release);
+    }
+}
+
+/// Error from putting on a [`Setter`] or [`SetterArgs`] whose invoker has
+/// been dropped. The callback was dropped without running.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Disconnected;
+
+impl Display for Disconnected {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("invoker was dropped")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Disconnected {}
+
+/// Error from taking on an [`Invoker`] or [`InvokerArgs`] when no callback
+/// was present, holding the original input.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TakeCallError<I> {
+    /// No callback was present, but a setter is still live and may put one.
+    Empty(I),
+    /// No callback was present, and all setters have been dropped, so none
+    /// ever will be.
+    Disconnected(I),
+}
+
+impl<I> TakeCallError<I> {
+    /// Get the original input.
+    pub fn into_input(self) -> I {
+        match self {
+            TakeCallError::Empty(input) => input,
+            TakeCallError::Disconnected(input) => input,
+        }
+    }
+
+    /// Whether this is [`Disconnected`][Self::Disconnected].
+    pub fn is_disconnected(&self) -> bool {
+        matches!(self, TakeCallError::Disconnected(_))
+    }
+}
+
+impl<I> Display for TakeCallError<I> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            TakeCallError::Empty(_) => f.write_str("no callback present"),
+            TakeCallError::Disconnected(_) => f.write_str("no callback present and all setters were dropped"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<I: Debug> std::error::Error for TakeCallError<I> {}
+
+/// Handle which can register callbacks on a cell shared with an [`Invoker`].
+///
+/// Created with [`channel`]. Setters can be cloned, to let several parts of a
+/// program register callbacks. Dropping a setter doesn't affect a callback it
+/// put, but once all setters are dropped and the cell is empty, the invoker
+/// reports disconnection.
+pub struct Setter(Arc<Shared<CallbackCell>>);
+
+/// Handle which can run callbacks registered by the [`Setter`]s of a shared
+/// cell.
+///
+/// Created with [`channel`]. There is only one invoker per channel; to share
+/// it, wrap it in an `Arc`. Dropping the invoker drops the callback, if
+/// present, and further puts report disconnection.
+pub struct Invoker(Arc<Shared<CallbackCell>>);
+
+/// Create a new channel, a connected [`Setter`] and [`Invoker`] sharing one
+/// [`CallbackCell`] with no callback.
+pub fn channel() -> (Setter, Invoker) {
+    let shared = Shared::new(CallbackCell::new());
+    (Setter(Arc::clone(&shared)), Invoker(shared))
+}
+
+impl Setter {
+    /// Atomically set the callback. Any callback previously present is
+    /// dropped.
+    ///
+    /// If the invoker has been dropped, the callback is dropped without
+    /// running, and this returns an error.
+    pub fn put<F: FnOnce() + Send + 'static>(&self, f: F) -> Result<(), Disconnected> {
+        self.0.cell.put(f);
+        if self.0.check_invoker() {
+            Ok(())
+        } else {
+            self.0.cell.clear();
+            Err(Disconnected)
+        }
+    }
+
+    /// Whether a callback is currently present.
+    pub fn is_set(&self) -> bool {
+        self.0.cell.is_set()
+    }
+}
+
+impl Invoker {
+    /// Atomically take the callback then run it.
+    ///
+    /// If no callback was present, returns whether a setter is still live.
+    pub fn take_call(&self) -> Result<(), TakeCallError<()>> {
+        if self.0.cell.take_call() {
+            Ok(())
+        } else if !self.0.setters_dropped() {
+            Err(TakeCallError::Empty(()))
+        } else if self.0.cell.take_call() {
+            Ok(())
+        } else {
+            Err(TakeCallError::Disconnected(()))
+        }
+    }
+
+    /// Atomically take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        self.0.cell.clear()
+    }
+
+    /// Whether a callback is currently present.
+    pub fn is_set(&self) -> bool {
+        self.0.cell.is_set()
+    }
+}
+
+impl Clone for Setter {
+    fn clone(&self) -> Self {
+        Setter(self.0.clone_setter())
+    }
+}
+
+impl Drop for Setter {
+    fn drop(&mut self) {
+        self.0.drop_setter();
+    }
+}
+
+impl Drop for Invoker {
+    fn drop(&mut self) {
+        self.0.drop_invoker();
+        self.0.cell.clear();
+    }
+}
+
+impl Debug for Setter {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("Setter").field(&self.0.cell).finish()
+    }
+}
+
+impl Debug for Invoker {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("Invoker").field(&self.0.cell).finish()
+    }
+}
+
+/// Like a [`Setter`], but for a [`CallbackCellArgs`].
+///
+/// Created with [`channel_args`].
+pub struct SetterArgs<I, O>(Arc<Shared<CallbackCellArgs<I, O>>>);
+
+/// Like an [`Invoker`], but for a [`CallbackCellArgs`].
+///
+/// Created with [`channel_args`].
+pub struct InvokerArgs<I, O>(Arc<Shared<CallbackCellArgs<I, O>>>);
+
+/// Create a new channel, a connected [`SetterArgs`] and [`InvokerArgs`]
+/// sharing one [`CallbackCellArgs`] with no callback.
+pub fn channel_args<I, O>() -> (SetterArgs<I, O>, InvokerArgs<I, O>) {
+    let shared = Shared::new(CallbackCellArgs::new());
+    (SetterArgs(Arc::clone(&shared)), InvokerArgs(shared))
+}
+
+impl<I, O> SetterArgs<I, O> {
+    /// Atomically set the callback. Any callback previously present is
+    /// dropped.
+    ///
+    /// If the invoker has been dropped, the callback is dropped without
+    /// running, and this returns an error.
+    pub fn put<F: FnOnce(I) -> O + Send + 'static>(&self, f: F) -> Result<(), Disconnected> {
+        self.0.cell.put(f);
+        if self.0.check_invoker() {
+            Ok(())
+        } else {
+            self.0.cell.clear();
+            Err(Disconnected)
+        }
+    }
+
+    /// Whether a callback is currently present.
+    pub fn is_set(&self) -> bool {
+        self.0.cell.is_set()
+    }
+}
+
+impl<I, O> InvokerArgs<I, O> {
+    /// Atomically take the callback then run it with the given input.
+    ///
+    /// Returns the output if a callback was present. If a callback was not
+    /// present, returns the original input, and whether a setter is still
+    /// live.
+    pub fn take_call(&self, input: I) -> Result<O, TakeCallError<I>> {
+        match self.0.cell.take_call(input) {
+            Ok(output) => Ok(output),
+            Err(input) if !self.0.setters_dropped() => Err(TakeCallError::Empty(input)),
+            Err(input) => self.0.cell.take_call(input).map_err(TakeCallError::Disconnected),
+        }
+    }
+
+    /// Atomically take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        self.0.cell.clear()
+    }
+
+    /// Whether a callback is currently present.
+    pub fn is_set(&self) -> bool {
+        self.0.cell.is_set()
+    }
+}
+
+impl<I, O> Clone for SetterArgs<I, O> {
+    fn clone(&self) -> Self {
+        SetterArgs(self.0.clone_setter())
+    }
+}
+
+impl<I, O> Drop for SetterArgs<I, O> {
+    fn drop(&mut self) {
+        self.0.drop_setter();
+    }
+}
+
+impl<I, O> Drop for InvokerArgs<I, O> {
+    fn drop(&mut self) {
+        self.0.drop_invoker();
+        self.0.cell.clear();
+    }
+}
+
+impl<I, O> Debug for SetterArgs<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("SetterArgs").field(&self.0.cell).finish()
+    }
+}
+
+impl<I, O> Debug for InvokerArgs<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("InvokerArgs").field(&self.0.cell).finish()
+    }
+}
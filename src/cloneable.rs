@@ -0,0 +1,173 @@
+
+use crate::{
+    sync::{self, AtomicBool},
+    raw,
+};
+use core::{
+    sync::atomic::Ordering,
+    cell::UnsafeCell,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+
+// internals
+// ---------
+//
+// the callback, as an owned erased callback described in the `raw` module, with
+// a function pointer which clones it into a new erased callback, guarded by a
+// spin lock. a cloned take clones the callback while holding the lock, so the
+// callback stays in the cell throughout, and no take or put can move or drop it
+// mid-clone. the lock is never held while a callback runs or is dropped, only
+// while one is cloned. if the clone panics, the lock is released as the panic
+// unwinds, and the original is left in place.
+
+struct Entry<I, O> {
+    callback: raw::Owned<I, O>,
+    clone: unsafe fn(*const u8) -> *mut u8,
+}
+
+// releases the lock when dropped, even if a clone panics.
+struct Unlock<'a>(&'a AtomicBool);
+
+impl Drop for Unlock<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// A [`CallbackCellArgs`][crate::CallbackCellArgs] whose callback can also be
+/// run without being taken, by running a clone of it.
+///
+/// The callback is still an `FnOnce`, put with
+/// [`put_cloneable`][Self::put_cloneable], and [`take_call`][Self::take_call]
+/// takes and runs it as usual. [`take_call_cloned`][Self::take_call_cloned]
+/// instead runs a clone, leaving the original armed. This is for callbacks
+/// which are cheap to clone, such as continuations capturing a few `Arc`s,
+/// which are sometimes fired speculatively.
+///
+/// Unlike [`MultiCallbackCellArgs`][crate::MultiCallbackCellArgs], the
+/// callback needn't be `Fn`, and each run consumes its own copy.
+///
+/// The callback is guarded by a spin lock, held only briefly to update it,
+/// and while cloning it, but never while a callback runs or is dropped. So
+/// the cell is never seen empty because of a clone in progress, and a
+/// callback's `clone` must not use the cell it's in, or it deadlocks.
+pub struct CloneableCallbackCellArgs<I, O> {
+    locked: AtomicBool,
+    entry: UnsafeCell<Option<Entry<I, O>>>,
+}
+
+// safety: the entry is only accessed while holding the lock, and callbacks are
+//         Send. a callback is only cloned while holding the lock, so it's never
+//         accessed from two threads at once, and needn't be Sync. an `I` or
+//         `O` value never crosses threads through the cell.
+unsafe impl<I, O> Send for CloneableCallbackCellArgs<I, O> {}
+unsafe impl<I, O> Sync for CloneableCallbackCellArgs<I, O> {}
+
+// a panicking callback has already been taken out of the cell, or is a clone,
+// and a panicking clone leaves the original in place, with the lock released.
+impl<I, O> UnwindSafe for CloneableCallbackCellArgs<I, O> {}
+impl<I, O> RefUnwindSafe for CloneableCallbackCellArgs<I, O> {}
+
+impl<I, O> CloneableCallbackCellArgs<I, O> {
+    const_fn! {
+        /// Construct with no callback.
+        ///
+        /// This is a `const fn`, so cells can be placed in statics.
+        pub fn new() -> Self {
+            CloneableCallbackCellArgs {
+                locked: AtomicBool::new(false),
+                entry: UnsafeCell::new(None),
+            }
+        }
+    }
+
+    // run the closure on the entry while holding the lock.
+    fn with_lock<R>(&self, f: impl FnOnce(&mut Option<Entry<I, O>>) -> R) -> R {
+        while self.locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            sync::spin_loop();
+        }
+        let _unlock = Unlock(&self.locked);
+        f(unsafe { &mut *self.entry.get() })
+    }
+
+    /// Atomically set the callback.
+    ///
+    /// Makes only one heap allocation. Any callback previously present is
+    /// dropped.
+    pub fn put_cloneable<F>(&self, f: F)
+    where
+        F: FnOnce(I) -> O + Clone + Send + 'static,
+    {
+        let entry = Entry {
+            callback: unsafe { raw::Owned::from_erased(raw::alloc_raw(f)) },
+            clone: raw::clone_raw::<I, O, F>,
+        };
+        drop(self.with_lock(|old| old.replace(entry)));
+    }
+
+    /// Atomically take the callback then run it with the given input.
+    ///
+    /// Returns the output if a callback was present. Otherwise, returns the
+    /// original input.
+    pub fn take_call(&self, input: I) -> Result<O, I> {
+        match self.with_lock(|entry| entry.take()) {
+            Some(entry) => Ok(entry.callback.call(input)),
+            None => Err(input),
+        }
+    }
+
+    /// Clone the callback, then run the clone with the given input, leaving
+    /// the original in place.
+    ///
+    /// Makes one heap allocation, for the clone. Returns the clone's output
+    /// if a callback was present. Otherwise, returns the original input.
+    ///
+    /// The callback is cloned while holding the cell's lock, so concurrent
+    /// takes and puts wait for the clone, rather than finding the cell empty.
+    pub fn take_call_cloned(&self, input: I) -> Result<O, I> {
+        let clone = self.with_lock(|entry| {
+            entry.as_ref().map(|entry| unsafe {
+                raw::Owned::<I, O>::from_erased((entry.clone)(entry.callback.as_erased()))
+            })
+        });
+        match clone {
+            Some(clone) => Ok(clone.call(input)),
+            None => Err(input),
+        }
+    }
+
+    /// Atomically take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        self.with_lock(|entry| entry.take()).is_some()
+    }
+
+    /// Whether a callback is currently present.
+    ///
+    /// This is only a snapshot: another thread may put or take a callback
+    /// immediately afterwards.
+    pub fn is_set(&self) -> bool {
+        self.with_lock(|entry| entry.is_some())
+    }
+}
+
+impl<I, O> Default for CloneableCallbackCellArgs<I, O> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I, O> Debug for CloneableCallbackCellArgs<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_set() {
+            f.write_str("CloneableCallbackCellArgs(NOT NULL)")
+        } else {
+            f.write_str("CloneableCallbackCellArgs(NULL)")
+        }
+    }
+}
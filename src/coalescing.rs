@@ -0,0 +1,157 @@
+
+use crate::AtomicOptionBox;
+use alloc::boxed::Box;
+use core::{
+    mem,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+
+// internals
+// ---------
+//
+// two `AtomicOptionBox`es: the pending input, and the handler, boxed a second
+// time so that the cell holds a thin pointer.
+//
+// a pump takes the handler out of its slot, so that no other pump or put can
+// touch it while it runs, then takes the input. afterwards, it puts the
+// handler back only if the slot is still empty: if a new handler was set
+// meanwhile, the old one is dropped, as though replaced. a pump which finds
+// the handler slot empty leaves the input pending.
+
+type Handler<I> = Box<dyn FnMut(I) + Send + 'static>;
+
+/// A cell which keeps only the latest of the inputs signalled to it, for a
+/// long-lived handler to be run with when the consumer pumps.
+///
+/// For producers which signal far more often than the handler should run:
+/// each [`signal`][Self::signal] replaces any input not yet delivered,
+/// dropping it, and each [`pump`][Self::pump] runs the handler with the
+/// input pending, if any. Unlike a [`CallbackCellArgs`][crate::CallbackCellArgs]
+/// callback, the handler isn't taken once it has run: it stays until
+/// replaced or cleared.
+pub struct CoalescingCell<I> {
+    input: AtomicOptionBox<I>,
+    handler: AtomicOptionBox<Handler<I>>,
+}
+
+// safety: inputs move from the signalling thread to the pumping thread, and
+//         are never accessed by reference from more than one thread. the
+//         handler is Send, and is only run by whichever pump took it.
+unsafe impl<I: Send> Send for CoalescingCell<I> {}
+unsafe impl<I: Send> Sync for CoalescingCell<I> {}
+
+// a handler which panics is put back, like one which returns, and the input it
+// was running with is dropped during unwinding.
+impl<I> UnwindSafe for CoalescingCell<I> {}
+impl<I> RefUnwindSafe for CoalescingCell<I> {}
+
+impl<I> CoalescingCell<I> {
+    const_fn! {
+        /// Construct with no handler and no input pending.
+        ///
+        /// This is a `const fn`, so cells can be placed in statics.
+        pub fn new() -> Self {
+            CoalescingCell {
+                input: AtomicOptionBox::new(),
+                handler: AtomicOptionBox::new(),
+            }
+        }
+    }
+
+    /// Atomically set the handler.
+    ///
+    /// Any handler previously present is dropped, including one being run by
+    /// [`pump`][Self::pump], once that run returns. An input already pending
+    /// is delivered to the new handler.
+    pub fn set_handler<F: FnMut(I) + Send + 'static>(&self, f: F) {
+        drop(self.handler.swap(Some(Box::new(Box::new(f)))));
+    }
+
+    /// Atomically take the handler and drop it.
+    ///
+    /// Returns true if a handler was present, not counting one being run by
+    /// [`pump`][Self::pump], which is put back once that run returns.
+    pub fn clear_handler(&self) -> bool {
+        self.handler.clear()
+    }
+
+    /// Atomically set the pending input, dropping any input pending which
+    /// hasn't yet been delivered.
+    ///
+    /// Returns true if an input was dropped.
+    pub fn signal(&self, input: I) -> bool {
+        self.input.swap(Some(Box::new(input))).is_some()
+    }
+
+    /// Run the handler with the pending input, if there is both a handler and
+    /// an input pending.
+    ///
+    /// Returns true if the handler ran. Each input signalled is either
+    /// delivered by exactly one pump, or replaced by a later signal. If
+    /// another pump is running the handler, this returns false, leaving the
+    /// input pending.
+    pub fn pump(&self) -> bool {
+        let Some(handler) = self.handler.take() else {
+            return false;
+        };
+
+        // puts the handler back when dropped, including if it panics.
+        struct Restore<'a, I> {
+            handler: mem::ManuallyDrop<Box<Handler<I>>>,
+            slot: &'a AtomicOptionBox<Handler<I>>,
+        }
+
+        impl<I> Drop for Restore<'_, I> {
+            fn drop(&mut self) {
+                let handler = unsafe { mem::ManuallyDrop::take(&mut self.handler) };
+                // replaced meanwhile if this fails
+                let _ = self.slot.put_if_empty(handler);
+            }
+        }
+
+        let mut restore = Restore {
+            handler: mem::ManuallyDrop::new(handler),
+            slot: &self.handler,
+        };
+        match self.input.take() {
+            Some(input) => {
+                (restore.handler)(*input);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether an input is currently pending.
+    ///
+    /// This is only a snapshot: another thread may signal or pump
+    /// immediately afterwards.
+    pub fn is_pending(&self) -> bool {
+        self.input.is_set()
+    }
+
+    /// Whether a handler is currently present, and not being run by
+    /// [`pump`][Self::pump].
+    ///
+    /// This is only a snapshot: another thread may set or clear the handler
+    /// immediately afterwards.
+    pub fn has_handler(&self) -> bool {
+        self.handler.is_set()
+    }
+}
+
+impl<I> Default for CoalescingCell<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I> Debug for CoalescingCell<I> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("CoalescingCell")
+            .field("pending", &self.is_pending())
+            .field("handler", &self.has_handler())
+            .finish()
+    }
+}
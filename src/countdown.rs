@@ -0,0 +1,216 @@
+
+use crate::{
+    sync::{self, AtomicBool},
+    raw,
+    Fired,
+};
+use core::{
+    sync::atomic::Ordering,
+    cell::UnsafeCell,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+
+// internals
+// ---------
+//
+// the callback, as an owned erased callback described in the `raw` module, and
+// the counts, all guarded by a spin lock. the lock is only held to update the
+// state, and to allocate the callback being put, so that it can be handed back
+// if the cell has fired, never while a callback runs or is dropped. whichever
+// signal or put meets the threshold takes the callback and marks the cell
+// fired while holding the lock, then runs it after unlocking. so the callback
+// runs exactly once, however signals and puts race.
+
+/// Outcome of [`CountdownCallbackCell::signal`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SignalOutcome {
+    /// The signal was counted, but the threshold isn't met yet, or no
+    /// callback is registered yet.
+    NotYet,
+    /// The signal met the threshold, and the callback ran on this thread.
+    Fired,
+    /// The callback had already run, so the signal was ignored.
+    AlreadyFired,
+}
+
+struct State {
+    callback: Option<raw::Owned<(), ()>>,
+    // the count given with the callback, if one has been put.
+    count: Option<usize>,
+    added: usize,
+    signals: usize,
+    fired: bool,
+}
+
+impl State {
+    // take the callback, marking the cell fired, if the threshold is met.
+    fn take_if_met(&mut self) -> Option<raw::Owned<(), ()>> {
+        let count = self.count?;
+        if self.signals >= count.saturating_add(self.added) {
+            self.fired = true;
+            self.count = None;
+            self.callback.take()
+        } else {
+            None
+        }
+    }
+}
+
+/// A one-shot callback which runs once a number of signals have arrived.
+///
+/// Like a barrier: [`put_with_count`][Self::put_with_count] registers the
+/// callback and the number of signals it waits for, and any thread may
+/// [`signal`][Self::signal]. The signal which meets the threshold runs the
+/// callback, on its own thread, exactly once. Later signals are ignored.
+///
+/// Signals which arrive before the callback is put are counted, so putting
+/// a callback whose threshold is already met runs it immediately.
+/// [`add_count`][Self::add_count] raises the threshold, before or after the
+/// callback is put, for fan-out which isn't known up front.
+pub struct CountdownCallbackCell {
+    locked: AtomicBool,
+    state: UnsafeCell<State>,
+}
+
+// safety: the state is only accessed while holding the lock, and the callback
+//         is Send.
+unsafe impl Send for CountdownCallbackCell {}
+unsafe impl Sync for CountdownCallbackCell {}
+
+// the lock is never held while the callback runs or is dropped, and the
+// callback is taken, and the cell marked fired, before it runs, so a panicking
+// callback leaves the cell fired.
+impl UnwindSafe for CountdownCallbackCell {}
+impl RefUnwindSafe for CountdownCallbackCell {}
+
+impl CountdownCallbackCell {
+    const_fn! {
+        /// Construct with no callback and no signals.
+        ///
+        /// This is a `const fn`, so cells can be placed in statics.
+        pub fn new() -> Self {
+            CountdownCallbackCell {
+                locked: AtomicBool::new(false),
+                state: UnsafeCell::new(State {
+                    callback: None,
+                    count: None,
+                    added: 0,
+                    signals: 0,
+                    fired: false,
+                }),
+            }
+        }
+    }
+
+    // run the closure on the state while holding the lock.
+    fn with_lock<R>(&self, f: impl FnOnce(&mut State) -> R) -> R {
+        while self.locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            sync::spin_loop();
+        }
+        let r = f(unsafe { &mut *self.state.get() });
+        self.locked.store(false, Ordering::Release);
+        r
+    }
+
+    /// Set the callback, to run once `n` signals have arrived, counting any
+    /// which arrived before this, plus any added by
+    /// [`add_count`][Self::add_count].
+    ///
+    /// Makes only one heap allocation. If the threshold is already met, the
+    /// callback runs immediately, on this thread, and this returns true. Any
+    /// callback previously put, and not yet run, is dropped, and its count
+    /// replaced. If the cell has already fired, returns the callback instead,
+    /// without storing it.
+    pub fn put_with_count<F: FnOnce() + Send + 'static>(&self, f: F, n: usize) -> Result<bool, Fired<F>> {
+        let (old, ready) = self.with_lock(|state| {
+            if state.fired {
+                return Err(Fired(f));
+            }
+            let callback = unsafe { raw::Owned::from_erased(raw::alloc_raw(move |()| f())) };
+            let old = state.callback.replace(callback);
+            state.count = Some(n);
+            Ok((old, state.take_if_met()))
+        })?;
+        drop(old);
+        match ready {
+            Some(callback) => {
+                callback.call(());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Deliver a signal, running the callback, on this thread, if that meets
+    /// the threshold.
+    pub fn signal(&self) -> SignalOutcome {
+        let ready = self.with_lock(|state| {
+            if state.fired {
+                return Err(());
+            }
+            state.signals += 1;
+            Ok(state.take_if_met())
+        });
+        match ready {
+            Ok(Some(callback)) => {
+                callback.call(());
+                SignalOutcome::Fired
+            }
+            Ok(None) => SignalOutcome::NotYet,
+            Err(()) => SignalOutcome::AlreadyFired,
+        }
+    }
+
+    /// Raise the threshold by `k` signals.
+    ///
+    /// Returns false, doing nothing, if the cell has already fired.
+    pub fn add_count(&self, k: usize) -> bool {
+        self.with_lock(|state| {
+            if !state.fired {
+                state.added = state.added.saturating_add(k);
+            }
+            !state.fired
+        })
+    }
+
+    /// The number of signals still needed to run the callback, or `None` if
+    /// no callback is waiting.
+    ///
+    /// This is only a snapshot: another thread may signal immediately
+    /// afterwards.
+    pub fn remaining(&self) -> Option<usize> {
+        self.with_lock(|state| {
+            let count = state.count?;
+            Some(count.saturating_add(state.added).saturating_sub(state.signals))
+        })
+    }
+
+    /// Whether the callback has run.
+    pub fn has_fired(&self) -> bool {
+        self.with_lock(|state| state.fired)
+    }
+}
+
+impl Default for CountdownCallbackCell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for CountdownCallbackCell {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let (remaining, fired) = self.with_lock(|state| {
+            let remaining = state.count.map(|count| count.saturating_add(state.added).saturating_sub(state.signals));
+            (remaining, state.fired)
+        });
+        match (remaining, fired) {
+            (_, true) => f.write_str("CountdownCallbackCell(FIRED)"),
+            (Some(remaining), false) => write!(f, "CountdownCallbackCell({} REMAINING)", remaining),
+            (None, false) => f.write_str("CountdownCallbackCell(NULL)"),
+        }
+    }
+}
@@ -0,0 +1,111 @@
+
+use crate::raw;
+use core::{
+    cell::Cell,
+    ptr,
+    fmt::{self, Formatter, Debug},
+};
+use critical_section::Mutex;
+
+// internals
+// ---------
+//
+// the inner cell is a nullable pointer to an erased callback, as described in
+// the `raw` module, with `I = ()` and `O = ()`. it is only ever accessed
+// within a critical section. allocating, running, and dropping callbacks
+// happens outside of the critical section.
+
+/// Like a [`CallbackCell`][crate::CallbackCell], but synchronized with
+/// [`critical_section::with`] rather than with atomics.
+///
+/// This is for bare-metal targets where interrupt masking should be explicit.
+/// On single-core systems, `put` and `take_call` are safe to call from
+/// interrupt context. The critical section is held only for the pointer swap;
+/// callbacks are allocated, run, and dropped with interrupts enabled.
+pub struct CriticalSectionCallbackCell(Mutex<Cell<*mut u8>>);
+
+// safety: the pointer is only accessed within a critical section, and the
+//         callbacks it points to are Send.
+unsafe impl Send for CriticalSectionCallbackCell {}
+unsafe impl Sync for CriticalSectionCallbackCell {}
+
+impl CriticalSectionCallbackCell {
+    /// Construct with no callback.
+    ///
+    /// This is a `const fn`, so cells can be placed in statics.
+    pub const fn new() -> Self {
+        CriticalSectionCallbackCell(Mutex::new(Cell::new(ptr::null_mut())))
+    }
+
+    /// Set the callback within a critical section.
+    ///
+    /// Any callback previously present is dropped, outside of the critical
+    /// section.
+    pub fn put<F: FnOnce() + Send + 'static>(&self, f: F) {
+        unsafe {
+            // allocate and initialize heap allocation
+            let ptr = raw::alloc_raw(move |()| f());
+
+            // put
+            let old_ptr = critical_section::with(|cs| self.0.borrow(cs).replace(ptr));
+
+            // clean up previous value
+            raw::drop_raw::<(), ()>(old_ptr);
+        }
+    }
+
+    /// Take the callback within a critical section, then run it outside of
+    /// the critical section.
+    ///
+    /// Returns true if a callback was present.
+    pub fn take_call(&self) -> bool {
+        unsafe {
+            // take
+            let ptr = critical_section::with(|cs| self.0.borrow(cs).replace(ptr::null_mut()));
+
+            // run it
+            if !ptr.is_null() {
+                raw::call_raw::<(), ()>(ptr, ());
+                true
+            } else {
+                false
+            }
+        }
+    }
+}
+
+impl Drop for CriticalSectionCallbackCell {
+    fn drop(&mut self) {
+        unsafe {
+            raw::drop_raw::<(), ()>(self.0.get_mut().get());
+        }
+    }
+}
+
+impl Default for CriticalSectionCallbackCell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for CriticalSectionCallbackCell {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if critical_section::with(|cs| self.0.borrow(cs).get()).is_null() {
+            f.write_str("CriticalSectionCallbackCell(NULL)")
+        } else {
+            f.write_str("CriticalSectionCallbackCell(NOT NULL)")
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+#[allow(clippy::if_same_then_else)]
+impl defmt::Format for CriticalSectionCallbackCell {
+    fn format(&self, f: defmt::Formatter) {
+        if !critical_section::with(|cs| self.0.borrow(cs).get()).is_null() {
+            defmt::write!(f, "CriticalSectionCallbackCell(NOT NULL)")
+        } else {
+            defmt::write!(f, "CriticalSectionCallbackCell(NULL)")
+        }
+    }
+}
@@ -0,0 +1,121 @@
+
+use crate::{
+    CallbackCellArgs,
+    MultiCallbackCellArgs,
+};
+use core::fmt::{self, Formatter, Debug};
+
+// internals
+// ---------
+//
+// a `CallbackCellArgs` for the one-shot callback, beside a
+// `MultiCallbackCellArgs` for the default, which snapshots its `Arc` for each
+// call, so the default can be replaced while it runs. a take tries the one-shot
+// callback first, then the default, as two separate atomic operations: a
+// callback put in between isn't taken, and runs on the next take.
+
+/// A [`CallbackCellArgs`][crate::CallbackCellArgs] with a long-lived default
+/// handler, run when no one-shot callback is present.
+///
+/// The default is configured once, with [`set_default`][Self::set_default],
+/// rather than at each call site. [`take_call`][Self::take_call] takes and
+/// runs the one-shot callback if there is one, and otherwise calls the
+/// default by reference, leaving it in place, like a
+/// [`MultiCallbackCellArgs`][crate::MultiCallbackCellArgs]. It only hands the
+/// input back if neither is present.
+pub struct DefaultingCallbackCellArgs<I, O> {
+    cell: CallbackCellArgs<I, O>,
+    default: MultiCallbackCellArgs<I, O>,
+}
+
+impl<I, O> DefaultingCallbackCellArgs<I, O> {
+    const_fn! {
+        /// Construct with no callback and no default.
+        ///
+        /// This is a `const fn`, so cells can be placed in statics.
+        pub fn new() -> Self {
+            DefaultingCallbackCellArgs {
+                cell: CallbackCellArgs::new(),
+                default: MultiCallbackCellArgs::new(),
+            }
+        }
+    }
+
+    /// Atomically set the one-shot callback.
+    ///
+    /// See [`CallbackCellArgs::put`].
+    pub fn put<F: FnOnce(I) -> O + Send + 'static>(&self, f: F) {
+        self.cell.put(f)
+    }
+
+    /// Set the default.
+    ///
+    /// Any default previously present is dropped once calls of it in progress
+    /// finish.
+    pub fn set_default<F: Fn(I) -> O + Send + Sync + 'static>(&self, f: F) {
+        self.default.put(f)
+    }
+
+    /// Remove the default.
+    ///
+    /// Returns true if a default was present. It's dropped as for
+    /// [`set_default`][Self::set_default].
+    pub fn clear_default(&self) -> bool {
+        self.default.clear()
+    }
+
+    /// Atomically take the one-shot callback then run it with the given
+    /// input, or if none is present, call the default.
+    ///
+    /// Returns the output of whichever ran. If neither was present, returns
+    /// the original input.
+    pub fn take_call(&self, input: I) -> Result<O, I> {
+        self.cell.take_call(input).or_else(|input| self.default.call(input))
+    }
+
+    /// Atomically take the one-shot callback and drop it without running it,
+    /// leaving the default.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        self.cell.clear()
+    }
+
+    /// Whether a one-shot callback is currently present.
+    ///
+    /// This is only a snapshot: another thread may put or take a callback
+    /// immediately afterwards.
+    pub fn is_set(&self) -> bool {
+        self.cell.is_set()
+    }
+
+    /// Whether a default is currently present.
+    ///
+    /// This is only a snapshot: another thread may set or clear the default
+    /// immediately afterwards.
+    pub fn has_default(&self) -> bool {
+        self.default.is_set()
+    }
+
+    /// The cell holding the one-shot callback.
+    pub fn as_args(&self) -> &CallbackCellArgs<I, O> {
+        &self.cell
+    }
+}
+
+impl<I, O> Default for DefaultingCallbackCellArgs<I, O> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I, O> Debug for DefaultingCallbackCellArgs<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let set = if self.is_set() { "NOT NULL" } else { "NULL" };
+        if self.has_default() {
+            write!(f, "DefaultingCallbackCellArgs({}, DEFAULT)", set)
+        } else {
+            write!(f, "DefaultingCallbackCellArgs({})", set)
+        }
+    }
+}
@@ -0,0 +1,510 @@
+
+use crate::{
+    sync::{AtomicPtr, AtomicBool, LoadMut},
+    sealed::{Sealed, SEALED, is_tagged, untagged, empty_like},
+};
+use core::{
+    sync::atomic::Ordering,
+    ptr,
+    hint,
+    marker::PhantomData,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+use alloc::{
+    boxed::Box,
+    vec::Vec,
+};
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+#[cfg(feature = "futures")]
+use crate::WakerCell;
+
+// internals
+// ---------
+//
+// the queue is a lock-free stack of entries: the inner atomic pointer is a
+// nullable pointer to the most recently pushed entry, each of which points to
+// the one pushed before it. running takes the whole stack at once, then
+// reverses it to run the entries in the order they were pushed.
+//
+// each entry is a single heap allocation, a `Box<Node<I, O, F>>`, holding the
+// callback and its input. a `Node` starts with a `Header`, which consists of:
+//
+// - the pointer to the next entry
+// - an `unsafe fn(*mut Header<O>, bool) -> Option<O>` which, when called with
+//   the pointer to the entry, frees the heap allocation and, if the bool is
+//   true, runs the callback and returns its output, or else drops it without
+//   running it
+// - the entry's tag
+//
+// the header is aligned to at least 2, and the low bit of the head pointer is
+// whether the queue is sealed. every update to the head keeps that bit.
+//
+// pushing only ever writes to the head. taking entries off the queue, which
+// running, clearing, and `retain` do, is guarded by a spin lock, so that
+// `retain` can be the only one walking the queue's entries. it takes every
+// entry, filters them, then links the survivors back in below any entries
+// pushed meanwhile, so the order is kept. running and clearing hold the lock
+// only for the take.
+//
+// with `futures`, the queue also holds the waker of a stream draining it,
+// woken by a push into an empty queue and by sealing it. the stream registers
+// its waker, then checks the queue again, so a push between its first check
+// and registering is seen by one or the other.
+
+#[repr(C, align(2))]
+pub(crate) struct Header<O> {
+    pub(crate) next: *mut Header<O>,
+    pub(crate) fn_ptr: unsafe fn(*mut Header<O>, bool) -> Option<O>,
+    tag: u64,
+}
+
+#[repr(C)]
+struct Node<I, O, F> {
+    // must be first
+    header: Header<O>,
+    f: F,
+    input: I,
+}
+
+// free the heap allocation for an entry, returning its callback and input. the
+// pointer must point to an entry of this type.
+unsafe fn into_parts<I, O, F>(ptr: *mut Header<O>) -> (F, I) {
+    let node = Box::from_raw(ptr as *mut Node<I, O, F>);
+    #[cfg(any(test, feature = "test-util"))]
+    crate::test_util::on_dealloc();
+    #[cfg(feature = "alloc-stats")]
+    crate::alloc_stats::on_dealloc(core::mem::size_of::<Node<I, O, F>>());
+    let Node { f, input, .. } = *node;
+    (f, input)
+}
+
+// implementation for the function pointer for a given callback type F.
+unsafe fn fn_ptr_impl<I, O, F: FnOnce(I) -> O>(ptr: *mut Header<O>, run: bool) -> Option<O> {
+    let (f, input) = into_parts::<I, O, F>(ptr);
+    if run {
+        Some(f(input))
+    } else {
+        None
+    }
+}
+
+// drop every entry in the list starting at `ptr`, without running them.
+pub(crate) unsafe fn drop_list<O>(mut ptr: *mut Header<O>) {
+    while !ptr.is_null() {
+        let next = (*ptr).next;
+        ((*ptr).fn_ptr)(ptr, false);
+        ptr = next;
+    }
+}
+
+/// Outcome of a [`DeferredCallQueue::run_for`].
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DrainStats {
+    /// Number of entries run.
+    pub ran: usize,
+    /// Whether the budget ran out with entries left queued.
+    pub stopped_early: bool,
+}
+
+/// A queue of callbacks, each to be run later with the input it was pushed
+/// with.
+///
+/// Like a command buffer: any number of threads may [`push`][Self::push]
+/// entries concurrently without locking, then [`run_all`][Self::run_all]
+/// runs them in the order they were pushed.
+pub struct DeferredCallQueue<I, O> {
+    head: AtomicPtr<Header<O>>,
+    taking: AtomicBool,
+    #[cfg(feature = "futures")]
+    pub(crate) waker: WakerCell,
+    // invariant in `I` and `O`, like `CallbackCellArgs`.
+    _p: PhantomData<dyn FnOnce(I) -> O + Send + 'static>,
+}
+
+// safety: the callbacks and inputs stored in the queue are Send. outputs are
+//         only produced on the thread which calls `run_all`.
+unsafe impl<I, O> Send for DeferredCallQueue<I, O> {}
+unsafe impl<I, O> Sync for DeferredCallQueue<I, O> {}
+
+// a panic in an entry unwinds out of `run_all` after the entries have been
+// taken, so the queue is left without them, which is a valid state.
+impl<I, O> UnwindSafe for DeferredCallQueue<I, O> {}
+impl<I, O> RefUnwindSafe for DeferredCallQueue<I, O> {}
+
+impl<I, O> DeferredCallQueue<I, O> {
+    const_fn! {
+        /// Construct with no entries.
+        ///
+        /// This is a `const fn`, so queues can be placed in statics.
+        pub fn new() -> Self {
+            DeferredCallQueue {
+                head: AtomicPtr::new(ptr::null_mut()),
+                taking: AtomicBool::new(false),
+                #[cfg(feature = "futures")]
+                waker: WakerCell::new(),
+                _p: PhantomData,
+            }
+        }
+    }
+
+    /// Push a callback along with the input to later run it with.
+    ///
+    /// Makes only one heap allocation, holding both the callback and the
+    /// input. Neither is touched again until the entry is run or dropped. If
+    /// the queue is [sealed][Self::seal], returns both instead, without
+    /// queueing them.
+    pub fn push<F>(&self, f: F, input: I) -> Result<(), Sealed<(F, I)>>
+    where
+        F: FnOnce(I) -> O + Send + 'static,
+        I: Send + 'static,
+    {
+        self.push_tagged(f, input, 0)
+    }
+
+    /// Like [`push`][Self::push], but tags the entry, for
+    /// [`retain`][Self::retain]. Untagged entries have tag 0.
+    pub fn push_tagged<F>(&self, f: F, input: I, tag: u64) -> Result<(), Sealed<(F, I)>>
+    where
+        F: FnOnce(I) -> O + Send + 'static,
+        I: Send + 'static,
+    {
+        let mut head = self.head.load(Ordering::Relaxed);
+        if is_tagged(head) {
+            return Err(Sealed((f, input)));
+        }
+        let node = Box::into_raw(Box::new(Node {
+            header: Header {
+                next: ptr::null_mut(),
+                fn_ptr: fn_ptr_impl::<I, O, F>,
+                tag,
+            },
+            f,
+            input,
+        })) as *mut Header<O>;
+        #[cfg(any(test, feature = "test-util"))]
+        crate::test_util::on_alloc();
+        #[cfg(feature = "alloc-stats")]
+        crate::alloc_stats::on_alloc(core::mem::size_of::<Node<I, O, F>>());
+
+        loop {
+            if is_tagged(head) {
+                // sealed since the check above
+                return Err(Sealed(unsafe { into_parts::<I, O, F>(node) }));
+            }
+            unsafe { (*node).next = head };
+            match self.head.compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => {
+                    #[cfg(feature = "futures")]
+                    if head.is_null() {
+                        self.waker.wake();
+                    }
+                    return Ok(());
+                }
+                Err(new_head) => head = new_head,
+            }
+        }
+    }
+
+    /// Whether any entries are currently queued.
+    ///
+    /// This is only a snapshot: another thread may push or run entries
+    /// immediately afterwards.
+    pub fn is_empty(&self) -> bool {
+        untagged(self.head.load(Ordering::Acquire)).is_null()
+    }
+
+    /// Atomically seal the queue, so that every later push is rejected.
+    ///
+    /// Entries already queued stay, to be drained with
+    /// [`run_all`][Self::run_all]. Returns false if the queue was already
+    /// sealed.
+    pub fn seal(&self) -> bool {
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            if is_tagged(head) {
+                return false;
+            }
+            match self.head.compare_exchange_weak(
+                head, head.map_addr(|addr| addr | SEALED), Ordering::Release, Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    #[cfg(feature = "futures")]
+                    self.waker.wake();
+                    return true;
+                }
+                Err(new_head) => head = new_head,
+            }
+        }
+    }
+
+    /// Whether the queue has been sealed.
+    pub fn is_sealed(&self) -> bool {
+        is_tagged(self.head.load(Ordering::Acquire))
+    }
+
+    /// Atomically take every queued entry, then run each in the order they
+    /// were pushed, discarding the outputs.
+    ///
+    /// Returns the number of entries run. Entries pushed while this runs,
+    /// including by the entries themselves, are left for the next call, so
+    /// this always finishes. If an entry panics, the entries after it are
+    /// dropped without running.
+    pub fn run_all(&self) -> usize {
+        self.run_all_with(drop)
+    }
+
+    /// Like [`run_all`][Self::run_all], but returns the outputs, in the order
+    /// the entries were pushed.
+    pub fn run_all_collect(&self) -> Vec<O> {
+        let mut outputs = Vec::new();
+        self.run_all_with(|output| outputs.push(output));
+        outputs
+    }
+
+    fn run_all_with(&self, mut on_output: impl FnMut(O)) -> usize {
+        // atomic take, then reverse into push order
+        let mut batch = Batch(reverse(self.take_all()));
+
+        // run them. if one panics, `batch` drops the rest
+        let mut count = 0;
+        while !batch.0.is_null() {
+            unsafe {
+                let ptr = batch.0;
+                batch.0 = (*ptr).next;
+                on_output(((*ptr).fn_ptr)(ptr, true).unwrap());
+            }
+            count += 1;
+        }
+        count
+    }
+
+    /// Like [`run_all`][Self::run_all], but stops once `budget` has elapsed,
+    /// leaving the entries not yet run queued.
+    ///
+    /// The time is checked between entries, so an entry which starts within
+    /// the budget runs to completion. Entries left over stay in the order they
+    /// were pushed, ahead of any entries pushed while this runs, unless
+    /// another thread runs the queue meanwhile. If an entry panics, the
+    /// entries after it are dropped without running, as with `run_all`.
+    #[cfg(feature = "std")]
+    pub fn run_for(&self, budget: Duration) -> DrainStats {
+        let start = Instant::now();
+        let (ran, stopped_early) = self.run_until(|| start.elapsed() >= budget, drop);
+        DrainStats { ran, stopped_early }
+    }
+
+    // like `run_all_with`, but checks `stop` before each entry, and once it
+    // returns true, puts the entries not yet run back, as for `run_for`.
+    // returns the number run, and whether it stopped.
+    #[cfg(feature = "std")]
+    pub(crate) fn run_until(&self, mut stop: impl FnMut() -> bool, mut on_output: impl FnMut(O)) -> (usize, bool) {
+        let mut batch = Batch(reverse(self.take_all()));
+        let mut ran = 0;
+        while !batch.0.is_null() {
+            if stop() {
+                unsafe { self.put_back(batch.0) };
+                core::mem::forget(batch);
+                return (ran, true);
+            }
+            unsafe {
+                let ptr = batch.0;
+                batch.0 = (*ptr).next;
+                on_output(((*ptr).fn_ptr)(ptr, true).unwrap());
+            }
+            ran += 1;
+        }
+        (ran, false)
+    }
+
+    /// Atomically take every queued entry and drop them without running them.
+    ///
+    /// Returns true if any entries were queued.
+    pub fn clear(&self) -> bool {
+        let ptr = self.take_all();
+        unsafe { drop_list(ptr) };
+        !ptr.is_null()
+    }
+
+    /// Drop, without running, every queued entry whose tag `keep` returns
+    /// false for.
+    ///
+    /// Returns the number of entries removed. Entries which are already
+    /// being run by [`run_all`][Self::run_all] are not queued, so can't be
+    /// removed. Pushes may happen concurrently; entries pushed while this runs
+    /// aren't checked. Running and clearing wait for this to finish, so
+    /// `keep` must not use the queue.
+    pub fn retain(&self, mut keep: impl FnMut(u64) -> bool) -> usize {
+        self.lock();
+        let mut retain = Retain {
+            queue: self,
+            kept: self.take_locked(),
+            removed: ptr::null_mut(),
+            count: 0,
+        };
+
+        // unlink the entries to remove. if `keep` panics, `retain` still puts
+        // back the rest
+        unsafe {
+            let mut link: *mut *mut Header<O> = &mut retain.kept;
+            while !(*link).is_null() {
+                let node = *link;
+                if keep((*node).tag) {
+                    link = &mut (*node).next;
+                } else {
+                    *link = (*node).next;
+                    (*node).next = retain.removed;
+                    retain.removed = node;
+                    retain.count += 1;
+                }
+            }
+        }
+        retain.count
+    }
+
+    // atomically take every queued entry, in push order.
+    #[cfg(feature = "futures")]
+    pub(crate) fn take_batch(&self) -> *mut Header<O> {
+        reverse(self.take_all())
+    }
+
+    // put back entries taken and not yet run, in push order, ahead of any
+    // entries pushed meanwhile.
+    #[cfg(any(feature = "std", feature = "futures"))]
+    pub(crate) unsafe fn put_back(&self, batch: *mut Header<O>) {
+        self.lock();
+        self.put_back_locked(reverse(batch));
+        self.unlock();
+    }
+
+    // atomically take every queued entry, in reverse push order.
+    fn take_all(&self) -> *mut Header<O> {
+        self.lock();
+        let ptr = self.take_locked();
+        self.unlock();
+        ptr
+    }
+
+    // take_all, with the lock already held.
+    fn take_locked(&self) -> *mut Header<O> {
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            match self.head.compare_exchange_weak(head, empty_like(head), Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => return untagged(head),
+                Err(new_head) => head = new_head,
+            }
+        }
+    }
+
+    // link the entries, in reverse push order, back into the queue below any
+    // entries pushed since they were taken, with the lock held.
+    unsafe fn put_back_locked(&self, list: *mut Header<O>) {
+        if list.is_null() {
+            return;
+        }
+        let mut head = self.head.load(Ordering::Acquire);
+        loop {
+            if untagged(head).is_null() {
+                // put back regardless of sealing, since they were pushed
+                // before it
+                let list = list.map_addr(|addr| addr | (head.addr() & SEALED));
+                match self.head.compare_exchange(head, list, Ordering::Release, Ordering::Acquire) {
+                    Ok(_) => break,
+                    Err(new_head) => head = new_head,
+                }
+            } else {
+                // only pushes happen meanwhile, which never write to entries
+                // already in the queue
+                let mut tail = untagged(head);
+                while !(*tail).next.is_null() {
+                    tail = (*tail).next;
+                }
+                (*tail).next = list;
+                break;
+            }
+        }
+    }
+
+    // acquire the lock for taking entries.
+    fn lock(&self) {
+        while self.taking
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            hint::spin_loop();
+        }
+    }
+
+    fn unlock(&self) {
+        self.taking.store(false, Ordering::Release);
+    }
+}
+
+// entries taken by `retain`, while it holds the lock. dropping this links the
+// kept entries back in, below any entries pushed meanwhile, releases the lock,
+// then drops the removed entries.
+struct Retain<'a, I, O> {
+    queue: &'a DeferredCallQueue<I, O>,
+    kept: *mut Header<O>,
+    removed: *mut Header<O>,
+    count: usize,
+}
+
+impl<I, O> Drop for Retain<'_, I, O> {
+    fn drop(&mut self) {
+        unsafe {
+            self.queue.put_back_locked(self.kept);
+            self.queue.unlock();
+            drop_list(self.removed);
+        }
+    }
+}
+
+// reverse a list of entries, between push order and reverse push order.
+fn reverse<O>(mut ptr: *mut Header<O>) -> *mut Header<O> {
+    let mut reversed = ptr::null_mut();
+    while !ptr.is_null() {
+        unsafe {
+            let next = (*ptr).next;
+            (*ptr).next = reversed;
+            reversed = ptr;
+            ptr = next;
+        }
+    }
+    reversed
+}
+
+// entries taken to be run, in push order, which are dropped if not run.
+struct Batch<O>(*mut Header<O>);
+
+impl<O> Drop for Batch<O> {
+    fn drop(&mut self) {
+        unsafe { drop_list(self.0) };
+    }
+}
+
+impl<I, O> Drop for DeferredCallQueue<I, O> {
+    fn drop(&mut self) {
+        unsafe { drop_list(untagged(self.head.load_mut())) };
+    }
+}
+
+impl<I, O> Default for DeferredCallQueue<I, O> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I, O> Debug for DeferredCallQueue<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let head = self.head.load(Ordering::Relaxed);
+        let empty = if untagged(head).is_null() { "EMPTY" } else { "NOT EMPTY" };
+        if is_tagged(head) {
+            write!(f, "DeferredCallQueue({}, SEALED)", empty)
+        } else {
+            write!(f, "DeferredCallQueue({})", empty)
+        }
+    }
+}
@@ -0,0 +1,146 @@
+
+use crate::{
+    deferred::{Header, drop_list},
+    DeferredCallQueue,
+};
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+    ptr,
+    fmt::{self, Formatter, Debug},
+};
+use futures_core::Stream;
+
+// internals
+// ---------
+//
+// the stream takes every queued entry at once, as `run_all` does, then yields
+// them one at a time, keeping the rest in push order. dropping the stream puts
+// the entries not yet yielded back, ahead of any pushed meanwhile, as
+// `run_for` does when its budget runs out. a yielded entry is unlinked, and
+// owned by its `QueuedCall`.
+//
+// when both the stream's entries and the queue are empty, the stream registers
+// its waker in the queue, then checks the queue again. see the `deferred`
+// module.
+
+/// One entry taken from a [`DeferredCallQueue`] by a [`DeferredCallStream`],
+/// with the input it was pushed with.
+///
+/// [`call`][Self::call] runs it. Dropping it drops the callback and input
+/// without running it.
+pub struct QueuedCall<O>(*mut Header<O>);
+
+// safety: as for `DeferredCallQueue`, the callback and input are Send, and the
+//         output is only produced by `call`.
+unsafe impl<O> Send for QueuedCall<O> {}
+unsafe impl<O> Sync for QueuedCall<O> {}
+
+impl<O> QueuedCall<O> {
+    /// Run the callback with its input, returning its output.
+    pub fn call(self) -> O {
+        let ptr = self.0;
+        core::mem::forget(self);
+        unsafe { ((*ptr).fn_ptr)(ptr, true).unwrap() }
+    }
+}
+
+impl<O> Drop for QueuedCall<O> {
+    fn drop(&mut self) {
+        unsafe { drop_list(self.0) };
+    }
+}
+
+impl<O> Debug for QueuedCall<O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("QueuedCall(..)")
+    }
+}
+
+/// A [`Stream`] of the entries pushed to a [`DeferredCallQueue`], in the
+/// order they were pushed, behind the `futures` feature.
+///
+/// Returned by [`DeferredCallQueue::stream`]. For draining a queue from an
+/// async task: when the queue is empty, the stream waits for a push, and
+/// ends once the queue is [sealed][DeferredCallQueue::seal] and empty.
+///
+/// The queue holds one waker, so only one stream should wait on a queue at a
+/// time. Dropping the stream puts back the entries it has taken but not yet
+/// yielded, so they stay queued for the next consumer.
+pub struct DeferredCallStream<'a, I, O> {
+    queue: &'a DeferredCallQueue<I, O>,
+    // entries taken and not yet yielded, in push order.
+    batch: *mut Header<O>,
+}
+
+// safety: the entries taken are owned by the stream, and are Send, as in the
+//         queue.
+unsafe impl<I, O> Send for DeferredCallStream<'_, I, O> {}
+unsafe impl<I, O> Sync for DeferredCallStream<'_, I, O> {}
+
+impl<I, O> DeferredCallQueue<I, O> {
+    /// A [`Stream`] of the entries pushed to this queue, each yielded as a
+    /// [`QueuedCall`] to run.
+    ///
+    /// See [`DeferredCallStream`].
+    pub fn stream(&self) -> DeferredCallStream<'_, I, O> {
+        DeferredCallStream {
+            queue: self,
+            batch: ptr::null_mut(),
+        }
+    }
+}
+
+impl<I, O> DeferredCallStream<'_, I, O> {
+    // yield the next entry taken, taking any queued if none are left.
+    fn next_entry(&mut self) -> Option<QueuedCall<O>> {
+        if self.batch.is_null() {
+            self.batch = self.queue.take_batch();
+        }
+        if self.batch.is_null() {
+            return None;
+        }
+        unsafe {
+            let ptr = self.batch;
+            self.batch = (*ptr).next;
+            (*ptr).next = ptr::null_mut();
+            Some(QueuedCall(ptr))
+        }
+    }
+}
+
+impl<I, O> Stream for DeferredCallStream<'_, I, O> {
+    type Item = QueuedCall<O>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<QueuedCall<O>>> {
+        let this = &mut *self;
+        if let Some(entry) = this.next_entry() {
+            return Poll::Ready(Some(entry));
+        }
+        this.queue.waker.register(cx.waker());
+        // checked after registering, so a push or seal since the check above is
+        // either seen here or wakes the waker
+        let sealed = this.queue.is_sealed();
+        match this.next_entry() {
+            Some(entry) => Poll::Ready(Some(entry)),
+            None if sealed => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<I, O> Drop for DeferredCallStream<'_, I, O> {
+    fn drop(&mut self) {
+        if !self.batch.is_null() {
+            unsafe { self.queue.put_back(self.batch) };
+        }
+    }
+}
+
+impl<I, O> Debug for DeferredCallStream<'_, I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("DeferredCallStream")
+            .field("queue", self.queue)
+            .finish()
+    }
+}
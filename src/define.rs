@@ -0,0 +1,225 @@
+
+// internals
+// ---------
+//
+// `define_callback!` generates a newtype around a `CallbackCellArgs` or a
+// `MultiCallbackCellArgs` whose input is the tuple of the callback's
+// arguments. the callback itself takes the arguments unpacked, and is adapted
+// to take the tuple, within the same allocation, by the `ApplyOnce` and
+// `Apply` traits here. those are public only for the macro's use.
+// `CallbackCellArgs`'s unpacked methods use `ApplyOnce` the same way.
+
+/// Call a function with a tuple of its arguments. For use by
+/// [`define_callback!`].
+#[doc(hidden)]
+pub trait ApplyOnce<Args> {
+    type Output;
+
+    fn apply_once(self, args: Args) -> Self::Output;
+}
+
+/// Call a function by reference with a tuple of its arguments. For use by
+/// [`define_callback!`].
+#[doc(hidden)]
+pub trait Apply<Args>: ApplyOnce<Args> {
+    fn apply(&self, args: Args) -> Self::Output;
+}
+
+macro_rules! impl_apply {
+    ($($arg:ident),*) => {
+        impl<F: FnOnce($($arg),*) -> R, $($arg,)* R> ApplyOnce<($($arg,)*)> for F {
+            type Output = R;
+
+            #[allow(non_snake_case)]
+            fn apply_once(self, ($($arg,)*): ($($arg,)*)) -> R {
+                self($($arg),*)
+            }
+        }
+
+        impl<F: Fn($($arg),*) -> R, $($arg,)* R> Apply<($($arg,)*)> for F {
+            #[allow(non_snake_case)]
+            fn apply(&self, ($($arg,)*): ($($arg,)*)) -> R {
+                self($($arg),*)
+            }
+        }
+    };
+}
+
+impl_apply!();
+impl_apply!(A);
+impl_apply!(A, B);
+impl_apply!(A, B, C);
+impl_apply!(A, B, C, D);
+impl_apply!(A, B, C, D, E);
+impl_apply!(A, B, C, D, E, G);
+impl_apply!(A, B, C, D, E, G, H);
+impl_apply!(A, B, C, D, E, G, H, J);
+
+/// Define nominal newtypes around callback cells, so that cells for
+/// different hooks can't be mixed up.
+///
+/// Each definition is a name, optionally with generic parameters, then a
+/// flavor and signature:
+///
+/// - `FnOnce<(A, ..), O>` wraps a [`CallbackCellArgs`][crate::CallbackCellArgs],
+///   with `put` and `take_call`.
+/// - `Fn<(A, ..), O>` wraps a
+///   [`MultiCallbackCellArgs`][crate::MultiCallbackCellArgs], with `put` and
+///   `call`.
+///
+/// Callbacks take the arguments unpacked, while `take_call` and `call` take
+/// them as a tuple. Up to 8 arguments are supported. Attributes, including
+/// doc comments, and visibility apply to the generated type, which also
+/// implements `Default` and `Debug`.
+///
+// with the `disabled` feature, cells never hold callbacks, so this can't pass
+#[cfg_attr(not(feature = "disabled"), doc = "```")]
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+/// # struct FrameInfo;
+/// callback_cell::define_callback! {
+///     /// Called after each frame.
+///     pub OnFrame: Fn<(FrameInfo,), ()>;
+///     /// Called once, on quit.
+///     pub OnQuit: FnOnce<(), ()>;
+///     /// Called once, with the event.
+///     pub(crate) OnEvent<T>: FnOnce<(T, u32), bool>;
+/// }
+///
+/// let on_quit = OnQuit::new();
+/// on_quit.put(|| println!("bye"));
+/// assert_eq!(on_quit.take_call(()), Ok(()));
+///
+/// let on_event = OnEvent::<&str>::new();
+/// on_event.put(|s, n| s.len() == n as usize);
+/// assert_eq!(on_event.take_call(("four", 4)), Ok(true));
+/// ```
+#[macro_export]
+macro_rules! define_callback {
+    () => {};
+    (@common $name:ident $(<$($gen:ident),+>)?) => {
+        impl $(<$($gen),+>)? ::core::default::Default for $name $(<$($gen),+>)? {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        impl $(<$($gen),+>)? ::core::fmt::Debug for $name $(<$($gen),+>)? {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+                f.debug_tuple(::core::stringify!($name)).field(&self.0).finish()
+            }
+        }
+    };
+    (
+        $(#[$attr:meta])*
+        $vis:vis $name:ident $(<$($gen:ident),+ $(,)?>)?
+        : FnOnce<($($arg:ty),* $(,)?), $out:ty>
+        $(; $($rest:tt)*)?
+    ) => {
+        $(#[$attr])*
+        $vis struct $name $(<$($gen),+>)? ($crate::CallbackCellArgs<($($arg,)*), $out>);
+
+        #[allow(dead_code, clippy::unused_unit)]
+        impl $(<$($gen),+>)? $name $(<$($gen),+>)? {
+            /// Construct with no callback.
+            pub const fn new() -> Self {
+                $name($crate::CallbackCellArgs::new())
+            }
+
+            /// Atomically set the callback.
+            ///
+            /// Any callback previously present is dropped.
+            pub fn put<F>(&self, f: F)
+            where
+                F: FnOnce($($arg),*) -> $out + Send + 'static,
+            {
+                self.0.put(move |args: ($($arg,)*)| $crate::__private::ApplyOnce::apply_once(f, args))
+            }
+
+            /// Atomically take the callback then run it with the given
+            /// arguments.
+            ///
+            /// Returns the output if a callback was present. If a callback
+            /// was not present, returns the original arguments.
+            pub fn take_call(&self, args: ($($arg,)*)) -> ::core::result::Result<$out, ($($arg,)*)> {
+                self.0.take_call(args)
+            }
+
+            /// Atomically take the callback and drop it without running it.
+            ///
+            /// Returns true if a callback was present.
+            pub fn clear(&self) -> bool {
+                self.0.clear()
+            }
+
+            /// Whether a callback is currently present.
+            pub fn is_set(&self) -> bool {
+                self.0.is_set()
+            }
+
+            /// The underlying cell.
+            pub fn as_inner(&self) -> &$crate::CallbackCellArgs<($($arg,)*), $out> {
+                &self.0
+            }
+        }
+
+        $crate::define_callback!(@common $name $(<$($gen),+>)?);
+        $($crate::define_callback!($($rest)*);)?
+    };
+    (
+        $(#[$attr:meta])*
+        $vis:vis $name:ident $(<$($gen:ident),+ $(,)?>)?
+        : Fn<($($arg:ty),* $(,)?), $out:ty>
+        $(; $($rest:tt)*)?
+    ) => {
+        $(#[$attr])*
+        $vis struct $name $(<$($gen),+>)? ($crate::MultiCallbackCellArgs<($($arg,)*), $out>);
+
+        #[allow(dead_code, clippy::unused_unit)]
+        impl $(<$($gen),+>)? $name $(<$($gen),+>)? {
+            /// Construct with no handler.
+            pub const fn new() -> Self {
+                $name($crate::MultiCallbackCellArgs::new())
+            }
+
+            /// Set the handler.
+            ///
+            /// Any handler previously present is dropped once calls in
+            /// progress finish.
+            pub fn put<F>(&self, f: F)
+            where
+                F: Fn($($arg),*) -> $out + Send + Sync + 'static,
+            {
+                self.0.put(move |args: ($($arg,)*)| $crate::__private::Apply::apply(&f, args))
+            }
+
+            /// Call the handler with the given arguments, leaving it in
+            /// place.
+            ///
+            /// Returns the output if a handler was present. If a handler was
+            /// not present, returns the original arguments.
+            pub fn call(&self, args: ($($arg,)*)) -> ::core::result::Result<$out, ($($arg,)*)> {
+                self.0.call(args)
+            }
+
+            /// Remove the handler.
+            ///
+            /// Returns true if a handler was present.
+            pub fn clear(&self) -> bool {
+                self.0.clear()
+            }
+
+            /// Whether a handler is currently present.
+            pub fn is_set(&self) -> bool {
+                self.0.is_set()
+            }
+
+            /// The underlying cell.
+            pub fn as_inner(&self) -> &$crate::MultiCallbackCellArgs<($($arg,)*), $out> {
+                &self.0
+            }
+        }
+
+        $crate::define_callback!(@common $name $(<$($gen),+>)?);
+        $($crate::define_callback!($($rest)*);)?
+    };
+}
@@ -0,0 +1,125 @@
+
+use crate::{
+    sync::{self, AtomicUsize},
+    DeferredCallQueue,
+};
+use core::{
+    sync::atomic::Ordering,
+    fmt::{self, Formatter, Debug},
+};
+
+// internals
+// ---------
+//
+// two queues, the index of the current one, and for each queue the number of
+// pushes which may be pushing into it.
+//
+// a push announces itself on the current queue's count, then checks the index
+// again, retrying if it changed. a flip changes the index, then waits for the
+// previous queue's count to drop to zero. both checks are read-modify-writes
+// rather than loads, so a push and a flip which race can't both miss each
+// other's write: whichever of them modifies the count second sees the other's
+// write to the index or the count. so after a flip returns, no push can reach
+// the previous queue until the next flip, and draining it sees exactly the
+// pushes which happened before the flip.
+
+/// A pair of queues of callbacks, for frame-based pipelines: callbacks pushed
+/// during one frame are run together after the next [`flip`][Self::flip],
+/// while callbacks pushed meanwhile wait for the flip after that.
+///
+/// Any number of threads may push. Flipping and draining are for one consumer
+/// at a time: if two threads flip at once, the frames they delimit are
+/// unspecified, though every callback still runs exactly once.
+pub struct DoubleBufferedCallbackQueue {
+    queues: [DeferredCallQueue<(), ()>; 2],
+    current: AtomicUsize,
+    pushing: [AtomicUsize; 2],
+}
+
+impl DoubleBufferedCallbackQueue {
+    const_fn! {
+        /// Construct with no callbacks.
+        ///
+        /// This is a `const fn`, so queues can be placed in statics.
+        pub fn new() -> Self {
+            DoubleBufferedCallbackQueue {
+                queues: [DeferredCallQueue::new(), DeferredCallQueue::new()],
+                current: AtomicUsize::new(0),
+                pushing: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            }
+        }
+    }
+
+    /// Push a callback onto the current frame's queue, to be run by the
+    /// first [`drain_previous`][Self::drain_previous] after the next
+    /// [`flip`][Self::flip].
+    ///
+    /// Makes one heap allocation, and doesn't block.
+    pub fn push<F: FnOnce() + Send + 'static>(&self, f: F) {
+        let mut i = self.current.load(Ordering::Acquire);
+        loop {
+            self.pushing[i].fetch_add(1, Ordering::AcqRel);
+            let now = self.current.fetch_add(0, Ordering::AcqRel);
+            if now == i {
+                break;
+            }
+            self.pushing[i].fetch_sub(1, Ordering::Relaxed);
+            i = now;
+        }
+        // never sealed
+        let _ = self.queues[i].push(move |()| f(), ());
+        self.pushing[i].fetch_sub(1, Ordering::Release);
+    }
+
+    /// End the current frame, so that the callbacks pushed during it become
+    /// the previous frame's, and later pushes go to the next frame.
+    ///
+    /// Waits for pushes already in progress onto the frame being ended, so
+    /// that each push lands in exactly one frame. If callbacks from the
+    /// previous frame haven't been drained, they stay queued, and run along
+    /// with this frame's the next time its queue is drained.
+    pub fn flip(&self) {
+        let i = self.current.fetch_xor(1, Ordering::AcqRel);
+        while self.pushing[i].fetch_add(0, Ordering::AcqRel) != 0 {
+            sync::spin_loop();
+        }
+    }
+
+    /// Run every callback of the previous frame, in the order they were
+    /// pushed.
+    ///
+    /// Returns the number of callbacks run. If a callback panics, the ones
+    /// after it are dropped without running.
+    pub fn drain_previous(&self) -> usize {
+        let i = self.current.load(Ordering::Acquire);
+        self.queues[1 - i].run_all()
+    }
+
+    /// Whether the current frame has no callbacks yet.
+    ///
+    /// This is only a snapshot: another thread may push immediately
+    /// afterwards.
+    pub fn is_current_empty(&self) -> bool {
+        self.queues[self.current.load(Ordering::Acquire)].is_empty()
+    }
+
+    /// Whether the previous frame has no callbacks left to drain.
+    pub fn is_previous_empty(&self) -> bool {
+        self.queues[1 - self.current.load(Ordering::Acquire)].is_empty()
+    }
+}
+
+impl Default for DoubleBufferedCallbackQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for DoubleBufferedCallbackQueue {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("DoubleBufferedCallbackQueue")
+            .field("current_empty", &self.is_current_empty())
+            .field("previous_empty", &self.is_previous_empty())
+            .finish()
+    }
+}
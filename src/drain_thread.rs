@@ -0,0 +1,242 @@
+
+use crate::{
+    sync::{AtomicBool, AtomicUsize},
+    DeferredCallQueue,
+    Sealed,
+};
+use alloc::{
+    string::String,
+    sync::Arc,
+};
+use core::{
+    sync::atomic::Ordering,
+    fmt::{self, Formatter, Debug},
+};
+use std::{
+    io,
+    panic::{self, AssertUnwindSafe},
+    thread::{self, JoinHandle, Thread},
+};
+
+// internals
+// ---------
+//
+// the worker thread runs a `DeferredCallQueue` of callbacks, each wrapped to
+// catch its panic and output whether it panicked, so that one panicking
+// callback neither stops the thread nor drops the rest of its batch. between
+// callbacks, it checks whether to drop the rest, so that a shutdown dropping
+// them doesn't wait for the whole batch.
+//
+// every push unparks the worker after pushing. the worker only parks after
+// finding the queue empty, and a push after that check leaves the thread's
+// unpark token set, so the park returns immediately, and no push is missed.
+//
+// a push announces itself in `pushing`, then checks `closed`. shutting down
+// stores the policy, sets `closed`, then waits for `pushing` to drop to zero.
+// both checks follow a write to the other's variable, all sequentially
+// consistent, so a push and a shutdown which race can't both miss each other:
+// the push either sees `closed`, and hands its callback back, or is waited for,
+// and lands in the queue. once no push is in progress, shutting down seals the
+// queue, which the worker reads before the policy, then unparks the worker,
+// which exits once the queue is sealed and empty.
+
+/// What [`DrainThread::shutdown`] does with callbacks still queued.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ShutdownPolicy {
+    /// Run every queued callback before the thread exits.
+    RunRemaining,
+    /// Drop queued callbacks without running them.
+    DropRemaining,
+}
+
+/// Outcome of [`DrainThread::shutdown`], counting every callback pushed over
+/// the thread's lifetime.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct DrainReport {
+    /// Number of callbacks run, including those which panicked.
+    pub ran: usize,
+    /// Number of callbacks which panicked.
+    pub panicked: usize,
+    /// Number of callbacks dropped without running, by
+    /// [`ShutdownPolicy::DropRemaining`].
+    pub dropped: usize,
+}
+
+struct Shared {
+    queue: DeferredCallQueue<(), bool>,
+    closed: AtomicBool,
+    pushing: AtomicUsize,
+    drop_remaining: AtomicBool,
+    ran: AtomicUsize,
+    panicked: AtomicUsize,
+    dropped: AtomicUsize,
+}
+
+/// A background thread which runs callbacks pushed to it, in the order they
+/// were pushed.
+///
+/// For deferring work to another thread: [`push`][Self::push] a callback,
+/// from any thread, through a [`DrainHandle`] from [`handle`][Self::handle].
+/// The thread parks while there's nothing to run, rather than spinning.
+///
+/// A callback which panics is caught and counted, and the thread carries on
+/// with the next. [`shutdown`][Self::shutdown] stops accepting callbacks,
+/// then runs or drops those still queued, and joins the thread. Dropping a
+/// `DrainThread` shuts it down with [`ShutdownPolicy::RunRemaining`].
+pub struct DrainThread {
+    handle: DrainHandle,
+    join: Option<JoinHandle<()>>,
+}
+
+/// A handle for pushing callbacks to a [`DrainThread`], from any thread.
+#[derive(Clone)]
+pub struct DrainHandle {
+    shared: Arc<Shared>,
+    thread: Thread,
+}
+
+impl DrainThread {
+    /// Spawn the thread, with the given name.
+    ///
+    /// Returns the error from spawning the thread, if it fails.
+    pub fn spawn(name: impl Into<String>) -> io::Result<Self> {
+        let shared = Arc::new(Shared {
+            queue: DeferredCallQueue::new(),
+            closed: AtomicBool::new(false),
+            pushing: AtomicUsize::new(0),
+            drop_remaining: AtomicBool::new(false),
+            ran: AtomicUsize::new(0),
+            panicked: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        });
+        let join = thread::Builder::new()
+            .name(name.into())
+            .spawn({
+                let shared = Arc::clone(&shared);
+                move || run(&shared)
+            })?;
+        Ok(DrainThread {
+            handle: DrainHandle {
+                shared,
+                thread: join.thread().clone(),
+            },
+            join: Some(join),
+        })
+    }
+
+    /// Push a callback, to be run on the thread.
+    ///
+    /// See [`DrainHandle::push`].
+    pub fn push<F: FnOnce() + Send + 'static>(&self, f: F) -> Result<(), Sealed<F>> {
+        self.handle.push(f)
+    }
+
+    /// A handle for pushing callbacks from other threads.
+    pub fn handle(&self) -> DrainHandle {
+        self.handle.clone()
+    }
+
+    /// The thread the callbacks run on.
+    pub fn thread(&self) -> &Thread {
+        &self.handle.thread
+    }
+
+    /// Stop accepting callbacks, deal with those still queued according to
+    /// the policy, then join the thread.
+    ///
+    /// Callbacks pushed once this has started are handed back to their
+    /// pushers. Waits for the callback running, if any, to finish.
+    pub fn shutdown(mut self, policy: ShutdownPolicy) -> DrainReport {
+        self.shutdown_with(policy)
+    }
+
+    fn shutdown_with(&mut self, policy: ShutdownPolicy) -> DrainReport {
+        let shared = &self.handle.shared;
+        if let Some(join) = self.join.take() {
+            shared.drop_remaining.store(policy == ShutdownPolicy::DropRemaining, Ordering::Relaxed);
+            shared.closed.store(true, Ordering::SeqCst);
+            while shared.pushing.load(Ordering::SeqCst) != 0 {
+                thread::yield_now();
+            }
+            shared.queue.seal();
+            join.thread().unpark();
+            // the worker catches every panic from the callbacks
+            join.join().unwrap();
+        }
+        DrainReport {
+            ran: shared.ran.load(Ordering::Relaxed),
+            panicked: shared.panicked.load(Ordering::Relaxed),
+            dropped: shared.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl DrainHandle {
+    /// Push a callback, to be run on the thread.
+    ///
+    /// Makes only one heap allocation, and doesn't block. If the thread is
+    /// shutting down, returns the callback instead, without queueing it.
+    pub fn push<F: FnOnce() + Send + 'static>(&self, f: F) -> Result<(), Sealed<F>> {
+        let shared = &self.shared;
+        shared.pushing.fetch_add(1, Ordering::SeqCst);
+        if shared.closed.load(Ordering::SeqCst) {
+            shared.pushing.fetch_sub(1, Ordering::Release);
+            return Err(Sealed(f));
+        }
+        // panics are caught on the thread, and only counted, so nothing broken
+        // by one is observed. never sealed while a push is in progress
+        let _ = shared.queue.push(move |()| panic::catch_unwind(AssertUnwindSafe(f)).is_err(), ());
+        shared.pushing.fetch_sub(1, Ordering::Release);
+        self.thread.unpark();
+        Ok(())
+    }
+
+    /// The number of callbacks which have panicked so far.
+    pub fn panicked(&self) -> usize {
+        self.shared.panicked.load(Ordering::Relaxed)
+    }
+}
+
+// the worker thread's loop.
+fn run(shared: &Shared) {
+    // whether to drop the callbacks left, checked between callbacks too
+    let dropping = || shared.queue.is_sealed() && shared.drop_remaining.load(Ordering::Relaxed);
+    loop {
+        let sealed = shared.queue.is_sealed();
+        if dropping() {
+            let dropped = shared.queue.retain(|_| false);
+            shared.dropped.fetch_add(dropped, Ordering::Relaxed);
+            return;
+        }
+        let (ran, _) = shared.queue.run_until(dropping, |panicked| {
+            if panicked {
+                shared.panicked.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        shared.ran.fetch_add(ran, Ordering::Relaxed);
+        if shared.queue.is_empty() {
+            if sealed {
+                return;
+            }
+            thread::park();
+        }
+    }
+}
+
+impl Drop for DrainThread {
+    fn drop(&mut self) {
+        self.shutdown_with(ShutdownPolicy::RunRemaining);
+    }
+}
+
+impl Debug for DrainThread {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("DrainThread").field(&self.handle.thread.name()).finish()
+    }
+}
+
+impl Debug for DrainHandle {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("DrainHandle").field(&self.thread.name()).finish()
+    }
+}
@@ -0,0 +1,69 @@
+
+use crate::raw;
+use core::fmt::{self, Formatter, Debug};
+
+/// A callback displaced by a `put_defer_drop`, handed to a [`DropSink`] to be
+/// dropped elsewhere.
+///
+/// This owns the callback, which can no longer be run. Dropping it drops the
+/// callback and frees its heap allocation, on whichever thread that happens.
+pub struct DisplacedCallback {
+    ptr: *mut u8,
+    drop_fn: unsafe fn(*mut u8),
+}
+
+// safety: erased callbacks are Send.
+unsafe impl Send for DisplacedCallback {}
+
+impl DisplacedCallback {
+    // take ownership of the pointed to erased callback. the pointer must be
+    // non-null.
+    pub(crate) unsafe fn new<I, O>(ptr: *mut u8) -> Self {
+        DisplacedCallback {
+            ptr,
+            drop_fn: raw::drop_raw::<I, O>,
+        }
+    }
+}
+
+impl Drop for DisplacedCallback {
+    fn drop(&mut self) {
+        unsafe { (self.drop_fn)(self.ptr) };
+    }
+}
+
+impl Debug for DisplacedCallback {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("DisplacedCallback(..)")
+    }
+}
+
+/// Somewhere to send displaced callbacks instead of dropping them inline, such
+/// as a channel to a janitor thread which drops them.
+///
+/// Implemented for closures, and with `std`, for `mpsc` senders. If a sender's
+/// receiver has been dropped, the callback is dropped inline.
+pub trait DropSink {
+    /// Take ownership of the displaced callback.
+    fn accept(&self, callback: DisplacedCallback);
+}
+
+impl<F: Fn(DisplacedCallback)> DropSink for F {
+    fn accept(&self, callback: DisplacedCallback) {
+        self(callback);
+    }
+}
+
+#[cfg(feature = "std")]
+impl DropSink for std::sync::mpsc::Sender<DisplacedCallback> {
+    fn accept(&self, callback: DisplacedCallback) {
+        let _ = self.send(callback);
+    }
+}
+
+#[cfg(feature = "std")]
+impl DropSink for std::sync::mpsc::SyncSender<DisplacedCallback> {
+    fn accept(&self, callback: DisplacedCallback) {
+        let _ = self.send(callback);
+    }
+}
@@ -0,0 +1,234 @@
+
+use crate::waiters::WaiterList;
+use crossbeam_epoch::{self as epoch, Atomic, Owned};
+use core::{
+    sync::atomic::Ordering,
+    panic::{UnwindSafe, RefUnwindSafe},
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    fmt::{self, Formatter, Debug},
+};
+use alloc::{
+    boxed::Box,
+    vec::Vec,
+};
+
+// internals
+// ---------
+//
+// the handler is an epoch-managed pointer to a boxed `dyn Fn`. a call pins
+// the current thread's epoch, loads the pointer, and runs the handler while
+// still pinned, so the handler can't be freed while it runs. replacing or
+// clearing the handler swaps the pointer, then defers destroying the old
+// handler until every thread pinned at the time has unpinned, through
+// crossbeam-epoch's default collector. so a call does no reference counting
+// and takes no lock, but a handler which runs for a long time delays freeing
+// every handler displaced meanwhile, and displaced handlers are freed on
+// whichever thread next advances the collector.
+//
+// tasks waiting for a handler to be set are in a `WaiterList`, woken by each
+// put, as in `MultiCallbackCellArgs`.
+
+type Handler<I, O> = Box<dyn Fn(I) -> O + Send + Sync + 'static>;
+
+/// A [`MultiCallbackCellArgs`][crate::MultiCallbackCellArgs] whose calls
+/// don't touch a reference count, behind the `crossbeam-epoch` feature.
+///
+/// Has the same methods as `MultiCallbackCellArgs`, other than output
+/// streams, so either can be chosen with a type alias. Calls are cheaper,
+/// since they only pin an epoch, at the cost of displaced handlers being
+/// dropped later, on an arbitrary thread, rather than once calls in progress
+/// finish. This suits handlers which are called very often and replaced
+/// rarely.
+pub struct EpochCallbackCellFn<I, O> {
+    handler: Atomic<Handler<I, O>>,
+    set_waiters: WaiterList,
+}
+
+// safety: the handler is Send and Sync, and `I` and `O` values never cross
+//         threads through the cell.
+unsafe impl<I, O> Send for EpochCallbackCellFn<I, O> {}
+unsafe impl<I, O> Sync for EpochCallbackCellFn<I, O> {}
+
+// the handler is swapped in one atomic operation, so a panicking handler leaves
+// the cell as it was.
+impl<I, O> UnwindSafe for EpochCallbackCellFn<I, O> {}
+impl<I, O> RefUnwindSafe for EpochCallbackCellFn<I, O> {}
+
+impl<I, O> EpochCallbackCellFn<I, O> {
+    /// Construct with no handler.
+    ///
+    /// This is a `const fn`, so cells can be placed in statics.
+    pub const fn new() -> Self {
+        EpochCallbackCellFn {
+            handler: Atomic::null(),
+            set_waiters: WaiterList::new(),
+        }
+    }
+
+    // swap the handler, deferring the destruction of the old one. returns
+    // whether one was present.
+    fn swap(&self, new: Option<Handler<I, O>>) -> bool {
+        let guard = epoch::pin();
+        let old = match new {
+            Some(new) => self.handler.swap(Owned::new(new), Ordering::AcqRel, &guard),
+            None => self.handler.swap(epoch::Shared::null(), Ordering::AcqRel, &guard),
+        };
+        if old.is_null() {
+            return false;
+        }
+        // safety: the old handler is unreachable from the cell, so can be
+        //         destroyed once threads which may have loaded it unpin.
+        unsafe { guard.defer_destroy(old) };
+        true
+    }
+
+    /// Set the handler, then wake any tasks waiting for a handler to be set.
+    ///
+    /// Any handler previously present is dropped once every call in progress
+    /// on any thread has finished, possibly on another thread.
+    pub fn put<F: Fn(I) -> O + Send + Sync + 'static>(&self, f: F) {
+        self.swap(Some(Box::new(f)));
+        self.set_waiters.wake_all();
+    }
+
+    // run the closure on the handler, if present, while pinned.
+    fn with_handler<R>(&self, f: impl FnOnce(&Handler<I, O>) -> R) -> Option<R> {
+        let guard = epoch::pin();
+        let handler = self.handler.load(Ordering::Acquire, &guard);
+        // safety: the handler isn't destroyed until after the guard is dropped.
+        unsafe { handler.as_ref() }.map(f)
+    }
+
+    /// Call the handler with the given input, leaving it in place.
+    ///
+    /// Returns the output if a handler was present. If a handler was not
+    /// present, returns the original input.
+    pub fn call(&self, input: I) -> Result<O, I> {
+        let mut input = Some(input);
+        self.with_handler(|handler| handler(input.take().unwrap()))
+            .ok_or_else(|| input.unwrap())
+    }
+
+    /// Call the handler with each input in turn, collecting the outputs.
+    ///
+    /// The handler is looked up once, so if it's replaced or cleared partway
+    /// through, the batch finishes with the handler present when this was
+    /// called. If no handler was present, returns the inputs untouched.
+    pub fn call_each<It: IntoIterator<Item = I>>(&self, inputs: It) -> Result<Vec<O>, It::IntoIter> {
+        let mut outputs = Vec::new();
+        self.call_each_with(inputs, |output| outputs.push(output))?;
+        Ok(outputs)
+    }
+
+    /// Like [`call_each`][Self::call_each], but passing each output to `sink`
+    /// rather than collecting them.
+    ///
+    /// Returns how many inputs were handled.
+    pub fn call_each_with<It, S>(&self, inputs: It, mut sink: S) -> Result<usize, It::IntoIter>
+    where
+        It: IntoIterator<Item = I>,
+        S: FnMut(O),
+    {
+        let mut inputs = Some(inputs.into_iter());
+        self.with_handler(|handler| {
+            let mut handled = 0;
+            for input in inputs.take().unwrap() {
+                sink(handler(input));
+                handled += 1;
+            }
+            handled
+        })
+        .ok_or_else(|| inputs.unwrap())
+    }
+
+    /// Remove the handler.
+    ///
+    /// Returns true if a handler was present. The handler is dropped as for
+    /// [`put`][Self::put].
+    pub fn clear(&self) -> bool {
+        self.swap(None)
+    }
+
+    /// Whether a handler is currently present.
+    ///
+    /// This is only a snapshot: another thread may put or clear a handler
+    /// immediately afterwards.
+    pub fn is_set(&self) -> bool {
+        let guard = epoch::pin();
+        !self.handler.load(Ordering::Acquire, &guard).is_null()
+    }
+
+    /// Get a closure which calls [`call`][Self::call], returning `None` and
+    /// dropping the input if no handler is present.
+    pub fn as_closure(&self) -> impl Fn(I) -> Option<O> + '_ {
+        move |input| self.call(input).ok()
+    }
+
+    /// Wait until a handler is present.
+    ///
+    /// Any number of tasks can wait at once. Dropping the future deregisters
+    /// its waker.
+    pub fn wait_set(&self) -> EpochWaitSet<'_, I, O> {
+        EpochWaitSet { cell: self, id: None }
+    }
+}
+
+impl<I, O> Drop for EpochCallbackCellFn<I, O> {
+    fn drop(&mut self) {
+        // safety: no other thread can access the cell, and handlers displaced
+        //         earlier are destroyed by the collector.
+        unsafe {
+            let handler = self.handler.load(Ordering::Relaxed, epoch::unprotected());
+            if !handler.is_null() {
+                drop(handler.into_owned());
+            }
+        }
+    }
+}
+
+impl<I, O> Default for EpochCallbackCellFn<I, O> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I, O> Debug for EpochCallbackCellFn<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_set() {
+            f.write_str("EpochCallbackCellFn(NOT NULL)")
+        } else {
+            f.write_str("EpochCallbackCellFn(NULL)")
+        }
+    }
+}
+
+/// Future returned by [`EpochCallbackCellFn::wait_set`].
+#[must_use = "futures do nothing unless polled"]
+pub struct EpochWaitSet<'a, I, O> {
+    cell: &'a EpochCallbackCellFn<I, O>,
+    id: Option<u64>,
+}
+
+impl<I, O> Future for EpochWaitSet<'_, I, O> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = &mut *self;
+        if this.cell.is_set() {
+            return Poll::Ready(());
+        }
+        this.cell.set_waiters.register(&mut this.id, cx.waker());
+        if this.cell.is_set() {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl<I, O> Drop for EpochWaitSet<'_, I, O> {
+    fn drop(&mut self) {
+        self.cell.set_waiters.deregister(self.id);
+    }
+}
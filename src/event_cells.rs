@@ -0,0 +1,148 @@
+
+use crate::{
+    sync::{self, AtomicBool},
+    CallbackCellArgs,
+};
+use core::{
+    sync::atomic::Ordering,
+    cell::UnsafeCell,
+    any::{Any, TypeId, type_name},
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+};
+
+// internals
+// ---------
+//
+// a map from the `TypeId` of each event type to a boxed
+// `CallbackCellArgs<Box<dyn Any + Send>, ()>`, guarded by a spin lock. the lock
+// is only held to look up or insert a cell, which is then used without the
+// lock: cells are never removed from the map until it's dropped, and being
+// boxed, they don't move when the map changes, so a reference to one lives as
+// long as the borrow of the registry.
+//
+// the callback put for an event type `E` is only ever stored in the cell for
+// `TypeId::of::<E>()`, and is only ever called with a box holding an `E`, so
+// the downcasts in both directions always succeed.
+
+type AnyEvent = Box<dyn Any + Send>;
+
+/// A registry of callbacks keyed by the type of event they handle.
+///
+/// For each event type `E`, holds a callback like a
+/// [`CallbackCellArgs<E, ()>`][crate::CallbackCellArgs], put with
+/// [`put_for`][Self::put_for] and run by [`emit`][Self::emit]ting an `E`.
+/// Each callback is taken when it runs, so it handles one event.
+pub struct EventCells {
+    locked: AtomicBool,
+    cells: UnsafeCell<BTreeMap<TypeId, Box<CallbackCellArgs<AnyEvent, ()>>>>,
+}
+
+// safety: the map is only accessed while holding the lock, and the cells in it
+//         are Send and Sync.
+unsafe impl Send for EventCells {}
+unsafe impl Sync for EventCells {}
+
+// the lock is only held for map operations, which don't call user code, and
+// callbacks run outside it, taken from their cell.
+impl UnwindSafe for EventCells {}
+impl RefUnwindSafe for EventCells {}
+
+impl EventCells {
+    const_fn! {
+        /// Construct with no callbacks.
+        ///
+        /// This is a `const fn`, so registries can be placed in statics.
+        pub fn new() -> Self {
+            EventCells {
+                locked: AtomicBool::new(false),
+                cells: UnsafeCell::new(BTreeMap::new()),
+            }
+        }
+    }
+
+    // run the closure on the map while holding the lock.
+    fn with_lock<R>(&self, f: impl FnOnce(&mut BTreeMap<TypeId, Box<CallbackCellArgs<AnyEvent, ()>>>) -> R) -> R {
+        while self.locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            sync::spin_loop();
+        }
+        let r = f(unsafe { &mut *self.cells.get() });
+        self.locked.store(false, Ordering::Release);
+        r
+    }
+
+    // the cell for the given event type, if there is one yet.
+    fn cell<E: 'static>(&self) -> Option<&CallbackCellArgs<AnyEvent, ()>> {
+        let cell = self.with_lock(|cells| cells.get(&TypeId::of::<E>()).map(|cell| &**cell as *const _))?;
+        // safety: see internals
+        Some(unsafe { &*cell })
+    }
+
+    // the cell for the given event type, inserting one if needed.
+    fn cell_or_insert<E: 'static>(&self) -> &CallbackCellArgs<AnyEvent, ()> {
+        let cell = self.with_lock(|cells| {
+            &**cells.entry(TypeId::of::<E>()).or_insert_with(|| Box::new(CallbackCellArgs::new())) as *const _
+        });
+        // safety: see internals
+        unsafe { &*cell }
+    }
+
+    /// Atomically set the callback for events of type `E`.
+    ///
+    /// Any callback previously present for `E` is dropped. Callbacks for
+    /// other event types are unaffected.
+    pub fn put_for<E: Send + 'static>(&self, f: impl FnOnce(E) + Send + 'static) {
+        self.cell_or_insert::<E>().put_replacing(
+            move |event: AnyEvent| f(*event.downcast::<E>().unwrap()),
+            Some(type_name::<E>()),
+        );
+    }
+
+    /// Atomically take the callback for the event's type then run it with the
+    /// event.
+    ///
+    /// Returns the event back if no callback was present for its type.
+    pub fn emit<E: Send + 'static>(&self, event: E) -> Result<(), E> {
+        let Some(cell) = self.cell::<E>() else {
+            return Err(event);
+        };
+        cell.take_call_named(Box::new(event), Some(type_name::<E>()))
+            .map_err(|event| *event.downcast::<E>().unwrap())
+    }
+
+    /// Atomically take the callback for events of type `E` and drop it
+    /// without running it.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear_for<E: 'static>(&self) -> bool {
+        self.cell::<E>().is_some_and(|cell| cell.clear_named(Some(type_name::<E>())))
+    }
+
+    /// Whether a callback is currently present for events of type `E`.
+    ///
+    /// This is only a snapshot: another thread may put or take a callback
+    /// immediately afterwards.
+    pub fn is_set_for<E: 'static>(&self) -> bool {
+        self.cell::<E>().is_some_and(|cell| cell.is_set())
+    }
+}
+
+impl Default for EventCells {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for EventCells {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let set = self.with_lock(|cells| cells.values().filter(|cell| cell.is_set()).count());
+        f.debug_struct("EventCells").field("set", &set).finish()
+    }
+}
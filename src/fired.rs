@@ -0,0 +1,202 @@
+
+use crate::{
+    sync::{AtomicPtr, LoadMut},
+    raw,
+    sealed::{is_tagged, untagged},
+};
+use core::{
+    sync::atomic::Ordering,
+    ptr,
+    marker::PhantomData,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug, Display},
+};
+
+// internals
+// ---------
+//
+// the inner atomic pointer is a nullable pointer to an erased callback, as
+// described in the `raw` module, or the null pointer tagged in its low bit once
+// a callback has run. the tag is the same bit the `sealed` module uses. a
+// `take_call` which finds a callback swaps in the tagged null pointer, and puts
+// are compare-exchanges which fail on the tag, so a put which races with the
+// firing `take_call` either lands before it, and is the callback which runs, or
+// sees the tag and hands the callback back.
+
+const FIRED: usize = 1;
+
+// the tagged null pointer.
+fn fired<T>() -> *mut T {
+    ptr::null_mut::<T>().map_addr(|_| FIRED)
+}
+
+/// Error returned when putting into a fired cell, holding the rejected
+/// callback.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Fired<T>(pub T);
+
+impl<T> Fired<T> {
+    /// The rejected callback.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Debug for Fired<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("Fired(..)")
+    }
+}
+
+impl<T> Display for Fired<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("already fired")
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> std::error::Error for Fired<T> {}
+
+/// A [`CallbackCellArgs`][crate::CallbackCellArgs] which remembers that its
+/// callback has run.
+///
+/// The cell is in one of three states: never armed, armed, or fired. Once
+/// [`take_call`][Self::take_call] runs a callback, the cell is fired:
+/// [`has_fired`][Self::has_fired] returns true, and puts hand the callback
+/// back, so that late producers know to run their work inline instead. This
+/// lasts until [`rearm`][Self::rearm].
+pub struct FireOnceCallbackCellArgs<I, O> {
+    ptr: AtomicPtr<u8>,
+    // invariant in `I` and `O`, like `CallbackCellArgs`.
+    _p: PhantomData<dyn FnOnce(I) -> O + Send + 'static>,
+}
+
+// safety: as for `CallbackCellArgs`.
+unsafe impl<I, O> Send for FireOnceCallbackCellArgs<I, O> {}
+unsafe impl<I, O> Sync for FireOnceCallbackCellArgs<I, O> {}
+impl<I, O> UnwindSafe for FireOnceCallbackCellArgs<I, O> {}
+impl<I, O> RefUnwindSafe for FireOnceCallbackCellArgs<I, O> {}
+
+impl<I, O> FireOnceCallbackCellArgs<I, O> {
+    const_fn! {
+        /// Construct never armed, with no callback.
+        ///
+        /// This is a `const fn`, so cells can be placed in statics.
+        pub fn new() -> Self {
+            FireOnceCallbackCellArgs {
+                ptr: AtomicPtr::new(ptr::null_mut()),
+                _p: PhantomData,
+            }
+        }
+    }
+
+    /// Atomically set the callback, unless the cell has fired.
+    ///
+    /// Any callback previously present is dropped. If the cell has fired,
+    /// returns the callback instead, without storing it.
+    pub fn put<F: FnOnce(I) -> O + Send + 'static>(&self, f: F) -> Result<(), Fired<F>> {
+        let mut old_ptr = self.ptr.load(Ordering::Relaxed);
+        if is_tagged(old_ptr) {
+            return Err(Fired(f));
+        }
+        let ptr = raw::alloc_raw(f);
+        loop {
+            if is_tagged(old_ptr) {
+                // fired since the check above
+                return Err(Fired(unsafe { raw::into_callback::<I, O, F>(ptr) }));
+            }
+            match self.ptr.compare_exchange_weak(old_ptr, ptr, Ordering::AcqRel, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(new_ptr) => old_ptr = new_ptr,
+            }
+        }
+        unsafe { raw::drop_raw::<I, O>(old_ptr) };
+        Ok(())
+    }
+
+    /// Atomically take the callback then run it with the given input, firing
+    /// the cell.
+    ///
+    /// Returns the output if a callback was present. If a callback was not
+    /// present, returns the original input, and the cell doesn't fire.
+    pub fn take_call(&self, input: I) -> Result<O, I> {
+        let mut old_ptr = self.ptr.load(Ordering::Relaxed);
+        loop {
+            if untagged(old_ptr).is_null() {
+                return Err(input);
+            }
+            match self.ptr.compare_exchange_weak(old_ptr, fired(), Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => return Ok(unsafe { raw::call_raw(old_ptr, input) }),
+                Err(new_ptr) => old_ptr = new_ptr,
+            }
+        }
+    }
+
+    /// Atomically take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present. This doesn't fire the cell.
+    pub fn clear(&self) -> bool {
+        let mut old_ptr = self.ptr.load(Ordering::Relaxed);
+        loop {
+            if untagged(old_ptr).is_null() {
+                return false;
+            }
+            match self.ptr.compare_exchange_weak(old_ptr, ptr::null_mut(), Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(new_ptr) => old_ptr = new_ptr,
+            }
+        }
+        unsafe { raw::drop_raw::<I, O>(old_ptr) };
+        true
+    }
+
+    /// Whether a callback is currently present.
+    ///
+    /// This is only a snapshot: another thread may put or take a callback
+    /// immediately afterwards.
+    pub fn is_set(&self) -> bool {
+        !untagged(self.ptr.load(Ordering::Acquire)).is_null()
+    }
+
+    /// Whether a callback has run, since construction or the last
+    /// [`rearm`][Self::rearm].
+    pub fn has_fired(&self) -> bool {
+        is_tagged(self.ptr.load(Ordering::Acquire))
+    }
+
+    /// Atomically reset a fired cell to empty, so that it accepts puts again.
+    ///
+    /// Returns false if the cell had not fired.
+    pub fn rearm(&self) -> bool {
+        self.ptr
+            .compare_exchange(fired(), ptr::null_mut(), Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+    }
+}
+
+impl<I, O> Drop for FireOnceCallbackCellArgs<I, O> {
+    fn drop(&mut self) {
+        unsafe {
+            raw::drop_raw::<I, O>(untagged(self.ptr.load_mut()));
+        }
+    }
+}
+
+impl<I, O> Default for FireOnceCallbackCellArgs<I, O> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I, O> Debug for FireOnceCallbackCellArgs<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let ptr = self.ptr.load(Ordering::Relaxed);
+        if is_tagged(ptr) {
+            f.write_str("FireOnceCallbackCellArgs(FIRED)")
+        } else if ptr.is_null() {
+            f.write_str("FireOnceCallbackCellArgs(NULL)")
+        } else {
+            f.write_str("FireOnceCallbackCellArgs(NOT NULL)")
+        }
+    }
+}
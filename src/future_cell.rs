@@ -0,0 +1,234 @@
+
+use crate::{
+    sync::{AtomicPtr, LoadMut},
+    async_raw::BoxFuture,
+};
+use alloc::boxed::Box;
+use core::{
+    sync::atomic::Ordering,
+    ptr,
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+
+// internals
+// ---------
+//
+// the inner atomic pointer is a nullable pointer to a heap-allocated
+// `BoxFuture<()>`, or one of two sentinel values, which are never the address
+// of an allocation:
+//
+// - `POLLING`, while a `poll_take` has taken the future to poll it
+// - `NOTIFIED`, while a `poll_take` is polling it, and another `poll_take`
+//   has found it being polled
+//
+// moving the outer box moves only the `Pin<Box<..>>`, never the future, so
+// the future stays where it was pinned however many times it changes hands.
+//
+// a `poll_take` which finds a future swaps in `POLLING`, polls the future, and
+// if it's pending, compare-exchanges it back in place of `POLLING`. if that
+// fails on `NOTIFIED`, the future may have been woken by a wake which found
+// it missing, so it's polled again rather than stored. if that fails on
+// anything else, a put replaced the future while it was being polled, so it's
+// dropped, as though replaced. puts, takes, and clears treat the sentinels as
+// empty, and a poll which panics drops the future and resets a sentinel to
+// empty.
+
+const POLLING: usize = 1;
+const NOTIFIED: usize = 2;
+
+fn sentinel(addr: usize) -> *mut BoxFuture<()> {
+    ptr::null_mut::<BoxFuture<()>>().map_addr(|_| addr)
+}
+
+fn is_future(ptr: *mut BoxFuture<()>) -> bool {
+    !ptr.is_null() && ptr.addr() != POLLING && ptr.addr() != NOTIFIED
+}
+
+unsafe fn drop_future(ptr: *mut BoxFuture<()>) {
+    if is_future(ptr) {
+        drop(Box::from_raw(ptr));
+    }
+}
+
+/// Like an `Atomic<Option<Pin<Box<dyn Future<Output = ()> + Send + 'static>>>>`.
+///
+/// For handing a future, perhaps already partially polled, from one thread
+/// to another. Puts replace and drop any future present, like
+/// [`CallbackCell`][crate::CallbackCell]'s. The future stays pinned at the
+/// same address throughout: only the box holding it changes hands.
+pub struct FutureCell(AtomicPtr<BoxFuture<()>>);
+
+// safety: the future is Send, and is only accessed by whichever thread took
+//         it from the cell.
+unsafe impl Send for FutureCell {}
+unsafe impl Sync for FutureCell {}
+
+// the cell is changed in single atomic operations, and a poll which panics
+// drops its future and leaves the cell empty, unless a put replaced it.
+impl UnwindSafe for FutureCell {}
+impl RefUnwindSafe for FutureCell {}
+
+impl FutureCell {
+    const_fn! {
+        /// Construct with no future.
+        ///
+        /// This is a `const fn`, so cells can be placed in statics.
+        pub fn new() -> Self {
+            FutureCell(AtomicPtr::new(ptr::null_mut()))
+        }
+    }
+
+    /// Atomically set the future.
+    ///
+    /// Any future previously present is dropped, including one being polled
+    /// by [`poll_take`][Self::poll_take], once that poll returns.
+    pub fn put(&self, fut: BoxFuture<()>) {
+        let ptr = Box::into_raw(Box::new(fut));
+        let old_ptr = self.0.swap(ptr, Ordering::AcqRel);
+        unsafe { drop_future(old_ptr) };
+    }
+
+    // atomically take the future's pointer, if a future is present.
+    fn take_ptr(&self) -> Option<*mut BoxFuture<()>> {
+        let mut old_ptr = self.0.load(Ordering::Relaxed);
+        loop {
+            if !is_future(old_ptr) {
+                return None;
+            }
+            match self.0.compare_exchange_weak(old_ptr, ptr::null_mut(), Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => return Some(old_ptr),
+                Err(ptr) => old_ptr = ptr,
+            }
+        }
+    }
+
+    /// Atomically take the future.
+    ///
+    /// Returns `None` if no future was present, including while one is being
+    /// polled by [`poll_take`][Self::poll_take].
+    pub fn take(&self) -> Option<BoxFuture<()>> {
+        self.take_ptr().map(|ptr| *unsafe { Box::from_raw(ptr) })
+    }
+
+    /// Atomically take the future, poll it once, and put it back if it's
+    /// still pending.
+    ///
+    /// Returns `None` if no future was present, `Some(Poll::Ready(()))` if it
+    /// completed, and `Some(Poll::Pending)` otherwise. While it's being
+    /// polled, the cell appears empty to other operations, except that
+    /// another `poll_take` makes this one poll it again before putting it
+    /// back, and returns `Some(Poll::Pending)` itself. So a wake which
+    /// arrives while the future is being polled isn't lost. If a put replaces
+    /// it while it's being polled, it's dropped instead of put back.
+    pub fn poll_take(&self, cx: &mut Context) -> Option<Poll<()>> {
+        let mut old_ptr = self.0.load(Ordering::Relaxed);
+        let ptr = loop {
+            if old_ptr.is_null() {
+                return None;
+            }
+            let new = if is_future(old_ptr) { sentinel(POLLING) } else { sentinel(NOTIFIED) };
+            match self.0.compare_exchange_weak(old_ptr, new, Ordering::AcqRel, Ordering::Relaxed) {
+                Ok(_) if is_future(old_ptr) => break old_ptr,
+                Ok(_) => return Some(Poll::Pending),
+                Err(ptr) => old_ptr = ptr,
+            }
+        };
+
+        // resets the sentinel if polling panics.
+        struct Reset<'a>(&'a FutureCell);
+
+        impl Drop for Reset<'_> {
+            fn drop(&mut self) {
+                self.0.reset();
+            }
+        }
+
+        let mut fut = unsafe { Box::from_raw(ptr) };
+        let reset = Reset(self);
+        loop {
+            if Pin::as_mut(&mut fut).poll(cx).is_ready() {
+                drop(fut);
+                drop(reset);
+                return Some(Poll::Ready(()));
+            }
+            let ptr = Box::into_raw(fut);
+            match self.0.compare_exchange(sentinel(POLLING), ptr, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => {
+                    mem::forget(reset);
+                    return Some(Poll::Pending);
+                }
+                Err(cur) if cur.addr() == NOTIFIED && self.0
+                    .compare_exchange(cur, sentinel(POLLING), Ordering::Relaxed, Ordering::Relaxed)
+                    .is_ok() =>
+                {
+                    // poll again
+                    fut = unsafe { Box::from_raw(ptr) };
+                }
+                Err(_) => {
+                    // replaced by a put
+                    unsafe { drop_future(ptr) };
+                    mem::forget(reset);
+                    return Some(Poll::Pending);
+                }
+            }
+        }
+    }
+
+    // reset a sentinel to empty, leaving a future put meanwhile in place.
+    fn reset(&self) {
+        let mut old_ptr = self.0.load(Ordering::Relaxed);
+        while !old_ptr.is_null() && !is_future(old_ptr) {
+            match self.0.compare_exchange_weak(old_ptr, ptr::null_mut(), Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(ptr) => old_ptr = ptr,
+            }
+        }
+    }
+
+    /// Atomically take the future and drop it without polling it.
+    ///
+    /// Returns true if a future was present.
+    pub fn clear(&self) -> bool {
+        match self.take_ptr() {
+            Some(ptr) => {
+                unsafe { drop_future(ptr) };
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether a future is currently present, and not being polled.
+    ///
+    /// This is only a snapshot: another thread may put or take a future
+    /// immediately afterwards.
+    pub fn is_set(&self) -> bool {
+        is_future(self.0.load(Ordering::Acquire))
+    }
+}
+
+impl Drop for FutureCell {
+    fn drop(&mut self) {
+        unsafe { drop_future(self.0.load_mut()) };
+    }
+}
+
+impl Default for FutureCell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for FutureCell {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_set() {
+            f.write_str("FutureCell(NOT NULL)")
+        } else {
+            f.write_str("FutureCell(NULL)")
+        }
+    }
+}
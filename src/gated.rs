@@ -0,0 +1,294 @@
+
+use crate::{
+    sync::AtomicBool,
+    CallbackCell,
+    CallbackCellArgs,
+};
+use alloc::sync::Arc;
+use core::{
+    sync::atomic::Ordering,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug, Display},
+};
+
+// internals
+// ---------
+//
+// a gate is a shared flag, which each gated cell holds a clone of beside an
+// ordinary cell. a take loads the flag, relaxed, before touching the cell, and
+// leaves the cell alone if the gate is closed. nothing is published through the
+// flag, so it needs no ordering: the callback itself is published through the
+// cell as usual. so unlike pausing a `PausableCallbackCellArgs`, closing a gate
+// isn't ordered with the takes of its cells: a take may see the gate as it was
+// shortly before, on another thread.
+
+/// A switch which enables or disables a whole family of gated cells at once.
+///
+/// Clones share one flag. Each [`GatedCallbackCell`] and
+/// [`GatedCallbackCellArgs`] is constructed with a clone, and while the gate
+/// is disabled, its takes are no-ops which leave its callback in place. Puts
+/// and clears work either way, so handlers can be registered ahead of time.
+///
+/// This is for instrumentation points, which are toggled as a group rather
+/// than one by one. While enabled, a take costs one relaxed load more than
+/// an ordinary cell's. Since that load isn't ordered with anything, a take
+/// racing with [`enable`][Self::enable] or [`disable`][Self::disable] on
+/// another thread may see the gate either way.
+#[derive(Clone)]
+pub struct Gate {
+    enabled: Arc<AtomicBool>,
+}
+
+impl Gate {
+    /// Construct, enabled.
+    pub fn new() -> Self {
+        Gate {
+            enabled: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    /// Construct, disabled.
+    pub fn new_disabled() -> Self {
+        Gate {
+            enabled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Enable every cell gated by this gate, so their takes run callbacks
+    /// again.
+    ///
+    /// Returns false if the gate was already enabled.
+    pub fn enable(&self) -> bool {
+        !self.enabled.swap(true, Ordering::Relaxed)
+    }
+
+    /// Disable every cell gated by this gate, so their takes leave callbacks
+    /// in place.
+    ///
+    /// Returns false if the gate was already disabled.
+    pub fn disable(&self) -> bool {
+        self.enabled.swap(false, Ordering::Relaxed)
+    }
+
+    /// Whether the gate is currently enabled.
+    ///
+    /// This is only a snapshot: another thread may enable or disable the gate
+    /// immediately afterwards.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for Gate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for Gate {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_enabled() {
+            f.write_str("Gate(ENABLED)")
+        } else {
+            f.write_str("Gate(DISABLED)")
+        }
+    }
+}
+
+/// Error returned by [`GatedCallbackCellArgs::take_call`] when no callback
+/// ran, holding the input.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum GateNotRun<I> {
+    /// No callback was present.
+    Empty(I),
+    /// The gate was disabled, so any callback present was left in place.
+    Disabled(I),
+}
+
+impl<I> GateNotRun<I> {
+    /// The input which wasn't passed to a callback.
+    pub fn into_input(self) -> I {
+        match self {
+            GateNotRun::Empty(input) | GateNotRun::Disabled(input) => input,
+        }
+    }
+
+    /// Whether no callback ran because the gate was disabled.
+    pub fn is_disabled(&self) -> bool {
+        matches!(self, GateNotRun::Disabled(_))
+    }
+}
+
+impl<I> Debug for GateNotRun<I> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            GateNotRun::Empty(_) => f.write_str("Empty(..)"),
+            GateNotRun::Disabled(_) => f.write_str("Disabled(..)"),
+        }
+    }
+}
+
+impl<I> Display for GateNotRun<I> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            GateNotRun::Empty(_) => f.write_str("no callback present"),
+            GateNotRun::Disabled(_) => f.write_str("gate disabled"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<I> std::error::Error for GateNotRun<I> {}
+
+/// A [`CallbackCell`][crate::CallbackCell] whose takes do nothing while its
+/// [`Gate`] is disabled.
+///
+/// Construct one with [`CallbackCell::new_gated`][crate::CallbackCell::new_gated],
+/// or [`new`][Self::new]. Besides the cell, it holds a clone of the gate, so
+/// it's two pointers wide.
+pub struct GatedCallbackCell {
+    cell: CallbackCell,
+    gate: Gate,
+}
+
+// a disabled take touches nothing, and otherwise, as for `CallbackCell`.
+impl UnwindSafe for GatedCallbackCell {}
+impl RefUnwindSafe for GatedCallbackCell {}
+
+impl GatedCallbackCell {
+    /// Construct with no callback, gated by the given gate.
+    pub fn new(gate: Gate) -> Self {
+        GatedCallbackCell {
+            cell: CallbackCell::new(),
+            gate,
+        }
+    }
+
+    /// Atomically set the callback, whether or not the gate is enabled.
+    ///
+    /// See [`CallbackCell::put`].
+    pub fn put<F: FnOnce() + Send + 'static>(&self, f: F) {
+        self.cell.put(f)
+    }
+
+    /// Atomically take the callback then run it, unless the gate is
+    /// disabled.
+    ///
+    /// Returns true if a callback ran. If the gate is disabled, any callback
+    /// present is left in place.
+    pub fn take_call(&self) -> bool {
+        self.gate.is_enabled() && self.cell.take_call()
+    }
+
+    /// Atomically take the callback and drop it without running it, whether
+    /// or not the gate is enabled.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        self.cell.clear()
+    }
+
+    /// Whether a callback is currently present, whether or not the gate is
+    /// enabled.
+    ///
+    /// This is only a snapshot: another thread may put or take a callback
+    /// immediately afterwards.
+    pub fn is_set(&self) -> bool {
+        self.cell.is_set()
+    }
+
+    /// The gate this cell is gated by.
+    pub fn gate(&self) -> &Gate {
+        &self.gate
+    }
+}
+
+impl Debug for GatedCallbackCell {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let set = if self.is_set() { "NOT NULL" } else { "NULL" };
+        if self.gate.is_enabled() {
+            write!(f, "GatedCallbackCell({})", set)
+        } else {
+            write!(f, "GatedCallbackCell({}, DISABLED)", set)
+        }
+    }
+}
+
+/// A [`CallbackCellArgs`][crate::CallbackCellArgs] whose takes do nothing
+/// while its [`Gate`] is disabled.
+///
+/// Construct one with
+/// [`CallbackCellArgs::new_gated`][crate::CallbackCellArgs::new_gated], or
+/// [`new`][Self::new]. Besides the cell, it holds a clone of the gate, so
+/// it's two pointers wide.
+pub struct GatedCallbackCellArgs<I, O> {
+    cell: CallbackCellArgs<I, O>,
+    gate: Gate,
+}
+
+// as for `GatedCallbackCell`.
+impl<I, O> UnwindSafe for GatedCallbackCellArgs<I, O> {}
+impl<I, O> RefUnwindSafe for GatedCallbackCellArgs<I, O> {}
+
+impl<I, O> GatedCallbackCellArgs<I, O> {
+    /// Construct with no callback, gated by the given gate.
+    pub fn new(gate: Gate) -> Self {
+        GatedCallbackCellArgs {
+            cell: CallbackCellArgs::new(),
+            gate,
+        }
+    }
+
+    /// Atomically set the callback, whether or not the gate is enabled.
+    ///
+    /// See [`CallbackCellArgs::put`].
+    pub fn put<F: FnOnce(I) -> O + Send + 'static>(&self, f: F) {
+        self.cell.put(f)
+    }
+
+    /// Atomically take the callback then run it with the given input, unless
+    /// the gate is disabled.
+    ///
+    /// Returns the output if a callback ran. Otherwise, returns the original
+    /// input, and whether the gate was disabled, in which case any callback
+    /// present is left in place.
+    pub fn take_call(&self, input: I) -> Result<O, GateNotRun<I>> {
+        if !self.gate.is_enabled() {
+            return Err(GateNotRun::Disabled(input));
+        }
+        self.cell.take_call(input).map_err(GateNotRun::Empty)
+    }
+
+    /// Atomically take the callback and drop it without running it, whether
+    /// or not the gate is enabled.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        self.cell.clear()
+    }
+
+    /// Whether a callback is currently present, whether or not the gate is
+    /// enabled.
+    ///
+    /// This is only a snapshot: another thread may put or take a callback
+    /// immediately afterwards.
+    pub fn is_set(&self) -> bool {
+        self.cell.is_set()
+    }
+
+    /// The gate this cell is gated by.
+    pub fn gate(&self) -> &Gate {
+        &self.gate
+    }
+}
+
+impl<I, O> Debug for GatedCallbackCellArgs<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let set = if self.is_set() { "NOT NULL" } else { "NULL" };
+        if self.gate.is_enabled() {
+            write!(f, "GatedCallbackCellArgs({})", set)
+        } else {
+            write!(f, "GatedCallbackCellArgs({}, DISABLED)", set)
+        }
+    }
+}
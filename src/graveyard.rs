@@ -0,0 +1,116 @@
+
+use crate::{
+    sync::{AtomicPtr, LoadMut},
+    raw,
+};
+use core::{
+    sync::atomic::Ordering,
+    ptr,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+
+// internals
+// ---------
+//
+// a graveyard is a lock-free stack of freed callbacks, as described in the
+// `raw` module, linked through their headers. the inner atomic pointer is a
+// nullable pointer to the most recently buried one. burying pushes with a
+// compare-exchange, and collecting takes the whole stack at once with a
+// swap, so there's no ABA hazard.
+
+/// Somewhere for the heap allocations of callbacks run by
+/// `take_call_defer_free` to wait to be deallocated, off the thread which ran
+/// them.
+///
+/// Where a callback cell's `take_call` deallocates the callback's heap
+/// allocation right after moving the callback out of it, which may take a
+/// lock inside the allocator, `take_call_defer_free` instead pushes the
+/// allocation onto a graveyard, without allocating, locking, or deallocating.
+/// Another thread, which can afford to, then deallocates them with
+/// [`collect`][Self::collect]. Dropping the graveyard deallocates any left.
+pub struct Graveyard {
+    head: AtomicPtr<u8>,
+}
+
+// safety: freed callbacks are only memory, holding no values.
+unsafe impl Send for Graveyard {}
+unsafe impl Sync for Graveyard {}
+
+// burying and collecting are single atomic operations.
+impl UnwindSafe for Graveyard {}
+impl RefUnwindSafe for Graveyard {}
+
+impl Graveyard {
+    const_fn! {
+        /// Construct with nothing buried.
+        ///
+        /// This is a `const fn`, so graveyards can be placed in statics.
+        pub fn new() -> Self {
+            Graveyard {
+                head: AtomicPtr::new(ptr::null_mut()),
+            }
+        }
+    }
+
+    // push a freed callback. the pointer must be non-null.
+    pub(crate) unsafe fn bury(&self, freed: *mut u8) {
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            raw::set_freed_next(freed, head);
+            match self.head.compare_exchange_weak(head, freed, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return,
+                Err(new_head) => head = new_head,
+            }
+        }
+    }
+
+    /// Deallocate every heap allocation buried so far.
+    ///
+    /// Returns how many were deallocated.
+    pub fn collect(&self) -> usize {
+        unsafe { collect_list(self.head.swap(ptr::null_mut(), Ordering::Acquire)) }
+    }
+
+    /// Whether nothing is currently buried.
+    ///
+    /// This is only a snapshot: another thread may bury or collect
+    /// immediately afterwards.
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire).is_null()
+    }
+}
+
+// deallocate every freed callback in the list starting at `ptr`.
+unsafe fn collect_list(mut ptr: *mut u8) -> usize {
+    let mut count = 0;
+    while !ptr.is_null() {
+        let next = raw::freed_next(ptr);
+        raw::dealloc_freed(ptr);
+        ptr = next;
+        count += 1;
+    }
+    count
+}
+
+impl Drop for Graveyard {
+    fn drop(&mut self) {
+        unsafe { collect_list(self.head.load_mut()) };
+    }
+}
+
+impl Default for Graveyard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for Graveyard {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_empty() {
+            f.write_str("Graveyard(EMPTY)")
+        } else {
+            f.write_str("Graveyard(NOT EMPTY)")
+        }
+    }
+}
@@ -0,0 +1,97 @@
+
+use core::{
+    ptr,
+    cell::Cell,
+    sync::atomic::{AtomicPtr, Ordering, fence},
+};
+use alloc::boxed::Box;
+
+// internals
+// ---------
+//
+// the installed hooks are a nullable pointer to a leaked `Hooks`. leaked
+// values are never freed, since another thread may be calling a hook from
+// them at any time. this uses core's atomics rather than those in `sync`, so
+// that it can be a static under loom.
+//
+// while a hook runs, further hooks on the same thread are skipped, so a hook
+// can use callback cells without recursing into itself.
+
+static HOOKS: AtomicPtr<Hooks> = AtomicPtr::new(ptr::null_mut());
+
+std::thread_local! {
+    static IN_HOOK: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Hooks for [`set_hooks`].
+///
+/// Each hook is optional. Hooks may be called from any thread, and must not
+/// panic.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Hooks {
+    /// Called when a callback is put into a cell.
+    pub on_put: Option<fn(&HookInfo)>,
+    /// Called when a callback is taken to be run, just before running it.
+    pub on_take: Option<fn(&HookInfo)>,
+    /// Called when a callback is dropped without being run: because it was
+    /// replaced, the cell was cleared, or the cell was dropped.
+    pub on_drop_unrun: Option<fn(&HookInfo)>,
+}
+
+/// Metadata passed to [`Hooks`].
+#[derive(Debug, Copy, Clone)]
+#[non_exhaustive]
+pub struct HookInfo {
+    /// The cell's type name, e.g. `"CallbackCell"`.
+    pub kind: &'static str,
+    /// The cell's address.
+    pub cell: *const (),
+    /// The cell's name, if it has one.
+    pub name: Option<&'static str>,
+    /// The callback's type name. Only known when it is put.
+    pub callback: Option<&'static str>,
+    /// The callback's label, if it was put with a label. Only known when it
+    /// is put.
+    pub label: Option<&'static str>,
+    /// For [`on_put`][Hooks::on_put], whether another callback was replaced.
+    pub displaced: bool,
+}
+
+/// Install process-wide hooks, replacing any installed before.
+///
+/// Like [`std::panic::set_hook`]. Covers [`CallbackCell`][crate::CallbackCell]
+/// and [`CallbackCellArgs`][crate::CallbackCellArgs].
+pub fn set_hooks(hooks: Hooks) {
+    let new = Box::into_raw(Box::new(hooks));
+    HOOKS.swap(new, Ordering::Release);
+}
+
+/// Uninstall the process-wide hooks, returning them.
+///
+/// Like [`std::panic::take_hook`].
+pub fn take_hooks() -> Option<Hooks> {
+    let old = HOOKS.swap(ptr::null_mut(), Ordering::Acquire);
+    unsafe { old.as_ref().copied() }
+}
+
+// call `f` with the installed hooks, if any and not already in a hook.
+#[inline]
+pub(crate) fn call(f: impl FnOnce(&Hooks)) {
+    let hooks = HOOKS.load(Ordering::Relaxed);
+    if hooks.is_null() {
+        return;
+    }
+    fence(Ordering::Acquire);
+    let _ = IN_HOOK.try_with(|in_hook| {
+        if !in_hook.replace(true) {
+            struct Reset<'a>(&'a Cell<bool>);
+            impl Drop for Reset<'_> {
+                fn drop(&mut self) {
+                    self.0.set(false);
+                }
+            }
+            let _reset = Reset(in_hook);
+            f(unsafe { &*hooks });
+        }
+    });
+}
@@ -0,0 +1,226 @@
+
+use crate::{
+    sync::{AtomicPtr, LoadMut},
+    raw,
+};
+use core::{
+    sync::atomic::Ordering,
+    ptr,
+    ops::{BitOr, BitOrAssign},
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+
+// internals
+// ---------
+//
+// two nullable pointers to erased callbacks, described in the `raw` module, one
+// for each interest, side by side and aligned to their combined size, so they
+// share one cache line. each slot is an independent `CallbackCell`: a put swaps
+// into its slot, and a fire or clear swaps each slot it names out, so whichever
+// swap takes a callback owns it, and it runs or drops at most once. there's no
+// lock to deadlock on, and a put racing a fire of the same interest either
+// lands before the fire's swap, and runs, or after, and stays armed.
+
+/// A set of I/O readiness interests, for [`InterestCallbackCell`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Interest(u8);
+
+impl Interest {
+    /// No interests.
+    pub const NONE: Interest = Interest(0);
+    /// The source is readable.
+    pub const READABLE: Interest = Interest(1);
+    /// The source is writable.
+    pub const WRITABLE: Interest = Interest(2);
+    /// Both readable and writable.
+    pub const BOTH: Interest = Interest(3);
+
+    /// Whether this includes [`READABLE`][Self::READABLE].
+    pub const fn is_readable(self) -> bool {
+        self.0 & Self::READABLE.0 != 0
+    }
+
+    /// Whether this includes [`WRITABLE`][Self::WRITABLE].
+    pub const fn is_writable(self) -> bool {
+        self.0 & Self::WRITABLE.0 != 0
+    }
+
+    /// Whether this includes no interests.
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether this includes every interest in `other`.
+    pub const fn contains(self, other: Interest) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Interest {
+    type Output = Interest;
+
+    fn bitor(self, rhs: Interest) -> Interest {
+        Interest(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Interest {
+    fn bitor_assign(&mut self, rhs: Interest) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl Debug for Interest {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Interest::NONE => "NONE",
+            Interest::READABLE => "READABLE",
+            Interest::WRITABLE => "WRITABLE",
+            _ => "READABLE | WRITABLE",
+        })
+    }
+}
+
+/// A pair of one-shot callbacks, one run when an I/O source becomes
+/// readable and one when it becomes writable.
+///
+/// For reactors, which register interest in each direction of a source
+/// independently: [`put_read`][Self::put_read] and
+/// [`put_write`][Self::put_write] each set one callback, replacing only the
+/// callback for their direction, and [`fire`][Self::fire] takes and runs the
+/// callbacks for the given [`Interest`]s. Each slot works like a
+/// [`CallbackCell`][crate::CallbackCell], so puts and fires never block each
+/// other, and no callback runs twice, however they race.
+///
+/// Both slots share one cache line, and each callback makes one heap
+/// allocation.
+#[repr(align(16))]
+pub struct InterestCallbackCell {
+    read: AtomicPtr<u8>,
+    write: AtomicPtr<u8>,
+}
+
+// safety: as for `CallbackCell`.
+unsafe impl Send for InterestCallbackCell {}
+unsafe impl Sync for InterestCallbackCell {}
+
+// a callback is taken out of its slot before it runs, so a panicking callback
+// leaves its slot empty.
+impl UnwindSafe for InterestCallbackCell {}
+impl RefUnwindSafe for InterestCallbackCell {}
+
+impl InterestCallbackCell {
+    const_fn! {
+        /// Construct with no callbacks.
+        ///
+        /// This is a `const fn`, so cells can be placed in statics.
+        pub fn new() -> Self {
+            InterestCallbackCell {
+                read: AtomicPtr::new(ptr::null_mut()),
+                write: AtomicPtr::new(ptr::null_mut()),
+            }
+        }
+    }
+
+    // each slot, with its interest, readable first.
+    fn slots(&self) -> [(&AtomicPtr<u8>, Interest); 2] {
+        [(&self.read, Interest::READABLE), (&self.write, Interest::WRITABLE)]
+    }
+
+    fn put_slot<F: FnOnce() + Send + 'static>(slot: &AtomicPtr<u8>, f: F) {
+        let old = slot.swap(raw::alloc_raw(move |()| f()), Ordering::AcqRel);
+        unsafe { raw::drop_raw::<(), ()>(old) };
+    }
+
+    /// Atomically set the callback for when the source becomes readable.
+    ///
+    /// Makes only one heap allocation. Any readable callback previously
+    /// present is dropped. The writable callback is left alone.
+    pub fn put_read<F: FnOnce() + Send + 'static>(&self, f: F) {
+        Self::put_slot(&self.read, f);
+    }
+
+    /// Atomically set the callback for when the source becomes writable.
+    ///
+    /// Makes only one heap allocation. Any writable callback previously
+    /// present is dropped. The readable callback is left alone.
+    pub fn put_write<F: FnOnce() + Send + 'static>(&self, f: F) {
+        Self::put_slot(&self.write, f);
+    }
+
+    /// Atomically take the callback for each of the given interests, then
+    /// run it.
+    ///
+    /// Returns the interests whose callbacks ran. Takes and runs the
+    /// readable callback, then the writable one, so if the readable one
+    /// panics, the writable one is left in place.
+    pub fn fire(&self, interest: Interest) -> Interest {
+        let mut ran = Interest::NONE;
+        for (slot, bit) in self.slots() {
+            if !interest.contains(bit) {
+                continue;
+            }
+            let ptr = slot.swap(ptr::null_mut(), Ordering::Acquire);
+            if !ptr.is_null() {
+                unsafe { raw::Owned::<(), ()>::from_erased(ptr) }.call(());
+                ran |= bit;
+            }
+        }
+        ran
+    }
+
+    /// Atomically take the callback for each of the given interests and drop
+    /// it without running it.
+    ///
+    /// Returns the interests whose callbacks were present.
+    pub fn clear(&self, interest: Interest) -> Interest {
+        let mut cleared = Interest::NONE;
+        for (slot, bit) in self.slots() {
+            if !interest.contains(bit) {
+                continue;
+            }
+            let old = slot.swap(ptr::null_mut(), Ordering::Acquire);
+            if !old.is_null() {
+                unsafe { raw::drop_raw::<(), ()>(old) };
+                cleared |= bit;
+            }
+        }
+        cleared
+    }
+
+    /// The interests with a callback currently present.
+    ///
+    /// This is only a snapshot: another thread may put or take a callback
+    /// immediately afterwards.
+    pub fn armed(&self) -> Interest {
+        let mut armed = Interest::NONE;
+        for (slot, bit) in self.slots() {
+            if !slot.load(Ordering::Acquire).is_null() {
+                armed |= bit;
+            }
+        }
+        armed
+    }
+}
+
+impl Default for InterestCallbackCell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for InterestCallbackCell {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "InterestCallbackCell({:?})", self.armed())
+    }
+}
+
+impl Drop for InterestCallbackCell {
+    fn drop(&mut self) {
+        unsafe {
+            raw::drop_raw::<(), ()>(self.read.load_mut());
+            raw::drop_raw::<(), ()>(self.write.load_mut());
+        }
+    }
+}
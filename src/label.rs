@@ -0,0 +1,32 @@
+
+// internals
+// ---------
+//
+// labels of callbacks put with `put_named` are kept in their heap allocations,
+// after the header, as described in the `raw` module. a labeled callback's
+// function pointer reads its label before moving it out, and runs it by `call`.
+//
+// while a labeled callback runs, a guard is held, which is forgotten once it
+// returns, as in the `abort` module. if the callback unwinds instead, the guard
+// is dropped during unwinding, and reports the label, with `std`.
+
+#[cfg(feature = "std")]
+struct ReportUnwind(&'static str);
+
+#[cfg(feature = "std")]
+impl Drop for ReportUnwind {
+    fn drop(&mut self) {
+        std::eprintln!("callback_cell: callback {:?} panicked", self.0);
+    }
+}
+
+// run the callback with the given label, by `f`.
+pub(crate) fn call<R>(label: &'static str, f: impl FnOnce() -> R) -> R {
+    crate::trace::call_labeled(label);
+    #[cfg(feature = "std")]
+    let guard = ReportUnwind(label);
+    let r = f();
+    #[cfg(feature = "std")]
+    core::mem::forget(guard);
+    r
+}
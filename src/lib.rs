@@ -1,12 +1,371 @@
 #![doc = include_str!("../README.md")]
+#![no_std]
+#![cfg_attr(feature = "unstable-async-fn", feature(async_fn_traits))]
+#![cfg_attr(feature = "unstable-fn-traits", feature(fn_traits, unboxed_closures))]
 
-#[cfg(test)]
-mod test;
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(any(test, feature = "std"))]
+extern crate std;
+
+#[macro_use]
+#[cfg_attr(not(feature = "alloc"), allow(unused_imports, unused_macros, dead_code))]
+mod sync;
+mod waker_cell;
+
+pub use self::waker_cell::{
+    WakerCell,
+    RegisterOutcome,
+};
 
+// outside `cfg_alloc!`, since a `macro_export` macro defined by a macro
+// expansion can't be referred to by its path.
+#[cfg(feature = "alloc")]
+mod define;
+
+// heap-based items are compiled out without the `alloc` feature.
+macro_rules! cfg_alloc {
+    ($($item:item)*) => {
+        $(
+            #[cfg(feature = "alloc")]
+            $item
+        )*
+    };
+}
+
+// the cells themselves don't allocate, only putting callbacks other than static
+// ones does, so they stay without `alloc`, with only the methods which never
+// allocate.
+mod raw;
+mod trace;
+#[cfg(all(feature = "std", target_has_atomic = "ptr"))]
+mod hooks;
+#[cfg(feature = "std")]
+mod abort;
 mod without_args;
 mod with_args;
+mod static_callback;
 
 pub use self::{
     without_args::CallbackCell,
-    with_args::CallbackCellArgs,
+    with_args::{
+        CallbackCellArgs,
+        RawCallbackPtr,
+    },
+    static_callback::StaticCallback,
+};
+
+// everything else is heap-based, so compiled out without the `alloc` feature.
+cfg_alloc! {
+
+#[cfg(all(test, feature = "std", not(loom), not(feature = "disabled")))]
+mod test;
+#[cfg(all(test, feature = "std", loom, not(feature = "disabled")))]
+mod loom_test;
+
+mod raw_callback;
+mod label;
+#[cfg(feature = "watchdog")]
+mod watchdog;
+mod with_input;
+mod with_output;
+#[cfg(target_has_atomic = "ptr")]
+mod shared_callback;
+mod prealloc;
+mod blocking;
+mod waitable;
+mod waiters;
+mod async_raw;
+mod async_without_args;
+mod async_with_args;
+mod future_cell;
+mod option_box;
+mod graveyard;
+#[cfg(feature = "std")]
+mod thread_bound;
+#[cfg(all(feature = "std", not(loom)))]
+mod drain_thread;
+#[cfg(all(feature = "std", not(loom)))]
+mod sharded;
+#[cfg(all(feature = "std", not(loom)))]
+mod quiescent;
+#[cfg(all(feature = "std", not(loom)))]
+mod blocking_queue;
+#[cfg(target_has_atomic = "ptr")]
+mod receipt;
+#[cfg(target_has_atomic = "ptr")]
+mod channel;
+mod mailbox;
+mod spawn;
+mod slot;
+mod notify;
+mod local;
+pub mod auto;
+#[cfg(feature = "std")]
+pub mod scoped;
+mod auto_traits;
+mod drop_sink;
+mod result_cell;
+mod named;
+mod deferred;
+mod double_buffered;
+mod priority;
+mod event_cells;
+mod coalescing;
+mod sealed;
+mod fired;
+mod pausable;
+mod countdown;
+mod stateful;
+mod slot_map;
+mod shutdown;
+mod any_cell;
+mod interest;
+mod masked;
+mod cloneable;
+mod sticky;
+mod as_fn;
+#[cfg(target_has_atomic = "ptr")]
+mod multi;
+#[cfg(target_has_atomic = "ptr")]
+mod broadcast;
+#[cfg(target_has_atomic = "ptr")]
+mod defaulting;
+#[cfg(target_has_atomic = "ptr")]
+mod gated;
+#[cfg(all(target_has_atomic = "ptr", feature = "futures"))]
+mod sink;
+#[cfg(all(target_has_atomic = "ptr", feature = "futures"))]
+mod outputs;
+#[cfg(feature = "futures")]
+mod deferred_stream;
+#[cfg(all(feature = "stamped", not(loom)))]
+mod stamped;
+#[cfg(all(feature = "wide", not(loom)))]
+mod wide;
+#[cfg(all(feature = "crossbeam-epoch", not(loom)))]
+mod epoch;
+#[cfg(feature = "critical-section")]
+mod critical_section_cell;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+#[cfg(feature = "alloc-stats")]
+pub mod alloc_stats;
+
+pub use self::{
+    raw_callback::{
+        RawCallback,
+        RawCallbackArgs,
+    },
+    with_args::{
+        PutToken,
+        TakeGuard,
+    },
+    with_input::CallbackCellInput,
+    with_output::CallbackCellOutput,
+    prealloc::ArgsSlot,
+    waitable::{
+        WaitableCallbackCell,
+        WaitSet,
+        WaitTakeCall,
+        WaitEmpty,
+        PutWhenEmpty,
+        DidRun,
+    },
+    async_raw::BoxFuture,
+    future_cell::FutureCell,
+    option_box::AtomicOptionBox,
+    graveyard::Graveyard,
+    async_without_args::AsyncCallbackCell,
+    async_with_args::AsyncCallbackCellArgs,
+    mailbox::MailboxCallbackCell,
+    spawn::Spawn,
+    slot::CallbackSlot,
+    notify::{
+        Notifier,
+        NotifyCallbackCell,
+    },
+    local::{
+        LocalCallbackCell,
+        LocalCallbackCellArgs,
+        LocalCallbackCellFn,
+        LocalCallbackQueue,
+    },
+    drop_sink::{
+        DropSink,
+        DisplacedCallback,
+    },
+    result_cell::ResultCallbackCell,
+    named::{
+        NamedCallbackCell,
+        NamedCallbackCellArgs,
+    },
+    deferred::DeferredCallQueue,
+    double_buffered::DoubleBufferedCallbackQueue,
+    priority::PriorityCallbackQueue,
+    event_cells::EventCells,
+    coalescing::CoalescingCell,
+    sealed::{
+        Sealed,
+        SealableCallbackCellArgs,
+    },
+    fired::{
+        Fired,
+        FireOnceCallbackCellArgs,
+    },
+    pausable::{
+        NotRun,
+        PausableCallbackCellArgs,
+    },
+    countdown::{
+        CountdownCallbackCell,
+        SignalOutcome,
+    },
+    stateful::{
+        StatefulCallbackCell,
+        StatefulCallbackQueue,
+        Full,
+    },
+    slot_map::{
+        CallbackSlotMap,
+        CallbackKey,
+        BatchOutcome,
+    },
+    shutdown::{
+        ShutdownHooks,
+        HookHandle,
+    },
+    any_cell::AnyCallbackCell,
+    interest::{
+        InterestCallbackCell,
+        Interest,
+    },
+    masked::{
+        MaskedCallbackCell,
+        FireOutcome,
+    },
+    cloneable::CloneableCallbackCellArgs,
+    sticky::StickyEventCell,
+};
+#[cfg(target_has_atomic = "ptr")]
+pub use self::{
+    receipt::{
+        PutReceipt,
+        Discarded,
+    },
+    channel::{
+        channel,
+        channel_args,
+        Setter,
+        Invoker,
+        SetterArgs,
+        InvokerArgs,
+        Disconnected,
+        TakeCallError,
+    },
+    multi::{
+        MultiCallbackCellArgs,
+        MultiWaitSet,
+    },
+    broadcast::{
+        ListenerRegistry,
+        ListenerId,
+        DispatchReport,
+    },
+    defaulting::DefaultingCallbackCellArgs,
+    gated::{
+        Gate,
+        GateNotRun,
+        GatedCallbackCell,
+        GatedCallbackCellArgs,
+    },
+    shared_callback::SharedCallback,
 };
+#[cfg(all(target_has_atomic = "ptr", feature = "futures"))]
+pub use self::{
+    sink::{
+        CallbackSink,
+        NoHandler,
+    },
+    outputs::{
+        CallbackOutputs,
+        DEFAULT_OUTPUTS_CAPACITY,
+    },
+};
+#[cfg(feature = "futures")]
+pub use self::deferred_stream::{
+    DeferredCallStream,
+    QueuedCall,
+};
+#[cfg(feature = "std")]
+pub use self::{
+    thread_bound::ThreadBoundCallbackCell,
+    deferred::DrainStats,
+};
+#[cfg(all(feature = "std", not(loom)))]
+pub use self::drain_thread::{
+    DrainThread,
+    DrainHandle,
+    DrainReport,
+    ShutdownPolicy,
+};
+#[cfg(all(feature = "std", not(loom)))]
+pub use self::sharded::{
+    ShardedCallbackBuffer,
+    ShardedCallbackCell,
+};
+#[cfg(all(feature = "std", not(loom)))]
+pub use self::quiescent::QuiescentCallbackCellArgs;
+#[cfg(all(feature = "std", not(loom)))]
+pub use self::blocking_queue::{
+    BlockingCallbackQueue,
+    callback_queue,
+    CallbackQueueSender,
+    CallbackQueueReceiver,
+    IterBlocking,
+};
+#[cfg(all(feature = "std", target_has_atomic = "ptr"))]
+pub use self::hooks::{
+    set_hooks,
+    take_hooks,
+    Hooks,
+    HookInfo,
+};
+#[cfg(feature = "watchdog")]
+pub use self::watchdog::{
+    set_watchdog,
+    take_watchdog,
+    Watchdog,
+    SlowCallback,
+};
+#[cfg(all(feature = "stamped", not(loom)))]
+pub use self::stamped::{
+    StampedCallbackCellArgs,
+    CancelHandle,
+};
+#[cfg(all(feature = "wide", not(loom)))]
+pub use self::wide::WideCallbackCellArgs;
+#[cfg(all(feature = "crossbeam-epoch", not(loom)))]
+pub use self::epoch::{
+    EpochCallbackCellFn,
+    EpochWaitSet,
+};
+#[cfg(feature = "critical-section")]
+pub use self::critical_section_cell::CriticalSectionCallbackCell;
+#[cfg(feature = "unstable-fn-traits")]
+pub use self::as_fn::CellFn;
+#[cfg(all(target_has_atomic = "ptr", feature = "unstable-fn-traits"))]
+pub use self::as_fn::MultiCellFn;
+
+// for use by the crate's macros.
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::define::{
+        ApplyOnce,
+        Apply,
+    };
+}
+
+}
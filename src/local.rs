@@ -0,0 +1,408 @@
+
+use crate::raw;
+use core::{
+    cell::{Cell, RefCell},
+    ptr,
+    marker::PhantomData,
+    fmt::{self, Formatter, Debug},
+};
+use alloc::{
+    rc::Rc,
+    collections::VecDeque,
+};
+
+// internals
+// ---------
+//
+// the inner cell is a nullable pointer to an erased callback, as described in
+// the `raw` module. since the cells aren't Send or Sync, neither are the
+// callbacks required to be.
+//
+// `LocalCallbackCellFn` instead holds an `Rc` of the handler, which each call
+// clones before running it, so that the handler stays alive while it runs even
+// if it puts a new handler into its cell or clears it.
+//
+// `LocalCallbackQueue` is a `VecDeque` of erased callbacks. the `RefCell` is
+// only borrowed to push or pop one, never while a callback runs, so callbacks
+// may push to or run the queue.
+
+/// Like a [`CallbackCell`][crate::CallbackCell], but for a single thread.
+///
+/// The callback need not be `Send`, so it can capture `Rc`s and other
+/// thread-bound handles. In exchange, the cell is neither `Send` nor `Sync`.
+/// No atomic operations are used.
+pub struct LocalCallbackCell(Cell<*mut u8>);
+
+impl LocalCallbackCell {
+    /// Construct with no callback.
+    pub const fn new() -> Self {
+        LocalCallbackCell(Cell::new(ptr::null_mut()))
+    }
+
+    /// Set the callback.
+    ///
+    /// Makes only one heap allocation. Any callback previously present is dropped.
+    pub fn put<F: FnOnce() + 'static>(&self, f: F) {
+        unsafe {
+            let old_ptr = self.0.replace(raw::alloc_raw(move |()| f()));
+            raw::drop_raw::<(), ()>(old_ptr);
+        }
+    }
+
+    /// Take the callback then run it.
+    ///
+    /// Returns true if a callback was present. The callback may put a new
+    /// callback into the cell.
+    pub fn take_call(&self) -> bool {
+        unsafe {
+            let ptr = self.0.replace(ptr::null_mut());
+            if !ptr.is_null() {
+                raw::call_raw::<(), ()>(ptr, ());
+                true
+            } else {
+                false
+            }
+        }
+    }
+
+    /// Whether a callback is currently present.
+    pub fn is_set(&self) -> bool {
+        !self.0.get().is_null()
+    }
+
+    /// Take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        unsafe {
+            let ptr = self.0.replace(ptr::null_mut());
+            raw::drop_raw::<(), ()>(ptr);
+            !ptr.is_null()
+        }
+    }
+}
+
+impl Drop for LocalCallbackCell {
+    fn drop(&mut self) {
+        unsafe {
+            raw::drop_raw::<(), ()>(self.0.get());
+        }
+    }
+}
+
+impl Default for LocalCallbackCell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for LocalCallbackCell {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_set() {
+            f.write_str("LocalCallbackCell(NOT NULL)")
+        } else {
+            f.write_str("LocalCallbackCell(NULL)")
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+#[allow(clippy::if_same_then_else)]
+impl defmt::Format for LocalCallbackCell {
+    fn format(&self, f: defmt::Formatter) {
+        if self.is_set() {
+            defmt::write!(f, "LocalCallbackCell(NOT NULL)")
+        } else {
+            defmt::write!(f, "LocalCallbackCell(NULL)")
+        }
+    }
+}
+
+/// Like a [`CallbackCellArgs`][crate::CallbackCellArgs], but for a single
+/// thread.
+///
+/// See [`LocalCallbackCell`].
+pub struct LocalCallbackCellArgs<I, O> {
+    ptr: Cell<*mut u8>,
+    // invariant in `I` and `O`, as with `CallbackCellArgs`.
+    _p: PhantomData<dyn FnOnce(I) -> O + 'static>,
+}
+
+impl<I, O> LocalCallbackCellArgs<I, O> {
+    /// Construct with no callback.
+    pub const fn new() -> Self {
+        LocalCallbackCellArgs {
+            ptr: Cell::new(ptr::null_mut()),
+            _p: PhantomData,
+        }
+    }
+
+    /// Set the callback.
+    ///
+    /// Makes only one heap allocation. Any callback previously present is dropped.
+    pub fn put<F: FnOnce(I) -> O + 'static>(&self, f: F) {
+        unsafe {
+            let old_ptr = self.ptr.replace(raw::alloc_raw(f));
+            raw::drop_raw::<I, O>(old_ptr);
+        }
+    }
+
+    /// Take the callback then run it with the given input.
+    ///
+    /// Returns the output if a callback was present. If a callback was not
+    /// present, returns the original input.
+    pub fn take_call(&self, input: I) -> Result<O, I> {
+        unsafe {
+            let ptr = self.ptr.replace(ptr::null_mut());
+            if !ptr.is_null() {
+                Ok(raw::call_raw(ptr, input))
+            } else {
+                Err(input)
+            }
+        }
+    }
+
+    /// Whether a callback is currently present.
+    pub fn is_set(&self) -> bool {
+        !self.ptr.get().is_null()
+    }
+
+    /// Take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        unsafe {
+            let ptr = self.ptr.replace(ptr::null_mut());
+            raw::drop_raw::<I, O>(ptr);
+            !ptr.is_null()
+        }
+    }
+}
+
+impl<I, O> Drop for LocalCallbackCellArgs<I, O> {
+    fn drop(&mut self) {
+        unsafe {
+            raw::drop_raw::<I, O>(self.ptr.get());
+        }
+    }
+}
+
+impl<I, O> Default for LocalCallbackCellArgs<I, O> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I, O> Debug for LocalCallbackCellArgs<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_set() {
+            f.write_str("LocalCallbackCellArgs(NOT NULL)")
+        } else {
+            f.write_str("LocalCallbackCellArgs(NULL)")
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+#[allow(clippy::if_same_then_else)]
+impl<I, O> defmt::Format for LocalCallbackCellArgs<I, O> {
+    fn format(&self, f: defmt::Formatter) {
+        if self.is_set() {
+            defmt::write!(f, "LocalCallbackCellArgs(NOT NULL)")
+        } else {
+            defmt::write!(f, "LocalCallbackCellArgs(NULL)")
+        }
+    }
+}
+
+type LocalHandler<I, O> = Rc<dyn Fn(I) -> O + 'static>;
+
+/// Like a [`MultiCallbackCellArgs`][crate::MultiCallbackCellArgs], but for a
+/// single thread.
+///
+/// Holds a handler which is called any number of times, rather than taken.
+/// The handler need not be `Send` or `Sync`, so it can capture `Rc`s, such
+/// as an `Rc<RefCell<_>>` of state shared with the rest of an event loop. In
+/// exchange, the cell is neither `Send` nor `Sync`.
+///
+/// The handler may [`put`][Self::put] a new handler into its own cell, or
+/// [`clear`][Self::clear] it, while it's being called: it keeps running, and
+/// is dropped once it returns. Later calls, including ones it makes itself,
+/// find the new handler, or none.
+pub struct LocalCallbackCellFn<I, O>(Cell<Option<LocalHandler<I, O>>>);
+
+impl<I, O> LocalCallbackCellFn<I, O> {
+    /// Construct with no handler.
+    pub const fn new() -> Self {
+        LocalCallbackCellFn(Cell::new(None))
+    }
+
+    /// Set the handler.
+    ///
+    /// Makes only one heap allocation. Any handler previously present is
+    /// dropped, or if it's being called, dropped once that call returns.
+    pub fn put<F: Fn(I) -> O + 'static>(&self, f: F) {
+        drop(self.0.replace(Some(Rc::new(f))));
+    }
+
+    /// Call the handler with the given input, leaving it in place.
+    ///
+    /// Returns the output if a handler was present. If a handler was not
+    /// present, returns the original input.
+    pub fn call(&self, input: I) -> Result<O, I> {
+        match self.handler() {
+            Some(handler) => Ok(handler(input)),
+            None => Err(input),
+        }
+    }
+
+    // a clone of the handler, if there is one.
+    fn handler(&self) -> Option<LocalHandler<I, O>> {
+        let handler = self.0.take();
+        self.0.set(handler.clone());
+        handler
+    }
+
+    /// Whether a handler is currently present.
+    pub fn is_set(&self) -> bool {
+        self.handler().is_some()
+    }
+
+    /// Remove the handler.
+    ///
+    /// Returns true if a handler was present. The handler is dropped as for
+    /// [`put`][Self::put].
+    pub fn clear(&self) -> bool {
+        self.0.take().is_some()
+    }
+}
+
+impl<I, O> Default for LocalCallbackCellFn<I, O> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I, O> Debug for LocalCallbackCellFn<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_set() {
+            f.write_str("LocalCallbackCellFn(NOT NULL)")
+        } else {
+            f.write_str("LocalCallbackCellFn(NULL)")
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+#[allow(clippy::if_same_then_else)]
+impl<I, O> defmt::Format for LocalCallbackCellFn<I, O> {
+    fn format(&self, f: defmt::Formatter) {
+        if self.is_set() {
+            defmt::write!(f, "LocalCallbackCellFn(NOT NULL)")
+        } else {
+            defmt::write!(f, "LocalCallbackCellFn(NULL)")
+        }
+    }
+}
+
+/// Like a [`DeferredCallQueue<(), ()>`][crate::DeferredCallQueue], but for a
+/// single thread.
+///
+/// Callbacks are run in the order they were pushed. They need not be `Send`.
+/// In exchange, the queue is neither `Send` nor `Sync`. No atomic operations
+/// are used.
+///
+/// Callbacks may push more callbacks onto the queue while it's being run.
+/// Those are left for the next run, so a callback which pushes itself again
+/// runs once per run, rather than forever.
+pub struct LocalCallbackQueue(RefCell<VecDeque<raw::Owned<(), ()>>>);
+
+impl LocalCallbackQueue {
+    /// Construct with no callbacks.
+    pub const fn new() -> Self {
+        LocalCallbackQueue(RefCell::new(VecDeque::new()))
+    }
+
+    /// Push a callback onto the end of the queue.
+    ///
+    /// Makes only one heap allocation, other than when the queue grows.
+    pub fn push<F: FnOnce() + 'static>(&self, f: F) {
+        let callback = unsafe { raw::Owned::from_erased(raw::alloc_raw(move |()| f())) };
+        self.0.borrow_mut().push_back(callback);
+    }
+
+    /// Run every callback queued, in the order they were pushed.
+    ///
+    /// Returns the number of callbacks run. Callbacks pushed while this runs
+    /// are left for the next run. If a callback panics, the callbacks after
+    /// it stay queued.
+    pub fn run_all(&self) -> usize {
+        self.run_batch(usize::MAX)
+    }
+
+    /// Like [`run_all`][Self::run_all], but runs at most `n` callbacks,
+    /// leaving the rest queued, in order.
+    pub fn run_batch(&self, n: usize) -> usize {
+        let n = n.min(self.len());
+        let mut count = 0;
+        while count < n {
+            // the borrow ends before the callback runs
+            let Some(callback) = self.0.borrow_mut().pop_front() else {
+                // the queue was run from a callback
+                break;
+            };
+            callback.call(());
+            count += 1;
+        }
+        count
+    }
+
+    /// The number of callbacks queued.
+    pub fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
+
+    /// Whether no callbacks are queued.
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().is_empty()
+    }
+
+    /// Drop every callback queued without running it.
+    ///
+    /// Returns the number of callbacks dropped. Callbacks pushed while they're
+    /// dropped, by their destructors, stay queued.
+    pub fn clear(&self) -> usize {
+        let callbacks = self.0.take();
+        let count = callbacks.len();
+        drop(callbacks);
+        count
+    }
+}
+
+impl Default for LocalCallbackQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for LocalCallbackQueue {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_empty() {
+            f.write_str("LocalCallbackQueue(EMPTY)")
+        } else {
+            f.write_str("LocalCallbackQueue(NOT EMPTY)")
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+#[allow(clippy::if_same_then_else)]
+impl defmt::Format for LocalCallbackQueue {
+    fn format(&self, f: defmt::Formatter) {
+        if self.is_empty() {
+            defmt::write!(f, "LocalCallbackQueue(EMPTY)")
+        } else {
+            defmt::write!(f, "LocalCallbackQueue(NOT EMPTY)")
+        }
+    }
+}
@@ -0,0 +1,770 @@
+// model-checked tests of the cells' atomic protocols, run with:
+//
+//     RUSTFLAGS="--cfg loom" cargo test --lib --release
+
+use crate::*;
+use loom::{
+    sync::Arc,
+    thread,
+};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+// counts of how many times each tracked callback ran and was dropped.
+#[derive(Default)]
+struct Counts {
+    ran: [AtomicU32; 2],
+    dropped: [AtomicU32; 2],
+}
+
+impl Counts {
+    // assert every callback was dropped exactly once, and that `ran`
+    // callbacks in total ran, each at most once.
+    fn check(&self, ran: u32) {
+        for i in 0..2 {
+            assert!(self.ran[i].load(Ordering::SeqCst) <= 1);
+            assert_eq!(self.dropped[i].load(Ordering::SeqCst), 1);
+        }
+        assert_eq!(self.ran.iter().map(|r| r.load(Ordering::SeqCst)).sum::<u32>(), ran);
+    }
+}
+
+// tracks when the callback which owns it runs and is dropped.
+struct Tracker(Arc<Counts>, usize);
+
+impl Tracker {
+    fn run(&self) {
+        self.0.ran[self.1].fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+impl Drop for Tracker {
+    fn drop(&mut self) {
+        self.0.dropped[self.1].fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+fn callback(counts: &Arc<Counts>, i: usize) -> impl FnOnce() + Send + 'static {
+    let tracker = Tracker(Arc::clone(counts), i);
+    move || tracker.run()
+}
+
+fn callback_args(counts: &Arc<Counts>, i: usize) -> impl FnOnce(u32) -> u32 + Send + 'static {
+    let tracker = Tracker(Arc::clone(counts), i);
+    move |n| {
+        tracker.run();
+        n + 1
+    }
+}
+
+#[test]
+fn put_put() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(CallbackCell::new());
+        let thread = thread::spawn({
+            let (counts, cell) = (Arc::clone(&counts), Arc::clone(&cell));
+            move || cell.put(callback(&counts, 0))
+        });
+        cell.put(callback(&counts, 1));
+        thread.join().unwrap();
+        assert!(cell.take_call());
+        drop(cell);
+        counts.check(1);
+    });
+}
+
+#[test]
+fn put_take_call() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(CallbackCell::new());
+        cell.put(callback(&counts, 0));
+        let thread = thread::spawn({
+            let cell = Arc::clone(&cell);
+            move || cell.take_call()
+        });
+        cell.put(callback(&counts, 1));
+        let took = thread.join().unwrap();
+        drop(cell);
+        counts.check(took as u32);
+    });
+}
+
+#[test]
+fn put_with_cleanup_take_call() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(CallbackCell::new());
+        let discarded = Arc::new(Counts::default());
+        cell.put_with_cleanup(callback(&counts, 0), callback(&discarded, 0));
+        let thread = thread::spawn({
+            let cell = Arc::clone(&cell);
+            move || cell.take_call()
+        });
+        cell.put(callback(&counts, 1));
+        let took = thread.join().unwrap();
+        drop(cell);
+        counts.check(took as u32);
+        // the cleanup ran if, and only if, the callback didn't
+        assert_eq!(discarded.ran[0].load(Ordering::SeqCst), counts.ran[0].load(Ordering::SeqCst) ^ 1);
+    });
+}
+
+#[test]
+fn put_take_call_fast() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(CallbackCell::new());
+        cell.put(callback(&counts, 0));
+        let thread = thread::spawn({
+            let cell = Arc::clone(&cell);
+            move || cell.take_call_fast()
+        });
+        cell.put(callback(&counts, 1));
+        let took = thread.join().unwrap();
+        drop(cell);
+        counts.check(took as u32);
+    });
+}
+
+#[test]
+fn take_call_take_call() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(CallbackCell::new());
+        cell.put(callback(&counts, 0));
+        let thread = thread::spawn({
+            let cell = Arc::clone(&cell);
+            move || cell.take_call()
+        });
+        let took_1 = cell.take_call();
+        let took_2 = thread.join().unwrap();
+        assert!(took_1 != took_2);
+        cell.put(callback(&counts, 1));
+        drop(cell);
+        counts.check(1);
+    });
+}
+
+#[test]
+fn put_drop() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(CallbackCell::new());
+        cell.put(callback(&counts, 0));
+        let thread = thread::spawn({
+            let (counts, cell) = (Arc::clone(&counts), Arc::clone(&cell));
+            move || cell.put(callback(&counts, 1))
+        });
+        drop(cell);
+        thread.join().unwrap();
+        counts.check(0);
+    });
+}
+
+#[test]
+fn args_put_put() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(CallbackCellArgs::new());
+        let thread = thread::spawn({
+            let (counts, cell) = (Arc::clone(&counts), Arc::clone(&cell));
+            move || cell.put(callback_args(&counts, 0))
+        });
+        cell.put(callback_args(&counts, 1));
+        thread.join().unwrap();
+        assert_eq!(cell.take_call(1), Ok(2));
+        drop(cell);
+        counts.check(1);
+    });
+}
+
+#[test]
+fn args_put_take_call() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(CallbackCellArgs::new());
+        cell.put(callback_args(&counts, 0));
+        let thread = thread::spawn({
+            let cell = Arc::clone(&cell);
+            move || cell.take_call(1)
+        });
+        cell.put(callback_args(&counts, 1));
+        let result = thread.join().unwrap();
+        assert!(result == Ok(2) || result == Err(1));
+        drop(cell);
+        counts.check(result.is_ok() as u32);
+    });
+}
+
+#[test]
+fn args_take_call_take_call() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(CallbackCellArgs::new());
+        cell.put(callback_args(&counts, 0));
+        let thread = thread::spawn({
+            let cell = Arc::clone(&cell);
+            move || cell.take_call(1)
+        });
+        let result_1 = cell.take_call(1);
+        let result_2 = thread.join().unwrap();
+        assert!(result_1.is_ok() != result_2.is_ok());
+        cell.put(callback_args(&counts, 1));
+        drop(cell);
+        counts.check(1);
+    });
+}
+
+#[test]
+fn args_put_drop() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(CallbackCellArgs::new());
+        cell.put(callback_args(&counts, 0));
+        let thread = thread::spawn({
+            let (counts, cell) = (Arc::clone(&counts), Arc::clone(&cell));
+            move || cell.put(callback_args(&counts, 1))
+        });
+        drop(cell);
+        thread.join().unwrap();
+        counts.check(0);
+    });
+}
+
+#[test]
+fn args_put_around_put() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(CallbackCellArgs::new());
+        cell.put(callback_args(&counts, 0));
+        let thread = thread::spawn({
+            let (counts, cell) = (Arc::clone(&counts), Arc::clone(&cell));
+            move || cell.put(callback_args(&counts, 1))
+        });
+        assert!(cell.put_around(|f, n| f(n) * 10));
+        thread.join().unwrap();
+        let output = cell.take_call(1).unwrap();
+        assert!(output == 2 || output == 20);
+        drop(cell);
+        counts.check(1);
+    });
+}
+
+#[test]
+fn args_replace_if_current_take_call() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(CallbackCellArgs::new());
+        let token = cell.put_tracked(callback_args(&counts, 0));
+        let thread = thread::spawn({
+            let cell = Arc::clone(&cell);
+            move || cell.take_call(1).is_ok()
+        });
+        // if replaced, the take must come after, and takes the new callback
+        let _ = cell.replace_if_current(&token, callback_args(&counts, 1));
+        assert!(thread.join().unwrap());
+        assert!(!cell.is_set());
+        drop(cell);
+        counts.check(1);
+    });
+}
+
+#[test]
+fn channel_final_put() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let (setter, invoker) = channel();
+        counts.ran[1].store(1, Ordering::SeqCst);
+        counts.dropped[1].store(1, Ordering::SeqCst);
+        let thread = thread::spawn({
+            let counts = Arc::clone(&counts);
+            move || setter.put(callback(&counts, 0)).unwrap()
+        });
+        loop {
+            match invoker.take_call() {
+                Ok(()) => break,
+                Err(TakeCallError::Empty(())) => thread::yield_now(),
+                Err(TakeCallError::Disconnected(())) => panic!("final put was lost"),
+            }
+        }
+        thread.join().unwrap();
+        counts.check(2);
+    });
+}
+
+#[test]
+fn channel_put_invoker_drop() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let (setter, invoker) = channel();
+        counts.dropped[1].store(1, Ordering::SeqCst);
+        let thread = thread::spawn({
+            let counts = Arc::clone(&counts);
+            move || {
+                let _ = setter.put(callback(&counts, 0));
+                setter
+            }
+        });
+        drop(invoker);
+        let setter = thread.join().unwrap();
+        // the callback is dropped by now, whether or not the put reported
+        // disconnection
+        assert_eq!(counts.dropped[0].load(Ordering::SeqCst), 1);
+        drop(setter);
+        counts.check(0);
+    });
+}
+
+#[test]
+fn deferred_push_push_run() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let queue = Arc::new(DeferredCallQueue::new());
+        let thread = thread::spawn({
+            let (counts, queue) = (Arc::clone(&counts), Arc::clone(&queue));
+            move || queue.push(callback_args(&counts, 0), 0).unwrap()
+        });
+        queue.push(callback_args(&counts, 1), 1).unwrap();
+        let ran = queue.run_all();
+        thread.join().unwrap();
+        drop(queue);
+        counts.check(ran as u32);
+    });
+}
+
+#[test]
+fn deferred_push_retain() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let queue = Arc::new(DeferredCallQueue::new());
+        queue.push_tagged(callback_args(&counts, 0), 0, 0).unwrap();
+        let thread = thread::spawn({
+            let (counts, queue) = (Arc::clone(&counts), Arc::clone(&queue));
+            move || queue.push_tagged(callback_args(&counts, 1), 1, 1).unwrap()
+        });
+        let removed = queue.retain(|tag| tag == 1);
+        thread.join().unwrap();
+        let ran = queue.run_all();
+        assert_eq!(removed + ran, 2);
+        drop(queue);
+        counts.check(ran as u32);
+    });
+}
+
+#[test]
+fn sealable_put_seal() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(SealableCallbackCellArgs::new());
+        cell.put(callback_args(&counts, 0)).unwrap();
+        let thread = thread::spawn({
+            let (counts, cell) = (Arc::clone(&counts), Arc::clone(&cell));
+            move || cell.put(callback_args(&counts, 1)).is_ok()
+        });
+        cell.seal();
+        let ran = cell.take_call(0).is_ok();
+        let accepted = thread.join().unwrap();
+        let ran = ran as u32 + cell.take_call(0).is_ok() as u32;
+        assert!(ran >= 1 && ran <= 1 + accepted as u32);
+        assert!(cell.put(|n| n).is_err());
+        drop(cell);
+        counts.check(ran);
+    });
+}
+
+#[test]
+fn deferred_push_seal() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let queue = Arc::new(DeferredCallQueue::new());
+        queue.push(callback_args(&counts, 0), 0).unwrap();
+        let thread = thread::spawn({
+            let (counts, queue) = (Arc::clone(&counts), Arc::clone(&queue));
+            move || queue.push(callback_args(&counts, 1), 1).is_ok()
+        });
+        queue.seal();
+        let ran = queue.run_all();
+        let accepted = thread.join().unwrap();
+        let ran = ran + queue.run_all();
+        assert_eq!(ran, 1 + accepted as usize);
+        drop(queue);
+        counts.check(ran as u32);
+    });
+}
+
+#[test]
+fn fire_once_put_take_call() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(FireOnceCallbackCellArgs::new());
+        cell.put(callback_args(&counts, 0)).unwrap();
+        let thread = thread::spawn({
+            let (counts, cell) = (Arc::clone(&counts), Arc::clone(&cell));
+            move || cell.put(callback_args(&counts, 1)).is_ok()
+        });
+        assert!(cell.take_call(0).is_ok());
+        thread.join().unwrap();
+        assert!(cell.has_fired());
+        assert!(!cell.is_set());
+        drop(cell);
+        counts.check(1);
+    });
+}
+
+#[test]
+fn pausable_put_pause_take_call() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(PausableCallbackCellArgs::new());
+        cell.put(callback_args(&counts, 0));
+        let thread = thread::spawn({
+            let (counts, cell) = (Arc::clone(&counts), Arc::clone(&cell));
+            move || cell.put(callback_args(&counts, 1))
+        });
+        cell.pause();
+        assert!(cell.take_call(0).unwrap_err().is_paused());
+        thread.join().unwrap();
+        assert!(cell.is_paused());
+        assert!(cell.is_set());
+        cell.resume();
+        assert!(cell.take_call(0).is_ok());
+        drop(cell);
+        counts.check(1);
+    });
+}
+
+#[test]
+fn countdown_signal_put() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(CountdownCallbackCell::new());
+        let thread = thread::spawn({
+            let cell = Arc::clone(&cell);
+            move || cell.signal() == SignalOutcome::Fired
+        });
+        let put = cell.put_with_count(callback(&counts, 0), 2).unwrap();
+        let signalled = cell.signal() == SignalOutcome::Fired;
+        let fired = thread.join().unwrap();
+        assert_eq!(put as u32 + signalled as u32 + fired as u32, 1);
+        assert!(cell.has_fired());
+        assert!(cell.put_with_count(callback(&counts, 1), 0).is_err());
+        counts.check(1);
+    });
+}
+
+#[test]
+fn slot_map_take_remove_insert() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let map = Arc::new(CallbackSlotMap::new());
+        let k0 = map.insert(callback_args(&counts, 0));
+        let thread = thread::spawn({
+            let map = Arc::clone(&map);
+            move || map.take_call(k0, 1).is_ok()
+        });
+        let removed = map.remove(k0);
+        let k1 = map.insert(callback_args(&counts, 1));
+        let ran = thread.join().unwrap();
+        assert!(ran != removed);
+        assert_eq!(map.take_call(k0, 1), Err(1));
+        assert_eq!(map.take_call(k1, 1), Ok(2));
+        counts.check(ran as u32 + 1);
+    });
+}
+
+#[test]
+fn shutdown_hooks_register_run() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let hooks = Arc::new(ShutdownHooks::new());
+        hooks.register(callback(&counts, 0));
+        let thread = thread::spawn({
+            let hooks = Arc::clone(&hooks);
+            move || hooks.run()
+        });
+        hooks.register(callback(&counts, 1));
+        let ran = hooks.run() + thread.join().unwrap();
+        assert!(ran == 1 || ran == 2);
+        counts.check(2);
+    });
+}
+
+#[cfg(feature = "futures")]
+#[test]
+fn deferred_stream_poll_push() {
+    use futures_core::Stream;
+
+    struct Flag(loom::sync::atomic::AtomicBool);
+    impl std::task::Wake for Flag {
+        fn wake(self: std::sync::Arc<Self>) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let queue = Arc::new(DeferredCallQueue::new());
+        let thread = thread::spawn({
+            let queue = Arc::clone(&queue);
+            let f = callback_args(&counts, 0);
+            move || queue.push(f, 1).unwrap()
+        });
+        let flag = std::sync::Arc::new(Flag(loom::sync::atomic::AtomicBool::new(false)));
+        let waker = std::task::Waker::from(std::sync::Arc::clone(&flag));
+        let mut stream = queue.stream();
+        let poll = core::pin::Pin::new(&mut stream).poll_next(&mut std::task::Context::from_waker(&waker));
+        thread.join().unwrap();
+        // a push the poll missed woke it
+        let call = match poll {
+            std::task::Poll::Ready(call) => call.unwrap(),
+            std::task::Poll::Pending => {
+                assert!(flag.0.load(Ordering::SeqCst));
+                let poll = core::pin::Pin::new(&mut stream).poll_next(&mut std::task::Context::from_waker(&waker));
+                let std::task::Poll::Ready(Some(call)) = poll else { panic!() };
+                call
+            }
+        };
+        assert_eq!(call.call(), 2);
+        drop(stream);
+        drop(queue.push(callback_args(&counts, 1), 1));
+        drop(queue);
+        counts.check(1);
+    });
+}
+
+fn pending_future(counts: &Arc<Counts>, i: usize, ready_on: u32) -> BoxFuture<()> {
+    let tracker = Tracker(Arc::clone(counts), i);
+    std::boxed::Box::pin(std::future::poll_fn(move |_| {
+        tracker.run();
+        if tracker.0.ran[tracker.1].load(Ordering::SeqCst) == ready_on {
+            std::task::Poll::Ready(())
+        } else {
+            std::task::Poll::Pending
+        }
+    }))
+}
+
+#[test]
+fn future_poll_take_poll_take() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(FutureCell::new());
+        cell.put(pending_future(&counts, 0, 2));
+        let thread = thread::spawn({
+            let cell = Arc::clone(&cell);
+            move || cell.poll_take(&mut std::task::Context::from_waker(std::task::Waker::noop())).is_some()
+        });
+        let found = cell.poll_take(&mut std::task::Context::from_waker(std::task::Waker::noop())).is_some();
+        assert!(thread.join().unwrap() || found);
+        // polled twice, by both, or by one on the other's behalf
+        assert_eq!(counts.ran[0].load(Ordering::SeqCst), 2);
+        assert_eq!(counts.dropped[0].load(Ordering::SeqCst), 1);
+        assert!(!cell.is_set());
+    });
+}
+
+#[test]
+fn future_put_poll_take() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(FutureCell::new());
+        cell.put(pending_future(&counts, 0, 0));
+        let thread = thread::spawn({
+            let (counts, cell) = (Arc::clone(&counts), Arc::clone(&cell));
+            move || cell.put(pending_future(&counts, 1, 0))
+        });
+        cell.poll_take(&mut std::task::Context::from_waker(std::task::Waker::noop()));
+        thread.join().unwrap();
+        // the put future is never displaced by the polled one
+        assert_eq!(counts.dropped[0].load(Ordering::SeqCst), 1);
+        assert_eq!(counts.dropped[1].load(Ordering::SeqCst), 0);
+        assert!(cell.is_set());
+        drop(cell);
+        assert_eq!(counts.dropped[1].load(Ordering::SeqCst), 1);
+    });
+}
+
+#[test]
+fn option_box_put_take() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(AtomicOptionBox::new());
+        cell.put(Tracker(Arc::clone(&counts), 0));
+        let thread = thread::spawn({
+            let (counts, cell) = (Arc::clone(&counts), Arc::clone(&cell));
+            move || cell.put(Tracker(counts, 1))
+        });
+        let taken = cell.take();
+        thread.join().unwrap();
+        drop(taken);
+        drop(cell);
+        counts.check(0);
+    });
+}
+
+#[test]
+fn double_buffered_push_flip() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let queue = Arc::new(DoubleBufferedCallbackQueue::new());
+        let thread = thread::spawn({
+            let (counts, queue) = (Arc::clone(&counts), Arc::clone(&queue));
+            move || queue.push(callback(&counts, 0))
+        });
+        queue.flip();
+        let first = queue.drain_previous();
+        thread.join().unwrap();
+        queue.flip();
+        let second = queue.drain_previous();
+        // in exactly one of the two frames
+        assert_eq!(first + second, 1);
+        queue.push(callback(&counts, 1));
+        drop(queue);
+        counts.check(1);
+    });
+}
+
+#[test]
+fn coalescing_signal_signal() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(CoalescingCell::new());
+        cell.set_handler(|tracker: Tracker| tracker.run());
+        let thread = thread::spawn({
+            let (counts, cell) = (Arc::clone(&counts), Arc::clone(&cell));
+            move || cell.signal(Tracker(counts, 0))
+        });
+        let replaced = cell.signal(Tracker(Arc::clone(&counts), 1));
+        let other_replaced = thread.join().unwrap();
+        // exactly one survives
+        assert!(replaced != other_replaced);
+        assert!(cell.pump());
+        assert!(!cell.pump());
+        drop(cell);
+        counts.check(1);
+    });
+}
+
+#[test]
+fn coalescing_signal_pump() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(CoalescingCell::new());
+        cell.set_handler(|tracker: Tracker| tracker.run());
+        cell.signal(Tracker(Arc::clone(&counts), 0));
+        let thread = thread::spawn({
+            let (counts, cell) = (Arc::clone(&counts), Arc::clone(&cell));
+            move || cell.signal(Tracker(counts, 1))
+        });
+        let first = cell.pump();
+        let replaced = thread.join().unwrap();
+        let second = cell.pump();
+        // either both are delivered, or the first is replaced before the
+        // first pump
+        assert!(first);
+        assert_eq!(second, !replaced);
+        drop(cell);
+        counts.check(if replaced { 1 } else { 2 });
+    });
+}
+
+#[test]
+fn interest_fire_both_put_read() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(InterestCallbackCell::new());
+        cell.put_read(callback(&counts, 0));
+        cell.put_write(callback(&counts, 1));
+        let thread = thread::spawn({
+            let cell = Arc::clone(&cell);
+            move || cell.fire(Interest::BOTH)
+        });
+        let replacement = Arc::new(Counts::default());
+        cell.put_read(callback(&replacement, 0));
+        let ran = thread.join().unwrap();
+        // both slots were armed when the fire swapped them, so it ran both.
+        // if it beat the put to the read slot, the replacement is left armed,
+        // otherwise the replacement is what it ran.
+        assert_eq!(ran, Interest::BOTH);
+        let replaced = !cell.armed().is_readable();
+        drop(cell);
+        counts.check(2 - replaced as u32);
+        assert_eq!(replacement.ran[0].load(Ordering::SeqCst), replaced as u32);
+        assert_eq!(replacement.dropped[0].load(Ordering::SeqCst), 1);
+    });
+}
+
+#[test]
+fn masked_fire_put_masked() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(MaskedCallbackCell::new());
+        let f = callback(&counts, 0);
+        cell.put_masked(1, move |_| f());
+        let thread = thread::spawn({
+            let cell = Arc::clone(&cell);
+            move || cell.fire(1)
+        });
+        let f = callback(&counts, 1);
+        cell.put_masked(2, move |_| f());
+        let outcome = thread.join().unwrap();
+        // the fire either ran the first callback, or tested the second's mask,
+        // and left it armed. it's never consumed by a stale decision.
+        assert_ne!(outcome, FireOutcome::Empty);
+        assert_eq!(cell.mask(), Some(2));
+        drop(cell);
+        counts.check((outcome == FireOutcome::Ran) as u32);
+        assert_eq!(counts.ran[1].load(Ordering::SeqCst), 0);
+    });
+}
+
+#[test]
+fn cloneable_take_call_cloned_take_call() {
+    loom::model(|| {
+        let ran = Arc::new(AtomicU32::new(0));
+        let cell = Arc::new(CloneableCallbackCellArgs::new());
+        let f = {
+            let ran = Arc::clone(&ran);
+            move |n: u32| {
+                ran.fetch_add(1, Ordering::SeqCst);
+                n + 1
+            }
+        };
+        cell.put_cloneable(f);
+        let thread = thread::spawn({
+            let cell = Arc::clone(&cell);
+            move || cell.take_call_cloned(1)
+        });
+        // a clone in progress never makes the cell look empty
+        assert_eq!(cell.take_call(2), Ok(3));
+        let cloned = thread.join().unwrap();
+        assert!(!cell.is_set());
+        assert_eq!(ran.load(Ordering::SeqCst), 1 + cloned.is_ok() as u32);
+        drop(cell);
+        assert_eq!(Arc::strong_count(&ran), 1);
+    });
+}
+
+#[test]
+fn begin_take_abort_put() {
+    loom::model(|| {
+        let counts = Arc::new(Counts::default());
+        let cell = Arc::new(CallbackCellArgs::new());
+        cell.put(callback_args(&counts, 0));
+        let thread = thread::spawn({
+            let cell = Arc::clone(&cell);
+            let f = callback_args(&counts, 1);
+            move || cell.put(f)
+        });
+        let aborted = cell.begin_take().unwrap().abort();
+        thread.join().unwrap();
+        // the put either replaced the reinstated callback, or landed while it
+        // was taken, and the abort handed it back. either way, the put's stays.
+        drop(aborted);
+        assert_eq!(cell.take_call(1), Ok(2));
+        drop(cell);
+        counts.check(1);
+        assert_eq!(counts.ran[1].load(Ordering::SeqCst), 1);
+    });
+}
@@ -0,0 +1,121 @@
+
+use crate::{
+    sync::{AtomicPtr, LoadMut},
+    CallbackCellArgs,
+};
+use core::{
+    sync::atomic::Ordering,
+    ptr,
+    fmt::{self, Formatter, Debug},
+};
+use alloc::boxed::Box;
+
+// internals
+// ---------
+//
+// a callback cell, plus an atomic pointer which is null or points to a boxed
+// output. storing an output swaps it in and drops whichever output was there
+// before.
+
+/// A [`CallbackCellArgs`] which keeps the output of the last callback run, for
+/// the putting side to collect.
+///
+/// This gives poll-based request/response over a single cell: one side puts
+/// a callback and later polls [`try_take_output`][Self::try_take_output], the
+/// other side runs it with [`take_call_store`][Self::take_call_store]. Only
+/// the most recent output is kept; storing an output drops any uncollected
+/// older one.
+pub struct MailboxCallbackCell<I, O> {
+    cell: CallbackCellArgs<I, O>,
+    output: AtomicPtr<O>,
+}
+
+// safety: outputs are sent between threads through the mailbox, so require
+//         O: Send. the inner cell is Send and Sync regardless of I and O.
+unsafe impl<I, O: Send> Send for MailboxCallbackCell<I, O> {}
+unsafe impl<I, O: Send> Sync for MailboxCallbackCell<I, O> {}
+
+impl<I, O> MailboxCallbackCell<I, O> {
+    const_fn! {
+        /// Construct with no callback and no output.
+        ///
+        /// This is a `const fn`, so cells can be placed in statics.
+        pub fn new() -> Self {
+            MailboxCallbackCell {
+                cell: CallbackCellArgs::new(),
+                output: AtomicPtr::new(ptr::null_mut()),
+            }
+        }
+    }
+
+    /// Atomically set the callback.
+    ///
+    /// Any callback previously present is dropped. Does not affect the stored
+    /// output.
+    pub fn put<F: FnOnce(I) -> O + Send + 'static>(&self, f: F) {
+        self.cell.put(f);
+    }
+
+    /// Whether a callback is currently present.
+    ///
+    /// This is only a snapshot: another thread may put or take a callback
+    /// immediately afterwards.
+    pub fn is_set(&self) -> bool {
+        self.cell.is_set()
+    }
+
+    /// Atomically take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        self.cell.clear()
+    }
+
+    /// Atomically take the callback, run it with the given input, then store
+    /// its output in the mailbox.
+    ///
+    /// Any uncollected output previously stored is dropped. If a callback was
+    /// not present, returns the original input.
+    pub fn take_call_store(&self, input: I) -> Result<(), I> {
+        let output = self.cell.take_call(input)?;
+        let ptr = Box::into_raw(Box::new(output));
+        let old_ptr = self.output.swap(ptr, Ordering::AcqRel);
+        if !old_ptr.is_null() {
+            drop(unsafe { Box::from_raw(old_ptr) });
+        }
+        Ok(())
+    }
+
+    /// Atomically take the stored output, if any.
+    pub fn try_take_output(&self) -> Option<O> {
+        let ptr = self.output.swap(ptr::null_mut(), Ordering::Acquire);
+        if !ptr.is_null() {
+            Some(*unsafe { Box::from_raw(ptr) })
+        } else {
+            None
+        }
+    }
+}
+
+impl<I, O> Drop for MailboxCallbackCell<I, O> {
+    fn drop(&mut self) {
+        let ptr = self.output.load_mut();
+        if !ptr.is_null() {
+            drop(unsafe { Box::from_raw(ptr) });
+        }
+    }
+}
+
+impl<I, O> Default for MailboxCallbackCell<I, O> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I, O> Debug for MailboxCallbackCell<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let callback = if self.cell.is_set() { "NOT NULL" } else { "NULL" };
+        let output = if self.output.load(Ordering::Relaxed).is_null() { "NULL" } else { "NOT NULL" };
+        write!(f, "MailboxCallbackCell({}, output {})", callback, output)
+    }
+}
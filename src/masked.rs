@@ -0,0 +1,201 @@
+use crate::{
+    sync::{self, AtomicPtr, AtomicUsize, LoadMut},
+    raw,
+};
+use core::{
+    sync::atomic::Ordering,
+    ptr,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+
+// internals
+// ---------
+//
+// the callback is a masked callback, with its event mask in its header, as
+// described in the `raw` module, behind an atomic pointer, as in
+// `CallbackCellArgs`. firing loads the pointer, reads the mask through it, and
+// only if it matches, takes the callback by a compare-exchange from the loaded
+// pointer to null, so the decision and the take are one atomic step: a put
+// which replaces the callback concurrently either happens before the load, and
+// its mask is the one tested, or after, and the compare-exchange fails, and the
+// fire tries again with the new callback. a mismatch leaves the callback in
+// place without a window in which another thread sees the cell empty.
+//
+// another thread could take the callback while a fire reads its mask, so a
+// callback mustn't be freed while a fire might still read it. `readers` counts
+// the fires which have loaded, or are about to load, the pointer. whichever
+// put, clear, or fire takes a callback out of the cell waits for `readers` to
+// be 0 before running or dropping it. a fire counts itself before loading the
+// pointer, and all of these are sequentially consistent, so once the taker
+// sees 0, any fire yet to count itself loads the pointer after the take, and
+// can't see the taken callback. nor can its memory be reused by a later
+// callback while a fire holds the pointer, so the compare-exchange can't match
+// a different callback at the same address. a fire does nothing else while
+// counted, so the wait is brief, and the cell's lock-free, except for the
+// wait. the callback is never run or dropped while counted.
+
+/// Outcome of [`MaskedCallbackCell::fire`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FireOutcome {
+    /// The events matched the callback's mask, and it ran on this thread.
+    Ran,
+    /// The events didn't match the callback's mask, so it's still armed.
+    NoMatch,
+    /// No callback was present.
+    Empty,
+}
+
+/// A cell for a one-shot callback which only runs for the events it's
+/// interested in.
+///
+/// Like epoll's or kqueue's interest sets: [`put_masked`][Self::put_masked]
+/// registers a callback with a bitmask of events, and [`fire`][Self::fire]
+/// with some events runs it, with those events, only if they share a bit
+/// with its mask. Otherwise, the callback stays registered for a later
+/// fire. The mask test is a plain integer test, made without running or
+/// moving the callback.
+///
+/// The mask is kept with the callback, in its heap allocation, and tested
+/// and the callback taken without locking. Before a callback taken out of
+/// the cell, by replacing, clearing, or firing it, is run or dropped, this
+/// waits for any fires on other threads which may be reading its mask,
+/// which is brief. So no call on the cell may interrupt a fire on the same
+/// cell on the same thread, as from a signal handler, since that fire
+/// couldn't finish.
+pub struct MaskedCallbackCell {
+    ptr: AtomicPtr<u8>,
+    readers: AtomicUsize,
+}
+
+// safety: the pointer is to a callback, which is Send.
+unsafe impl Send for MaskedCallbackCell {}
+unsafe impl Sync for MaskedCallbackCell {}
+
+// a callback is taken out of the cell before it runs, so a panicking callback
+// leaves the cell empty, and no fire is counted in `readers` while it runs.
+impl UnwindSafe for MaskedCallbackCell {}
+impl RefUnwindSafe for MaskedCallbackCell {}
+
+impl MaskedCallbackCell {
+    const_fn! {
+        /// Construct with no callback.
+        ///
+        /// This is a `const fn`, so cells can be placed in statics.
+        pub fn new() -> Self {
+            MaskedCallbackCell {
+                ptr: AtomicPtr::new(ptr::null_mut()),
+                readers: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    // wait until no fire may be reading the mask of a callback taken out of
+    // the cell, before it's run or dropped.
+    fn wait_for_readers(&self) {
+        while self.readers.load(Ordering::SeqCst) != 0 {
+            sync::spin_loop();
+        }
+    }
+
+    // drop a callback taken out of the cell, if not null.
+    fn drop_taken(&self, ptr: *mut u8) {
+        if !ptr.is_null() {
+            self.wait_for_readers();
+            unsafe { raw::drop_raw::<u32, ()>(ptr) };
+        }
+    }
+
+    /// Atomically set the callback, to run when fired with any of the events
+    /// in `mask`.
+    ///
+    /// Makes only one heap allocation. Any callback previously present,
+    /// whatever its mask, is dropped. The callback is given the events it's
+    /// fired with.
+    pub fn put_masked<F: FnOnce(u32) + Send + 'static>(&self, mask: u32, f: F) {
+        let ptr = raw::alloc_raw_masked(f, mask);
+        let old_ptr = self.ptr.swap(ptr, Ordering::SeqCst);
+        self.drop_taken(old_ptr);
+    }
+
+    /// Atomically take the callback, if `events` shares a bit with its mask,
+    /// then run it with `events`.
+    ///
+    /// If the events don't match, returns [`FireOutcome::NoMatch`], leaving
+    /// the callback in place.
+    pub fn fire(&self, events: u32) -> FireOutcome {
+        self.readers.fetch_add(1, Ordering::SeqCst);
+        let taken = loop {
+            let ptr = self.ptr.load(Ordering::SeqCst);
+            if ptr.is_null() {
+                break Err(FireOutcome::Empty);
+            }
+            // safety: counted in `readers`, so the callback isn't freed.
+            if unsafe { raw::mask_raw::<u32, ()>(ptr) } & events == 0 {
+                break Err(FireOutcome::NoMatch);
+            }
+            if self.ptr.compare_exchange(ptr, ptr::null_mut(), Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                break Ok(ptr);
+            }
+        };
+        self.readers.fetch_sub(1, Ordering::SeqCst);
+        match taken {
+            Ok(ptr) => {
+                self.wait_for_readers();
+                unsafe { raw::call_raw::<u32, ()>(ptr, events) };
+                FireOutcome::Ran
+            }
+            Err(outcome) => outcome,
+        }
+    }
+
+    /// Atomically take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        let ptr = self.ptr.swap(ptr::null_mut(), Ordering::SeqCst);
+        self.drop_taken(ptr);
+        !ptr.is_null()
+    }
+
+    /// Whether a callback is currently present.
+    ///
+    /// This is only a snapshot: another thread may put or take a callback
+    /// immediately afterwards.
+    pub fn is_set(&self) -> bool {
+        !self.ptr.load(Ordering::Acquire).is_null()
+    }
+
+    /// The mask of the callback currently present, if any.
+    ///
+    /// This is only a snapshot, as for [`is_set`][Self::is_set].
+    pub fn mask(&self) -> Option<u32> {
+        self.readers.fetch_add(1, Ordering::SeqCst);
+        let ptr = self.ptr.load(Ordering::SeqCst);
+        // safety: counted in `readers`, so the callback isn't freed.
+        let mask = (!ptr.is_null()).then(|| unsafe { raw::mask_raw::<u32, ()>(ptr) });
+        self.readers.fetch_sub(1, Ordering::SeqCst);
+        mask
+    }
+}
+
+impl Drop for MaskedCallbackCell {
+    fn drop(&mut self) {
+        unsafe { raw::drop_raw::<u32, ()>(self.ptr.load_mut()) };
+    }
+}
+
+impl Default for MaskedCallbackCell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for MaskedCallbackCell {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.mask() {
+            Some(mask) => write!(f, "MaskedCallbackCell(NOT NULL: {:#x})", mask),
+            None => f.write_str("MaskedCallbackCell(NULL)"),
+        }
+    }
+}
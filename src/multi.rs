@@ -0,0 +1,364 @@
+
+use crate::{
+    sync::AtomicBool,
+    waiters::WaiterList,
+};
+use core::{
+    sync::atomic::Ordering,
+    cell::UnsafeCell,
+    hint,
+    mem,
+    panic::{UnwindSafe, RefUnwindSafe},
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    fmt::{self, Formatter, Debug},
+};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+#[cfg(feature = "futures")]
+use alloc::collections::VecDeque;
+#[cfg(feature = "futures")]
+use core::task::Waker;
+
+// internals
+// ---------
+//
+// the handler is an `Option<Arc<dyn Fn>>` guarded by a spin lock. the lock is
+// only held to clone or swap the `Arc`, never while the handler runs or is
+// dropped, so a handler can be called on several threads at once, and
+// replacing it doesn't wait for calls in progress. the lock is released by a
+// guard, so it's released even if something run under it panics.
+//
+// tasks waiting for a handler to be set are in a `WaiterList`, woken by each
+// put.
+//
+// with the `futures` feature, the lock also guards the buffers of the output
+// streams. each call clones its output into every buffer, evicting the oldest
+// output of any full buffer. values are evicted and wakers woken after
+// unlocking. cloning an output runs `O::clone` under the lock, which may panic:
+// the lock is then released as usual, and the wakers of the buffers the output
+// already reached are still woken, during unwinding. `has_outputs` allows
+// skipping the lock when there are no streams.
+
+type Handler<I, O> = Arc<dyn Fn(I) -> O + Send + Sync + 'static>;
+
+// releases the lock when dropped.
+struct Unlock<'a>(&'a AtomicBool);
+
+impl Drop for Unlock<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+// wakes the wakers when dropped, including during unwinding.
+#[cfg(feature = "futures")]
+struct WakeAll(Vec<Waker>);
+
+#[cfg(feature = "futures")]
+impl Drop for WakeAll {
+    fn drop(&mut self) {
+        for waker in self.0.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// Like an `Atomic<Option<Arc<dyn Fn(I) -> O + Send + Sync + 'static>>>`.
+///
+/// Unlike [`CallbackCellArgs`][crate::CallbackCellArgs], the handler is not
+/// taken when called: it stays until replaced or cleared, and can be called
+/// any number of times, from any number of threads at once.
+pub struct MultiCallbackCellArgs<I, O> {
+    locked: AtomicBool,
+    inner: UnsafeCell<Inner<I, O>>,
+    set_waiters: WaiterList,
+    #[cfg(feature = "futures")]
+    has_outputs: AtomicBool,
+}
+
+struct Inner<I, O> {
+    handler: Option<Handler<I, O>>,
+    #[cfg(feature = "futures")]
+    outputs: Outputs<O>,
+}
+
+#[cfg(feature = "futures")]
+pub(crate) struct Outputs<O> {
+    next_id: u64,
+    pub(crate) buffers: Vec<OutputBuffer<O>>,
+}
+
+#[cfg(feature = "futures")]
+pub(crate) struct OutputBuffer<O> {
+    pub(crate) id: u64,
+    pub(crate) queue: VecDeque<O>,
+    capacity: usize,
+    pub(crate) waker: Option<Waker>,
+    // outputs evicted since the stream last polled.
+    pub(crate) dropped: u64,
+    clone: fn(&O) -> O,
+}
+
+// safety: the handler is only accessed while holding the lock, and is Send and
+//         Sync. `I` values never cross threads through the cell. `O` values
+//         only do so through output streams, which require `O: Send`.
+unsafe impl<I, O> Send for MultiCallbackCellArgs<I, O> {}
+unsafe impl<I, O> Sync for MultiCallbackCellArgs<I, O> {}
+
+// handlers run outside the lock, so a panicking handler leaves the cell as it
+// was.
+impl<I, O> UnwindSafe for MultiCallbackCellArgs<I, O> {}
+impl<I, O> RefUnwindSafe for MultiCallbackCellArgs<I, O> {}
+
+impl<I, O> MultiCallbackCellArgs<I, O> {
+    const_fn! {
+        /// Construct with no handler.
+        ///
+        /// This is a `const fn`, so cells can be placed in statics.
+        pub fn new() -> Self {
+            MultiCallbackCellArgs {
+                locked: AtomicBool::new(false),
+                inner: UnsafeCell::new(Inner {
+                    handler: None,
+                    #[cfg(feature = "futures")]
+                    outputs: Outputs {
+                        next_id: 0,
+                        buffers: Vec::new(),
+                    },
+                }),
+                set_waiters: WaiterList::new(),
+                #[cfg(feature = "futures")]
+                has_outputs: AtomicBool::new(false),
+            }
+        }
+    }
+
+    // run the closure on the inner data while holding the lock.
+    fn with_inner<R>(&self, f: impl FnOnce(&mut Inner<I, O>) -> R) -> R {
+        while self.locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            hint::spin_loop();
+        }
+        let _unlock = Unlock(&self.locked);
+        f(unsafe { &mut *self.inner.get() })
+    }
+
+    // run the closure on the handler while holding the lock.
+    fn with_lock<R>(&self, f: impl FnOnce(&mut Option<Handler<I, O>>) -> R) -> R {
+        self.with_inner(|inner| f(&mut inner.handler))
+    }
+
+    /// Set the handler, then wake any tasks waiting for a handler to be set.
+    ///
+    /// Any handler previously present is dropped once calls in progress
+    /// finish.
+    pub fn put<F: Fn(I) -> O + Send + Sync + 'static>(&self, f: F) {
+        let new: Handler<I, O> = Arc::new(f);
+        let old = self.with_lock(|handler| handler.replace(new));
+        drop(old);
+        self.set_waiters.wake_all();
+    }
+
+    /// Call the handler with the given input, leaving it in place.
+    ///
+    /// Returns the output if a handler was present. If a handler was not
+    /// present, returns the original input.
+    ///
+    /// With the `futures` feature, a clone of the output is also buffered for
+    /// each stream returned by [`outputs`][Self::outputs].
+    pub fn call(&self, input: I) -> Result<O, I> {
+        match self.with_lock(|handler| handler.clone()) {
+            Some(handler) => {
+                let output = handler(input);
+                #[cfg(feature = "futures")]
+                self.publish(&output);
+                Ok(output)
+            }
+            None => Err(input),
+        }
+    }
+
+    /// Call the handler with each input in turn, collecting the outputs.
+    ///
+    /// The handler is looked up once, so if it's replaced or cleared partway
+    /// through, the batch finishes with the handler present when this was
+    /// called. If no handler was present, returns the inputs untouched.
+    pub fn call_each<It: IntoIterator<Item = I>>(&self, inputs: It) -> Result<Vec<O>, It::IntoIter> {
+        let mut outputs = Vec::new();
+        self.call_each_with(inputs, |output| outputs.push(output))?;
+        Ok(outputs)
+    }
+
+    /// Like [`call_each`][Self::call_each], but passing each output to `sink`
+    /// rather than collecting them.
+    ///
+    /// Returns how many inputs were handled.
+    pub fn call_each_with<It, S>(&self, inputs: It, mut sink: S) -> Result<usize, It::IntoIter>
+    where
+        It: IntoIterator<Item = I>,
+        S: FnMut(O),
+    {
+        let inputs = inputs.into_iter();
+        let Some(handler) = self.with_lock(|handler| handler.clone()) else {
+            return Err(inputs);
+        };
+        let mut handled = 0;
+        for input in inputs {
+            let output = handler(input);
+            #[cfg(feature = "futures")]
+            self.publish(&output);
+            sink(output);
+            handled += 1;
+        }
+        Ok(handled)
+    }
+
+    /// Remove the handler.
+    ///
+    /// Returns true if a handler was present.
+    pub fn clear(&self) -> bool {
+        let old = self.with_lock(mem::take);
+        old.is_some()
+    }
+
+    /// Whether a handler is currently present.
+    ///
+    /// This is only a snapshot: another thread may put or clear a handler
+    /// immediately afterwards.
+    pub fn is_set(&self) -> bool {
+        self.with_lock(|handler| handler.is_some())
+    }
+
+    /// Wait until a handler is present.
+    ///
+    /// Any number of tasks can wait at once. Dropping the future deregisters
+    /// its waker.
+    pub fn wait_set(&self) -> MultiWaitSet<'_, I, O> {
+        MultiWaitSet { cell: self, id: None }
+    }
+
+    // register the waker to be woken by the next put, unless a handler is
+    // present. returns whether a handler is present.
+    pub(crate) fn poll_set(&self, cx: &mut Context, id: &mut Option<u64>) -> Poll<()> {
+        if self.is_set() {
+            return Poll::Ready(());
+        }
+        self.set_waiters.register(id, cx.waker());
+        if self.is_set() {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+
+    pub(crate) fn deregister(&self, id: Option<u64>) {
+        self.set_waiters.deregister(id);
+    }
+
+    // clone the output into each output stream's buffer.
+    #[cfg(feature = "futures")]
+    fn publish(&self, output: &O) {
+        if !self.has_outputs.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut wakers = WakeAll(Vec::new());
+        let mut evicted = Vec::new();
+        self.with_inner(|inner| {
+            for buffer in &mut inner.outputs.buffers {
+                if buffer.queue.len() == buffer.capacity {
+                    evicted.extend(buffer.queue.pop_front());
+                    buffer.dropped += 1;
+                }
+                buffer.queue.push_back((buffer.clone)(output));
+                wakers.0.extend(buffer.waker.take());
+            }
+        });
+        drop(evicted);
+        drop(wakers);
+    }
+
+    // add an empty output buffer, returning its id.
+    #[cfg(feature = "futures")]
+    pub(crate) fn add_output_buffer(&self, capacity: usize, clone: fn(&O) -> O) -> u64 {
+        self.with_inner(|inner| {
+            let id = inner.outputs.next_id;
+            inner.outputs.next_id += 1;
+            inner.outputs.buffers.push(OutputBuffer {
+                id,
+                queue: VecDeque::new(),
+                capacity,
+                waker: None,
+                dropped: 0,
+                clone,
+            });
+            self.has_outputs.store(true, Ordering::Relaxed);
+            id
+        })
+    }
+
+    // run the closure on the output buffer with the given id while holding the
+    // lock.
+    #[cfg(feature = "futures")]
+    pub(crate) fn with_output_buffer<R>(&self, id: u64, f: impl FnOnce(&mut OutputBuffer<O>) -> R) -> R {
+        self.with_inner(|inner| {
+            let buffer = inner.outputs.buffers.iter_mut().find(|b| b.id == id).unwrap();
+            f(buffer)
+        })
+    }
+
+    // remove the output buffer with the given id, dropping its contents after
+    // unlocking.
+    #[cfg(feature = "futures")]
+    pub(crate) fn remove_output_buffer(&self, id: u64) {
+        let buffer = self.with_inner(|inner| {
+            let i = inner.outputs.buffers.iter().position(|b| b.id == id)?;
+            let buffer = inner.outputs.buffers.swap_remove(i);
+            if inner.outputs.buffers.is_empty() {
+                self.has_outputs.store(false, Ordering::Relaxed);
+            }
+            Some(buffer)
+        });
+        drop(buffer);
+    }
+}
+
+impl<I, O> Default for MultiCallbackCellArgs<I, O> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I, O> Debug for MultiCallbackCellArgs<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_set() {
+            f.write_str("MultiCallbackCellArgs(NOT NULL)")
+        } else {
+            f.write_str("MultiCallbackCellArgs(NULL)")
+        }
+    }
+}
+
+/// Future returned by [`MultiCallbackCellArgs::wait_set`].
+#[must_use = "futures do nothing unless polled"]
+pub struct MultiWaitSet<'a, I, O> {
+    cell: &'a MultiCallbackCellArgs<I, O>,
+    id: Option<u64>,
+}
+
+impl<I, O> Future for MultiWaitSet<'_, I, O> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = &mut *self;
+        this.cell.poll_set(cx, &mut this.id)
+    }
+}
+
+impl<I, O> Drop for MultiWaitSet<'_, I, O> {
+    fn drop(&mut self) {
+        self.cell.deregister(self.id);
+    }
+}
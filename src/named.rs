@@ -0,0 +1,215 @@
+
+use crate::{
+    CallbackCell,
+    CallbackCellArgs,
+};
+use core::fmt::{self, Formatter, Debug};
+
+// internals
+// ---------
+//
+// a named cell is the unnamed cell plus its name, which it passes along to
+// tracing, the global hooks, and abort messages. the unnamed cells stay a
+// single atomic pointer.
+
+/// A [`CallbackCell`] with a name, for diagnostics.
+///
+/// The name appears in the `Debug` output, in `tracing` events, in
+/// [`Hooks`][crate::Hooks], and in the message printed by
+/// [`take_call_abort_on_panic`][Self::take_call_abort_on_panic].
+pub struct NamedCallbackCell {
+    cell: CallbackCell,
+    name: &'static str,
+}
+
+impl NamedCallbackCell {
+    const_fn! {
+        /// Construct with the given name and no callback.
+        ///
+        /// This is a `const fn`, so cells can be placed in statics.
+        pub fn new_named(name: &'static str) -> Self {
+            NamedCallbackCell {
+                cell: CallbackCell::new(),
+                name,
+            }
+        }
+    }
+
+    /// The cell's name.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Change the cell's name.
+    pub fn set_name(&mut self, name: &'static str) {
+        self.name = name;
+    }
+
+    /// Atomically set the callback.
+    ///
+    /// See [`CallbackCell::put`].
+    pub fn put<F: FnOnce() + Send + 'static>(&self, f: F) {
+        self.cell.put_replacing(f, Some(self.name));
+    }
+
+    /// Whether a callback is currently present.
+    ///
+    /// See [`CallbackCell::is_set`].
+    pub fn is_set(&self) -> bool {
+        self.cell.is_set()
+    }
+
+    /// Atomically take the callback then run it.
+    ///
+    /// See [`CallbackCell::take_call`].
+    pub fn take_call(&self) -> bool {
+        self.cell.take_call_named(Some(self.name))
+    }
+
+    /// Like [`take_call`][Self::take_call], but aborts the process if the
+    /// callback panics.
+    ///
+    /// See [`CallbackCell::take_call_abort_on_panic`].
+    #[cfg(feature = "std")]
+    pub fn take_call_abort_on_panic(&self) -> bool {
+        self.cell.take_call_abort_on_panic_named(Some(self.name))
+    }
+
+    /// Atomically take the callback and drop it without running it.
+    ///
+    /// See [`CallbackCell::clear`].
+    pub fn clear(&self) -> bool {
+        self.cell.clear_named(Some(self.name))
+    }
+
+    /// The underlying cell, for operations not covered here.
+    ///
+    /// Operations through it are not named.
+    pub fn as_unnamed(&self) -> &CallbackCell {
+        &self.cell
+    }
+}
+
+impl Drop for NamedCallbackCell {
+    fn drop(&mut self) {
+        self.cell.drop_named(Some(self.name));
+    }
+}
+
+impl Debug for NamedCallbackCell {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.name, if self.is_set() { "armed" } else { "empty" })
+    }
+}
+
+#[cfg(feature = "defmt")]
+#[allow(clippy::if_same_then_else)]
+impl defmt::Format for NamedCallbackCell {
+    fn format(&self, f: defmt::Formatter) {
+        if self.is_set() {
+            defmt::write!(f, "{=str}: armed", self.name)
+        } else {
+            defmt::write!(f, "{=str}: empty", self.name)
+        }
+    }
+}
+
+/// A [`CallbackCellArgs`] with a name, for diagnostics.
+///
+/// See [`NamedCallbackCell`].
+pub struct NamedCallbackCellArgs<I, O> {
+    cell: CallbackCellArgs<I, O>,
+    name: &'static str,
+}
+
+impl<I, O> NamedCallbackCellArgs<I, O> {
+    const_fn! {
+        /// Construct with the given name and no callback.
+        ///
+        /// This is a `const fn`, so cells can be placed in statics.
+        pub fn new_named(name: &'static str) -> Self {
+            NamedCallbackCellArgs {
+                cell: CallbackCellArgs::new(),
+                name,
+            }
+        }
+    }
+
+    /// The cell's name.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Change the cell's name.
+    pub fn set_name(&mut self, name: &'static str) {
+        self.name = name;
+    }
+
+    /// Atomically set the callback.
+    ///
+    /// See [`CallbackCellArgs::put`].
+    pub fn put<F: FnOnce(I) -> O + Send + 'static>(&self, f: F) {
+        self.cell.put_replacing(f, Some(self.name));
+    }
+
+    /// Whether a callback is currently present.
+    ///
+    /// See [`CallbackCellArgs::is_set`].
+    pub fn is_set(&self) -> bool {
+        self.cell.is_set()
+    }
+
+    /// Atomically take the callback then run it with the given input.
+    ///
+    /// See [`CallbackCellArgs::take_call`].
+    pub fn take_call(&self, input: I) -> Result<O, I> {
+        self.cell.take_call_named(input, Some(self.name))
+    }
+
+    /// Like [`take_call`][Self::take_call], but aborts the process if the
+    /// callback panics.
+    ///
+    /// See [`CallbackCellArgs::take_call_abort_on_panic`].
+    #[cfg(feature = "std")]
+    pub fn take_call_abort_on_panic(&self, input: I) -> Result<O, I> {
+        self.cell.take_call_abort_on_panic_named(input, Some(self.name))
+    }
+
+    /// Atomically take the callback and drop it without running it.
+    ///
+    /// See [`CallbackCellArgs::clear`].
+    pub fn clear(&self) -> bool {
+        self.cell.clear_named(Some(self.name))
+    }
+
+    /// The underlying cell, for operations not covered here.
+    ///
+    /// Operations through it are not named.
+    pub fn as_unnamed(&self) -> &CallbackCellArgs<I, O> {
+        &self.cell
+    }
+}
+
+impl<I, O> Drop for NamedCallbackCellArgs<I, O> {
+    fn drop(&mut self) {
+        self.cell.drop_named(Some(self.name));
+    }
+}
+
+impl<I, O> Debug for NamedCallbackCellArgs<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.name, if self.is_set() { "armed" } else { "empty" })
+    }
+}
+
+#[cfg(feature = "defmt")]
+#[allow(clippy::if_same_then_else)]
+impl<I, O> defmt::Format for NamedCallbackCellArgs<I, O> {
+    fn format(&self, f: defmt::Formatter) {
+        if self.is_set() {
+            defmt::write!(f, "{=str}: armed", self.name)
+        } else {
+            defmt::write!(f, "{=str}: empty", self.name)
+        }
+    }
+}
@@ -0,0 +1,169 @@
+
+use crate::CallbackCell;
+use core::{
+    mem,
+    fmt::{self, Formatter, Debug},
+};
+use alloc::boxed::Box;
+#[cfg(all(unix, feature = "unix"))]
+use std::{
+    io::{self, Write},
+    os::unix::net::UnixStream,
+};
+#[cfg(all(target_os = "linux", feature = "unix"))]
+use std::{
+    fs::File,
+    os::fd::{FromRawFd, OwnedFd},
+};
+
+/// Hook run by a [`NotifyCallbackCell`] when a callback becomes present.
+pub struct Notifier(Box<dyn Fn() + Send + Sync + 'static>);
+
+impl Notifier {
+    /// Notifier which calls the given function.
+    ///
+    /// The function is called on the thread which puts the callback, and
+    /// should not block.
+    pub fn from_fn<F: Fn() + Send + Sync + 'static>(f: F) -> Self {
+        Notifier(Box::new(f))
+    }
+
+    /// Notifier which makes a file descriptor readable, for event loops built
+    /// on `poll`, `epoll`, `kqueue`, or similar.
+    ///
+    /// Returns the notifier and the readable end, a connected non-blocking
+    /// socket. Each notification writes a byte to it. The event loop should
+    /// read from it until `WouldBlock` to clear it, then call `take_call`.
+    /// Notifications are coalesced if the socket's buffer fills up.
+    ///
+    /// Behind the `unix` feature.
+    #[cfg(all(unix, feature = "unix"))]
+    pub fn socket_pair() -> io::Result<(Self, UnixStream)> {
+        let (write, read) = UnixStream::pair()?;
+        write.set_nonblocking(true)?;
+        read.set_nonblocking(true)?;
+        let notifier = Notifier::from_fn(move || {
+            // if this would block, the buffer is full, so the socket is
+            // readable anyway. if it fails otherwise, the readable end was
+            // closed, so there's no one to notify.
+            let _ = (&write).write(&[1]);
+        });
+        Ok((notifier, read))
+    }
+
+    /// Notifier which makes an eventfd readable, for event loops built on
+    /// `epoll` or similar, on Linux.
+    ///
+    /// Returns the notifier and the eventfd, which is non-blocking. Each
+    /// notification adds 1 to its counter, making it readable. The event
+    /// loop should read 8 bytes from it, which resets the counter, then call
+    /// `take_call`. Unlike [`socket_pair`][Self::socket_pair], this needs only
+    /// one file descriptor, besides the notifier's duplicate of it, and
+    /// notifications never fill a buffer.
+    ///
+    /// Behind the `unix` feature.
+    #[cfg(all(target_os = "linux", feature = "unix"))]
+    pub fn eventfd() -> io::Result<(Self, File)> {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // safety: the fd was just opened, and isn't owned by anything else.
+        let read = File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+        let write = read.try_clone()?;
+        let notifier = Notifier::from_fn(move || {
+            // this only fails if the counter would overflow, in which case
+            // the eventfd is readable anyway.
+            let _ = (&write).write(&1u64.to_ne_bytes());
+        });
+        Ok((notifier, read))
+    }
+}
+
+impl Debug for Notifier {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("Notifier(..)")
+    }
+}
+
+/// A [`CallbackCell`] which runs a [`Notifier`] whenever it goes from empty to
+/// holding a callback.
+///
+/// This lets event loops which wait on something other than the cell, such
+/// as a file descriptor, learn that `take_call` has work to do. Putting while
+/// a callback is already present replaces it without notifying, so several
+/// puts before a `take_call` make only one notification.
+pub struct NotifyCallbackCell {
+    cell: CallbackCell,
+    notifier: Notifier,
+}
+
+impl NotifyCallbackCell {
+    /// Construct with no callback and the given notifier.
+    pub fn new(notifier: Notifier) -> Self {
+        NotifyCallbackCell {
+            cell: CallbackCell::new(),
+            notifier,
+        }
+    }
+
+    /// Atomically set the callback, then run the notifier if no callback was
+    /// previously present.
+    ///
+    /// Any callback previously present is dropped. With the `disabled`
+    /// feature, the cell never holds a callback, so this drops `f` and never
+    /// runs the notifier.
+    pub fn put<F: FnOnce() + Send + 'static>(&self, f: F) {
+        #[cfg(feature = "disabled")]
+        {
+            drop(f);
+            return;
+        }
+        #[allow(unreachable_code)]
+        if !self.cell.put_replacing(f, None) {
+            (self.notifier.0)();
+        }
+    }
+
+    /// Atomically take the callback then run it.
+    ///
+    /// Returns true if a callback was present.
+    pub fn take_call(&self) -> bool {
+        self.cell.take_call()
+    }
+
+    /// Atomically take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        self.cell.clear()
+    }
+
+    /// Whether a callback is currently present.
+    pub fn is_set(&self) -> bool {
+        self.cell.is_set()
+    }
+
+    /// Replace the notifier, returning the old one.
+    ///
+    /// If a callback is present, the new notifier is run, since whoever it
+    /// notifies hasn't heard of that callback yet. This takes `&mut self`, so
+    /// that no put is running the old notifier meanwhile.
+    pub fn set_notifier(&mut self, notifier: Notifier) -> Notifier {
+        let old = mem::replace(&mut self.notifier, notifier);
+        if self.cell.is_set() {
+            (self.notifier.0)();
+        }
+        old
+    }
+}
+
+impl Debug for NotifyCallbackCell {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.cell.is_set() {
+            f.write_str("NotifyCallbackCell(NOT NULL)")
+        } else {
+            f.write_str("NotifyCallbackCell(NULL)")
+        }
+    }
+}
@@ -0,0 +1,154 @@
+
+use crate::sync::{AtomicPtr, LoadMut};
+use alloc::boxed::Box;
+use core::{
+    sync::atomic::Ordering,
+    ptr,
+    marker::PhantomData,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+
+// internals
+// ---------
+//
+// the inner atomic pointer is a nullable pointer from `Box::into_raw`. the
+// same swap-and-drop protocol as the callback cells, without erasing the type.
+
+fn into_raw<T>(value: Option<Box<T>>) -> *mut T {
+    value.map_or(ptr::null_mut(), Box::into_raw)
+}
+
+unsafe fn from_raw<T>(ptr: *mut T) -> Option<Box<T>> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(Box::from_raw(ptr))
+    }
+}
+
+/// Like an `Atomic<Option<Box<T>>>`.
+///
+/// The same primitive as [`CallbackCell`][crate::CallbackCell], for plain
+/// values rather than callbacks: a value put into the cell is moved out by
+/// whichever thread takes it, and a value replaced without being taken is
+/// dropped.
+pub struct AtomicOptionBox<T> {
+    ptr: AtomicPtr<T>,
+    _p: PhantomData<Box<T>>,
+}
+
+// safety: values only ever move through the cell, never being accessed by
+//         reference from more than one thread.
+unsafe impl<T: Send> Send for AtomicOptionBox<T> {}
+unsafe impl<T: Send> Sync for AtomicOptionBox<T> {}
+
+// the cell is changed in single atomic operations, and only drops values which
+// are no longer in it.
+impl<T> UnwindSafe for AtomicOptionBox<T> {}
+impl<T> RefUnwindSafe for AtomicOptionBox<T> {}
+
+impl<T> AtomicOptionBox<T> {
+    const_fn! {
+        /// Construct with no value.
+        ///
+        /// This is a `const fn`, so cells can be placed in statics.
+        pub fn new() -> Self {
+            AtomicOptionBox {
+                ptr: AtomicPtr::new(ptr::null_mut()),
+                _p: PhantomData,
+            }
+        }
+    }
+
+    /// Construct with the given value, if any.
+    pub fn new_with(value: Option<Box<T>>) -> Self {
+        AtomicOptionBox {
+            ptr: AtomicPtr::new(into_raw(value)),
+            _p: PhantomData,
+        }
+    }
+
+    /// Atomically set the value.
+    ///
+    /// Any value previously present is dropped.
+    pub fn put(&self, value: T) {
+        drop(self.swap(Some(Box::new(value))));
+    }
+
+    /// Atomically replace the value, returning the previous value.
+    pub fn swap(&self, value: Option<Box<T>>) -> Option<Box<T>> {
+        unsafe { from_raw(self.ptr.swap(into_raw(value), Ordering::AcqRel)) }
+    }
+
+    // atomically set the value if no value is present, or else return it.
+    pub(crate) fn put_if_empty(&self, value: Box<T>) -> Result<(), Box<T>> {
+        let ptr = Box::into_raw(value);
+        match self.ptr.compare_exchange(ptr::null_mut(), ptr, Ordering::AcqRel, Ordering::Relaxed) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(unsafe { Box::from_raw(ptr) }),
+        }
+    }
+
+    /// Atomically take the value.
+    ///
+    /// Returns `None` if no value was present.
+    pub fn take(&self) -> Option<Box<T>> {
+        self.swap(None)
+    }
+
+    /// Atomically take the value and drop it.
+    ///
+    /// Returns true if a value was present.
+    pub fn clear(&self) -> bool {
+        self.take().is_some()
+    }
+
+    /// Whether a value is currently present.
+    ///
+    /// This is only a snapshot: another thread may put or take a value
+    /// immediately afterwards.
+    pub fn is_set(&self) -> bool {
+        !self.ptr.load(Ordering::Acquire).is_null()
+    }
+
+    /// The value, through a unique reference to the cell.
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        unsafe { self.ptr.load_mut().as_mut() }
+    }
+
+    /// Convert into the value.
+    pub fn into_inner(mut self) -> Option<Box<T>> {
+        let ptr = self.ptr.load_mut();
+        core::mem::forget(self);
+        unsafe { from_raw(ptr) }
+    }
+}
+
+impl<T> Drop for AtomicOptionBox<T> {
+    fn drop(&mut self) {
+        drop(unsafe { from_raw(self.ptr.load_mut()) });
+    }
+}
+
+impl<T> Default for AtomicOptionBox<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> From<T> for AtomicOptionBox<T> {
+    fn from(value: T) -> Self {
+        Self::new_with(Some(Box::new(value)))
+    }
+}
+
+impl<T> Debug for AtomicOptionBox<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_set() {
+            f.write_str("AtomicOptionBox(NOT NULL)")
+        } else {
+            f.write_str("AtomicOptionBox(NULL)")
+        }
+    }
+}
@@ -0,0 +1,108 @@
+
+use crate::MultiCallbackCellArgs;
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+    mem,
+    fmt::{self, Formatter, Debug},
+};
+use futures_core::Stream;
+
+// internals
+// ---------
+//
+// each stream owns a buffer in the cell, identified by id, which the cell's
+// `call` pushes clones of outputs into. the buffer and the stream's waker are
+// both guarded by the cell's lock, so an output pushed while the stream is
+// registering its waker is not missed. dropping the stream removes its
+// buffer, so calls stop cloning outputs for it. the waker is cloned before
+// locking, since cloning it runs the executor's code, which could panic or
+// take long, and only stored if it's needed.
+
+/// Default capacity of the buffer of a stream returned by
+/// [`MultiCallbackCellArgs::outputs`].
+pub const DEFAULT_OUTPUTS_CAPACITY: usize = 16;
+
+/// A [`Stream`] of the outputs of a [`MultiCallbackCellArgs`]'s handler,
+/// behind the `futures` feature.
+///
+/// Returned by [`MultiCallbackCellArgs::outputs`]. Each call made through
+/// [`call`][MultiCallbackCellArgs::call] after the stream is created, with
+/// any handler, clones its output into the stream's buffer. Calls never wait
+/// for a stream to make space: when the buffer is full, the oldest output is
+/// dropped to make room, and counted in [`dropped`][Self::dropped].
+///
+/// The stream never ends on its own. Dropping it stops buffering for it.
+pub struct CallbackOutputs<'a, I, O> {
+    cell: &'a MultiCallbackCellArgs<I, O>,
+    id: u64,
+    dropped: u64,
+}
+
+impl<I, O: Clone + Send> MultiCallbackCellArgs<I, O> {
+    /// A [`Stream`] of the outputs of calls made after this, buffering up to
+    /// [`DEFAULT_OUTPUTS_CAPACITY`] outputs.
+    ///
+    /// See [`CallbackOutputs`].
+    pub fn outputs(&self) -> CallbackOutputs<'_, I, O> {
+        self.outputs_with_capacity(DEFAULT_OUTPUTS_CAPACITY)
+    }
+
+    /// A [`Stream`] of the outputs of calls made after this, buffering up to
+    /// `capacity` outputs.
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn outputs_with_capacity(&self, capacity: usize) -> CallbackOutputs<'_, I, O> {
+        assert!(capacity > 0, "outputs capacity must be non-zero");
+        let id = self.add_output_buffer(capacity, O::clone);
+        CallbackOutputs { cell: self, id, dropped: 0 }
+    }
+}
+
+impl<I, O> CallbackOutputs<'_, I, O> {
+    /// Number of outputs dropped so far because the buffer was full.
+    ///
+    /// Only counts drops noticed by polling the stream.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+impl<I, O> Stream for CallbackOutputs<'_, I, O> {
+    type Item = O;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<O>> {
+        let this = &mut *self;
+        let mut new_waker = Some(cx.waker().clone());
+        let (poll, dropped, old_waker) = this.cell.with_output_buffer(this.id, |buffer| {
+            let dropped = mem::take(&mut buffer.dropped);
+            if let Some(output) = buffer.queue.pop_front() {
+                return (Poll::Ready(Some(output)), dropped, None);
+            }
+            let old_waker = match &buffer.waker {
+                Some(waker) if waker.will_wake(cx.waker()) => None,
+                _ => mem::replace(&mut buffer.waker, new_waker.take()),
+            };
+            (Poll::Pending, dropped, old_waker)
+        });
+        this.dropped += dropped;
+        drop(old_waker);
+        drop(new_waker);
+        poll
+    }
+}
+
+impl<I, O> Drop for CallbackOutputs<'_, I, O> {
+    fn drop(&mut self) {
+        self.cell.remove_output_buffer(self.id);
+    }
+}
+
+impl<I, O> Debug for CallbackOutputs<'_, I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("CallbackOutputs")
+            .field("cell", self.cell)
+            .field("dropped", &self.dropped)
+            .finish()
+    }
+}
@@ -0,0 +1,224 @@
+
+use crate::{
+    sync::{AtomicPtr, LoadMut},
+    raw,
+    sealed::{is_tagged, untagged, empty_like},
+};
+use core::{
+    sync::atomic::Ordering,
+    ptr,
+    marker::PhantomData,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug, Display},
+};
+
+// internals
+// ---------
+//
+// the inner atomic pointer is a nullable pointer to an erased callback, as
+// described in the `raw` module, tagged in its low bit with whether the cell is
+// paused. the tag is the same bit the `sealed` module uses. every update is a
+// compare-exchange which keeps the tag, other than pausing and resuming, which
+// are compare-exchanges which keep the pointer. so pauses, resumes, puts, and
+// takes are all totally ordered: a take either lands before a pause, and runs
+// its callback, or after, and sees the tag.
+
+const PAUSED: usize = 1;
+
+/// Error returned by
+/// [`PausableCallbackCellArgs::take_call`] when no callback ran, holding the
+/// input.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum NotRun<I> {
+    /// No callback was present.
+    Empty(I),
+    /// The cell was paused, so any callback present was left in place.
+    Paused(I),
+}
+
+impl<I> NotRun<I> {
+    /// The input which wasn't passed to a callback.
+    pub fn into_input(self) -> I {
+        match self {
+            NotRun::Empty(input) | NotRun::Paused(input) => input,
+        }
+    }
+
+    /// Whether no callback ran because the cell was paused.
+    pub fn is_paused(&self) -> bool {
+        matches!(self, NotRun::Paused(_))
+    }
+}
+
+impl<I> Debug for NotRun<I> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            NotRun::Empty(_) => f.write_str("Empty(..)"),
+            NotRun::Paused(_) => f.write_str("Paused(..)"),
+        }
+    }
+}
+
+impl<I> Display for NotRun<I> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            NotRun::Empty(_) => f.write_str("no callback present"),
+            NotRun::Paused(_) => f.write_str("paused"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<I> std::error::Error for NotRun<I> {}
+
+/// A [`CallbackCellArgs`][crate::CallbackCellArgs] whose delivery can be
+/// paused without losing its callback.
+///
+/// This is for modal states, such as a configuration reload, during which
+/// callbacks shouldn't run but should stay registered. While the cell is
+/// [`pause`][Self::pause]d, [`take_call`][Self::take_call] leaves the
+/// callback in place and hands the input back. Puts and clears work as
+/// usual. [`resume`][Self::resume] only clears the flag, so the callback
+/// present then runs on the next take.
+///
+/// Pauses and takes are totally ordered, each taking effect at a single
+/// atomic operation on the cell. Pausing doesn't wait for takes in progress:
+/// a take which took its callback before the pause still runs it, possibly
+/// after `pause` returns. Any take after the pause sees the cell paused,
+/// until `resume`.
+pub struct PausableCallbackCellArgs<I, O> {
+    ptr: AtomicPtr<u8>,
+    // invariant in `I` and `O`, like `CallbackCellArgs`.
+    _p: PhantomData<dyn FnOnce(I) -> O + Send + 'static>,
+}
+
+// safety: as for `CallbackCellArgs`.
+unsafe impl<I, O> Send for PausableCallbackCellArgs<I, O> {}
+unsafe impl<I, O> Sync for PausableCallbackCellArgs<I, O> {}
+impl<I, O> UnwindSafe for PausableCallbackCellArgs<I, O> {}
+impl<I, O> RefUnwindSafe for PausableCallbackCellArgs<I, O> {}
+
+impl<I, O> PausableCallbackCellArgs<I, O> {
+    const_fn! {
+        /// Construct unpaused, with no callback.
+        ///
+        /// This is a `const fn`, so cells can be placed in statics.
+        pub fn new() -> Self {
+            PausableCallbackCellArgs {
+                ptr: AtomicPtr::new(ptr::null_mut()),
+                _p: PhantomData,
+            }
+        }
+    }
+
+    /// Atomically set the callback, whether or not the cell is paused.
+    ///
+    /// Makes only one heap allocation. Any callback previously present is
+    /// dropped.
+    pub fn put<F: FnOnce(I) -> O + Send + 'static>(&self, f: F) {
+        let ptr = raw::alloc_raw(f);
+        let old_ptr = self.replace(|old_ptr| ptr.map_addr(|addr| addr | (old_ptr.addr() & PAUSED)));
+        unsafe { raw::drop_raw::<I, O>(untagged(old_ptr)) };
+    }
+
+    // atomically replace the pointer with `new(old)`, returning the old one.
+    fn replace(&self, new: impl Fn(*mut u8) -> *mut u8) -> *mut u8 {
+        let mut old_ptr = self.ptr.load(Ordering::Relaxed);
+        loop {
+            match self.ptr.compare_exchange_weak(old_ptr, new(old_ptr), Ordering::AcqRel, Ordering::Relaxed) {
+                Ok(_) => return old_ptr,
+                Err(new_ptr) => old_ptr = new_ptr,
+            }
+        }
+    }
+
+    /// Atomically take the callback then run it with the given input, unless
+    /// the cell is paused.
+    ///
+    /// Returns the output if a callback was present. Otherwise, returns the
+    /// original input, and whether the cell was paused, in which case any
+    /// callback present is left in place.
+    pub fn take_call(&self, input: I) -> Result<O, NotRun<I>> {
+        let mut old_ptr = self.ptr.load(Ordering::Relaxed);
+        loop {
+            if is_tagged(old_ptr) {
+                return Err(NotRun::Paused(input));
+            }
+            if old_ptr.is_null() {
+                return Err(NotRun::Empty(input));
+            }
+            match self.ptr.compare_exchange_weak(old_ptr, ptr::null_mut(), Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => return Ok(unsafe { raw::call_raw(old_ptr, input) }),
+                Err(new_ptr) => old_ptr = new_ptr,
+            }
+        }
+    }
+
+    /// Atomically take the callback and drop it without running it, whether
+    /// or not the cell is paused.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        let ptr = untagged(self.replace(empty_like));
+        unsafe { raw::drop_raw::<I, O>(ptr) };
+        !ptr.is_null()
+    }
+
+    /// Whether a callback is currently present, whether or not the cell is
+    /// paused.
+    ///
+    /// This is only a snapshot: another thread may put or take a callback
+    /// immediately afterwards.
+    pub fn is_set(&self) -> bool {
+        !untagged(self.ptr.load(Ordering::Acquire)).is_null()
+    }
+
+    /// Atomically pause the cell, so that takes leave the callback in place.
+    ///
+    /// Returns false if the cell was already paused.
+    pub fn pause(&self) -> bool {
+        !is_tagged(self.replace(|old_ptr| old_ptr.map_addr(|addr| addr | PAUSED)))
+    }
+
+    /// Atomically resume the cell, so that takes run the callback again.
+    ///
+    /// The callback present, if any, is untouched. Returns false if the cell
+    /// wasn't paused.
+    pub fn resume(&self) -> bool {
+        is_tagged(self.replace(untagged))
+    }
+
+    /// Whether the cell is currently paused.
+    ///
+    /// This is only a snapshot: another thread may pause or resume the cell
+    /// immediately afterwards.
+    pub fn is_paused(&self) -> bool {
+        is_tagged(self.ptr.load(Ordering::Acquire))
+    }
+}
+
+impl<I, O> Drop for PausableCallbackCellArgs<I, O> {
+    fn drop(&mut self) {
+        unsafe {
+            raw::drop_raw::<I, O>(untagged(self.ptr.load_mut()));
+        }
+    }
+}
+
+impl<I, O> Default for PausableCallbackCellArgs<I, O> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I, O> Debug for PausableCallbackCellArgs<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let ptr = self.ptr.load(Ordering::Relaxed);
+        let set = if untagged(ptr).is_null() { "NULL" } else { "NOT NULL" };
+        if is_tagged(ptr) {
+            write!(f, "PausableCallbackCellArgs({}, PAUSED)", set)
+        } else {
+            write!(f, "PausableCallbackCellArgs({})", set)
+        }
+    }
+}
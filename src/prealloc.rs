@@ -0,0 +1,96 @@
+
+use crate::raw;
+use alloc::alloc::Layout;
+use core::fmt::{self, Formatter, Debug};
+
+// internals
+// ---------
+//
+// a preallocated heap allocation, as described in the `raw` module, not yet
+// holding a callback.
+
+/// A heap allocation made ahead of time, for putting a callback into a
+/// [`CallbackCellArgs`][crate::CallbackCellArgs] without allocating.
+///
+/// For code which mustn't allocate when it registers a callback, such as a
+/// real-time thread: make the slot beforehand, on another thread, with room
+/// for any callback up to a given size and alignment, then hand it over and
+/// [`put_prealloc`][crate::CallbackCellArgs::put_prealloc] the callback,
+/// which only moves it into the slot and swaps it into the cell.
+///
+/// The room needed is only the callback's own size and alignment, those of
+/// its captures: the slot adds the cell's bookkeeping itself. Each slot
+/// holds one callback, and its heap allocation is freed once that callback
+/// has been taken and run or dropped, like any other. A slot dropped without
+/// being used frees its heap allocation.
+///
+// with the `disabled` feature, cells never hold callbacks, so this can't pass
+#[cfg_attr(not(feature = "disabled"), doc = "```")]
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+/// use callback_cell::{ArgsSlot, CallbackCellArgs};
+///
+/// let cell = CallbackCellArgs::<u32, u32>::new();
+/// let slot = ArgsSlot::with_capacity(16, 8);
+///
+/// let offset = 5u64;
+/// assert!(cell.put_prealloc(slot, move |i| i + offset as u32).is_ok());
+/// assert_eq!(cell.take_call(1), Ok(6));
+/// ```
+pub struct ArgsSlot<I, O>(raw::Prealloc<I, O>);
+
+impl<I, O> ArgsSlot<I, O> {
+    /// Allocate room for any callback whose size is at most `bytes` and whose
+    /// alignment is at most `align`.
+    ///
+    /// Makes exactly one heap allocation.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `align` is not a power of two, or if `bytes`, rounded up to
+    /// `align`, overflows an `isize`.
+    pub fn with_capacity(bytes: usize, align: usize) -> Self {
+        ArgsSlot(raw::Prealloc::new(Layout::from_size_align(bytes, align).unwrap()))
+    }
+
+    /// The largest size, in bytes, of a callback which fits.
+    pub fn capacity(&self) -> usize {
+        self.0.capacity().size()
+    }
+
+    /// The largest alignment of a callback which fits.
+    pub fn align(&self) -> usize {
+        self.0.capacity().align()
+    }
+
+    /// The size, in bytes, of the heap allocation, which a callback put with
+    /// it reports from
+    /// [`allocated_bytes`][crate::CallbackCellArgs::allocated_bytes].
+    pub fn allocated_bytes(&self) -> usize {
+        self.0.size()
+    }
+
+    /// Whether a callback of type `F` fits.
+    pub fn fits<F: FnOnce(I) -> O>(&self) -> bool {
+        self.0.fits::<F>()
+    }
+
+    /// Whether the given callback fits, for closures, whose type can't be
+    /// named.
+    pub fn fits_val<F: FnOnce(I) -> O>(&self, _f: &F) -> bool {
+        self.0.fits::<F>()
+    }
+
+    // move the callback in, or give both back if it doesn't fit.
+    pub(crate) fn init<F: FnOnce(I) -> O>(self, f: F) -> Result<*mut u8, (Self, F)> {
+        self.0.init(f).map_err(|(prealloc, f)| (ArgsSlot(prealloc), f))
+    }
+}
+
+impl<I, O> Debug for ArgsSlot<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("ArgsSlot")
+            .field("capacity", &self.capacity())
+            .field("align", &self.align())
+            .finish()
+    }
+}
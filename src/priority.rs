@@ -0,0 +1,357 @@
+
+use crate::sync::{self, AtomicPtr, AtomicBool, LoadMut};
+use core::{
+    sync::atomic::Ordering,
+    ptr,
+    cell::UnsafeCell,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+use alloc::{
+    boxed::Box,
+    vec::Vec,
+    collections::VecDeque,
+};
+
+// internals
+// ---------
+//
+// the queue is a list of bands, in ascending order of priority. each band but
+// the lowest has a threshold, the lowest priority it holds, and every priority
+// goes in the highest band whose threshold it reaches. so every entry in a band
+// has a higher priority than every entry in the bands below it.
+//
+// each band has a lock-free stack of entries pushed to it, like a
+// `DeferredCallQueue`'s: the inner atomic pointer is a nullable pointer to the
+// most recently pushed entry, each of which points to the one pushed before
+// it. each entry is a single heap allocation, a `Box<Node<P, F>>`, starting
+// with a `Header`, which consists of:
+//
+// - the pointer to the next entry
+// - an `unsafe fn(*mut Header<P>, bool) -> P` which, when called with the
+//   pointer to the entry, frees the heap allocation and, if the bool is true,
+//   runs the callback, or else drops it without running it, then returns the
+//   priority
+// - the entry's priority
+//
+// taking entries is guarded by a spin lock. the consumer takes a band's whole
+// stack at once, and moves its entries, in push order, onto the end of the
+// band's ready queue, then stably sorts that by descending priority, so that
+// the front is the highest priority entry pushed first. the lock is only held
+// to take the next entry, never while it runs or is dropped, so an entry can
+// push to and take from the queue.
+
+struct Header<P> {
+    next: *mut Header<P>,
+    fn_ptr: unsafe fn(*mut Header<P>, bool) -> P,
+    priority: P,
+}
+
+#[repr(C)]
+struct Node<P, F> {
+    // must be first
+    header: Header<P>,
+    f: F,
+}
+
+// implementation for the function pointer for a given callback type F.
+unsafe fn fn_ptr_impl<P, F: FnOnce()>(ptr: *mut Header<P>, run: bool) -> P {
+    let node = Box::from_raw(ptr as *mut Node<P, F>);
+    #[cfg(any(test, feature = "test-util"))]
+    crate::test_util::on_dealloc();
+    #[cfg(feature = "alloc-stats")]
+    crate::alloc_stats::on_dealloc(core::mem::size_of::<Node<P, F>>());
+    let Node { header, f } = *node;
+    if run {
+        f();
+    }
+    header.priority
+}
+
+// drop every entry in the list starting at `ptr`, without running them.
+unsafe fn drop_list<P>(mut ptr: *mut Header<P>) {
+    while !ptr.is_null() {
+        let next = (*ptr).next;
+        ((*ptr).fn_ptr)(ptr, false);
+        ptr = next;
+    }
+}
+
+struct Band<P> {
+    head: AtomicPtr<Header<P>>,
+    // only accessed while holding the lock.
+    ready: UnsafeCell<VecDeque<*mut Header<P>>>,
+}
+
+/// A queue of callbacks, run in order of priority, highest first, then in
+/// the order they were pushed.
+///
+/// Any number of threads may [`push`][Self::push] callbacks concurrently
+/// without locking. The consumer runs them with
+/// [`take_call_highest`][Self::take_call_highest],
+/// [`take_call_all`][Self::take_call_all] or
+/// [`take_call_snapshot`][Self::take_call_snapshot]. Consumers on several
+/// threads at once are serialized by a spin lock, which is only held while
+/// choosing the next callback, never while it runs.
+///
+/// The queue is divided into a fixed set of bands, given by thresholds when
+/// constructing it, to bound the work of finding the highest priority
+/// callback: each push goes to the highest band whose threshold its priority
+/// reaches, and the consumer only sorts the callbacks within one band. Giving
+/// every priority in use its own band means no sorting at all.
+pub struct PriorityCallbackQueue<P> {
+    // in ascending order, with one fewer threshold than bands.
+    thresholds: Box<[P]>,
+    bands: Box<[Band<P>]>,
+    taking: AtomicBool,
+}
+
+// safety: the callbacks stored in the queue are Send. priorities are moved from
+//         the pushing thread to the consumer, and thresholds are compared on
+//         every pushing thread, so those require Send and Sync. the ready
+//         queues are only accessed while holding the lock.
+unsafe impl<P: Send> Send for PriorityCallbackQueue<P> {}
+unsafe impl<P: Send + Sync> Sync for PriorityCallbackQueue<P> {}
+
+// callbacks run outside the lock, after being taken, so a panicking callback
+// leaves the queue without it, which is a valid state. a panicking comparison
+// while sorting leaves a ready queue in some order, still holding every entry.
+impl<P> UnwindSafe for PriorityCallbackQueue<P> {}
+impl<P> RefUnwindSafe for PriorityCallbackQueue<P> {}
+
+// releases the lock when dropped, including if a comparison panics.
+struct Unlock<'a>(&'a AtomicBool);
+
+impl Drop for Unlock<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+impl<P: Ord> PriorityCallbackQueue<P> {
+    /// Construct with no callbacks and a single band, so that every callback
+    /// is sorted when taken.
+    pub fn new() -> Self {
+        Self::with_bands([])
+    }
+
+    /// Construct with no callbacks, and a band for each threshold, plus one
+    /// for priorities below every threshold.
+    ///
+    /// The thresholds may be given in any order. Duplicates are removed.
+    pub fn with_bands(thresholds: impl IntoIterator<Item = P>) -> Self {
+        let mut thresholds = thresholds.into_iter().collect::<Vec<_>>();
+        thresholds.sort();
+        thresholds.dedup();
+        let bands = (0..=thresholds.len())
+            .map(|_| Band {
+                head: AtomicPtr::new(ptr::null_mut()),
+                ready: UnsafeCell::new(VecDeque::new()),
+            })
+            .collect();
+        PriorityCallbackQueue {
+            thresholds: thresholds.into_boxed_slice(),
+            bands,
+            taking: AtomicBool::new(false),
+        }
+    }
+
+    /// The number of bands.
+    pub fn bands(&self) -> usize {
+        self.bands.len()
+    }
+
+    /// Push a callback with the given priority.
+    ///
+    /// Makes only one heap allocation, holding both the callback and the
+    /// priority.
+    pub fn push<F: FnOnce() + Send + 'static>(&self, priority: P, f: F) {
+        let band = &self.bands[self.thresholds.partition_point(|threshold| *threshold <= priority)];
+        let node = Box::into_raw(Box::new(Node {
+            header: Header {
+                next: ptr::null_mut(),
+                fn_ptr: fn_ptr_impl::<P, F>,
+                priority,
+            },
+            f,
+        })) as *mut Header<P>;
+        #[cfg(any(test, feature = "test-util"))]
+        crate::test_util::on_alloc();
+        #[cfg(feature = "alloc-stats")]
+        crate::alloc_stats::on_alloc(core::mem::size_of::<Node<P, F>>());
+
+        let mut head = band.head.load(Ordering::Relaxed);
+        loop {
+            unsafe { (*node).next = head };
+            match band.head.compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return,
+                Err(new_head) => head = new_head,
+            }
+        }
+    }
+
+    /// Take the highest priority callback, the first pushed of those with
+    /// that priority, then run it.
+    ///
+    /// Returns its priority, or `None` if the queue was empty.
+    pub fn take_call_highest(&self) -> Option<P> {
+        let ptr = self.take_highest()?;
+        Some(unsafe { ((*ptr).fn_ptr)(ptr, true) })
+    }
+
+    /// Run callbacks in priority order until the queue is empty, including
+    /// any pushed meanwhile, which run in their turn if they have a higher
+    /// priority than those left.
+    ///
+    /// Returns the number of callbacks run. So long as callbacks keep being
+    /// pushed, including by the callbacks themselves, this keeps running
+    /// them. [`take_call_snapshot`][Self::take_call_snapshot] always
+    /// finishes.
+    pub fn take_call_all(&self) -> usize {
+        let mut count = 0;
+        while self.take_call_highest().is_some() {
+            count += 1;
+        }
+        count
+    }
+
+    /// Atomically take every queued callback, then run each in priority
+    /// order.
+    ///
+    /// Returns the number of callbacks run. Callbacks pushed while this runs,
+    /// including by the callbacks themselves, are left for the next call,
+    /// whatever their priority, so this finishes however callbacks are
+    /// pushed, like [`DeferredCallQueue::run_all`][crate::DeferredCallQueue::run_all].
+    /// If a callback panics, the callbacks after it are dropped without
+    /// running.
+    ///
+    /// For a bounded amount of work per call instead, call
+    /// [`take_call_highest`][Self::take_call_highest] up to a limit, which
+    /// also runs newly pushed callbacks in their turn.
+    pub fn take_call_snapshot(&self) -> usize {
+        let snapshot = {
+            let _unlock = self.lock();
+            let mut snapshot = Vec::new();
+            for band in self.bands.iter().rev() {
+                let ready = unsafe { self.absorb(band) };
+                snapshot.extend(ready.drain(..));
+            }
+            snapshot
+        };
+        // if one panics, `snapshot` drops the rest
+        let mut snapshot = Snapshot(snapshot.into_iter());
+        let mut count = 0;
+        for ptr in &mut snapshot.0 {
+            unsafe { ((*ptr).fn_ptr)(ptr, true) };
+            count += 1;
+        }
+        count
+    }
+
+    // take the highest priority entry off the queue.
+    fn take_highest(&self) -> Option<*mut Header<P>> {
+        let _unlock = self.lock();
+        for band in self.bands.iter().rev() {
+            if let Some(ptr) = unsafe { self.absorb(band) }.pop_front() {
+                return Some(ptr);
+            }
+        }
+        None
+    }
+
+    // move the entries pushed to the band onto the end of its ready queue, then
+    // sort it, returning it. the lock must be held.
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn absorb<'a>(&self, band: &'a Band<P>) -> &'a mut VecDeque<*mut Header<P>> {
+        let ready = &mut *band.ready.get();
+        let mut ptr = band.head.swap(ptr::null_mut(), Ordering::Acquire);
+        if !ptr.is_null() {
+            // reverse into push order
+            let start = ready.len();
+            while !ptr.is_null() {
+                ready.push_back(ptr);
+                ptr = (*ptr).next;
+            }
+            ready.make_contiguous()[start..].reverse();
+            ready.make_contiguous().sort_by(|a, b| (**b).priority.cmp(&(**a).priority));
+        }
+        ready
+    }
+
+    /// Drop every callback without running it.
+    ///
+    /// Returns the number of callbacks dropped.
+    pub fn clear(&self) -> usize {
+        let mut count = 0;
+        while let Some(ptr) = self.take_highest() {
+            unsafe { ((*ptr).fn_ptr)(ptr, false) };
+            count += 1;
+        }
+        count
+    }
+
+    /// Whether no callbacks are currently queued.
+    ///
+    /// This is only a snapshot: another thread may push or take a callback
+    /// immediately afterwards.
+    pub fn is_empty(&self) -> bool {
+        let _unlock = self.lock();
+        self.bands.iter().all(|band| {
+            band.head.load(Ordering::Acquire).is_null() && unsafe { (*band.ready.get()).is_empty() }
+        })
+    }
+}
+
+impl<P> PriorityCallbackQueue<P> {
+    // acquire the lock for taking entries, which is released when the returned
+    // guard is dropped.
+    fn lock(&self) -> Unlock<'_> {
+        while self.taking
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            sync::spin_loop();
+        }
+        Unlock(&self.taking)
+    }
+}
+
+// entries taken by `take_call_snapshot`, which are dropped if not run.
+struct Snapshot<P>(alloc::vec::IntoIter<*mut Header<P>>);
+
+impl<P> Drop for Snapshot<P> {
+    fn drop(&mut self) {
+        for ptr in &mut self.0 {
+            unsafe { ((*ptr).fn_ptr)(ptr, false) };
+        }
+    }
+}
+
+impl<P> Drop for PriorityCallbackQueue<P> {
+    fn drop(&mut self) {
+        for band in self.bands.iter_mut() {
+            unsafe {
+                drop_list(band.head.load_mut());
+                for ptr in band.ready.get_mut().drain(..) {
+                    ((*ptr).fn_ptr)(ptr, false);
+                }
+            }
+        }
+    }
+}
+
+impl<P: Ord> Default for PriorityCallbackQueue<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: Ord> Debug for PriorityCallbackQueue<P> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_empty() {
+            f.write_str("PriorityCallbackQueue(EMPTY)")
+        } else {
+            f.write_str("PriorityCallbackQueue(NOT EMPTY)")
+        }
+    }
+}
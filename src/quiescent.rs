@@ -0,0 +1,245 @@
+
+use crate::{
+    sync::{AtomicPtr, AtomicUsize, AtomicBool},
+    raw,
+};
+use alloc::vec::Vec;
+use core::{
+    sync::atomic::Ordering,
+    ptr,
+    cell::RefCell,
+    marker::PhantomData,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+use std::thread;
+
+// internals
+// ---------
+//
+// the callback is a nullable pointer to an erased callback, described in the
+// `raw` module. waiting for the callbacks in flight is like sleepable RCU:
+// `take_call` registers in one of two counters, chosen by the parity of
+// `epoch`, before swapping the pointer, and unregisters once the callback has
+// finished. it loads `epoch` again after registering, and if the parity has
+// changed, unregisters and tries again with the other counter. a wait flips
+// the parity, then waits for the old parity's counter to reach zero.
+//
+// every operation here is sequentially consistent. a `take_call` which takes
+// the callback a wait removed swapped the pointer before the wait did, so its
+// registration, and the load confirming it, precede the wait's flip, which
+// then precedes the wait's loads of the counter, which see the registration
+// until it's undone. a `take_call` registering with the old parity after the
+// flip sees the flip, and moves to the new parity, so it only holds the old
+// counter up briefly. waits are serialized by a spin lock, so the counter for
+// the new parity holds no registrations older than the previous wait.
+//
+// each thread keeps a list of the cells whose callbacks it's running, so that a
+// wait from within one of them, which would wait for itself, panics instead.
+
+std::thread_local! {
+    static RUNNING: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+// unregisters from the counter, and pops the cell off this thread's list if it
+// was pushed, when dropped, even if the callback panics.
+struct Exit<'a> {
+    count: &'a AtomicUsize,
+    pushed: bool,
+}
+
+impl Drop for Exit<'_> {
+    fn drop(&mut self) {
+        if self.pushed {
+            RUNNING.with(|running| running.borrow_mut().pop());
+        }
+        self.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A [`CallbackCellArgs`][crate::CallbackCellArgs] which can wait for
+/// callbacks it has handed out to finish running.
+///
+/// For tearing down whatever a callback borrows, through pointers the
+/// compiler can't see: [`clear_and_wait`][Self::clear_and_wait] removes the
+/// callback, then blocks until every callback taken by a
+/// [`take_call`][Self::take_call] which started beforehand has returned or
+/// panicked, like RCU's `synchronize`. Callbacks taken afterwards, by
+/// `take_call`s which started afterwards, aren't waited for.
+///
+/// Taking a callback costs two more atomic operations than
+/// `CallbackCellArgs`, and two thread-local accesses when the cell isn't
+/// empty. Waiting yields to other threads until the callbacks in flight finish.
+pub struct QuiescentCallbackCellArgs<I, O> {
+    ptr: AtomicPtr<u8>,
+    epoch: AtomicUsize,
+    counts: [AtomicUsize; 2],
+    waiting: AtomicBool,
+    _p: PhantomData<dyn FnOnce(I) -> O + Send + 'static>,
+}
+
+// safety: as for `CallbackCellArgs`.
+unsafe impl<I, O> Send for QuiescentCallbackCellArgs<I, O> {}
+unsafe impl<I, O> Sync for QuiescentCallbackCellArgs<I, O> {}
+
+// a callback is taken before it runs, and unregistered even if it panics, so a
+// panicking callback leaves the cell empty, and holds up no waits.
+impl<I, O> UnwindSafe for QuiescentCallbackCellArgs<I, O> {}
+impl<I, O> RefUnwindSafe for QuiescentCallbackCellArgs<I, O> {}
+
+impl<I, O> QuiescentCallbackCellArgs<I, O> {
+    /// Construct with no callback.
+    ///
+    /// This is a `const fn`, so cells can be placed in statics.
+    pub const fn new() -> Self {
+        QuiescentCallbackCellArgs {
+            ptr: AtomicPtr::new(ptr::null_mut()),
+            epoch: AtomicUsize::new(0),
+            counts: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            waiting: AtomicBool::new(false),
+            _p: PhantomData,
+        }
+    }
+
+    fn addr(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    /// Atomically set the callback.
+    ///
+    /// Makes only one heap allocation. Any callback previously present is
+    /// dropped, without waiting for any callbacks in flight.
+    pub fn put<F: FnOnce(I) -> O + Send + 'static>(&self, f: F) {
+        let old = self.ptr.swap(raw::alloc_raw(f), Ordering::SeqCst);
+        unsafe { raw::drop_raw::<I, O>(old) };
+    }
+
+    /// Atomically take the callback then run it with the given input.
+    ///
+    /// Returns the output if a callback was present. If a callback was not
+    /// present, returns the original input.
+    pub fn take_call(&self, input: I) -> Result<O, I> {
+        let count = loop {
+            let parity = self.epoch.load(Ordering::SeqCst) & 1;
+            let count = &self.counts[parity];
+            count.fetch_add(1, Ordering::SeqCst);
+            if self.epoch.load(Ordering::SeqCst) & 1 == parity {
+                break count;
+            }
+            count.fetch_sub(1, Ordering::SeqCst);
+        };
+        let mut exit = Exit { count, pushed: false };
+        let ptr = self.ptr.swap(ptr::null_mut(), Ordering::SeqCst);
+        if ptr.is_null() {
+            return Err(input);
+        }
+        RUNNING.with(|running| running.borrow_mut().push(self.addr()));
+        exit.pushed = true;
+        let callback = unsafe { raw::Owned::<I, O>::from_erased(ptr) };
+        Ok(callback.call(input))
+    }
+
+    /// Atomically take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present. Doesn't wait for any
+    /// callbacks in flight.
+    pub fn clear(&self) -> bool {
+        let old = self.ptr.swap(ptr::null_mut(), Ordering::SeqCst);
+        unsafe { raw::drop_raw::<I, O>(old) };
+        !old.is_null()
+    }
+
+    /// Atomically take the callback and drop it without running it, then
+    /// wait for every callback already taken to finish running.
+    ///
+    /// Returns true if a callback was present. Once this returns, no
+    /// callback taken by a [`take_call`][Self::take_call] which started
+    /// before it is still running, though a `take_call` on another thread
+    /// may be running a callback put since.
+    ///
+    /// # Panics
+    ///
+    /// Panics, without changing the cell, if called from within one of this
+    /// cell's callbacks, which would wait for itself. Waiting from within a
+    /// callback of another `QuiescentCallbackCellArgs`, whose own callback
+    /// waits for that one, deadlocks.
+    pub fn clear_and_wait(&self) -> bool {
+        self.check_not_running();
+        let cleared = self.clear();
+        self.wait();
+        cleared
+    }
+
+    /// Atomically set the callback, then wait for every callback already
+    /// taken to finish running.
+    ///
+    /// Returns true if a callback was present, which is dropped. Once this
+    /// returns, no callback taken by a [`take_call`][Self::take_call]
+    /// which started before it is still running, as for
+    /// [`clear_and_wait`][Self::clear_and_wait].
+    ///
+    /// # Panics
+    ///
+    /// Panics, without changing the cell, if called from within one of this
+    /// cell's callbacks, as for `clear_and_wait`.
+    pub fn put_and_wait_previous<F: FnOnce(I) -> O + Send + 'static>(&self, f: F) -> bool {
+        self.check_not_running();
+        let old = self.ptr.swap(raw::alloc_raw(f), Ordering::SeqCst);
+        unsafe { raw::drop_raw::<I, O>(old) };
+        self.wait();
+        !old.is_null()
+    }
+
+    /// Whether a callback is currently present.
+    ///
+    /// This is only a snapshot: another thread may put or take a callback
+    /// immediately afterwards.
+    pub fn is_set(&self) -> bool {
+        !self.ptr.load(Ordering::SeqCst).is_null()
+    }
+
+    // panic if called from within one of the cell's callbacks.
+    fn check_not_running(&self) {
+        let nested = RUNNING.with(|running| running.borrow().contains(&self.addr()));
+        if nested {
+            panic!("QuiescentCallbackCellArgs waited on from within its own callback");
+        }
+    }
+
+    // wait for every `take_call` registered before now to finish.
+    fn wait(&self) {
+        while self.waiting
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            thread::yield_now();
+        }
+        let parity = self.epoch.fetch_add(1, Ordering::SeqCst) & 1;
+        while self.counts[parity].load(Ordering::SeqCst) != 0 {
+            thread::yield_now();
+        }
+        self.waiting.store(false, Ordering::Release);
+    }
+}
+
+impl<I, O> Default for QuiescentCallbackCellArgs<I, O> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I, O> Debug for QuiescentCallbackCellArgs<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_set() {
+            f.write_str("QuiescentCallbackCellArgs(NOT NULL)")
+        } else {
+            f.write_str("QuiescentCallbackCellArgs(NULL)")
+        }
+    }
+}
+
+impl<I, O> Drop for QuiescentCallbackCellArgs<I, O> {
+    fn drop(&mut self) {
+        unsafe { raw::drop_raw::<I, O>(*self.ptr.get_mut()) };
+    }
+}
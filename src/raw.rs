@@ -0,0 +1,889 @@
+
+use core::mem::ManuallyDrop;
+#[cfg(any(feature = "alloc", not(feature = "disabled")))]
+use core::marker::PhantomData;
+#[cfg(feature = "alloc")]
+use core::ptr;
+#[cfg(feature = "alloc")]
+use alloc::alloc::{
+    Layout,
+    alloc,
+    dealloc,
+    handle_alloc_error,
+};
+#[cfg(all(feature = "alloc", target_has_atomic = "ptr"))]
+use alloc::sync::Arc;
+
+// internals
+// ---------
+//
+// an erased callback is a non-null pointer to a heap allocation. the
+// pointed-to data consists of:
+//
+// - a header, which consists of:
+//
+//   - an `unsafe fn(Option<&mut union { I, O }, *mut u8, bool)` which, when
+//     called with the pointer:
+//
+//     - if the option is Some, reads the input from the union, runs the
+//       callback with the input (dropping it and the input), and writes
+//       the output back to the union
+//     - if the option is None, drops the callback without running it
+//     - if the bool is true, deallocates the heap allocation. otherwise,
+//       before running or dropping the callback, leaves the heap allocation
+//       as a freed callback, described below
+//   - the size of the heap allocation, in bytes
+//
+//   the header is aligned to at least 2, so the low bit of a pointer to an
+//   erased callback is always clear, and free for tagging.
+// - padding
+// - the `F: FnOnce(I) -> O` value
+//
+// the cells without args use this with `I = ()` and `O = ()`.
+//
+// a freed callback is a heap allocation which once held an erased callback,
+// which has since been moved out, but which hasn't been deallocated. its
+// header is overwritten with:
+//
+// - an `unsafe fn(*mut u8)` which deallocates it
+// - a pointer, for intrusive lists of freed callbacks
+//
+// so that a `Graveyard` can deallocate the heap allocation later, knowing
+// nothing of its layout.
+//
+// a static callback is an erased callback which isn't a heap allocation, but a
+// `Static` in a static: a header with size 0, followed by a reference to the
+// callback. its function pointer runs the callback by reference, and never
+// drops, frees, or writes anything, so the cells use a pointer to it like any
+// other erased callback. the only difference is that it's never left as a
+// freed callback, since there's no allocation to free. a static callback of a
+// zero-sized type is only the header, whose function pointer conjures the
+// callback rather than following a reference to it. without the `alloc`
+// feature, static callbacks are the only erased callbacks, so everything else
+// here is compiled out.
+//
+// a shared callback is an erased callback in the heap allocation of an `Arc`,
+// holding a header with size 0, followed by the callback. putting one clones
+// the `Arc`, and the cell holds the pointer from `Arc::into_raw` in place of a
+// heap allocation of its own. as for a static callback, its function pointer
+// never frees or writes anything in the heap allocation, which other cells
+// may hold too. instead, it runs the callback by reference, if running it,
+// then releases the cell's reference to the `Arc`, which frees the heap
+// allocation if that was the last.
+//
+// a method callback is an erased callback which calls a function pointer with
+// an `Arc` receiver. its header is followed by the function pointer, then the
+// pointer from `Arc::into_raw`, so its layout is the same for every method and
+// receiver type, and its function pointer is only generic over the receiver
+// type, and which of the two kinds of method it is, rather than over each
+// method. the two pointers come after the two words of header a freed callback
+// overwrites, as the layout of a preallocated callback does.
+//
+// a preallocated callback is an erased callback in a heap allocation made
+// before the callback's type was known, with room for any callback up to a
+// given size and alignment. its header is followed by the layout of the whole
+// heap allocation, which can't be derived from the callback's type, then
+// padding, then the callback. the layout comes after the two words of header a
+// freed callback overwrites, so it survives that, to deallocate with.
+//
+// a callback with bytes is an erased callback whose callback is followed by a
+// byte buffer, in the same heap allocation, which the callback borrows when it
+// runs. its header is followed by the layout of the whole heap allocation, as
+// for a preallocated callback, and then the number of bytes. its function
+// pointer frees the heap allocation, or leaves it as a freed callback, only once
+// the callback has returned, or panicked, and no longer borrows the bytes.
+//
+// a labeled callback is an erased callback whose header is followed by its
+// label, then padding, then the callback. the label comes after the two words
+// of header a freed callback overwrites, as the layout of a preallocated
+// callback does. the high bit of its header's size, which is never set in a
+// real size, since heap allocations are at most `isize::MAX` bytes, marks it as
+// labeled, so the label can be found knowing nothing of the callback's type.
+// its function pointer reads the label, then does as the unlabeled one would,
+// reporting the label if the callback panics.
+//
+// a masked callback is an erased callback whose header is followed by a `u32`
+// event mask, then padding, then the callback, for `MaskedCallbackCell`. the
+// mask comes after the two words of header a freed callback overwrites, as the
+// label does. only that cell holds masked callbacks, so nothing marks them.
+
+pub(crate) union IoSlot<I, O> {
+    pub(crate) input: ManuallyDrop<I>,
+    pub(crate) output: ManuallyDrop<O>,
+}
+
+pub(crate) type FnPtrType<I, O> = unsafe fn(Option<&mut IoSlot<I, O>>, *mut u8, bool);
+
+#[repr(C, align(2))]
+struct Header<I, O> {
+    // must be first
+    fn_ptr: FnPtrType<I, O>,
+    size: usize,
+}
+
+#[repr(C)]
+pub(crate) struct Static<I: 'static, O: 'static> {
+    // must be first
+    header: Header<I, O>,
+    f: &'static (dyn Fn(I) -> O + Send + Sync + 'static),
+}
+
+impl<I: 'static, O: 'static> Static<I, O> {
+    pub(crate) const fn new(f: &'static (dyn Fn(I) -> O + Send + Sync + 'static)) -> Self {
+        Static {
+            header: Header {
+                fn_ptr: static_fn_ptr_impl::<I, O>,
+                size: 0,
+            },
+            f,
+        }
+    }
+
+    // the erased callback. the pointer is never written through.
+    pub(crate) const fn as_raw(&'static self) -> *mut u8 {
+        self as *const Self as *mut u8
+    }
+}
+
+// the static callback for a zero-sized, copyable callback type, such as a
+// function item or a closure which captures nothing. in a const fn, there's no
+// `&'static F` to construct a `Static` from, but there needn't be: a value of
+// such a type has no bytes, and copies of it are indistinguishable, so its
+// function pointer conjures one to run. so it's only a header with size 0, an
+// associated const, monomorphized into a promoted static for each type.
+#[cfg(not(feature = "disabled"))]
+pub(crate) struct ZstStatic<F, I, O>(PhantomData<fn(F, I) -> O>);
+
+#[cfg(not(feature = "disabled"))]
+impl<F, I, O> ZstStatic<F, I, O>
+where
+    F: Fn(I) -> O + Copy + Send + Sync + 'static,
+    I: 'static,
+    O: 'static,
+{
+    const HEADER: &'static Header<I, O> = &{
+        assert!(
+            core::mem::size_of::<F>() == 0,
+            "the callback must be zero-sized: a function item, not a function pointer",
+        );
+        Header {
+            fn_ptr: zst_fn_ptr_impl::<F, I, O>,
+            size: 0,
+        }
+    };
+
+    // the erased callback. the pointer is never written through.
+    pub(crate) const fn as_raw() -> *mut u8 {
+        Self::HEADER as *const Header<I, O> as *mut u8
+    }
+}
+
+// implementation for the function pointer of a zero-sized static callback.
+#[cfg(not(feature = "disabled"))]
+unsafe fn zst_fn_ptr_impl<F, I, O>(run: Option<&mut IoSlot<I, O>>, _ptr: *mut u8, _free: bool)
+where
+    F: Fn(I) -> O + Copy,
+{
+    if let Some(io_slot) = run {
+        // safety: F is zero-sized and Copy, checked when the header was
+        //         constructed, so this is a copy of the callback the cell was
+        //         constructed with.
+        let f: F = core::mem::zeroed();
+        io_slot.output = ManuallyDrop::new(f(ManuallyDrop::take(&mut io_slot.input)));
+    }
+}
+
+// implementation for the function pointer of a static callback.
+unsafe fn static_fn_ptr_impl<I: 'static, O: 'static>(run: Option<&mut IoSlot<I, O>>, ptr: *mut u8, _free: bool) {
+    if let Some(io_slot) = run {
+        let f = (*(ptr as *const Static<I, O>)).f;
+        io_slot.output = ManuallyDrop::new(f(ManuallyDrop::take(&mut io_slot.input)));
+    }
+}
+
+cfg_alloc! {
+
+// the callback, in an `Arc`, behind its header. public, as `SharedCallback`.
+#[cfg(target_has_atomic = "ptr")]
+#[repr(C)]
+pub(crate) struct Shared<I, O, F: ?Sized> {
+    // must be first
+    header: Header<I, O>,
+    f: F,
+}
+
+#[cfg(target_has_atomic = "ptr")]
+impl<I, O, F: Fn(I) -> O + Send + Sync + 'static> Shared<I, O, F> {
+    pub(crate) fn new(f: F) -> Self {
+        Shared {
+            header: Header {
+                fn_ptr: shared_fn_ptr_impl::<I, O, F>,
+                size: 0,
+            },
+            f,
+        }
+    }
+}
+
+// implementation for the function pointer of a shared callback of type F.
+#[cfg(target_has_atomic = "ptr")]
+unsafe fn shared_fn_ptr_impl<I, O, F: Fn(I) -> O>(run: Option<&mut IoSlot<I, O>>, ptr: *mut u8, _free: bool) {
+    // the cell's reference, released once the callback returns, or unwinds.
+    // the `Arc` was made as one of a `SharedCallback`, which is transparent.
+    let shared = Arc::from_raw(ptr as *const Shared<I, O, F>);
+    if let Some(io_slot) = run {
+        #[cfg(feature = "watchdog")]
+        crate::watchdog::note_callback(core::any::type_name::<F>());
+        io_slot.output = ManuallyDrop::new((shared.f)(ManuallyDrop::take(&mut io_slot.input)));
+    }
+}
+
+// a function pointer taking an `Arc` receiver, and the input, if it isn't ().
+#[cfg(target_has_atomic = "ptr")]
+pub(crate) trait Method<T, I, O>: Copy {
+    fn call(self, receiver: Arc<T>, input: I) -> O;
+}
+
+#[cfg(target_has_atomic = "ptr")]
+impl<T, I, O> Method<T, I, O> for fn(Arc<T>, I) -> O {
+    fn call(self, receiver: Arc<T>, input: I) -> O {
+        self(receiver, input)
+    }
+}
+
+#[cfg(target_has_atomic = "ptr")]
+impl<T> Method<T, (), ()> for fn(Arc<T>) {
+    fn call(self, receiver: Arc<T>, (): ()) {
+        self(receiver)
+    }
+}
+
+#[cfg(target_has_atomic = "ptr")]
+#[repr(C)]
+struct MethodHeader<I, O, T, M> {
+    // must be first
+    header: Header<I, O>,
+    method: M,
+    receiver: *const T,
+}
+
+// allocate and initialize the heap allocation for a method callback, holding
+// the method and the receiver's pointer. makes exactly one heap allocation. the
+// returned pointer is never null.
+#[cfg(target_has_atomic = "ptr")]
+pub(crate) fn alloc_raw_method<I, O, T, M: Method<T, I, O>>(receiver: Arc<T>, method: M) -> *mut u8 {
+    unsafe {
+        let layout = Layout::new::<MethodHeader<I, O, T, M>>();
+        let ptr = alloc(layout);
+        if ptr.is_null() {
+            handle_alloc_error(layout);
+        }
+        (ptr as *mut MethodHeader<I, O, T, M>).write(MethodHeader {
+            header: Header {
+                fn_ptr: method_fn_ptr_impl::<I, O, T, M>,
+                size: layout.size(),
+            },
+            method,
+            receiver: Arc::into_raw(receiver),
+        });
+        #[cfg(any(test, feature = "test-util"))]
+        crate::test_util::on_alloc();
+        #[cfg(feature = "alloc-stats")]
+        crate::alloc_stats::on_alloc(layout.size());
+        ptr
+    }
+}
+
+// implementation for the function pointer for a given receiver type T and kind
+// of method M.
+#[cfg(target_has_atomic = "ptr")]
+unsafe fn method_fn_ptr_impl<I, O, T, M: Method<T, I, O>>(run: Option<&mut IoSlot<I, O>>, ptr: *mut u8, free: bool) {
+    // extract method and receiver from heap allocation and free heap
+    // allocation, or leave it freed
+    let method = (*(ptr as *const MethodHeader<I, O, T, M>)).method;
+    let receiver = Arc::from_raw((*(ptr as *const MethodHeader<I, O, T, M>)).receiver);
+    if free {
+        method_free_impl::<I, O, T, M>(ptr);
+    } else {
+        (ptr as *mut Freed).write(Freed {
+            free_fn: method_free_impl::<I, O, T, M>,
+            next: ptr::null_mut(),
+        });
+    }
+
+    // run
+    if let Some(io_slot) = run {
+        #[cfg(feature = "watchdog")]
+        crate::watchdog::note_callback(core::any::type_name::<M>());
+        io_slot.output = ManuallyDrop::new(method.call(receiver, ManuallyDrop::take(&mut io_slot.input)));
+    }
+}
+
+// deallocate the heap allocation for a method callback, after the receiver has
+// been moved out.
+#[cfg(target_has_atomic = "ptr")]
+unsafe fn method_free_impl<I, O, T, M>(ptr: *mut u8) {
+    let layout = Layout::new::<MethodHeader<I, O, T, M>>();
+    dealloc(ptr, layout);
+    #[cfg(any(test, feature = "test-util"))]
+    crate::test_util::on_dealloc();
+    #[cfg(feature = "alloc-stats")]
+    crate::alloc_stats::on_dealloc(layout.size());
+}
+
+#[repr(C)]
+struct PreallocHeader<I, O> {
+    // must be first
+    header: Header<I, O>,
+    layout: Layout,
+}
+
+// layout of a preallocated heap allocation with room for any callback fitting
+// in the given layout.
+fn prealloc_layout<I, O>(capacity: Layout) -> Layout {
+    Layout::new::<PreallocHeader<I, O>>().extend(capacity).unwrap().0
+}
+
+// offset of a callback of type F within a preallocated heap allocation.
+fn prealloc_offset<I, O, F>() -> usize {
+    Layout::new::<PreallocHeader<I, O>>().extend(Layout::new::<F>()).unwrap().1
+}
+
+// an owned preallocated heap allocation, not yet holding a callback, which is
+// deallocated if never initialized.
+pub(crate) struct Prealloc<I, O> {
+    ptr: *mut u8,
+    capacity: Layout,
+    _p: PhantomData<fn(I) -> O>,
+}
+
+// safety: it's only uninitialized memory.
+unsafe impl<I, O> Send for Prealloc<I, O> {}
+unsafe impl<I, O> Sync for Prealloc<I, O> {}
+
+impl<I, O> Prealloc<I, O> {
+    // allocate room for any callback fitting in the given layout. makes exactly
+    // one heap allocation.
+    pub(crate) fn new(capacity: Layout) -> Self {
+        unsafe {
+            let layout = prealloc_layout::<I, O>(capacity);
+            let ptr = alloc(layout);
+            if ptr.is_null() {
+                handle_alloc_error(layout);
+            }
+            ptr::addr_of_mut!((*(ptr as *mut PreallocHeader<I, O>)).layout).write(layout);
+            #[cfg(any(test, feature = "test-util"))]
+            crate::test_util::on_alloc();
+            #[cfg(feature = "alloc-stats")]
+            crate::alloc_stats::on_alloc(layout.size());
+            Prealloc { ptr, capacity, _p: PhantomData }
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> Layout {
+        self.capacity
+    }
+
+    // the size of the whole heap allocation, in bytes.
+    pub(crate) fn size(&self) -> usize {
+        prealloc_layout::<I, O>(self.capacity).size()
+    }
+
+    // whether a callback of type F fits. if its alignment is at most the
+    // capacity's, its offset is at most the capacity's, so its size being at
+    // most the capacity's is enough.
+    pub(crate) fn fits<F>(&self) -> bool {
+        core::mem::size_of::<F>() <= self.capacity.size() && core::mem::align_of::<F>() <= self.capacity.align()
+    }
+
+    // move the callback into the heap allocation, returning the erased
+    // callback, or the callback back if it doesn't fit. the returned pointer is
+    // never null.
+    pub(crate) fn init<F: FnOnce(I) -> O>(self, f: F) -> Result<*mut u8, (Self, F)> {
+        if !self.fits::<F>() {
+            return Err((self, f));
+        }
+        let this = ManuallyDrop::new(self);
+        unsafe {
+            let layout = (*(this.ptr as *mut PreallocHeader<I, O>)).layout;
+            (this.ptr as *mut Header<I, O>).write(Header {
+                fn_ptr: prealloc_fn_ptr_impl::<I, O, F>,
+                size: layout.size(),
+            });
+            (this.ptr.add(prealloc_offset::<I, O, F>()) as *mut F).write(f);
+        }
+        Ok(this.ptr)
+    }
+}
+
+impl<I, O> Drop for Prealloc<I, O> {
+    fn drop(&mut self) {
+        unsafe { prealloc_free_impl::<I, O>(self.ptr) };
+    }
+}
+
+// implementation for the function pointer for a given callback type F in a
+// preallocated heap allocation.
+unsafe fn prealloc_fn_ptr_impl<I, O, F: FnOnce(I) -> O>(run: Option<&mut IoSlot<I, O>>, ptr: *mut u8, free: bool) {
+    // extract callback value from heap allocation and free heap allocation,
+    // or leave it freed
+    let f = (ptr.add(prealloc_offset::<I, O, F>()) as *mut F).read();
+    if free {
+        prealloc_free_impl::<I, O>(ptr);
+    } else {
+        (ptr as *mut Freed).write(Freed {
+            free_fn: prealloc_free_impl::<I, O>,
+            next: ptr::null_mut(),
+        });
+    }
+
+    // run
+    if let Some(io_slot) = run {
+        #[cfg(feature = "watchdog")]
+        crate::watchdog::note_callback(core::any::type_name::<F>());
+        io_slot.output = ManuallyDrop::new(f(ManuallyDrop::take(&mut io_slot.input)));
+    }
+}
+
+// deallocate a preallocated heap allocation, after any callback has been moved
+// out.
+unsafe fn prealloc_free_impl<I, O>(ptr: *mut u8) {
+    let layout = (*(ptr as *mut PreallocHeader<I, O>)).layout;
+    dealloc(ptr, layout);
+    #[cfg(any(test, feature = "test-util"))]
+    crate::test_util::on_dealloc();
+    #[cfg(feature = "alloc-stats")]
+    crate::alloc_stats::on_dealloc(layout.size());
+}
+
+#[repr(C)]
+struct BytesHeader<I, O> {
+    // must be first
+    prealloc: PreallocHeader<I, O>,
+    len: usize,
+}
+
+// layout of the heap allocation for a callback of type F with a given number of
+// bytes, and the offsets of the callback and of the bytes within it. the
+// callback's offset doesn't depend on the number of bytes.
+fn bytes_layout<I, O, F>(len: usize) -> (Layout, usize, usize) {
+    let (layout, callback_offset) = Layout::new::<BytesHeader<I, O>>().extend(Layout::new::<F>()).unwrap();
+    let (layout, bytes_offset) = layout.extend(Layout::array::<u8>(len).unwrap()).unwrap();
+    (layout, callback_offset, bytes_offset)
+}
+
+// allocate and initialize the heap allocation for a callback with bytes,
+// holding both. makes exactly one heap allocation. the returned pointer is never
+// null.
+pub(crate) fn alloc_raw_with_bytes<I, O, F: FnOnce(&mut [u8], I) -> O>(data: &[u8], f: F) -> *mut u8 {
+    unsafe {
+        let (layout, callback_offset, bytes_offset) = bytes_layout::<I, O, F>(data.len());
+        let ptr = alloc(layout);
+        if ptr.is_null() {
+            handle_alloc_error(layout);
+        }
+        (ptr as *mut BytesHeader<I, O>).write(BytesHeader {
+            prealloc: PreallocHeader {
+                header: Header {
+                    fn_ptr: bytes_fn_ptr_impl::<I, O, F>,
+                    size: layout.size(),
+                },
+                layout,
+            },
+            len: data.len(),
+        });
+        (ptr.add(callback_offset) as *mut F).write(f);
+        ptr::copy_nonoverlapping(data.as_ptr(), ptr.add(bytes_offset), data.len());
+        #[cfg(any(test, feature = "test-util"))]
+        crate::test_util::on_alloc();
+        #[cfg(feature = "alloc-stats")]
+        crate::alloc_stats::on_alloc(layout.size());
+        ptr
+    }
+}
+
+// frees, or leaves freed, a heap allocation with bytes when dropped, even if the
+// callback panics. its layout is where a preallocated heap allocation's is, so
+// it's deallocated the same way.
+struct ReleaseBytes<I, O> {
+    ptr: *mut u8,
+    free: bool,
+    _p: PhantomData<fn(I) -> O>,
+}
+
+impl<I, O> Drop for ReleaseBytes<I, O> {
+    fn drop(&mut self) {
+        unsafe {
+            if self.free {
+                prealloc_free_impl::<I, O>(self.ptr);
+            } else {
+                (self.ptr as *mut Freed).write(Freed {
+                    free_fn: prealloc_free_impl::<I, O>,
+                    next: ptr::null_mut(),
+                });
+            }
+        }
+    }
+}
+
+// implementation for the function pointer for a given callback type F with
+// bytes. unlike the others, it frees the heap allocation, or leaves it freed,
+// only after running the callback, which borrows the bytes from it.
+unsafe fn bytes_fn_ptr_impl<I, O, F: FnOnce(&mut [u8], I) -> O>(run: Option<&mut IoSlot<I, O>>, ptr: *mut u8, free: bool) {
+    let len = (*(ptr as *const BytesHeader<I, O>)).len;
+    let (_, callback_offset, bytes_offset) = bytes_layout::<I, O, F>(len);
+    let f = (ptr.add(callback_offset) as *mut F).read();
+    let _release = ReleaseBytes::<I, O> { ptr, free, _p: PhantomData };
+
+    // run
+    if let Some(io_slot) = run {
+        #[cfg(feature = "watchdog")]
+        crate::watchdog::note_callback(core::any::type_name::<F>());
+        let bytes = core::slice::from_raw_parts_mut(ptr.add(bytes_offset), len);
+        io_slot.output = ManuallyDrop::new(f(bytes, ManuallyDrop::take(&mut io_slot.input)));
+    }
+}
+
+#[repr(C)]
+struct Freed {
+    // must be first
+    free_fn: unsafe fn(*mut u8),
+    next: *mut u8,
+}
+
+// layout of the heap allocation for a given callback type F, and the offset of the callback within
+// it.
+fn layout<I, O, F>() -> (Layout, usize) {
+    Layout::new::<Header<I, O>>().extend(Layout::new::<F>()).unwrap()
+}
+
+// allocate and initialize the heap allocation for a callback. makes exactly one heap allocation.
+// the returned pointer is never null.
+pub(crate) fn alloc_raw<I, O, F: FnOnce(I) -> O>(f: F) -> *mut u8 {
+    unsafe {
+        let (layout, callback_offset) = layout::<I, O, F>();
+        let ptr = alloc(layout);
+        if ptr.is_null() {
+            handle_alloc_error(layout);
+        }
+        (ptr as *mut Header<I, O>).write(Header {
+            fn_ptr: fn_ptr_impl::<I, O, F>,
+            size: layout.size(),
+        });
+        (ptr.add(callback_offset) as *mut F).write(f);
+        #[cfg(any(test, feature = "test-util"))]
+        crate::test_util::on_alloc();
+        #[cfg(feature = "alloc-stats")]
+        crate::alloc_stats::on_alloc(layout.size());
+        ptr
+    }
+}
+
+}
+
+// the high bit of a header's size, marking a labeled callback.
+const LABELED: usize = 1 << (usize::BITS - 1);
+
+cfg_alloc! {
+
+#[repr(C)]
+struct LabeledHeader<I, O> {
+    // must be first
+    header: Header<I, O>,
+    label: &'static str,
+}
+
+// layout of the heap allocation for a given labeled callback type F, and the
+// offset of the callback within it.
+fn labeled_layout<I, O, F>() -> (Layout, usize) {
+    Layout::new::<LabeledHeader<I, O>>().extend(Layout::new::<F>()).unwrap()
+}
+
+// allocate and initialize the heap allocation for a labeled callback, holding
+// both the callback and its label. makes exactly one heap allocation. the
+// returned pointer is never null.
+pub(crate) fn alloc_raw_labeled<I, O, F: FnOnce(I) -> O>(f: F, label: &'static str) -> *mut u8 {
+    unsafe {
+        let (layout, callback_offset) = labeled_layout::<I, O, F>();
+        let ptr = alloc(layout);
+        if ptr.is_null() {
+            handle_alloc_error(layout);
+        }
+        (ptr as *mut LabeledHeader<I, O>).write(LabeledHeader {
+            header: Header {
+                fn_ptr: labeled_fn_ptr_impl::<I, O, F>,
+                size: layout.size() | LABELED,
+            },
+            label,
+        });
+        (ptr.add(callback_offset) as *mut F).write(f);
+        #[cfg(any(test, feature = "test-util"))]
+        crate::test_util::on_alloc();
+        #[cfg(feature = "alloc-stats")]
+        crate::alloc_stats::on_alloc(layout.size());
+        ptr
+    }
+}
+
+// implementation for the function pointer for a given labeled callback type F.
+unsafe fn labeled_fn_ptr_impl<I, O, F: FnOnce(I) -> O>(run: Option<&mut IoSlot<I, O>>, ptr: *mut u8, free: bool) {
+    // extract label and callback value from heap allocation and free heap
+    // allocation, or leave it freed
+    let label = (*(ptr as *const LabeledHeader<I, O>)).label;
+    let (_, callback_offset) = labeled_layout::<I, O, F>();
+    let f = (ptr.add(callback_offset) as *mut F).read();
+    if free {
+        labeled_free_impl::<I, O, F>(ptr);
+    } else {
+        (ptr as *mut Freed).write(Freed {
+            free_fn: labeled_free_impl::<I, O, F>,
+            next: ptr::null_mut(),
+        });
+    }
+
+    // run
+    if let Some(io_slot) = run {
+        #[cfg(feature = "watchdog")]
+        {
+            crate::watchdog::note_callback(core::any::type_name::<F>());
+            crate::watchdog::note_label(label);
+        }
+        let input = ManuallyDrop::take(&mut io_slot.input);
+        io_slot.output = ManuallyDrop::new(crate::label::call(label, move || f(input)));
+    }
+}
+
+// deallocate the heap allocation for a given labeled callback type F, after the
+// callback has been moved out.
+unsafe fn labeled_free_impl<I, O, F: FnOnce(I) -> O>(ptr: *mut u8) {
+    let (layout, _) = labeled_layout::<I, O, F>();
+    dealloc(ptr, layout);
+    #[cfg(any(test, feature = "test-util"))]
+    crate::test_util::on_dealloc();
+    #[cfg(feature = "alloc-stats")]
+    crate::alloc_stats::on_dealloc(layout.size());
+}
+
+// the label of the pointed to callback, if it's labeled. the pointer must be
+// non-null, and not freed meanwhile.
+pub(crate) unsafe fn label_raw<I, O>(ptr: *mut u8) -> Option<&'static str> {
+    if (*(ptr as *const Header<I, O>)).size & LABELED != 0 {
+        Some((*(ptr as *const LabeledHeader<I, O>)).label)
+    } else {
+        None
+    }
+}
+
+#[repr(C)]
+struct MaskedHeader<I, O> {
+    // must be first
+    header: Header<I, O>,
+    mask: u32,
+}
+
+// layout of the heap allocation for a given masked callback type F, and the
+// offset of the callback within it.
+fn masked_layout<I, O, F>() -> (Layout, usize) {
+    Layout::new::<MaskedHeader<I, O>>().extend(Layout::new::<F>()).unwrap()
+}
+
+// allocate and initialize the heap allocation for a masked callback, holding
+// both the callback and its mask. makes exactly one heap allocation. the
+// returned pointer is never null.
+pub(crate) fn alloc_raw_masked<I, O, F: FnOnce(I) -> O>(f: F, mask: u32) -> *mut u8 {
+    unsafe {
+        let (layout, callback_offset) = masked_layout::<I, O, F>();
+        let ptr = alloc(layout);
+        if ptr.is_null() {
+            handle_alloc_error(layout);
+        }
+        (ptr as *mut MaskedHeader<I, O>).write(MaskedHeader {
+            header: Header {
+                fn_ptr: masked_fn_ptr_impl::<I, O, F>,
+                size: layout.size(),
+            },
+            mask,
+        });
+        (ptr.add(callback_offset) as *mut F).write(f);
+        #[cfg(any(test, feature = "test-util"))]
+        crate::test_util::on_alloc();
+        #[cfg(feature = "alloc-stats")]
+        crate::alloc_stats::on_alloc(layout.size());
+        ptr
+    }
+}
+
+// implementation for the function pointer for a given masked callback type F.
+unsafe fn masked_fn_ptr_impl<I, O, F: FnOnce(I) -> O>(run: Option<&mut IoSlot<I, O>>, ptr: *mut u8, free: bool) {
+    // extract callback value from heap allocation and free heap allocation,
+    // or leave it freed
+    let (_, callback_offset) = masked_layout::<I, O, F>();
+    let f = (ptr.add(callback_offset) as *mut F).read();
+    if free {
+        masked_free_impl::<I, O, F>(ptr);
+    } else {
+        (ptr as *mut Freed).write(Freed {
+            free_fn: masked_free_impl::<I, O, F>,
+            next: ptr::null_mut(),
+        });
+    }
+
+    // run
+    if let Some(io_slot) = run {
+        #[cfg(feature = "watchdog")]
+        crate::watchdog::note_callback(core::any::type_name::<F>());
+        io_slot.output = ManuallyDrop::new(f(ManuallyDrop::take(&mut io_slot.input)));
+    }
+}
+
+// deallocate the heap allocation for a given masked callback type F, after the
+// callback has been moved out.
+unsafe fn masked_free_impl<I, O, F: FnOnce(I) -> O>(ptr: *mut u8) {
+    let (layout, _) = masked_layout::<I, O, F>();
+    dealloc(ptr, layout);
+    #[cfg(any(test, feature = "test-util"))]
+    crate::test_util::on_dealloc();
+    #[cfg(feature = "alloc-stats")]
+    crate::alloc_stats::on_dealloc(layout.size());
+}
+
+// the mask of the pointed to masked callback. the pointer must be non-null, to
+// a masked callback, and not freed meanwhile, though it may be read from by
+// other threads at once.
+pub(crate) unsafe fn mask_raw<I, O>(ptr: *mut u8) -> u32 {
+    (*(ptr as *const MaskedHeader<I, O>)).mask
+}
+
+// implementation for the function pointer for a given callback type F.
+unsafe fn fn_ptr_impl<I, O, F: FnOnce(I) -> O>(run: Option<&mut IoSlot<I, O>>, ptr: *mut u8, free: bool) {
+    // extract callback value from heap allocation and free heap allocation,
+    // or leave it freed
+    let (_, callback_offset) = layout::<I, O, F>();
+    let f = (ptr.add(callback_offset) as *mut F).read();
+    if free {
+        free_impl::<I, O, F>(ptr);
+    } else {
+        (ptr as *mut Freed).write(Freed {
+            free_fn: free_impl::<I, O, F>,
+            next: ptr::null_mut(),
+        });
+    }
+
+    // run
+    if let Some(io_slot) = run {
+        #[cfg(feature = "watchdog")]
+        crate::watchdog::note_callback(core::any::type_name::<F>());
+        io_slot.output = ManuallyDrop::new(f(ManuallyDrop::take(&mut io_slot.input)));
+    }
+}
+
+// deallocate the heap allocation for a given callback type F, after the
+// callback has been moved out.
+unsafe fn free_impl<I, O, F: FnOnce(I) -> O>(ptr: *mut u8) {
+    let (layout, _) = layout::<I, O, F>();
+    dealloc(ptr, layout);
+    #[cfg(any(test, feature = "test-util"))]
+    crate::test_util::on_dealloc();
+    #[cfg(feature = "alloc-stats")]
+    crate::alloc_stats::on_dealloc(layout.size());
+}
+
+}
+
+// run the pointed to callback with the given input, including freeing the heap allocation. the
+// pointer must be non-null.
+pub(crate) unsafe fn call_raw<I, O>(ptr: *mut u8, input: I) -> O {
+    let fn_ptr = (ptr as *mut FnPtrType<I, O>).read();
+    let mut io_slot = IoSlot { input: ManuallyDrop::new(input) };
+    fn_ptr(Some(&mut io_slot), ptr, true);
+    ManuallyDrop::into_inner(io_slot.output)
+}
+
+cfg_alloc! {
+
+// run the pointed to callback with the given input, leaving the heap
+// allocation as a freed callback, which `on_freed` is called with even if the
+// callback panics. static callbacks are just run. the pointer must be
+// non-null.
+pub(crate) unsafe fn call_raw_keep_alloc<I, O>(ptr: *mut u8, input: I, on_freed: impl FnOnce(*mut u8)) -> O {
+    if size_raw::<I, O>(ptr) == 0 {
+        return call_raw(ptr, input);
+    }
+
+    struct OnFreed<G: FnOnce(*mut u8)>(Option<G>, *mut u8);
+
+    impl<G: FnOnce(*mut u8)> Drop for OnFreed<G> {
+        fn drop(&mut self) {
+            (self.0.take().unwrap())(self.1);
+        }
+    }
+
+    let fn_ptr = (ptr as *mut FnPtrType<I, O>).read();
+    let mut io_slot = IoSlot { input: ManuallyDrop::new(input) };
+    let on_freed = OnFreed(Some(on_freed), ptr);
+    fn_ptr(Some(&mut io_slot), ptr, false);
+    drop(on_freed);
+    ManuallyDrop::into_inner(io_slot.output)
+}
+
+// the next pointer of the pointed to freed callback.
+pub(crate) unsafe fn freed_next(ptr: *mut u8) -> *mut u8 {
+    (*(ptr as *mut Freed)).next
+}
+
+// set the next pointer of the pointed to freed callback.
+pub(crate) unsafe fn set_freed_next(ptr: *mut u8, next: *mut u8) {
+    (*(ptr as *mut Freed)).next = next;
+}
+
+// deallocate the pointed to freed callback.
+pub(crate) unsafe fn dealloc_freed(ptr: *mut u8) {
+    ((*(ptr as *mut Freed)).free_fn)(ptr);
+}
+
+// move the callback back out of the heap allocation, freeing it without running the callback. the
+// pointer must be non-null, and point to a callback of type F.
+pub(crate) unsafe fn into_callback<I, O, F: FnOnce(I) -> O>(ptr: *mut u8) -> F {
+    let (layout, callback_offset) = layout::<I, O, F>();
+    let f = (ptr.add(callback_offset) as *mut F).read();
+    dealloc(ptr, layout);
+    #[cfg(any(test, feature = "test-util"))]
+    crate::test_util::on_dealloc();
+    #[cfg(feature = "alloc-stats")]
+    crate::alloc_stats::on_dealloc(layout.size());
+    f
+}
+
+// allocate a copy of the callback, cloned by reference, leaving the original in
+// place. the pointer must be non-null, and point to a callback of type F, which
+// isn't accessed elsewhere meanwhile. the returned pointer is never null.
+pub(crate) unsafe fn clone_raw<I, O, F: FnOnce(I) -> O + Clone>(ptr: *const u8) -> *mut u8 {
+    let (_, callback_offset) = layout::<I, O, F>();
+    alloc_raw((*(ptr.add(callback_offset) as *const F)).clone())
+}
+
+}
+
+// size of the pointed to heap allocation, in bytes, or 0 if the pointer is null
+// or to a static callback.
+pub(crate) unsafe fn size_raw<I, O>(ptr: *mut u8) -> usize {
+    if !ptr.is_null() {
+        (*(ptr as *const Header<I, O>)).size & !LABELED
+    } else {
+        0
+    }
+}
+
+// drop the pointed to data, including freeing the heap allocation, without running the callback,
+// if the pointer is non-null.
+pub(crate) unsafe fn drop_raw<I, O>(ptr: *mut u8) {
+    if !ptr.is_null() {
+        let fn_ptr = (ptr as *mut FnPtrType<I, O>).read();
+        fn_ptr(None, ptr, true);
+    }
+}
+
+// an owned non-null erased callback, which is dropped if not run. public, as
+// `RawCallbackArgs`.
+#[cfg(feature = "alloc")]
+pub(crate) type Owned<I, O> = crate::raw_callback::RawCallbackArgs<I, O>;
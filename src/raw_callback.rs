@@ -0,0 +1,176 @@
+
+use crate::raw;
+use core::{
+    ptr::NonNull,
+    mem::ManuallyDrop,
+    marker::PhantomData,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+
+// internals
+// ---------
+//
+// an owned, non-null erased callback, as described in the `raw` module. this is
+// the building block every cell stores its callbacks with, exposed for custom
+// containers. every operation which gives up the callback consumes the value,
+// and the destructor drops the callback without running it, so each callback
+// is run or dropped exactly once unless it's leaked with `into_raw`.
+
+/// An owned, type-erased `FnOnce(I) -> O + Send` callback in a single heap
+/// allocation, for building custom containers of callbacks.
+///
+/// This is what the crate's cells hold: [`new`][Self::new] makes one heap
+/// allocation holding the callback behind a small header, and the value is
+/// one pointer wide. [`call`][Self::call] runs the callback, and dropping
+/// the value drops the callback without running it, both freeing the
+/// allocation. Both consume the value, so a callback can't be run twice.
+///
+/// For storing a callback in a container of your own, such as an
+/// `AtomicPtr` in an intrusive structure, [`into_raw`][Self::into_raw]
+/// gives up the value for a pointer, and [`from_raw`][Self::from_raw] takes
+/// it back. The pointer is aligned to at least 2, so its low bit is always
+/// clear, and free for tagging.
+pub struct RawCallbackArgs<I, O> {
+    ptr: NonNull<u8>,
+    _p: PhantomData<fn(I) -> O>,
+}
+
+// safety: erased callbacks are Send, and a shared reference to one can't be
+//         used to reach the callback.
+unsafe impl<I, O> Send for RawCallbackArgs<I, O> {}
+unsafe impl<I, O> Sync for RawCallbackArgs<I, O> {}
+
+// a shared reference can't be used to reach the callback, and running it
+// consumes the value.
+impl<I, O> UnwindSafe for RawCallbackArgs<I, O> {}
+impl<I, O> RefUnwindSafe for RawCallbackArgs<I, O> {}
+
+impl<I, O> RawCallbackArgs<I, O> {
+    /// Erase a callback.
+    ///
+    /// Makes only one heap allocation.
+    pub fn new<F: FnOnce(I) -> O + Send + 'static>(f: F) -> Self {
+        unsafe { Self::from_erased(raw::alloc_raw(f)) }
+    }
+
+    // take ownership of the pointed to callback. the pointer must be non-null.
+    pub(crate) unsafe fn from_erased(ptr: *mut u8) -> Self {
+        RawCallbackArgs { ptr: NonNull::new_unchecked(ptr), _p: PhantomData }
+    }
+
+    // the pointed to callback, still owned.
+    pub(crate) fn as_erased(&self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    /// Run the callback with the given input, freeing its allocation.
+    ///
+    /// If the callback panics, its allocation is still freed, and the
+    /// panic propagates.
+    pub fn call(self, input: I) -> O {
+        let this = ManuallyDrop::new(self);
+        unsafe { raw::call_raw(this.ptr.as_ptr(), input) }
+    }
+
+    /// Give up the callback for a pointer to it, to store in a container of
+    /// your own.
+    ///
+    /// The callback is leaked unless the pointer is passed to
+    /// [`from_raw`][Self::from_raw]. The pointer is aligned to at least 2.
+    pub fn into_raw(self) -> NonNull<()> {
+        ManuallyDrop::new(self).ptr.cast()
+    }
+
+    /// Take back the callback behind a pointer from
+    /// [`into_raw`][Self::into_raw].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have come from `into_raw` on a `RawCallbackArgs<I, O>`
+    /// with the same `I` and `O`, and must not have been passed to
+    /// `from_raw` since. In particular, taking the same pointer back twice,
+    /// such as by reading it out of an atomic without swapping it out, would
+    /// run or drop the callback twice.
+    ///
+    /// Pointers from a [`RawCallback`] may be taken back as a
+    /// `RawCallbackArgs<(), ()>`, and vice versa.
+    pub unsafe fn from_raw(ptr: NonNull<()>) -> Self {
+        RawCallbackArgs { ptr: ptr.cast(), _p: PhantomData }
+    }
+}
+
+impl<I, O> Drop for RawCallbackArgs<I, O> {
+    fn drop(&mut self) {
+        unsafe { raw::drop_raw::<I, O>(self.ptr.as_ptr()) };
+    }
+}
+
+impl<I, O> Debug for RawCallbackArgs<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("RawCallbackArgs").field(&self.ptr).finish()
+    }
+}
+
+/// An owned, type-erased `FnOnce() + Send` callback in a single heap
+/// allocation, for building custom containers of callbacks.
+///
+/// The same as a [`RawCallbackArgs<(), ()>`], with the input and output
+/// elided. See there for details.
+pub struct RawCallback(RawCallbackArgs<(), ()>);
+
+impl RawCallback {
+    /// Erase a callback.
+    ///
+    /// Makes only one heap allocation.
+    pub fn new<F: FnOnce() + Send + 'static>(f: F) -> Self {
+        RawCallback(RawCallbackArgs::new(move |()| f()))
+    }
+
+    /// Run the callback, freeing its allocation.
+    ///
+    /// If the callback panics, its allocation is still freed, and the
+    /// panic propagates.
+    pub fn call(self) {
+        self.0.call(())
+    }
+
+    /// Give up the callback for a pointer to it, to store in a container of
+    /// your own.
+    ///
+    /// The callback is leaked unless the pointer is passed to
+    /// [`from_raw`][Self::from_raw]. The pointer is aligned to at least 2.
+    pub fn into_raw(self) -> NonNull<()> {
+        self.0.into_raw()
+    }
+
+    /// Take back the callback behind a pointer from
+    /// [`into_raw`][Self::into_raw].
+    ///
+    /// # Safety
+    ///
+    /// As for [`RawCallbackArgs::from_raw`]: `ptr` must have come from
+    /// `into_raw` on a `RawCallback`, or a `RawCallbackArgs<(), ()>`, and
+    /// must not have been passed to `from_raw` since.
+    pub unsafe fn from_raw(ptr: NonNull<()>) -> Self {
+        RawCallback(RawCallbackArgs::from_raw(ptr))
+    }
+}
+
+impl From<RawCallback> for RawCallbackArgs<(), ()> {
+    fn from(callback: RawCallback) -> Self {
+        callback.0
+    }
+}
+
+impl From<RawCallbackArgs<(), ()>> for RawCallback {
+    fn from(callback: RawCallbackArgs<(), ()>) -> Self {
+        RawCallback(callback)
+    }
+}
+
+impl Debug for RawCallback {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("RawCallback").field(&self.0.ptr).finish()
+    }
+}
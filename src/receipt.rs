@@ -0,0 +1,180 @@
+
+use crate::{
+    sync::{AtomicUsize, LoadMut},
+    WakerCell,
+};
+use core::{
+    sync::atomic::Ordering,
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    fmt::{self, Formatter, Debug, Display},
+};
+use alloc::sync::Arc;
+
+// internals
+// ---------
+//
+// the putter's receipt and the callback wrapper share a heap allocation with
+// a state, an output slot, and a waker. the callback wrapper, when run,
+// writes the output and then sets the state to READY. when dropped without
+// running, it sets the state to DISCARDED. either way, it then wakes the
+// receipt's waker. once the receipt has taken the result, it sets the state
+// to TAKEN.
+
+const PENDING: usize = 0;
+const READY: usize = 1;
+const DISCARDED: usize = 2;
+const TAKEN: usize = 3;
+
+struct Shared<O> {
+    state: AtomicUsize,
+    output: UnsafeCell<MaybeUninit<O>>,
+    waker: WakerCell,
+}
+
+// safety: the output is written once by the sender before it sets the state
+//         to READY, and read once by the receipt after it observes READY.
+unsafe impl<O: Send> Send for Shared<O> {}
+unsafe impl<O: Send> Sync for Shared<O> {}
+
+impl<O> Drop for Shared<O> {
+    fn drop(&mut self) {
+        if self.state.load_mut() == READY {
+            unsafe { self.output.get_mut().assume_init_drop() };
+        }
+    }
+}
+
+// sending half, owned by the callback wrapper.
+pub(crate) struct Sender<O> {
+    shared: Arc<Shared<O>>,
+    sent: bool,
+}
+
+impl<O> Sender<O> {
+    pub(crate) fn send(mut self, output: O) {
+        unsafe { (*self.shared.output.get()).write(output) };
+        self.shared.state.store(READY, Ordering::Release);
+        self.shared.waker.wake();
+        self.sent = true;
+    }
+}
+
+impl<O> Drop for Sender<O> {
+    fn drop(&mut self) {
+        if !self.sent {
+            self.shared.state.store(DISCARDED, Ordering::Release);
+            self.shared.waker.wake();
+        }
+    }
+}
+
+pub(crate) fn receipt<O>() -> (Sender<O>, PutReceipt<O>) {
+    let shared = Arc::new(Shared {
+        state: AtomicUsize::new(PENDING),
+        output: UnsafeCell::new(MaybeUninit::uninit()),
+        waker: WakerCell::new(),
+    });
+    (
+        Sender { shared: Arc::clone(&shared), sent: false },
+        PutReceipt { shared },
+    )
+}
+
+/// Receipt for a callback put with
+/// [`CallbackCellArgs::put_with_receipt`][crate::CallbackCellArgs::put_with_receipt],
+/// which resolves with the callback's output once it runs.
+///
+/// If the callback is dropped without running, because it is replaced, the
+/// cell is cleared, or the cell is dropped, the receipt resolves with
+/// [`Discarded`].
+///
+/// This is a future, and also supports blocking with [`wait`][Self::wait] and
+/// polling with [`try_get`][Self::try_get]. Once the result has been
+/// obtained, the receipt is spent, and trying to obtain it again panics.
+#[must_use = "the receipt is the only way to get the callback's output"]
+pub struct PutReceipt<O> {
+    shared: Arc<Shared<O>>,
+}
+
+/// Error for a [`PutReceipt`] whose callback was dropped without running.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Discarded;
+
+impl Display for Discarded {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("callback was dropped without running")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Discarded {}
+
+impl<O> PutReceipt<O> {
+    /// Get the result without blocking, if the callback has run or been
+    /// discarded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the result has already been obtained.
+    pub fn try_get(&mut self) -> Option<Result<O, Discarded>> {
+        match self.shared.state.load(Ordering::Acquire) {
+            PENDING => None,
+            READY => {
+                self.shared.state.store(TAKEN, Ordering::Relaxed);
+                Some(Ok(unsafe { (*self.shared.output.get()).assume_init_read() }))
+            }
+            DISCARDED => {
+                self.shared.state.store(TAKEN, Ordering::Relaxed);
+                Some(Err(Discarded))
+            }
+            _ => panic!("PutReceipt result already obtained"),
+        }
+    }
+
+    /// Block until the callback has run or been discarded.
+    #[cfg(feature = "std")]
+    pub fn wait(mut self) -> Result<O, Discarded> {
+        let waker = crate::waiters::thread_waker();
+        loop {
+            if let Some(result) = self.try_get() {
+                return result;
+            }
+            self.shared.waker.register(&waker);
+            if let Some(result) = self.try_get() {
+                return result;
+            }
+            std::thread::park();
+        }
+    }
+}
+
+impl<O> Future for PutReceipt<O> {
+    type Output = Result<O, Discarded>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<O, Discarded>> {
+        if let Some(result) = self.try_get() {
+            return Poll::Ready(result);
+        }
+        self.shared.waker.register(cx.waker());
+        match self.try_get() {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<O> Debug for PutReceipt<O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let state = match self.shared.state.load(Ordering::Relaxed) {
+            PENDING => "PENDING",
+            READY => "READY",
+            DISCARDED => "DISCARDED",
+            _ => "TAKEN",
+        };
+        write!(f, "PutReceipt({})", state)
+    }
+}
@@ -0,0 +1,102 @@
+
+use crate::CallbackCellArgs;
+use core::fmt::{self, Formatter, Debug};
+
+// internals
+// ---------
+//
+// the pair of callbacks is stored as one callback taking a `Result`, so both
+// share one heap allocation, and taking it consumes both at once.
+
+/// A cell holding a pair of callbacks, one for success and one for failure,
+/// of which exactly one runs.
+///
+/// Resolving the cell atomically takes both callbacks, runs the one matching
+/// the outcome, and drops the other. If the pair is replaced, cleared, or the
+/// cell is dropped, both are dropped without running.
+pub struct ResultCallbackCell<T, E>(CallbackCellArgs<Result<T, E>, ()>);
+
+impl<T, E> ResultCallbackCell<T, E> {
+    const_fn! {
+        /// Construct with no callbacks.
+        ///
+        /// This is a `const fn`, so cells can be placed in statics.
+        pub fn new() -> Self {
+            ResultCallbackCell(CallbackCellArgs::new())
+        }
+    }
+
+    /// Atomically set the pair of callbacks.
+    ///
+    /// Makes only one heap allocation, holding both callbacks. Any pair
+    /// previously present is dropped.
+    pub fn put<F, G>(&self, on_ok: F, on_err: G)
+    where
+        F: FnOnce(T) + Send + 'static,
+        G: FnOnce(E) + Send + 'static,
+    {
+        self.0.put(move |result| match result {
+            Ok(value) => on_ok(value),
+            Err(error) => on_err(error),
+        });
+    }
+
+    /// Atomically take the pair of callbacks, run the success callback with
+    /// the value, and drop the failure callback.
+    ///
+    /// If no callbacks were present, returns the original value.
+    pub fn resolve_ok(&self, value: T) -> Result<(), T> {
+        self.resolve(Ok(value)).map_err(|result| match result {
+            Ok(value) => value,
+            Err(_) => unreachable!(),
+        })
+    }
+
+    /// Atomically take the pair of callbacks, run the failure callback with
+    /// the error, and drop the success callback.
+    ///
+    /// If no callbacks were present, returns the original error.
+    pub fn resolve_err(&self, error: E) -> Result<(), E> {
+        self.resolve(Err(error)).map_err(|result| match result {
+            Err(error) => error,
+            Ok(_) => unreachable!(),
+        })
+    }
+
+    /// Atomically take the pair of callbacks, run the one matching the
+    /// result, and drop the other.
+    ///
+    /// If no callbacks were present, returns the original result.
+    pub fn resolve(&self, result: Result<T, E>) -> Result<(), Result<T, E>> {
+        self.0.take_call(result)
+    }
+
+    /// Whether a pair of callbacks is currently present.
+    pub fn is_set(&self) -> bool {
+        self.0.is_set()
+    }
+
+    /// Atomically take the pair of callbacks and drop both without running
+    /// either.
+    ///
+    /// Returns true if callbacks were present.
+    pub fn clear(&self) -> bool {
+        self.0.clear()
+    }
+}
+
+impl<T, E> Default for ResultCallbackCell<T, E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, E> Debug for ResultCallbackCell<T, E> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.0.is_set() {
+            f.write_str("ResultCallbackCell(NOT NULL)")
+        } else {
+            f.write_str("ResultCallbackCell(NULL)")
+        }
+    }
+}
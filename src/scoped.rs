@@ -0,0 +1,124 @@
+//! Handlers scoped to a closure, found by the type of their input.
+//!
+//! Like a panic hook or a `tracing` subscriber, but lexically scoped and per
+//! thread: [`with`] registers a handler for inputs of some type `T` while it
+//! runs a closure, and [`invoke`], from anywhere within that closure on the
+//! same thread, however deeply nested, runs the innermost handler registered
+//! for `T`. So library code can report to whatever is listening, such as
+//! progress to the active UI, without a handle threaded through every call.
+//!
+//! Handlers are `Fn`, and may be invoked any number of times while their
+//! scope lasts. Since they never leave their thread or outlive their scope,
+//! they need not be `Send` or `'static`, and may borrow from the caller.
+//! Inner registrations for a type shadow outer ones, until they end. A
+//! registration ends when its closure returns or unwinds.
+
+use alloc::vec::Vec;
+use core::{
+    any::TypeId,
+    cell::RefCell,
+    mem::ManuallyDrop,
+};
+
+// internals
+// ---------
+//
+// each thread has a stack of the handlers registered on it, innermost last,
+// each as the `TypeId` of its input, a pointer to the handler, which lives in
+// the frame of the `with` which registered it, and a function which casts the
+// pointer back to the handler's type and calls it with the input, read from a
+// pointer to it. `with` pushes its handler, then pops it, and any entries above
+// it, even on unwind, before its frame ends, so every pointer on the stack is
+// to a live handler.
+//
+// the stack isn't borrowed while a handler runs, so handlers may register more
+// handlers and invoke them. while a handler runs, its entry is marked busy, and
+// `invoke` skips busy entries, so a handler invoking its own input type reaches
+// the next handler out, rather than itself. entries below a running handler
+// stay put until it returns, so it's unmarked by index.
+
+struct Entry {
+    type_id: TypeId,
+    handler: *const (),
+    call: unsafe fn(*const (), *mut ()),
+    busy: bool,
+}
+
+std::thread_local! {
+    static STACK: RefCell<Vec<Entry>> = const { RefCell::new(Vec::new()) };
+}
+
+// implementation for the call function for a given handler type H, taking T.
+unsafe fn call_impl<T, H: Fn(T)>(handler: *const (), input: *mut ()) {
+    (*(handler as *const H))(input.cast::<T>().read())
+}
+
+// pops the entries at and above `depth` when dropped.
+struct Pop(usize);
+
+impl Drop for Pop {
+    fn drop(&mut self) {
+        STACK.with(|stack| stack.borrow_mut().truncate(self.0));
+    }
+}
+
+// unmarks the busy entry at the index when dropped.
+struct Unbusy(usize);
+
+impl Drop for Unbusy {
+    fn drop(&mut self) {
+        STACK.with(|stack| stack.borrow_mut()[self.0].busy = false);
+    }
+}
+
+/// Run `f`, with `handler` registered on this thread for inputs of type `T`
+/// until it returns.
+///
+/// Within `f`, [`invoke`] with a `T` runs `handler`, unless a handler for
+/// `T` registered within `f` shadows it. The registration ends when `f`
+/// returns, or if it panics.
+pub fn with<T: 'static, H: Fn(T), R>(handler: H, f: impl FnOnce() -> R) -> R {
+    let depth = STACK.with(|stack| {
+        let mut stack = stack.borrow_mut();
+        stack.push(Entry {
+            type_id: TypeId::of::<T>(),
+            handler: &handler as *const H as *const (),
+            call: call_impl::<T, H>,
+            busy: false,
+        });
+        stack.len() - 1
+    });
+    let _pop = Pop(depth);
+    f()
+}
+
+/// Run the innermost handler registered on this thread for inputs of type
+/// `T` with the given input.
+///
+/// Returns the input if no handler for `T` is registered. A handler which
+/// invokes its own input type, while it runs, reaches the next handler for
+/// it out, if any.
+pub fn invoke<T: 'static>(input: T) -> Result<(), T> {
+    let found = STACK.with(|stack| {
+        let mut stack = stack.borrow_mut();
+        let i = stack.iter().rposition(|entry| entry.type_id == TypeId::of::<T>() && !entry.busy)?;
+        stack[i].busy = true;
+        Some((i, stack[i].handler, stack[i].call))
+    });
+    let Some((i, handler, call)) = found else {
+        return Err(input);
+    };
+    let _unbusy = Unbusy(i);
+    let mut input = ManuallyDrop::new(input);
+    // safety: the handler is live until its entry is popped, which happens
+    //         after it returns, and takes a `T`, which this gives up.
+    unsafe { call(handler, &mut *input as *mut T as *mut ()) };
+    Ok(())
+}
+
+/// Whether [`invoke`] with a `T` would find a handler, on this thread, here.
+pub fn is_registered<T: 'static>() -> bool {
+    STACK.with(|stack| {
+        stack.borrow().iter().any(|entry| entry.type_id == TypeId::of::<T>() && !entry.busy)
+    })
+}
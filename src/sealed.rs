@@ -0,0 +1,215 @@
+
+use crate::{
+    sync::{AtomicPtr, LoadMut},
+    raw,
+};
+use core::{
+    sync::atomic::Ordering,
+    ptr,
+    marker::PhantomData,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug, Display},
+};
+
+// internals
+// ---------
+//
+// the inner atomic pointer is a nullable pointer to an erased callback, as
+// described in the `raw` module, tagged in its low bit with whether the cell is
+// sealed. every update is a compare-exchange which keeps the tag, so a put
+// which races with sealing either lands before the seal, and can be drained, or
+// sees the tag and hands the callback back.
+
+pub(crate) const SEALED: usize = 1;
+
+pub(crate) fn is_tagged<T>(ptr: *mut T) -> bool {
+    ptr.addr() & SEALED != 0
+}
+
+pub(crate) fn untagged<T>(ptr: *mut T) -> *mut T {
+    ptr.map_addr(|addr| addr & !SEALED)
+}
+
+// the null pointer with the same tag as `ptr`.
+pub(crate) fn empty_like<T>(ptr: *mut T) -> *mut T {
+    ptr::null_mut::<T>().map_addr(|_| ptr.addr() & SEALED)
+}
+
+/// Error returned when putting into a sealed cell or queue, holding what
+/// was rejected.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Sealed<T>(pub T);
+
+impl<T> Sealed<T> {
+    /// The rejected value.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Debug for Sealed<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("Sealed(..)")
+    }
+}
+
+impl<T> Display for Sealed<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("sealed")
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> std::error::Error for Sealed<T> {}
+
+/// A [`CallbackCellArgs`][crate::CallbackCellArgs] which can be sealed to
+/// reject any further callbacks.
+///
+/// This is for shutdown: after [`seal`][Self::seal], puts hand the callback
+/// back rather than storing it, while [`take_call`][Self::take_call] still
+/// drains one put before the seal. So no callback can slip in after the final
+/// drain. Sealing is permanent.
+pub struct SealableCallbackCellArgs<I, O> {
+    ptr: AtomicPtr<u8>,
+    // invariant in `I` and `O`, like `CallbackCellArgs`.
+    _p: PhantomData<dyn FnOnce(I) -> O + Send + 'static>,
+}
+
+// safety: as for `CallbackCellArgs`.
+unsafe impl<I, O> Send for SealableCallbackCellArgs<I, O> {}
+unsafe impl<I, O> Sync for SealableCallbackCellArgs<I, O> {}
+impl<I, O> UnwindSafe for SealableCallbackCellArgs<I, O> {}
+impl<I, O> RefUnwindSafe for SealableCallbackCellArgs<I, O> {}
+
+impl<I, O> SealableCallbackCellArgs<I, O> {
+    const_fn! {
+        /// Construct unsealed, with no callback.
+        ///
+        /// This is a `const fn`, so cells can be placed in statics.
+        pub fn new() -> Self {
+            SealableCallbackCellArgs {
+                ptr: AtomicPtr::new(ptr::null_mut()),
+                _p: PhantomData,
+            }
+        }
+    }
+
+    /// Atomically set the callback, unless the cell is sealed.
+    ///
+    /// Any callback previously present is dropped. If the cell is sealed,
+    /// returns the callback instead, without storing it.
+    pub fn put<F: FnOnce(I) -> O + Send + 'static>(&self, f: F) -> Result<(), Sealed<F>> {
+        let mut old_ptr = self.ptr.load(Ordering::Relaxed);
+        if is_tagged(old_ptr) {
+            return Err(Sealed(f));
+        }
+        let ptr = raw::alloc_raw(f);
+        loop {
+            if is_tagged(old_ptr) {
+                // sealed since the check above
+                return Err(Sealed(unsafe { raw::into_callback::<I, O, F>(ptr) }));
+            }
+            match self.ptr.compare_exchange_weak(old_ptr, ptr, Ordering::AcqRel, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(new_ptr) => old_ptr = new_ptr,
+            }
+        }
+        unsafe { raw::drop_raw::<I, O>(old_ptr) };
+        Ok(())
+    }
+
+    // atomically take the callback, keeping the tag.
+    fn take(&self) -> *mut u8 {
+        let mut old_ptr = self.ptr.load(Ordering::Relaxed);
+        loop {
+            match self.ptr.compare_exchange_weak(
+                old_ptr, empty_like(old_ptr), Ordering::Acquire, Ordering::Relaxed,
+            ) {
+                Ok(_) => return untagged(old_ptr),
+                Err(new_ptr) => old_ptr = new_ptr,
+            }
+        }
+    }
+
+    /// Atomically take the callback then run it with the given input.
+    ///
+    /// This works whether or not the cell is sealed. Returns the output if a
+    /// callback was present. If a callback was not present, returns the
+    /// original input.
+    pub fn take_call(&self, input: I) -> Result<O, I> {
+        let ptr = self.take();
+        if !ptr.is_null() {
+            Ok(unsafe { raw::call_raw(ptr, input) })
+        } else {
+            Err(input)
+        }
+    }
+
+    /// Atomically take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        let ptr = self.take();
+        unsafe { raw::drop_raw::<I, O>(ptr) };
+        !ptr.is_null()
+    }
+
+    /// Whether a callback is currently present.
+    ///
+    /// This is only a snapshot: another thread may put or take a callback
+    /// immediately afterwards.
+    pub fn is_set(&self) -> bool {
+        !untagged(self.ptr.load(Ordering::Acquire)).is_null()
+    }
+
+    /// Atomically seal the cell, so that every later put is rejected.
+    ///
+    /// Any callback already present stays, to be drained with
+    /// [`take_call`][Self::take_call]. Returns false if the cell was already
+    /// sealed.
+    pub fn seal(&self) -> bool {
+        let mut old_ptr = self.ptr.load(Ordering::Relaxed);
+        loop {
+            if is_tagged(old_ptr) {
+                return false;
+            }
+            match self.ptr.compare_exchange_weak(
+                old_ptr, old_ptr.map_addr(|addr| addr | SEALED), Ordering::AcqRel, Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(new_ptr) => old_ptr = new_ptr,
+            }
+        }
+    }
+
+    /// Whether the cell has been sealed.
+    pub fn is_sealed(&self) -> bool {
+        is_tagged(self.ptr.load(Ordering::Acquire))
+    }
+}
+
+impl<I, O> Drop for SealableCallbackCellArgs<I, O> {
+    fn drop(&mut self) {
+        unsafe {
+            raw::drop_raw::<I, O>(untagged(self.ptr.load_mut()));
+        }
+    }
+}
+
+impl<I, O> Default for SealableCallbackCellArgs<I, O> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I, O> Debug for SealableCallbackCellArgs<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let ptr = self.ptr.load(Ordering::Relaxed);
+        let set = if untagged(ptr).is_null() { "NULL" } else { "NOT NULL" };
+        if is_tagged(ptr) {
+            write!(f, "SealableCallbackCellArgs({}, SEALED)", set)
+        } else {
+            write!(f, "SealableCallbackCellArgs({})", set)
+        }
+    }
+}
@@ -0,0 +1,375 @@
+
+use crate::{
+    sync::{self, AtomicBool, AtomicUsize, AtomicPtr},
+    raw,
+};
+use alloc::{
+    boxed::Box,
+    vec::Vec,
+};
+use core::{
+    sync::atomic::Ordering,
+    ptr,
+    cell::UnsafeCell,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+use std::thread;
+
+// internals
+// ---------
+//
+// a fixed number of shards, each a vec of owned erased callbacks, described in
+// the `raw` module, on its own cache line, and owned by at most one thread,
+// identified by a token unique to that thread for the life of the process. a
+// pushing thread looks for its shard, starting from one picked by its token,
+// claiming the first unowned shard it comes to if it has none. once claimed, a
+// shard's vec is only touched by its owner, until `drain`, which has exclusive
+// access, runs every shard's callbacks and releases every claim. so a push
+// only loads the owners of the shards it probes, which are written once per
+// frame, and writes its own shard.
+//
+// a thread which finds every shard owned by others pushes to the overflow
+// shard instead, guarded by a spin lock.
+//
+// `ShardedCallbackCell` is a fixed array of `CallbackCell`-like nullable
+// pointers to erased callbacks, each on its own cache line. a put swaps into
+// the shard picked by its thread's token, so threads with different tokens
+// modulo the number of shards never write the same line. takes load each shard
+// before swapping it, so scanning empty shards doesn't take their lines
+// exclusively.
+
+// the calling thread's token. never zero, which marks an unowned shard.
+fn thread_token() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(1);
+    std::thread_local! {
+        static TOKEN: usize = NEXT.fetch_add(1, Ordering::Relaxed);
+    }
+    TOKEN.with(|token| *token)
+}
+
+#[repr(align(128))]
+struct Shard {
+    owner: AtomicUsize,
+    callbacks: UnsafeCell<Vec<raw::Owned<(), ()>>>,
+}
+
+/// A buffer of deferred callbacks, recorded from many threads in parallel
+/// and run later on one.
+///
+/// For when even a lock-free queue's shared tail is contended, such as
+/// worker threads recording deferred actions during a frame: each thread
+/// [`push`][Self::push]es into a shard of its own, without synchronizing
+/// with other threads, then [`drain`][Self::drain], given exclusive access,
+/// runs everything once the workers are done. Shards keep their allocations
+/// across drains.
+///
+/// Each callback makes one heap allocation. Threads claim a shard on their
+/// first push after a drain, and keep it until the next drain. If there are
+/// more pushing threads than shards, the rest share an overflow shard
+/// guarded by a spin lock.
+pub struct ShardedCallbackBuffer {
+    shards: Box<[Shard]>,
+    overflow_locked: AtomicBool,
+    overflow: UnsafeCell<Vec<raw::Owned<(), ()>>>,
+}
+
+// safety: each shard's callbacks are only accessed by the thread which owns
+//         it, or with exclusive access, the overflow shard's only while holding
+//         the lock, and callbacks are Send.
+unsafe impl Send for ShardedCallbackBuffer {}
+unsafe impl Sync for ShardedCallbackBuffer {}
+
+// a push only appends one callback, and a drain takes each callback out of its
+// shard before running it, so a panic leaves the buffer consistent.
+impl UnwindSafe for ShardedCallbackBuffer {}
+impl RefUnwindSafe for ShardedCallbackBuffer {}
+
+impl ShardedCallbackBuffer {
+    /// Construct with one shard for each thread the machine can run in
+    /// parallel, as reported by [`std::thread::available_parallelism`].
+    pub fn new() -> Self {
+        Self::with_shards(thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+
+    /// Construct with the given number of shards, for as many threads as
+    /// will push between drains.
+    pub fn with_shards(n: usize) -> Self {
+        ShardedCallbackBuffer {
+            shards: (0..n)
+                .map(|_| Shard {
+                    owner: AtomicUsize::new(0),
+                    callbacks: UnsafeCell::new(Vec::new()),
+                })
+                .collect(),
+            overflow_locked: AtomicBool::new(false),
+            overflow: UnsafeCell::new(Vec::new()),
+        }
+    }
+
+    /// The number of shards, not counting the overflow shard.
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Record a callback, to run on the next [`drain`][Self::drain].
+    ///
+    /// Makes one heap allocation, plus one to grow this thread's shard if
+    /// it's full.
+    pub fn push<F: FnOnce() + Send + 'static>(&self, f: F) {
+        let callback = unsafe { raw::Owned::from_erased(raw::alloc_raw(move |()| f())) };
+        let token = thread_token();
+        let n = self.shards.len();
+        for i in 0..n {
+            let shard = &self.shards[token.wrapping_add(i) % n];
+            let owner = shard.owner.load(Ordering::Relaxed);
+            let owned = owner == token || (owner == 0 && shard.owner
+                .compare_exchange(0, token, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok());
+            if owned {
+                // safety: this thread owns the shard.
+                unsafe { (*shard.callbacks.get()).push(callback) };
+                return;
+            }
+        }
+        while self.overflow_locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            sync::spin_loop();
+        }
+        unsafe { (*self.overflow.get()).push(callback) };
+        self.overflow_locked.store(false, Ordering::Release);
+    }
+
+    /// Run every recorded callback, then release every thread's shard.
+    ///
+    /// Runs the callbacks shard by shard, then those in the overflow shard.
+    /// So the callbacks pushed by one thread run in the order it pushed
+    /// them, but there's no order between threads. Returns the number of
+    /// callbacks run.
+    ///
+    /// If a callback panics, the rest of its shard's callbacks are dropped
+    /// without running, and those of later shards are left for the next
+    /// drain.
+    pub fn drain(&mut self) -> usize {
+        let mut ran = 0;
+        let shards = self.shards.iter_mut()
+            .map(|shard| {
+                *shard.owner.get_mut() = 0;
+                shard.callbacks.get_mut()
+            })
+            .chain([self.overflow.get_mut()]);
+        for callbacks in shards {
+            for callback in callbacks.drain(..) {
+                callback.call(());
+                ran += 1;
+            }
+        }
+        ran
+    }
+
+    /// The number of callbacks recorded.
+    pub fn len(&mut self) -> usize {
+        self.shards.iter_mut()
+            .map(|shard| shard.callbacks.get_mut().len())
+            .sum::<usize>()
+            + self.overflow.get_mut().len()
+    }
+
+    /// Whether no callbacks are recorded.
+    pub fn is_empty(&mut self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop every recorded callback without running it, then release every
+    /// thread's shard.
+    ///
+    /// Returns the number of callbacks dropped.
+    pub fn clear(&mut self) -> usize {
+        let mut dropped = 0;
+        let shards = self.shards.iter_mut()
+            .map(|shard| {
+                *shard.owner.get_mut() = 0;
+                shard.callbacks.get_mut()
+            })
+            .chain([self.overflow.get_mut()]);
+        for callbacks in shards {
+            dropped += callbacks.len();
+            callbacks.clear();
+        }
+        dropped
+    }
+}
+
+impl Default for ShardedCallbackBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for ShardedCallbackBuffer {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "ShardedCallbackBuffer({} SHARDS)", self.shards())
+    }
+}
+
+#[repr(align(128))]
+struct CellShard(AtomicPtr<u8>);
+
+impl CellShard {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: CellShard = CellShard(AtomicPtr::new(ptr::null_mut()));
+
+    // take the callback, if any, without writing to the shard if it's empty.
+    fn take(&self) -> Option<raw::Owned<(), ()>> {
+        if self.0.load(Ordering::Relaxed).is_null() {
+            return None;
+        }
+        let ptr = self.0.swap(ptr::null_mut(), Ordering::Acquire);
+        if ptr.is_null() {
+            return None;
+        }
+        Some(unsafe { raw::Owned::from_erased(ptr) })
+    }
+}
+
+/// A [`CallbackCell`][crate::CallbackCell] split into `SHARDS` cells, so
+/// that many threads can put callbacks without contending for one cache
+/// line.
+///
+/// For when many producers register wakeups on one logical cell, and most
+/// registrations are superseded before they're taken:
+/// [`put`][Self::put] swaps into a shard picked by the putting thread, so
+/// producers on different threads rarely write the same shard, and
+/// [`take_call_any`][Self::take_call_any] scans the shards for a callback to
+/// take and run.
+///
+/// This relaxes the cell's semantics. Up to `SHARDS` callbacks may be
+/// pending at once, one in each shard, and a put only replaces the callback
+/// in its own shard, which was put by this thread or another thread sharing
+/// its shard. [`take_call_all_shards`][Self::take_call_all_shards] runs
+/// every pending callback, for consumers which need them all.
+///
+/// Each shard takes a cache line, 128 bytes, so the cell is `128 * SHARDS`
+/// bytes. `SHARDS` must be at least 1.
+pub struct ShardedCallbackCell<const SHARDS: usize> {
+    shards: [CellShard; SHARDS],
+}
+
+// safety: as for `CallbackCell`.
+unsafe impl<const SHARDS: usize> Send for ShardedCallbackCell<SHARDS> {}
+unsafe impl<const SHARDS: usize> Sync for ShardedCallbackCell<SHARDS> {}
+
+// a callback is taken out of its shard before it runs, so a panicking callback
+// leaves the shard empty.
+impl<const SHARDS: usize> UnwindSafe for ShardedCallbackCell<SHARDS> {}
+impl<const SHARDS: usize> RefUnwindSafe for ShardedCallbackCell<SHARDS> {}
+
+impl<const SHARDS: usize> ShardedCallbackCell<SHARDS> {
+    /// Construct with no callbacks.
+    ///
+    /// This is a `const fn`, so cells can be placed in statics.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `SHARDS` is 0.
+    pub const fn new() -> Self {
+        assert!(SHARDS > 0, "ShardedCallbackCell needs at least one shard");
+        ShardedCallbackCell { shards: [CellShard::EMPTY; SHARDS] }
+    }
+
+    // the shards in order, starting from the calling thread's.
+    fn starting_from_own(&self) -> impl Iterator<Item = &CellShard> {
+        let start = thread_token() % SHARDS;
+        self.shards[start..].iter().chain(&self.shards[..start])
+    }
+
+    /// Atomically set the callback in the calling thread's shard.
+    ///
+    /// Makes only one heap allocation. Any callback previously present in
+    /// the shard is dropped. Callbacks in other shards are left alone.
+    pub fn put<F: FnOnce() + Send + 'static>(&self, f: F) {
+        let shard = &self.shards[thread_token() % SHARDS];
+        let old = shard.0.swap(raw::alloc_raw(move |()| f()), Ordering::AcqRel);
+        unsafe { raw::drop_raw::<(), ()>(old) };
+    }
+
+    /// Atomically take a callback from some shard, then run it.
+    ///
+    /// Returns true if a callback was present. Scans the shards, starting
+    /// from the calling thread's, and runs the first callback it takes.
+    /// Callbacks in other shards are left pending.
+    ///
+    /// Like [`CallbackCell::take_call_fast`][crate::CallbackCell::take_call_fast],
+    /// each shard is loaded before it's swapped, so this may miss a
+    /// concurrent put.
+    pub fn take_call_any(&self) -> bool {
+        match self.starting_from_own().find_map(CellShard::take) {
+            Some(callback) => {
+                callback.call(());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Atomically take the callback from each shard in turn, and run it.
+    ///
+    /// Returns the number of callbacks run. A callback put into a shard
+    /// after it's been scanned is left pending. If a callback panics, those
+    /// in shards not yet scanned are left pending.
+    pub fn take_call_all_shards(&self) -> usize {
+        let mut ran = 0;
+        for shard in &self.shards {
+            if let Some(callback) = shard.take() {
+                callback.call(());
+                ran += 1;
+            }
+        }
+        ran
+    }
+
+    /// Atomically take the callback from each shard and drop it without
+    /// running it.
+    ///
+    /// Returns the number of callbacks dropped.
+    pub fn clear(&self) -> usize {
+        self.shards.iter().filter_map(CellShard::take).count()
+    }
+
+    /// Whether any shard currently holds a callback.
+    ///
+    /// This is only a snapshot: another thread may put or take a callback
+    /// immediately afterwards.
+    pub fn is_set(&self) -> bool {
+        self.shards.iter().any(|shard| !shard.0.load(Ordering::Acquire).is_null())
+    }
+
+    /// The number of shards currently holding a callback.
+    ///
+    /// This is only a snapshot, as for [`is_set`][Self::is_set].
+    pub fn pending(&self) -> usize {
+        self.shards.iter().filter(|shard| !shard.0.load(Ordering::Acquire).is_null()).count()
+    }
+}
+
+impl<const SHARDS: usize> Default for ShardedCallbackCell<SHARDS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SHARDS: usize> Debug for ShardedCallbackCell<SHARDS> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "ShardedCallbackCell({} OF {} SHARDS NOT NULL)", self.pending(), SHARDS)
+    }
+}
+
+impl<const SHARDS: usize> Drop for ShardedCallbackCell<SHARDS> {
+    fn drop(&mut self) {
+        for shard in &mut self.shards {
+            unsafe { raw::drop_raw::<(), ()>(*shard.0.get_mut()) };
+        }
+    }
+}
@@ -0,0 +1,72 @@
+
+use crate::raw;
+use alloc::sync::Arc;
+use core::{
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+
+// internals
+// ---------
+//
+// a shared callback, as described in the `raw` module.
+
+/// A callback, in an `Arc`, for putting into any number of cells without
+/// allocating.
+///
+/// Construct one once, in an `Arc`, then put clones of the `Arc` with
+/// [`CallbackCellArgs::put_arc`][crate::CallbackCellArgs::put_arc] or
+/// [`CallbackCell::put_arc`][crate::CallbackCell::put_arc]. A put only bumps
+/// the reference count: the cell holds the `Arc`'s pointer in place of a heap
+/// allocation. Taking and calling it calls the callback by reference, then
+/// releases the cell's reference, and replacing or clearing it just releases
+/// the reference. In every other way it behaves like a callback put by value.
+///
+/// An `Arc<dyn Fn>` alone is two pointers, too big for the cell's one, so the
+/// `Arc` holds the callback along with what the cell needs to call it. The
+/// `Arc` coerces to `Arc<SharedCallback<I, O>>`, for handlers of different
+/// types.
+///
+// with the `disabled` feature, cells never hold callbacks, so this can't pass
+#[cfg_attr(not(feature = "disabled"), doc = "```")]
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+/// use callback_cell::{CallbackCellArgs, SharedCallback};
+/// use std::sync::Arc;
+///
+/// let double: Arc<SharedCallback<u32, u32>> = Arc::new(SharedCallback::new(|i| i * 2));
+///
+/// let cells = [CallbackCellArgs::new(), CallbackCellArgs::new()];
+/// for cell in &cells {
+///     cell.put_arc(Arc::clone(&double));
+/// }
+/// assert_eq!(cells[0].take_call(2), Ok(4));
+/// assert_eq!(cells[1].take_call(3), Ok(6));
+/// ```
+#[repr(transparent)]
+pub struct SharedCallback<I, O, F: ?Sized = dyn Fn(I) -> O + Send + Sync + 'static>(raw::Shared<I, O, F>);
+
+// the callback is only ever called by shared reference, and nothing in the
+// `Arc` is written, so a panicking callback leaves it as it was.
+impl<I, O, F: ?Sized> UnwindSafe for SharedCallback<I, O, F> {}
+impl<I, O, F: ?Sized> RefUnwindSafe for SharedCallback<I, O, F> {}
+
+impl<I, O, F: Fn(I) -> O + Send + Sync + 'static> SharedCallback<I, O, F> {
+    /// Construct from the callback.
+    pub fn new(f: F) -> Self {
+        SharedCallback(raw::Shared::new(f))
+    }
+}
+
+impl<I, O, F: ?Sized> SharedCallback<I, O, F> {
+    // the erased callback, for putting into a cell, taking the reference.
+    // the returned pointer is never null.
+    pub(crate) fn into_raw(this: Arc<Self>) -> *mut u8 {
+        Arc::into_raw(this) as *const u8 as *mut u8
+    }
+}
+
+impl<I, O, F: ?Sized> Debug for SharedCallback<I, O, F> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("SharedCallback")
+    }
+}
@@ -0,0 +1,187 @@
+
+use crate::{
+    sync::{self, AtomicBool},
+    raw,
+};
+use alloc::vec::Vec;
+use core::{
+    sync::atomic::Ordering,
+    cell::UnsafeCell,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+
+// internals
+// ---------
+//
+// the hooks, as owned erased callbacks described in the `raw` module, each
+// with the id of its handle, and whether the run has started, all guarded by a
+// spin lock. the lock is never held while a hook runs or is dropped. `run`
+// marks the registry run and takes every hook while holding the lock, and a
+// register which finds it run keeps its hook, to run after unlocking. so every
+// hook is either taken by the run or run by its register, exactly once, and a
+// hook which registers another, during the run, finds the registry run.
+
+/// Identifies a hook registered in a [`ShutdownHooks`], for deregistering it.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct HookHandle(u64);
+
+struct Inner {
+    next_id: u64,
+    hooks: Vec<(u64, raw::Owned<(), ()>)>,
+    run: bool,
+}
+
+/// A registry of shutdown hooks, each run exactly once.
+///
+/// Like `atexit`, for threads: [`register`][Self::register] adds a hook, and
+/// [`run`][Self::run] runs every hook registered, in reverse registration
+/// order. Once the run has started, registering a hook runs it immediately,
+/// on the registering thread, rather than never running it, so a
+/// registration racing with the run still runs exactly once. Later runs
+/// do nothing.
+///
+/// Hooks still registered when the registry is dropped, without being run,
+/// are dropped without running.
+pub struct ShutdownHooks {
+    locked: AtomicBool,
+    inner: UnsafeCell<Inner>,
+}
+
+// safety: the inner data is only accessed while holding the lock, and the
+//         hooks are Send.
+unsafe impl Send for ShutdownHooks {}
+unsafe impl Sync for ShutdownHooks {}
+
+// the lock is never held while a hook runs or is dropped, and the run takes
+// every hook before running them, so a panicking hook leaves the registry run.
+impl UnwindSafe for ShutdownHooks {}
+impl RefUnwindSafe for ShutdownHooks {}
+
+impl ShutdownHooks {
+    const_fn! {
+        /// Construct with no hooks, not yet run.
+        ///
+        /// This is a `const fn`, so registries can be placed in statics.
+        pub fn new() -> Self {
+            ShutdownHooks {
+                locked: AtomicBool::new(false),
+                inner: UnsafeCell::new(Inner {
+                    next_id: 0,
+                    hooks: Vec::new(),
+                    run: false,
+                }),
+            }
+        }
+    }
+
+    // run the closure on the inner data while holding the lock.
+    fn with_lock<R>(&self, f: impl FnOnce(&mut Inner) -> R) -> R {
+        while self.locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            sync::spin_loop();
+        }
+        let r = f(unsafe { &mut *self.inner.get() });
+        self.locked.store(false, Ordering::Release);
+        r
+    }
+
+    /// Register a hook, to run when the registry is run.
+    ///
+    /// Makes only one heap allocation. If the run has already started, runs
+    /// the hook immediately, on this thread, instead. Either way, returns a
+    /// handle for deregistering the hook, which does nothing once it has
+    /// run.
+    pub fn register<F: FnOnce() + Send + 'static>(&self, f: F) -> HookHandle {
+        let hook = unsafe { raw::Owned::from_erased(raw::alloc_raw(move |()| f())) };
+        let (id, late) = self.with_lock(|inner| {
+            let id = inner.next_id;
+            inner.next_id += 1;
+            if inner.run {
+                (id, Some(hook))
+            } else {
+                inner.hooks.push((id, hook));
+                (id, None)
+            }
+        });
+        if let Some(hook) = late {
+            hook.call(());
+        }
+        HookHandle(id)
+    }
+
+    /// Deregister a hook, dropping it without running it.
+    ///
+    /// Returns true if the hook was still registered, and so will never run.
+    /// Returns false if it has already run, or been taken by a run in
+    /// progress, or already been deregistered.
+    pub fn deregister(&self, handle: HookHandle) -> bool {
+        let hook = self.with_lock(|inner| {
+            let i = inner.hooks.iter().position(|&(id, _)| id == handle.0)?;
+            Some(inner.hooks.remove(i))
+        });
+        hook.is_some()
+    }
+
+    /// Run every registered hook, on this thread, in reverse registration
+    /// order.
+    ///
+    /// Only the first run does anything: returns the number of hooks it ran,
+    /// and 0 from every later run. Hooks registered once this has started,
+    /// including by the hooks themselves, run immediately when registered.
+    ///
+    /// If a hook panics, the hooks after it are dropped without running.
+    pub fn run(&self) -> usize {
+        let hooks = self.with_lock(|inner| {
+            if inner.run {
+                return Vec::new();
+            }
+            inner.run = true;
+            core::mem::take(&mut inner.hooks)
+        });
+        let ran = hooks.len();
+        for (_, hook) in hooks.into_iter().rev() {
+            hook.call(());
+        }
+        ran
+    }
+
+    /// Whether the run has started.
+    pub fn has_run(&self) -> bool {
+        self.with_lock(|inner| inner.run)
+    }
+
+    /// The number of hooks registered, and waiting for the run.
+    ///
+    /// This is only a snapshot: another thread may register or deregister a
+    /// hook immediately afterwards.
+    pub fn len(&self) -> usize {
+        self.with_lock(|inner| inner.hooks.len())
+    }
+
+    /// Whether no hooks are waiting for the run.
+    ///
+    /// This is only a snapshot, as for [`len`][Self::len].
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for ShutdownHooks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for ShutdownHooks {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let (len, run) = self.with_lock(|inner| (inner.hooks.len(), inner.run));
+        if run {
+            f.write_str("ShutdownHooks(RUN)")
+        } else {
+            write!(f, "ShutdownHooks({} REGISTERED)", len)
+        }
+    }
+}
@@ -0,0 +1,90 @@
+
+use crate::MultiCallbackCellArgs;
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+    fmt::{self, Formatter, Debug, Display},
+};
+use futures_sink::Sink;
+
+// internals
+// ---------
+//
+// readiness is "a handler is present", waited for through the cell's waiter
+// list, which registers then re-checks, so a put racing with `poll_ready` is
+// not missed. the sink buffers nothing: `start_send` calls the handler inline.
+
+/// A [`Sink`] which calls a [`MultiCallbackCellArgs`]'s handler with each
+/// item, behind the `futures` feature.
+///
+/// Returned by [`MultiCallbackCellArgs::sink`]. The sink is ready whenever a
+/// handler is present, so `stream.forward(cell.sink())` waits for a handler
+/// whenever there is none. Nothing is buffered: each item is handed to the
+/// handler in `start_send`. If the handler was cleared between `poll_ready`
+/// and `start_send`, `start_send` fails with [`NoHandler`], holding the item.
+pub struct CallbackSink<'a, I> {
+    cell: &'a MultiCallbackCellArgs<I, ()>,
+    id: Option<u64>,
+}
+
+/// Error from [`CallbackSink`] when no handler was present to send an item
+/// to, holding the item.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct NoHandler<I>(pub I);
+
+impl<I> Debug for NoHandler<I> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("NoHandler(..)")
+    }
+}
+
+impl<I> Display for NoHandler<I> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("no handler present")
+    }
+}
+
+#[cfg(feature = "std")]
+impl<I> std::error::Error for NoHandler<I> {}
+
+impl<I> MultiCallbackCellArgs<I, ()> {
+    /// A [`Sink`] which calls the handler with each item.
+    ///
+    /// See [`CallbackSink`].
+    pub fn sink(&self) -> CallbackSink<'_, I> {
+        CallbackSink { cell: self, id: None }
+    }
+}
+
+impl<I> Sink<I> for CallbackSink<'_, I> {
+    type Error = NoHandler<I>;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), NoHandler<I>>> {
+        let this = &mut *self;
+        this.cell.poll_set(cx, &mut this.id).map(Ok)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: I) -> Result<(), NoHandler<I>> {
+        self.cell.call(item).map_err(NoHandler)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), NoHandler<I>>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), NoHandler<I>>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<I> Drop for CallbackSink<'_, I> {
+    fn drop(&mut self) {
+        self.cell.deregister(self.id);
+    }
+}
+
+impl<I> Debug for CallbackSink<'_, I> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("CallbackSink").field(self.cell).finish()
+    }
+}
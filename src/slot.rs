@@ -0,0 +1,85 @@
+
+use crate::{
+    CallbackCell,
+    CallbackCellArgs,
+    WaitableCallbackCell,
+};
+use alloc::boxed::Box;
+
+/// A place a callback can be registered, for code generic over the cell
+/// flavors.
+///
+/// This is object safe, so callers can hold a
+/// `&dyn CallbackSlot<Input = I, Output = O>`. Since it takes callbacks
+/// already boxed, putting through it makes one more heap allocation than
+/// calling a cell's own `put`.
+pub trait CallbackSlot {
+    /// The callback's input. `()` for cells without args.
+    type Input;
+
+    /// The callback's output. `()` for cells without args.
+    type Output;
+
+    /// Atomically set the callback, dropping any callback previously present.
+    fn put_boxed(&self, f: Box<dyn FnOnce(Self::Input) -> Self::Output + Send + 'static>);
+
+    /// Atomically take the callback then run it with the given input.
+    ///
+    /// Returns the output if a callback was present. If a callback was not
+    /// present, returns the original input.
+    fn take_call(&self, input: Self::Input) -> Result<Self::Output, Self::Input>;
+
+    /// Whether a callback is currently present.
+    fn is_set(&self) -> bool;
+}
+
+impl CallbackSlot for CallbackCell {
+    type Input = ();
+    type Output = ();
+
+    fn put_boxed(&self, f: Box<dyn FnOnce(()) + Send + 'static>) {
+        self.put(move || f(()));
+    }
+
+    fn take_call(&self, (): ()) -> Result<(), ()> {
+        if CallbackCell::take_call(self) { Ok(()) } else { Err(()) }
+    }
+
+    fn is_set(&self) -> bool {
+        CallbackCell::is_set(self)
+    }
+}
+
+impl<I: 'static, O: 'static> CallbackSlot for CallbackCellArgs<I, O> {
+    type Input = I;
+    type Output = O;
+
+    fn put_boxed(&self, f: Box<dyn FnOnce(I) -> O + Send + 'static>) {
+        self.put(f);
+    }
+
+    fn take_call(&self, input: I) -> Result<O, I> {
+        CallbackCellArgs::take_call(self, input)
+    }
+
+    fn is_set(&self) -> bool {
+        CallbackCellArgs::is_set(self)
+    }
+}
+
+impl CallbackSlot for WaitableCallbackCell {
+    type Input = ();
+    type Output = ();
+
+    fn put_boxed(&self, f: Box<dyn FnOnce(()) + Send + 'static>) {
+        self.put(move || f(()));
+    }
+
+    fn take_call(&self, (): ()) -> Result<(), ()> {
+        if WaitableCallbackCell::take_call(self) { Ok(()) } else { Err(()) }
+    }
+
+    fn is_set(&self) -> bool {
+        WaitableCallbackCell::is_set(self)
+    }
+}
@@ -0,0 +1,449 @@
+
+use crate::{
+    sync::{self, AtomicBool},
+    raw,
+    RawCallbackArgs,
+};
+use alloc::{
+    boxed::Box,
+    vec::Vec,
+};
+use core::{
+    sync::atomic::Ordering,
+    cell::UnsafeCell,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+
+// internals
+// ---------
+//
+// a vec of slots, each either holding an owned erased callback, described in
+// the `raw` module, or vacant and linked into a freelist through the index of
+// the next vacant slot, all guarded by a spin lock. the lock is only held to
+// update the slots: callbacks are allocated before locking, and run or dropped
+// after unlocking.
+//
+// each slot has a generation, which a key must match. removing a callback from
+// a slot, by taking, removing or clearing it, advances its generation, so no
+// key for that callback matches whatever the slot holds next. a slot whose
+// generation would wrap around is retired rather than put back on the
+// freelist, so a key never matches a later callback however long the map is
+// used.
+//
+// the occupied slots are also linked, through their indices, into a list in
+// order of recency, from the least recently inserted or touched to the most. a
+// bounded map evicts from the front of the list when an insert finds it full,
+// under the same lock as the insert, so the map never holds more callbacks
+// than its capacity. the evicted callback is handed to the eviction hook after
+// unlocking.
+
+/// Identifies one callback inserted into a [`CallbackSlotMap`].
+///
+/// Made of the callback's slot index and the slot's generation when it was
+/// inserted, so a key for a callback which has been removed never matches a
+/// later callback reusing its slot.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct CallbackKey {
+    index: u32,
+    generation: u32,
+}
+
+impl CallbackKey {
+    /// The index of the callback's slot.
+    pub fn index(self) -> u32 {
+        self.index
+    }
+
+    /// The generation of the callback's slot when it was inserted.
+    pub fn generation(self) -> u32 {
+        self.generation
+    }
+}
+
+/// Outcome of [`CallbackSlotMap::take_call_many`].
+#[derive(Debug)]
+pub struct BatchOutcome<I, O> {
+    /// The output of each callback which ran, with its key, in the order the
+    /// keys came in the batch.
+    pub outputs: Vec<(CallbackKey, O)>,
+    /// Each key whose callback wasn't present, with the input given for it,
+    /// in the order the keys came in the batch.
+    pub unmatched: Vec<(CallbackKey, I)>,
+}
+
+// marks the end of the freelist, and of the recency list.
+const NO_FREE: u32 = u32::MAX;
+
+enum Entry<I, O> {
+    Occupied(raw::Owned<I, O>),
+    Vacant { next_free: u32 },
+    Retired,
+}
+
+struct Slot<I, O> {
+    generation: u32,
+    entry: Entry<I, O>,
+    // neighbours in the recency list, while occupied.
+    prev: u32,
+    next: u32,
+}
+
+struct Inner<I, O> {
+    slots: Vec<Slot<I, O>>,
+    free_head: u32,
+    len: usize,
+    // least and most recent ends of the recency list.
+    lru_head: u32,
+    lru_tail: u32,
+}
+
+type EvictHook<I, O> = Box<dyn Fn(CallbackKey, RawCallbackArgs<I, O>) + Send + Sync + 'static>;
+
+impl<I, O> Inner<I, O> {
+    // whether the key refers to a callback still present.
+    fn is_current(&self, key: CallbackKey) -> bool {
+        matches!(
+            self.slots.get(key.index as usize),
+            Some(Slot { generation, entry: Entry::Occupied(_), .. }) if *generation == key.generation
+        )
+    }
+
+    // link an occupied slot onto the most recent end of the recency list.
+    fn link_back(&mut self, index: u32) {
+        let tail = self.lru_tail;
+        let slot = &mut self.slots[index as usize];
+        slot.prev = tail;
+        slot.next = NO_FREE;
+        match tail {
+            NO_FREE => self.lru_head = index,
+            tail => self.slots[tail as usize].next = index,
+        }
+        self.lru_tail = index;
+    }
+
+    // unlink an occupied slot from the recency list.
+    fn unlink(&mut self, index: u32) {
+        let Slot { prev, next, .. } = self.slots[index as usize];
+        match prev {
+            NO_FREE => self.lru_head = next,
+            prev => self.slots[prev as usize].next = next,
+        }
+        match next {
+            NO_FREE => self.lru_tail = prev,
+            next => self.slots[next as usize].prev = prev,
+        }
+    }
+
+    // take the callback the key refers to, if it's still present, freeing its
+    // slot.
+    fn take(&mut self, key: CallbackKey) -> Option<raw::Owned<I, O>> {
+        if !self.is_current(key) {
+            return None;
+        }
+        self.unlink(key.index);
+        let slot = &mut self.slots[key.index as usize];
+        let entry = match slot.generation.checked_add(1) {
+            Some(generation) => {
+                slot.generation = generation;
+                let next_free = self.free_head;
+                self.free_head = key.index;
+                Entry::Vacant { next_free }
+            }
+            None => Entry::Retired,
+        };
+        self.len -= 1;
+        match core::mem::replace(&mut slot.entry, entry) {
+            Entry::Occupied(callback) => Some(callback),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Storage for many one-shot callbacks, each identified by the
+/// [`CallbackKey`] returned when it was inserted.
+///
+/// Like a slot map of [`CallbackCellArgs`][crate::CallbackCellArgs]:
+/// [`insert`][Self::insert] stores a callback, in one heap allocation, and
+/// returns its key, for [`take_call`][Self::take_call] to run it or
+/// [`remove`][Self::remove] to cancel it. Keys embed a generation, so once
+/// its callback is gone a key fails cleanly, even if its slot has been
+/// reused. Vacant slots are reused before the map grows.
+///
+/// The slots are guarded by a spin lock, held only briefly to update them,
+/// never while a callback is allocated, run, or dropped. That suits many
+/// threads inserting and removing callbacks while a few complete them.
+pub struct CallbackSlotMap<I, O> {
+    locked: AtomicBool,
+    inner: UnsafeCell<Inner<I, O>>,
+    // the most callbacks present at once, or `usize::MAX` if unbounded.
+    capacity: usize,
+    on_evict: Option<EvictHook<I, O>>,
+}
+
+// safety: the slots are only accessed while holding the lock, and callbacks are
+//         Send. the eviction hook is Send and Sync.
+unsafe impl<I, O> Send for CallbackSlotMap<I, O> {}
+unsafe impl<I, O> Sync for CallbackSlotMap<I, O> {}
+
+// the lock is never held while a callback runs or is dropped, and a callback
+// is removed from its slot before it runs, so a panicking callback leaves the
+// map consistent.
+impl<I, O> UnwindSafe for CallbackSlotMap<I, O> {}
+impl<I, O> RefUnwindSafe for CallbackSlotMap<I, O> {}
+
+impl<I, O> CallbackSlotMap<I, O> {
+    const_fn! {
+        /// Construct with no callbacks.
+        ///
+        /// This is a `const fn`, so maps can be placed in statics.
+        pub fn new() -> Self {
+            CallbackSlotMap {
+                locked: AtomicBool::new(false),
+                inner: UnsafeCell::new(Inner {
+                    slots: Vec::new(),
+                    free_head: NO_FREE,
+                    len: 0,
+                    lru_head: NO_FREE,
+                    lru_tail: NO_FREE,
+                }),
+                capacity: usize::MAX,
+                on_evict: None,
+            }
+        }
+    }
+
+    /// Construct with no callbacks, holding at most `capacity` at once.
+    ///
+    /// An insert into a full map first evicts the least recently used
+    /// callback: the one inserted, or [`touch`][Self::touch]ed, longest
+    /// ago. The eviction and the insert are one step under the map's lock, so
+    /// however many threads insert at once, the map never holds more than
+    /// `capacity` callbacks. After unlocking, the inserting thread passes the
+    /// evicted callback, with its key, to `on_evict`, which may run it to
+    /// fail whatever was waiting on it, or drop it. Its key no longer
+    /// matches. If `on_evict` panics, the panic propagates out of the
+    /// insert, whose callback has already been inserted.
+    ///
+    /// Room for `capacity` slots is allocated up front.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn with_capacity_lru<E>(capacity: usize, on_evict: E) -> Self
+    where
+        E: Fn(CallbackKey, RawCallbackArgs<I, O>) + Send + Sync + 'static,
+    {
+        assert!(capacity > 0, "CallbackSlotMap capacity must be nonzero");
+        let map = CallbackSlotMap {
+            capacity,
+            on_evict: Some(Box::new(on_evict)),
+            ..Self::new()
+        };
+        map.with_lock(|inner| inner.slots.reserve(capacity.min(NO_FREE as usize)));
+        map
+    }
+
+    // run the closure on the inner data while holding the lock.
+    fn with_lock<R>(&self, f: impl FnOnce(&mut Inner<I, O>) -> R) -> R {
+        while self.locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            sync::spin_loop();
+        }
+        let r = f(unsafe { &mut *self.inner.get() });
+        self.locked.store(false, Ordering::Release);
+        r
+    }
+
+    /// Store a callback, returning its key.
+    ///
+    /// Makes one heap allocation for the callback, plus one to grow the map
+    /// if no vacant slot is left. If the map is bounded and full, first
+    /// evicts its least recently used callback, as described for
+    /// [`with_capacity_lru`][Self::with_capacity_lru].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the map would need more than `u32::MAX - 1` slots.
+    pub fn insert<F: FnOnce(I) -> O + Send + 'static>(&self, f: F) -> CallbackKey {
+        let callback = unsafe { raw::Owned::from_erased(raw::alloc_raw(f)) };
+        let mut evicted = None;
+        let key = self.with_lock(|inner| {
+            if inner.len >= self.capacity {
+                let index = inner.lru_head;
+                let key = CallbackKey { index, generation: inner.slots[index as usize].generation };
+                evicted = inner.take(key).map(|callback| (key, callback));
+            }
+            let index = inner.free_head;
+            let key = if index != NO_FREE {
+                let slot = &mut inner.slots[index as usize];
+                inner.free_head = match slot.entry {
+                    Entry::Vacant { next_free } => next_free,
+                    _ => unreachable!(),
+                };
+                slot.entry = Entry::Occupied(callback);
+                CallbackKey { index, generation: slot.generation }
+            } else {
+                let index = inner.slots.len();
+                if index >= NO_FREE as usize {
+                    return Err(callback);
+                }
+                inner.slots.push(Slot {
+                    generation: 0,
+                    entry: Entry::Occupied(callback),
+                    prev: NO_FREE,
+                    next: NO_FREE,
+                });
+                CallbackKey { index: index as u32, generation: 0 }
+            };
+            inner.link_back(key.index);
+            inner.len += 1;
+            Ok(key)
+        });
+        if let (Some((key, callback)), Some(on_evict)) = (evicted, &self.on_evict) {
+            on_evict(key, callback);
+        }
+        key.unwrap_or_else(|_| panic!("CallbackSlotMap ran out of slots"))
+    }
+
+    /// Mark the callback the key refers to as the most recently used, so
+    /// that it's evicted last.
+    ///
+    /// Returns true if the callback was present.
+    pub fn touch(&self, key: CallbackKey) -> bool {
+        self.with_lock(|inner| {
+            if !inner.is_current(key) {
+                return false;
+            }
+            inner.unlink(key.index);
+            inner.link_back(key.index);
+            true
+        })
+    }
+
+    /// The most callbacks the map holds at once, if it's bounded.
+    pub fn capacity(&self) -> Option<usize> {
+        (self.capacity != usize::MAX).then_some(self.capacity)
+    }
+
+    /// Take the callback the key refers to, then run it with the given input.
+    ///
+    /// Returns the output if the callback was present. If it was already
+    /// taken or removed, returns the original input.
+    pub fn take_call(&self, key: CallbackKey, input: I) -> Result<O, I> {
+        match self.with_lock(|inner| inner.take(key)) {
+            Some(callback) => Ok(callback.call(input)),
+            None => Err(input),
+        }
+    }
+
+    /// Take the callbacks for a batch of keys, then run each with the input
+    /// given with its key.
+    ///
+    /// Resolves the whole batch with one lock acquisition, rather than one
+    /// per key as a loop of [`take_call`][Self::take_call] would. Then runs
+    /// the callbacks, after unlocking, in the order the keys came in the
+    /// batch. A key whose callback isn't present, including one which comes
+    /// in the batch twice, has its input handed back.
+    ///
+    /// If a callback panics, those after it in the batch have already been
+    /// taken, and are dropped without running.
+    pub fn take_call_many(&self, completions: impl IntoIterator<Item = (CallbackKey, I)>) -> BatchOutcome<I, O> {
+        // collected before locking, so that the iterator doesn't run under the
+        // lock.
+        let completions = completions.into_iter().collect::<Vec<_>>();
+        let taken = self.with_lock(|inner| {
+            completions.into_iter()
+                .map(|(key, input)| (key, inner.take(key), input))
+                .collect::<Vec<_>>()
+        });
+        let mut outcome = BatchOutcome {
+            outputs: Vec::new(),
+            unmatched: Vec::new(),
+        };
+        for (key, callback, input) in taken {
+            match callback {
+                Some(callback) => outcome.outputs.push((key, callback.call(input))),
+                None => outcome.unmatched.push((key, input)),
+            }
+        }
+        outcome
+    }
+
+    /// Take the callback the key refers to and drop it without running it.
+    ///
+    /// Returns true if the callback was present.
+    pub fn remove(&self, key: CallbackKey) -> bool {
+        self.with_lock(|inner| inner.take(key)).is_some()
+    }
+
+    /// Whether the callback the key refers to is still present.
+    ///
+    /// This is only a snapshot: another thread may take or remove it
+    /// immediately afterwards.
+    pub fn contains(&self, key: CallbackKey) -> bool {
+        self.with_lock(|inner| inner.is_current(key))
+    }
+
+    /// Drop every callback without running it.
+    ///
+    /// Returns the number of callbacks dropped. Their keys no longer match.
+    pub fn clear(&self) -> usize {
+        let callbacks = self.with_lock(|inner| {
+            (0..inner.slots.len() as u32)
+                .filter_map(|index| {
+                    let generation = inner.slots[index as usize].generation;
+                    inner.take(CallbackKey { index, generation })
+                })
+                .collect::<Vec<_>>()
+        });
+        callbacks.len()
+    }
+
+    /// The keys of the callbacks currently present, in slot order, for
+    /// diagnostics.
+    ///
+    /// This is only a snapshot: other threads may insert or take callbacks
+    /// immediately afterwards.
+    pub fn keys(&self) -> Vec<CallbackKey> {
+        self.with_lock(|inner| {
+            inner.slots.iter()
+                .enumerate()
+                .filter(|(_, slot)| matches!(slot.entry, Entry::Occupied(_)))
+                .map(|(index, slot)| CallbackKey { index: index as u32, generation: slot.generation })
+                .collect()
+        })
+    }
+
+    /// The number of callbacks currently present.
+    ///
+    /// This is only a snapshot: other threads may insert or take callbacks
+    /// immediately afterwards.
+    pub fn len(&self) -> usize {
+        self.with_lock(|inner| inner.len)
+    }
+
+    /// Whether no callbacks are currently present.
+    ///
+    /// This is only a snapshot, as for [`len`][Self::len].
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<I, O> Default for CallbackSlotMap<I, O> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I, O> Debug for CallbackSlotMap<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.capacity() {
+            Some(capacity) => write!(f, "CallbackSlotMap({} OF {} PRESENT)", self.len(), capacity),
+            None => write!(f, "CallbackSlotMap({} PRESENT)", self.len()),
+        }
+    }
+}
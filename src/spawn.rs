@@ -0,0 +1,45 @@
+
+use crate::BoxFuture;
+use alloc::boxed::Box;
+
+/// Something which can run callbacks and futures in the background, such as
+/// an async runtime.
+///
+/// Used by the cells' `take_spawn` methods, which take the callback and hand
+/// it to the spawner instead of running it inline. This keeps the cells
+/// runtime-agnostic; impls for specific runtimes are behind features:
+///
+/// - `tokio`: `tokio::runtime::Handle`
+///
+/// Implementations should not let a panic in a spawned callback or future
+/// propagate to the caller of `take_spawn`.
+pub trait Spawn {
+    /// Run the callback in the background.
+    fn spawn_boxed(&self, f: Box<dyn FnOnce() + Send + 'static>);
+
+    /// Drive the future to completion in the background.
+    fn spawn_future(&self, fut: BoxFuture<()>);
+}
+
+impl<S: Spawn + ?Sized> Spawn for &S {
+    fn spawn_boxed(&self, f: Box<dyn FnOnce() + Send + 'static>) {
+        (**self).spawn_boxed(f);
+    }
+
+    fn spawn_future(&self, fut: BoxFuture<()>) {
+        (**self).spawn_future(fut);
+    }
+}
+
+/// Callbacks are run with `spawn_blocking`, since they may block, and futures
+/// with `spawn`. Panics are caught by tokio and discarded.
+#[cfg(feature = "tokio")]
+impl Spawn for tokio::runtime::Handle {
+    fn spawn_boxed(&self, f: Box<dyn FnOnce() + Send + 'static>) {
+        drop(self.spawn_blocking(f));
+    }
+
+    fn spawn_future(&self, fut: BoxFuture<()>) {
+        drop(self.spawn(fut));
+    }
+}
@@ -0,0 +1,232 @@
+
+use crate::raw;
+use portable_atomic::AtomicU128;
+use core::{
+    sync::atomic::Ordering,
+    ptr,
+    marker::PhantomData,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+
+// internals
+// ---------
+//
+// the state is a 128-bit word, the low 64 bits of which are the address of a
+// nullable pointer to an erased callback, as described in the `raw` module,
+// and the high 64 bits of which are a generation count. every put stores its
+// callback with the generation one past the current one, and takes and clears
+// keep the generation, so each stored callback gets a stamp which no other
+// callback stored in the same cell shares, even if the allocator hands it the
+// address of an earlier one. conditional operations compare-exchange against
+// the whole stamp, so they can't mistake a new callback for the old one they
+// were handed a stamp for.
+//
+// the pointer's provenance is exposed when it's packed into the word, and
+// recovered from the address when unpacked.
+//
+// `AtomicU128` comes from `portable-atomic`. it's lock-free on targets with a
+// native 128-bit compare-exchange, and otherwise falls back to a global lock
+// table, through `portable-atomic`'s `fallback` feature.
+
+fn pack(ptr: *mut u8, generation: u64) -> u128 {
+    ((generation as u128) << 64) | ptr.expose_provenance() as u128
+}
+
+fn ptr_of(state: u128) -> *mut u8 {
+    ptr::with_exposed_provenance_mut(state as u64 as usize)
+}
+
+fn generation_of(state: u128) -> u64 {
+    (state >> 64) as u64
+}
+
+/// A [`CallbackCellArgs`][crate::CallbackCellArgs] whose puts return a
+/// [`CancelHandle`], for cancelling or replacing exactly that callback later.
+///
+/// Behind the `stamped` feature. The cell stores the callback pointer
+/// together with a generation count, which every put advances, in one 128-bit
+/// atomic. So unlike a comparison of pointers alone, a handle never matches a
+/// different callback which happens to have been allocated at the same
+/// address as the one it was returned for.
+///
+/// The cell is lock-free where 128-bit atomic compare-exchange is native:
+/// x86_64 with `cmpxchg16b` (detected at run time unless enabled at compile
+/// time), aarch64, powerpc64 (pwr8 and later), s390x, and the others listed
+/// by [`portable_atomic::AtomicU128`]. Elsewhere it falls back to a lock.
+pub struct StampedCallbackCellArgs<I, O> {
+    state: AtomicU128,
+    // invariant in `I` and `O`, like `CallbackCellArgs`.
+    _p: PhantomData<dyn FnOnce(I) -> O + Send + 'static>,
+}
+
+/// Identifies one put into a [`StampedCallbackCellArgs`], for cancelling or
+/// replacing that callback if it's still present.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CancelHandle(u128);
+
+impl CancelHandle {
+    // address of the callback this handle was returned for.
+    #[cfg(all(test, not(feature = "disabled")))]
+    pub(crate) fn addr(&self) -> usize {
+        ptr_of(self.0).addr()
+    }
+}
+
+impl Debug for CancelHandle {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("CancelHandle").field(&generation_of(self.0)).finish()
+    }
+}
+
+// safety: as for `CallbackCellArgs`.
+unsafe impl<I, O> Send for StampedCallbackCellArgs<I, O> {}
+unsafe impl<I, O> Sync for StampedCallbackCellArgs<I, O> {}
+impl<I, O> UnwindSafe for StampedCallbackCellArgs<I, O> {}
+impl<I, O> RefUnwindSafe for StampedCallbackCellArgs<I, O> {}
+
+impl<I, O> StampedCallbackCellArgs<I, O> {
+    /// Construct with no callback.
+    ///
+    /// This is a `const fn`, so cells can be placed in statics.
+    pub const fn new() -> Self {
+        StampedCallbackCellArgs {
+            state: AtomicU128::new(0),
+            _p: PhantomData,
+        }
+    }
+
+    /// Atomically set the callback.
+    ///
+    /// Any callback previously present is dropped. Returns a handle to this
+    /// callback.
+    pub fn put<F: FnOnce(I) -> O + Send + 'static>(&self, f: F) -> CancelHandle {
+        let ptr = raw::alloc_raw(f);
+        let mut old = self.state.load(Ordering::Relaxed);
+        loop {
+            let new = pack(ptr, generation_of(old).wrapping_add(1));
+            match self.state.compare_exchange_weak(old, new, Ordering::AcqRel, Ordering::Relaxed) {
+                Ok(_) => {
+                    unsafe { raw::drop_raw::<I, O>(ptr_of(old)) };
+                    return CancelHandle(new);
+                }
+                Err(state) => old = state,
+            }
+        }
+    }
+
+    /// Atomically take the callback then run it with the given input.
+    ///
+    /// Returns the output if a callback was present. If a callback was not
+    /// present, returns the original input.
+    pub fn take_call(&self, input: I) -> Result<O, I> {
+        let mut old = self.state.load(Ordering::Relaxed);
+        loop {
+            if ptr_of(old).is_null() {
+                return Err(input);
+            }
+            let new = pack(ptr::null_mut(), generation_of(old));
+            match self.state.compare_exchange_weak(old, new, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => return Ok(unsafe { raw::call_raw(ptr_of(old), input) }),
+                Err(state) => old = state,
+            }
+        }
+    }
+
+    /// Atomically take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        let mut old = self.state.load(Ordering::Relaxed);
+        loop {
+            if ptr_of(old).is_null() {
+                return false;
+            }
+            let new = pack(ptr::null_mut(), generation_of(old));
+            match self.state.compare_exchange_weak(old, new, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(state) => old = state,
+            }
+        }
+        unsafe { raw::drop_raw::<I, O>(ptr_of(old)) };
+        true
+    }
+
+    /// Atomically take and drop the callback, if it's still the one the
+    /// handle was returned for.
+    ///
+    /// Returns true if it was. Returns false if it has since been taken,
+    /// cleared, cancelled, or replaced, leaving any other callback in place.
+    pub fn cancel(&self, handle: CancelHandle) -> bool {
+        let new = pack(ptr::null_mut(), generation_of(handle.0));
+        match self.state.compare_exchange(handle.0, new, Ordering::Acquire, Ordering::Relaxed) {
+            Ok(_) => {
+                unsafe { raw::drop_raw::<I, O>(ptr_of(handle.0)) };
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Atomically replace the callback, if it's still the one the handle was
+    /// returned for.
+    ///
+    /// If it was, the old callback is dropped, and this returns a handle to
+    /// the new one. Otherwise, returns the new callback, without storing it.
+    pub fn replace_if_same<F>(&self, handle: CancelHandle, f: F) -> Result<CancelHandle, F>
+    where
+        F: FnOnce(I) -> O + Send + 'static,
+    {
+        if self.state.load(Ordering::Relaxed) != handle.0 {
+            return Err(f);
+        }
+        let ptr = raw::alloc_raw(f);
+        let new = pack(ptr, generation_of(handle.0).wrapping_add(1));
+        match self.state.compare_exchange(handle.0, new, Ordering::AcqRel, Ordering::Relaxed) {
+            Ok(_) => {
+                unsafe { raw::drop_raw::<I, O>(ptr_of(handle.0)) };
+                Ok(CancelHandle(new))
+            }
+            Err(_) => Err(unsafe { raw::into_callback::<I, O, F>(ptr) }),
+        }
+    }
+
+    /// Whether a callback is currently present.
+    ///
+    /// This is only a snapshot: another thread may put or take a callback
+    /// immediately afterwards.
+    pub fn is_set(&self) -> bool {
+        !ptr_of(self.state.load(Ordering::Acquire)).is_null()
+    }
+
+    /// Whether the callback the handle was returned for is still present.
+    ///
+    /// This is only a snapshot, like [`is_set`][Self::is_set].
+    pub fn is_current(&self, handle: CancelHandle) -> bool {
+        self.state.load(Ordering::Acquire) == handle.0
+    }
+}
+
+impl<I, O> Drop for StampedCallbackCellArgs<I, O> {
+    fn drop(&mut self) {
+        unsafe {
+            raw::drop_raw::<I, O>(ptr_of(*self.state.get_mut()));
+        }
+    }
+}
+
+impl<I, O> Default for StampedCallbackCellArgs<I, O> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I, O> Debug for StampedCallbackCellArgs<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_set() {
+            f.write_str("StampedCallbackCellArgs(NOT NULL)")
+        } else {
+            f.write_str("StampedCallbackCellArgs(NULL)")
+        }
+    }
+}
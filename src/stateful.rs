@@ -0,0 +1,510 @@
+
+use crate::{
+    sync::{self, AtomicBool, AtomicPtr, AtomicUsize, LoadMut},
+    CallbackCellArgs,
+};
+use core::{
+    sync::atomic::Ordering,
+    ptr,
+    cell::UnsafeCell,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug, Display},
+};
+use alloc::boxed::Box;
+
+// internals
+// ---------
+//
+// a `CallbackCellArgs` taking a pointer to the state, beside the state, guarded
+// by a spin lock. unlike the other spin locked types, the lock is held while a
+// callback runs, since the callback is what has exclusive access to the state.
+// a take takes the callback first, then acquires the lock to run it, so puts
+// never wait for the lock, and a callback being run doesn't block the next
+// being put.
+//
+// the lock is released by a guard, so that a panicking callback doesn't leave
+// it held. the state is left as the callback left it.
+//
+// the queue is a lock-free stack of messages, like a `DeferredCallQueue`'s,
+// except that the state is passed in when a message is run, rather than stored
+// with it. each message is a single heap allocation, a `Box<Node<S, F>>`,
+// starting with a `Header`, which consists of:
+//
+// - the pointer to the next message
+// - an `unsafe fn(*mut Header<S>, Option<&mut S>)` which, when called with the
+//   pointer to the message, frees the heap allocation and, if given the state,
+//   runs the callback with it, or else drops it without running it
+//
+// processing takes the whole stack at once, so messages sent meanwhile wait
+// for the next call, then reverses it to run the messages in the order they
+// were sent. taking, and putting back the messages a batch didn't reach, are
+// guarded by a spin lock, as in `DeferredCallQueue`. the callbacks run outside
+// it.
+//
+// the length counts messages sent and not yet taken off the list to run or
+// drop. a bounded send reserves its place in it before allocating.
+
+/// A [`CallbackCellArgs`][crate::CallbackCellArgs] which owns some state, and
+/// whose callbacks receive exclusive access to it.
+///
+/// Like a one-slot actor: rather than pairing a `Mutex<S>` with a cell and
+/// locking it in every callback, [`put`][Self::put] a callback taking
+/// `&mut S`, and [`take_call`][Self::take_call] runs it with the state.
+/// [`with_state`][Self::with_state] reads the state between callbacks.
+///
+/// Exclusivity is provided by a spin lock, held while a callback or
+/// `with_state` runs, so those should be short. Calling `take_call` or
+/// `with_state` on the same cell from within either deadlocks. Putting a
+/// callback never waits for the lock.
+///
+/// If a callback panics, the lock is released, and the state left as the
+/// callback left it, with no poisoning, like a `RefCell`.
+pub struct StatefulCallbackCell<S> {
+    cell: CallbackCellArgs<*mut S, ()>,
+    locked: AtomicBool,
+    state: UnsafeCell<S>,
+}
+
+// safety: the state is only accessed while holding the lock, or through
+//         `&mut self`, so it's moved between threads but never shared, like a
+//         `Mutex`'s. the callbacks are Send.
+unsafe impl<S: Send> Send for StatefulCallbackCell<S> {}
+unsafe impl<S: Send> Sync for StatefulCallbackCell<S> {}
+
+// a panicking callback releases the lock, but may leave the state broken for
+// the next, so this is only UnwindSafe, like a `RefCell`.
+impl<S: UnwindSafe> UnwindSafe for StatefulCallbackCell<S> {}
+
+// releases the lock when dropped, including if a callback panics.
+struct Unlock<'a>(&'a AtomicBool);
+
+impl Drop for Unlock<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+impl<S> StatefulCallbackCell<S> {
+    const_fn! {
+        /// Construct with the given state and no callback.
+        ///
+        /// This is a `const fn`, so cells can be placed in statics.
+        pub fn new(state: S) -> Self {
+            StatefulCallbackCell {
+                cell: CallbackCellArgs::new(),
+                locked: AtomicBool::new(false),
+                state: UnsafeCell::new(state),
+            }
+        }
+    }
+
+    // run the closure on the state while holding the lock.
+    fn with_lock<R>(&self, f: impl FnOnce(*mut S) -> R) -> R {
+        while self.locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            sync::spin_loop();
+        }
+        let _unlock = Unlock(&self.locked);
+        f(self.state.get())
+    }
+
+    /// Atomically set the callback.
+    ///
+    /// Makes only one heap allocation. Any callback previously present is
+    /// dropped. Doesn't wait for a callback being run.
+    pub fn put<F: FnOnce(&mut S) + Send + 'static>(&self, f: F) {
+        // safety: the pointer is only ever passed by `take_call`, while
+        //         holding the lock.
+        self.cell.put(move |state: *mut S| f(unsafe { &mut *state }))
+    }
+
+    /// Atomically take the callback, then run it with exclusive access to
+    /// the state.
+    ///
+    /// Waits for any callback or [`with_state`][Self::with_state] running on
+    /// another thread to finish first. Returns true if a callback was
+    /// present.
+    pub fn take_call(&self) -> bool {
+        let ptr = self.cell.take_raw();
+        if ptr.is_null() {
+            return false;
+        }
+        self.with_lock(|state| unsafe { CallbackCellArgs::run_raw(ptr, state) });
+        true
+    }
+
+    /// Atomically take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        self.cell.clear()
+    }
+
+    /// Whether a callback is currently present.
+    ///
+    /// This is only a snapshot: another thread may put or take a callback
+    /// immediately afterwards.
+    pub fn is_set(&self) -> bool {
+        self.cell.is_set()
+    }
+
+    /// Read the state.
+    ///
+    /// Waits for any callback or `with_state` running on another thread to
+    /// finish first.
+    pub fn with_state<R>(&self, f: impl FnOnce(&S) -> R) -> R {
+        self.with_lock(|state| f(unsafe { &*state }))
+    }
+
+    /// The state, without locking, since this has exclusive access to the
+    /// cell.
+    pub fn get_mut(&mut self) -> &mut S {
+        self.state.get_mut()
+    }
+}
+
+impl<S: Default> Default for StatefulCallbackCell<S> {
+    fn default() -> Self {
+        Self::new(S::default())
+    }
+}
+
+impl<S> Debug for StatefulCallbackCell<S> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_set() {
+            f.write_str("StatefulCallbackCell(NOT NULL)")
+        } else {
+            f.write_str("StatefulCallbackCell(NULL)")
+        }
+    }
+}
+
+#[repr(C)]
+struct Header<S> {
+    next: *mut Header<S>,
+    fn_ptr: unsafe fn(*mut Header<S>, Option<&mut S>),
+}
+
+#[repr(C)]
+struct Node<S, F> {
+    // must be first
+    header: Header<S>,
+    f: F,
+}
+
+// implementation for the function pointer for a given callback type F.
+unsafe fn fn_ptr_impl<S, F: FnOnce(&mut S)>(ptr: *mut Header<S>, state: Option<&mut S>) {
+    let node = Box::from_raw(ptr as *mut Node<S, F>);
+    #[cfg(any(test, feature = "test-util"))]
+    crate::test_util::on_dealloc();
+    #[cfg(feature = "alloc-stats")]
+    crate::alloc_stats::on_dealloc(core::mem::size_of::<Node<S, F>>());
+    if let Some(state) = state {
+        (node.f)(state);
+    }
+}
+
+/// Error returned by [`StatefulCallbackQueue::try_send`] when the queue is
+/// full, holding the rejected callback.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Full<T>(pub T);
+
+impl<T> Full<T> {
+    /// The rejected callback.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Debug for Full<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("Full(..)")
+    }
+}
+
+impl<T> Display for Full<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("queue full")
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> std::error::Error for Full<T> {}
+
+/// A queue of callbacks, each to be run later with exclusive access to some
+/// state, which the queue doesn't own.
+///
+/// Like an actor's mailbox: any number of threads may [`send`][Self::send]
+/// mutations concurrently without locking, then whoever owns the state
+/// passes it to [`process`][Self::process], which applies them in the order
+/// they were sent. Since the state is borrowed mutably for the call, no
+/// locking is needed to access it.
+///
+/// Messages sent while processing wait for the next call. Messages still
+/// queued when the queue is dropped are dropped without running.
+///
+/// A queue may be [bounded][Self::with_bound], for backpressure: then
+/// [`try_send`][Self::try_send] hands the callback back if the queue is full.
+/// `send` never fails, and ignores the bound.
+pub struct StatefulCallbackQueue<S> {
+    head: AtomicPtr<Header<S>>,
+    taking: AtomicBool,
+    len: AtomicUsize,
+    bound: usize,
+}
+
+// safety: the callbacks stored in the queue are Send. the state is only
+//         borrowed for the duration of a call, on the calling thread.
+unsafe impl<S> Send for StatefulCallbackQueue<S> {}
+unsafe impl<S> Sync for StatefulCallbackQueue<S> {}
+
+// a panic in a message unwinds out of `process` after the batch has been
+// taken, so the queue is left without it, which is a valid state. the state
+// isn't the queue's.
+impl<S> UnwindSafe for StatefulCallbackQueue<S> {}
+impl<S> RefUnwindSafe for StatefulCallbackQueue<S> {}
+
+impl<S> StatefulCallbackQueue<S> {
+    const_fn! {
+        /// Construct unbounded, with no messages.
+        ///
+        /// This is a `const fn`, so queues can be placed in statics.
+        pub fn new() -> Self {
+            Self::with_bound(usize::MAX)
+        }
+    }
+
+    const_fn! {
+        /// Construct with no messages, and room for `bound` messages to be
+        /// queued by [`try_send`][Self::try_send].
+        pub fn with_bound(bound: usize) -> Self {
+            StatefulCallbackQueue {
+                head: AtomicPtr::new(ptr::null_mut()),
+                taking: AtomicBool::new(false),
+                len: AtomicUsize::new(0),
+                bound,
+            }
+        }
+    }
+
+    /// The bound given to [`with_bound`][Self::with_bound], or `None` if
+    /// unbounded.
+    pub fn bound(&self) -> Option<usize> {
+        (self.bound != usize::MAX).then_some(self.bound)
+    }
+
+    /// Send a callback, to be run with the state by a later
+    /// [`process`][Self::process].
+    ///
+    /// Makes only one heap allocation, and doesn't block. Queues the
+    /// callback even if the queue is full.
+    pub fn send<F: FnOnce(&mut S) + Send + 'static>(&self, f: F) {
+        self.len.fetch_add(1, Ordering::Relaxed);
+        self.push(f);
+    }
+
+    /// Like [`send`][Self::send], unless the queue is full, in which case
+    /// returns the callback instead, without queueing it.
+    pub fn try_send<F: FnOnce(&mut S) + Send + 'static>(&self, f: F) -> Result<(), Full<F>> {
+        let mut len = self.len.load(Ordering::Relaxed);
+        loop {
+            if len >= self.bound {
+                return Err(Full(f));
+            }
+            match self.len.compare_exchange_weak(len, len + 1, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(new_len) => len = new_len,
+            }
+        }
+        self.push(f);
+        Ok(())
+    }
+
+    fn push<F: FnOnce(&mut S) + Send + 'static>(&self, f: F) {
+        let node = Box::into_raw(Box::new(Node {
+            header: Header {
+                next: ptr::null_mut(),
+                fn_ptr: fn_ptr_impl::<S, F>,
+            },
+            f,
+        })) as *mut Header<S>;
+        #[cfg(any(test, feature = "test-util"))]
+        crate::test_util::on_alloc();
+        #[cfg(feature = "alloc-stats")]
+        crate::alloc_stats::on_alloc(core::mem::size_of::<Node<S, F>>());
+
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            unsafe { (*node).next = head };
+            match self.head.compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return,
+                Err(new_head) => head = new_head,
+            }
+        }
+    }
+
+    /// Atomically take every queued message, then run each with the state, in
+    /// the order they were sent.
+    ///
+    /// Returns the number of messages run. Messages sent while this runs,
+    /// including by the messages themselves, are left for the next call. If a
+    /// message panics, the messages after it are dropped without running.
+    pub fn process(&self, state: &mut S) -> usize {
+        self.process_batch(state, usize::MAX)
+    }
+
+    /// Like [`process`][Self::process], but runs at most `max` messages,
+    /// leaving the rest queued, ahead of any sent meanwhile.
+    pub fn process_batch(&self, state: &mut S, max: usize) -> usize {
+        self.lock();
+        let mut batch = Batch { queue: self, list: reverse(self.take_locked()) };
+        self.unlock();
+
+        let mut count = 0;
+        while count < max && !batch.list.is_null() {
+            unsafe {
+                let ptr = batch.list;
+                batch.list = (*ptr).next;
+                self.len.fetch_sub(1, Ordering::Relaxed);
+                ((*ptr).fn_ptr)(ptr, Some(state));
+            }
+            count += 1;
+        }
+        if !batch.list.is_null() {
+            self.lock();
+            unsafe { self.put_back_locked(reverse(batch.list)) };
+            self.unlock();
+            batch.list = ptr::null_mut();
+        }
+        count
+    }
+
+    /// Atomically take every queued message and drop them without running
+    /// them.
+    ///
+    /// Returns the number of messages dropped.
+    pub fn clear(&self) -> usize {
+        self.lock();
+        let list = self.take_locked();
+        self.unlock();
+        unsafe { self.drop_list(list) }
+    }
+
+    /// The number of messages queued.
+    ///
+    /// This is only a snapshot: another thread may send or process messages
+    /// immediately afterwards.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// Whether no messages are queued.
+    ///
+    /// This is only a snapshot, as for [`len`][Self::len].
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // take every queued message, in reverse send order, with the lock held.
+    fn take_locked(&self) -> *mut Header<S> {
+        self.head.swap(ptr::null_mut(), Ordering::Acquire)
+    }
+
+    // link the messages, in reverse send order, back into the queue below any
+    // messages sent since they were taken, with the lock held.
+    unsafe fn put_back_locked(&self, list: *mut Header<S>) {
+        let mut head = self.head.load(Ordering::Acquire);
+        loop {
+            if head.is_null() {
+                match self.head.compare_exchange(head, list, Ordering::Release, Ordering::Acquire) {
+                    Ok(_) => break,
+                    Err(new_head) => head = new_head,
+                }
+            } else {
+                // only sends happen meanwhile, which never write to messages
+                // already in the queue
+                let mut tail = head;
+                while !(*tail).next.is_null() {
+                    tail = (*tail).next;
+                }
+                (*tail).next = list;
+                break;
+            }
+        }
+    }
+
+    // drop every message in the list starting at `ptr`, without running them,
+    // returning how many there were.
+    unsafe fn drop_list(&self, mut ptr: *mut Header<S>) -> usize {
+        let mut count = 0;
+        while !ptr.is_null() {
+            let next = (*ptr).next;
+            self.len.fetch_sub(1, Ordering::Relaxed);
+            ((*ptr).fn_ptr)(ptr, None);
+            ptr = next;
+            count += 1;
+        }
+        count
+    }
+
+    // acquire the lock for taking messages.
+    fn lock(&self) {
+        while self.taking
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            sync::spin_loop();
+        }
+    }
+
+    fn unlock(&self) {
+        self.taking.store(false, Ordering::Release);
+    }
+}
+
+// reverse a list of messages, between send order and reverse send order.
+fn reverse<S>(mut ptr: *mut Header<S>) -> *mut Header<S> {
+    let mut reversed = ptr::null_mut();
+    while !ptr.is_null() {
+        unsafe {
+            let next = (*ptr).next;
+            (*ptr).next = reversed;
+            reversed = ptr;
+            ptr = next;
+        }
+    }
+    reversed
+}
+
+// messages taken to be run, in send order, which are dropped if not run.
+struct Batch<'a, S> {
+    queue: &'a StatefulCallbackQueue<S>,
+    list: *mut Header<S>,
+}
+
+impl<S> Drop for Batch<'_, S> {
+    fn drop(&mut self) {
+        unsafe { self.queue.drop_list(self.list) };
+    }
+}
+
+impl<S> Drop for StatefulCallbackQueue<S> {
+    fn drop(&mut self) {
+        let list = self.head.load_mut();
+        unsafe { self.drop_list(list) };
+    }
+}
+
+impl<S> Default for StatefulCallbackQueue<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Debug for StatefulCallbackQueue<S> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "StatefulCallbackQueue({} QUEUED)", self.len())
+    }
+}
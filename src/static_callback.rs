@@ -0,0 +1,62 @@
+
+use crate::raw;
+use core::{
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+
+// internals
+// ---------
+//
+// a static callback, as described in the `raw` module.
+
+/// A callback which lives forever, for putting into cells without
+/// allocating.
+///
+/// Construct one in a `static`, then put it with
+/// [`CallbackCellArgs::put_ref`][crate::CallbackCellArgs::put_ref] or
+/// [`CallbackCell::put_ref`][crate::CallbackCell::put_ref]. The cell holds a
+/// pointer to it in place of a heap allocation: taking and calling it calls
+/// the callback by reference, and replacing or clearing it drops nothing. In
+/// every other way it behaves like a callback put by value, and either kind
+/// can replace the other.
+///
+/// A `&'static dyn Fn` alone is two pointers, too big for the cell's one, so
+/// the static holds the reference along with what the cell needs to call it.
+///
+// with the `disabled` feature, cells never hold callbacks, so this can't pass
+#[cfg_attr(not(feature = "disabled"), doc = "```")]
+#[cfg_attr(feature = "disabled", doc = "```ignore")]
+/// use callback_cell::{CallbackCellArgs, StaticCallback};
+///
+/// static DOUBLE: StaticCallback<u32, u32> = StaticCallback::new(&|i| i * 2);
+///
+/// let cell = CallbackCellArgs::new();
+/// cell.put_ref(&DOUBLE);
+/// assert_eq!(cell.take_call(2), Ok(4));
+/// ```
+#[repr(transparent)]
+pub struct StaticCallback<I: 'static, O: 'static>(raw::Static<I, O>);
+
+// the callback is only ever called by shared reference, and nothing in the
+// static is written, so a panicking callback leaves it as it was.
+impl<I: 'static, O: 'static> UnwindSafe for StaticCallback<I, O> {}
+impl<I: 'static, O: 'static> RefUnwindSafe for StaticCallback<I, O> {}
+
+impl<I: 'static, O: 'static> StaticCallback<I, O> {
+    /// Construct from a reference to the callback.
+    pub const fn new(f: &'static (dyn Fn(I) -> O + Send + Sync + 'static)) -> Self {
+        StaticCallback(raw::Static::new(f))
+    }
+
+    // the erased callback, for putting into a cell.
+    pub(crate) fn as_raw(&'static self) -> *mut u8 {
+        self.0.as_raw()
+    }
+}
+
+impl<I: 'static, O: 'static> Debug for StaticCallback<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("StaticCallback")
+    }
+}
@@ -0,0 +1,178 @@
+
+use crate::{
+    sync::AtomicBool,
+    raw,
+};
+use core::{
+    sync::atomic::Ordering,
+    cell::UnsafeCell,
+    hint,
+    mem,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+use alloc::vec::Vec;
+
+// internals
+// ---------
+//
+// a spin lock guards the list of pending callbacks and the value. `fire`
+// stores the value, then sets `fired`, then takes the pending callbacks, all
+// while holding the lock. `put` checks `fired` while holding the lock before
+// adding its callback. so a put concurrent with the fire either lands first,
+// and its callback is among those the fire runs, or sees the value, and runs
+// its callback inline. neither side ever runs callbacks or clones the value
+// while holding the lock.
+//
+// once `fired` is set, the value is never written again until the cell drops,
+// so it can be read without the lock after loading `fired` with acquire
+// ordering.
+
+/// A cell for an event which fires once, where callbacks registered after the
+/// event run immediately.
+///
+/// Like a promise: callbacks [`put`][Self::put] before [`fire`][Self::fire]
+/// run on the firing thread, each with a clone of the value. Callbacks put
+/// after run inline on the putting thread, with a clone of the stored value.
+/// The value lives until the cell drops.
+pub struct StickyEventCell<I> {
+    locked: AtomicBool,
+    fired: AtomicBool,
+    pending: UnsafeCell<Vec<raw::Owned<I, ()>>>,
+    value: UnsafeCell<Option<I>>,
+}
+
+// safety: the pending callbacks are only accessed while holding the lock, and
+//         are Send. the value is moved in on the firing thread, so requires
+//         `I: Send`, and read by reference from any thread, so requires
+//         `I: Sync` for the cell to be Sync.
+unsafe impl<I: Send> Send for StickyEventCell<I> {}
+unsafe impl<I: Send + Sync> Sync for StickyEventCell<I> {}
+
+// callbacks run and the value is cloned outside the lock, and the value is
+// stored before anything which could panic, so a panic leaves the cell either
+// unfired or fired with its value.
+impl<I> UnwindSafe for StickyEventCell<I> {}
+impl<I> RefUnwindSafe for StickyEventCell<I> {}
+
+impl<I> StickyEventCell<I> {
+    const_fn! {
+        /// Construct unfired, with no callbacks.
+        ///
+        /// This is a `const fn`, so cells can be placed in statics.
+        pub fn new() -> Self {
+            StickyEventCell {
+                locked: AtomicBool::new(false),
+                fired: AtomicBool::new(false),
+                pending: UnsafeCell::new(Vec::new()),
+                value: UnsafeCell::new(None),
+            }
+        }
+    }
+
+    fn lock(&self) {
+        while self.locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            hint::spin_loop();
+        }
+    }
+
+    fn unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+
+    /// Whether the event has fired.
+    pub fn has_fired(&self) -> bool {
+        self.fired.load(Ordering::Acquire)
+    }
+
+    /// The value the event fired with, if it has fired.
+    pub fn value(&self) -> Option<&I> {
+        if self.has_fired() {
+            // safety: see the internals comment.
+            unsafe { (*self.value.get()).as_ref() }
+        } else {
+            None
+        }
+    }
+
+    /// Fire the event with the given value, running every callback put so far
+    /// on this thread, each with a clone of the value.
+    ///
+    /// If the event has already fired, returns the value instead. If a
+    /// callback panics, the panic propagates, and the callbacks after it are
+    /// dropped without running. The event still counts as fired.
+    pub fn fire(&self, value: I) -> Result<(), I>
+    where
+        I: Clone,
+    {
+        self.lock();
+        if self.fired.load(Ordering::Relaxed) {
+            self.unlock();
+            return Err(value);
+        }
+        // safety: the value is only written while holding the lock, before
+        //         `fired` is set.
+        unsafe { *self.value.get() = Some(value) };
+        self.fired.store(true, Ordering::Release);
+        let pending = mem::take(unsafe { &mut *self.pending.get() });
+        self.unlock();
+
+        let value = self.value().unwrap();
+        for callback in pending {
+            callback.call(value.clone());
+        }
+        Ok(())
+    }
+
+    /// Put a callback to run with a clone of the value when the event fires,
+    /// or run it now if the event has already fired.
+    ///
+    /// Returns true if the callback ran inline. Makes one heap allocation if
+    /// the callback is stored.
+    pub fn put<F: FnOnce(I) + Send + 'static>(&self, f: F) -> bool
+    where
+        I: Clone,
+    {
+        if let Some(value) = self.value() {
+            f(value.clone());
+            return true;
+        }
+        let callback = unsafe { raw::Owned::from_erased(raw::alloc_raw(f)) };
+        self.lock();
+        if self.fired.load(Ordering::Relaxed) {
+            self.unlock();
+            callback.call(self.value().unwrap().clone());
+            return true;
+        }
+        unsafe { (*self.pending.get()).push(callback) };
+        self.unlock();
+        false
+    }
+
+    /// Number of callbacks waiting for the event to fire.
+    pub fn pending(&self) -> usize {
+        self.lock();
+        let n = unsafe { (*self.pending.get()).len() };
+        self.unlock();
+        n
+    }
+}
+
+impl<I> Default for StickyEventCell<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I> Debug for StickyEventCell<I> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.has_fired() {
+            f.write_str("StickyEventCell(FIRED)")
+        } else {
+            write!(f, "StickyEventCell({} pending)", self.pending())
+        }
+    }
+}
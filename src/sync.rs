@@ -0,0 +1,148 @@
+// atomic types used by the cells.
+//
+// with the `portable-atomic` feature, these come from the `portable-atomic`
+// crate, which provides them (typically via critical sections) on targets
+// without native pointer-width atomic swap, such as `thumbv6m`.
+//
+// under `cfg(loom)`, these come from `loom`, so that the cells' protocols can
+// be model-checked. loom's atomics can't be constructed in const context, so
+// constructors which make atomics are wrapped in `const_fn!`, which makes them
+// `const fn` except under loom. loom's atomics also have no `get_mut`, so
+// reading an atomic through a unique reference goes through `LoadMut`.
+// loom can only explore a spin loop which yields, so spinning waits go
+// through `spin_loop`.
+
+#[cfg(all(not(loom), not(feature = "portable-atomic")))]
+pub(crate) use core::sync::atomic::{
+    AtomicPtr,
+    AtomicUsize,
+    AtomicBool,
+    fence,
+};
+
+#[cfg(all(not(loom), feature = "portable-atomic"))]
+pub(crate) use portable_atomic::{
+    AtomicPtr,
+    AtomicUsize,
+    AtomicBool,
+    fence,
+};
+
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::{
+    AtomicPtr,
+    AtomicUsize,
+    AtomicBool,
+    fence,
+};
+
+// the pointer in `CallbackCell` and `CallbackCellArgs`. with the `disabled`
+// feature, a zero-sized stand-in which is always null: storing a pointer in it
+// hands the pointer straight back, as if it were the old value, so the caller
+// drops the callback it was putting, and loading it finds the cell empty. every
+// operation is inlined to a constant, with no branch or atomic access left in
+// the callers once they're optimized.
+#[cfg(not(feature = "disabled"))]
+pub(crate) type CellPtr = AtomicPtr<u8>;
+
+#[cfg(feature = "disabled")]
+pub(crate) struct CellPtr;
+
+#[cfg(feature = "disabled")]
+#[allow(dead_code)]
+impl CellPtr {
+    // callers only construct cells with a callback present through
+    // `disabled`-aware paths.
+    #[inline(always)]
+    pub(crate) const fn new(ptr: *mut u8) -> Self {
+        assert!(ptr.is_null());
+        CellPtr
+    }
+
+    #[inline(always)]
+    pub(crate) fn load(&self, _: core::sync::atomic::Ordering) -> *mut u8 {
+        core::ptr::null_mut()
+    }
+
+    #[inline(always)]
+    pub(crate) fn swap(&self, ptr: *mut u8, _: core::sync::atomic::Ordering) -> *mut u8 {
+        ptr
+    }
+
+    // the exchange only succeeds from null, which would lose the new pointer,
+    // so callers which exchange from null are `disabled`-aware.
+    #[inline(always)]
+    pub(crate) fn compare_exchange(
+        &self,
+        current: *mut u8,
+        _: *mut u8,
+        _: core::sync::atomic::Ordering,
+        _: core::sync::atomic::Ordering,
+    ) -> Result<*mut u8, *mut u8> {
+        debug_assert!(!current.is_null());
+        Err(core::ptr::null_mut())
+    }
+}
+
+// hint that a spinning wait is spinning.
+#[cfg(not(loom))]
+pub(crate) use core::hint::spin_loop;
+
+#[cfg(loom)]
+pub(crate) use loom::hint::spin_loop;
+
+// define a function which is `const fn` except under loom.
+macro_rules! const_fn {
+    ($(#[$attr:meta])* $vis:vis fn $($rest:tt)*) => {
+        #[cfg(not(loom))]
+        $(#[$attr])* $vis const fn $($rest)*
+
+        #[cfg(loom)]
+        $(#[$attr])* $vis fn $($rest)*
+    };
+}
+
+// read an atomic's value through a unique reference.
+pub(crate) trait LoadMut {
+    type Value;
+
+    fn load_mut(&mut self) -> Self::Value;
+}
+
+impl<T> LoadMut for AtomicPtr<T> {
+    type Value = *mut T;
+
+    #[cfg(not(loom))]
+    fn load_mut(&mut self) -> *mut T {
+        *self.get_mut()
+    }
+
+    #[cfg(loom)]
+    fn load_mut(&mut self) -> *mut T {
+        self.with_mut(|ptr| *ptr)
+    }
+}
+
+impl LoadMut for AtomicUsize {
+    type Value = usize;
+
+    #[cfg(not(loom))]
+    fn load_mut(&mut self) -> usize {
+        *self.get_mut()
+    }
+
+    #[cfg(loom)]
+    fn load_mut(&mut self) -> usize {
+        self.with_mut(|value| *value)
+    }
+}
+
+#[cfg(feature = "disabled")]
+impl LoadMut for CellPtr {
+    type Value = *mut u8;
+
+    #[inline(always)]
+    fn load_mut(&mut self) -> *mut u8 {
+        core::ptr::null_mut()
+    }
+}
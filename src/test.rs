@@ -5,14 +5,45 @@ use std::{
         Arc,
         atomic::{
             AtomicU32,
+            AtomicBool,
             Ordering,
         },
     },
     ops::Add,
+    boxed::Box,
+    ffi::c_void,
+    task::{Wake, Waker, Context, Poll},
+    future::Future,
+    pin::{pin, Pin},
+    thread,
+    vec::Vec,
 };
 
+// waker which counts how many times it's been woken.
+struct CountingWaker(AtomicU32);
+
+impl CountingWaker {
+    fn new() -> (Arc<Self>, Waker) {
+        let arc = Arc::new(CountingWaker(AtomicU32::new(0)));
+        let waker = Waker::from(Arc::clone(&arc));
+        (arc, waker)
+    }
+
+    fn count(&self) -> u32 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
 #[test]
 fn without_args_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
     let counter = Arc::new(AtomicU32::new(0));
     let cell = CallbackCell::new();
     assert_eq!(counter.load(Ordering::Relaxed), 0);
@@ -53,10 +84,25 @@ fn without_args_test() {
     assert_eq!(counter.load(Ordering::Relaxed), 104);
     assert!(!cell.take_call());
     assert_eq!(counter.load(Ordering::Relaxed), 104);
+    assert!(!cell.is_set());
+    cell.put({
+        let counter = Arc::clone(&counter);
+        move || {
+            counter.fetch_add(1000, Ordering::Relaxed);
+        }
+    });
+    assert!(cell.is_set());
+    assert!(cell.clear());
+    assert!(!cell.is_set());
+    assert!(!cell.clear());
+    assert_eq!(counter.load(Ordering::Relaxed), 104);
+    assert_eq!(Arc::strong_count(&counter), 1);
 }
 
 #[test]
 fn with_args_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
     #[derive(Debug)]
     struct Thing(i32, &'static AtomicU32);
     impl From<i32> for Thing {
@@ -122,3 +168,5616 @@ fn with_args_test() {
     assert_eq!(counter.load(Ordering::Relaxed), 104);
 }
 
+#[test]
+fn callback_cell_input_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    static CELL: CallbackCellInput<u32> = CallbackCellInput::new();
+    let sum = Arc::new(AtomicU32::new(0));
+    let add = || {
+        let sum = Arc::clone(&sum);
+        move |i: u32| { sum.fetch_add(i, Ordering::SeqCst); }
+    };
+    assert_eq!(CELL.take_call(1), Err(1));
+    CELL.put(add());
+    assert!(CELL.is_set());
+    assert_eq!(CELL.take_call(2), Ok(()));
+    assert_eq!(CELL.take_call(3), Err(3));
+    assert_eq!(sum.load(Ordering::SeqCst), 2);
+
+    // the same callback as the args cell
+    CELL.put(add());
+    assert_eq!(CELL.as_args().take_call(4), Ok(()));
+    let args = CallbackCellArgs::new_with(add());
+    assert_eq!(CallbackCellInput::from_args(&args).take_call(5), Ok(()));
+    args.put(add());
+    let cell = CallbackCellInput::from(args);
+    assert_eq!(std::format!("{:?}", cell), "CallbackCellInput(NOT NULL)");
+    let args = cell.into_args();
+    assert_eq!(args.take_call(6), Ok(()));
+    assert_eq!(sum.load(Ordering::SeqCst), 17);
+
+    let graveyard = Graveyard::new();
+    let cell: CallbackCellInput<u32> = add().into();
+    assert_eq!(cell.take_call_defer_free(7, &graveyard), Ok(()));
+    assert_eq!(graveyard.collect(), 1);
+    cell.put(add());
+    assert!(cell.clear());
+    assert!(!cell.clear());
+    assert_eq!(sum.load(Ordering::SeqCst), 24);
+}
+
+#[test]
+fn callback_cell_output_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    static SUMMARY: CallbackCellOutput<Box<u32>> = CallbackCellOutput::new();
+    assert_eq!(SUMMARY.take_call(), None);
+    SUMMARY.put(|| Box::new(1));
+    assert!(SUMMARY.is_set());
+    assert_eq!(SUMMARY.take_call(), Some(Box::new(1)));
+    assert_eq!(SUMMARY.take_call(), None);
+
+    // never collected, so never computed
+    let computed = Arc::new(AtomicBool::new(false));
+    let cell = CallbackCellOutput::new_with({
+        let computed = Arc::clone(&computed);
+        move || computed.store(true, Ordering::SeqCst)
+    });
+    assert_eq!(std::format!("{:?}", cell), "CallbackCellOutput(NOT NULL)");
+    drop(cell);
+    assert!(!computed.load(Ordering::SeqCst));
+
+    // the same callback as the args cell
+    SUMMARY.put(|| Box::new(2));
+    assert_eq!(SUMMARY.as_args().take_call(()), Ok(Box::new(2)));
+    let args = CallbackCellArgs::new_with(|()| 3);
+    assert_eq!(CallbackCellOutput::from_args(&args).take_call(), Some(3));
+    args.put(|()| 4);
+    let cell = CallbackCellOutput::from(args);
+    assert_eq!(cell.take_call(), Some(4));
+    let cell: CallbackCellOutput<u32> = (|| 5).into();
+    assert!(cell.clear());
+    assert!(!cell.clear());
+    assert_eq!(cell.into_args().take_call(()), Err(()));
+}
+
+#[test]
+fn unpacked_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell = CallbackCellArgs::<(u32,), u32>::new();
+    assert_eq!(cell.take_call_unpacked(1), Err((1,)));
+    cell.put_unpacked(|a| a + 1);
+    assert_eq!(cell.take_call_unpacked(1), Ok(2));
+
+    let cell = CallbackCellArgs::<(&str, Vec<u8>, usize), _>::new();
+    cell.put_unpacked(|sock, buf, len| std::format!("{}:{:?}", sock, &buf[..len]));
+    assert_eq!(cell.take_call_unpacked("a", std::vec![1, 2, 3], 2).unwrap(), "a:[1, 2]");
+    assert_eq!(cell.take_call_unpacked("b", std::vec![], 0), Err(("b", std::vec![], 0)));
+
+    // the same callbacks as the packed forms
+    let cell = CallbackCellArgs::<(u32, u32), u32>::new();
+    cell.put(|(a, b)| a * b);
+    assert_eq!(cell.take_call_unpacked(2, 3), Ok(6));
+    cell.put_unpacked(|a, b| a - b);
+    assert_eq!(cell.take_call((5, 3)), Ok(2));
+
+    let cell = CallbackCellArgs::<(u8, u16, u32, u64, u8, u16, u32, u64), u64>::new();
+    cell.put_unpacked(|a, b, c, d, e, g, h, j| {
+        a as u64 + b as u64 + c as u64 + d + e as u64 + g as u64 + h as u64 + j
+    });
+    assert_eq!(cell.take_call_unpacked(1, 2, 3, 4, 5, 6, 7, 8), Ok(36));
+
+    // dropped unrun
+    let dropped = Arc::new(());
+    let cell = CallbackCellArgs::<(u8, u8, u8, u8), ()>::new();
+    cell.put_unpacked({
+        let dropped = Arc::clone(&dropped);
+        move |_, _, _, _| drop(dropped)
+    });
+    drop(cell);
+    assert_eq!(Arc::strong_count(&dropped), 1);
+}
+
+#[test]
+fn take_call_fast_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell = CallbackCell::new();
+    assert!(!cell.take_call_fast());
+    let ran = Arc::new(AtomicBool::new(false));
+    cell.put({
+        let ran = Arc::clone(&ran);
+        move || ran.store(true, Ordering::SeqCst)
+    });
+    assert!(cell.take_call_fast());
+    assert!(ran.load(Ordering::SeqCst));
+    assert!(!cell.take_call_fast());
+
+    let cell = CallbackCellArgs::<u32, u32>::new();
+    assert_eq!(cell.take_call_fast(1), Err(1));
+    cell.put(|i| i + 1);
+    assert_eq!(cell.take_call_fast(1), Ok(2));
+    assert_eq!(cell.take_call_fast(1), Err(1));
+
+    // a put on another thread which is joined is always seen
+    let cell = Arc::new(CallbackCellArgs::<u32, u32>::new());
+    thread::spawn({
+        let cell = Arc::clone(&cell);
+        move || cell.put(|i| i * 2)
+    }).join().unwrap();
+    assert_eq!(cell.take_call_fast(2), Ok(4));
+}
+
+#[test]
+fn put_ref_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    static COUNT: AtomicU32 = AtomicU32::new(0);
+    static DOUBLE: StaticCallback<u32, u32> = StaticCallback::new(&|i| i * 2);
+    static COUNT_UP: StaticCallback<(), ()> = StaticCallback::new(&|()| {
+        COUNT.fetch_add(1, Ordering::SeqCst);
+    });
+
+    let mut cell = CallbackCellArgs::new();
+    cell.put_ref(&DOUBLE);
+    assert!(cell.is_set());
+    assert_eq!(cell.allocated_bytes(), 0);
+    assert_eq!(std::format!("{:?}", cell), "CallbackCellArgs(armed: \"<unnamed>\")");
+    assert_eq!(cell.take_call(2), Ok(4));
+    assert_eq!(cell.take_call(2), Err(2));
+
+    // replacing in either direction
+    let dropped = Arc::new(());
+    cell.put({
+        let dropped = Arc::clone(&dropped);
+        move |i| { drop(dropped); i + 1 }
+    });
+    cell.put_ref(&DOUBLE);
+    assert_eq!(Arc::strong_count(&dropped), 1);
+    cell.put(|i| i + 1);
+    assert!(cell.allocated_bytes() > 0);
+    assert_eq!(cell.take_call(2), Ok(3));
+    cell.put_ref(&DOUBLE);
+    cell.put_ref(&DOUBLE);
+    assert_eq!(cell.take_call(3), Ok(6));
+
+    // through the raw pointer and the graveyard
+    cell.put_ref(&DOUBLE);
+    let ptr = cell.take_raw();
+    assert_eq!(unsafe { CallbackCellArgs::run_raw(ptr, 4) }, 8);
+    let graveyard = Graveyard::new();
+    cell.put_ref(&DOUBLE);
+    assert_eq!(cell.take_call_defer_free(5, &graveyard), Ok(10));
+    assert!(graveyard.is_empty());
+
+    // dropped with the cell, dropping nothing
+    cell.put_ref(&DOUBLE);
+    drop(cell);
+
+    let cell = CallbackCell::new();
+    cell.put_ref(&COUNT_UP);
+    assert!(cell.take_call());
+    cell.put_ref(&COUNT_UP);
+    assert!(cell.clear());
+    assert!(!cell.take_call());
+    cell.put_ref(&COUNT_UP);
+    assert!(cell.take_call());
+    assert_eq!(COUNT.load(Ordering::SeqCst), 2);
+    assert_eq!(std::format!("{:?}", COUNT_UP), "StaticCallback");
+}
+
+#[test]
+fn new_with_fn_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    static COUNT: AtomicU32 = AtomicU32::new(0);
+    fn count_up(i: u32) -> u32 {
+        COUNT.fetch_add(i, Ordering::SeqCst) + i
+    }
+    static SINK: CallbackCellArgs<u32, u32> = CallbackCellArgs::new_with_fn(count_up);
+
+    // armed from the start, without allocating
+    assert!(SINK.is_set());
+    assert_eq!(SINK.take_call(2), Ok(2));
+    assert_eq!(SINK.take_call(2), Err(2));
+    assert_eq!(test_util::live_allocations(), 0);
+
+    // replacing and clearing it drops nothing
+    let mut cell = CallbackCellArgs::new_with_fn(count_up);
+    assert_eq!(cell.allocated_bytes(), 0);
+    cell.put(|i| i + 1);
+    assert_eq!(cell.take_call(2), Ok(3));
+    let cell = CallbackCellArgs::new_with_fn(|i: u32| i * 2);
+    assert!(cell.clear());
+    let cell = CallbackCellArgs::new_with_fn(count_up);
+    let ptr = cell.take_raw();
+    assert_eq!(unsafe { CallbackCellArgs::run_raw(ptr, 3) }, 5);
+    let graveyard = Graveyard::new();
+    let cell = CallbackCellArgs::new_with_fn(count_up);
+    assert_eq!(cell.take_call_defer_free(1, &graveyard), Ok(6));
+    assert!(graveyard.is_empty());
+    drop(CallbackCellArgs::new_with_fn(count_up));
+    assert_eq!(COUNT.load(Ordering::SeqCst), 6);
+}
+
+#[test]
+fn put_prealloc_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let mut cell = CallbackCellArgs::<u32, u32>::new();
+
+    // made ahead of time, then put without allocating
+    let slot = ArgsSlot::with_capacity(16, 8);
+    assert_eq!((slot.capacity(), slot.align()), (16, 8));
+    let bytes = slot.allocated_bytes();
+    assert_eq!(test_util::live_allocations(), 1);
+    let offset = 5u64;
+    assert!(slot.fits_val(&move |i: u32| i + offset as u32));
+    assert!(cell.put_prealloc(slot, move |i| i + offset as u32).is_ok());
+    assert_eq!(test_util::live_allocations(), 1);
+    assert_eq!(cell.allocated_bytes(), bytes);
+    assert_eq!(cell.take_call(1), Ok(6));
+    assert_eq!(test_util::live_allocations(), 0);
+
+    // too big, or too aligned, is given back
+    let slot = ArgsSlot::with_capacity(8, 8);
+    let big = [1u32; 4];
+    let (slot, f) = cell.put_prealloc(slot, move |i| i + big[0]).unwrap_err();
+    assert!(!cell.is_set());
+    #[repr(align(16))]
+    struct Aligned(u32);
+    let aligned = Aligned(2);
+    let (slot, _) = cell.put_prealloc(slot, move |i| { let aligned = aligned; i + aligned.0 }).unwrap_err();
+    cell.put(f);
+    assert_eq!(cell.take_call(1), Ok(2));
+
+    // unused slots are freed
+    drop(slot);
+    assert_eq!(test_util::live_allocations(), 0);
+
+    // replacing, and being replaced, drops the callback
+    let dropped = Arc::new(());
+    let d = Arc::clone(&dropped);
+    cell.put(|i| i);
+    assert!(cell.put_prealloc(ArgsSlot::with_capacity(8, 8), move |i| { drop(d); i }).is_ok());
+    cell.put(|i| i * 3);
+    assert_eq!(Arc::strong_count(&dropped), 1);
+    assert_eq!(cell.take_call(2), Ok(6));
+
+    // through the graveyard
+    let graveyard = Graveyard::new();
+    assert!(cell.put_prealloc(ArgsSlot::with_capacity(0, 1), |i| i + 1).is_ok());
+    assert_eq!(cell.take_call_defer_free(1, &graveyard), Ok(2));
+    assert_eq!(test_util::live_allocations(), 1);
+    assert_eq!(graveyard.collect(), 1);
+
+    // dropped with the cell
+    assert!(cell.put_prealloc(ArgsSlot::with_capacity(8, 8), |i| i).is_ok());
+    drop(cell);
+    test_util::assert_no_live_allocations();
+}
+
+#[test]
+fn put_with_cleanup_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let discarded = Arc::new(AtomicU32::new(0));
+    let on_discard = || {
+        let discarded = Arc::clone(&discarded);
+        move || {
+            discarded.fetch_add(1, Ordering::SeqCst);
+        }
+    };
+
+    // running the callback never runs the cleanup
+    let cell = CallbackCellArgs::<u32, u32>::new();
+    cell.put_with_cleanup(|i| i + 1, on_discard());
+    assert_eq!(cell.take_call(1), Ok(2));
+    assert_eq!(discarded.load(Ordering::SeqCst), 0);
+
+    // replaced, cleared, or dropped with the cell
+    cell.put_with_cleanup(|i| i + 1, on_discard());
+    cell.put(|i| i + 2);
+    assert_eq!(discarded.load(Ordering::SeqCst), 1);
+    cell.put_with_cleanup(|i| i + 1, on_discard());
+    assert!(cell.clear());
+    assert_eq!(discarded.load(Ordering::SeqCst), 2);
+    cell.put_with_cleanup(|i| i + 1, on_discard());
+    drop(cell);
+    assert_eq!(discarded.load(Ordering::SeqCst), 3);
+
+    // a panicking callback counts as having run
+    let cell = CallbackCell::new();
+    cell.put_with_cleanup(|| panic!("put_with_cleanup_test"), on_discard());
+    assert!(std::panic::catch_unwind(|| cell.take_call()).is_err());
+    assert_eq!(discarded.load(Ordering::SeqCst), 3);
+    cell.put_with_cleanup(|| (), on_discard());
+    assert!(cell.take_call());
+    cell.put_with_cleanup(|| (), on_discard());
+    drop(cell);
+    assert_eq!(discarded.load(Ordering::SeqCst), 4);
+}
+
+#[test]
+fn put_map_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    struct DropGuardThing(Arc<AtomicU32>);
+    impl Drop for DropGuardThing {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    // one allocation, for both pieces
+    let cell = CallbackCellArgs::<&'static str, u32>::new();
+    cell.put_map_input(|s: &str| s.len(), |n: usize| n as u32 * 2);
+    assert_eq!(test_util::live_allocations(), 1);
+    assert_eq!(cell.take_call("four"), Ok(8));
+    cell.put_map_output(|s: &str| s.to_uppercase(), |s: std::string::String| s.len() as u32 + 1);
+    assert_eq!(test_util::live_allocations(), 1);
+    assert_eq!(cell.take_call("abc"), Ok(4));
+    assert_eq!(test_util::live_allocations(), 0);
+
+    // both pieces are dropped exactly once, when replaced, dropped with the
+    // cell, or run
+    let dropped = Arc::new(AtomicU32::new(0));
+    let guards = || (DropGuardThing(Arc::clone(&dropped)), DropGuardThing(Arc::clone(&dropped)));
+    let (a, b) = guards();
+    cell.put_map_input(move |s: &str| { let _ = &a; s.len() }, move |n| { let _ = &b; n as u32 });
+    let (a, b) = guards();
+    cell.put_map_output(move |s: &str| { let _ = &a; s.len() }, move |n| { let _ = &b; n as u32 });
+    assert_eq!(dropped.load(Ordering::SeqCst), 2);
+    assert_eq!(cell.take_call("ab"), Ok(2));
+    assert_eq!(dropped.load(Ordering::SeqCst), 4);
+    let (a, b) = guards();
+    cell.put_map_input(move |s: &str| { let _ = &a; s.len() }, move |n| { let _ = &b; n as u32 });
+    drop(cell);
+    assert_eq!(dropped.load(Ordering::SeqCst), 6);
+}
+
+#[test]
+fn put_with_bytes_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    // one allocation, for the callback and its bytes
+    let mut cell = CallbackCellArgs::<u8, Vec<u8>>::new();
+    let frame: Vec<u8> = (0..200).collect();
+    cell.put_with_bytes(&frame, |bytes, i| {
+        bytes[0] = i;
+        bytes.to_vec()
+    });
+    assert_eq!(test_util::live_allocations(), 1);
+    assert!(cell.allocated_bytes() >= 200);
+    let output = cell.take_call(7).unwrap();
+    assert_eq!(output[0], 7);
+    assert_eq!(output[1..], frame[1..]);
+    assert_eq!(test_util::live_allocations(), 0);
+
+    // the bytes follow a callback of any alignment, and may be empty
+    #[repr(align(64))]
+    struct Aligned(u8);
+    let aligned = Aligned(3);
+    cell.put_with_bytes(&[1, 2], move |bytes, i| std::vec![bytes[0] + bytes[1] + aligned.0 + i]);
+    assert_eq!(cell.take_call(4), Ok(std::vec![10]));
+    cell.put_with_bytes(&[], |bytes, _| bytes.to_vec());
+    assert_eq!(cell.take_call(4), Ok(Vec::new()));
+    assert_eq!(test_util::live_allocations(), 0);
+
+    // replaced, cleared, or dropped with the cell, the callback is dropped
+    // and the allocation freed once
+    let dropped = Arc::new(());
+    let put = |cell: &CallbackCellArgs<u8, Vec<u8>>| {
+        let d = Arc::clone(&dropped);
+        cell.put_with_bytes(&frame, move |_, _| { drop(d); Vec::new() });
+    };
+    put(&cell);
+    put(&cell);
+    assert_eq!(Arc::strong_count(&dropped), 2);
+    assert!(cell.clear());
+    assert_eq!(Arc::strong_count(&dropped), 1);
+    put(&cell);
+    drop(cell);
+    assert_eq!(Arc::strong_count(&dropped), 1);
+    assert_eq!(test_util::live_allocations(), 0);
+
+    // freed even if the callback panics, and left freed for the graveyard
+    let cell = CallbackCell::new();
+    cell.put_with_bytes(&frame, |_| panic!("bytes"));
+    assert!(std::panic::catch_unwind(|| cell.take_call()).is_err());
+    assert_eq!(test_util::live_allocations(), 0);
+    let graveyard = Graveyard::new();
+    let sum = Arc::new(AtomicU32::new(0));
+    let s = Arc::clone(&sum);
+    cell.put_with_bytes(&frame, move |bytes| {
+        s.store(bytes.iter().map(|&b| b as u32).sum(), Ordering::SeqCst);
+    });
+    assert!(cell.take_call_defer_free(&graveyard));
+    assert_eq!(sum.load(Ordering::SeqCst), (0..200).sum::<u32>());
+    assert_eq!(test_util::live_allocations(), 1);
+    assert_eq!(graveyard.collect(), 1);
+    assert_eq!(test_util::live_allocations(), 0);
+}
+
+#[test]
+fn put_around_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let cell = CallbackCellArgs::<u32, u32>::new();
+
+    // nothing to wrap
+    let wrap_log = Arc::clone(&log);
+    assert!(!cell.put_around(move |inner, i| {
+        wrap_log.lock().unwrap().push("unreachable");
+        inner(i)
+    }));
+    assert!(!cell.is_set());
+    assert_eq!(Arc::strong_count(&log), 1);
+
+    // wrappers nest, the last outermost
+    cell.put(|i| i * 10);
+    for name in ["inner", "outer"] {
+        let log = Arc::clone(&log);
+        assert!(cell.put_around(move |f, i| {
+            log.lock().unwrap().push(name);
+            let o = f(i + 1);
+            log.lock().unwrap().push(name);
+            o
+        }));
+    }
+    assert_eq!(cell.take_call(1), Ok(30));
+    assert_eq!(*log.lock().unwrap(), ["outer", "inner", "inner", "outer"]);
+    assert!(!cell.is_set());
+
+    // a wrapper which doesn't run the original drops it
+    struct DropGuardThing(Arc<AtomicU32>);
+    impl Drop for DropGuardThing {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+    let dropped = Arc::new(AtomicU32::new(0));
+    let guard = DropGuardThing(Arc::clone(&dropped));
+    cell.put(move |i| {
+        let _ = &guard;
+        i
+    });
+    assert!(cell.put_around(|_, i| i + 100));
+    assert_eq!(dropped.load(Ordering::SeqCst), 0);
+    assert_eq!(cell.take_call(1), Ok(101));
+    assert_eq!(dropped.load(Ordering::SeqCst), 1);
+
+    // dropped with the cell, unrun
+    let guard = DropGuardThing(Arc::clone(&dropped));
+    cell.put(move |i| {
+        let _ = &guard;
+        i
+    });
+    assert!(cell.put_around(|f, i| f(i)));
+    drop(cell);
+    assert_eq!(dropped.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn replace_if_current_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell = CallbackCellArgs::<u32, u32>::new();
+    let token = cell.put_tracked(|i| i + 1);
+    let Ok(token) = cell.replace_if_current(&token, |i| i + 2) else { panic!() };
+    assert_eq!(cell.take_call(1), Ok(3));
+    // taken, so the closure comes back untouched
+    let f = cell.replace_if_current(&token, |i| i + 3).unwrap_err();
+    assert_eq!(f(1), 4);
+    assert!(!cell.is_set());
+
+    // superseded by another put
+    let token = cell.put_tracked(|i| i + 1);
+    let other = cell.put_tracked(|i| i + 10);
+    assert_ne!(token, other);
+    assert!(cell.replace_if_current(&token, |i| i + 3).is_err());
+    assert_eq!(cell.take_call(1), Ok(11));
+}
+
+#[test]
+fn begin_take_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell = CallbackCellArgs::<u32, u32>::new();
+    assert!(cell.begin_take().is_none());
+
+    // committing runs the callback, leaving the cell empty
+    cell.put(|i| i + 1);
+    let guard = cell.begin_take().unwrap();
+    assert!(!cell.is_set());
+    assert_eq!(guard.commit(1), 2);
+    assert!(!cell.is_set());
+
+    // aborting puts it back, as does dropping the guard
+    cell.put(|i| i + 2);
+    assert!(cell.begin_take().unwrap().abort().is_ok());
+    drop(cell.begin_take().unwrap());
+    assert_eq!(std::format!("{:?}", cell.begin_take().unwrap()), "TakeGuard");
+    assert_eq!(cell.take_call(1), Ok(3));
+
+    // a callback put meanwhile stays. aborting hands the taken one back, and
+    // dropping the guard drops it
+    let dropped = Arc::new(());
+    let d = Arc::clone(&dropped);
+    cell.put(move |i| { drop(d); i + 3 });
+    let guard = cell.begin_take().unwrap();
+    cell.put(|i| i + 4);
+    let taken = guard.abort().unwrap_err();
+    assert_eq!(taken.call(1), 4);
+    let d = Arc::clone(&dropped);
+    cell.put(move |i| { drop(d); i + 3 });
+    let guard = cell.begin_take().unwrap();
+    cell.put(|i| i + 4);
+    drop(guard);
+    assert_eq!(Arc::strong_count(&dropped), 1);
+    assert_eq!(cell.take_call(1), Ok(5));
+    assert_eq!(test_util::live_allocations(), 0);
+}
+
+#[test]
+fn put_named_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let mut cell = CallbackCellArgs::<u32, u32>::new();
+    assert_eq!(cell.label(), None);
+    cell.put_named("double", |i| i * 2);
+    assert_eq!(cell.label(), Some("double"));
+    // one heap allocation, with room for the label
+    assert_eq!(test_util::live_allocations(), 1);
+    let mut unlabeled = CallbackCellArgs::<u32, u32>::new();
+    unlabeled.put(|i| i * 2);
+    assert_eq!(cell.allocated_bytes(), unlabeled.allocated_bytes() + std::mem::size_of::<&str>());
+    drop(unlabeled);
+    assert_eq!(std::format!("{:?}", cell), "CallbackCellArgs(armed: \"double\")");
+    // reading the label puts the callback back
+    assert_eq!(cell.label(), Some("double"));
+    assert_eq!(test_util::live_allocations(), 1);
+    assert_eq!(cell.take_call(2), Ok(4));
+    assert_eq!(cell.label(), None);
+    assert_eq!(std::format!("{:?}", cell), "CallbackCellArgs(NULL)");
+
+    // unlabeled callbacks have no label, and replace labeled ones
+    cell.put_named("double", |i| i * 2);
+    cell.put(|i| i + 1);
+    assert_eq!(cell.label(), None);
+    assert_eq!(std::format!("{:?}", cell), "CallbackCellArgs(armed: \"<unnamed>\")");
+    cell.put_named("triple", |i| i * 3);
+    cell.put_named("quadruple", |i| i * 4);
+    assert_eq!(cell.label(), Some("quadruple"));
+    assert!(cell.clear());
+    assert_eq!(cell.label(), None);
+
+    // through the graveyard
+    let graveyard = Graveyard::new();
+    cell.put_named("double", |i| i * 2);
+    assert_eq!(cell.take_call_defer_free(3, &graveyard), Ok(6));
+    assert_eq!(cell.label(), None);
+    assert_eq!(graveyard.collect(), 1);
+
+    // a panicking labeled callback still unwinds, leaving the cell empty
+    cell.put_named("oh no", |_| panic!("oh no"));
+    let result = std::panic::catch_unwind(|| cell.take_call(1));
+    assert!(result.is_err());
+    assert!(!cell.is_set());
+    assert_eq!(cell.label(), None);
+
+    cell.put_named("dropped", |i| i);
+    drop(cell);
+
+    let cell = CallbackCell::new();
+    cell.put_named("retry-dns-lookup", || ());
+    assert_eq!(cell.label(), Some("retry-dns-lookup"));
+    assert_eq!(std::format!("{:?}", cell), "CallbackCell(armed: \"retry-dns-lookup\")");
+    assert_eq!(test_util::live_allocations(), 1);
+    assert!(cell.take_call());
+    assert_eq!(cell.label(), None);
+    assert_eq!(std::format!("{:?}", cell), "CallbackCell(NULL)");
+}
+
+
+// `new` being a `const fn` is an API guarantee.
+static STATIC_CELL: CallbackCell = CallbackCell::new();
+static STATIC_CELL_ARGS: CallbackCellArgs<u32, u32> = CallbackCellArgs::new();
+
+#[test]
+fn static_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let counter = Arc::new(AtomicU32::new(0));
+    STATIC_CELL.put({
+        let counter = Arc::clone(&counter);
+        move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    assert!(STATIC_CELL.take_call());
+    assert!(!STATIC_CELL.take_call());
+    assert_eq!(counter.load(Ordering::Relaxed), 1);
+    STATIC_CELL_ARGS.put(|i| i + 1);
+    assert_eq!(STATIC_CELL_ARGS.take_call(1), Ok(2));
+    assert_eq!(STATIC_CELL_ARGS.take_call(1), Err(1));
+}
+
+#[test]
+fn c_trampoline_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    static CELL: CallbackCell = CallbackCell::new();
+    static CELL_ARGS: CallbackCellArgs<*mut c_void, ()> = CallbackCellArgs::new();
+
+    let counter = Arc::new(AtomicU32::new(0));
+    let (cb, ctx) = CELL.as_c_trampoline();
+    unsafe { cb(ctx) };
+    CELL.put({
+        let counter = Arc::clone(&counter);
+        move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    unsafe { cb(ctx) };
+    unsafe { cb(ctx) };
+    assert_eq!(counter.load(Ordering::Relaxed), 1);
+
+    let (cb, ctx) = CELL_ARGS.as_c_trampoline();
+    let mut data = 0u32;
+    unsafe { cb(ctx, &mut data as *mut u32 as *mut c_void) };
+    CELL_ARGS.put(|data| unsafe {
+        *(data as *mut u32) += 5;
+    });
+    unsafe { cb(ctx, &mut data as *mut u32 as *mut c_void) };
+    unsafe { cb(ctx, &mut data as *mut u32 as *mut c_void) };
+    assert_eq!(data, 5);
+}
+
+#[test]
+fn raw_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let counter = Arc::new(AtomicU32::new(0));
+    let cell = CallbackCell::new();
+    assert!(cell.take_raw().is_null());
+    cell.put({
+        let counter = Arc::clone(&counter);
+        move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    let ptr = cell.take_raw();
+    assert!(!ptr.is_null());
+    assert!(!cell.take_call());
+    unsafe { cell.put_raw(ptr) };
+    assert!(cell.take_call());
+    assert_eq!(counter.load(Ordering::Relaxed), 1);
+    cell.put({
+        let counter = Arc::clone(&counter);
+        move || {
+            counter.fetch_add(2, Ordering::Relaxed);
+        }
+    });
+    unsafe { CallbackCell::run_raw(cell.take_raw()) };
+    assert_eq!(counter.load(Ordering::Relaxed), 3);
+    cell.put({
+        let counter = Arc::clone(&counter);
+        move || {
+            counter.fetch_add(4, Ordering::Relaxed);
+        }
+    });
+    unsafe { CallbackCell::drop_raw_ptr(cell.take_raw()) };
+    assert_eq!(counter.load(Ordering::Relaxed), 3);
+    assert_eq!(Arc::strong_count(&counter), 1);
+
+    let cell: CallbackCellArgs<u32, u32> = CallbackCellArgs::new();
+    assert!(cell.take_raw().is_null());
+    cell.put(|i| i * 2);
+    let ptr = cell.take_raw().into_ptr();
+    assert_eq!(cell.take_call(1), Err(1));
+    let ptr = unsafe { RawCallbackPtr::<u32, u32>::from_ptr(ptr) };
+    unsafe { cell.put_raw(ptr) };
+    let ptr = cell.take_raw();
+    assert_eq!(unsafe { CallbackCellArgs::run_raw(ptr, 21) }, 42);
+    cell.put({
+        let counter = Arc::clone(&counter);
+        move |i| i + counter.load(Ordering::Relaxed)
+    });
+    unsafe { CallbackCellArgs::drop_raw_ptr(cell.take_raw()) };
+    assert_eq!(Arc::strong_count(&counter), 1);
+}
+
+#[test]
+fn waker_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let counter = Arc::new(AtomicU32::new(0));
+    let cell = Arc::new(CallbackCell::new());
+    let waker = Arc::clone(&cell).into_waker();
+    waker.wake_by_ref();
+    cell.put({
+        let counter = Arc::clone(&counter);
+        move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    waker.wake_by_ref();
+    waker.wake_by_ref();
+    assert_eq!(counter.load(Ordering::Relaxed), 1);
+    cell.put({
+        let counter = Arc::clone(&counter);
+        move || {
+            counter.fetch_add(2, Ordering::Relaxed);
+        }
+    });
+    let waker_2 = waker.clone();
+    drop(waker);
+    waker_2.wake();
+    assert_eq!(counter.load(Ordering::Relaxed), 3);
+    assert_eq!(Arc::strong_count(&cell), 1);
+}
+
+#[test]
+fn waker_cell_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell = WakerCell::new();
+    let (counter_1, waker_1) = CountingWaker::new();
+    let (counter_2, waker_2) = CountingWaker::new();
+    assert!(!cell.wake());
+    assert!(cell.take().is_none());
+    cell.register(&waker_1);
+    assert!(cell.wake());
+    assert!(!cell.wake());
+    assert_eq!(counter_1.count(), 1);
+    cell.register(&waker_1);
+    cell.register(&waker_2);
+    assert!(cell.wake());
+    assert_eq!(counter_1.count(), 1);
+    assert_eq!(counter_2.count(), 1);
+    cell.register(&waker_1);
+    cell.take().unwrap().wake();
+    assert_eq!(counter_1.count(), 2);
+    assert!(cell.take().is_none());
+    cell.register(&waker_2);
+    drop(cell);
+    assert_eq!(counter_1.count(), 2);
+    assert_eq!(counter_2.count(), 1);
+    assert_eq!(Arc::strong_count(&counter_1), 2);
+    assert_eq!(Arc::strong_count(&counter_2), 2);
+}
+
+#[test]
+fn waker_cell_threads_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell = Arc::new(WakerCell::new());
+    let (counter, waker) = CountingWaker::new();
+    let threads = (0..4)
+        .map(|_| {
+            let cell = Arc::clone(&cell);
+            let waker = waker.clone();
+            thread::spawn(move || {
+                let mut woken = 0;
+                for _ in 0..10000 {
+                    if cell.register(&waker) == RegisterOutcome::Woken {
+                        woken += 1;
+                    }
+                    if cell.wake() {
+                        woken += 1;
+                    }
+                }
+                woken
+            })
+        })
+        .collect::<Vec<_>>();
+    let woken: u32 = threads.into_iter().map(|t| t.join().unwrap()).sum();
+    assert!(!cell.wake());
+    assert_eq!(woken, counter.count());
+    assert_eq!(Arc::strong_count(&counter), 2);
+}
+
+#[test]
+fn waker_cell_no_lost_wakeup_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    // waker which unparks a thread
+    struct Unparker(thread::Thread);
+    impl Wake for Unparker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    for _ in 0..1000 {
+        let cell = Arc::new(WakerCell::new());
+        let ready = Arc::new(AtomicBool::new(false));
+        let producer = thread::spawn({
+            let cell = Arc::clone(&cell);
+            let ready = Arc::clone(&ready);
+            move || {
+                ready.store(true, Ordering::SeqCst);
+                cell.wake();
+            }
+        });
+        let waker = Waker::from(Arc::new(Unparker(thread::current())));
+        // if a wake were lost, this would park forever
+        loop {
+            cell.register(&waker);
+            if ready.load(Ordering::SeqCst) {
+                break;
+            }
+            thread::park();
+        }
+        producer.join().unwrap();
+    }
+}
+
+#[test]
+fn wait_set_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let (counter, waker) = CountingWaker::new();
+    let mut cx = Context::from_waker(&waker);
+    let cell = WaitableCallbackCell::new();
+
+    let mut fut = pin!(cell.wait_set());
+    assert_eq!(fut.as_mut().poll(&mut cx), Poll::Pending);
+    assert_eq!(fut.as_mut().poll(&mut cx), Poll::Pending);
+    assert_eq!(counter.count(), 0);
+    cell.put(|| ());
+    assert_eq!(counter.count(), 1);
+    assert_eq!(fut.as_mut().poll(&mut cx), Poll::Ready(()));
+    assert!(cell.is_set());
+    assert!(cell.take_call());
+
+    // dropping the future deregisters its waker
+    let mut fut = Box::pin(cell.wait_set());
+    assert_eq!(fut.as_mut().poll(&mut cx), Poll::Pending);
+    drop(fut);
+    cell.put(|| ());
+    assert_eq!(counter.count(), 1);
+    assert_eq!(Arc::strong_count(&counter), 2);
+}
+
+#[test]
+fn wait_take_call_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let counter = Arc::new(AtomicU32::new(0));
+    let cell = Arc::new(WaitableCallbackCell::new());
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    for i in 0..100 {
+        let task = rt.spawn({
+            let cell = Arc::clone(&cell);
+            async move { cell.wait_take_call().await }
+        });
+        cell.put({
+            let counter = Arc::clone(&counter);
+            move || {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        rt.block_on(task).unwrap();
+        assert_eq!(counter.load(Ordering::Relaxed), i + 1);
+    }
+}
+
+#[test]
+fn wait_call_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let counter = Arc::new(AtomicU32::new(0));
+    let cell = WaitableCallbackCell::new();
+    assert!(!cell.wait_call_spin_for(10));
+    cell.put(|| ());
+    assert!(cell.wait_call_spin_for(0));
+
+    // parking, then spinning, on another thread
+    thread::scope(|s| {
+        for _ in 0..100 {
+            let waiter = s.spawn(|| cell.wait_call());
+            let spinner = s.spawn(|| cell.wait_call_spin());
+            for _ in 0..2 {
+                let counter = Arc::clone(&counter);
+                cell.put(move || {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                });
+                while cell.is_set() {
+                    thread::yield_now();
+                }
+            }
+            waiter.join().unwrap();
+            spinner.join().unwrap();
+        }
+    });
+    assert_eq!(counter.load(Ordering::Relaxed), 200);
+}
+
+#[test]
+fn wait_empty_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let (counter, waker) = CountingWaker::new();
+    let mut cx = Context::from_waker(&waker);
+    let cell = WaitableCallbackCell::new();
+
+    assert_eq!(pin!(cell.wait_empty()).poll(&mut cx), Poll::Ready(()));
+    cell.put(|| ());
+    let mut fut_1 = pin!(cell.wait_empty());
+    let mut fut_2 = pin!(cell.wait_empty());
+    assert_eq!(fut_1.as_mut().poll(&mut cx), Poll::Pending);
+    assert_eq!(fut_2.as_mut().poll(&mut cx), Poll::Pending);
+    assert!(cell.clear());
+    assert!(!cell.clear());
+    assert_eq!(counter.count(), 2);
+    assert_eq!(fut_1.poll(&mut cx), Poll::Ready(()));
+    assert_eq!(fut_2.poll(&mut cx), Poll::Ready(()));
+
+    // put_when_empty waits for the cell to be drained
+    let dropped = Arc::new(AtomicU32::new(0));
+    cell.put(|| ());
+    let mut fut = pin!(cell.put_when_empty({
+        let dropped = Arc::clone(&dropped);
+        move || drop(dropped)
+    }));
+    assert_eq!(fut.as_mut().poll(&mut cx), Poll::Pending);
+    assert!(cell.take_call());
+    assert_eq!(counter.count(), 3);
+    assert_eq!(fut.poll(&mut cx), Poll::Ready(()));
+    assert_eq!(Arc::strong_count(&dropped), 2);
+    assert!(cell.take_call());
+    assert_eq!(Arc::strong_count(&dropped), 1);
+
+    // dropping the future drops the callback unrun
+    cell.put(|| ());
+    let mut fut = Box::pin(cell.put_when_empty({
+        let dropped = Arc::clone(&dropped);
+        move || drop(dropped)
+    }));
+    assert_eq!(fut.as_mut().poll(&mut cx), Poll::Pending);
+    drop(fut);
+    assert_eq!(Arc::strong_count(&dropped), 1);
+    assert!(cell.take_call());
+    assert_eq!(counter.count(), 3);
+    assert_eq!(Arc::strong_count(&counter), 2);
+}
+
+#[test]
+fn put_when_empty_producers_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let counter = Arc::new(AtomicU32::new(0));
+    let cell = Arc::new(WaitableCallbackCell::new());
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let producers = (0..4)
+        .map(|_| rt.spawn({
+            let cell = Arc::clone(&cell);
+            let counter = Arc::clone(&counter);
+            async move {
+                for _ in 0..250 {
+                    let counter = Arc::clone(&counter);
+                    cell.put_when_empty(move || {
+                        counter.fetch_add(1, Ordering::Relaxed);
+                    }).await;
+                }
+            }
+        }))
+        .collect::<Vec<_>>();
+    rt.block_on(async {
+        for _ in 0..1000 {
+            cell.wait_take_call().await;
+        }
+        for producer in producers {
+            producer.await.unwrap();
+        }
+    });
+    assert!(!cell.is_set());
+    assert_eq!(counter.load(Ordering::Relaxed), 1000);
+}
+
+#[test]
+fn take_call_or_wait_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell = WaitableCallbackCell::new();
+    assert_eq!(cell.take_call_or_wait(), DidRun::Empty);
+    cell.put(|| ());
+    assert_eq!(cell.take_call_or_wait(), DidRun::RanHere);
+    assert_eq!(cell.take_call_or_wait(), DidRun::Empty);
+
+    // losers return only once the winner's callback has finished, even if it
+    // panics
+    for panics in [false, true] {
+        let done = Arc::new(AtomicBool::new(false));
+        cell.put({
+            let done = Arc::clone(&done);
+            move || {
+                thread::sleep(std::time::Duration::from_millis(50));
+                done.store(true, Ordering::SeqCst);
+                if panics {
+                    panic!("callback panicked");
+                }
+            }
+        });
+        let barrier = std::sync::Barrier::new(4);
+        let outcomes = thread::scope(|s| {
+            let threads = (0..4)
+                .map(|_| s.spawn(|| {
+                    barrier.wait();
+                    let outcome = cell.take_call_or_wait();
+                    assert!(done.load(Ordering::SeqCst));
+                    outcome
+                }))
+                .collect::<Vec<_>>();
+            threads.into_iter().map(|t| t.join().ok()).collect::<Vec<_>>()
+        });
+        let ran_here = outcomes.iter().filter(|o| matches!(o, Some(DidRun::RanHere) | None)).count();
+        assert_eq!(ran_here, 1);
+        assert_eq!(outcomes.iter().filter(|o| **o == Some(DidRun::RanHere)).count(), !panics as usize);
+        assert!(outcomes.iter().all(|o| *o != Some(DidRun::Empty)));
+    }
+    assert_eq!(cell.take_call_or_wait(), DidRun::Empty);
+}
+
+#[test]
+fn async_callback_cell_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    struct DropGuardThing(Arc<AtomicU32>);
+    impl Drop for DropGuardThing {
+        fn drop(&mut self) {
+            self.0.fetch_add(100, Ordering::Relaxed);
+        }
+    }
+
+    let counter = Arc::new(AtomicU32::new(0));
+    let cell = AsyncCallbackCell::new();
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    assert!(cell.take_call().is_none());
+    cell.put({
+        let dgt = DropGuardThing(Arc::clone(&counter));
+        move || async move {
+            tokio::task::yield_now().await;
+            dgt.0.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    assert!(cell.is_set());
+    let fut = cell.take_call().unwrap();
+    assert!(cell.take_call().is_none());
+    assert_eq!(counter.load(Ordering::Relaxed), 0);
+    rt.block_on(fut);
+    assert_eq!(counter.load(Ordering::Relaxed), 101);
+
+    // the callback runs on take, the future only when polled
+    cell.put({
+        let counter = Arc::clone(&counter);
+        move || {
+            counter.fetch_add(1000, Ordering::Relaxed);
+            let dgt = DropGuardThing(counter);
+            async move {
+                dgt.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    });
+    let fut = cell.take_call().unwrap();
+    assert_eq!(counter.load(Ordering::Relaxed), 1101);
+    drop(fut);
+    assert_eq!(counter.load(Ordering::Relaxed), 1201);
+
+    // replacing and clearing drop unrun
+    cell.put({
+        let dgt = DropGuardThing(Arc::clone(&counter));
+        move || async move { drop(dgt) }
+    });
+    cell.put({
+        let dgt = DropGuardThing(Arc::clone(&counter));
+        move || async move { drop(dgt) }
+    });
+    assert_eq!(counter.load(Ordering::Relaxed), 1301);
+    assert!(cell.clear());
+    assert!(!cell.clear());
+    assert_eq!(counter.load(Ordering::Relaxed), 1401);
+    cell.put({
+        let dgt = DropGuardThing(Arc::clone(&counter));
+        move || async move { drop(dgt) }
+    });
+    drop(cell);
+    assert_eq!(counter.load(Ordering::Relaxed), 1501);
+    assert_eq!(Arc::strong_count(&counter), 1);
+}
+
+#[test]
+fn async_callback_cell_args_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    struct DropGuardThing(Arc<AtomicU32>);
+    impl Drop for DropGuardThing {
+        fn drop(&mut self) {
+            self.0.fetch_add(100, Ordering::Relaxed);
+        }
+    }
+
+    let counter = Arc::new(AtomicU32::new(0));
+    let cell: AsyncCallbackCellArgs<Box<u32>, u32> = AsyncCallbackCellArgs::new();
+    let rt = tokio::runtime::Runtime::new().unwrap();
+
+    // the input is handed back unmoved when nothing is registered
+    let input = Box::new(7);
+    let input_ptr = &*input as *const u32;
+    let input = cell.take_call(input).err().unwrap();
+    assert_eq!(&*input as *const u32, input_ptr);
+
+    cell.put({
+        let dgt = DropGuardThing(Arc::clone(&counter));
+        move |i: Box<u32>| async move {
+            tokio::task::yield_now().await;
+            dgt.0.fetch_add(1, Ordering::Relaxed);
+            *i * 2
+        }
+    });
+    let fut = cell.take_call(input).ok().unwrap();
+    assert!(!cell.is_set());
+    assert_eq!(rt.block_on(fut), 14);
+    assert_eq!(counter.load(Ordering::Relaxed), 101);
+
+    // dropping the future before completion drops the captured state once
+    cell.put({
+        let dgt = DropGuardThing(Arc::clone(&counter));
+        move |i: Box<u32>| async move {
+            tokio::task::yield_now().await;
+            drop(dgt);
+            *i
+        }
+    });
+    let mut fut = cell.take_call(Box::new(1)).ok().unwrap();
+    let (_, waker) = CountingWaker::new();
+    assert!(fut.as_mut().poll(&mut Context::from_waker(&waker)).is_pending());
+    assert_eq!(counter.load(Ordering::Relaxed), 101);
+    drop(fut);
+    assert_eq!(counter.load(Ordering::Relaxed), 201);
+
+    cell.put({
+        let dgt = DropGuardThing(Arc::clone(&counter));
+        move |i: Box<u32>| async move {
+            drop(dgt);
+            *i
+        }
+    });
+    drop(cell);
+    assert_eq!(counter.load(Ordering::Relaxed), 301);
+    assert_eq!(Arc::strong_count(&counter), 1);
+}
+
+#[test]
+fn put_with_receipt_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell: CallbackCellArgs<u32, u32> = CallbackCellArgs::new();
+
+    // run
+    let mut receipt = cell.put_with_receipt(|i| i + 1);
+    assert_eq!(receipt.try_get(), None);
+    assert_eq!(cell.take_call(1), Ok(2));
+    assert_eq!(receipt.try_get(), Some(Ok(2)));
+
+    // replaced, cleared, or dropped
+    let mut receipt_1 = cell.put_with_receipt(|i| i + 1);
+    let mut receipt_2 = cell.put_with_receipt(|i| i + 2);
+    assert_eq!(receipt_1.try_get(), Some(Err(Discarded)));
+    assert_eq!(receipt_2.try_get(), None);
+    cell.clear();
+    assert_eq!(receipt_2.try_get(), Some(Err(Discarded)));
+    let receipt = {
+        let cell: CallbackCellArgs<u32, u32> = CallbackCellArgs::new();
+        cell.put_with_receipt(|i| i + 1)
+    };
+    assert_eq!(receipt.wait(), Err(Discarded));
+
+    // blocking
+    let cell = Arc::new(cell);
+    let receipt = cell.put_with_receipt(|i| i * 3);
+    let thread = thread::spawn({
+        let cell = Arc::clone(&cell);
+        move || cell.take_call(5)
+    });
+    assert_eq!(receipt.wait(), Ok(15));
+    assert_eq!(thread.join().unwrap(), Ok(15));
+
+    // async
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let receipt = cell.put_with_receipt(|i| i * 4);
+    rt.spawn({
+        let cell = Arc::clone(&cell);
+        async move { cell.take_call(5) }
+    });
+    assert_eq!(rt.block_on(receipt), Ok(20));
+
+    // an uncollected output is dropped with the receipt
+    let cell: CallbackCellArgs<(), Arc<()>> = CallbackCellArgs::new();
+    let output = Arc::new(());
+    let receipt = cell.put_with_receipt({
+        let output = Arc::clone(&output);
+        move |()| output
+    });
+    drop(cell.take_call(()));
+    assert_eq!(Arc::strong_count(&output), 2);
+    drop(receipt);
+    assert_eq!(Arc::strong_count(&output), 1);
+}
+
+#[test]
+fn mailbox_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell: MailboxCallbackCell<u32, Arc<u32>> = MailboxCallbackCell::new();
+    assert_eq!(cell.try_take_output(), None);
+    assert_eq!(cell.take_call_store(1), Err(1));
+
+    cell.put(|i| Arc::new(i + 1));
+    assert!(cell.is_set());
+    assert_eq!(cell.take_call_store(1), Ok(()));
+    assert!(!cell.is_set());
+    assert_eq!(cell.try_take_output().as_deref(), Some(&2));
+    assert_eq!(cell.try_take_output(), None);
+
+    // only the most recent output is kept
+    let old_output = Arc::new(0);
+    cell.put({
+        let old_output = Arc::clone(&old_output);
+        move |_| old_output
+    });
+    cell.take_call_store(0).unwrap();
+    assert_eq!(Arc::strong_count(&old_output), 2);
+    cell.put(|i| Arc::new(i * 2));
+    cell.take_call_store(5).unwrap();
+    assert_eq!(Arc::strong_count(&old_output), 1);
+    assert_eq!(cell.try_take_output().as_deref(), Some(&10));
+
+    // an uncollected output is dropped with the cell
+    cell.put({
+        let old_output = Arc::clone(&old_output);
+        move |_| old_output
+    });
+    cell.take_call_store(0).unwrap();
+    assert_eq!(Arc::strong_count(&old_output), 2);
+    drop(cell);
+    assert_eq!(Arc::strong_count(&old_output), 1);
+}
+
+#[test]
+fn take_spawn_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    #[derive(Default)]
+    struct Deferred {
+        callbacks: std::sync::Mutex<Vec<Box<dyn FnOnce() + Send>>>,
+        futures: std::sync::Mutex<Vec<BoxFuture<()>>>,
+    }
+
+    impl Spawn for Deferred {
+        fn spawn_boxed(&self, f: Box<dyn FnOnce() + Send + 'static>) {
+            self.callbacks.lock().unwrap().push(f);
+        }
+
+        fn spawn_future(&self, fut: BoxFuture<()>) {
+            self.futures.lock().unwrap().push(fut);
+        }
+    }
+
+    let spawner = Deferred::default();
+    let counter = Arc::new(AtomicU32::new(0));
+
+    let cell = CallbackCell::new();
+    assert!(!cell.take_spawn(&spawner));
+    cell.put({
+        let counter = Arc::clone(&counter);
+        move || { counter.fetch_add(1, Ordering::SeqCst); }
+    });
+    assert!(cell.take_spawn(&spawner));
+    assert!(!cell.is_set());
+    assert_eq!(counter.load(Ordering::SeqCst), 0);
+    for f in spawner.callbacks.lock().unwrap().drain(..) {
+        f();
+    }
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+
+    // dropped without running
+    let dropped = Arc::new(());
+    cell.put({
+        let dropped = Arc::clone(&dropped);
+        move || drop(dropped)
+    });
+    assert!(cell.take_spawn(&spawner));
+    assert_eq!(Arc::strong_count(&dropped), 2);
+    spawner.callbacks.lock().unwrap().clear();
+    assert_eq!(Arc::strong_count(&dropped), 1);
+
+    // the async callback itself runs in the background
+    let cell = AsyncCallbackCell::new();
+    assert!(!cell.take_spawn(&spawner));
+    cell.put({
+        let counter = Arc::clone(&counter);
+        move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async move { counter.fetch_add(1, Ordering::SeqCst); }
+        }
+    });
+    assert!(cell.take_spawn(&spawner));
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+    let (_, waker) = CountingWaker::new();
+    let mut cx = Context::from_waker(&waker);
+    for mut fut in spawner.futures.lock().unwrap().drain(..) {
+        assert!(fut.as_mut().poll(&mut cx).is_ready());
+    }
+    assert_eq!(counter.load(Ordering::SeqCst), 3);
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn take_spawn_tokio_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let (send, recv) = std::sync::mpsc::channel();
+
+    let cell = CallbackCell::new();
+    cell.put({
+        let send = send.clone();
+        move || send.send(1).unwrap()
+    });
+    assert!(cell.take_spawn(rt.handle()));
+    assert_eq!(recv.recv().unwrap(), 1);
+
+    // a panicking callback doesn't reach the caller
+    cell.put(|| panic!("callback panicked"));
+    assert!(cell.take_spawn(rt.handle()));
+
+    let cell = AsyncCallbackCell::new();
+    cell.put(|| async move { send.send(2).unwrap() });
+    assert!(cell.take_spawn(rt.handle()));
+    assert_eq!(recv.recv().unwrap(), 2);
+}
+
+#[test]
+fn new_with_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let counter = Arc::new(AtomicU32::new(0));
+    let cell = CallbackCell::new_with({
+        let counter = Arc::clone(&counter);
+        move || { counter.fetch_add(1, Ordering::SeqCst); }
+    });
+    assert!(cell.is_set());
+    assert!(cell.take_call());
+    assert!(!cell.take_call());
+    let cell: CallbackCell = CallbackCell::from({
+        let counter = Arc::clone(&counter);
+        move || { counter.fetch_add(1, Ordering::SeqCst); }
+    });
+    assert!(cell.take_call());
+    assert_eq!(counter.load(Ordering::SeqCst), 2);
+
+    let cell = CallbackCellArgs::new_with(|i: u32| i + 1);
+    assert_eq!(cell.take_call(1), Ok(2));
+    assert_eq!(cell.take_call(1), Err(1));
+    let cell: CallbackCellArgs<u32, u32> = (|i| i * 2).into();
+    assert_eq!(cell.take_call(2), Ok(4));
+
+    // dropped unrun with the cell
+    let dropped = Arc::new(());
+    drop(CallbackCell::new_with({
+        let dropped = Arc::clone(&dropped);
+        move || drop(dropped)
+    }));
+    assert_eq!(Arc::strong_count(&dropped), 1);
+}
+
+#[test]
+fn init_at_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    // a cell embedded in a C struct, in memory which is never dropped
+    #[repr(C)]
+    struct Arena {
+        tag: u32,
+        cell: CallbackCell,
+        args_cell: CallbackCellArgs<u32, u32>,
+    }
+
+    let mut memory = std::mem::MaybeUninit::<Arena>::uninit();
+    let arena = memory.as_mut_ptr();
+    let counter = Arc::new(AtomicU32::new(0));
+    unsafe {
+        core::ptr::addr_of_mut!((*arena).tag).write(7);
+        let cell = CallbackCell::init_at(core::ptr::addr_of_mut!((*arena).cell));
+        let args_cell = CallbackCellArgs::init_at(core::ptr::addr_of_mut!((*arena).args_cell));
+        assert!(!cell.is_set());
+        cell.put({
+            let counter = Arc::clone(&counter);
+            move || { counter.fetch_add(1, Ordering::SeqCst); }
+        });
+        assert!(CallbackCell::from_ptr(core::ptr::addr_of!((*arena).cell)).take_call());
+        args_cell.put(|i| i + 1);
+        assert_eq!(args_cell.take_call(1), Ok(2));
+        args_cell.put(|i| i * 2);
+        assert!(args_cell.clear());
+        assert_eq!((*arena).tag, 7);
+    }
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+
+    // zeroed memory is an empty cell
+    let zeroed = std::mem::MaybeUninit::<CallbackCellArgs<(), ()>>::zeroed();
+    let cell = unsafe { CallbackCellArgs::from_ptr(zeroed.as_ptr()) };
+    assert!(!cell.is_set());
+    cell.put(|()| ());
+    assert!(cell.take_call(()).is_ok());
+}
+
+#[test]
+fn channel_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Setter>();
+    assert_send_sync::<Invoker>();
+    assert_send_sync::<SetterArgs<*mut (), *mut ()>>();
+    assert_send_sync::<InvokerArgs<*mut (), *mut ()>>();
+
+    let counter = Arc::new(AtomicU32::new(0));
+    let (setter, invoker) = channel();
+    assert_eq!(invoker.take_call(), Err(TakeCallError::Empty(())));
+    let setter_2 = setter.clone();
+    let thread = thread::spawn({
+        let counter = Arc::clone(&counter);
+        move || setter_2.put(move || { counter.fetch_add(1, Ordering::SeqCst); })
+    });
+    assert_eq!(thread.join().unwrap(), Ok(()));
+    assert!(setter.is_set());
+    assert_eq!(invoker.take_call(), Ok(()));
+    assert!(!invoker.is_set());
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+
+    // a callback put by the last setter still runs
+    setter.put(|| ()).unwrap();
+    drop(setter);
+    assert_eq!(invoker.take_call(), Ok(()));
+    assert_eq!(invoker.take_call(), Err(TakeCallError::Disconnected(())));
+
+    // dropping the invoker drops the callback, and disconnects setters
+    let (setter, invoker) = channel();
+    let dropped = Arc::new(());
+    setter.put({
+        let dropped = Arc::clone(&dropped);
+        move || drop(dropped)
+    }).unwrap();
+    drop(invoker);
+    assert_eq!(Arc::strong_count(&dropped), 1);
+    let result = setter.put({
+        let dropped = Arc::clone(&dropped);
+        move || drop(dropped)
+    });
+    assert_eq!(result, Err(Disconnected));
+    assert_eq!(Arc::strong_count(&dropped), 1);
+    assert!(!setter.is_set());
+
+    let (setter, invoker) = channel_args::<u32, u32>();
+    setter.clone().put(|i| i + 1).unwrap();
+    assert_eq!(invoker.take_call(1), Ok(2));
+    assert_eq!(invoker.take_call(1), Err(TakeCallError::Empty(1)));
+    setter.put(|i| i + 1).unwrap();
+    assert!(invoker.clear());
+    assert!(!setter.is_set());
+    drop(setter);
+    assert_eq!(invoker.take_call(1), Err(TakeCallError::Disconnected(1)));
+    assert_eq!(invoker.take_call(1).unwrap_err().into_input(), 1);
+}
+
+#[test]
+fn channel_final_put_race_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    // a put by the last setter, just before it drops, is never reported as
+    // disconnection
+    for _ in 0..200 {
+        let (setter, invoker) = channel_args::<u32, u32>();
+        let thread = thread::spawn(move || {
+            setter.put(|i| i + 1).unwrap();
+        });
+        loop {
+            match invoker.take_call(1) {
+                Ok(output) => {
+                    assert_eq!(output, 2);
+                    break;
+                }
+                Err(TakeCallError::Empty(_)) => (),
+                Err(TakeCallError::Disconnected(_)) => panic!("final put was lost"),
+            }
+        }
+        thread.join().unwrap();
+    }
+}
+
+#[test]
+fn notify_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let notified = Arc::new(AtomicU32::new(0));
+    let cell = NotifyCallbackCell::new(Notifier::from_fn({
+        let notified = Arc::clone(&notified);
+        move || { notified.fetch_add(1, Ordering::SeqCst); }
+    }));
+    cell.put(|| ());
+    assert_eq!(notified.load(Ordering::SeqCst), 1);
+    // replacing doesn't notify
+    cell.put(|| ());
+    assert_eq!(notified.load(Ordering::SeqCst), 1);
+    assert!(cell.take_call());
+    assert!(!cell.take_call());
+    cell.put(|| ());
+    assert_eq!(notified.load(Ordering::SeqCst), 2);
+    assert!(cell.clear());
+    cell.put(|| ());
+    assert_eq!(notified.load(Ordering::SeqCst), 3);
+
+    // a new notifier hears of a callback already present
+    let mut cell = cell;
+    let renotified = Arc::new(AtomicU32::new(0));
+    let old = cell.set_notifier(Notifier::from_fn({
+        let renotified = Arc::clone(&renotified);
+        move || { renotified.fetch_add(1, Ordering::SeqCst); }
+    }));
+    assert_eq!(renotified.load(Ordering::SeqCst), 1);
+    drop(old);
+    assert_eq!(Arc::strong_count(&notified), 1);
+    assert!(cell.take_call());
+    cell.set_notifier(Notifier::from_fn(|| panic!("the cell is empty")));
+}
+
+#[cfg(all(unix, feature = "unix"))]
+#[test]
+fn notify_socket_pair_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    use std::io::{Read, ErrorKind};
+
+    let (notifier, mut read) = Notifier::socket_pair().unwrap();
+    let cell = Arc::new(NotifyCallbackCell::new(notifier));
+    let ran = Arc::new(AtomicBool::new(false));
+    let thread = thread::spawn({
+        let (cell, ran) = (Arc::clone(&cell), Arc::clone(&ran));
+        move || {
+            cell.put(|| ());
+            cell.put(move || ran.store(true, Ordering::SeqCst));
+        }
+    });
+    thread.join().unwrap();
+
+    // one notification for both puts
+    let mut buf = [0; 16];
+    assert_eq!(read.read(&mut buf).unwrap(), 1);
+    assert_eq!(read.read(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+    assert!(cell.take_call());
+    assert!(ran.load(Ordering::SeqCst));
+}
+
+#[cfg(all(target_os = "linux", feature = "unix"))]
+#[test]
+fn notify_eventfd_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    use std::io::{Read, ErrorKind};
+
+    let (notifier, mut read) = Notifier::eventfd().unwrap();
+    let cell = Arc::new(NotifyCallbackCell::new(notifier));
+    let ran = Arc::new(AtomicBool::new(false));
+    let thread = thread::spawn({
+        let (cell, ran) = (Arc::clone(&cell), Arc::clone(&ran));
+        move || {
+            cell.put(|| ());
+            cell.put(move || ran.store(true, Ordering::SeqCst));
+        }
+    });
+    thread.join().unwrap();
+
+    // one notification for both puts, and reading resets the counter
+    let mut buf = [0; 8];
+    read.read_exact(&mut buf).unwrap();
+    assert_eq!(u64::from_ne_bytes(buf), 1);
+    assert_eq!(read.read(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+    assert!(cell.take_call());
+    assert!(ran.load(Ordering::SeqCst));
+
+    // notifications add up until read
+    cell.put(|| ());
+    assert!(cell.take_call());
+    cell.put(|| ());
+    read.read_exact(&mut buf).unwrap();
+    assert_eq!(u64::from_ne_bytes(buf), 2);
+    assert!(cell.take_call());
+}
+
+#[test]
+fn local_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    use std::rc::Rc;
+
+    let counter = Rc::new(std::cell::Cell::new(0));
+    let cell = LocalCallbackCell::new();
+    assert!(!cell.take_call());
+    cell.put({
+        let counter = Rc::clone(&counter);
+        move || counter.set(counter.get() + 1)
+    });
+    assert!(cell.is_set());
+    assert!(cell.take_call());
+    assert!(!cell.take_call());
+    assert_eq!(counter.get(), 1);
+
+    // a callback may re-arm its own cell
+    let cell = Rc::new(LocalCallbackCell::new());
+    cell.put({
+        let cell_2 = Rc::clone(&cell);
+        move || cell_2.put(|| ())
+    });
+    assert!(cell.take_call());
+    assert!(cell.is_set());
+    assert!(cell.clear());
+
+    let cell = LocalCallbackCellArgs::new();
+    cell.put({
+        let counter = Rc::clone(&counter);
+        move |i: u32| i + counter.get()
+    });
+    assert_eq!(cell.take_call(1), Ok(2));
+    assert_eq!(cell.take_call(1), Err(1));
+    cell.put({
+        let counter = Rc::clone(&counter);
+        move |i| i + counter.get()
+    });
+    assert_eq!(Rc::strong_count(&counter), 2);
+    drop(cell);
+    assert_eq!(Rc::strong_count(&counter), 1);
+}
+
+#[test]
+fn local_fn_test() {
+    use std::{cell::RefCell, rc::{Rc, Weak}, vec::Vec};
+
+    let state = Rc::new(RefCell::new(Vec::new()));
+    let cell = LocalCallbackCellFn::new();
+    assert_eq!(cell.call(1), Err(1));
+    cell.put({
+        let state = Rc::clone(&state);
+        move |i: u32| {
+            state.borrow_mut().push(i);
+            i * 2
+        }
+    });
+    assert!(cell.is_set());
+    assert_eq!(cell.call(1), Ok(2));
+    assert_eq!(cell.call(2), Ok(4));
+    assert_eq!(*state.borrow(), [1, 2]);
+    assert_eq!(std::format!("{:?}", cell), "LocalCallbackCellFn(NOT NULL)");
+    assert!(cell.clear());
+    assert!(!cell.clear());
+    assert_eq!(Rc::strong_count(&state), 1);
+
+    // a handler which replaces itself keeps running, with its captures, and
+    // is dropped once it returns
+    let cell = Rc::new(LocalCallbackCellFn::<u32, u32>::new());
+    cell.put({
+        let weak: Weak<LocalCallbackCellFn<u32, u32>> = Rc::downgrade(&cell);
+        let state = Rc::clone(&state);
+        move |i| {
+            let cell = weak.upgrade().unwrap();
+            cell.put(|i| i + 100);
+            assert_eq!(cell.call(i), Ok(i + 100));
+            state.borrow_mut().push(i);
+            assert_eq!(Rc::strong_count(&state), 2);
+            i
+        }
+    });
+    assert_eq!(cell.call(3), Ok(3));
+    assert_eq!(Rc::strong_count(&state), 1);
+    assert_eq!(cell.call(3), Ok(103));
+
+    // as does one which clears its cell
+    cell.put({
+        let weak = Rc::downgrade(&cell);
+        let state = Rc::clone(&state);
+        move |i| {
+            assert!(weak.upgrade().unwrap().clear());
+            assert_eq!(weak.upgrade().unwrap().call(i), Err(i));
+            state.borrow_mut().push(i);
+            i
+        }
+    });
+    assert_eq!(cell.call(4), Ok(4));
+    assert!(!cell.is_set());
+    assert_eq!(*state.borrow(), [1, 2, 3, 4]);
+    assert_eq!(Rc::strong_count(&state), 1);
+}
+
+#[test]
+fn local_queue_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    use std::{cell::RefCell, rc::Rc, vec::Vec};
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let queue = Rc::new(LocalCallbackQueue::new());
+    assert_eq!(queue.run_all(), 0);
+    for i in 0..5 {
+        let log = Rc::clone(&log);
+        queue.push(move || log.borrow_mut().push(i));
+    }
+    assert_eq!(queue.len(), 5);
+    assert_eq!(queue.run_batch(2), 2);
+    assert_eq!(*log.borrow(), [0, 1]);
+    assert_eq!(queue.run_all(), 3);
+    assert_eq!(*log.borrow(), [0, 1, 2, 3, 4]);
+    assert!(queue.is_empty());
+
+    // callbacks pushed while running are left for the next run
+    fn again(queue: Rc<LocalCallbackQueue>, log: Rc<RefCell<Vec<u32>>>, i: u32) {
+        queue.clone().push(move || {
+            log.borrow_mut().push(i);
+            again(queue, log, i + 1);
+        });
+    }
+    log.borrow_mut().clear();
+    again(Rc::clone(&queue), Rc::clone(&log), 0);
+    assert_eq!(queue.run_all(), 1);
+    assert_eq!(queue.run_all(), 1);
+    assert_eq!(*log.borrow(), [0, 1]);
+    assert_eq!(queue.len(), 1);
+    assert_eq!(std::format!("{:?}", queue), "LocalCallbackQueue(NOT EMPTY)");
+    assert_eq!(queue.clear(), 1);
+
+    // a panicking callback leaves the rest queued
+    queue.push(|| panic!("oh no"));
+    queue.push({
+        let log = Rc::clone(&log);
+        move || log.borrow_mut().push(10)
+    });
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| queue.run_all()));
+    assert!(result.is_err());
+    assert_eq!(queue.run_all(), 1);
+    assert_eq!(log.borrow().last(), Some(&10));
+
+    // dropping drops everything unrun
+    queue.push({
+        let log = Rc::clone(&log);
+        move || log.borrow_mut().push(11)
+    });
+    drop(queue);
+    assert_eq!(Rc::strong_count(&log), 1);
+    assert_eq!(log.borrow().last(), Some(&10));
+}
+
+#[test]
+fn auto_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    fn arm<F: FnOnce() + auto::MaybeSend + 'static>(cell: &auto::CallbackCell, f: F) {
+        cell.put(f);
+    }
+
+    let cell = auto::CallbackCell::new();
+    let ran = Arc::new(AtomicBool::new(false));
+    arm(&cell, {
+        let ran = Arc::clone(&ran);
+        move || ran.store(true, Ordering::SeqCst)
+    });
+    assert!(cell.take_call());
+    assert!(ran.load(Ordering::SeqCst));
+}
+
+#[test]
+fn auto_traits_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    use std::{rc::Rc, panic::{self, AssertUnwindSafe}};
+
+    // a cell whose I and O aren't Send can still be shared between threads
+    let cell = Arc::new(CallbackCellArgs::<Rc<u32>, Rc<u32>>::new());
+    thread::spawn({
+        let cell = Arc::clone(&cell);
+        move || cell.put(|i| Rc::new(*i + 1))
+    }).join().unwrap();
+    assert_eq!(cell.take_call(Rc::new(1)).map(|o| *o), Ok(2));
+
+    // a panicking callback leaves the cell empty and usable
+    let cell = CallbackCellArgs::<u32, u32>::new();
+    cell.put(|_| panic!("callback panicked"));
+    assert!(panic::catch_unwind(|| cell.take_call(1)).is_err());
+    assert!(!cell.is_set());
+    cell.put(|i| i + 1);
+    assert_eq!(panic::catch_unwind(AssertUnwindSafe(|| cell.take_call(1))).unwrap(), Ok(2));
+}
+
+#[test]
+fn callback_slot_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    fn put_add<S: CallbackSlot<Input = u32, Output = u32>>(slot: &S, n: u32) {
+        slot.put_boxed(Box::new(move |i| i + n));
+    }
+
+    let cell = CallbackCellArgs::new();
+    put_add(&cell, 2);
+    assert!(CallbackSlot::is_set(&cell));
+    assert_eq!(CallbackSlot::take_call(&cell, 1), Ok(3));
+    assert_eq!(CallbackSlot::take_call(&cell, 1), Err(1));
+
+    let counter = Arc::new(AtomicU32::new(0));
+    let cell_1 = CallbackCell::new();
+    let cell_2 = WaitableCallbackCell::new();
+    let slots: [&dyn CallbackSlot<Input = (), Output = ()>; 2] = [&cell_1, &cell_2];
+    for slot in slots {
+        assert!(!slot.is_set());
+        slot.put_boxed(Box::new({
+            let counter = Arc::clone(&counter);
+            move |()| { counter.fetch_add(1, Ordering::SeqCst); }
+        }));
+        assert!(slot.is_set());
+        assert_eq!(slot.take_call(()), Ok(()));
+        assert_eq!(slot.take_call(()), Err(()));
+    }
+    assert_eq!(counter.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn put_defer_drop_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    // records which thread it's dropped on
+    struct DropThread(Arc<std::sync::Mutex<Option<thread::ThreadId>>>);
+    impl Drop for DropThread {
+        fn drop(&mut self) {
+            *self.0.lock().unwrap() = Some(thread::current().id());
+        }
+    }
+
+    let (send, recv) = std::sync::mpsc::channel::<DisplacedCallback>();
+    let janitor = thread::spawn(move || {
+        let id = thread::current().id();
+        for callback in recv {
+            drop(callback);
+        }
+        id
+    });
+
+    let dropped_on = Arc::new(std::sync::Mutex::new(None));
+    let cell = CallbackCell::new();
+    // nothing displaced
+    cell.put_defer_drop(|| (), &send);
+    cell.clear();
+    cell.put({
+        let guard = DropThread(Arc::clone(&dropped_on));
+        move || drop(guard)
+    });
+    cell.put_defer_drop(|| (), &send);
+    assert!(cell.take_call());
+    drop(send);
+    let janitor_id = janitor.join().unwrap();
+    assert_eq!(*dropped_on.lock().unwrap(), Some(janitor_id));
+
+    // with the sink gone, the displaced callback is dropped inline
+    let (send, recv) = std::sync::mpsc::channel();
+    drop(recv);
+    let cell = CallbackCellArgs::<u32, u32>::new();
+    cell.put({
+        let guard = DropThread(Arc::clone(&dropped_on));
+        move |i| { drop(guard); i }
+    });
+    cell.put_defer_drop(|i| i + 1, &send);
+    assert_eq!(*dropped_on.lock().unwrap(), Some(thread::current().id()));
+
+    // closure sinks
+    let deferred = std::sync::Mutex::new(Vec::new());
+    cell.put_defer_drop(|i| i + 2, &|callback| deferred.lock().unwrap().push(callback));
+    assert_eq!(deferred.lock().unwrap().len(), 1);
+    assert_eq!(cell.take_call(1), Ok(3));
+}
+
+#[test]
+fn result_callback_cell_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    // counts runs in the low digits and drops in the hundreds
+    struct Guard(Arc<AtomicU32>);
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            self.0.fetch_add(100, Ordering::SeqCst);
+        }
+    }
+
+    let ok = Arc::new(AtomicU32::new(0));
+    let err = Arc::new(AtomicU32::new(0));
+    let put = |cell: &ResultCallbackCell<u32, &'static str>| {
+        let ok_guard = Guard(Arc::clone(&ok));
+        let err_guard = Guard(Arc::clone(&err));
+        cell.put(
+            move |value| { ok_guard.0.fetch_add(value, Ordering::SeqCst); },
+            move |_| { err_guard.0.fetch_add(1, Ordering::SeqCst); },
+        );
+    };
+
+    let cell = ResultCallbackCell::new();
+    assert_eq!(cell.resolve_ok(1), Err(1));
+    put(&cell);
+    assert!(cell.is_set());
+    assert_eq!(cell.resolve_ok(2), Ok(()));
+    assert!(!cell.is_set());
+    assert_eq!(ok.load(Ordering::SeqCst), 102);
+    assert_eq!(err.load(Ordering::SeqCst), 100);
+
+    put(&cell);
+    assert_eq!(cell.resolve_err("oops"), Ok(()));
+    assert_eq!(cell.resolve_err("oops"), Err("oops"));
+    assert_eq!(ok.load(Ordering::SeqCst), 202);
+    assert_eq!(err.load(Ordering::SeqCst), 201);
+
+    // replaced, cleared, or dropped
+    put(&cell);
+    put(&cell);
+    assert_eq!(ok.load(Ordering::SeqCst), 302);
+    assert_eq!(err.load(Ordering::SeqCst), 301);
+    assert!(cell.clear());
+    put(&cell);
+    drop(cell);
+    assert_eq!(ok.load(Ordering::SeqCst), 502);
+    assert_eq!(err.load(Ordering::SeqCst), 501);
+}
+
+#[test]
+fn put_method_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    struct Receiver(AtomicU32);
+
+    impl Receiver {
+        fn on_event(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn add(self: Arc<Self>, n: u32) -> u32 {
+            self.0.fetch_add(n, Ordering::SeqCst) + n
+        }
+    }
+
+    let receiver = Arc::new(Receiver(AtomicU32::new(0)));
+    let mut cell = CallbackCell::new();
+    cell.put_method(Arc::clone(&receiver), Receiver::on_event);
+    assert_eq!(Arc::strong_count(&receiver), 2);
+    // the header, the function pointer, and the receiver's pointer
+    assert_eq!(cell.allocated_bytes(), 4 * std::mem::size_of::<usize>());
+    assert!(cell.take_call());
+    assert_eq!(Arc::strong_count(&receiver), 1);
+    assert_eq!(receiver.0.load(Ordering::SeqCst), 1);
+
+    // replaced and dropped
+    cell.put_method(Arc::clone(&receiver), Receiver::on_event);
+    cell.put_method(Arc::clone(&receiver), Receiver::on_event);
+    assert_eq!(Arc::strong_count(&receiver), 2);
+    drop(cell);
+    assert_eq!(Arc::strong_count(&receiver), 1);
+
+    let mut cell = CallbackCellArgs::new();
+    cell.put_method(Arc::clone(&receiver), Receiver::add);
+    assert_eq!(cell.allocated_bytes(), 4 * std::mem::size_of::<usize>());
+    assert_eq!(cell.take_call(10), Ok(11));
+    assert_eq!(Arc::strong_count(&receiver), 1);
+
+    // through the graveyard, and after a panic
+    let graveyard = Graveyard::new();
+    cell.put_method(Arc::clone(&receiver), Receiver::add);
+    assert_eq!(cell.take_call_defer_free(1, &graveyard), Ok(12));
+    assert_eq!(Arc::strong_count(&receiver), 1);
+    assert_eq!(graveyard.collect(), 1);
+    cell.put_method(Arc::clone(&receiver), |_, _| panic!("oh no"));
+    assert!(std::panic::catch_unwind(|| cell.take_call(1)).is_err());
+    assert_eq!(Arc::strong_count(&receiver), 1);
+}
+
+#[test]
+fn put_arc_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let counter = Arc::new(AtomicU32::new(0));
+    let handler: Arc<SharedCallback<(), ()>> = Arc::new(SharedCallback::new({
+        let counter = Arc::clone(&counter);
+        move |()| { counter.fetch_add(1, Ordering::SeqCst); }
+    }));
+    let cells = [CallbackCell::new(), CallbackCell::new(), CallbackCell::new()];
+    for cell in &cells {
+        cell.put_arc(Arc::clone(&handler));
+    }
+    assert_eq!(test_util::live_allocations(), 0);
+    assert_eq!(Arc::strong_count(&handler), 4);
+    assert!(cells[0].take_call());
+    assert!(cells[1].take_call());
+    assert_eq!(counter.load(Ordering::SeqCst), 2);
+    assert_eq!(Arc::strong_count(&handler), 2);
+    cells[2].put_arc(Arc::clone(&handler));
+    assert_eq!(Arc::strong_count(&handler), 2);
+    drop(cells);
+    assert_eq!(Arc::strong_count(&handler), 1);
+
+    // the cell's reference is released after a panic, and through the
+    // graveyard, which has nothing to free
+    let handler = Arc::new(SharedCallback::new(|i: u32| if i == 0 { panic!("oh no") } else { i + 1 }));
+    let cell = CallbackCellArgs::new();
+    cell.put_arc(Arc::clone(&handler));
+    assert_eq!(cell.take_call(1), Ok(2));
+    assert_eq!(Arc::strong_count(&handler), 1);
+    cell.put_arc(Arc::clone(&handler));
+    assert!(std::panic::catch_unwind(|| cell.take_call(0)).is_err());
+    assert_eq!(Arc::strong_count(&handler), 1);
+    let graveyard = Graveyard::new();
+    cell.put_arc(Arc::clone(&handler));
+    assert_eq!(cell.take_call_defer_free(1, &graveyard), Ok(2));
+    assert_eq!(Arc::strong_count(&handler), 1);
+    assert_eq!(graveyard.collect(), 0);
+
+    // the last reference may be a cell's
+    let mut cell = CallbackCellArgs::new();
+    cell.put_arc(handler);
+    assert_eq!(cell.allocated_bytes(), 0);
+    assert_eq!(cell.take_call(2), Ok(3));
+}
+
+#[cfg(feature = "alloc-stats")]
+#[test]
+fn put_arc_alloc_stats_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let handler: Arc<SharedCallback<u32, u32>> = Arc::new(SharedCallback::new(|i| i * 2));
+    let cells: Vec<_> = (0..16).map(|_| CallbackCellArgs::new()).collect();
+    let before = alloc_stats::allocated_bytes();
+    for cell in &cells {
+        cell.put_arc(Arc::clone(&handler));
+        assert_eq!(alloc_stats::allocated_bytes(), before);
+    }
+    for cell in &cells {
+        cell.put_arc(Arc::clone(&handler));
+        assert_eq!(cell.take_call(2), Ok(4));
+    }
+    assert_eq!(alloc_stats::allocated_bytes(), before);
+    assert_eq!(test_util::live_allocations(), 0);
+    assert_eq!(Arc::strong_count(&handler), 1);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn take_call_abort_on_panic_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    // in the child process, panic in the callback, which should abort
+    if std::env::var_os("CALLBACK_CELL_ABORT_CHILD").is_some() {
+        let cell = CallbackCellArgs::<(), ()>::new();
+        cell.put(|()| panic!("oh no"));
+        let _ = cell.take_call_abort_on_panic(());
+        return;
+    }
+
+    let cell = CallbackCell::new();
+    let ran = Arc::new(AtomicBool::new(false));
+    cell.put({
+        let ran = Arc::clone(&ran);
+        move || ran.store(true, Ordering::SeqCst)
+    });
+    assert!(cell.take_call_abort_on_panic());
+    assert!(ran.load(Ordering::SeqCst));
+    assert!(!cell.take_call_abort_on_panic());
+
+    let cell = CallbackCellArgs::new();
+    cell.put(|i: u32| i + 1);
+    assert_eq!(cell.take_call_abort_on_panic(1), Ok(2));
+    assert_eq!(cell.take_call_abort_on_panic(1), Err(1));
+
+    let output = std::process::Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "test::take_call_abort_on_panic_test", "--nocapture"])
+        .env("CALLBACK_CELL_ABORT_CHILD", "1")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = std::string::String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("callback in CallbackCellArgs panicked, aborting"), "{}", stderr);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn hooks_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    static PUTS: AtomicU32 = AtomicU32::new(0);
+    static DISPLACED: AtomicU32 = AtomicU32::new(0);
+    static TAKES: AtomicU32 = AtomicU32::new(0);
+    static DROPS_UNRUN: AtomicU32 = AtomicU32::new(0);
+    static LABELED: AtomicU32 = AtomicU32::new(0);
+    static REENTRANT: CallbackCell = CallbackCell::new();
+
+    set_hooks(Hooks {
+        on_put: Some(|info| {
+            // the label is its own field, not the callback's type name
+            assert!(info.callback.is_some_and(|callback| callback.contains("hooks_test")));
+            if let Some(label) = info.label {
+                assert_eq!(label, "labeled");
+                LABELED.fetch_add(1, Ordering::SeqCst);
+            }
+            PUTS.fetch_add(1, Ordering::SeqCst);
+            if info.displaced {
+                DISPLACED.fetch_add(1, Ordering::SeqCst);
+            }
+            // hooks aren't called re-entrantly
+            REENTRANT.put(|| ());
+            REENTRANT.clear();
+        }),
+        on_take: Some(|info| {
+            assert_eq!(info.kind, "CallbackCellArgs");
+            TAKES.fetch_add(1, Ordering::SeqCst);
+        }),
+        on_drop_unrun: Some(|_| {
+            DROPS_UNRUN.fetch_add(1, Ordering::SeqCst);
+        }),
+    });
+
+    let cell = CallbackCellArgs::new();
+    cell.put(|i: u32| i);
+    cell.put(|i: u32| i + 1);
+    assert_eq!(cell.take_call(1), Ok(2));
+    assert_eq!(cell.take_call(1), Err(1));
+    cell.put(|i: u32| i);
+    assert!(cell.clear());
+    cell.put_named("labeled", |i: u32| i);
+    drop(cell);
+
+    assert!(take_hooks().is_some());
+    assert!(take_hooks().is_none());
+    let cell = CallbackCell::new();
+    cell.put(|| ());
+
+    assert_eq!(PUTS.load(Ordering::SeqCst), 4);
+    assert_eq!(DISPLACED.load(Ordering::SeqCst), 1);
+    assert_eq!(TAKES.load(Ordering::SeqCst), 1);
+    assert_eq!(DROPS_UNRUN.load(Ordering::SeqCst), 3);
+    assert_eq!(LABELED.load(Ordering::SeqCst), 1);
+}
+
+#[cfg(feature = "watchdog")]
+#[test]
+fn watchdog_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    static SLOW: NamedCallbackCellArgs<u32, u32> = NamedCallbackCellArgs::new_named("slow");
+    // the name, the label, and whether the type name is the closure's
+    type Report = (Option<&'static str>, Option<&'static str>, bool);
+    static REPORTS: std::sync::Mutex<Vec<Report>> = std::sync::Mutex::new(Vec::new());
+    set_watchdog(Watchdog {
+        threshold: core::time::Duration::from_millis(20),
+        report: |slow| {
+            // other tests' cells may be slow too
+            if slow.name == Some("slow") {
+                assert!(slow.elapsed > core::time::Duration::from_millis(20));
+                assert_eq!(slow.kind, "CallbackCellArgs");
+                let is_closure = slow.callback.is_some_and(|callback| callback.contains("watchdog_test"));
+                REPORTS.lock().unwrap().push((slow.name, slow.label, is_closure));
+            }
+        },
+    });
+    let sleep = || thread::sleep(core::time::Duration::from_millis(40));
+
+    SLOW.put(|i| i);
+    assert_eq!(SLOW.take_call(1), Ok(1));
+    SLOW.put(move |i| {
+        sleep();
+        i
+    });
+    assert_eq!(SLOW.take_call(2), Ok(2));
+    SLOW.as_unnamed().put_named("frame", move |i| {
+        sleep();
+        i
+    });
+    assert_eq!(SLOW.take_call(3), Ok(3));
+    // a fast callback which takes a slow one is reported without the inner
+    // one's identity
+    SLOW.put(move |i| {
+        let inner = CallbackCellArgs::new();
+        inner.put(move |()| sleep());
+        inner.take_call(()).unwrap();
+        i
+    });
+    assert_eq!(SLOW.take_call(4), Ok(4));
+
+    assert!(take_watchdog().is_some());
+    SLOW.put(move |i| {
+        sleep();
+        i
+    });
+    assert_eq!(SLOW.take_call(5), Ok(5));
+    assert_eq!(*REPORTS.lock().unwrap(), [(Some("slow"), None, true), (Some("slow"), Some("frame"), true), (Some("slow"), None, true)]);
+}
+
+#[test]
+fn named_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    static FRAME_DONE: NamedCallbackCell = NamedCallbackCell::new_named("gpu.frame_done");
+    assert_eq!(FRAME_DONE.name(), "gpu.frame_done");
+    assert_eq!(std::format!("{:?}", FRAME_DONE), "gpu.frame_done: empty");
+    FRAME_DONE.put(|| ());
+    assert_eq!(std::format!("{:?}", FRAME_DONE), "gpu.frame_done: armed");
+    assert!(FRAME_DONE.as_unnamed().is_set());
+    assert!(FRAME_DONE.take_call());
+    assert!(!FRAME_DONE.take_call());
+
+    let mut cell = NamedCallbackCellArgs::new_named("input");
+    cell.set_name("renamed");
+    cell.put(|i: u32| i + 1);
+    assert_eq!(std::format!("{:?}", cell), "renamed: armed");
+    assert_eq!(cell.take_call(1), Ok(2));
+    cell.put(|i: u32| i);
+    assert!(cell.clear());
+    cell.put(|i: u32| i);
+}
+
+// callback capture of known size, with drop glue.
+struct Droppy(#[allow(dead_code)] [u8; 64]);
+
+impl Drop for Droppy {
+    fn drop(&mut self) {}
+}
+
+#[test]
+fn allocated_bytes_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    // header is a function pointer and a size
+    let header = 2 * std::mem::size_of::<usize>();
+
+    let mut cell = CallbackCell::new();
+    assert_eq!(cell.allocated_bytes(), 0);
+    let payload = Droppy([0; 64]);
+    cell.put(move || drop(payload));
+    assert_eq!(cell.allocated_bytes(), header + 64);
+    cell.put(|| ());
+    assert_eq!(cell.allocated_bytes(), header);
+    assert!(cell.take_call());
+    assert_eq!(cell.allocated_bytes(), 0);
+
+    let mut cell = CallbackCellArgs::new();
+    let payload = std::vec![0u8; 1000];
+    cell.put(move |i: usize| payload.len() + i);
+    assert_eq!(cell.allocated_bytes(), header + std::mem::size_of::<Vec<u8>>());
+}
+
+#[cfg(feature = "alloc-stats")]
+#[test]
+fn alloc_stats_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let header = 2 * std::mem::size_of::<usize>();
+    let before = alloc_stats::allocated_bytes();
+
+    let cell = CallbackCell::new();
+    let payload = Droppy([0; 64]);
+    cell.put(move || drop(payload));
+    assert_eq!(alloc_stats::allocated_bytes(), before + header + 64);
+    cell.put(|| ());
+    assert_eq!(alloc_stats::allocated_bytes(), before + header);
+    let other = CallbackCellArgs::new();
+    let payload = Droppy([0; 64]);
+    other.put(move |()| drop(payload));
+    assert_eq!(alloc_stats::allocated_bytes(), before + 2 * header + 64);
+    drop(other);
+    assert!(cell.take_call());
+    assert_eq!(alloc_stats::allocated_bytes(), before);
+
+    let cell = AsyncCallbackCell::new();
+    let payload = Droppy([0; 64]);
+    cell.put(move || async move { drop(payload) });
+    assert!(alloc_stats::allocated_bytes() > before);
+    drop(cell);
+    assert_eq!(alloc_stats::allocated_bytes(), before);
+}
+
+#[test]
+fn unit_conversions_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let ran = Arc::new(AtomicU32::new(0));
+    let cell = CallbackCell::new();
+    cell.put({
+        let ran = Arc::clone(&ran);
+        move || { ran.fetch_add(1, Ordering::SeqCst); }
+    });
+    let args = CallbackCellArgs::from(cell);
+    assert_eq!(ran.load(Ordering::SeqCst), 0);
+    assert!(args.as_cell().is_set());
+    let cell = CallbackCell::from(args);
+    assert_eq!(ran.load(Ordering::SeqCst), 0);
+    assert_eq!(cell.as_args().take_call_unit(), Some(()));
+    assert_eq!(ran.load(Ordering::SeqCst), 1);
+    assert_eq!(cell.as_args().take_call_unit(), None);
+
+    let args = CallbackCellArgs::from(CallbackCell::new());
+    assert!(!args.is_set());
+    let cell = CallbackCell::from(args);
+    assert!(!cell.is_set());
+
+    let args = CallbackCellArgs::new();
+    args.as_cell().put(|| ());
+    assert!(args.take_call(()).is_ok());
+    args.put(|()| ());
+    drop(CallbackCell::from(args));
+
+    let cell = CallbackCellArgs::new();
+    cell.put(|()| 5);
+    assert_eq!(cell.take_call_unit(), Some(5));
+}
+
+#[test]
+fn deferred_call_queue_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let queue = Arc::new(DeferredCallQueue::new());
+    assert!(queue.is_empty());
+    assert_eq!(queue.run_all(), 0);
+
+    let threads = (0..4)
+        .map(|t| thread::spawn({
+            let queue = Arc::clone(&queue);
+            move || {
+                for i in 0..100 {
+                    queue.push(move |(t, i): (u32, u32)| (t, i), (t, i)).unwrap();
+                }
+            }
+        }))
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    let outputs = queue.run_all_collect();
+    assert_eq!(outputs.len(), 400);
+    // each thread's entries run in the order it pushed them
+    for t in 0..4 {
+        let mine = outputs.iter().filter(|o| o.0 == t).map(|o| o.1).collect::<Vec<_>>();
+        assert_eq!(mine, (0..100).collect::<Vec<_>>());
+    }
+    assert!(queue.is_empty());
+
+    // a panicking entry drops the rest unrun
+    let counter = Arc::new(AtomicU32::new(0));
+    let queue = DeferredCallQueue::new();
+    let make = |add: u32| {
+        let counter = Arc::clone(&counter);
+        move |panic: bool| {
+            assert!(!panic);
+            counter.fetch_add(add, Ordering::SeqCst);
+        }
+    };
+    queue.push(make(1), false).unwrap();
+    queue.push(make(10), true).unwrap();
+    queue.push(make(100), false).unwrap();
+    assert!(std::panic::catch_unwind(|| queue.run_all()).is_err());
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+    assert!(queue.is_empty());
+
+    // dropping and clearing drop both callback and input
+    queue.push(make(1), false).unwrap();
+    assert!(queue.clear());
+    assert!(!queue.clear());
+    let input = Arc::new(());
+    let queue = DeferredCallQueue::new();
+    queue.push(|input: Arc<()>| drop(input), Arc::clone(&input)).unwrap();
+    queue.push(|input: Arc<()>| drop(input), Arc::clone(&input)).unwrap();
+    assert_eq!(Arc::strong_count(&input), 3);
+    drop(queue);
+    assert_eq!(Arc::strong_count(&input), 1);
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn deferred_call_queue_retain_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let queue = DeferredCallQueue::new();
+    for i in 0..10 {
+        queue.push_tagged(|i: u32| i, i, (i % 3) as u64).unwrap();
+    }
+    queue.push(|i: u32| i, 10).unwrap();
+    assert_eq!(queue.retain(|tag| tag != 1), 3);
+    assert_eq!(queue.retain(|tag| tag != 1), 0);
+    assert_eq!(queue.run_all_collect(), [0, 2, 3, 5, 6, 8, 9, 10]);
+    assert_eq!(queue.retain(|_| false), 0);
+
+    // entries pushed during retain are kept, and stay after the older ones
+    let queue = DeferredCallQueue::new();
+    queue.push_tagged(|i: u32| i, 0, 0).unwrap();
+    queue.push_tagged(|i: u32| i, 1, 1).unwrap();
+    let removed = queue.retain(|tag| {
+        if tag == 1 {
+            thread::scope(|s| {
+                s.spawn(|| queue.push_tagged(|i: u32| i, 2, 1).unwrap());
+            });
+        }
+        tag == 0
+    });
+    assert_eq!(removed, 1);
+    assert_eq!(queue.run_all_collect(), [0, 2]);
+
+    // a panicking predicate leaves the queue intact
+    queue.push_tagged(|i: u32| i, 0, 0).unwrap();
+    queue.push_tagged(|i: u32| i, 1, 1).unwrap();
+    queue.push_tagged(|i: u32| i, 2, 2).unwrap();
+    assert!(std::panic::catch_unwind(|| queue.retain(|tag| {
+        assert!(tag != 1);
+        false
+    })).is_err());
+    assert_eq!(queue.run_all_collect(), [0, 1]);
+}
+
+#[test]
+fn deferred_call_queue_run_for_test() {
+    use std::time::Duration;
+
+    let _leak_check = test_util::LeakCheck::new();
+
+    let queue = DeferredCallQueue::new();
+    assert_eq!(queue.run_for(Duration::ZERO), DrainStats { ran: 0, stopped_early: false });
+    let order = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let push = |i: u32| {
+        let order = Arc::clone(&order);
+        queue.push(move |i: u32| {
+            thread::sleep(Duration::from_millis(5));
+            order.lock().unwrap().push(i);
+        }, i).unwrap();
+    };
+    for i in 0..10 {
+        push(i);
+    }
+    assert_eq!(queue.run_for(Duration::ZERO), DrainStats { ran: 0, stopped_early: true });
+    let stats = queue.run_for(Duration::from_millis(12));
+    assert!(stats.stopped_early);
+    assert!(stats.ran >= 1 && stats.ran < 10);
+    assert_eq!(*order.lock().unwrap(), (0..stats.ran as u32).collect::<Vec<_>>());
+
+    // the entries left over run before ones pushed since, in order
+    push(10);
+    assert_eq!(queue.run_for(Duration::from_secs(10)), DrainStats {
+        ran: 11 - stats.ran,
+        stopped_early: false,
+    });
+    assert_eq!(*order.lock().unwrap(), (0..11).collect::<Vec<_>>());
+    assert!(queue.is_empty());
+}
+
+#[test]
+fn double_buffered_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let queue = DoubleBufferedCallbackQueue::new();
+    let order = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let push = |i: u32| {
+        let order = Arc::clone(&order);
+        queue.push(move || order.lock().unwrap().push(i));
+    };
+    assert!(queue.is_current_empty());
+    push(0);
+    push(1);
+    assert!(!queue.is_current_empty());
+    assert_eq!(queue.drain_previous(), 0);
+    queue.flip();
+    assert!(queue.is_current_empty());
+    assert!(!queue.is_previous_empty());
+    push(2);
+    assert_eq!(queue.drain_previous(), 2);
+    assert_eq!(*order.lock().unwrap(), [0, 1]);
+    queue.flip();
+    assert_eq!(queue.drain_previous(), 1);
+    assert_eq!(*order.lock().unwrap(), [0, 1, 2]);
+
+    // pushes racing with flips each run exactly once, in one frame
+    let ran = Arc::new(AtomicU32::new(0));
+    let done = AtomicBool::new(false);
+    thread::scope(|s| {
+        let pushers = (0..4).map(|_| s.spawn(|| {
+            for _ in 0..1000 {
+                let ran = Arc::clone(&ran);
+                queue.push(move || {
+                    ran.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+        })).collect::<Vec<_>>();
+        s.spawn(|| {
+            while !done.load(Ordering::SeqCst) {
+                queue.flip();
+                queue.drain_previous();
+            }
+        });
+        for pusher in pushers {
+            pusher.join().unwrap();
+        }
+        done.store(true, Ordering::SeqCst);
+    });
+    queue.flip();
+    queue.drain_previous();
+    queue.flip();
+    queue.drain_previous();
+    assert_eq!(ran.load(Ordering::SeqCst), 4000);
+    drop(queue);
+}
+
+#[test]
+fn priority_queue_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let order = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let push = |queue: &PriorityCallbackQueue<u32>, priority: u32, i: u32| {
+        let order = Arc::clone(&order);
+        queue.push(priority, move || order.lock().unwrap().push(i));
+    };
+
+    // ties in push order, within and across bands
+    for queue in [PriorityCallbackQueue::new(), PriorityCallbackQueue::with_bands([10, 5, 10])] {
+        assert!(queue.is_empty());
+        assert_eq!(queue.take_call_highest(), None);
+        push(&queue, 1, 0);
+        push(&queue, 7, 1);
+        push(&queue, 12, 2);
+        push(&queue, 1, 3);
+        push(&queue, 7, 4);
+        assert_eq!(queue.take_call_highest(), Some(12));
+        // pushed after some have been taken
+        push(&queue, 6, 5);
+        push(&queue, 20, 6);
+        assert!(!queue.is_empty());
+        assert_eq!(queue.take_call_all(), 6);
+        assert!(queue.is_empty());
+        assert_eq!(std::mem::take(&mut *order.lock().unwrap()), [2, 6, 1, 4, 5, 0, 3]);
+    }
+    assert_eq!(PriorityCallbackQueue::<u32>::with_bands([10, 5, 10]).bands(), 3);
+
+    // callbacks may push, and a higher priority one runs next
+    let queue = Arc::new(PriorityCallbackQueue::with_bands([1]));
+    queue.push(0, {
+        let (queue, order) = (Arc::clone(&queue), Arc::clone(&order));
+        move || {
+            order.lock().unwrap().push(0);
+            let order = Arc::clone(&order);
+            queue.push(1, move || order.lock().unwrap().push(2));
+        }
+    });
+    push(&queue, 0, 1);
+    assert_eq!(queue.take_call_all(), 3);
+    assert_eq!(std::mem::take(&mut *order.lock().unwrap()), [0, 2, 1]);
+
+    // a snapshot runs in priority order, and leaves pushes during it, even of
+    // higher priority, for the next
+    let queue = Arc::new(PriorityCallbackQueue::with_bands([1]));
+    push(&queue, 0, 0);
+    queue.push(1, {
+        let (queue, order) = (Arc::clone(&queue), Arc::clone(&order));
+        move || {
+            order.lock().unwrap().push(1);
+            let order = Arc::clone(&order);
+            queue.push(2, move || order.lock().unwrap().push(3));
+        }
+    });
+    push(&queue, 0, 2);
+    assert_eq!(queue.take_call_snapshot(), 3);
+    assert_eq!(std::mem::take(&mut *order.lock().unwrap()), [1, 0, 2]);
+    assert_eq!(queue.take_call_snapshot(), 1);
+    assert_eq!(queue.take_call_snapshot(), 0);
+    assert_eq!(std::mem::take(&mut *order.lock().unwrap()), [3]);
+
+    // a callback which always requeues itself runs once per snapshot
+    fn requeue(queue: Arc<PriorityCallbackQueue<u32>>, ran: Arc<AtomicU32>) {
+        queue.clone().push(0, move || {
+            ran.fetch_add(1, Ordering::Relaxed);
+            requeue(queue, ran);
+        });
+    }
+    let ran = Arc::new(AtomicU32::new(0));
+    requeue(Arc::clone(&queue), Arc::clone(&ran));
+    assert_eq!(queue.take_call_snapshot(), 1);
+    assert_eq!(queue.take_call_snapshot(), 1);
+    assert_eq!(ran.load(Ordering::Relaxed), 2);
+    // the queue and its callback own each other; break the cycle
+    assert_eq!(queue.clear(), 1);
+
+    // concurrent pushes
+    let ran = Arc::new(AtomicU32::new(0));
+    let queue = PriorityCallbackQueue::with_bands([2, 4]);
+    thread::scope(|s| {
+        for t in 0..4 {
+            let (queue, ran) = (&queue, &ran);
+            s.spawn(move || {
+                for _ in 0..100 {
+                    let ran = Arc::clone(ran);
+                    queue.push(t + 1, move || { ran.fetch_add(1, Ordering::SeqCst); });
+                }
+            });
+        }
+        while ran.load(Ordering::SeqCst) < 400 {
+            queue.take_call_highest();
+        }
+    });
+    assert!(queue.is_empty());
+
+    // dropped unrun, with the queue or by clear
+    let dropped = Arc::new(());
+    let queue = PriorityCallbackQueue::with_bands([1]);
+    for priority in 0..3 {
+        let dropped = Arc::clone(&dropped);
+        queue.push(priority, move || drop(dropped));
+    }
+    assert_eq!(queue.take_call_highest(), Some(2));
+    drop(queue);
+    assert_eq!(Arc::strong_count(&dropped), 1);
+    let queue = PriorityCallbackQueue::new();
+    queue.push(0, move || drop(dropped));
+    assert_eq!(queue.clear(), 1);
+    assert!(queue.is_empty());
+}
+
+#[test]
+fn event_cells_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    #[derive(Debug, PartialEq)]
+    struct ConnectionClosed(u32);
+    #[derive(Debug, PartialEq)]
+    struct FrameRendered(Box<u64>);
+
+    static EVENTS: EventCells = EventCells::new();
+    let closed = Arc::new(AtomicU32::new(0));
+    assert_eq!(EVENTS.emit(ConnectionClosed(1)), Err(ConnectionClosed(1)));
+    EVENTS.put_for({
+        let closed = Arc::clone(&closed);
+        move |e: ConnectionClosed| { closed.store(e.0, Ordering::SeqCst); }
+    });
+    assert!(EVENTS.is_set_for::<ConnectionClosed>());
+    assert!(!EVENTS.is_set_for::<FrameRendered>());
+    assert_eq!(EVENTS.emit(FrameRendered(Box::new(2))), Err(FrameRendered(Box::new(2))));
+    assert_eq!(EVENTS.emit(ConnectionClosed(3)), Ok(()));
+    assert_eq!(closed.load(Ordering::SeqCst), 3);
+    // one-shot
+    assert_eq!(EVENTS.emit(ConnectionClosed(4)), Err(ConnectionClosed(4)));
+
+    // handlers may re-register themselves, and are independent per type
+    let frames = Arc::new(AtomicU32::new(0));
+    fn on_frame(frames: Arc<AtomicU32>) {
+        EVENTS.put_for(move |e: FrameRendered| {
+            frames.fetch_add(*e.0 as u32, Ordering::SeqCst);
+            on_frame(frames);
+        });
+    }
+    on_frame(Arc::clone(&frames));
+    EVENTS.put_for(|_: ConnectionClosed| unreachable!());
+    for i in 0..3 {
+        assert_eq!(EVENTS.emit(FrameRendered(Box::new(i))), Ok(()));
+    }
+    assert_eq!(frames.load(Ordering::SeqCst), 3);
+    assert!(EVENTS.clear_for::<ConnectionClosed>());
+    assert!(!EVENTS.clear_for::<ConnectionClosed>());
+    assert!(!EVENTS.clear_for::<u8>());
+    assert!(EVENTS.clear_for::<FrameRendered>());
+
+    // dropped unrun with the registry
+    let dropped = Arc::new(());
+    let events = EventCells::new();
+    events.put_for({
+        let dropped = Arc::clone(&dropped);
+        move |_: u32| drop(dropped)
+    });
+    assert_eq!(std::format!("{:?}", events), "EventCells { set: 1 }");
+    drop(events);
+    assert_eq!(Arc::strong_count(&dropped), 1);
+}
+
+#[test]
+fn coalescing_cell_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell = CoalescingCell::new();
+    let received = Arc::new(std::sync::Mutex::new(Vec::new()));
+    assert!(!cell.signal(Box::new(0)));
+    // no handler yet, so the input stays pending
+    assert!(!cell.pump());
+    assert!(cell.is_pending());
+    cell.set_handler({
+        let received = Arc::clone(&received);
+        move |i: Box<u32>| received.lock().unwrap().push(*i)
+    });
+    assert!(cell.pump());
+    assert!(!cell.pump());
+    assert!(cell.has_handler());
+
+    // only the latest is delivered
+    assert!(!cell.signal(Box::new(1)));
+    assert!(cell.signal(Box::new(2)));
+    assert!(cell.signal(Box::new(3)));
+    assert!(cell.pump());
+    assert!(!cell.pump());
+    assert_eq!(*received.lock().unwrap(), [0, 3]);
+
+    // a handler replaced while running is dropped once it returns
+    let cell = Arc::new(cell);
+    let dropped = Arc::new(());
+    cell.set_handler({
+        let (cell, received, dropped) = (Arc::clone(&cell), Arc::clone(&received), Arc::clone(&dropped));
+        move |i: Box<u32>| {
+            let _ = &dropped;
+            let received = Arc::clone(&received);
+            cell.set_handler(move |i: Box<u32>| received.lock().unwrap().push(*i + 100));
+            cell.signal(Box::new(*i + 1));
+        }
+    });
+    cell.signal(Box::new(4));
+    assert!(cell.pump());
+    assert_eq!(Arc::strong_count(&dropped), 1);
+    assert!(cell.pump());
+    assert_eq!(*received.lock().unwrap(), [0, 3, 105]);
+    assert!(cell.clear_handler());
+    assert!(!cell.clear_handler());
+
+    // producers racing each other and the consumer: each value is delivered
+    // at most once, and the last one is delivered
+    let cell = CoalescingCell::new();
+    let sum = Arc::new(AtomicU32::new(0));
+    cell.set_handler({
+        let sum = Arc::clone(&sum);
+        move |i: u32| { sum.fetch_add(i, Ordering::SeqCst); }
+    });
+    let delivered = AtomicU32::new(0);
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..1000 {
+                    cell.signal(1);
+                }
+            });
+        }
+        s.spawn(|| {
+            for _ in 0..1000 {
+                if cell.pump() {
+                    delivered.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        });
+    });
+    if cell.pump() {
+        delivered.fetch_add(1, Ordering::SeqCst);
+    }
+    assert!(!cell.is_pending());
+    assert_eq!(sum.load(Ordering::SeqCst), delivered.load(Ordering::SeqCst));
+    assert!(delivered.load(Ordering::SeqCst) >= 1);
+}
+
+#[test]
+fn seal_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell = SealableCallbackCellArgs::new();
+    assert!(cell.put(|i: u32| i + 1).is_ok());
+    assert!(!cell.is_sealed());
+    assert!(cell.seal());
+    assert!(!cell.seal());
+    assert!(cell.is_sealed());
+    assert!(cell.is_set());
+    let rejected = cell.put(|i: u32| i + 2).unwrap_err().into_inner();
+    assert_eq!(rejected(1), 3);
+    assert_eq!(cell.take_call(1), Ok(2));
+    assert_eq!(cell.take_call(1), Err(1));
+    assert!(cell.is_sealed());
+    assert_eq!(std::format!("{:?}", cell), "SealableCallbackCellArgs(NULL, SEALED)");
+    let cell = SealableCallbackCellArgs::new();
+    cell.put(|()| ()).unwrap();
+    cell.seal();
+    drop(cell);
+
+    let queue = DeferredCallQueue::new();
+    queue.push(|i: u32| i, 0).unwrap();
+    queue.push_tagged(|i: u32| i, 1, 1).unwrap();
+    assert!(queue.seal());
+    let (f, input) = queue.push(|i: u32| i * 10, 2).unwrap_err().into_inner();
+    assert_eq!(f(input), 20);
+    assert_eq!(queue.retain(|tag| tag == 0), 1);
+    assert!(queue.is_sealed());
+    assert_eq!(queue.run_all_collect(), [0]);
+    assert!(queue.is_sealed());
+    assert!(queue.push(|i: u32| i, 3).is_err());
+
+    // each callback put racing with a seal either drains or is handed back
+    for _ in 0..100 {
+        let cell = SealableCallbackCellArgs::new();
+        let queue = DeferredCallQueue::new();
+        let (cell_accepted, queue_accepted) = thread::scope(|s| {
+            let putter = s.spawn(|| {
+                let mut cell_accepted = false;
+                let mut queue_accepted = 0;
+                for i in 0..100 {
+                    // the cell keeps only the last accepted callback
+                    cell_accepted |= cell.put(|()| ()).is_ok();
+                    queue_accepted += queue.push(|i: u32| i, i).is_ok() as usize;
+                }
+                (cell_accepted, queue_accepted)
+            });
+            thread::yield_now();
+            cell.seal();
+            queue.seal();
+            putter.join().unwrap()
+        });
+        assert_eq!(cell.take_call(()).is_ok(), cell_accepted);
+        assert_eq!(queue.run_all(), queue_accepted);
+        assert!(cell.put(|()| ()).is_err());
+    }
+}
+
+#[test]
+fn fire_once_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell = FireOnceCallbackCellArgs::new();
+    assert!(!cell.is_set());
+    assert!(!cell.has_fired());
+    assert_eq!(cell.take_call(1), Err(1));
+    assert!(!cell.has_fired());
+    cell.put(|i: u32| i + 1).unwrap();
+    assert!(cell.is_set());
+    assert!(cell.clear());
+    assert!(!cell.has_fired());
+    cell.put(|i: u32| i + 1).unwrap();
+    assert_eq!(std::format!("{:?}", cell), "FireOnceCallbackCellArgs(NOT NULL)");
+    assert_eq!(cell.take_call(1), Ok(2));
+    assert!(cell.has_fired());
+    assert!(!cell.is_set());
+    assert_eq!(std::format!("{:?}", cell), "FireOnceCallbackCellArgs(FIRED)");
+    let rejected = cell.put(|i: u32| i + 2).unwrap_err().into_inner();
+    assert_eq!(rejected(1), 3);
+    assert_eq!(cell.take_call(1), Err(1));
+    assert!(!cell.clear());
+    assert!(cell.has_fired());
+    assert!(cell.rearm());
+    assert!(!cell.rearm());
+    assert!(!cell.has_fired());
+    cell.put(|i: u32| i + 3).unwrap();
+    assert!(!cell.rearm());
+    assert_eq!(cell.take_call(1), Ok(4));
+
+    // a put racing with the firing take_call either runs or is rejected
+    for _ in 0..1000 {
+        let cell = FireOnceCallbackCellArgs::new();
+        cell.put(|()| 'a').unwrap();
+        let (ran, put) = thread::scope(|s| {
+            let taker = s.spawn(|| cell.take_call(()).unwrap());
+            let put = cell.put(|()| 'b').is_ok();
+            (taker.join().unwrap(), put)
+        });
+        assert_eq!(ran, if put { 'b' } else { 'a' });
+        assert!(cell.has_fired());
+        assert!(!cell.is_set());
+    }
+}
+
+#[test]
+fn pausable_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell = PausableCallbackCellArgs::new();
+    assert!(!cell.is_paused());
+    assert_eq!(cell.take_call(1), Err(NotRun::Empty(1)));
+    cell.put(|i: u32| i + 1);
+    assert!(cell.pause());
+    assert!(!cell.pause());
+    assert!(cell.is_paused());
+    assert_eq!(std::format!("{:?}", cell), "PausableCallbackCellArgs(NOT NULL, PAUSED)");
+
+    // paused takes leave the callback in place
+    let not_run = cell.take_call(1).unwrap_err();
+    assert!(not_run.is_paused());
+    assert_eq!(not_run.into_input(), 1);
+    assert!(cell.is_set());
+    assert!(cell.resume());
+    assert!(!cell.resume());
+    assert_eq!(cell.take_call(1), Ok(2));
+
+    // puts and clears work while paused, and keep it paused
+    cell.pause();
+    cell.put(|i| i + 2);
+    cell.put(|i| i + 3);
+    assert!(cell.is_paused());
+    assert_eq!(cell.take_call(1), Err(NotRun::Paused(1)));
+    assert!(cell.clear());
+    assert!(!cell.clear());
+    assert!(cell.is_paused());
+    assert_eq!(cell.take_call(1), Err(NotRun::Paused(1)));
+    cell.put(|i| i + 4);
+    cell.resume();
+    assert_eq!(std::format!("{:?}", cell), "PausableCallbackCellArgs(NOT NULL)");
+    assert_eq!(cell.take_call(1), Ok(5));
+    assert_eq!(cell.take_call(1), Err(NotRun::Empty(1)));
+
+    // dropped while paused
+    cell.put(|i| i);
+    cell.pause();
+    drop(cell);
+
+    // a take racing with a pause either runs or leaves the callback
+    for _ in 0..1000 {
+        let cell = PausableCallbackCellArgs::new();
+        cell.put(|()| ());
+        let ran = thread::scope(|s| {
+            let taker = s.spawn(|| cell.take_call(()).is_ok());
+            cell.pause();
+            taker.join().unwrap()
+        });
+        assert_eq!(cell.is_set(), !ran);
+        assert!(cell.is_paused());
+    }
+}
+
+#[test]
+fn countdown_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let count = Arc::new(AtomicU32::new(0));
+    let callback = || {
+        let count = Arc::clone(&count);
+        move || { count.fetch_add(1, Ordering::SeqCst); }
+    };
+
+    let cell = CountdownCallbackCell::new();
+    assert_eq!(cell.remaining(), None);
+    assert_eq!(cell.put_with_count(callback(), 3).ok(), Some(false));
+    assert_eq!(cell.remaining(), Some(3));
+    assert_eq!(cell.signal(), SignalOutcome::NotYet);
+    assert_eq!(cell.signal(), SignalOutcome::NotYet);
+    assert_eq!(std::format!("{:?}", cell), "CountdownCallbackCell(1 REMAINING)");
+    assert_eq!(cell.signal(), SignalOutcome::Fired);
+    assert_eq!(cell.signal(), SignalOutcome::AlreadyFired);
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+    assert!(cell.has_fired());
+    assert_eq!(std::format!("{:?}", cell), "CountdownCallbackCell(FIRED)");
+    assert!(!cell.add_count(1));
+    let rejected = cell.put_with_count(callback(), 1).unwrap_err().into_inner();
+    rejected();
+    drop(rejected);
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+
+    // signals before the put are counted
+    let cell = CountdownCallbackCell::new();
+    assert_eq!(cell.signal(), SignalOutcome::NotYet);
+    assert_eq!(cell.signal(), SignalOutcome::NotYet);
+    assert_eq!(cell.put_with_count(callback(), 2).ok(), Some(true));
+    assert_eq!(count.load(Ordering::SeqCst), 3);
+
+    // added counts raise the threshold, and replaced callbacks are dropped
+    let cell = CountdownCallbackCell::new();
+    assert!(cell.add_count(1));
+    assert_eq!(cell.put_with_count(callback(), 5).ok(), Some(false));
+    assert_eq!(cell.put_with_count(callback(), 1).ok(), Some(false));
+    assert_eq!(cell.remaining(), Some(2));
+    assert!(cell.add_count(1));
+    assert_eq!(cell.signal(), SignalOutcome::NotYet);
+    assert_eq!(cell.signal(), SignalOutcome::NotYet);
+    assert_eq!(cell.signal(), SignalOutcome::Fired);
+    assert_eq!(count.load(Ordering::SeqCst), 4);
+    assert_eq!(Arc::strong_count(&count), 1);
+
+    // dropped unfired
+    let cell = CountdownCallbackCell::new();
+    cell.put_with_count(callback(), 1).unwrap();
+    drop(cell);
+    assert_eq!(Arc::strong_count(&count), 1);
+
+    // runs exactly once however signals and the put race
+    for _ in 0..100 {
+        let cell = CountdownCallbackCell::new();
+        let fired = thread::scope(|s| {
+            let signallers = (0..3).map(|_| s.spawn(|| cell.signal())).collect::<std::vec::Vec<_>>();
+            let put = cell.put_with_count(callback(), 3).unwrap();
+            let signals = signallers.into_iter().map(|t| t.join().unwrap()).collect::<std::vec::Vec<_>>();
+            assert!(!signals.contains(&SignalOutcome::AlreadyFired));
+            put as usize + signals.iter().filter(|&&o| o == SignalOutcome::Fired).count()
+        });
+        assert_eq!(fired, 1);
+    }
+    assert_eq!(count.load(Ordering::SeqCst), 104);
+}
+
+#[test]
+fn stateful_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell = StatefulCallbackCell::new(Vec::new());
+    assert!(!cell.take_call());
+    cell.put(|state: &mut Vec<u32>| state.push(1));
+    assert_eq!(std::format!("{:?}", cell), "StatefulCallbackCell(NOT NULL)");
+    assert!(cell.take_call());
+    assert!(!cell.take_call());
+    assert_eq!(cell.with_state(|state| state.clone()), [1]);
+
+    // each callback sees the state the previous left
+    let cell = Arc::new(StatefulCallbackCell::new(0u32));
+    let threads = (0..4)
+        .map(|_| thread::spawn({
+            let cell = Arc::clone(&cell);
+            move || for _ in 0..100 {
+                cell.put(|n| *n += 1);
+                cell.take_call();
+            }
+        }))
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    let total = cell.with_state(|n| *n);
+    assert!(total > 0 && total <= 400);
+
+    // a panicking callback releases the lock
+    let mut cell = StatefulCallbackCell::new(1u32);
+    cell.put(|n| {
+        *n = 2;
+        panic!("stateful_test");
+    });
+    assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| cell.take_call())).is_err());
+    assert_eq!(cell.with_state(|n| *n), 2);
+    *cell.get_mut() = 3;
+    cell.put(|n| *n += 1);
+    assert!(cell.take_call());
+    assert_eq!(*cell.get_mut(), 4);
+
+    // a callback not run is dropped with the cell
+    let dropped = Arc::new(());
+    cell.put({
+        let dropped = Arc::clone(&dropped);
+        move |_| drop(dropped)
+    });
+    drop(cell);
+    assert_eq!(Arc::strong_count(&dropped), 1);
+}
+
+#[test]
+fn stateful_queue_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let queue = StatefulCallbackQueue::new();
+    let mut state = Vec::new();
+    assert_eq!(queue.process(&mut state), 0);
+    for i in 0..5 {
+        queue.send(move |state: &mut Vec<u32>| state.push(i));
+    }
+    assert_eq!(queue.len(), 5);
+    assert_eq!(std::format!("{:?}", queue), "StatefulCallbackQueue(5 QUEUED)");
+
+    // batches keep the send order, leaving the rest ahead of later sends
+    assert_eq!(queue.process_batch(&mut state, 2), 2);
+    queue.send(|state| state.push(5));
+    assert_eq!(queue.process(&mut state), 4);
+    assert_eq!(state, [0, 1, 2, 3, 4, 5]);
+
+    // messages sent while processing wait for the next call
+    let queue = Arc::new(StatefulCallbackQueue::new());
+    queue.send({
+        let queue = Arc::clone(&queue);
+        move |state: &mut Vec<u32>| {
+            state.push(0);
+            queue.send(|state| state.push(1));
+        }
+    });
+    let mut state = Vec::new();
+    assert_eq!(queue.process(&mut state), 1);
+    assert_eq!(state, [0]);
+    assert_eq!(queue.process(&mut state), 1);
+    assert_eq!(state, [0, 1]);
+
+    // bounded
+    let queue = StatefulCallbackQueue::with_bound(2);
+    assert_eq!(queue.bound(), Some(2));
+    assert!(queue.try_send(|n: &mut u32| *n += 1).is_ok());
+    assert!(queue.try_send(|n| *n += 1).is_ok());
+    let rejected = queue.try_send(|n| *n += 10).unwrap_err().into_inner();
+    queue.send(|n| *n += 100);
+    assert_eq!(queue.len(), 3);
+    let mut n = 0;
+    assert_eq!(queue.process_batch(&mut n, 1), 1);
+    assert!(queue.try_send(rejected).is_err());
+    assert_eq!(queue.process(&mut n), 2);
+    assert!(queue.is_empty());
+    assert_eq!(n, 102);
+
+    // messages not run are dropped, including those after a panic
+    let dropped = Arc::new(());
+    let queue = StatefulCallbackQueue::new();
+    queue.send(|_: &mut u32| panic!("stateful_queue_test"));
+    for _ in 0..2 {
+        let dropped = Arc::clone(&dropped);
+        queue.send(move |_| drop(dropped));
+    }
+    assert!(std::panic::catch_unwind(|| queue.process(&mut 0)).is_err());
+    assert!(queue.is_empty());
+    assert_eq!(Arc::strong_count(&dropped), 1);
+    for _ in 0..2 {
+        let dropped = Arc::clone(&dropped);
+        queue.send(move |_| drop(dropped));
+    }
+    assert_eq!(queue.clear(), 2);
+    let dropped_2 = Arc::clone(&dropped);
+    queue.send(move |_| drop(dropped_2));
+    drop(queue);
+    assert_eq!(Arc::strong_count(&dropped), 1);
+}
+
+#[test]
+fn drain_thread_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    // runs callbacks in order on the named thread, surviving panics
+    let drain = DrainThread::spawn("drain_thread_test").unwrap();
+    assert_eq!(std::format!("{:?}", drain), "DrainThread(Some(\"drain_thread_test\"))");
+    let ran = Arc::new(std::sync::Mutex::new(Vec::new()));
+    for i in 0..5 {
+        let ran = Arc::clone(&ran);
+        assert!(drain.push(move || {
+            assert_eq!(thread::current().name(), Some("drain_thread_test"));
+            ran.lock().unwrap().push(i);
+        }).is_ok());
+        if i == 2 {
+            assert!(drain.push(|| panic!("drain_thread_test")).is_ok());
+        }
+    }
+    let handle = drain.handle();
+    let report = drain.shutdown(ShutdownPolicy::RunRemaining);
+    assert_eq!(report, DrainReport { ran: 6, panicked: 1, dropped: 0 });
+    assert_eq!(*ran.lock().unwrap(), [0, 1, 2, 3, 4]);
+    assert_eq!(handle.panicked(), 1);
+    // pushes after shutdown are handed back
+    let dropped = Arc::new(());
+    let rejected = handle.push({
+        let dropped = Arc::clone(&dropped);
+        move || drop(dropped)
+    });
+    drop(rejected.unwrap_err().into_inner());
+    assert_eq!(Arc::strong_count(&dropped), 1);
+
+    // dropping the rest, including those in the batch being run
+    let drain = DrainThread::spawn("drain_thread_test").unwrap();
+    let (release, blocked) = std::sync::mpsc::channel::<()>();
+    drain.push(move || blocked.recv().unwrap()).unwrap();
+    for _ in 0..3 {
+        let dropped = Arc::clone(&dropped);
+        drain.push(move || drop(dropped)).unwrap();
+    }
+    let handle = drain.handle();
+    let shutdown = thread::spawn(move || drain.shutdown(ShutdownPolicy::DropRemaining));
+    while handle.push(|| ()).is_ok() {
+        thread::yield_now();
+    }
+    thread::sleep(std::time::Duration::from_millis(50));
+    release.send(()).unwrap();
+    let report = shutdown.join().unwrap();
+    assert_eq!(report.ran, 1);
+    assert!(report.dropped >= 3);
+    assert_eq!(Arc::strong_count(&dropped), 1);
+
+    // a push racing shutdown either runs or is handed back
+    let drain = DrainThread::spawn("drain_thread_test").unwrap();
+    let handle = drain.handle();
+    let ran = Arc::new(AtomicU32::new(0));
+    let pushers = (0..4)
+        .map(|_| thread::spawn({
+            let handle = handle.clone();
+            let ran = Arc::clone(&ran);
+            move || {
+                let mut accepted = 0;
+                loop {
+                    let ran = Arc::clone(&ran);
+                    let pushed = handle.push(move || {
+                        ran.fetch_add(1, Ordering::SeqCst);
+                    });
+                    match pushed {
+                        Ok(()) => accepted += 1,
+                        Err(_) => return accepted,
+                    }
+                }
+            }
+        }))
+        .collect::<Vec<_>>();
+    thread::sleep(std::time::Duration::from_millis(10));
+    let report = drain.shutdown(ShutdownPolicy::RunRemaining);
+    let accepted = pushers.into_iter().map(|pusher| pusher.join().unwrap()).sum::<u32>();
+    assert_eq!(ran.load(Ordering::SeqCst), accepted);
+    assert_eq!(report.ran, accepted as usize);
+}
+
+#[test]
+fn defaulting_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell = DefaultingCallbackCellArgs::new();
+    assert_eq!(cell.take_call(1), Err(1));
+    cell.put(|i: u32| i + 1);
+    assert_eq!(cell.take_call(1), Ok(2));
+    assert_eq!(cell.take_call(1), Err(1));
+
+    // the default runs when the one-shot slot is empty, and stays
+    let dropped = Arc::new(());
+    cell.set_default({
+        let dropped = Arc::clone(&dropped);
+        move |i| {
+            let _ = &dropped;
+            i + 100
+        }
+    });
+    assert!(cell.has_default());
+    assert_eq!(cell.take_call(1), Ok(101));
+    assert_eq!(cell.take_call(2), Ok(102));
+    cell.put(|i| i + 1);
+    assert_eq!(std::format!("{:?}", cell), "DefaultingCallbackCellArgs(NOT NULL, DEFAULT)");
+    assert_eq!(cell.take_call(1), Ok(2));
+    assert_eq!(cell.take_call(1), Ok(101));
+    cell.put(|i| i + 1);
+    assert!(cell.clear());
+    assert!(cell.has_default());
+    assert_eq!(cell.take_call(1), Ok(101));
+
+    // a default may replace itself while it runs
+    let cell = Arc::new(cell);
+    cell.set_default({
+        let cell = Arc::downgrade(&cell);
+        let dropped = Arc::clone(&dropped);
+        move |i| {
+            cell.upgrade().unwrap().set_default(|i| i + 200);
+            i + (Arc::strong_count(&dropped) > 1) as u32
+        }
+    });
+    assert_eq!(Arc::strong_count(&dropped), 2);
+    assert_eq!(cell.take_call(1), Ok(2));
+    assert_eq!(cell.take_call(1), Ok(201));
+    assert_eq!(Arc::strong_count(&dropped), 1);
+    assert!(cell.clear_default());
+    assert!(!cell.has_default());
+    assert_eq!(cell.take_call(1), Err(1));
+    assert_eq!(std::format!("{:?}", cell), "DefaultingCallbackCellArgs(NULL)");
+}
+
+#[test]
+fn gated_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let gate = Gate::new();
+    assert!(gate.is_enabled());
+    let cell = CallbackCell::new_gated(gate.clone());
+    let args = CallbackCellArgs::new_gated(gate.clone());
+    let ran = Arc::new(AtomicU32::new(0));
+    let count_up = || {
+        let ran = Arc::clone(&ran);
+        move || {
+            ran.fetch_add(1, Ordering::SeqCst);
+        }
+    };
+
+    // enabled, they're ordinary cells
+    cell.put(count_up());
+    assert!(cell.take_call());
+    assert!(!cell.take_call());
+    args.put(|i: u32| i + 1);
+    assert_eq!(args.take_call(1), Ok(2));
+    assert_eq!(args.take_call(1), Err(GateNotRun::Empty(1)));
+
+    // disabling one clone disables every cell, leaving their callbacks, and
+    // puts still work
+    assert!(gate.clone().disable());
+    assert!(!gate.disable());
+    cell.put(count_up());
+    args.put(|i| i + 1);
+    assert!(!cell.take_call());
+    assert!(cell.is_set());
+    let not_run = args.take_call(1).unwrap_err();
+    assert!(not_run.is_disabled());
+    assert_eq!(not_run.into_input(), 1);
+    assert!(args.is_set());
+    assert_eq!(std::format!("{:?}", cell), "GatedCallbackCell(NOT NULL, DISABLED)");
+    assert_eq!(std::format!("{:?}", args.gate()), "Gate(DISABLED)");
+    assert_eq!(ran.load(Ordering::SeqCst), 1);
+
+    // enabling again runs the callbacks left in place
+    assert!(cell.gate().enable());
+    assert!(!gate.enable());
+    assert!(cell.take_call());
+    assert_eq!(args.take_call(2), Ok(3));
+    assert_eq!(ran.load(Ordering::SeqCst), 2);
+    assert_eq!(std::format!("{:?}", args), "GatedCallbackCellArgs(NULL)");
+
+    // clears work either way, and the cells drop their callbacks
+    let gate = Gate::new_disabled();
+    let cell = GatedCallbackCellArgs::<u32, u32>::new(gate);
+    cell.put(|i| i);
+    assert!(cell.clear());
+    cell.put(|i| i);
+    drop(cell);
+}
+
+#[test]
+fn graveyard_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let graveyard = Graveyard::new();
+    assert!(graveyard.is_empty());
+    assert_eq!(std::format!("{:?}", graveyard), "Graveyard(EMPTY)");
+    let cell = CallbackCell::new();
+    assert!(!cell.take_call_defer_free(&graveyard));
+    let counter = Arc::new(AtomicU32::new(0));
+    let counter_2 = Arc::clone(&counter);
+    cell.put(move || {
+        counter_2.fetch_add(1, Ordering::SeqCst);
+    });
+    assert!(cell.take_call_defer_free(&graveyard));
+    // ran, and the callback was dropped, but its allocation is kept
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+    assert_eq!(Arc::strong_count(&counter), 1);
+    assert_eq!(test_util::live_allocations(), 1);
+    assert_eq!(std::format!("{:?}", graveyard), "Graveyard(NOT EMPTY)");
+
+    // callbacks of any size and alignment
+    #[repr(align(64))]
+    struct Big([u8; 100]);
+    let args = CallbackCellArgs::new();
+    let big = Big([3; 100]);
+    args.put(move |i: u8| big.0[0] + i);
+    assert_eq!(args.take_call_defer_free(1, &graveyard), Ok(4));
+    assert_eq!(args.take_call_defer_free(1, &graveyard), Err(1));
+    args.put(|_: u8| -> u8 { panic!("callback panicked") });
+    assert!(std::panic::catch_unwind(|| args.take_call_defer_free(1, &graveyard)).is_err());
+    assert_eq!(test_util::live_allocations(), 3);
+    assert_eq!(graveyard.collect(), 3);
+    assert_eq!(graveyard.collect(), 0);
+    assert_eq!(test_util::live_allocations(), 0);
+
+    // dropping the graveyard frees what's left, including from other threads
+    let graveyard = Graveyard::new();
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..100 {
+                    let cell = CallbackCell::new();
+                    cell.put(|| ());
+                    assert!(cell.take_call_defer_free(&graveyard));
+                }
+            });
+        }
+    });
+    assert_eq!(test_util::live_allocations(), 400);
+    drop(graveyard);
+}
+
+#[test]
+fn thread_bound_test() {
+    use std::{cell::Cell, rc::Rc};
+
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell = ThreadBoundCallbackCell::new();
+    assert_eq!(cell.owner(), thread::current().id());
+    assert!(cell.is_owner_thread());
+    assert!(!cell.take_call());
+    let ran = Rc::new(Cell::new(0));
+    let ran_2 = Rc::clone(&ran);
+    cell.put_local(move || ran_2.set(ran_2.get() + 1));
+    assert_eq!(std::format!("{:?}", cell), "ThreadBoundCallbackCell(NOT NULL)");
+    assert!(cell.take_call());
+    assert_eq!(ran.get(), 1);
+    assert_eq!(Rc::strong_count(&ran), 1);
+    let ran_2 = Rc::clone(&ran);
+    cell.put_local(move || ran_2.set(ran_2.get() + 1));
+    assert!(cell.clear());
+    assert_eq!(Rc::strong_count(&ran), 1);
+
+    // other threads can put Send callbacks, but not take or displace local
+    // ones
+    let ran_2 = Rc::clone(&ran);
+    cell.put_local(move || ran_2.set(ran_2.get() + 1));
+    thread::scope(|s| {
+        s.spawn(|| {
+            assert!(!cell.is_owner_thread());
+            assert!(std::panic::catch_unwind(|| cell.take_call()).is_err());
+            assert!(std::panic::catch_unwind(|| cell.clear()).is_err());
+            assert!(std::panic::catch_unwind(|| cell.put(|| ())).is_err());
+            assert!(std::panic::catch_unwind(|| cell.put_local(|| ())).is_err());
+        });
+    });
+    assert!(cell.take_call());
+    assert_eq!(ran.get(), 2);
+    let counter = Arc::new(AtomicU32::new(0));
+    thread::scope(|s| {
+        s.spawn(|| {
+            let counter = Arc::clone(&counter);
+            cell.put(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+            cell.put(|| ());
+        });
+    });
+    assert_eq!(counter.load(Ordering::SeqCst), 0);
+    assert_eq!(Arc::strong_count(&counter), 1);
+    assert!(cell.take_call());
+    assert!(!cell.is_set());
+
+    // a cell holding a Send callback can be dropped on any thread
+    let cell = ThreadBoundCallbackCell::new();
+    cell.put(|| ());
+    thread::spawn(move || drop(cell)).join().unwrap();
+}
+
+#[test]
+fn atomic_option_box_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    struct DropGuardThing(Arc<AtomicU32>, u32);
+    impl Drop for DropGuardThing {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let dropped = Arc::new(AtomicU32::new(0));
+    let thing = |n| DropGuardThing(Arc::clone(&dropped), n);
+
+    let cell = AtomicOptionBox::new();
+    assert!(!cell.is_set());
+    assert!(cell.take().is_none());
+    assert_eq!(std::format!("{:?}", cell), "AtomicOptionBox(NULL)");
+    cell.put(thing(1));
+    assert!(cell.is_set());
+    assert_eq!(std::format!("{:?}", cell), "AtomicOptionBox(NOT NULL)");
+    cell.put(thing(2));
+    assert_eq!(dropped.load(Ordering::SeqCst), 1);
+    let old = cell.swap(Some(Box::new(thing(3)))).unwrap();
+    assert_eq!(old.1, 2);
+    assert_eq!(dropped.load(Ordering::SeqCst), 1);
+    drop(old);
+    assert_eq!(dropped.load(Ordering::SeqCst), 2);
+    assert_eq!(cell.take().unwrap().1, 3);
+    assert_eq!(dropped.load(Ordering::SeqCst), 3);
+    assert!(!cell.clear());
+    cell.put(thing(4));
+    assert!(cell.clear());
+    assert_eq!(dropped.load(Ordering::SeqCst), 4);
+
+    let mut cell = AtomicOptionBox::from(thing(5));
+    cell.get_mut().unwrap().1 = 6;
+    assert_eq!(cell.into_inner().unwrap().1, 6);
+    assert_eq!(dropped.load(Ordering::SeqCst), 5);
+    drop(AtomicOptionBox::from(thing(7)));
+    assert_eq!(dropped.load(Ordering::SeqCst), 6);
+    assert!(AtomicOptionBox::<()>::default().into_inner().is_none());
+
+    // racing puts and takes drop each value exactly once
+    let cell = AtomicOptionBox::new();
+    let taken = AtomicU32::new(0);
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for i in 0..1000 {
+                    cell.put(thing(i));
+                    if cell.take().is_some() {
+                        taken.fetch_add(1, Ordering::SeqCst);
+                    }
+                }
+            });
+        }
+    });
+    drop(cell);
+    assert!(taken.load(Ordering::SeqCst) > 0);
+    assert_eq!(dropped.load(Ordering::SeqCst), 6 + 4000);
+}
+
+#[test]
+fn future_cell_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    // a future which records where it's polled, and is ready on its nth poll
+    struct Polls {
+        n: u32,
+        polls: AtomicU32,
+        addrs: Arc<std::sync::Mutex<Vec<usize>>>,
+    }
+    impl Future for Polls {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, _: &mut Context) -> Poll<()> {
+            self.addrs.lock().unwrap().push(&*self as *const Self as usize);
+            if self.polls.fetch_add(1, Ordering::SeqCst) + 1 >= self.n {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }
+    }
+
+    let (_, waker) = CountingWaker::new();
+    let mut cx = Context::from_waker(&waker);
+    let addrs = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let polls = |n| Box::pin(Polls { n, polls: AtomicU32::new(0), addrs: Arc::clone(&addrs) });
+
+    let cell = FutureCell::new();
+    assert!(!cell.is_set());
+    assert!(cell.take().is_none());
+    assert_eq!(cell.poll_take(&mut cx), None);
+    cell.put(polls(3));
+    assert_eq!(std::format!("{:?}", cell), "FutureCell(NOT NULL)");
+    assert_eq!(cell.poll_take(&mut cx), Some(Poll::Pending));
+    assert!(cell.is_set());
+    let mut fut = cell.take().unwrap();
+    assert!(!cell.is_set());
+    assert_eq!(fut.as_mut().poll(&mut cx), Poll::Pending);
+    cell.put(fut);
+    assert_eq!(cell.poll_take(&mut cx), Some(Poll::Ready(())));
+    assert!(!cell.is_set());
+    assert_eq!(std::format!("{:?}", cell), "FutureCell(NULL)");
+    // pinned in place throughout
+    let recorded = std::mem::take(&mut *addrs.lock().unwrap());
+    assert_eq!(recorded.len(), 3);
+    assert!(recorded.iter().all(|&addr| addr == recorded[0]));
+    cell.put(polls(1));
+    assert!(cell.clear());
+    assert!(!cell.clear());
+
+    // a poll_take which finds the future being polled makes it polled again
+    static CELL: FutureCell = FutureCell::new();
+    let repolled = Arc::new(AtomicU32::new(0));
+    let repolled_2 = Arc::clone(&repolled);
+    CELL.put(Box::pin(std::future::poll_fn(move |cx| {
+        if repolled_2.fetch_add(1, Ordering::SeqCst) == 0 {
+            assert_eq!(CELL.poll_take(cx), Some(Poll::Pending));
+            assert_eq!(CELL.poll_take(cx), Some(Poll::Pending));
+        }
+        Poll::Pending
+    })));
+    assert_eq!(CELL.poll_take(&mut cx), Some(Poll::Pending));
+    assert_eq!(repolled.load(Ordering::SeqCst), 2);
+    assert!(CELL.is_set());
+    assert!(CELL.clear());
+
+    // a put while the future is being polled replaces it
+    CELL.put(Box::pin(std::future::poll_fn(|_| {
+        CELL.put(Box::pin(std::future::ready(())));
+        Poll::Pending
+    })));
+    assert_eq!(CELL.poll_take(&mut cx), Some(Poll::Pending));
+    assert_eq!(CELL.poll_take(&mut cx), Some(Poll::Ready(())));
+    assert!(!CELL.is_set());
+
+    // a poll which panics leaves the cell empty
+    CELL.put(Box::pin(std::future::poll_fn(|_| panic!("poll panicked"))));
+    assert!(std::panic::catch_unwind(|| CELL.poll_take(&mut Context::from_waker(&waker))).is_err());
+    assert!(!CELL.is_set());
+    assert_eq!(CELL.poll_take(&mut cx), None);
+
+    // racing poll_takes complete the future exactly once
+    for _ in 0..100 {
+        let cell = FutureCell::new();
+        cell.put(polls(50));
+        let ready = AtomicU32::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let mut cx = Context::from_waker(&waker);
+                    while let Some(poll) = cell.poll_take(&mut cx) {
+                        if poll.is_ready() {
+                            ready.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(ready.load(Ordering::SeqCst), 1);
+        assert!(!cell.is_set());
+        addrs.lock().unwrap().clear();
+    }
+}
+
+#[cfg(feature = "stamped")]
+#[test]
+fn stamped_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell = StampedCallbackCellArgs::new();
+    assert!(!cell.is_set());
+    assert_eq!(cell.take_call(1), Err(1));
+    let h1 = cell.put(|i: u32| i + 1);
+    assert!(cell.is_set());
+    assert!(cell.is_current(h1));
+    assert_eq!(std::format!("{:?}", cell), "StampedCallbackCellArgs(NOT NULL)");
+    let h2 = cell.replace_if_same(h1, |i: u32| i + 2).ok().unwrap();
+    assert_ne!(h1, h2);
+    assert!(!cell.cancel(h1));
+    let rejected = cell.replace_if_same(h1, |i: u32| i + 3).unwrap_err();
+    assert_eq!(rejected(1), 4);
+    assert_eq!(cell.take_call(1), Ok(3));
+    assert!(!cell.cancel(h2));
+    let h3 = cell.put(|i: u32| i + 4);
+    assert!(cell.cancel(h3));
+    assert!(!cell.is_set());
+    assert!(!cell.clear());
+    assert_eq!(std::format!("{:?}", cell), "StampedCallbackCellArgs(NULL)");
+
+    // a callback allocated at the address of a taken one doesn't match the
+    // taken one's handle
+    let mut reused = 0;
+    for _ in 0..100 {
+        let old = cell.put(|i: u32| i + 1);
+        assert_eq!(cell.take_call(1), Ok(2));
+        let new = cell.put(|i: u32| i + 2);
+        if new.addr() == old.addr() {
+            reused += 1;
+        }
+        assert!(!cell.cancel(old));
+        assert!(cell.replace_if_same(old, |i: u32| i + 3).is_err());
+        assert!(cell.is_current(new));
+        assert!(cell.cancel(new));
+    }
+    assert!(reused > 0, "allocator never reused an address");
+}
+
+#[cfg(feature = "wide")]
+#[test]
+fn wide_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    struct DropGuardThing(Arc<AtomicU32>);
+    impl Drop for DropGuardThing {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let cell = WideCallbackCellArgs::new();
+    assert!(!cell.is_set());
+    assert_eq!(cell.take_call(1), Err(1));
+    assert_eq!(std::format!("{:?}", cell), "WideCallbackCellArgs(NULL)");
+
+    // zero-sized, and capturing, callbacks
+    cell.put(|i: u32| i + 1);
+    assert!(cell.is_set());
+    assert_eq!(std::format!("{:?}", cell), "WideCallbackCellArgs(NOT NULL)");
+    assert_eq!(cell.take_call(1), Ok(2));
+    assert!(!cell.is_set());
+    let k = 10u32;
+    cell.put(move |i: u32| i + k);
+    assert_eq!(cell.take_call(1), Ok(11));
+    assert_eq!(cell.take_call(1), Err(1));
+
+    // callbacks replaced, cleared, or left in the cell are dropped unrun
+    let dropped = Arc::new(AtomicU32::new(0));
+    let callback = || {
+        let dgt = DropGuardThing(Arc::clone(&dropped));
+        move |i: u32| {
+            let _ = &dgt;
+            i
+        }
+    };
+    cell.put(callback());
+    cell.put(callback());
+    assert_eq!(dropped.load(Ordering::SeqCst), 1);
+    assert!(cell.clear());
+    assert!(!cell.clear());
+    assert_eq!(dropped.load(Ordering::SeqCst), 2);
+    cell.put(callback());
+    assert_eq!(cell.take_call(5), Ok(5));
+    assert_eq!(dropped.load(Ordering::SeqCst), 3);
+    cell.put(callback());
+    drop(cell);
+    assert_eq!(dropped.load(Ordering::SeqCst), 4);
+}
+
+#[test]
+fn callback_slot_map_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let map = CallbackSlotMap::new();
+    assert!(map.is_empty());
+    let k1 = map.insert(|i: u32| i + 1);
+    let k2 = map.insert(|i: u32| i + 2);
+    assert_ne!(k1, k2);
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.keys(), [k1, k2]);
+    assert_eq!(std::format!("{:?}", map), "CallbackSlotMap(2 PRESENT)");
+    assert_eq!(map.take_call(k1, 1), Ok(2));
+    assert_eq!(map.take_call(k1, 1), Err(1));
+    assert!(!map.contains(k1));
+    assert!(map.contains(k2));
+
+    // a stale key doesn't match the callback reusing its slot
+    let k3 = map.insert(|i: u32| i + 3);
+    assert_eq!(k3.index(), k1.index());
+    assert_ne!(k3.generation(), k1.generation());
+    assert_eq!(map.take_call(k1, 1), Err(1));
+    assert!(!map.remove(k1));
+    assert!(map.contains(k3));
+    assert!(map.remove(k3));
+    assert!(!map.remove(k3));
+    assert_eq!(map.take_call(k3, 1), Err(1));
+    assert_eq!(map.len(), 1);
+
+    // a batch runs its matched callbacks in order, and hands back the inputs
+    // of the rest
+    let b1 = map.insert(|i: u32| i * 10);
+    let b2 = map.insert(|i: u32| i * 100);
+    let outcome = map.take_call_many([(b2, 1), (k3, 2), (b1, 3), (b2, 4)]);
+    assert_eq!(outcome.outputs, [(b2, 100), (b1, 30)]);
+    assert_eq!(outcome.unmatched, [(k3, 2), (b2, 4)]);
+    assert_eq!(map.len(), 1);
+    let outcome = map.take_call_many(core::iter::empty());
+    assert!(outcome.outputs.is_empty() && outcome.unmatched.is_empty());
+
+    // callbacks cleared, or left in the map, are dropped unrun
+    let dropped = Arc::new(());
+    let callback = || {
+        let dropped = Arc::clone(&dropped);
+        move |i: u32| {
+            let _ = &dropped;
+            i
+        }
+    };
+    let keys: Vec<_> = (0..4).map(|_| map.insert(callback())).collect();
+    assert_eq!(Arc::strong_count(&dropped), 5);
+    assert_eq!(map.clear(), 5);
+    assert_eq!(Arc::strong_count(&dropped), 1);
+    assert!(map.is_empty());
+    assert_eq!(map.take_call(k2, 1), Err(1));
+    assert!(keys.iter().all(|&key| !map.contains(key)));
+    for _ in 0..3 {
+        map.insert(callback());
+    }
+    drop(map);
+    assert_eq!(Arc::strong_count(&dropped), 1);
+
+    // concurrent inserts and completions each run exactly once
+    let ran = AtomicU32::new(0);
+    let map = CallbackSlotMap::new();
+    thread::scope(|s| {
+        let (tx, rx) = std::sync::mpsc::channel();
+        for _ in 0..4 {
+            let tx = tx.clone();
+            let map = &map;
+            s.spawn(move || {
+                for i in 0..100 {
+                    let key = map.insert(|ran: &AtomicU32| {
+                        ran.fetch_add(1, Ordering::Relaxed);
+                    });
+                    if i % 4 == 0 {
+                        assert!(map.remove(key));
+                    } else {
+                        tx.send(key).unwrap();
+                    }
+                }
+            });
+        }
+        drop(tx);
+        for key in rx {
+            assert!(map.take_call(key, &ran).is_ok());
+            assert!(map.take_call(key, &ran).is_err());
+        }
+    });
+    assert_eq!(ran.load(Ordering::Relaxed), 300);
+    assert!(map.is_empty());
+}
+
+#[test]
+fn callback_slot_map_lru_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let evicted = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let map = CallbackSlotMap::with_capacity_lru(3, {
+        let evicted = Arc::clone(&evicted);
+        move |key, callback: RawCallbackArgs<u32, u32>| {
+            evicted.lock().unwrap().push((key, callback.call(0)));
+        }
+    });
+    assert_eq!(map.capacity(), Some(3));
+    assert_eq!(CallbackSlotMap::<u32, u32>::new().capacity(), None);
+    let k1 = map.insert(|i| i + 1);
+    let k2 = map.insert(|i| i + 2);
+    let k3 = map.insert(|i| i + 3);
+    assert_eq!(std::format!("{:?}", map), "CallbackSlotMap(3 OF 3 PRESENT)");
+
+    // the least recently inserted is evicted, and handed to the hook
+    let k4 = map.insert(|i| i + 4);
+    assert_eq!(map.len(), 3);
+    assert!(!map.contains(k1));
+    assert_eq!(*evicted.lock().unwrap(), [(k1, 1)]);
+
+    // touching refreshes recency, and taking leaves room without evicting
+    assert!(map.touch(k2));
+    assert!(!map.touch(k1));
+    let k5 = map.insert(|i| i + 5);
+    assert!(map.contains(k2));
+    assert!(!map.contains(k3));
+    assert_eq!(map.take_call(k4, 1), Ok(5));
+    let k6 = map.insert(|i| i + 6);
+    assert_eq!(*evicted.lock().unwrap(), [(k1, 1), (k3, 3)]);
+    let k7 = map.insert(|i| i + 7);
+    assert_eq!(*evicted.lock().unwrap(), [(k1, 1), (k3, 3), (k2, 2)]);
+    assert_eq!(map.keys().len(), 3);
+    for key in [k5, k6, k7] {
+        assert!(map.contains(key));
+    }
+
+    // removal unlinks from the middle, the list survives a clear, and a
+    // hook may drop the callback instead
+    assert!(map.remove(k6));
+    map.insert(|i| i);
+    assert!(map.contains(k5));
+    map.insert(|i| i);
+    assert!(!map.contains(k5));
+    assert_eq!(map.clear(), 3);
+    map.insert(|i| i);
+    assert_eq!(map.len(), 1);
+    drop(map);
+    let dropping = CallbackSlotMap::with_capacity_lru(1, |_, callback: RawCallbackArgs<u32, u32>| drop(callback));
+    let dropped = Arc::new(());
+    let d = Arc::clone(&dropped);
+    dropping.insert(move |i| { drop(d); i });
+    dropping.insert(|i| i);
+    assert_eq!(Arc::strong_count(&dropped), 1);
+    assert_eq!(test_util::live_allocations(), 1);
+}
+
+#[test]
+fn callback_slot_map_lru_concurrent_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    // however many threads insert at once, the capacity is never exceeded,
+    // and every callback is either present or evicted
+    let evicted = Arc::new(AtomicU32::new(0));
+    let map = Arc::new(CallbackSlotMap::with_capacity_lru(8, {
+        let evicted = Arc::clone(&evicted);
+        move |_, _: RawCallbackArgs<(), ()>| {
+            evicted.fetch_add(1, Ordering::SeqCst);
+        }
+    }));
+    let threads = (0..4)
+        .map(|_| {
+            let map = Arc::clone(&map);
+            thread::spawn(move || {
+                for _ in 0..200 {
+                    map.insert(|()| ());
+                    assert!(map.len() <= 8);
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(map.len(), 8);
+    assert_eq!(evicted.load(Ordering::SeqCst), 800 - 8);
+}
+
+#[test]
+fn quiescent_callback_cell_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell = QuiescentCallbackCellArgs::<u32, u32>::new();
+    assert!(!cell.clear_and_wait());
+    cell.put(|i| i + 1);
+    assert!(cell.is_set());
+    assert!(cell.put_and_wait_previous(|i| i + 2));
+    assert_eq!(cell.take_call(1), Ok(3));
+    assert_eq!(cell.take_call(1), Err(1));
+    assert_eq!(std::format!("{:?}", cell), "QuiescentCallbackCellArgs(NULL)");
+
+    // waits for a callback taken beforehand to finish
+    static STARTED: AtomicBool = AtomicBool::new(false);
+    static RELEASE: AtomicBool = AtomicBool::new(false);
+    static FINISHED: AtomicBool = AtomicBool::new(false);
+    let cell = QuiescentCallbackCellArgs::<(), ()>::new();
+    cell.put(|()| {
+        STARTED.store(true, Ordering::SeqCst);
+        while !RELEASE.load(Ordering::SeqCst) {
+            thread::yield_now();
+        }
+        FINISHED.store(true, Ordering::SeqCst);
+    });
+    thread::scope(|s| {
+        let taker = s.spawn(|| cell.take_call(()).is_ok());
+        while !STARTED.load(Ordering::SeqCst) {
+            thread::yield_now();
+        }
+        let waiter = s.spawn(|| {
+            assert!(!cell.clear_and_wait());
+            FINISHED.load(Ordering::SeqCst)
+        });
+        thread::sleep(core::time::Duration::from_millis(10));
+        assert!(!FINISHED.load(Ordering::SeqCst));
+        RELEASE.store(true, Ordering::SeqCst);
+        assert!(taker.join().unwrap());
+        assert!(waiter.join().unwrap());
+    });
+
+    // waiting from within the cell's own callback panics, without changing it
+    let cell = Arc::new(QuiescentCallbackCellArgs::<(), bool>::new());
+    cell.put({
+        let cell = Arc::clone(&cell);
+        move |()| {
+            cell.put(|()| true);
+            std::panic::catch_unwind(|| cell.clear_and_wait()).is_err()
+        }
+    });
+    assert_eq!(cell.take_call(()), Ok(true));
+    assert!(cell.is_set());
+    assert!(cell.clear_and_wait());
+}
+
+#[test]
+fn sharded_callback_buffer_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let mut buffer = ShardedCallbackBuffer::with_shards(2);
+    assert_eq!(buffer.shards(), 2);
+    assert!(buffer.is_empty());
+    assert_eq!(buffer.drain(), 0);
+
+    // each thread's callbacks run in the order it pushed them, including
+    // those of threads which had to use the overflow shard
+    let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+    for _ in 0..2 {
+        thread::scope(|s| {
+            for t in 0..4 {
+                let buffer = &buffer;
+                let log = &log;
+                s.spawn(move || {
+                    for i in 0..50 {
+                        let log = Arc::clone(log);
+                        buffer.push(move || log.lock().unwrap().push((t, i)));
+                    }
+                });
+            }
+        });
+        assert_eq!(buffer.len(), 200);
+        assert_eq!(buffer.drain(), 200);
+        assert!(buffer.is_empty());
+        let log = core::mem::take(&mut *log.lock().unwrap());
+        assert_eq!(log.len(), 200);
+        for t in 0..4 {
+            let order: Vec<_> = log.iter().filter(|&&(u, _)| u == t).map(|&(_, i)| i).collect();
+            assert_eq!(order, (0..50).collect::<Vec<_>>());
+        }
+    }
+
+    // callbacks cleared, or left in the buffer, are dropped unrun
+    let dropped = Arc::new(());
+    for _ in 0..3 {
+        let dropped = Arc::clone(&dropped);
+        buffer.push(move || drop(dropped));
+    }
+    assert_eq!(buffer.clear(), 3);
+    assert_eq!(Arc::strong_count(&dropped), 1);
+    let dropped_2 = Arc::clone(&dropped);
+    buffer.push(move || drop(dropped_2));
+    drop(buffer);
+    assert_eq!(Arc::strong_count(&dropped), 1);
+}
+
+#[test]
+fn sharded_callback_cell_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    // a put on one thread replaces the callback it put before
+    let cell = ShardedCallbackCell::<4>::new();
+    assert!(!cell.is_set());
+    assert!(!cell.take_call_any());
+    let ran = Arc::new(AtomicU32::new(0));
+    for i in 1..=3 {
+        let ran = Arc::clone(&ran);
+        cell.put(move || ran.store(i, Ordering::Relaxed));
+    }
+    assert_eq!(cell.pending(), 1);
+    assert!(cell.take_call_any());
+    assert_eq!(ran.load(Ordering::Relaxed), 3);
+    assert!(!cell.is_set());
+
+    // puts on other threads may land in other shards, and are all taken
+    let cell = ShardedCallbackCell::<4>::new();
+    thread::scope(|s| {
+        for _ in 0..8 {
+            s.spawn(|| {
+                let ran = Arc::clone(&ran);
+                cell.put(move || {
+                    ran.fetch_add(1, Ordering::Relaxed);
+                });
+            });
+        }
+    });
+    let pending = cell.pending();
+    assert!((1..=4).contains(&pending));
+    assert_eq!(std::format!("{:?}", cell), std::format!("ShardedCallbackCell({} OF 4 SHARDS NOT NULL)", pending));
+    ran.store(0, Ordering::Relaxed);
+    assert!(cell.take_call_any());
+    assert_eq!(cell.take_call_all_shards(), pending - 1);
+    assert_eq!(ran.load(Ordering::Relaxed) as usize, pending);
+    assert_eq!(cell.take_call_all_shards(), 0);
+
+    // with one shard, it's a `CallbackCell`
+    static CELL: ShardedCallbackCell<1> = ShardedCallbackCell::new();
+    thread::spawn(|| CELL.put(|| ())).join().unwrap();
+    let ran_2 = Arc::clone(&ran);
+    CELL.put(move || drop(ran_2));
+    assert_eq!(CELL.pending(), 1);
+    assert_eq!(CELL.clear(), 1);
+    assert_eq!(Arc::strong_count(&ran), 1);
+
+    // callbacks left in the cell are dropped unrun
+    let ran_2 = Arc::clone(&ran);
+    cell.put(move || drop(ran_2));
+    drop(cell);
+    assert_eq!(Arc::strong_count(&ran), 1);
+}
+
+#[test]
+fn blocking_callback_queue_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    const WORKERS: usize = 4;
+    const PRODUCERS: u32 = 4;
+    const PUTS: u32 = 500;
+    let queue = BlockingCallbackQueue::new();
+    assert!(!queue.take_call());
+
+    // every push runs exactly once, on a worker or on the thread taking
+    // without blocking, and no worker is left waiting with work pending
+    let ran = Arc::new(AtomicU32::new(0));
+    let stolen = AtomicU32::new(0);
+    std::thread_local! {
+        static STOP: core::cell::Cell<bool> = const { core::cell::Cell::new(false) };
+    }
+    thread::scope(|s| {
+        let workers: Vec<_> = (0..WORKERS)
+            .map(|_| s.spawn(|| {
+                let mut calls = 0;
+                while !STOP.with(|stop| stop.get()) {
+                    queue.wait_call();
+                    calls += 1;
+                }
+                calls
+            }))
+            .collect();
+        let stealer = s.spawn(|| {
+            while ran.load(Ordering::SeqCst) < PRODUCERS * PUTS {
+                stolen.fetch_add(queue.take_call() as u32, Ordering::SeqCst);
+                thread::yield_now();
+            }
+        });
+        for _ in 0..PRODUCERS {
+            s.spawn(|| {
+                for _ in 0..PUTS {
+                    let ran = Arc::clone(&ran);
+                    queue.push(move || {
+                        ran.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+            });
+        }
+        stealer.join().unwrap();
+        // wake each worker with one more callback, which stops it
+        for _ in 0..WORKERS {
+            queue.push(|| STOP.with(|stop| stop.set(true)));
+        }
+        let calls: u32 = workers.into_iter().map(|worker| worker.join().unwrap()).sum();
+        assert_eq!(calls + stolen.load(Ordering::SeqCst), PRODUCERS * PUTS + WORKERS as u32);
+    });
+    assert_eq!(ran.load(Ordering::SeqCst), PRODUCERS * PUTS);
+    assert!(queue.is_empty());
+    assert_eq!(queue.waiting(), 0);
+
+    // callbacks run in order, and those cleared or left are dropped unrun
+    let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+    for i in 0..3 {
+        let log = Arc::clone(&log);
+        queue.push(move || log.lock().unwrap().push(i));
+    }
+    assert_eq!(std::format!("{:?}", queue), "BlockingCallbackQueue(3 PENDING)");
+    queue.wait_call();
+    assert!(queue.take_call());
+    assert_eq!(*log.lock().unwrap(), [0, 1]);
+    assert_eq!(queue.clear(), 1);
+    let log_2 = Arc::clone(&log);
+    queue.push(move || drop(log_2));
+    drop(queue);
+    assert_eq!(Arc::strong_count(&log), 1);
+}
+
+#[test]
+fn callback_queue_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    // yields in order, including what callbacks run in the loop push, and
+    // ends once every sender is gone and the queue is drained
+    let (sender, receiver) = callback_queue();
+    let order = Arc::new(std::sync::Mutex::new(Vec::new()));
+    for i in 0..3 {
+        let order = Arc::clone(&order);
+        sender.push(move || order.lock().unwrap().push(i)).unwrap();
+    }
+    {
+        let order = Arc::clone(&order);
+        let sender = sender.clone();
+        sender.clone().push(move || {
+            order.lock().unwrap().push(3);
+            sender.push(move || order.lock().unwrap().push(4)).unwrap();
+        }).unwrap();
+    }
+    assert_eq!(receiver.len(), 4);
+    assert_eq!(std::format!("{:?}", receiver), "CallbackQueueReceiver(4 PENDING)");
+    drop(sender);
+    for callback in receiver.iter_blocking() {
+        callback.call();
+    }
+    assert_eq!(*order.lock().unwrap(), [0, 1, 2, 3, 4]);
+    assert!(receiver.recv().is_none());
+    assert!(receiver.try_recv().is_none());
+
+    // a waiting receiver takes what's pushed, and stops when the last sender
+    // drops
+    let (sender, receiver) = callback_queue();
+    let ran = Arc::new(AtomicU32::new(0));
+    let worker = thread::spawn({
+        let ran = Arc::clone(&ran);
+        move || {
+            let mut count = 0;
+            for callback in receiver.iter_blocking() {
+                callback.call();
+                count += 1;
+            }
+            assert_eq!(ran.load(Ordering::SeqCst), count);
+            count
+        }
+    });
+    let senders = (0..4)
+        .map(|_| {
+            let sender = sender.clone();
+            let ran = Arc::clone(&ran);
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    let ran = Arc::clone(&ran);
+                    sender.push(move || { ran.fetch_add(1, Ordering::SeqCst); }).unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in senders {
+        thread.join().unwrap();
+    }
+    drop(sender);
+    assert_eq!(worker.join().unwrap(), 400);
+
+    // dropping the receiver drops what's queued, and disconnects the senders
+    let (sender, receiver) = callback_queue();
+    let dropped = Arc::new(());
+    let d = Arc::clone(&dropped);
+    sender.push(move || drop(d)).unwrap();
+    drop(receiver);
+    assert_eq!(Arc::strong_count(&dropped), 1);
+    let d = Arc::clone(&dropped);
+    assert_eq!(sender.push(move || drop(d)), Err(Disconnected));
+    assert_eq!(Arc::strong_count(&dropped), 1);
+    assert_eq!(test_util::live_allocations(), 0);
+}
+
+#[test]
+fn shutdown_hooks_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    static HOOKS: ShutdownHooks = ShutdownHooks::new();
+    let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let hook = |i: u32| {
+        let log = Arc::clone(&log);
+        move || log.lock().unwrap().push(i)
+    };
+    assert!(HOOKS.is_empty());
+    HOOKS.register(hook(0));
+    let h1 = HOOKS.register(hook(1));
+    HOOKS.register(hook(2));
+    // a hook registered during the run runs immediately
+    HOOKS.register({
+        let late = hook(4);
+        let log = Arc::clone(&log);
+        move || {
+            log.lock().unwrap().push(3);
+            HOOKS.register(late);
+        }
+    });
+    assert!(HOOKS.deregister(h1));
+    assert!(!HOOKS.deregister(h1));
+    assert_eq!(HOOKS.len(), 3);
+    assert_eq!(std::format!("{:?}", HOOKS), "ShutdownHooks(3 REGISTERED)");
+    assert!(!HOOKS.has_run());
+    assert_eq!(HOOKS.run(), 3);
+    assert_eq!(*log.lock().unwrap(), [3, 4, 2, 0]);
+    assert!(HOOKS.has_run());
+    assert_eq!(std::format!("{:?}", HOOKS), "ShutdownHooks(RUN)");
+
+    // later runs do nothing, and later registrations run immediately
+    assert_eq!(HOOKS.run(), 0);
+    let h5 = HOOKS.register(hook(5));
+    assert_eq!(*log.lock().unwrap(), [3, 4, 2, 0, 5]);
+    assert!(!HOOKS.deregister(h5));
+    assert!(HOOKS.is_empty());
+
+    // hooks left registered are dropped unrun
+    let hooks = ShutdownHooks::new();
+    hooks.register(hook(6));
+    drop(hooks);
+    assert_eq!(Arc::strong_count(&log), 1);
+    assert_eq!(log.lock().unwrap().len(), 5);
+}
+
+#[test]
+fn interest_callback_cell_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell = InterestCallbackCell::new();
+    let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let push = |s: &'static str| {
+        let log = Arc::clone(&log);
+        move || log.lock().unwrap().push(s)
+    };
+    assert_eq!(cell.fire(Interest::BOTH), Interest::NONE);
+
+    // each slot is replaced independently
+    cell.put_read(push("read 1"));
+    cell.put_write(push("write 1"));
+    cell.put_read(push("read 2"));
+    assert_eq!(cell.armed(), Interest::BOTH);
+    assert_eq!(std::format!("{:?}", cell), "InterestCallbackCell(READABLE | WRITABLE)");
+
+    // each fires independently, at most once
+    assert_eq!(cell.fire(Interest::WRITABLE), Interest::WRITABLE);
+    assert_eq!(cell.fire(Interest::WRITABLE), Interest::NONE);
+    assert_eq!(cell.armed(), Interest::READABLE);
+    cell.put_write(push("write 2"));
+    assert_eq!(cell.fire(Interest::READABLE | Interest::WRITABLE), Interest::BOTH);
+    assert_eq!(*log.lock().unwrap(), ["write 1", "read 2", "write 2"]);
+
+    // clear drops without running
+    cell.put_read(push("read 3"));
+    cell.put_write(push("write 3"));
+    assert_eq!(cell.clear(Interest::READABLE), Interest::READABLE);
+    assert_eq!(cell.armed(), Interest::WRITABLE);
+    drop(cell);
+    assert_eq!(log.lock().unwrap().len(), 3);
+    assert_eq!(Arc::strong_count(&log), 1);
+}
+
+#[test]
+fn masked_callback_cell_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    const READ: u32 = 1;
+    const WRITE: u32 = 2;
+    const HUP: u32 = 4;
+    let cell = MaskedCallbackCell::new();
+    assert_eq!(cell.fire(READ), FireOutcome::Empty);
+    assert_eq!(cell.mask(), None);
+
+    // a mismatch leaves the callback armed
+    let fired = Arc::new(AtomicU32::new(0));
+    let fired_2 = Arc::clone(&fired);
+    cell.put_masked(READ | HUP, move |events| fired_2.store(events, Ordering::Relaxed));
+    assert_eq!(cell.mask(), Some(READ | HUP));
+    assert_eq!(std::format!("{:?}", cell), "MaskedCallbackCell(NOT NULL: 0x5)");
+    assert_eq!(cell.fire(WRITE), FireOutcome::NoMatch);
+    assert!(cell.is_set());
+    assert_eq!(cell.fire(0), FireOutcome::NoMatch);
+
+    // a match runs it, with the events fired, once
+    assert_eq!(cell.fire(WRITE | HUP), FireOutcome::Ran);
+    assert_eq!(fired.load(Ordering::Relaxed), WRITE | HUP);
+    assert_eq!(cell.fire(HUP), FireOutcome::Empty);
+
+    // re-registering replaces the mask
+    let fired_2 = Arc::clone(&fired);
+    cell.put_masked(READ, move |events| fired_2.store(events, Ordering::Relaxed));
+    let fired_2 = Arc::clone(&fired);
+    cell.put_masked(WRITE, move |events| fired_2.store(events, Ordering::Relaxed));
+    assert_eq!(cell.fire(READ), FireOutcome::NoMatch);
+    assert_eq!(cell.mask(), Some(WRITE));
+    assert!(cell.clear());
+    assert!(!cell.is_set());
+    let fired_2 = Arc::clone(&fired);
+    cell.put_masked(WRITE, move |events| fired_2.store(events, Ordering::Relaxed));
+    drop(cell);
+    assert_eq!(Arc::strong_count(&fired), 1);
+}
+
+#[test]
+fn masked_callback_cell_threads_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    // each callback runs at most once, and only for matching events, while
+    // others replace and fire it, and every one is either run or dropped
+    const PUTS: u32 = 2000;
+    let cell = MaskedCallbackCell::new();
+    let ran = Arc::new(AtomicU32::new(0));
+    let mismatched = AtomicBool::new(false);
+    thread::scope(|s| {
+        s.spawn(|| {
+            for i in 0..PUTS {
+                let ran = Arc::clone(&ran);
+                let mask = if i % 2 == 0 { 1 } else { 2 };
+                cell.put_masked(mask, move |events| {
+                    ran.fetch_add(1, Ordering::SeqCst);
+                    assert_ne!(events & mask, 0);
+                });
+                if i % 3 == 0 {
+                    cell.clear();
+                }
+            }
+        });
+        for events in [1, 2, 3] {
+            let (cell, mismatched) = (&cell, &mismatched);
+            s.spawn(move || {
+                for _ in 0..PUTS {
+                    match cell.fire(events) {
+                        FireOutcome::NoMatch if events == 3 => mismatched.store(true, Ordering::SeqCst),
+                        _ => {}
+                    }
+                    let _ = cell.mask();
+                }
+            });
+        }
+    });
+    assert!(!mismatched.load(Ordering::SeqCst));
+    assert!(ran.load(Ordering::SeqCst) <= PUTS);
+    drop(cell);
+    assert_eq!(Arc::strong_count(&ran), 1);
+}
+
+#[test]
+fn cloneable_callback_cell_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell = CloneableCallbackCellArgs::new();
+    assert_eq!(cell.take_call_cloned(1), Err(1));
+    let ran = Arc::new(AtomicU32::new(0));
+    let f = {
+        let ran = Arc::clone(&ran);
+        move |n: u32| n + ran.fetch_add(1, Ordering::SeqCst)
+    };
+
+    // cloned takes leave the original armed, and ordinary takes consume it
+    cell.put_cloneable(f);
+    assert_eq!(test_util::live_allocations(), 1);
+    assert_eq!(cell.take_call_cloned(10), Ok(10));
+    assert_eq!(cell.take_call_cloned(10), Ok(11));
+    assert!(cell.is_set());
+    assert_eq!(test_util::live_allocations(), 1);
+    assert_eq!(Arc::strong_count(&ran), 2);
+    assert_eq!(std::format!("{:?}", cell), "CloneableCallbackCellArgs(NOT NULL)");
+    assert_eq!(cell.take_call(10), Ok(12));
+    assert_eq!(cell.take_call(10), Err(10));
+    assert_eq!(cell.take_call_cloned(10), Err(10));
+    assert_eq!(Arc::strong_count(&ran), 1);
+
+    // a panicking clone leaves the original in place, and the cell unlocked
+    struct PanicOnClone;
+    impl Clone for PanicOnClone {
+        fn clone(&self) -> Self {
+            panic!("clone")
+        }
+    }
+    let guard = (PanicOnClone, Arc::clone(&ran));
+    cell.put_cloneable(move |n| {
+        let _ = &guard;
+        n
+    });
+    let result = std::panic::catch_unwind(|| cell.take_call_cloned(1));
+    assert!(result.is_err());
+    assert!(cell.is_set());
+    assert_eq!(cell.take_call(1), Ok(1));
+    assert_eq!(Arc::strong_count(&ran), 1);
+
+    // replaced, cleared, and dropped with the cell
+    let f = {
+        let ran = Arc::clone(&ran);
+        move |n: u32| n + ran.load(Ordering::SeqCst)
+    };
+    cell.put_cloneable(f.clone());
+    cell.put_cloneable(f.clone());
+    assert_eq!(Arc::strong_count(&ran), 3);
+    assert!(cell.clear());
+    assert!(!cell.clear());
+    cell.put_cloneable(f);
+    drop(cell);
+    assert_eq!(Arc::strong_count(&ran), 1);
+}
+
+#[test]
+fn raw_callback_test() {
+    use std::sync::atomic::AtomicPtr;
+
+    let _leak_check = test_util::LeakCheck::new();
+
+    // calling consumes the callback
+    let callback = RawCallbackArgs::new(|n: u32| n * 2);
+    assert_eq!(callback.call(21), 42);
+
+    // a container of one's own, with the low bit as a tag
+    let slot = AtomicPtr::new(core::ptr::null_mut::<()>());
+    let ran = Arc::new(AtomicU32::new(0));
+    let ran_2 = Arc::clone(&ran);
+    let ptr = RawCallback::new(move || {
+        ran_2.fetch_add(1, Ordering::Relaxed);
+    }).into_raw();
+    assert_eq!(ptr.as_ptr() as usize & 1, 0);
+    slot.store(ptr.as_ptr().map_addr(|addr| addr | 1), Ordering::Release);
+    let tagged = slot.swap(core::ptr::null_mut(), Ordering::Acquire);
+    assert_eq!(tagged as usize & 1, 1);
+    let ptr = core::ptr::NonNull::new(tagged.map_addr(|addr| addr & !1)).unwrap();
+    let callback = unsafe { RawCallback::from_raw(ptr) };
+    callback.call();
+    assert_eq!(ran.load(Ordering::Relaxed), 1);
+
+    // dropping drops it without running it, and the two types interconvert
+    let ran_2 = Arc::clone(&ran);
+    let callback = RawCallbackArgs::<(), ()>::from(RawCallback::new(move || drop(ran_2)));
+    let callback = unsafe { RawCallbackArgs::<(), ()>::from_raw(callback.into_raw()) };
+    drop(RawCallback::from(callback));
+    assert_eq!(Arc::strong_count(&ran), 1);
+    assert_eq!(ran.load(Ordering::Relaxed), 1);
+
+    // the allocation is freed even if the callback panics
+    let callback = RawCallback::new(|| panic!("raw callback panicked"));
+    assert!(std::panic::catch_unwind(|| callback.call()).is_err());
+}
+
+#[test]
+fn any_callback_cell_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    struct Frame(Arc<u32>);
+    let ran = Arc::new(AtomicU32::new(0));
+    let on_frame = || {
+        let ran = Arc::clone(&ran);
+        move |frame: Frame| {
+            ran.fetch_add(*frame.0, Ordering::SeqCst);
+        }
+    };
+    let payload = Arc::new(3);
+    let cell = AnyCallbackCell::new();
+    assert!(!cell.is_set());
+    assert!(cell.fire_any(Box::new(Frame(Arc::clone(&payload)))).is_err());
+    assert_eq!(std::format!("{:?}", cell), "AnyCallbackCell(NULL)");
+
+    cell.on(on_frame());
+    assert!(cell.accepts::<Frame>());
+    assert!(!cell.accepts::<u32>());
+    assert_eq!(std::format!("{:?}", cell), "AnyCallbackCell(NOT NULL)");
+    // a mismatch hands the input back, and leaves the callback
+    let input = cell.fire_any(Box::new(5_u32)).unwrap_err();
+    assert_eq!(input.downcast_ref::<u32>(), Some(&5));
+    assert_eq!(cell.fire(5_u32), Err(5));
+    assert!(cell.is_set());
+    assert!(cell.fire_any(Box::new(Frame(Arc::clone(&payload)))).is_ok());
+    assert_eq!(ran.load(Ordering::SeqCst), 3);
+    assert!(!cell.is_set());
+    assert_eq!(Arc::strong_count(&payload), 1);
+
+    // the typed path
+    cell.on(on_frame());
+    assert!(cell.fire(Frame(Arc::clone(&payload))).is_ok());
+    assert_eq!(ran.load(Ordering::SeqCst), 6);
+    let Err(Frame(returned)) = cell.fire(Frame(Arc::clone(&payload))) else { panic!() };
+    drop(returned);
+    assert_eq!(Arc::strong_count(&payload), 1);
+
+    // replacing with another type, and clearing
+    cell.on(on_frame());
+    cell.on(|_: u32| ());
+    assert!(cell.accepts::<u32>());
+    assert_eq!(Arc::strong_count(&ran), 1);
+    assert!(cell.clear());
+    assert!(!cell.clear());
+    cell.on(on_frame());
+    drop(cell);
+    assert_eq!(Arc::strong_count(&ran), 1);
+}
+
+#[test]
+fn scoped_test() {
+    use core::cell::RefCell;
+
+    struct Progress(u32);
+    let log = RefCell::new(Vec::new());
+    assert!(matches!(scoped::invoke(Progress(0)), Err(Progress(0))));
+    assert!(!scoped::is_registered::<Progress>());
+
+    scoped::with(|p: Progress| log.borrow_mut().push(("outer", p.0)), || {
+        assert!(scoped::invoke(Progress(1)).is_ok());
+        scoped::with(|p: Progress| {
+            log.borrow_mut().push(("inner", p.0));
+            // reaches the outer handler
+            assert!(scoped::invoke(Progress(p.0 + 10)).is_ok());
+        }, || {
+            assert!(scoped::invoke(Progress(2)).is_ok());
+            // other types are unaffected
+            assert_eq!(scoped::invoke(3_u32), Err(3));
+            // not shared with other threads
+            thread::spawn(|| assert!(!scoped::is_registered::<Progress>())).join().unwrap();
+        });
+        // unregistered on unwind
+        let unwound = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            scoped::with(|_: Progress| log.borrow_mut().push(("unwound", 0)), || panic!("scoped_test"))
+        }));
+        assert!(unwound.is_err());
+        assert!(scoped::invoke(Progress(3)).is_ok());
+    });
+    assert!(!scoped::is_registered::<Progress>());
+    assert_eq!(*log.borrow(), [("outer", 1), ("inner", 2), ("outer", 12), ("outer", 3)]);
+
+    // the value is dropped whether or not a handler takes it
+    let value = Arc::new(());
+    assert!(scoped::invoke(Arc::clone(&value)).is_err());
+    assert_eq!(scoped::with(|v: Arc<()>| drop(v), || scoped::invoke(Arc::clone(&value))), Ok(()));
+    assert_eq!(Arc::strong_count(&value), 1);
+}
+
+#[test]
+fn sticky_event_cell_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    struct DropGuardThing(Arc<AtomicU32>);
+    impl Drop for DropGuardThing {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let ran = Arc::new(AtomicU32::new(0));
+    let dropped = Arc::new(AtomicU32::new(0));
+    let callback = || {
+        let ran = Arc::clone(&ran);
+        let dgt = DropGuardThing(Arc::clone(&dropped));
+        move |value: Arc<u32>| {
+            let _ = &dgt;
+            ran.fetch_add(*value, Ordering::SeqCst);
+        }
+    };
+    let value = Arc::new(1);
+    let cell = StickyEventCell::new();
+    assert!(!cell.has_fired());
+    assert!(cell.value().is_none());
+    assert!(!cell.put(callback()));
+    assert!(!cell.put(callback()));
+    assert_eq!(cell.pending(), 2);
+    assert_eq!(ran.load(Ordering::SeqCst), 0);
+    assert_eq!(cell.fire(Arc::clone(&value)), Ok(()));
+    assert_eq!(ran.load(Ordering::SeqCst), 2);
+    assert_eq!(dropped.load(Ordering::SeqCst), 2);
+    assert!(cell.has_fired());
+    assert_eq!(cell.pending(), 0);
+    assert!(cell.put(callback()));
+    assert_eq!(ran.load(Ordering::SeqCst), 3);
+    assert_eq!(dropped.load(Ordering::SeqCst), 3);
+    let refired = cell.fire(Arc::new(5)).unwrap_err();
+    assert_eq!(*refired, 5);
+    assert_eq!(**cell.value().unwrap(), 1);
+    assert_eq!(std::format!("{:?}", cell), "StickyEventCell(FIRED)");
+    assert_eq!(Arc::strong_count(&value), 2);
+    drop(cell);
+    assert_eq!(Arc::strong_count(&value), 1);
+
+    // callbacks pending when the cell drops are dropped without running
+    let cell = StickyEventCell::<Arc<u32>>::new();
+    cell.put(callback());
+    drop(cell);
+    assert_eq!(ran.load(Ordering::SeqCst), 3);
+    assert_eq!(dropped.load(Ordering::SeqCst), 4);
+
+    // a put concurrent with the fire runs exactly once, on one side or the
+    // other
+    for _ in 0..1000 {
+        let cell = StickyEventCell::new();
+        let ran = Arc::new(AtomicU32::new(0));
+        thread::scope(|s| {
+            s.spawn(|| cell.fire(Arc::clone(&value)).unwrap());
+            let ran = Arc::clone(&ran);
+            cell.put(move |value: Arc<u32>| {
+                ran.fetch_add(*value, Ordering::SeqCst);
+            });
+        });
+        assert_eq!(ran.load(Ordering::SeqCst), 1);
+    }
+}
+
+#[test]
+fn multi_callback_cell_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell = Arc::new(MultiCallbackCellArgs::new());
+    assert_eq!(cell.call(1), Err(1));
+    cell.put(|i: u32| i + 1);
+    assert_eq!(cell.call(1), Ok(2));
+    assert_eq!(cell.call(2), Ok(3));
+    assert!(cell.is_set());
+
+    // calls in progress keep the handler alive when it's replaced
+    let threads = (0..4)
+        .map(|_| thread::spawn({
+            let cell = Arc::clone(&cell);
+            move || (0..100).all(|i| matches!(cell.call(i), Ok(o) if o == i + 1 || o == i * 2))
+        }))
+        .collect::<Vec<_>>();
+    cell.put(|i: u32| i * 2);
+    for thread in threads {
+        assert!(thread.join().unwrap());
+    }
+    assert!(cell.clear());
+    assert!(!cell.clear());
+
+    // waiting for a handler
+    let (counting, waker) = CountingWaker::new();
+    let mut cx = Context::from_waker(&waker);
+    let mut wait = pin!(cell.wait_set());
+    assert!(wait.as_mut().poll(&mut cx).is_pending());
+    cell.put(|i: u32| i);
+    assert_eq!(counting.count(), 1);
+    assert!(wait.as_mut().poll(&mut cx).is_ready());
+}
+
+#[test]
+fn call_each_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell = MultiCallbackCellArgs::new();
+    let mut inputs = cell.call_each(0..3).unwrap_err();
+    assert_eq!(inputs.next(), Some(0));
+    cell.put(|i: u32| i * 2);
+    assert_eq!(cell.call_each(0..3), Ok(std::vec![0, 2, 4]));
+    let mut sum = 0;
+    assert_eq!(cell.call_each_with(0..4, |o| sum += o), Ok(4));
+    assert_eq!(sum, 12);
+
+    // the batch finishes with the handler it started with
+    let cell = Arc::new(cell);
+    let replacing = (0..3).inspect(|&i| if i == 1 {
+        cell.put(|i: u32| i + 100);
+    });
+    assert_eq!(cell.call_each(replacing).ok().unwrap(), [0, 2, 4]);
+    assert_eq!(cell.call(0), Ok(100));
+}
+
+#[cfg(feature = "crossbeam-epoch")]
+#[test]
+fn epoch_cell_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    struct DropGuardThing(Arc<AtomicU32>);
+    impl Drop for DropGuardThing {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let cell = EpochCallbackCellFn::new();
+    assert!(!cell.is_set());
+    assert_eq!(cell.call(1), Err(1));
+    assert_eq!(std::format!("{:?}", cell), "EpochCallbackCellFn(NULL)");
+    cell.put(|i: u32| i * 2);
+    assert_eq!(cell.call(2), Ok(4));
+    assert_eq!(cell.call_each(0..3), Ok(std::vec![0, 2, 4]));
+    assert_eq!((cell.as_closure())(3), Some(6));
+    assert_eq!(std::format!("{:?}", cell), "EpochCallbackCellFn(NOT NULL)");
+    assert!(cell.clear());
+    assert!(!cell.clear());
+    futures::executor::block_on(async {
+        let wait = cell.wait_set();
+        cell.put(|i: u32| i);
+        wait.await;
+    });
+
+    // calls overlapping replacements see a live handler, and every handler
+    // is eventually dropped
+    let dropped = Arc::new(AtomicU32::new(0));
+    let cell = EpochCallbackCellFn::new();
+    let dgt = DropGuardThing(Arc::clone(&dropped));
+    cell.put(move |i: u32| (dgt.0.load(Ordering::SeqCst), i).1);
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for i in 0..10000 {
+                    assert_eq!(cell.call(i), Ok(i));
+                }
+            });
+        }
+        for _ in 0..100 {
+            let dgt = DropGuardThing(Arc::clone(&dropped));
+            cell.put(move |i: u32| (dgt.0.load(Ordering::SeqCst), i).1);
+        }
+    });
+    drop(cell);
+    for _ in 0..10000 {
+        if dropped.load(Ordering::SeqCst) == 101 {
+            break;
+        }
+        crossbeam::epoch::pin().flush();
+    }
+    assert_eq!(dropped.load(Ordering::SeqCst), 101);
+}
+
+#[cfg(feature = "futures")]
+#[test]
+fn callback_sink_test() {
+    use futures::{SinkExt, StreamExt};
+
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell = MultiCallbackCellArgs::new();
+    let received = Arc::new(std::sync::Mutex::new(Vec::new()));
+    thread::scope(|s| {
+        s.spawn(|| {
+            thread::sleep(std::time::Duration::from_millis(10));
+            let received = Arc::clone(&received);
+            cell.put(move |i: u32| received.lock().unwrap().push(i));
+        });
+        let stream = futures::stream::iter(0..10).map(Ok);
+        futures::executor::block_on(stream.forward(cell.sink())).unwrap();
+    });
+    assert_eq!(*received.lock().unwrap(), (0..10).collect::<Vec<_>>());
+
+    // readiness tracks whether a handler is present
+    let cell = MultiCallbackCellArgs::<u32, ()>::new();
+    let mut sink = cell.sink();
+    let (counting, waker) = CountingWaker::new();
+    let mut cx = Context::from_waker(&waker);
+    assert!(sink.poll_ready_unpin(&mut cx).is_pending());
+    cell.put(|_| ());
+    assert_eq!(counting.count(), 1);
+    assert_eq!(sink.poll_ready_unpin(&mut cx), Poll::Ready(Ok(())));
+    cell.clear();
+    assert_eq!(sink.start_send_unpin(5), Err(NoHandler(5)));
+}
+
+#[cfg(feature = "futures")]
+#[test]
+fn deferred_stream_test() {
+    use futures::StreamExt;
+
+    let _leak_check = test_util::LeakCheck::new();
+
+    let queue = DeferredCallQueue::new();
+    queue.push(|i: u32| i + 1, 1).unwrap();
+    let mut stream = queue.stream();
+    let (counting, waker) = CountingWaker::new();
+    let mut cx = Context::from_waker(&waker);
+    let Poll::Ready(Some(call)) = stream.poll_next_unpin(&mut cx) else { panic!() };
+    assert_eq!(call.call(), 2);
+    assert!(stream.poll_next_unpin(&mut cx).is_pending());
+
+    // a push into the empty queue wakes the stream, later pushes don't
+    queue.push(|i: u32| i + 2, 1).unwrap();
+    assert_eq!(counting.count(), 1);
+    queue.push(|i: u32| i + 3, 1).unwrap();
+    queue.push(|i: u32| i + 4, 1).unwrap();
+    assert_eq!(counting.count(), 1);
+    let Poll::Ready(Some(call)) = stream.poll_next_unpin(&mut cx) else { panic!() };
+    assert_eq!(call.call(), 3);
+
+    // entries taken by a dropped stream stay queued, ahead of later pushes,
+    // and a new stream yields them
+    let Poll::Ready(Some(unrun)) = stream.poll_next_unpin(&mut cx) else { panic!() };
+    drop(unrun);
+    drop(stream);
+    queue.push(|i: u32| i + 5, 1).unwrap();
+    let outputs = futures::executor::block_on(async {
+        let mut stream = queue.stream();
+        let mut outputs = Vec::new();
+        while let Some(call) = stream.next().await {
+            outputs.push(call.call());
+            if outputs.len() == 2 {
+                queue.seal();
+            }
+        }
+        outputs
+    });
+    assert_eq!(outputs, [5, 6]);
+
+    // sealing wakes a waiting stream, which then ends
+    let queue = Arc::new(DeferredCallQueue::<u32, u32>::new());
+    let sum = thread::scope(|s| {
+        let consumer = s.spawn(|| futures::executor::block_on(async {
+            let mut sum = 0;
+            let mut stream = queue.stream();
+            while let Some(call) = stream.next().await {
+                sum += call.call();
+            }
+            sum
+        }));
+        for i in 0..100 {
+            queue.push(|i| i, i).unwrap();
+            if i % 10 == 0 {
+                thread::yield_now();
+            }
+        }
+        queue.seal();
+        consumer.join().unwrap()
+    });
+    assert_eq!(sum, 4950);
+}
+
+#[cfg(feature = "futures")]
+#[test]
+fn callback_outputs_test() {
+    use futures::StreamExt;
+
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell = MultiCallbackCellArgs::new();
+    cell.put(|i: u32| i * 2);
+    assert_eq!(cell.call(0), Ok(0));
+    let mut outputs = cell.outputs();
+    let (counting, waker) = CountingWaker::new();
+    let mut cx = Context::from_waker(&waker);
+    assert!(outputs.poll_next_unpin(&mut cx).is_pending());
+    assert_eq!(cell.call(1), Ok(2));
+    assert_eq!(counting.count(), 1);
+    assert_eq!(cell.call(2), Ok(4));
+    assert_eq!(outputs.poll_next_unpin(&mut cx), Poll::Ready(Some(2)));
+    assert_eq!(outputs.poll_next_unpin(&mut cx), Poll::Ready(Some(4)));
+    assert!(outputs.poll_next_unpin(&mut cx).is_pending());
+
+    // full buffers drop the oldest output
+    let mut small = cell.outputs_with_capacity(2);
+    for i in 0..5 {
+        cell.call(i).unwrap();
+    }
+    assert_eq!(futures::executor::block_on((&mut small).take(2).collect::<Vec<_>>()), [6, 8]);
+    assert_eq!(small.dropped(), 3);
+    drop(small);
+    assert_eq!(futures::executor::block_on((&mut outputs).take(5).collect::<Vec<_>>()), [0, 2, 4, 6, 8]);
+
+    // across threads, with a droppy output
+    let cell = MultiCallbackCellArgs::new();
+    cell.put(|i: u32| Arc::new(i));
+    let outputs = cell.outputs_with_capacity(100);
+    thread::scope(|s| {
+        s.spawn(|| {
+            for i in 0..10 {
+                cell.call(i).unwrap();
+            }
+        });
+        let received = futures::executor::block_on(outputs.take(10).map(|i| *i).collect::<Vec<_>>());
+        assert_eq!(received, (0..10).collect::<Vec<_>>());
+    });
+
+    // outputs still buffered are dropped with the stream
+    let outputs = cell.outputs();
+    cell.call(0).unwrap();
+    drop(outputs);
+    cell.call(1).unwrap();
+}
+
+#[cfg(feature = "futures")]
+#[test]
+fn callback_outputs_panicking_clone_test() {
+    use futures::StreamExt;
+    use std::cell::Cell;
+
+    std::thread_local! {
+        static CLONES_LEFT: Cell<u32> = const { Cell::new(u32::MAX) };
+    }
+
+    // an output whose clone panics once `CLONES_LEFT` runs out.
+    #[derive(Debug, PartialEq)]
+    struct Picky(u32);
+
+    impl Clone for Picky {
+        fn clone(&self) -> Self {
+            let left = CLONES_LEFT.get();
+            assert!(left > 0, "no clones left");
+            CLONES_LEFT.set(left - 1);
+            Picky(self.0)
+        }
+    }
+
+    let _leak_check = test_util::LeakCheck::new();
+
+    let cell = MultiCallbackCellArgs::new();
+    cell.put(Picky);
+    let mut first = cell.outputs();
+    let mut second = cell.outputs();
+    let (counting, waker) = CountingWaker::new();
+    let mut cx = Context::from_waker(&waker);
+    assert!(first.poll_next_unpin(&mut cx).is_pending());
+    assert!(second.poll_next_unpin(&mut cx).is_pending());
+
+    // the output reaches one stream, which is still woken, then cloning it
+    // for the other panics
+    CLONES_LEFT.set(1);
+    assert!(std::panic::catch_unwind(|| cell.call(1)).is_err());
+    assert_eq!(counting.count(), 1);
+    CLONES_LEFT.set(u32::MAX);
+
+    // the lock was released, and the cell still works
+    assert!(cell.is_set());
+    assert_eq!(cell.call(2), Ok(Picky(2)));
+    let received = [first.poll_next_unpin(&mut cx), second.poll_next_unpin(&mut cx)];
+    assert!(received.contains(&Poll::Ready(Some(Picky(1)))));
+    assert!(received.contains(&Poll::Ready(Some(Picky(2)))));
+}
+
+#[test]
+fn listener_registry_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let registry = Arc::new(ListenerRegistry::new());
+    let sum = Arc::new(AtomicU32::new(0));
+    let add = |n: u32| {
+        let sum = Arc::clone(&sum);
+        move |i: &u32| {
+            sum.fetch_add(i * n, Ordering::SeqCst);
+        }
+    };
+    registry.dispatch(&1);
+    let one = registry.subscribe(add(1));
+    let ten = registry.subscribe(add(10));
+    assert_eq!(registry.len(), 2);
+    registry.dispatch(&2);
+    assert_eq!(sum.load(Ordering::SeqCst), 22);
+    assert!(registry.unsubscribe(one));
+    assert!(!registry.unsubscribe(one));
+    registry.dispatch(&1);
+    assert_eq!(sum.load(Ordering::SeqCst), 32);
+    assert!(registry.unsubscribe(ten));
+
+    // changes during a dispatch apply to the next dispatch, and an
+    // unsubscribed listener is dropped once the dispatch holding it finishes
+    struct DropGuardThing(Arc<AtomicU32>);
+    impl Drop for DropGuardThing {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let drops = Arc::new(AtomicU32::new(0));
+    let dgt = DropGuardThing(Arc::clone(&drops));
+    let guarded = registry.subscribe(move |_: &u32| {
+        let _ = &dgt;
+    });
+    let hundred = registry.subscribe({
+        let registry = Arc::downgrade(&registry);
+        let add_100 = add(100);
+        let add_1000 = add(1000);
+        let drops = Arc::clone(&drops);
+        move |i: &u32| {
+            add_100(i);
+            let registry = registry.upgrade().unwrap();
+            if registry.unsubscribe(guarded) {
+                assert_eq!(drops.load(Ordering::SeqCst), 0);
+                registry.subscribe(add_1000.clone());
+            }
+        }
+    });
+    sum.store(0, Ordering::SeqCst);
+    registry.dispatch(&1);
+    assert_eq!(sum.load(Ordering::SeqCst), 100);
+    assert_eq!(drops.load(Ordering::SeqCst), 1);
+    registry.dispatch(&1);
+    assert_eq!(sum.load(Ordering::SeqCst), 1200);
+    assert!(registry.unsubscribe(hundred));
+    assert_eq!(registry.len(), 1);
+}
+
+#[test]
+fn reentrant_listener_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let registry = Arc::new(ListenerRegistry::new());
+    let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let ids = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let logger = |name: &'static str| {
+        let log = Arc::clone(&log);
+        move |i: &u32| log.lock().unwrap().push((name, *i))
+    };
+    // a unsubscribes c, later in the same dispatch, and b, earlier, on the
+    // first event. d unsubscribes itself. e subscribes f on each event.
+    let listener = |name: &'static str, unsubscribe: &'static [usize]| {
+        let registry = Arc::downgrade(&registry);
+        let ids = Arc::clone(&ids);
+        let log = logger(name);
+        move |i: &u32| {
+            log(i);
+            let registry = registry.upgrade().unwrap();
+            for &j in unsubscribe {
+                let id = ids.lock().unwrap()[j];
+                registry.unsubscribe(id);
+            }
+        }
+    };
+    let b = registry.subscribe(listener("b", &[]));
+    let a = registry.subscribe(listener("a", &[0, 2]));
+    let c = registry.subscribe(listener("c", &[]));
+    let d = registry.subscribe(listener("d", &[3]));
+    let e = registry.subscribe({
+        let registry = Arc::downgrade(&registry);
+        let log = logger("e");
+        let f = logger("f");
+        move |i: &u32| {
+            log(i);
+            registry.upgrade().unwrap().subscribe(f.clone());
+        }
+    });
+    ids.lock().unwrap().extend([b, a, c, d, e]);
+
+    let report = registry.dispatch(&1);
+    assert_eq!(report.delivered, 4);
+    assert_eq!(*log.lock().unwrap(), [("b", 1), ("a", 1), ("d", 1), ("e", 1)]);
+    assert_eq!(registry.len(), 3);
+    log.lock().unwrap().clear();
+    let report = registry.dispatch(&2);
+    assert_eq!(report.delivered, 3);
+    assert_eq!(*log.lock().unwrap(), [("a", 2), ("e", 2), ("f", 2)]);
+    assert_eq!(registry.len(), 4);
+
+    // a listener unsubscribed by another thread, during a dispatch, isn't
+    // called once the dispatch reaches it
+    let registry = Arc::new(ListenerRegistry::new());
+    let (started, proceed) = (Arc::new(std::sync::Barrier::new(2)), Arc::new(std::sync::Barrier::new(2)));
+    registry.subscribe({
+        let (started, proceed) = (Arc::clone(&started), Arc::clone(&proceed));
+        move |_: &u32| {
+            started.wait();
+            proceed.wait();
+        }
+    });
+    let late = registry.subscribe(logger("late"));
+    log.lock().unwrap().clear();
+    thread::scope(|s| {
+        s.spawn(|| {
+            started.wait();
+            assert!(registry.unsubscribe(late));
+            proceed.wait();
+        });
+        assert_eq!(registry.dispatch(&3).delivered, 1);
+    });
+    assert!(log.lock().unwrap().is_empty());
+}
+
+#[test]
+fn weak_listener_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    struct Counter(AtomicU32);
+
+    let registry = ListenerRegistry::new();
+    let counters = (0..3).map(|_| Arc::new(Counter(AtomicU32::new(0)))).collect::<Vec<_>>();
+    let strong = Arc::new(AtomicU32::new(0));
+    for counter in &counters {
+        registry.subscribe_weak(Arc::downgrade(counter), |counter: &Arc<Counter>, i: &u32| {
+            counter.0.fetch_add(*i, Ordering::SeqCst);
+        });
+    }
+    registry.subscribe({
+        let strong = Arc::clone(&strong);
+        move |i: &u32| { strong.fetch_add(*i, Ordering::SeqCst); }
+    });
+    registry.dispatch(&1);
+    assert_eq!(registry.len(), 4);
+
+    // dead listeners are skipped, then removed, without disturbing the others
+    let mut counters = counters.into_iter();
+    let (first, second, third) = (counters.next().unwrap(), counters.next().unwrap(), counters.next().unwrap());
+    drop(second);
+    registry.dispatch(&2);
+    assert_eq!(registry.len(), 3);
+    assert_eq!(first.0.load(Ordering::SeqCst), 3);
+    assert_eq!(third.0.load(Ordering::SeqCst), 3);
+    assert_eq!(strong.load(Ordering::SeqCst), 3);
+
+    // or by prune, without dispatching
+    drop(first);
+    assert_eq!(registry.len(), 3);
+    assert_eq!(registry.prune(), 1);
+    assert_eq!(registry.prune(), 0);
+    assert_eq!(registry.len(), 2);
+
+    // a target dropped by a listener during a dispatch
+    let registry = Arc::new(registry);
+    let third = Arc::new(std::sync::Mutex::new(Some(third)));
+    registry.subscribe({
+        let third = Arc::clone(&third);
+        move |_: &u32| drop(third.lock().unwrap().take())
+    });
+    registry.dispatch(&1);
+    assert_eq!(registry.len(), 3);
+    registry.dispatch(&1);
+    assert_eq!(registry.len(), 2);
+    assert_eq!(strong.load(Ordering::SeqCst), 5);
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn dispatch_par_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let registry = ListenerRegistry::new();
+    let sum = Arc::new(AtomicU32::new(0));
+    for n in 0..100 {
+        let sum = Arc::clone(&sum);
+        registry.subscribe(move |i: &u32| {
+            if n % 10 == 3 {
+                panic!("listener {}", n);
+            }
+            sum.fetch_add(*i, Ordering::SeqCst);
+        });
+    }
+    let report = registry.dispatch_par(&2);
+    assert_eq!(report.delivered, 90);
+    assert_eq!(report.panicked.len(), 10);
+    assert_eq!(sum.load(Ordering::SeqCst), 180);
+    assert_eq!(registry.len(), 100);
+}
+
+#[test]
+fn dispatch_panic_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    struct Target(AtomicU32);
+
+    let registry = ListenerRegistry::new();
+    let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let targets = (0..2).map(|_| Arc::new(Target(AtomicU32::new(0)))).collect::<Vec<_>>();
+    for n in 0..6 {
+        let log = Arc::clone(&log);
+        registry.subscribe(move |i: &u32| {
+            if n % 3 == 1 {
+                panic!("listener {}", n);
+            }
+            log.lock().unwrap().push((n, *i));
+        });
+        // weak listeners, the first panicking, the second dying
+        if n < 2 {
+            registry.subscribe_weak(Arc::downgrade(&targets[n as usize]), move |target: &Arc<Target>, _: &u32| {
+                target.0.fetch_add(1, Ordering::SeqCst);
+                if n == 0 {
+                    panic!("listener weak");
+                }
+            });
+        }
+    }
+    let mut targets = targets.into_iter();
+    let panicking = targets.next().unwrap();
+    drop(targets);
+
+    // every healthy listener hears each event, in order, however many panic
+    for i in 0..2 {
+        let report = registry.dispatch(&i);
+        assert_eq!(report.delivered, 4);
+        assert_eq!(report.panicked.len(), 3);
+        assert_eq!(*report.panicked[0].downcast_ref::<&str>().unwrap(), "listener weak");
+        assert_eq!(*report.panicked[1].downcast_ref::<std::string::String>().unwrap(), "listener 1");
+        assert_eq!(*report.panicked[2].downcast_ref::<std::string::String>().unwrap(), "listener 4");
+        assert_eq!(*log.lock().unwrap(), [(0, i), (2, i), (3, i), (5, i)]);
+        log.lock().unwrap().clear();
+        // the dead weak listener is removed, the panicking one kept
+        assert_eq!(registry.len(), 7);
+    }
+    assert_eq!(panicking.0.load(Ordering::SeqCst), 2);
+    drop(panicking);
+    assert_eq!(registry.prune(), 1);
+
+    // or re-thrown, once every listener has been called
+    let report = registry.dispatch(&2);
+    assert_eq!(log.lock().unwrap().len(), 4);
+    let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| report.resume_unwind())).unwrap_err();
+    assert_eq!(*payload.downcast_ref::<std::string::String>().unwrap(), "listener 1");
+}
+
+#[test]
+fn define_callback_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    define_callback! {
+        /// Called with each frame number.
+        OnFrame: Fn<(u32,), u32>;
+        OnQuit: FnOnce<(), ()>;
+        OnPair<T>: FnOnce<(T, T,), (T, T)>
+    }
+
+    static ON_FRAME: OnFrame = OnFrame::new();
+    assert_eq!(ON_FRAME.call((1,)), Err((1,)));
+    ON_FRAME.put(|i| i + 1);
+    assert_eq!(ON_FRAME.call((1,)), Ok(2));
+    assert_eq!(ON_FRAME.call((2,)), Ok(3));
+    assert!(ON_FRAME.clear());
+
+    let quit = Arc::new(AtomicBool::new(false));
+    let on_quit = OnQuit::default();
+    on_quit.put({
+        let quit = Arc::clone(&quit);
+        move || quit.store(true, Ordering::SeqCst)
+    });
+    assert_eq!(std::format!("{:?}", on_quit), "OnQuit(CallbackCellArgs(armed: \"<unnamed>\"))");
+    assert_eq!(on_quit.take_call(()), Ok(()));
+    assert!(quit.load(Ordering::SeqCst));
+    assert_eq!(on_quit.take_call(()), Err(()));
+
+    let on_pair = OnPair::new();
+    on_pair.put(|a: Box<u32>, b| (b, a));
+    assert!(on_pair.as_inner().is_set());
+    assert_eq!(on_pair.take_call((Box::new(1), Box::new(2))), Ok((Box::new(2), Box::new(1))));
+    on_pair.put(|a, b| (a, b));
+}
+
+#[test]
+fn as_closure_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    fn call_twice(f: impl Fn(u32) -> Option<u32>) -> [Option<u32>; 2] {
+        [f(1), f(2)]
+    }
+
+    let cell = CallbackCellArgs::new();
+    cell.put(|i: u32| i + 1);
+    assert_eq!(call_twice(cell.as_closure()), [Some(2), None]);
+
+    let multi = MultiCallbackCellArgs::new();
+    assert_eq!(call_twice(multi.as_closure()), [None, None]);
+    multi.put(|i: u32| i + 1);
+    assert_eq!(call_twice(multi.as_closure()), [Some(2), Some(3)]);
+}
+
+#[cfg(feature = "unstable-fn-traits")]
+#[test]
+fn as_fn_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    struct Handlers<F: Fn(u32) -> Option<u32>> {
+        handlers: Vec<F>,
+    }
+
+    let cell = CallbackCellArgs::new();
+    cell.put(|i: u32| i + 1);
+    let f: CellFn<u32, u32> = cell.as_fn();
+    let handlers = Handlers { handlers: std::vec![f, f] };
+    assert_eq!(handlers.handlers.iter().map(|f| f(1)).collect::<Vec<_>>(), [Some(2), None]);
+
+    let multi = MultiCallbackCellArgs::new();
+    multi.put(|i: u32| i * 2);
+    let handlers = Handlers { handlers: std::vec![multi.as_fn(); 2] };
+    assert_eq!(handlers.handlers.iter().map(|f| f(2)).collect::<Vec<_>>(), [Some(4), Some(4)]);
+}
+
+#[test]
+fn recording_cell_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    // code under test
+    fn register(slot: &dyn CallbackSlot<Input = u32, Output = u32>) {
+        slot.put_boxed(Box::new(|i| i * 2));
+    }
+
+    let cell = test_util::RecordingCell::new().strict();
+    register(&cell);
+    cell.assert_put_once();
+    assert!(cell.puts()[0].type_name.contains("FnOnce"));
+    assert!(!cell.puts()[0].replaced);
+    assert_eq!(cell.take_call(21), Ok(42));
+    assert_eq!(cell.take_call(21), Err(21));
+
+    let cell = test_util::RecordingCell::new();
+    cell.put(|()| ());
+    cell.put(|()| ());
+    let puts = cell.puts();
+    assert!(puts[0].seq < puts[1].seq);
+    assert!(puts[1].replaced);
+    assert!(puts[1].type_name.contains("recording_cell_test"));
+    drop(cell);
+
+    let cell = test_util::RecordingCell::new().panic_on_replace();
+    cell.put(|()| ());
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| cell.put(|()| ())));
+    assert!(result.is_err());
+    assert_eq!(cell.put_count(), 1);
+    assert!(cell.clear());
+
+    let cell = test_util::RecordingCell::new().panic_on_drop_pending();
+    cell.put(|()| ());
+    assert!(std::panic::catch_unwind(move || drop(cell)).is_err());
+}
+
+#[test]
+fn leak_check_test() {
+    use std::panic;
+
+    let _leak_check = test_util::LeakCheck::new();
+
+    // put then drop
+    let cell = CallbackCellArgs::<u32, u32>::new();
+    cell.put(|i| i + 1);
+    assert_eq!(test_util::live_allocations(), 1);
+    drop(cell);
+    test_util::assert_no_live_allocations();
+
+    // put overwrite
+    let cell = CallbackCellArgs::<u32, u32>::new();
+    cell.put(|i| i + 1);
+    cell.put(|i| i + 2);
+    assert_eq!(test_util::live_allocations(), 1);
+
+    // take_call
+    assert_eq!(cell.take_call(1), Ok(3));
+    test_util::assert_no_live_allocations();
+
+    // panic inside callback
+    cell.put(|_| panic!("callback panicked"));
+    assert!(panic::catch_unwind(|| cell.take_call(1)).is_err());
+    test_util::assert_no_live_allocations();
+
+    // async callback and its future
+    let cell = AsyncCallbackCell::new();
+    cell.put(|| async {});
+    let fut = cell.take_call().unwrap();
+    assert_eq!(test_util::live_allocations(), 1);
+    drop(fut);
+    test_util::assert_no_live_allocations();
+}
+
+#[cfg(feature = "unstable-async-fn")]
+#[test]
+fn put_async_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    struct DropGuardThing(Arc<AtomicU32>);
+    impl Drop for DropGuardThing {
+        fn drop(&mut self) {
+            self.0.fetch_add(100, Ordering::Relaxed);
+        }
+    }
+
+    let counter = Arc::new(AtomicU32::new(0));
+    let rt = tokio::runtime::Runtime::new().unwrap();
+
+    // captured state is held across the await point, and dropped once
+    let cell: AsyncCallbackCellArgs<u32, u32> = AsyncCallbackCellArgs::new();
+    let dgt = DropGuardThing(Arc::clone(&counter));
+    cell.put_async(async move |i| {
+        tokio::task::yield_now().await;
+        dgt.0.fetch_add(1, Ordering::Relaxed);
+        i * 2
+    });
+    let fut = cell.take_call(7).ok().unwrap();
+    assert_eq!(counter.load(Ordering::Relaxed), 0);
+    assert_eq!(rt.block_on(fut), 14);
+    assert_eq!(counter.load(Ordering::Relaxed), 101);
+
+    // dropped without running
+    let dgt = DropGuardThing(Arc::clone(&counter));
+    cell.put_async(async move |i| {
+        dgt.0.fetch_add(1, Ordering::Relaxed);
+        i
+    });
+    assert!(cell.clear());
+    assert_eq!(counter.load(Ordering::Relaxed), 201);
+
+    let cell = AsyncCallbackCell::new();
+    let dgt = DropGuardThing(Arc::clone(&counter));
+    cell.put_async(async move || {
+        tokio::task::yield_now().await;
+        dgt.0.fetch_add(1, Ordering::Relaxed);
+    });
+    rt.block_on(cell.take_call().unwrap());
+    assert_eq!(counter.load(Ordering::Relaxed), 302);
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn tracing_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    use std::{
+        sync::Mutex,
+        string::{String, ToString},
+        fmt::Debug,
+    };
+    use tracing::{
+        field::{Field, Visit},
+        span,
+        Event,
+        Metadata,
+        Subscriber,
+    };
+
+    type EventFields = Vec<(&'static str, String)>;
+
+    // records each event as its fields, in order
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<EventFields>>>);
+
+    struct Fields<'a>(&'a mut EventFields);
+
+    impl Visit for Fields<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0.push((field.name(), std::format!("{:?}", value)));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.push((field.name(), value.to_string()));
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.target() == "callback_cell"
+        }
+
+        fn new_span(&self, _: &span::Attributes) -> span::Id {
+            span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &span::Id, _: &span::Record) {}
+
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+        fn event(&self, event: &Event) {
+            let mut fields = Vec::new();
+            event.record(&mut Fields(&mut fields));
+            self.0.lock().unwrap().push(fields);
+        }
+
+        fn enter(&self, _: &span::Id) {}
+
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    let recorder = Recorder::default();
+    tracing::subscriber::with_default(recorder.clone(), || {
+        {
+            let cell = CallbackCell::new();
+            cell.put(|| ());
+            cell.put(|| ());
+            assert!(cell.take_call());
+            assert!(!cell.take_call());
+            cell.put(|| ());
+        }
+        let cell = NamedCallbackCellArgs::new_named("frame_done");
+        cell.put(|()| ());
+    });
+
+    let events = recorder.0.lock().unwrap();
+    let field = |i: usize, name: &str| -> &str {
+        &events[i].iter().find(|(field, _)| *field == name).unwrap().1
+    };
+    assert_eq!(events.len(), 8);
+    assert_eq!(field(0, "message"), "put");
+    assert_eq!(field(0, "kind"), "CallbackCell");
+    assert_eq!(field(0, "name"), "<unnamed>");
+    assert!(field(0, "callback").contains("closure"));
+    assert_eq!(field(0, "replaced"), "false");
+    assert_eq!(field(1, "message"), "put");
+    assert_eq!(field(1, "replaced"), "true");
+    assert_eq!(field(2, "message"), "take_call");
+    assert_eq!(field(2, "ran"), "true");
+    assert!(events[2].iter().any(|(field, _)| *field == "duration"));
+    assert_eq!(field(3, "message"), "take_call");
+    assert_eq!(field(3, "ran"), "false");
+    assert_eq!(field(4, "message"), "put");
+    assert_eq!(field(5, "message"), "drop with callback pending");
+    assert_eq!(field(5, "cell"), field(0, "cell"));
+    assert_eq!(field(6, "kind"), "CallbackCellArgs");
+    assert_eq!(field(6, "name"), "frame_done");
+    assert_eq!(field(7, "message"), "drop with callback pending");
+    assert_eq!(field(7, "name"), "frame_done");
+}
+
+#[cfg(feature = "critical-section")]
+#[test]
+fn critical_section_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let counter = Arc::new(AtomicU32::new(0));
+    let cell = CriticalSectionCallbackCell::new();
+    assert!(!cell.take_call());
+    cell.put({
+        let counter = Arc::clone(&counter);
+        move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    assert_eq!(counter.load(Ordering::Relaxed), 0);
+    assert!(cell.take_call());
+    assert_eq!(counter.load(Ordering::Relaxed), 1);
+    assert!(!cell.take_call());
+    cell.put({
+        struct DropGuardThing(Arc<AtomicU32>);
+        impl Drop for DropGuardThing {
+            fn drop(&mut self) {
+                self.0.fetch_add(100, Ordering::Relaxed);
+            }
+        }
+        let dgt = DropGuardThing(Arc::clone(&counter));
+        move || {
+            dgt.0.fetch_add(2, Ordering::Relaxed);
+        }
+    });
+    cell.put({
+        let counter = Arc::clone(&counter);
+        move || {
+            counter.fetch_add(3, Ordering::Relaxed);
+        }
+    });
+    assert_eq!(counter.load(Ordering::Relaxed), 101);
+    assert!(cell.take_call());
+    assert_eq!(counter.load(Ordering::Relaxed), 104);
+    cell.put({
+        let counter = Arc::clone(&counter);
+        move || {
+            counter.fetch_add(1000, Ordering::Relaxed);
+        }
+    });
+    drop(cell);
+    assert_eq!(counter.load(Ordering::Relaxed), 104);
+    assert_eq!(Arc::strong_count(&counter), 1);
+}
+
+#[cfg(feature = "capi")]
+#[test]
+fn capi_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    use crate::capi::*;
+
+    unsafe extern "C" fn run(data: *mut c_void) {
+        (*(data as *const AtomicU32)).fetch_add(1, Ordering::Relaxed);
+    }
+
+    unsafe extern "C" fn drop_data(data: *mut c_void) {
+        (*(data as *const AtomicU32)).fetch_add(100, Ordering::Relaxed);
+    }
+
+    let counter = AtomicU32::new(0);
+    let data = &counter as *const AtomicU32 as *mut c_void;
+    unsafe {
+        let cell = callback_cell_new();
+        assert_eq!(callback_cell_take_call(cell), 0);
+        callback_cell_put(cell, run, data, Some(drop_data));
+        assert_eq!(callback_cell_take_call(cell), 1);
+        assert_eq!(counter.load(Ordering::Relaxed), 1);
+        assert_eq!(callback_cell_take_call(cell), 0);
+        callback_cell_put(cell, run, data, Some(drop_data));
+        callback_cell_put(cell, run, data, None);
+        assert_eq!(counter.load(Ordering::Relaxed), 101);
+        callback_cell_put(cell, run, data, Some(drop_data));
+        callback_cell_free(cell);
+        assert_eq!(counter.load(Ordering::Relaxed), 201);
+        callback_cell_free(std::ptr::null_mut());
+    }
+}
+
+#[cfg(feature = "capi")]
+#[test]
+fn capi_header_test() {
+    let _leak_check = test_util::LeakCheck::new();
+
+    let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+    let out = std::env::temp_dir().join("callback_cell_capi_smoke.o");
+    let status = std::process::Command::new("cc")
+        .args(["-std=c99", "-Wall", "-Wextra", "-Werror", "-c"])
+        .arg("-I").arg(dir.join("include"))
+        .arg(dir.join("tests").join("capi_smoke.c"))
+        .arg("-o").arg(&out)
+        .status()
+        .expect("failed to run cc");
+    assert!(status.success());
+}
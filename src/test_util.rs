@@ -0,0 +1,257 @@
+//! Leak checking for callback allocations, behind the `test-util` feature.
+//!
+//! While this is enabled, each heap allocation the cells make to hold a
+//! callback is counted when it's made and when it's freed, so tests can
+//! check that every callback was freed exactly once.
+//!
+//! The count is global, so tests which check it must not run concurrently
+//! with other tests which make callbacks. [`LeakCheck`] serializes them.
+//!
+//! [`RecordingCell`] is a test double for code which takes a
+//! [`CallbackSlot`].
+
+use crate::CallbackSlot;
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    any::type_name,
+    fmt::{self, Formatter, Debug},
+};
+use alloc::{
+    boxed::Box,
+    vec::Vec,
+};
+use std::{
+    sync::{Mutex, MutexGuard},
+    thread,
+};
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+static SERIAL: Mutex<()> = Mutex::new(());
+
+pub(crate) fn on_alloc() {
+    LIVE.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn on_dealloc() {
+    LIVE.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// The number of callback allocations made and not yet freed.
+pub fn live_allocations() -> usize {
+    LIVE.load(Ordering::Relaxed)
+}
+
+/// Panic if any callback allocations have been made and not yet freed.
+pub fn assert_no_live_allocations() {
+    let live = live_allocations();
+    assert!(live == 0, "{} callback allocations still live", live);
+}
+
+/// Guard for a leak-checked test.
+///
+/// Creating one waits for any other `LeakCheck` to be dropped, then asserts
+/// that no callback allocations are live. Dropping it asserts the same again,
+/// unless the thread is panicking.
+pub struct LeakCheck(#[allow(dead_code)] MutexGuard<'static, ()>);
+
+impl LeakCheck {
+    /// Begin a leak-checked test.
+    pub fn new() -> Self {
+        let guard = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        assert_no_live_allocations();
+        LeakCheck(guard)
+    }
+}
+
+impl Default for LeakCheck {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for LeakCheck {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            assert_no_live_allocations();
+        }
+    }
+}
+
+static NEXT_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+/// A put recorded by a [`RecordingCell`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PutRecord {
+    /// Position among all puts to any `RecordingCell` in the process, so
+    /// puts to different cells can be ordered.
+    pub seq: usize,
+    /// The callback's type name. Through [`CallbackSlot::put_boxed`], this is
+    /// the boxed trait object's type.
+    pub type_name: &'static str,
+    /// Whether the put replaced a pending callback.
+    pub replaced: bool,
+}
+
+/// A cell which records every put, for testing code which registers
+/// callbacks.
+///
+/// It implements [`CallbackSlot`], and the test triggers the callback with
+/// [`take_call`][Self::take_call]. It is synchronized with a mutex, which is
+/// never held while a callback runs or is dropped.
+///
+/// By default it only records. [`strict`][Self::strict] makes it panic on
+/// likely misuse instead.
+pub struct RecordingCell<I, O> {
+    state: Mutex<RecordingState<I, O>>,
+    panic_on_replace: bool,
+    panic_on_drop_pending: bool,
+}
+
+struct RecordingState<I, O> {
+    callback: Option<Box<dyn FnOnce(I) -> O + Send + 'static>>,
+    puts: Vec<PutRecord>,
+}
+
+impl<I, O> RecordingCell<I, O> {
+    /// Construct with no callback, which only records.
+    pub fn new() -> Self {
+        RecordingCell {
+            state: Mutex::new(RecordingState {
+                callback: None,
+                puts: Vec::new(),
+            }),
+            panic_on_replace: false,
+            panic_on_drop_pending: false,
+        }
+    }
+
+    /// Panic on a put while a callback is pending.
+    pub fn panic_on_replace(mut self) -> Self {
+        self.panic_on_replace = true;
+        self
+    }
+
+    /// Panic if dropped while a callback is pending.
+    pub fn panic_on_drop_pending(mut self) -> Self {
+        self.panic_on_drop_pending = true;
+        self
+    }
+
+    /// Both [`panic_on_replace`][Self::panic_on_replace] and
+    /// [`panic_on_drop_pending`][Self::panic_on_drop_pending].
+    pub fn strict(self) -> Self {
+        self.panic_on_replace().panic_on_drop_pending()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, RecordingState<I, O>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn put_named(&self, f: Box<dyn FnOnce(I) -> O + Send + 'static>, type_name: &'static str) {
+        let old = {
+            let mut state = self.lock();
+            let replaced = state.callback.is_some();
+            if replaced && self.panic_on_replace {
+                drop(state);
+                panic!("RecordingCell: put of {} replaced a pending callback", type_name);
+            }
+            state.puts.push(PutRecord {
+                seq: NEXT_SEQ.fetch_add(1, Ordering::Relaxed),
+                type_name,
+                replaced,
+            });
+            state.callback.replace(f)
+        };
+        drop(old);
+    }
+
+    /// Set the callback, recording the put.
+    pub fn put<F: FnOnce(I) -> O + Send + 'static>(&self, f: F) {
+        self.put_named(Box::new(f), type_name::<F>());
+    }
+
+    /// Take the callback then run it with the given input.
+    ///
+    /// Returns the output if a callback was present. If a callback was not
+    /// present, returns the original input.
+    pub fn take_call(&self, input: I) -> Result<O, I> {
+        let callback = self.lock().callback.take();
+        match callback {
+            Some(f) => Ok(f(input)),
+            None => Err(input),
+        }
+    }
+
+    /// Take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        let callback = self.lock().callback.take();
+        callback.is_some()
+    }
+
+    /// Whether a callback is currently present.
+    pub fn is_set(&self) -> bool {
+        self.lock().callback.is_some()
+    }
+
+    /// Every put so far, oldest first.
+    pub fn puts(&self) -> Vec<PutRecord> {
+        self.lock().puts.clone()
+    }
+
+    /// The number of puts so far.
+    pub fn put_count(&self) -> usize {
+        self.lock().puts.len()
+    }
+
+    /// Panic unless exactly one callback has been put.
+    #[track_caller]
+    pub fn assert_put_once(&self) {
+        let count = self.put_count();
+        assert!(count == 1, "expected exactly one put to RecordingCell, got {}", count);
+    }
+}
+
+impl<I, O> CallbackSlot for RecordingCell<I, O> {
+    type Input = I;
+    type Output = O;
+
+    fn put_boxed(&self, f: Box<dyn FnOnce(I) -> O + Send + 'static>) {
+        self.put_named(f, type_name::<Box<dyn FnOnce(I) -> O + Send + 'static>>());
+    }
+
+    fn take_call(&self, input: I) -> Result<O, I> {
+        RecordingCell::take_call(self, input)
+    }
+
+    fn is_set(&self) -> bool {
+        RecordingCell::is_set(self)
+    }
+}
+
+impl<I, O> Drop for RecordingCell<I, O> {
+    fn drop(&mut self) {
+        let pending = self.lock().callback.is_some();
+        if pending && self.panic_on_drop_pending && !thread::panicking() {
+            panic!("RecordingCell dropped with a callback pending");
+        }
+    }
+}
+
+impl<I, O> Default for RecordingCell<I, O> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I, O> Debug for RecordingCell<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let state = self.lock();
+        f.debug_struct("RecordingCell")
+            .field("is_set", &state.callback.is_some())
+            .field("puts", &state.puts)
+            .finish()
+    }
+}
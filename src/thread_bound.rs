@@ -0,0 +1,212 @@
+
+use crate::{
+    sync::{AtomicPtr, LoadMut},
+    raw,
+    sealed::{is_tagged, untagged},
+};
+use core::{
+    sync::atomic::Ordering,
+    ptr,
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+use std::thread::{self, ThreadId};
+
+// internals
+// ---------
+//
+// the inner atomic pointer is a nullable pointer to an erased callback, as
+// described in the `raw` module, with `I = ()` and `O = ()`, tagged in its low
+// bit if the callback isn't `Send`. the tag is the same bit the `sealed`
+// module uses.
+//
+// untagged callbacks may be dropped on any thread. tagged callbacks are only
+// ever put, taken, run, and dropped on the owner thread: `put_local` checks the
+// thread before putting, and a `put` from another thread compare-exchanges
+// rather than swapping, so that it never displaces a tagged callback. taking
+// and clearing are checked for the owner thread regardless of the tag.
+
+const LOCAL: usize = 1;
+
+/// A [`CallbackCell`][crate::CallbackCell] bound to one thread, which can
+/// hold callbacks that aren't `Send`.
+///
+/// Between `CallbackCell` and
+/// [`LocalCallbackCell`][crate::LocalCallbackCell]: the cell is `Send` and
+/// `Sync`, and any thread can put a `Send` callback with [`put`][Self::put],
+/// but callbacks are only taken on the owner thread, the thread which
+/// constructed the cell, so the owner thread can also put callbacks which
+/// aren't `Send`, with [`put_local`][Self::put_local]. Methods which would
+/// run or drop a callback on another thread panic instead.
+///
+/// If the cell is dropped on another thread while holding a callback which
+/// isn't `Send`, the drop panics, unless the thread is already panicking, in
+/// which case the callback is leaked.
+pub struct ThreadBoundCallbackCell {
+    ptr: AtomicPtr<u8>,
+    owner: ThreadId,
+}
+
+// safety: callbacks which aren't Send are only accessed on the owner thread.
+unsafe impl Send for ThreadBoundCallbackCell {}
+unsafe impl Sync for ThreadBoundCallbackCell {}
+
+// the thread checks happen before the cell is changed, so a panicking check or
+// callback leaves the cell consistent.
+impl UnwindSafe for ThreadBoundCallbackCell {}
+impl RefUnwindSafe for ThreadBoundCallbackCell {}
+
+#[track_caller]
+fn wrong_thread(method: &str) -> ! {
+    panic!("ThreadBoundCallbackCell::{} called off its owner thread", method)
+}
+
+impl ThreadBoundCallbackCell {
+    /// Construct with no callback, owned by the current thread.
+    pub fn new() -> Self {
+        ThreadBoundCallbackCell {
+            ptr: AtomicPtr::new(ptr::null_mut()),
+            owner: thread::current().id(),
+        }
+    }
+
+    /// The thread which callbacks are taken on.
+    pub fn owner(&self) -> ThreadId {
+        self.owner
+    }
+
+    /// Whether this is called on the owner thread.
+    pub fn is_owner_thread(&self) -> bool {
+        thread::current().id() == self.owner
+    }
+
+    /// Atomically set the callback, from any thread.
+    ///
+    /// Any callback previously present is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics, leaving the cell unchanged, if called off the owner thread
+    /// while the cell holds a callback put with
+    /// [`put_local`][Self::put_local], since that would drop it there.
+    #[track_caller]
+    pub fn put<F: FnOnce() + Send + 'static>(&self, f: F) {
+        if self.is_owner_thread() {
+            self.put_ptr(raw::alloc_raw(move |()| f()));
+            return;
+        }
+        let mut old_ptr = self.ptr.load(Ordering::Relaxed);
+        if is_tagged(old_ptr) {
+            wrong_thread("put");
+        }
+        let ptr = raw::alloc_raw(move |()| f());
+        loop {
+            if is_tagged(old_ptr) {
+                unsafe { raw::drop_raw::<(), ()>(ptr) };
+                wrong_thread("put");
+            }
+            match self.ptr.compare_exchange_weak(old_ptr, ptr, Ordering::AcqRel, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(new_ptr) => old_ptr = new_ptr,
+            }
+        }
+        unsafe { raw::drop_raw::<(), ()>(old_ptr) };
+    }
+
+    /// Atomically set a callback which needn't be `Send`.
+    ///
+    /// Any callback previously present is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called off the owner thread.
+    #[track_caller]
+    pub fn put_local<F: FnOnce() + 'static>(&self, f: F) {
+        if !self.is_owner_thread() {
+            wrong_thread("put_local");
+        }
+        let ptr = raw::alloc_raw(move |()| f());
+        self.put_ptr(ptr.map_addr(|addr| addr | LOCAL));
+    }
+
+    // swap in the pointer, on the owner thread.
+    fn put_ptr(&self, ptr: *mut u8) {
+        let old_ptr = self.ptr.swap(ptr, Ordering::AcqRel);
+        unsafe { raw::drop_raw::<(), ()>(untagged(old_ptr)) };
+    }
+
+    /// Atomically take the callback then run it.
+    ///
+    /// Returns true if a callback was present.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called off the owner thread.
+    #[track_caller]
+    pub fn take_call(&self) -> bool {
+        if !self.is_owner_thread() {
+            wrong_thread("take_call");
+        }
+        let ptr = untagged(self.ptr.swap(ptr::null_mut(), Ordering::Acquire));
+        if !ptr.is_null() {
+            unsafe { raw::call_raw::<(), ()>(ptr, ()) };
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Atomically take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called off the owner thread.
+    #[track_caller]
+    pub fn clear(&self) -> bool {
+        if !self.is_owner_thread() {
+            wrong_thread("clear");
+        }
+        let ptr = untagged(self.ptr.swap(ptr::null_mut(), Ordering::Acquire));
+        unsafe { raw::drop_raw::<(), ()>(ptr) };
+        !ptr.is_null()
+    }
+
+    /// Whether a callback is currently present.
+    ///
+    /// This is only a snapshot: another thread may put a callback
+    /// immediately afterwards.
+    pub fn is_set(&self) -> bool {
+        !self.ptr.load(Ordering::Acquire).is_null()
+    }
+}
+
+impl Drop for ThreadBoundCallbackCell {
+    fn drop(&mut self) {
+        let ptr = self.ptr.load_mut();
+        if is_tagged(ptr) && !self.is_owner_thread() {
+            if thread::panicking() {
+                return;
+            }
+            panic!("ThreadBoundCallbackCell holding a callback which isn't Send dropped off its owner thread");
+        }
+        unsafe { raw::drop_raw::<(), ()>(untagged(ptr)) };
+    }
+}
+
+impl Default for ThreadBoundCallbackCell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for ThreadBoundCallbackCell {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_set() {
+            f.write_str("ThreadBoundCallbackCell(NOT NULL)")
+        } else {
+            f.write_str("ThreadBoundCallbackCell(NULL)")
+        }
+    }
+}
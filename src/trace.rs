@@ -0,0 +1,176 @@
+
+// internals
+// ---------
+//
+// instrumentation points, called by the cells at each point in a callback's
+// lifecycle. each one emits a `tracing` event, with the `tracing` feature, and
+// calls the global hooks, as described in the `hooks` module, with `std`. runs
+// of callbacks are timed for the watchdog, as described in the `watchdog`
+// module, with the `watchdog` feature. without any of these, they do nothing
+// and compile away.
+//
+// every event has target `callback_cell`, level TRACE, and the fields:
+//
+// - `kind`: the cell's type name, e.g. "CallbackCell"
+// - `cell`: the cell's address
+// - `name`: the cell's name, or "<unnamed>"
+//
+// except for the event for a labeled callback being run, which has only the
+// `label` field. a put also has the `callback` field, the callback's type
+// name, and for a labeled callback, the `label` field.
+
+#[cfg(all(feature = "std", target_has_atomic = "ptr"))]
+use crate::hooks::{self, HookInfo};
+
+#[cfg(feature = "tracing")]
+const UNNAMED: &str = "<unnamed>";
+
+// a callback was put. `replaced` is whether it replaced another callback.
+#[inline(always)]
+#[allow(unused_variables)]
+pub(crate) fn put(
+    kind: &'static str,
+    cell: *const (),
+    name: Option<&'static str>,
+    callback: &'static str,
+    label: Option<&'static str>,
+    replaced: bool,
+) {
+    #[cfg(feature = "tracing")]
+    tracing::trace!(
+        target: "callback_cell",
+        kind,
+        ?cell,
+        name = name.unwrap_or(UNNAMED),
+        callback,
+        label,
+        replaced,
+        "put",
+    );
+    #[cfg(all(feature = "std", target_has_atomic = "ptr"))]
+    hooks::call(|hooks| {
+        let info = HookInfo { kind, cell, name, callback: Some(callback), label, displaced: replaced };
+        if let Some(on_put) = hooks.on_put {
+            on_put(&info);
+        }
+        if replaced {
+            if let Some(on_drop_unrun) = hooks.on_drop_unrun {
+                on_drop_unrun(&HookInfo { callback: None, label: None, ..info });
+            }
+        }
+    });
+}
+
+// a callback was taken and is to be run by `f`. with `std`, records the
+// duration of the callback.
+#[inline(always)]
+#[allow(unused_variables)]
+pub(crate) fn call<R>(
+    kind: &'static str,
+    cell: *const (),
+    name: Option<&'static str>,
+    f: impl FnOnce() -> R,
+) -> R {
+    #[cfg(all(feature = "std", target_has_atomic = "ptr"))]
+    hooks::call(|hooks| {
+        if let Some(on_take) = hooks.on_take {
+            on_take(&HookInfo { kind, cell, name, callback: None, label: None, displaced: false });
+        }
+    });
+    #[cfg(all(feature = "tracing", feature = "std"))]
+    let start = std::time::Instant::now();
+    #[cfg(feature = "watchdog")]
+    let watch = crate::watchdog::start();
+    let r = f();
+    #[cfg(feature = "watchdog")]
+    crate::watchdog::finish(watch, kind, cell, name);
+    #[cfg(all(feature = "tracing", feature = "std"))]
+    tracing::trace!(
+        target: "callback_cell",
+        kind,
+        ?cell,
+        name = name.unwrap_or(UNNAMED),
+        ran = true,
+        duration = ?start.elapsed(),
+        "take_call",
+    );
+    #[cfg(all(feature = "tracing", not(feature = "std")))]
+    tracing::trace!(
+        target: "callback_cell",
+        kind,
+        ?cell,
+        name = name.unwrap_or(UNNAMED),
+        ran = true,
+        "take_call",
+    );
+    r
+}
+
+// a labeled callback is about to run, within the `call` of the take which
+// took it.
+#[cfg(feature = "alloc")]
+#[inline(always)]
+#[allow(unused_variables)]
+pub(crate) fn call_labeled(label: &'static str) {
+    #[cfg(feature = "tracing")]
+    tracing::trace!(target: "callback_cell", label, "call labeled");
+}
+
+// a take found no callback.
+#[inline(always)]
+#[allow(unused_variables)]
+pub(crate) fn take_empty(kind: &'static str, cell: *const (), name: Option<&'static str>) {
+    #[cfg(feature = "tracing")]
+    tracing::trace!(
+        target: "callback_cell",
+        kind,
+        ?cell,
+        name = name.unwrap_or(UNNAMED),
+        ran = false,
+        "take_call",
+    );
+}
+
+// the cell was cleared. `cleared` is whether a callback was present.
+#[inline(always)]
+#[allow(unused_variables)]
+pub(crate) fn clear(kind: &'static str, cell: *const (), name: Option<&'static str>, cleared: bool) {
+    #[cfg(feature = "tracing")]
+    tracing::trace!(
+        target: "callback_cell",
+        kind,
+        ?cell,
+        name = name.unwrap_or(UNNAMED),
+        cleared,
+        "clear",
+    );
+    #[cfg(all(feature = "std", target_has_atomic = "ptr"))]
+    if cleared {
+        drop_unrun_hook(kind, cell, name);
+    }
+}
+
+// the cell was dropped with a callback present.
+#[inline(always)]
+#[allow(unused_variables)]
+pub(crate) fn drop_pending(kind: &'static str, cell: *const (), name: Option<&'static str>) {
+    #[cfg(feature = "tracing")]
+    tracing::trace!(
+        target: "callback_cell",
+        kind,
+        ?cell,
+        name = name.unwrap_or(UNNAMED),
+        "drop with callback pending",
+    );
+    #[cfg(all(feature = "std", target_has_atomic = "ptr"))]
+    drop_unrun_hook(kind, cell, name);
+}
+
+#[cfg(all(feature = "std", target_has_atomic = "ptr"))]
+fn drop_unrun_hook(kind: &'static str, cell: *const (), name: Option<&'static str>) {
+    hooks::call(|hooks| {
+        if let Some(on_drop_unrun) = hooks.on_drop_unrun {
+            on_drop_unrun(&HookInfo { kind, cell, name, callback: None, label: None, displaced: false });
+        }
+    });
+}
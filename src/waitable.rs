@@ -0,0 +1,398 @@
+
+use crate::{
+    CallbackCell,
+    WakerCell,
+    raw,
+    blocking,
+    sync::AtomicUsize,
+    waiters::WaiterList,
+};
+use core::{
+    sync::atomic::Ordering,
+    ptr,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    fmt::{self, Formatter, Debug},
+};
+
+// internals
+// ---------
+//
+// `running` counts threads between starting to take a callback and the
+// callback finishing, including threads which find the cell empty. it's
+// incremented before the swap, so a thread whose swap finds the cell empty,
+// because another thread's swap took the callback, then sees the other
+// thread in `running`. `completed` counts callbacks which have finished
+// running, or panicked, and is incremented before `running` is decremented.
+// when `running` drops to 0, threads waiting for it in
+// `take_call_or_wait` are woken.
+
+/// A [`CallbackCell`] which can be asynchronously waited on.
+///
+/// Consumers can wait for a callback to be set. This has a single consumer
+/// waker slot: at most one task at a time should wait for a callback to be
+/// set on a given cell.
+///
+/// Producers can wait for the cell to be empty. Any number of tasks can do so
+/// at once.
+pub struct WaitableCallbackCell {
+    cell: CallbackCell,
+    set_waker: WakerCell,
+    empty_waiters: WaiterList,
+    running: AtomicUsize,
+    completed: AtomicUsize,
+    done_waiters: WaiterList,
+}
+
+/// Outcome of [`WaitableCallbackCell::take_call_or_wait`].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum DidRun {
+    /// The callback was taken and run on this thread.
+    RanHere,
+    /// The callback was taken by another thread, and has finished running,
+    /// or panicked.
+    RanElsewhere,
+    /// No callback was present, nor running.
+    Empty,
+}
+
+// marks a thread in `running`, and unmarks it when dropped, even if the
+// callback panics.
+struct Running<'a> {
+    cell: &'a WaitableCallbackCell,
+    ran: bool,
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        if self.ran {
+            self.cell.completed.fetch_add(1, Ordering::SeqCst);
+        }
+        if self.cell.running.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.cell.done_waiters.wake_all();
+        }
+    }
+}
+
+impl WaitableCallbackCell {
+    const_fn! {
+        /// Construct with no callback.
+        pub fn new() -> Self {
+            WaitableCallbackCell {
+                cell: CallbackCell::new(),
+                set_waker: WakerCell::new(),
+                empty_waiters: WaiterList::new(),
+                running: AtomicUsize::new(0),
+                completed: AtomicUsize::new(0),
+                done_waiters: WaiterList::new(),
+            }
+        }
+    }
+
+    /// Atomically set the callback, then wake the task waiting for a callback
+    /// to be set, if any.
+    pub fn put<F: FnOnce() + Send + 'static>(&self, f: F) {
+        self.cell.put(f);
+        self.set_waker.wake();
+        blocking::signal();
+    }
+
+    /// Atomically take the callback then run it.
+    ///
+    /// Returns true if a callback was present. If so, tasks waiting for the
+    /// cell to be empty are woken before the callback runs.
+    pub fn take_call(&self) -> bool {
+        self.running.fetch_add(1, Ordering::SeqCst);
+        let mut running = Running { cell: self, ran: false };
+        let ptr = self.cell.take_raw();
+        if !ptr.is_null() {
+            running.ran = true;
+            self.empty_waiters.wake_all();
+            unsafe { CallbackCell::run_raw(ptr) };
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Like [`take_call`][Self::take_call], but if another thread took the
+    /// callback first, block until it has finished running.
+    ///
+    /// So once this returns, the callback has finished running, wherever it
+    /// ran, unless it returns [`DidRun::Empty`]. If the callback panics on
+    /// another thread, this returns [`DidRun::RanElsewhere`] once it has
+    /// unwound. If callbacks are put and taken continuously, this may keep
+    /// waiting, since it waits until no callback is running at all.
+    #[cfg(feature = "std")]
+    pub fn take_call_or_wait(&self) -> DidRun {
+        let completed = self.completed.load(Ordering::SeqCst);
+        if self.take_call() {
+            return DidRun::RanHere;
+        }
+        let mut id = None;
+        let mut park = blocking::Park::new(|waker| self.done_waiters.register(&mut id, waker));
+        blocking::wait_until(&mut park, || (self.running.load(Ordering::SeqCst) == 0).then_some(()));
+        drop(park);
+        self.done_waiters.deregister(id);
+        if self.completed.load(Ordering::SeqCst) != completed {
+            DidRun::RanElsewhere
+        } else {
+            DidRun::Empty
+        }
+    }
+
+    /// Block until a callback is present, then atomically take and run it.
+    ///
+    /// Parks the thread while waiting. If another thread takes the callback
+    /// first, this keeps waiting. This uses the same single slot as
+    /// [`wait_set`][Self::wait_set], so at most one thread or task at a time
+    /// should wait for a callback to be set on a given cell this way.
+    #[cfg(feature = "std")]
+    pub fn wait_call(&self) {
+        let mut park = blocking::Park::new(|waker| {
+            self.set_waker.register(waker);
+        });
+        blocking::wait_until(&mut park, || self.take_call().then_some(()));
+    }
+
+    /// Spin until a callback is present, then atomically take and run it.
+    ///
+    /// For targets without threads to park, such as bare metal. Any number
+    /// of threads may spin on a cell at once. With the `wfe` feature, on arm
+    /// and aarch64, each spin waits for an event with `wfe` instead, so the
+    /// core can sleep, and [`put`][Self::put] sends one with `sev`. If
+    /// another thread takes the callback first, this keeps waiting.
+    pub fn wait_call_spin(&self) {
+        blocking::wait_until(&mut blocking::Spin(None), || self.take_call().then_some(()));
+    }
+
+    /// Like [`wait_call_spin`][Self::wait_call_spin], but gives up after
+    /// spinning `iterations` times.
+    ///
+    /// Returns true if a callback was run. With the `wfe` feature, on arm and
+    /// aarch64, each iteration waits for an event, so the bound counts
+    /// events, including those from other sources, rather than time.
+    pub fn wait_call_spin_for(&self, iterations: usize) -> bool {
+        blocking::wait_until(&mut blocking::Spin(Some(iterations)), || self.take_call().then_some(())).is_some()
+    }
+
+    /// Atomically take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present. If so, tasks waiting for the
+    /// cell to be empty are woken.
+    pub fn clear(&self) -> bool {
+        let ptr = self.cell.take_raw();
+        if !ptr.is_null() {
+            self.empty_waiters.wake_all();
+            unsafe { CallbackCell::drop_raw_ptr(ptr) };
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether a callback is currently present.
+    pub fn is_set(&self) -> bool {
+        self.cell.is_set()
+    }
+
+    /// Wait until a callback is present.
+    ///
+    /// This does not take the callback. Dropping the future deregisters its
+    /// waker.
+    pub fn wait_set(&self) -> WaitSet<'_> {
+        WaitSet { cell: self, registered: false }
+    }
+
+    /// Wait until a callback is present, then atomically take and run it.
+    ///
+    /// If another thread takes the callback first, this keeps waiting.
+    /// Dropping the future deregisters its waker.
+    pub fn wait_take_call(&self) -> WaitTakeCall<'_> {
+        WaitTakeCall { cell: self, registered: false }
+    }
+
+    /// Wait until no callback is present.
+    ///
+    /// Dropping the future deregisters its waker.
+    pub fn wait_empty(&self) -> WaitEmpty<'_> {
+        WaitEmpty { cell: self, id: None }
+    }
+
+    /// Wait until no callback is present, then set the callback.
+    ///
+    /// Setting is a compare-and-swap from empty, so if multiple producers are
+    /// waiting, only one of them sets its callback each time the cell becomes
+    /// empty, and the others keep waiting. Fairness between them is best
+    /// effort. Dropping the future before it completes drops the callback
+    /// without running it.
+    pub fn put_when_empty<F: FnOnce() + Send + 'static>(&self, f: F) -> PutWhenEmpty<'_> {
+        PutWhenEmpty {
+            cell: self,
+            ptr: raw::alloc_raw(move |()| f()),
+            id: None,
+        }
+    }
+
+    // register the waker for the task waiting for a callback to be set, then
+    // re-check whether `ready` holds, so that a concurrent put is not missed.
+    fn poll_set<R>(
+        &self,
+        cx: &mut Context,
+        registered: &mut bool,
+        mut ready: impl FnMut() -> Option<R>,
+    ) -> Poll<R> {
+        if let Some(r) = ready() {
+            return Poll::Ready(r);
+        }
+        *registered = true;
+        self.set_waker.register(cx.waker());
+        if let Some(r) = ready() {
+            return Poll::Ready(r);
+        }
+        Poll::Pending
+    }
+}
+
+/// Future returned by [`WaitableCallbackCell::wait_set`].
+#[must_use = "futures do nothing unless polled"]
+pub struct WaitSet<'a> {
+    cell: &'a WaitableCallbackCell,
+    registered: bool,
+}
+
+impl Future for WaitSet<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = &mut *self;
+        this.cell.poll_set(cx, &mut this.registered, || this.cell.is_set().then_some(()))
+    }
+}
+
+impl Drop for WaitSet<'_> {
+    fn drop(&mut self) {
+        if self.registered {
+            drop(self.cell.set_waker.take());
+        }
+    }
+}
+
+/// Future returned by [`WaitableCallbackCell::wait_take_call`].
+#[must_use = "futures do nothing unless polled"]
+pub struct WaitTakeCall<'a> {
+    cell: &'a WaitableCallbackCell,
+    registered: bool,
+}
+
+impl Future for WaitTakeCall<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = &mut *self;
+        this.cell.poll_set(cx, &mut this.registered, || this.cell.take_call().then_some(()))
+    }
+}
+
+impl Drop for WaitTakeCall<'_> {
+    fn drop(&mut self) {
+        if self.registered {
+            drop(self.cell.set_waker.take());
+        }
+    }
+}
+
+/// Future returned by [`WaitableCallbackCell::wait_empty`].
+#[must_use = "futures do nothing unless polled"]
+pub struct WaitEmpty<'a> {
+    cell: &'a WaitableCallbackCell,
+    id: Option<u64>,
+}
+
+impl Future for WaitEmpty<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = &mut *self;
+        if !this.cell.is_set() {
+            return Poll::Ready(());
+        }
+        this.cell.empty_waiters.register(&mut this.id, cx.waker());
+        if !this.cell.is_set() {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for WaitEmpty<'_> {
+    fn drop(&mut self) {
+        self.cell.empty_waiters.deregister(self.id);
+    }
+}
+
+/// Future returned by [`WaitableCallbackCell::put_when_empty`].
+#[must_use = "futures do nothing unless polled"]
+pub struct PutWhenEmpty<'a> {
+    cell: &'a WaitableCallbackCell,
+    // erased callback, or null once it's been put
+    ptr: *mut u8,
+    id: Option<u64>,
+}
+
+// safety: the erased callback is Send.
+unsafe impl Send for PutWhenEmpty<'_> {}
+unsafe impl Sync for PutWhenEmpty<'_> {}
+
+impl PutWhenEmpty<'_> {
+    fn try_put(&mut self) -> bool {
+        if self.cell.cell.put_raw_if_empty(self.ptr) {
+            self.ptr = ptr::null_mut();
+            self.cell.set_waker.wake();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Future for PutWhenEmpty<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = &mut *self;
+        assert!(!this.ptr.is_null(), "PutWhenEmpty polled after completion");
+        if this.try_put() {
+            return Poll::Ready(());
+        }
+        this.cell.empty_waiters.register(&mut this.id, cx.waker());
+        if this.try_put() {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for PutWhenEmpty<'_> {
+    fn drop(&mut self) {
+        self.cell.empty_waiters.deregister(self.id);
+        unsafe { raw::drop_raw::<(), ()>(self.ptr) };
+    }
+}
+
+impl Default for WaitableCallbackCell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for WaitableCallbackCell {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_set() {
+            f.write_str("WaitableCallbackCell(NOT NULL)")
+        } else {
+            f.write_str("WaitableCallbackCell(NULL)")
+        }
+    }
+}
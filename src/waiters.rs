@@ -0,0 +1,145 @@
+
+use crate::sync::{AtomicBool, fence};
+use core::{
+    sync::atomic::Ordering,
+    cell::UnsafeCell,
+    hint,
+    mem,
+    task::Waker,
+};
+use alloc::vec::Vec;
+
+// internals
+// ---------
+//
+// a list of wakers for any number of tasks waiting on some condition, guarded
+// by a spin lock. the lock is only held while editing the list, never while
+// waking or dropping wakers.
+//
+// each waiting future remembers the id of its entry, so that re-registering
+// updates its entry rather than adding another, and so that dropping the
+// future can remove its entry.
+//
+// to avoid lost wakeups, the waiting side registers then re-checks the
+// condition, and the notifying side changes the condition then checks for
+// waiters. both sides issue a SeqCst fence in between, so at least one of them
+// sees the other's write.
+
+pub(crate) struct WaiterList {
+    locked: AtomicBool,
+    // whether `waiters` is non-empty. allows skipping the lock.
+    has_waiters: AtomicBool,
+    inner: UnsafeCell<Inner>,
+}
+
+struct Inner {
+    next_id: u64,
+    waiters: Vec<(u64, Waker)>,
+}
+
+// safety: the inner data is only accessed while holding the lock, and Waker is
+//         Send and Sync.
+unsafe impl Send for WaiterList {}
+unsafe impl Sync for WaiterList {}
+
+impl WaiterList {
+    const_fn! {
+        pub(crate) fn new() -> Self {
+            WaiterList {
+                locked: AtomicBool::new(false),
+                has_waiters: AtomicBool::new(false),
+                inner: UnsafeCell::new(Inner {
+                    next_id: 0,
+                    waiters: Vec::new(),
+                }),
+            }
+        }
+    }
+
+    // run the closure on the inner data while holding the lock.
+    fn with_lock<R>(&self, f: impl FnOnce(&mut Inner) -> R) -> R {
+        while self.locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            hint::spin_loop();
+        }
+        let r = f(unsafe { &mut *self.inner.get() });
+        self.locked.store(false, Ordering::Release);
+        r
+    }
+
+    // register or update the waiter's waker. the caller must re-check its
+    // condition afterwards.
+    pub(crate) fn register(&self, id: &mut Option<u64>, waker: &Waker) {
+        let old_waker = self.with_lock(|inner| {
+            if let Some(entry) = id.and_then(|id| inner.waiters.iter_mut().find(|e| e.0 == id)) {
+                if entry.1.will_wake(waker) {
+                    return None;
+                }
+                return Some(mem::replace(&mut entry.1, waker.clone()));
+            }
+            let new_id = inner.next_id;
+            inner.next_id += 1;
+            inner.waiters.push((new_id, waker.clone()));
+            *id = Some(new_id);
+            self.has_waiters.store(true, Ordering::Relaxed);
+            None
+        });
+        fence(Ordering::SeqCst);
+        drop(old_waker);
+    }
+
+    // remove the waiter's entry, if present.
+    pub(crate) fn deregister(&self, id: Option<u64>) {
+        if let Some(id) = id {
+            let old_waker = self.with_lock(|inner| {
+                let i = inner.waiters.iter().position(|e| e.0 == id)?;
+                let (_, waker) = inner.waiters.swap_remove(i);
+                if inner.waiters.is_empty() {
+                    self.has_waiters.store(false, Ordering::Relaxed);
+                }
+                Some(waker)
+            });
+            drop(old_waker);
+        }
+    }
+
+    // wake and remove all waiters. the caller must have changed the condition
+    // beforehand.
+    pub(crate) fn wake_all(&self) {
+        fence(Ordering::SeqCst);
+        if !self.has_waiters.load(Ordering::Relaxed) {
+            return;
+        }
+        let waiters = self.with_lock(|inner| {
+            self.has_waiters.store(false, Ordering::Relaxed);
+            mem::take(&mut inner.waiters)
+        });
+        for (_, waker) in waiters {
+            waker.wake();
+        }
+    }
+}
+
+// waker which unparks the current thread, for blocking on a future-style
+// protocol.
+#[cfg(feature = "std")]
+pub(crate) fn thread_waker() -> Waker {
+    use std::thread::{self, Thread};
+    use alloc::{sync::Arc, task::Wake};
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+
+        fn wake_by_ref(self: &Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    Waker::from(Arc::new(ThreadWaker(thread::current())))
+}
@@ -0,0 +1,190 @@
+
+use crate::sync::AtomicUsize;
+use core::{
+    sync::atomic::Ordering,
+    cell::UnsafeCell,
+    hint,
+    task::Waker,
+    fmt::{self, Formatter, Debug},
+};
+
+// internals
+// ---------
+//
+// the waker is stored inline, in an `UnsafeCell<Option<Waker>>`. access to it
+// is arbitrated by the state, which is a combination of two bits:
+//
+// - `REGISTERING`: a `register` call has exclusive access to the waker.
+// - `WAKING`: a `take` (or `wake`) call has exclusive access to the waker if
+//   `REGISTERING` was not set when it set `WAKING`. otherwise, it leaves the
+//   waker to the registering call, which will see the bit and wake the newly
+//   registered waker itself.
+//
+// the transitions are:
+//
+// - `register`: WAITING -> REGISTERING -> WAITING, or, if a wake arrived in
+//   the meantime, REGISTERING | WAKING -> WAITING, waking the new waker. if
+//   already WAKING, wakes the new waker without storing it. if already
+//   REGISTERING, spins until the other `register` call is done.
+// - `take`: WAITING -> WAKING -> WAITING, taking the waker. if already
+//   REGISTERING, becomes REGISTERING | WAKING and returns nothing. if already
+//   WAKING, returns nothing.
+//
+// so `take` and `wake` never spin. wakers are never cloned, woken, or dropped
+// by `take` while holding the bits.
+
+const WAITING: usize = 0;
+const REGISTERING: usize = 0b01;
+const WAKING: usize = 0b10;
+
+/// Like an `Atomic<Option<Waker>>`, with the same register/wake protocol as
+/// futures' `AtomicWaker`.
+///
+/// Unlike putting a `move || waker.wake()` callback into a
+/// [`CallbackCell`][crate::CallbackCell], this makes no heap allocation.
+///
+/// The point of the protocol is that a wake is never lost when it races with
+/// a registration: if [`wake`][Self::wake] is called concurrently with
+/// [`register`][Self::register], then either the wake observes the newly
+/// registered waker and wakes it, or `register` itself wakes the new waker
+/// and reports [`RegisterOutcome::Woken`]. So the usual pattern is safe:
+///
+/// - the consumer calls `register`, then checks whether it's ready, and
+///   returns `Poll::Pending` if not.
+/// - the producer makes it ready, then calls `wake`.
+pub struct WakerCell {
+    state: AtomicUsize,
+    waker: UnsafeCell<Option<Waker>>,
+}
+
+/// Outcome of [`WakerCell::register`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RegisterOutcome {
+    /// The waker was stored in the cell.
+    Registered,
+    /// A wake happened concurrently with the registration, so the waker was
+    /// woken rather than left in the cell.
+    Woken,
+}
+
+// safety: the waker is only accessed with exclusive access arbitrated by the
+//         state, and Waker is Send and Sync.
+unsafe impl Send for WakerCell {}
+unsafe impl Sync for WakerCell {}
+
+impl WakerCell {
+    const_fn! {
+        /// Construct with no waker.
+        pub fn new() -> Self {
+            WakerCell {
+                state: AtomicUsize::new(WAITING),
+                waker: UnsafeCell::new(None),
+            }
+        }
+    }
+
+    /// Atomically set the waker to a clone of the given waker.
+    ///
+    /// Any waker previously present is dropped without being woken. If a
+    /// wake happens concurrently, the given waker is woken, and this returns
+    /// [`RegisterOutcome::Woken`].
+    ///
+    /// Concurrent calls to `register` are serialized by spinning, which is
+    /// brief since the only work done exclusively is cloning the waker.
+    pub fn register(&self, waker: &Waker) -> RegisterOutcome {
+        loop {
+            match self.state.compare_exchange_weak(
+                WAITING,
+                REGISTERING,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    // we have exclusive access to the waker
+                    let old_waker = unsafe { (*self.waker.get()).replace(waker.clone()) };
+                    return match self.state.compare_exchange(
+                        REGISTERING,
+                        WAITING,
+                        Ordering::AcqRel,
+                        Ordering::Acquire,
+                    ) {
+                        Ok(_) => {
+                            drop(old_waker);
+                            RegisterOutcome::Registered
+                        }
+                        Err(actual) => {
+                            // a wake arrived while registering, and left the
+                            // waking to us
+                            debug_assert_eq!(actual, REGISTERING | WAKING);
+                            let new_waker = unsafe { (*self.waker.get()).take() };
+                            self.state.swap(WAITING, Ordering::AcqRel);
+                            drop(old_waker);
+                            if let Some(new_waker) = new_waker {
+                                new_waker.wake();
+                            }
+                            RegisterOutcome::Woken
+                        }
+                    };
+                }
+                Err(WAKING) => {
+                    // a wake is in progress, which counts as happening after
+                    // this registration
+                    waker.wake_by_ref();
+                    return RegisterOutcome::Woken;
+                }
+                Err(_) => {
+                    // another register call is in progress, or spurious
+                    // failure
+                    hint::spin_loop();
+                }
+            }
+        }
+    }
+
+    /// Atomically take the waker then wake it.
+    ///
+    /// Returns true if a waker was present, and was woken by this call. If
+    /// this call races with a `register`, it may instead leave the waking to
+    /// that `register` call, and return false.
+    pub fn wake(&self) -> bool {
+        if let Some(waker) = self.take() {
+            waker.wake();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Atomically take the waker without waking it.
+    ///
+    /// If this races with a `register`, it returns `None`, and the
+    /// registering call wakes the new waker itself, as if this were a call to
+    /// [`wake`][Self::wake].
+    pub fn take(&self) -> Option<Waker> {
+        match self.state.fetch_or(WAKING, Ordering::AcqRel) {
+            WAITING => {
+                // we have exclusive access to the waker
+                let waker = unsafe { (*self.waker.get()).take() };
+                self.state.fetch_and(!WAKING, Ordering::Release);
+                waker
+            }
+            state => {
+                // a register or take call is in progress, and will handle it
+                debug_assert!(state & (REGISTERING | WAKING) != 0);
+                None
+            }
+        }
+    }
+}
+
+impl Default for WakerCell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for WakerCell {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("WakerCell(..)")
+    }
+}
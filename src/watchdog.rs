@@ -0,0 +1,165 @@
+
+use core::{
+    ptr,
+    cell::Cell,
+    time::Duration,
+    sync::atomic::{AtomicPtr, Ordering, fence},
+};
+use alloc::boxed::Box;
+use std::time::Instant;
+
+// internals
+// ---------
+//
+// the installed watchdog is a nullable pointer to a leaked `Watchdog`, as with
+// the hooks in the `hooks` module, and for the same reasons.
+//
+// `trace::call` brackets each run of a callback with `start` and `finish`,
+// which time it if a watchdog is installed. the callback's own function
+// pointer, described in the `raw` module, notes the callback's type name, and
+// its label if it has one, in a thread-local slot as it starts running. `start`
+// saves and empties the slot, and `finish` reads it, then restores it, even on
+// unwind, so a callback taken and run by another callback doesn't disturb the
+// outer one's identity. callbacks whose function pointers don't note their type, such as
+// static callbacks, leave the slot empty.
+//
+// `finish` reports after the callback has returned, when the take is over and
+// the cell is in a settled state. while a report runs, further reports on the
+// same thread are skipped, so a report can use callback cells.
+
+static WATCHDOG: AtomicPtr<Watchdog> = AtomicPtr::new(ptr::null_mut());
+
+#[derive(Clone, Copy, Default)]
+struct Identity {
+    callback: Option<&'static str>,
+    label: Option<&'static str>,
+}
+
+std::thread_local! {
+    static RUNNING: Cell<Identity> = const { Cell::new(Identity { callback: None, label: None }) };
+    static IN_REPORT: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Configuration for [`set_watchdog`].
+#[derive(Debug, Copy, Clone)]
+pub struct Watchdog {
+    /// How long a callback may run before it's reported.
+    pub threshold: Duration,
+    /// Called on the thread which ran a callback, once it returns, if it ran
+    /// for longer than the threshold. Must not panic.
+    pub report: fn(&SlowCallback),
+}
+
+/// Metadata passed to [`Watchdog::report`].
+#[derive(Debug, Copy, Clone)]
+#[non_exhaustive]
+pub struct SlowCallback {
+    /// How long the callback ran for.
+    pub elapsed: Duration,
+    /// The cell's type name, e.g. `"CallbackCell"`.
+    pub kind: &'static str,
+    /// The cell's address.
+    pub cell: *const (),
+    /// The cell's name, if it has one.
+    pub name: Option<&'static str>,
+    /// The callback's label, if it was put with a label.
+    pub label: Option<&'static str>,
+    /// The callback's type name, if known: it is for callbacks put as
+    /// closures, but not for static callbacks.
+    pub callback: Option<&'static str>,
+}
+
+/// Install a process-wide watchdog for slow callbacks, replacing any
+/// installed before.
+///
+/// Behind the `watchdog` feature. Once installed, every callback taken and
+/// run by [`CallbackCell`][crate::CallbackCell] and
+/// [`CallbackCellArgs`][crate::CallbackCellArgs], including the named
+/// cells, is timed, with a pair of [`Instant::now`] calls, and reported if
+/// it runs longer than the threshold. A callback which panics isn't
+/// reported.
+pub fn set_watchdog(watchdog: Watchdog) {
+    let new = Box::into_raw(Box::new(watchdog));
+    WATCHDOG.swap(new, Ordering::Release);
+}
+
+/// Uninstall the process-wide watchdog, returning it.
+pub fn take_watchdog() -> Option<Watchdog> {
+    let old = WATCHDOG.swap(ptr::null_mut(), Ordering::Acquire);
+    unsafe { old.as_ref().copied() }
+}
+
+// a callback is about to be run, by the function pointer of a callback of
+// the named type.
+#[inline]
+pub(crate) fn note_callback(callback: &'static str) {
+    let _ = RUNNING.try_with(|running| running.set(Identity { callback: Some(callback), ..running.get() }));
+}
+
+// a callback with the given label is about to be run.
+#[inline]
+pub(crate) fn note_label(label: &'static str) {
+    let _ = RUNNING.try_with(|running| running.set(Identity { label: Some(label), ..running.get() }));
+}
+
+// timing of one run of a callback, from `start`. restores the outer identity
+// when dropped, even if the callback panics.
+pub(crate) struct Watch {
+    start: Instant,
+    outer: Identity,
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        let _ = RUNNING.try_with(|running| running.set(self.outer));
+    }
+}
+
+// start timing a callback, if a watchdog is installed.
+#[inline]
+pub(crate) fn start() -> Option<Watch> {
+    if WATCHDOG.load(Ordering::Relaxed).is_null() {
+        return None;
+    }
+    let outer = RUNNING.try_with(|running| running.take()).ok()?;
+    Some(Watch { start: Instant::now(), outer })
+}
+
+// finish timing a callback, reporting it if it was slow.
+#[inline]
+pub(crate) fn finish(watch: Option<Watch>, kind: &'static str, cell: *const (), name: Option<&'static str>) {
+    let Some(watch) = watch else {
+        return;
+    };
+    let elapsed = watch.start.elapsed();
+    let identity = RUNNING.with(|running| running.get());
+    drop(watch);
+    let watchdog = WATCHDOG.load(Ordering::Relaxed);
+    if watchdog.is_null() {
+        return;
+    }
+    fence(Ordering::Acquire);
+    let watchdog = unsafe { &*watchdog };
+    if elapsed <= watchdog.threshold {
+        return;
+    }
+    let _ = IN_REPORT.try_with(|in_report| {
+        if !in_report.replace(true) {
+            struct Reset<'a>(&'a Cell<bool>);
+            impl Drop for Reset<'_> {
+                fn drop(&mut self) {
+                    self.0.set(false);
+                }
+            }
+            let _reset = Reset(in_report);
+            (watchdog.report)(&SlowCallback {
+                elapsed,
+                kind,
+                cell,
+                name,
+                label: identity.label,
+                callback: identity.callback,
+            });
+        }
+    });
+}
@@ -0,0 +1,237 @@
+
+use core::{
+    panic::{UnwindSafe, RefUnwindSafe},
+    fmt::{self, Formatter, Debug},
+};
+
+// internals
+// ---------
+//
+// on x86_64 and aarch64, the state is a 128-bit word, the low 64 bits of which
+// are the address of the callback's data, and the high 64 bits of which are
+// the address of an `unsafe fn(*mut u8, Option<I>) -> Option<O>` which, when
+// called with the data pointer, frees the data and, if given the input, runs
+// the callback with it and returns its output, or else drops the callback
+// without running it. the state is zero when empty, since the function's
+// address is never zero.
+//
+// the data is a `Box<F>`, so a zero-sized callback, such as a closure which
+// captures nothing or a function item, makes no heap allocation, and its
+// data pointer is dangling. so taking a callback only swaps the word: the
+// function pointer is in hand without reading the heap, and it's the callback
+// itself which first touches its data.
+//
+// the data pointer's provenance is exposed when it's packed into the word, and
+// recovered from the address when unpacked, as in the `stamped` module.
+// `AtomicU128` comes from `portable-atomic`, which is lock-free on x86_64 with
+// `cmpxchg16b`, detected at run time unless enabled at compile time, and on
+// aarch64.
+//
+// elsewhere, the cell is a `CallbackCellArgs`, with the same behavior.
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod imp {
+    use portable_atomic::AtomicU128;
+    use alloc::boxed::Box;
+    use core::{
+        sync::atomic::Ordering,
+        ptr,
+        mem,
+        marker::PhantomData,
+    };
+
+    type FnPtr<I, O> = unsafe fn(*mut u8, Option<I>) -> Option<O>;
+
+    // implementation for the function pointer for a given callback type F.
+    unsafe fn fn_ptr_impl<I, O, F: FnOnce(I) -> O>(data: *mut u8, input: Option<I>) -> Option<O> {
+        let f = Box::from_raw(data as *mut F);
+        if mem::size_of::<F>() != 0 {
+            #[cfg(any(test, feature = "test-util"))]
+            crate::test_util::on_dealloc();
+            #[cfg(feature = "alloc-stats")]
+            crate::alloc_stats::on_dealloc(mem::size_of::<F>());
+        }
+        input.map(f)
+    }
+
+    fn pack<I, O>(data: *mut u8, fn_ptr: FnPtr<I, O>) -> u128 {
+        ((fn_ptr as usize as u128) << 64) | data.expose_provenance() as u128
+    }
+
+    // the data pointer and function pointer of the non-zero state.
+    unsafe fn unpack<I, O>(state: u128) -> (*mut u8, FnPtr<I, O>) {
+        let data = ptr::with_exposed_provenance_mut(state as u64 as usize);
+        let fn_ptr = mem::transmute::<usize, FnPtr<I, O>>((state >> 64) as u64 as usize);
+        (data, fn_ptr)
+    }
+
+    pub(super) struct Inner<I, O> {
+        state: AtomicU128,
+        _p: PhantomData<dyn FnOnce(I) -> O + Send + 'static>,
+    }
+
+    impl<I, O> Inner<I, O> {
+        pub(super) const fn new() -> Self {
+            Inner {
+                state: AtomicU128::new(0),
+                _p: PhantomData,
+            }
+        }
+
+        pub(super) fn put<F: FnOnce(I) -> O + Send + 'static>(&self, f: F) {
+            let data = Box::into_raw(Box::new(f)) as *mut u8;
+            if mem::size_of::<F>() != 0 {
+                #[cfg(any(test, feature = "test-util"))]
+                crate::test_util::on_alloc();
+                #[cfg(feature = "alloc-stats")]
+                crate::alloc_stats::on_alloc(mem::size_of::<F>());
+            }
+            let old = self.state.swap(pack(data, fn_ptr_impl::<I, O, F> as FnPtr<I, O>), Ordering::AcqRel);
+            unsafe { drop_state::<I, O>(old) };
+        }
+
+        pub(super) fn take_call(&self, input: I) -> Result<O, I> {
+            let old = self.state.swap(0, Ordering::Acquire);
+            if old == 0 {
+                return Err(input);
+            }
+            unsafe {
+                let (data, fn_ptr) = unpack::<I, O>(old);
+                Ok(fn_ptr(data, Some(input)).unwrap())
+            }
+        }
+
+        pub(super) fn clear(&self) -> bool {
+            let old = self.state.swap(0, Ordering::Acquire);
+            unsafe { drop_state::<I, O>(old) };
+            old != 0
+        }
+
+        pub(super) fn is_set(&self) -> bool {
+            self.state.load(Ordering::Acquire) != 0
+        }
+    }
+
+    // drop the callback in the state, if any, without running it.
+    unsafe fn drop_state<I, O>(state: u128) {
+        if state != 0 {
+            let (data, fn_ptr) = unpack::<I, O>(state);
+            fn_ptr(data, None);
+        }
+    }
+
+    impl<I, O> Drop for Inner<I, O> {
+        fn drop(&mut self) {
+            unsafe { drop_state::<I, O>(*self.state.get_mut()) };
+        }
+    }
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod imp {
+    use crate::CallbackCellArgs;
+
+    pub(super) struct Inner<I, O>(CallbackCellArgs<I, O>);
+
+    impl<I, O> Inner<I, O> {
+        pub(super) const fn new() -> Self {
+            Inner(CallbackCellArgs::new())
+        }
+
+        pub(super) fn put<F: FnOnce(I) -> O + Send + 'static>(&self, f: F) {
+            self.0.put(f)
+        }
+
+        pub(super) fn take_call(&self, input: I) -> Result<O, I> {
+            self.0.take_call(input)
+        }
+
+        pub(super) fn clear(&self) -> bool {
+            self.0.clear()
+        }
+
+        pub(super) fn is_set(&self) -> bool {
+            self.0.is_set()
+        }
+    }
+}
+
+/// A [`CallbackCellArgs`][crate::CallbackCellArgs] which stores its
+/// callback's function pointer beside its data pointer, so that taking it
+/// needn't read the heap before calling it.
+///
+/// Behind the `wide` feature. A `CallbackCellArgs` is one pointer, to a heap
+/// allocation which starts with the function pointer, so `take_call` has to
+/// load that, likely a cache miss if another thread put the callback, before
+/// the call can start. This cell instead swaps both words at once, in one
+/// 128-bit atomic, and callbacks which capture nothing make no heap
+/// allocation at all. Those are the callbacks it's faster for: one which
+/// captures data reads its allocation when it runs anyway, and the 128-bit
+/// swap is slower than a pointer swap.
+///
+/// That's on x86_64, where it's lock-free given `cmpxchg16b`, which is
+/// detected at run time, and aarch64. On other targets, it's a
+/// `CallbackCellArgs`. Either way, it behaves the same.
+pub struct WideCallbackCellArgs<I, O>(imp::Inner<I, O>);
+
+// safety: as for `CallbackCellArgs`.
+unsafe impl<I, O> Send for WideCallbackCellArgs<I, O> {}
+unsafe impl<I, O> Sync for WideCallbackCellArgs<I, O> {}
+impl<I, O> UnwindSafe for WideCallbackCellArgs<I, O> {}
+impl<I, O> RefUnwindSafe for WideCallbackCellArgs<I, O> {}
+
+impl<I, O> WideCallbackCellArgs<I, O> {
+    /// Construct with no callback.
+    ///
+    /// This is a `const fn`, so cells can be placed in statics.
+    pub const fn new() -> Self {
+        WideCallbackCellArgs(imp::Inner::new())
+    }
+
+    /// Atomically set the callback.
+    ///
+    /// Makes at most one heap allocation, and none if the callback is
+    /// zero-sized. Any callback previously present is dropped.
+    pub fn put<F: FnOnce(I) -> O + Send + 'static>(&self, f: F) {
+        self.0.put(f)
+    }
+
+    /// Atomically take the callback then run it with the given input.
+    ///
+    /// Returns the output if a callback was present. If a callback was not
+    /// present, returns the original input.
+    pub fn take_call(&self, input: I) -> Result<O, I> {
+        self.0.take_call(input)
+    }
+
+    /// Atomically take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        self.0.clear()
+    }
+
+    /// Whether a callback is currently present.
+    ///
+    /// This is only a snapshot: another thread may put or take a callback
+    /// immediately afterwards.
+    pub fn is_set(&self) -> bool {
+        self.0.is_set()
+    }
+}
+
+impl<I, O> Default for WideCallbackCellArgs<I, O> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I, O> Debug for WideCallbackCellArgs<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_set() {
+            f.write_str("WideCallbackCellArgs(NOT NULL)")
+        } else {
+            f.write_str("WideCallbackCellArgs(NULL)")
+        }
+    }
+}
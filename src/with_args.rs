@@ -1,76 +1,521 @@
 
-
-use std::{
-    sync::atomic::{
-        AtomicUsize,
-        Ordering,
-    },
-    alloc::{
-        Layout,
-        alloc,
-        dealloc,
-        handle_alloc_error,
-    },
+use crate::{
+    CallbackCell,
+    sync::{CellPtr, LoadMut},
+    raw,
+    trace,
+    StaticCallback,
+};
+#[cfg(feature = "alloc")]
+use crate::{
+    define::ApplyOnce,
+    DropSink,
+    RawCallbackArgs,
+    ArgsSlot,
+    DisplacedCallback,
+    Graveyard,
+};
+#[cfg(all(feature = "alloc", target_has_atomic = "ptr"))]
+use crate::{
+    receipt::{self, PutReceipt},
+    SharedCallback,
+};
+#[cfg(all(feature = "alloc", target_has_atomic = "ptr"))]
+use alloc::sync::Arc;
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+use core::{
+    sync::atomic::Ordering,
+    ptr,
+    ffi::c_void,
     marker::PhantomData,
-    mem::ManuallyDrop,
+    panic::{UnwindSafe, RefUnwindSafe},
+    any::type_name,
     fmt::{self, Formatter, Debug},
 };
 
 // internals
 // ---------
 //
-// the inner atomic usize is a nullable pointer to a heap allocation.
-// the pointed-to data consists of:
+// the inner atomic pointer is a nullable pointer to an erased callback, as
+// described in the `raw` module.
 //
-// - an `unsafe fn(Option<&mut union { I, O }, *mut u8)` which, when
-//   called with the pointer:
+// `CallbackCell` is the same atomic pointer with `I = ()` and `O = ()`, and
+// both are `repr(transparent)`, so a `CallbackCellArgs<(), ()>` and a
+// `CallbackCell` can be viewed as each other.
 //
-//   - if the option is Some, reads the input from the union, runs the
-//     callback with the input (dropping it and the input), and writes
-//     the output back to the union
-//   - if the option is None, drops the callback without running it
-//   - deallocates the heap allocation
-// - padding
-// - the `F: FnOnce() + Send + 'static` value
+// a callback put with `put_with_cleanup` is wrapped in a closure which also
+// captures an `OnDiscard` guard, within the same allocation. the closure
+// disarms the guard before running the callback, so if the closure is dropped
+// without running, by whatever path, the guard runs the cleanup, and if it
+// runs, the cleanup never does, even if the callback panics.
+
+// runs the cleanup when dropped, unless disarmed.
+#[cfg(feature = "alloc")]
+pub(crate) struct OnDiscard<G: FnOnce()>(pub(crate) Option<G>);
+
+#[cfg(feature = "alloc")]
+impl<G: FnOnce()> OnDiscard<G> {
+    pub(crate) fn disarm(&mut self) {
+        self.0 = None;
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<G: FnOnce()> Drop for OnDiscard<G> {
+    fn drop(&mut self) {
+        if let Some(on_discard) = self.0.take() {
+            on_discard();
+        }
+    }
+}
 
 /// Like an `Atomic<Option<Box<dyn FnOnce(I) -> O + Send + 'static>>>`.
 ///
-/// It's a normal [`CallbackCell`][crate::CallbackCell] but with args.
+/// It's a normal [`CallbackCell`][crate::CallbackCell] but with args,
+/// including without the `alloc` feature, where only the methods which never
+/// allocate are available.
+///
+/// For callbacks which take no input but return an output, a
+/// `CallbackCellArgs<(), O>` has [`take_call_unit`][Self::take_call_unit],
+/// which returns an `Option<O>` without passing `()`, and
+/// `CallbackCellOutput<O>` wraps the same cell with only that form.
+/// `CallbackCell` doesn't take an output type parameter defaulting to `()`
+/// instead: its `take_call` returns a `bool`, which a generic output couldn't
+/// keep, and defaults don't drive inference, so `let cell = CallbackCell::new();`
+/// with no put would no longer compile.
+///
+/// # Layout
+///
+/// The same as `CallbackCell`'s, for any `I` and `O`: the cell is
+/// `repr(transparent)` over an atomic pointer, so it has the same size and
+/// alignment as an `AtomicPtr<()>`, and an empty cell is all zero bytes. That
+/// is part of the API and will stay so, except with the `disabled` feature,
+/// as for `CallbackCell`.
+#[repr(transparent)]
 pub struct CallbackCellArgs<I, O> {
-    ptr: AtomicUsize,
+    ptr: CellPtr,
+    // the generic parameters of a trait object are invariant, so this makes
+    // the cell invariant in both `I` and `O`, like `Cell<T>`. that is
+    // deliberate: a cell is both written and read through a shared reference,
+    // so variance in either direction would let `put` and `take_call` be
+    // used at different types. the ui tests pin this down.
     _p: PhantomData<dyn FnOnce(I) -> O + Send + 'static>,
 }
 
+#[cfg(all(target_has_atomic = "ptr", not(loom), not(feature = "disabled")))]
+const _: () = {
+    use core::{mem, sync::atomic};
+    type Big = CallbackCellArgs<[u64; 4], [u64; 3]>;
+    assert!(mem::size_of::<CallbackCellArgs<(), ()>>() == mem::size_of::<atomic::AtomicPtr<()>>());
+    assert!(mem::align_of::<CallbackCellArgs<(), ()>>() == mem::align_of::<atomic::AtomicPtr<()>>());
+    assert!(mem::size_of::<Big>() == mem::size_of::<atomic::AtomicPtr<()>>());
+    assert!(mem::align_of::<Big>() == mem::align_of::<atomic::AtomicPtr<()>>());
+};
+
+// safety: the callbacks stored in the cell are Send. an `I` or `O` value never
+//         crosses threads through the cell: they are passed into and out of the
+//         callback on the thread which calls `take_call`. so the cell is Send
+//         and Sync for any `I` and `O`, even ones which aren't Send or Sync.
+unsafe impl<I, O> Send for CallbackCellArgs<I, O> {}
+unsafe impl<I, O> Sync for CallbackCellArgs<I, O> {}
+
+// a panic in a callback unwinds out of `take_call` after the callback has been
+// taken, so the cell is left empty, which is a valid state. the callback
+// itself, with whatever broken state it captured, is dropped during unwinding.
+impl<I, O> UnwindSafe for CallbackCellArgs<I, O> {}
+impl<I, O> RefUnwindSafe for CallbackCellArgs<I, O> {}
+
 impl<I, O> CallbackCellArgs<I, O> {
-    /// Construct with no callback.
-    pub fn new() -> Self {
+    const_fn! {
+        /// Construct with no callback.
+        ///
+        /// This is a `const fn`, so cells can be placed in statics. That is part of
+        /// the API and will stay so.
+        pub fn new() -> Self {
+            CallbackCellArgs {
+                ptr: CellPtr::new(ptr::null_mut()),
+                _p: PhantomData,
+            }
+        }
+    }
+
+    /// Construct with the given callback already present.
+    ///
+    /// Makes only one heap allocation, and leaves the cell in the same state
+    /// as [`new`][Self::new] followed by [`put`][Self::put].
+    #[cfg(feature = "alloc")]
+    pub fn new_with<F: FnOnce(I) -> O + Send + 'static>(f: F) -> Self {
+        #[cfg(feature = "disabled")]
+        {
+            drop(f);
+            Self::new()
+        }
+        #[cfg(not(feature = "disabled"))]
         CallbackCellArgs {
-            ptr: AtomicUsize::new(0),
+            ptr: CellPtr::new(raw::alloc_raw(f)),
             _p: PhantomData,
         }
     }
 
+    /// Construct with no callback, gated by the given gate, so that takes do
+    /// nothing while it's disabled.
+    ///
+    /// See [`CallbackCell::new_gated`][crate::CallbackCell::new_gated].
+    #[cfg(all(feature = "alloc", target_has_atomic = "ptr"))]
+    pub fn new_gated(gate: crate::Gate) -> crate::GatedCallbackCellArgs<I, O> {
+        crate::GatedCallbackCellArgs::new(gate)
+    }
+
+    const_fn! {
+        /// Construct with the given function already present, without
+        /// allocating.
+        ///
+        /// This is a `const fn`, so a cell in a static can start out holding a
+        /// default handler. The callback must be a function item, or a closure
+        /// which captures nothing: something zero-sized and `Copy`, which can
+        /// be held like a [`StaticCallback`]. A cast `fn(I) -> O` pointer
+        /// fails to compile, as it can't be held in a static. Taking the
+        /// handler calls it like any other callback, and replacing or
+        /// clearing it drops nothing. Either way, the cell is then empty, as
+        /// usual, until something is put.
+        ///
+        // with the `disabled` feature, cells never hold callbacks, so this
+        // can't pass, and without `alloc`, there's no `put`
+        #[cfg_attr(all(feature = "alloc", not(feature = "disabled")), doc = "```")]
+        #[cfg_attr(any(not(feature = "alloc"), feature = "disabled"), doc = "```ignore")]
+        /// use callback_cell::CallbackCellArgs;
+        ///
+        /// fn default_sink(record: &'static str) {
+        ///     eprintln!("{}", record);
+        /// }
+        ///
+        /// static LOG_SINK: CallbackCellArgs<&'static str, ()> = CallbackCellArgs::new_with_fn(default_sink);
+        ///
+        /// assert!(LOG_SINK.is_set());
+        /// LOG_SINK.put(|_record| ());
+        /// ```
+        pub fn new_with_fn<F>(f: F) -> Self
+        where
+            F: Fn(I) -> O + Copy + Send + Sync + 'static,
+            I: 'static,
+            O: 'static,
+        {
+            let _ = f;
+            #[cfg(feature = "disabled")]
+            {
+                Self::new()
+            }
+            #[cfg(not(feature = "disabled"))]
+            CallbackCellArgs {
+                ptr: CellPtr::new(raw::ZstStatic::<F, I, O>::as_raw()),
+                _p: PhantomData,
+            }
+        }
+    }
+
+    /// Construct an empty cell in place, in memory owned by the caller,
+    /// returning a reference to it.
+    ///
+    /// See [`CallbackCell::init_at`][crate::CallbackCell::init_at], including
+    /// for cells which are never dropped.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for writes and aligned for a `CallbackCellArgs`,
+    /// and must stay valid, and not be written to other than through the
+    /// cell, for `'a`.
+    pub unsafe fn init_at<'a>(ptr: *mut Self) -> &'a Self {
+        ptr.write(Self::new());
+        &*ptr
+    }
+
+    /// A reference to a cell in memory owned by the caller.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a live cell with the same `I` and `O`, such as one
+    /// constructed with `init_at`, or zeroed memory aligned for a
+    /// `CallbackCellArgs`, and must stay valid, and not be written to other
+    /// than through the cell, for `'a`.
+    pub unsafe fn from_ptr<'a>(ptr: *const Self) -> &'a Self {
+        &*ptr
+    }
+
     /// Atomically set the callback.
     ///
     /// Makes only one heap allocation. Any callback previously present is dropped.
+    #[cfg(feature = "alloc")]
     pub fn put<F: FnOnce(I) -> O + Send + 'static>(&self, f: F) {
+        self.put_replacing(f, None)
+    }
+
+    // put, tracing with the given name.
+    #[cfg(feature = "alloc")]
+    pub(crate) fn put_replacing<F: FnOnce(I) -> O + Send + 'static>(&self, f: F, name: Option<&'static str>) {
+        #[cfg(feature = "disabled")]
+        {
+            let _ = name;
+            drop(f);
+            return;
+        }
+        #[allow(unreachable_code)]
         unsafe {
             // allocate and initialize heap allocation
-            let (layout, callback_offset) = Layout::new::<FnPtrType<I, O>>()
-                .extend(Layout::new::<F>()).unwrap();
-            let ptr = alloc(layout);
-            if ptr.is_null() {
-                handle_alloc_error(layout);
-            }
-            (ptr as *mut FnPtrType<I, O>).write(fn_ptr_impl::<I, O, F>);
-            (ptr.add(callback_offset) as *mut F).write(f);
+            let ptr = raw::alloc_raw(f);
 
             // atomic put
-            let old_ptr = self.ptr.swap(ptr as usize, Ordering::Release);
+            let old_ptr = self.ptr.swap(ptr, Ordering::Release);
+            trace::put("CallbackCellArgs", self.addr(), name, type_name::<F>(), None, !old_ptr.is_null());
 
             // clean up previous value
-            drop_raw::<I, O>(old_ptr as *mut u8);
+            raw::drop_raw::<I, O>(old_ptr);
+        }
+    }
+
+    /// Atomically set the callback, along with a cleanup to run if the
+    /// callback is dropped without running.
+    ///
+    /// See [`CallbackCell::put_with_cleanup`][crate::CallbackCell::put_with_cleanup].
+    #[cfg(feature = "alloc")]
+    pub fn put_with_cleanup<F, G>(&self, f: F, on_discard: G)
+    where
+        F: FnOnce(I) -> O + Send + 'static,
+        G: FnOnce() + Send + 'static,
+    {
+        let mut guard = OnDiscard(Some(on_discard));
+        self.put(move |input| {
+            guard.disarm();
+            f(input)
+        })
+    }
+
+    /// Atomically set the callback, along with a copy of some bytes, which
+    /// it's given mutable access to, with the input, when it runs.
+    ///
+    /// See [`CallbackCell::put_with_bytes`][crate::CallbackCell::put_with_bytes].
+    #[cfg(feature = "alloc")]
+    pub fn put_with_bytes<F>(&self, data: &[u8], f: F)
+    where
+        F: FnOnce(&mut [u8], I) -> O + Send + 'static,
+    {
+        #[cfg(feature = "disabled")]
+        {
+            let _ = data;
+            drop(f);
+            return;
+        }
+        #[allow(unreachable_code)]
+        let ptr = raw::alloc_raw_with_bytes(data, f);
+        let old_ptr = self.ptr.swap(ptr, Ordering::Release);
+        trace::put("CallbackCellArgs", self.addr(), None, type_name::<F>(), None, !old_ptr.is_null());
+        unsafe { raw::drop_raw::<I, O>(old_ptr) };
+    }
+
+    /// Atomically set a callback which converts its input with `map`, then
+    /// passes the result to `f`.
+    ///
+    /// For handlers which take their input in another form than the cell's.
+    /// Makes only one heap allocation, holding both `map` and `f`, as a
+    /// closure fusing the two by hand would. If the callback is dropped
+    /// without running, both are dropped. If `map` panics, `f` is dropped
+    /// without running.
+    #[cfg(feature = "alloc")]
+    pub fn put_map_input<J, M, F>(&self, map: M, f: F)
+    where
+        M: FnOnce(I) -> J + Send + 'static,
+        F: FnOnce(J) -> O + Send + 'static,
+    {
+        self.put(move |input| f(map(input)))
+    }
+
+    /// Atomically set a callback which runs `f`, then converts its output
+    /// with `map`.
+    ///
+    /// Makes only one heap allocation, holding both `f` and `map`, as for
+    /// [`put_map_input`][Self::put_map_input]. If the callback is dropped
+    /// without running, both are dropped. If `f` panics, `map` is dropped
+    /// without running.
+    #[cfg(feature = "alloc")]
+    pub fn put_map_output<P, F, M>(&self, f: F, map: M)
+    where
+        F: FnOnce(I) -> P + Send + 'static,
+        M: FnOnce(P) -> O + Send + 'static,
+    {
+        self.put(move |input| map(f(input)))
+    }
+
+    /// Atomically set the callback, with a label for diagnostics.
+    ///
+    /// See [`CallbackCell::put_named`][crate::CallbackCell::put_named].
+    #[cfg(feature = "alloc")]
+    pub fn put_named<F: FnOnce(I) -> O + Send + 'static>(&self, label: &'static str, f: F) {
+        #[cfg(feature = "disabled")]
+        {
+            let _ = label;
+            drop(f);
+            return;
+        }
+        #[allow(unreachable_code)]
+        let ptr = raw::alloc_raw_labeled(f, label);
+        let old_ptr = self.ptr.swap(ptr, Ordering::Release);
+        trace::put("CallbackCellArgs", self.addr(), None, type_name::<F>(), Some(label), !old_ptr.is_null());
+        unsafe { raw::drop_raw::<I, O>(old_ptr) };
+    }
+
+    /// The label of the callback currently present, if it was put with
+    /// [`put_named`][Self::put_named].
+    ///
+    /// See [`CallbackCell::label`][crate::CallbackCell::label].
+    #[cfg(feature = "alloc")]
+    pub fn label(&self) -> Option<&'static str> {
+        self.peek(|ptr| unsafe { raw::label_raw::<I, O>(ptr) }).flatten()
+    }
+
+    /// Atomically set a static callback, without allocating.
+    ///
+    /// Any callback previously present is dropped. The static callback is
+    /// called by reference when taken, and never dropped. See
+    /// [`StaticCallback`].
+    pub fn put_ref(&self, f: &'static StaticCallback<I, O>)
+    where
+        I: 'static,
+        O: 'static,
+    {
+        #[cfg(feature = "disabled")]
+        {
+            let _ = f;
+            return;
         }
+        #[allow(unreachable_code)]
+        let old_ptr = self.ptr.swap(f.as_raw(), Ordering::Release);
+        trace::put("CallbackCellArgs", self.addr(), None, type_name::<StaticCallback<I, O>>(), None, !old_ptr.is_null());
+        unsafe { raw::drop_raw::<I, O>(old_ptr) };
+    }
+
+    /// Atomically set the callback, moving it into a heap allocation made
+    /// ahead of time, rather than allocating.
+    ///
+    /// If the callback doesn't fit in the slot, returns both back, leaving
+    /// the cell unchanged. Otherwise, any callback previously present is
+    /// dropped.
+    #[cfg(feature = "alloc")]
+    pub fn put_prealloc<F: FnOnce(I) -> O + Send + 'static>(&self, slot: ArgsSlot<I, O>, f: F) -> Result<(), (ArgsSlot<I, O>, F)> {
+        #[cfg(feature = "disabled")]
+        {
+            drop(slot);
+            drop(f);
+            return Ok(());
+        }
+        #[allow(unreachable_code)]
+        let ptr = slot.init(f)?;
+        let old_ptr = self.ptr.swap(ptr, Ordering::Release);
+        trace::put("CallbackCellArgs", self.addr(), None, type_name::<F>(), None, !old_ptr.is_null());
+        unsafe { raw::drop_raw::<I, O>(old_ptr) };
+        Ok(())
+    }
+
+    /// Atomically set the callback, handing any callback previously present
+    /// to the sink rather than dropping it inline.
+    ///
+    /// See [`CallbackCell::put_defer_drop`][crate::CallbackCell::put_defer_drop].
+    #[cfg(feature = "alloc")]
+    pub fn put_defer_drop<F, S>(&self, f: F, sink: &S)
+    where
+        F: FnOnce(I) -> O + Send + 'static,
+        S: DropSink + ?Sized,
+    {
+        #[cfg(feature = "disabled")]
+        {
+            let _ = sink;
+            drop(f);
+            return;
+        }
+        #[allow(unreachable_code)]
+        let ptr = raw::alloc_raw(f);
+        let old_ptr = self.ptr.swap(ptr, Ordering::Release);
+        trace::put("CallbackCellArgs", self.addr(), None, type_name::<F>(), None, !old_ptr.is_null());
+        if !old_ptr.is_null() {
+            sink.accept(unsafe { DisplacedCallback::new::<I, O>(old_ptr) });
+        }
+    }
+
+    /// Atomically set the callback, returning a token for replacing it only
+    /// if it's still present.
+    ///
+    /// Like [`put`][Self::put], for use with
+    /// [`replace_if_current`][Self::replace_if_current].
+    #[cfg(feature = "alloc")]
+    pub fn put_tracked<F: FnOnce(I) -> O + Send + 'static>(&self, f: F) -> PutToken {
+        #[cfg(feature = "disabled")]
+        {
+            drop(f);
+            return PutToken(0);
+        }
+        #[allow(unreachable_code)]
+        let ptr = raw::alloc_raw(f);
+        let old_ptr = self.ptr.swap(ptr, Ordering::Release);
+        trace::put("CallbackCellArgs", self.addr(), None, type_name::<F>(), None, !old_ptr.is_null());
+        unsafe { raw::drop_raw::<I, O>(old_ptr) };
+        PutToken(ptr as usize)
+    }
+
+    /// Atomically replace the callback, if it's still the one the token was
+    /// returned for.
+    ///
+    /// If it was, the old callback is dropped, and this returns a token for
+    /// the new one. If it has since been taken, cleared, or replaced,
+    /// returns the new callback, without storing it, leaving any other
+    /// callback in place.
+    ///
+    /// The token is the callback's address, so once its callback is gone, a
+    /// later callback allocated at the same address matches it too. Where
+    /// that matters, `StampedCallbackCellArgs::replace_if_same`, behind the
+    /// `stamped` feature, tells them apart.
+    #[cfg(feature = "alloc")]
+    pub fn replace_if_current<F>(&self, token: &PutToken, f: F) -> Result<PutToken, F>
+    where
+        F: FnOnce(I) -> O + Send + 'static,
+    {
+        #[cfg(feature = "disabled")]
+        {
+            let _ = token;
+            return Err(f);
+        }
+        #[allow(unreachable_code)]
+        if self.ptr.load(Ordering::Relaxed) as usize != token.0 {
+            return Err(f);
+        }
+        let ptr = raw::alloc_raw(f);
+        match self.ptr.compare_exchange(token.0 as *mut u8, ptr, Ordering::AcqRel, Ordering::Relaxed) {
+            Ok(old_ptr) => {
+                trace::put("CallbackCellArgs", self.addr(), None, type_name::<F>(), None, true);
+                unsafe { raw::drop_raw::<I, O>(old_ptr) };
+                Ok(PutToken(ptr as usize))
+            }
+            Err(_) => Err(unsafe { raw::into_callback::<I, O, F>(ptr) }),
+        }
+    }
+
+    // address of the cell, for tracing.
+    fn addr(&self) -> *const () {
+        self as *const Self as *const ()
+    }
+
+    /// Whether a callback is currently present.
+    ///
+    /// This is only a snapshot: another thread may put or take a callback
+    /// immediately afterwards.
+    pub fn is_set(&self) -> bool {
+        !self.ptr.load(Ordering::Acquire).is_null()
+    }
+
+    /// The size, in bytes, of the heap allocation holding the callback, or 0
+    /// if no callback is present.
+    ///
+    /// See [`CallbackCell::allocated_bytes`][crate::CallbackCell::allocated_bytes].
+    pub fn allocated_bytes(&mut self) -> usize {
+        unsafe { raw::size_raw::<I, O>(self.ptr.load_mut()) }
     }
 
     /// Atomically take the callback then run it with the given input.
@@ -78,61 +523,547 @@ impl<I, O> CallbackCellArgs<I, O> {
     /// Returns the output if a callback was present. If a callback was not
     /// present, returns the original input.
     pub fn take_call(&self, input: I) -> Result<O, I> {
+        self.take_call_named(input, None)
+    }
+
+    /// Like [`take_call`][Self::take_call], but first loads the cell, and
+    /// returns the input without writing to the cell if it's empty.
+    ///
+    /// See [`CallbackCell::take_call_fast`][crate::CallbackCell::take_call_fast]
+    /// for when a concurrent put may be missed.
+    pub fn take_call_fast(&self, input: I) -> Result<O, I> {
+        if self.ptr.load(Ordering::Relaxed).is_null() {
+            trace::take_empty("CallbackCellArgs", self.addr(), None);
+            return Err(input);
+        }
+        self.take_call_named(input, None)
+    }
+
+    // take_call, tracing with the given name.
+    pub(crate) fn take_call_named(&self, input: I, name: Option<&'static str>) -> Result<O, I> {
         unsafe {
             // atomic take
-            let ptr = self.ptr.swap(0, Ordering::Acquire) as *mut u8;
+            let ptr = self.ptr.swap(ptr::null_mut(), Ordering::Acquire);
 
             // run it
             if !ptr.is_null() {
-                let fn_ptr = (ptr as *mut FnPtrType<I, O>).read();
-                let mut io_slot = IoSlot { input: ManuallyDrop::new(input) };
-                fn_ptr(Some(&mut io_slot), ptr);
-                Ok(ManuallyDrop::into_inner(io_slot.output))
+                Ok(trace::call("CallbackCellArgs", self.addr(), name, || raw::call_raw(ptr, input)))
+            } else {
+                trace::take_empty("CallbackCellArgs", self.addr(), name);
+                Err(input)
+            }
+        }
+    }
+
+    /// Like [`take_call`][Self::take_call], but leaves the callback's heap
+    /// allocation in the graveyard to be deallocated later, rather than
+    /// deallocating it on this thread.
+    ///
+    /// See [`Graveyard`].
+    #[cfg(feature = "alloc")]
+    pub fn take_call_defer_free(&self, input: I, graveyard: &Graveyard) -> Result<O, I> {
+        unsafe {
+            let ptr = self.ptr.swap(ptr::null_mut(), Ordering::Acquire);
+            if !ptr.is_null() {
+                Ok(trace::call("CallbackCellArgs", self.addr(), None, || {
+                    raw::call_raw_keep_alloc(ptr, input, |freed| graveyard.bury(freed))
+                }))
             } else {
+                trace::take_empty("CallbackCellArgs", self.addr(), None);
                 Err(input)
             }
         }
     }
+
+    /// Like [`take_call`][Self::take_call], but aborts the process if the
+    /// callback panics, rather than unwinding.
+    ///
+    /// See [`CallbackCell::take_call_abort_on_panic`][crate::CallbackCell::take_call_abort_on_panic].
+    #[cfg(feature = "std")]
+    pub fn take_call_abort_on_panic(&self, input: I) -> Result<O, I> {
+        self.take_call_abort_on_panic_named(input, None)
+    }
+
+    // take_call_abort_on_panic, tracing and aborting with the given name.
+    #[cfg(feature = "std")]
+    pub(crate) fn take_call_abort_on_panic_named(&self, input: I, name: Option<&'static str>) -> Result<O, I> {
+        crate::abort::call("CallbackCellArgs", name, || self.take_call_named(input, name))
+    }
 }
 
-impl<I, O> Drop for CallbackCellArgs<I, O> {
+impl<I, O> CallbackCellArgs<I, O> {
+    /// Atomically take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        self.clear_named(None)
+    }
+
+    // clear, tracing with the given name.
+    pub(crate) fn clear_named(&self, name: Option<&'static str>) -> bool {
+        unsafe {
+            let ptr = self.ptr.swap(ptr::null_mut(), Ordering::Acquire);
+            trace::clear("CallbackCellArgs", self.addr(), name, !ptr.is_null());
+            raw::drop_raw::<I, O>(ptr);
+            !ptr.is_null()
+        }
+    }
+
+    // take the callback, run `f` on its pointer, then put it back. returns
+    // `None` if no callback was present. the callback is owned while `f` runs,
+    // so it can't be freed by another thread, but a take on another thread
+    // meanwhile finds the cell empty. if a callback was put meanwhile, it's
+    // kept, and the peeked one is dropped, as if the put replaced it.
+    pub(crate) fn peek<R>(&self, f: impl FnOnce(*mut u8) -> R) -> Option<R> {
+        let ptr = self.ptr.swap(ptr::null_mut(), Ordering::Acquire);
+        if ptr.is_null() {
+            return None;
+        }
+        let result = f(ptr);
+        if self.ptr.compare_exchange(ptr::null_mut(), ptr, Ordering::Release, Ordering::Relaxed).is_err() {
+            unsafe { raw::drop_raw::<I, O>(ptr) };
+        }
+        Some(result)
+    }
+
+    // the label of the callback present, or "<unnamed>", if a callback is
+    // present, for `Debug`.
+    fn armed(&self) -> Option<&'static str> {
+        #[cfg(feature = "alloc")]
+        let label = self.peek(|ptr| unsafe { raw::label_raw::<I, O>(ptr) });
+        #[cfg(not(feature = "alloc"))]
+        let label = self.peek(|_| None);
+        label.map(|label| label.unwrap_or("<unnamed>"))
+    }
+
+    // `Debug`, with the given type name.
+    pub(crate) fn fmt_named(&self, f: &mut Formatter, kind: &str) -> fmt::Result {
+        match self.armed() {
+            Some(label) => write!(f, "{}(armed: {:?})", kind, label),
+            None => write!(f, "{}(NULL)", kind),
+        }
+    }
+
+    // `defmt::Format`, with the given type name.
+    #[cfg(feature = "defmt")]
+    pub(crate) fn format_named(&self, f: defmt::Formatter, kind: &str) {
+        match self.armed() {
+            Some(label) => defmt::write!(f, "{=str}(armed: {=str:?})", kind, label),
+            None => defmt::write!(f, "{=str}(NULL)", kind),
+        }
+    }
+
+    /// Atomically take the callback without running it yet, to decide
+    /// afterwards whether to run it or put it back.
+    ///
+    /// Returns `None` if no callback was present. See [`TakeGuard`].
+    #[cfg(feature = "alloc")]
+    pub fn begin_take(&self) -> Option<TakeGuard<'_, I, O>> {
+        let ptr = self.ptr.swap(ptr::null_mut(), Ordering::Acquire);
+        if ptr.is_null() {
+            trace::take_empty("CallbackCellArgs", self.addr(), None);
+            return None;
+        }
+        Some(TakeGuard {
+            cell: self,
+            callback: Some(unsafe { raw::Owned::from_erased(ptr) }),
+        })
+    }
+
+    /// Atomically take the callback without running it, as a raw pointer.
+    ///
+    /// Returns a null pointer if no callback was present. Otherwise, the
+    /// pointer is the crate's internal heap allocation for the callback, and
+    /// must be passed to exactly one of [`put_raw`][Self::put_raw],
+    /// [`run_raw`][Self::run_raw], or [`drop_raw_ptr`][Self::drop_raw_ptr],
+    /// exactly once, or else the callback is leaked.
+    pub fn take_raw(&self) -> RawCallbackPtr<I, O> {
+        RawCallbackPtr {
+            ptr: self.ptr.swap(ptr::null_mut(), Ordering::Acquire) as *mut c_void,
+            _p: PhantomData,
+        }
+    }
+
+    /// Atomically set the callback from a raw pointer, as returned by
+    /// [`take_raw`][Self::take_raw].
+    ///
+    /// Any callback previously present is dropped. If `ptr` is null, this
+    /// just clears the cell.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be null or a pointer returned by `take_raw`, which has not
+    /// yet been passed to any of these functions.
+    pub unsafe fn put_raw(&self, ptr: RawCallbackPtr<I, O>) {
+        let old_ptr = self.ptr.swap(ptr.ptr as *mut u8, Ordering::Release);
+        raw::drop_raw::<I, O>(old_ptr);
+    }
+
+    /// Run the callback behind a raw pointer, as returned by
+    /// [`take_raw`][Self::take_raw], with the given input.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a non-null pointer returned by `take_raw`, which has not
+    /// yet been passed to any of these functions.
+    pub unsafe fn run_raw(ptr: RawCallbackPtr<I, O>, input: I) -> O {
+        raw::call_raw(ptr.ptr as *mut u8, input)
+    }
+
+    /// Drop the callback behind a raw pointer, as returned by
+    /// [`take_raw`][Self::take_raw], without running it.
+    ///
+    /// Does nothing if `ptr` is null.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be null or a pointer returned by `take_raw`, which has not
+    /// yet been passed to any of these functions.
+    pub unsafe fn drop_raw_ptr(ptr: RawCallbackPtr<I, O>) {
+        raw::drop_raw::<I, O>(ptr.ptr as *mut u8);
+    }
+}
+
+/// A callback taken from a [`CallbackCellArgs`] with
+/// [`begin_take`][CallbackCellArgs::begin_take], to be either run or put
+/// back.
+///
+/// While the guard is held, the cell is empty, as after any take, so puts
+/// in the meantime succeed as usual. [`commit`][Self::commit] runs the
+/// callback. [`abort`][Self::abort] puts it back, but only if the cell is
+/// still empty: if another callback was put meanwhile, that one stays, as the
+/// later put, and the taken callback is handed back. Dropping the guard
+/// aborts, and drops the taken callback if another was put meanwhile, as if
+/// that put had replaced it.
+#[cfg(feature = "alloc")]
+#[must_use = "dropping the guard puts the callback back"]
+pub struct TakeGuard<'a, I, O> {
+    cell: &'a CallbackCellArgs<I, O>,
+    // only None once committed or aborted.
+    callback: Option<raw::Owned<I, O>>,
+}
+
+#[cfg(feature = "alloc")]
+impl<I, O> TakeGuard<'_, I, O> {
+    /// Run the callback with the given input.
+    pub fn commit(mut self, input: I) -> O {
+        let callback = self.callback.take().unwrap();
+        trace::call("CallbackCellArgs", self.cell.addr(), None, || callback.call(input))
+    }
+
+    /// Atomically put the callback back, if the cell is still empty.
+    ///
+    /// If another callback was put meanwhile, returns the taken callback,
+    /// to be run or dropped, leaving the other in place.
+    pub fn abort(mut self) -> Result<(), RawCallbackArgs<I, O>> {
+        self.reinstate()
+    }
+
+    // put the callback back if the cell is empty, otherwise return it.
+    fn reinstate(&mut self) -> Result<(), RawCallbackArgs<I, O>> {
+        let ptr = self.callback.take().unwrap().into_raw().as_ptr() as *mut u8;
+        match self.cell.ptr.compare_exchange(ptr::null_mut(), ptr, Ordering::Release, Ordering::Relaxed) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(unsafe { raw::Owned::from_erased(ptr) }),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<I, O> Drop for TakeGuard<'_, I, O> {
     fn drop(&mut self) {
+        if self.callback.is_some() {
+            drop(self.reinstate());
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<I, O> Debug for TakeGuard<'_, I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("TakeGuard")
+    }
+}
+
+/// Raw pointer to a callback taken from a [`CallbackCellArgs<I, O>`] with
+/// [`take_raw`][CallbackCellArgs::take_raw].
+///
+/// This is a thin typed wrapper around a `*mut c_void`, so that the `(I, O)`
+/// signature of the callback can't be mixed up. It has no destructor: it must
+/// be passed back into one of `CallbackCellArgs`'s raw pointer functions, or
+/// else the callback is leaked.
+#[repr(transparent)]
+pub struct RawCallbackPtr<I, O> {
+    ptr: *mut c_void,
+    _p: PhantomData<fn(I) -> O>,
+}
+
+impl<I, O> RawCallbackPtr<I, O> {
+    /// Get the untyped pointer, for carrying through a `*mut c_void`.
+    pub fn into_ptr(self) -> *mut c_void {
+        self.ptr
+    }
+
+    /// Re-type an untyped pointer from [`into_ptr`][Self::into_ptr].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be null or have come from `into_ptr` on a
+    /// `RawCallbackPtr<I, O>` with the same `I` and `O`.
+    pub unsafe fn from_ptr(ptr: *mut c_void) -> Self {
+        RawCallbackPtr { ptr, _p: PhantomData }
+    }
+
+    /// Whether this is a null pointer, meaning no callback was present.
+    pub fn is_null(&self) -> bool {
+        self.ptr.is_null()
+    }
+}
+
+impl<I, O> Debug for RawCallbackPtr<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("RawCallbackPtr").field(&self.ptr).finish()
+    }
+}
+
+/// Identifies one put into a [`CallbackCellArgs`], by
+/// [`put_tracked`][CallbackCellArgs::put_tracked], for replacing that
+/// callback if it's still present.
+#[cfg(feature = "alloc")]
+#[derive(PartialEq, Eq, Hash, Debug)]
+pub struct PutToken(usize);
+
+impl<I: From<*mut c_void>> CallbackCellArgs<I, ()> {
+    /// Get a C-style callback which calls [`take_call`][Self::take_call] on
+    /// this cell, in the form of a function pointer and context pointer, as
+    /// in `void (*cb)(void *ctx, void *data)` and `void *ctx`.
+    ///
+    /// The `data` pointer is converted into the input with `I::from`. If no
+    /// callback is present, the input is dropped.
+    ///
+    /// The function pointer is only valid to call with the returned context
+    /// pointer. Since `self` is `'static`, it may be called any number of
+    /// times, from any thread.
+    ///
+    /// If the callback panics, the process is aborted, since unwinding out of
+    /// an `extern "C"` function is not possible.
+    pub fn as_c_trampoline(
+        &'static self,
+    ) -> (unsafe extern "C" fn(*mut c_void, *mut c_void), *mut c_void) {
+        unsafe extern "C" fn trampoline<I: From<*mut c_void>>(ctx: *mut c_void, data: *mut c_void) {
+            let _ = (*(ctx as *const CallbackCellArgs<I, ()>)).take_call(I::from(data));
+        }
+
+        (trampoline::<I>, self as *const CallbackCellArgs<I, ()> as *mut c_void)
+    }
+}
+
+#[cfg(all(feature = "alloc", target_has_atomic = "ptr"))]
+impl<I: 'static, O: 'static> CallbackCellArgs<I, O> {
+    /// Atomically set the callback to call `f` with `receiver` and the input.
+    ///
+    /// See [`CallbackCell::put_method`][crate::CallbackCell::put_method].
+    pub fn put_method<T: Send + Sync + 'static>(&self, receiver: Arc<T>, f: fn(Arc<T>, I) -> O) {
+        #[cfg(feature = "disabled")]
+        {
+            let _ = f;
+            drop(receiver);
+            return;
+        }
+        #[allow(unreachable_code)]
+        let ptr = raw::alloc_raw_method(receiver, f);
+        let old_ptr = self.ptr.swap(ptr, Ordering::Release);
+        trace::put("CallbackCellArgs", self.addr(), None, type_name::<fn(Arc<T>, I) -> O>(), None, !old_ptr.is_null());
+        unsafe { raw::drop_raw::<I, O>(old_ptr) };
+    }
+
+    /// Atomically set the callback to a shared handler, without allocating.
+    ///
+    /// See [`CallbackCell::put_arc`][crate::CallbackCell::put_arc].
+    pub fn put_arc<F: ?Sized>(&self, f: Arc<SharedCallback<I, O, F>>) {
+        #[cfg(feature = "disabled")]
+        {
+            drop(f);
+            return;
+        }
+        #[allow(unreachable_code)]
+        let old_ptr = self.ptr.swap(SharedCallback::into_raw(f), Ordering::Release);
+        trace::put("CallbackCellArgs", self.addr(), None, type_name::<SharedCallback<I, O, F>>(), None, !old_ptr.is_null());
+        unsafe { raw::drop_raw::<I, O>(old_ptr) };
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<I: 'static, O: 'static> CallbackCellArgs<I, O> {
+    /// Atomically replace the callback with one which runs `wrap`, given the
+    /// original callback and the input.
+    ///
+    /// For middleware, such as timing or entering a context around the
+    /// callback: `wrap` decides whether and when to run the original, which
+    /// runs at most once, and is dropped without running if `wrap` doesn't
+    /// run it. Makes one heap allocation, plus one to box the original when
+    /// the wrapper runs.
+    ///
+    /// Returns true if a callback was present to wrap. If not, `wrap` is
+    /// dropped and the cell left empty. The callback is taken, then the
+    /// wrapper put only if the cell is still empty, so this acts as a take
+    /// followed by a put: a [`take_call`][Self::take_call] in between finds
+    /// the cell empty, and if another callback is put in between, the
+    /// wrapper is dropped without running, as if replaced by that put.
+    pub fn put_around<W>(&self, wrap: W) -> bool
+    where
+        W: FnOnce(Box<dyn FnOnce(I) -> O + Send>, I) -> O + Send + 'static,
+    {
+        let ptr = self.ptr.swap(ptr::null_mut(), Ordering::Acquire);
+        if ptr.is_null() {
+            return false;
+        }
+        let inner = unsafe { raw::Owned::<I, O>::from_erased(ptr) };
+        let around = raw::alloc_raw(move |input| wrap(Box::new(move |input| inner.call(input)), input));
+        let put = self.ptr
+            .compare_exchange(ptr::null_mut(), around, Ordering::Release, Ordering::Relaxed)
+            .is_ok();
+        trace::put("CallbackCellArgs", self.addr(), None, type_name::<W>(), None, false);
+        if !put {
+            unsafe { raw::drop_raw::<I, O>(around) };
+        }
+        true
+    }
+}
+
+#[cfg(all(feature = "alloc", target_has_atomic = "ptr"))]
+impl<I, O: Clone + Send + 'static> CallbackCellArgs<I, O> {
+    /// Atomically set the callback, and get a receipt which resolves with
+    /// its output once it runs.
+    ///
+    /// When the callback runs, a clone of its output is sent to the receipt,
+    /// and the output itself is returned from `take_call` as usual. If the
+    /// callback is instead dropped without running, the receipt resolves with
+    /// [`Discarded`][crate::Discarded].
+    ///
+    /// Makes two heap allocations: one for the callback, and one for the
+    /// state shared with the receipt.
+    pub fn put_with_receipt<F>(&self, f: F) -> PutReceipt<O>
+    where
+        F: FnOnce(I) -> O + Send + 'static,
+    {
+        let (sender, receipt) = receipt::receipt();
+        self.put(move |input| {
+            let output = f(input);
+            sender.send(output.clone());
+            output
+        });
+        receipt
+    }
+}
+
+impl<I, O> CallbackCellArgs<I, O> {
+    // drop any callback present, as when dropping the cell, tracing with the
+    // given name.
+    pub(crate) fn drop_named(&mut self, name: Option<&'static str>) {
+        let ptr = self.ptr.load_mut();
+        if !ptr.is_null() {
+            trace::drop_pending("CallbackCellArgs", self.addr(), name);
+        }
         unsafe {
-            drop_raw::<I, O>(*self.ptr.get_mut() as *mut u8);
+            raw::drop_raw::<I, O>(ptr);
         }
+        self.ptr = CellPtr::new(ptr::null_mut());
+    }
+}
+
+impl<O> CallbackCellArgs<(), O> {
+    /// Atomically take the callback then run it.
+    ///
+    /// Returns the output if a callback was present.
+    pub fn take_call_unit(&self) -> Option<O> {
+        self.take_call(()).ok()
+    }
+}
+
+impl CallbackCellArgs<(), ()> {
+    /// View this cell as a [`CallbackCell`].
+    ///
+    /// Both refer to the same callback.
+    pub fn as_cell(&self) -> &CallbackCell {
+        // safety: see the internals comment.
+        unsafe { &*(self as *const Self as *const CallbackCell) }
+    }
+}
+
+impl<I, O> CallbackCellArgs<I, O> {
+    // take the callback pointer out of the cell, leaving it empty.
+    pub(crate) fn into_ptr(mut self) -> *mut u8 {
+        let ptr = self.ptr.load_mut();
+        self.ptr = CellPtr::new(ptr::null_mut());
+        ptr
     }
 }
 
-union IoSlot<I, O> {
-    input: ManuallyDrop<I>,
-    output: ManuallyDrop<O>,
+// inherent methods for cells whose input is a tuple, taking the tuple's
+// elements as separate arguments. the callback is adapted to take the tuple
+// by `ApplyOnce`, within the same allocation. there's an impl per arity, so
+// the method is only found once the cell's input type is known.
+macro_rules! impl_unpacked {
+    ($($arg:ident),+) => {
+        impl<$($arg,)+ O> CallbackCellArgs<($($arg,)+), O> {
+            /// Atomically set a callback which takes the input tuple's
+            /// elements as separate arguments.
+            ///
+            /// Makes only one heap allocation, like [`put`][Self::put]. The
+            /// cell's input type must already be known, since there's a
+            /// method for each arity, up to 8.
+            #[cfg(feature = "alloc")]
+            pub fn put_unpacked<F>(&self, f: F)
+            where
+                F: FnOnce($($arg),+) -> O + Send + 'static,
+            {
+                self.put(move |args: ($($arg,)+)| ApplyOnce::apply_once(f, args))
+            }
+
+            /// Atomically take the callback then run it with the input
+            /// tuple's elements given as separate arguments.
+            ///
+            /// Returns the output if a callback was present. If a callback
+            /// was not present, returns the original arguments, as a tuple.
+            #[allow(non_snake_case, clippy::too_many_arguments)]
+            pub fn take_call_unpacked(&self, $($arg: $arg),+) -> Result<O, ($($arg,)+)> {
+                self.take_call(($($arg,)+))
+            }
+        }
+    };
 }
 
-type FnPtrType<I, O> = unsafe fn(Option<&mut IoSlot<I, O>>, *mut u8);
+impl_unpacked!(A);
+impl_unpacked!(A, B);
+impl_unpacked!(A, B, C);
+impl_unpacked!(A, B, C, D);
+impl_unpacked!(A, B, C, D, E);
+impl_unpacked!(A, B, C, D, E, G);
+impl_unpacked!(A, B, C, D, E, G, H);
+impl_unpacked!(A, B, C, D, E, G, H, J);
 
-// implementation for the function pointer for a given callback type F.
-unsafe fn fn_ptr_impl<I, O, F>(run: Option<&mut IoSlot<I, O>>, ptr: *mut u8)
-where
-    F: FnOnce(I) -> O + Send + 'static,
-{
-    // extract callback value from heap allocation and free heap allocation
-    let (layout, callback_offset) = Layout::new::<FnPtrType<I, O>>()
-        .extend(Layout::new::<F>()).unwrap();
-    let f = (ptr.add(callback_offset) as *mut F).read();
-    dealloc(ptr, layout);
+/// Moves any callback across, without running it.
+impl From<CallbackCell> for CallbackCellArgs<(), ()> {
+    fn from(cell: CallbackCell) -> Self {
+        CallbackCellArgs {
+            ptr: CellPtr::new(cell.into_ptr()),
+            _p: PhantomData,
+        }
+    }
+}
 
-    // run
-    if let Some(io_slot) = run {
-        io_slot.output = ManuallyDrop::new(f(ManuallyDrop::take(&mut io_slot.input)));
+impl<I, O> Drop for CallbackCellArgs<I, O> {
+    fn drop(&mut self) {
+        self.drop_named(None);
     }
 }
 
-// drop the pointed to data, including freeing the heap allocation, without running the callback,
-// if the pointer is non-null.
-unsafe fn drop_raw<I, O>(ptr: *mut u8) {
-    if !ptr.is_null() {
-        let fn_ptr = (ptr as *mut FnPtrType<I, O>).read();
-        fn_ptr(None, ptr);
+/// Same as [`CallbackCellArgs::new_with`].
+#[cfg(feature = "alloc")]
+impl<I, O, F: FnOnce(I) -> O + Send + 'static> From<F> for CallbackCellArgs<I, O> {
+    fn from(f: F) -> Self {
+        Self::new_with(f)
     }
 }
 
@@ -143,11 +1074,18 @@ impl<I, O> Default for CallbackCellArgs<I, O> {
 }
 
 impl<I, O> Debug for CallbackCellArgs<I, O> {
+    /// Shows the label of the callback present, if it was put with
+    /// [`put_named`][Self::put_named], or `"<unnamed>"` otherwise. The
+    /// callback is taken while its label is read, then put back, so a take
+    /// on another thread meanwhile finds the cell empty.
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        if (self.ptr.load(Ordering::Relaxed) as *const ()).is_null() {
-            f.write_str("CallbackCellArgs(NULL)")
-        } else {
-            f.write_str("CallbackCellArgs(NOT NULL)")
-        }
+        self.fmt_named(f, "CallbackCellArgs")
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<I, O> defmt::Format for CallbackCellArgs<I, O> {
+    fn format(&self, f: defmt::Formatter) {
+        self.format_named(f, "CallbackCellArgs")
     }
 }
@@ -0,0 +1,179 @@
+
+use crate::{
+    CallbackCellArgs,
+    DropSink,
+    Graveyard,
+};
+use core::fmt::{self, Formatter, Debug};
+
+// internals
+// ---------
+//
+// a `CallbackCellArgs<I, ()>`, `repr(transparent)`, so that the two can be
+// viewed as each other. with `O = ()` the output half of the `raw` module's
+// input/output union is zero-sized, so the union is just the input, and
+// writing the output compiles to nothing. so this adds no code of its own:
+// every method is the args cell's, instantiated with `O = ()`, and those
+// instantiations are shared with any `CallbackCellArgs<I, ()>` in the same
+// program, rather than duplicated by a parallel implementation.
+
+/// Like an `Atomic<Option<Box<dyn FnOnce(I) + Send + 'static>>>`.
+///
+/// The same as a [`CallbackCellArgs<I, ()>`][crate::CallbackCellArgs], for
+/// the common case of callbacks with no output, with the same semantics, the
+/// same layout, and the same generated code. Either can be viewed as the
+/// other with [`as_args`][Self::as_args] and [`from_args`][Self::from_args].
+#[repr(transparent)]
+pub struct CallbackCellInput<I>(CallbackCellArgs<I, ()>);
+
+impl<I> CallbackCellInput<I> {
+    const_fn! {
+        /// Construct with no callback.
+        ///
+        /// This is a `const fn`, so cells can be placed in statics.
+        pub fn new() -> Self {
+            CallbackCellInput(CallbackCellArgs::new())
+        }
+    }
+
+    /// Construct with the given callback already present.
+    ///
+    /// See [`CallbackCellArgs::new_with`].
+    pub fn new_with<F: FnOnce(I) + Send + 'static>(f: F) -> Self {
+        CallbackCellInput(CallbackCellArgs::new_with(f))
+    }
+
+    /// Atomically set the callback.
+    ///
+    /// See [`CallbackCellArgs::put`].
+    pub fn put<F: FnOnce(I) + Send + 'static>(&self, f: F) {
+        self.0.put(f)
+    }
+
+    /// Atomically set the callback, handing any callback previously present
+    /// to the sink rather than dropping it inline.
+    ///
+    /// See [`CallbackCellArgs::put_defer_drop`].
+    pub fn put_defer_drop<F, S>(&self, f: F, sink: &S)
+    where
+        F: FnOnce(I) + Send + 'static,
+        S: DropSink + ?Sized,
+    {
+        self.0.put_defer_drop(f, sink)
+    }
+
+    /// Whether a callback is currently present.
+    ///
+    /// See [`CallbackCellArgs::is_set`].
+    pub fn is_set(&self) -> bool {
+        self.0.is_set()
+    }
+
+    /// The size, in bytes, of the heap allocation holding the callback, or 0
+    /// if no callback is present.
+    ///
+    /// See [`CallbackCellArgs::allocated_bytes`].
+    pub fn allocated_bytes(&mut self) -> usize {
+        self.0.allocated_bytes()
+    }
+
+    /// Atomically take the callback then run it with the given input.
+    ///
+    /// If a callback was not present, returns the original input.
+    pub fn take_call(&self, input: I) -> Result<(), I> {
+        self.0.take_call(input)
+    }
+
+    /// Like [`take_call`][Self::take_call], but leaves the callback's heap
+    /// allocation in the graveyard to be deallocated later.
+    ///
+    /// See [`CallbackCellArgs::take_call_defer_free`].
+    pub fn take_call_defer_free(&self, input: I, graveyard: &Graveyard) -> Result<(), I> {
+        self.0.take_call_defer_free(input, graveyard)
+    }
+
+    /// Like [`take_call`][Self::take_call], but aborts the process if the
+    /// callback panics.
+    ///
+    /// See [`CallbackCellArgs::take_call_abort_on_panic`].
+    #[cfg(feature = "std")]
+    pub fn take_call_abort_on_panic(&self, input: I) -> Result<(), I> {
+        self.0.take_call_abort_on_panic(input)
+    }
+
+    /// Atomically take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        self.0.clear()
+    }
+
+    /// View this cell as a `CallbackCellArgs<I, ()>`.
+    ///
+    /// Both refer to the same callback.
+    pub fn as_args(&self) -> &CallbackCellArgs<I, ()> {
+        &self.0
+    }
+
+    /// View a `CallbackCellArgs<I, ()>` as a `CallbackCellInput<I>`.
+    ///
+    /// Both refer to the same callback.
+    pub fn from_args(cell: &CallbackCellArgs<I, ()>) -> &Self {
+        // safety: see the internals comment.
+        unsafe { &*(cell as *const CallbackCellArgs<I, ()> as *const Self) }
+    }
+
+    /// Convert into a `CallbackCellArgs<I, ()>`, moving any callback across.
+    pub fn into_args(self) -> CallbackCellArgs<I, ()> {
+        self.0
+    }
+}
+
+/// Moves any callback across, without running it.
+impl<I> From<CallbackCellArgs<I, ()>> for CallbackCellInput<I> {
+    fn from(cell: CallbackCellArgs<I, ()>) -> Self {
+        CallbackCellInput(cell)
+    }
+}
+
+/// Moves any callback across, without running it.
+impl<I> From<CallbackCellInput<I>> for CallbackCellArgs<I, ()> {
+    fn from(cell: CallbackCellInput<I>) -> Self {
+        cell.0
+    }
+}
+
+/// Same as [`CallbackCellInput::new_with`].
+impl<I, F: FnOnce(I) + Send + 'static> From<F> for CallbackCellInput<I> {
+    fn from(f: F) -> Self {
+        Self::new_with(f)
+    }
+}
+
+impl<I> Default for CallbackCellInput<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I> Debug for CallbackCellInput<I> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_set() {
+            f.write_str("CallbackCellInput(NOT NULL)")
+        } else {
+            f.write_str("CallbackCellInput(NULL)")
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+#[allow(clippy::if_same_then_else)]
+impl<I> defmt::Format for CallbackCellInput<I> {
+    fn format(&self, f: defmt::Formatter) {
+        if self.is_set() {
+            defmt::write!(f, "CallbackCellInput(NOT NULL)")
+        } else {
+            defmt::write!(f, "CallbackCellInput(NULL)")
+        }
+    }
+}
@@ -0,0 +1,179 @@
+
+use crate::{
+    CallbackCellArgs,
+    DropSink,
+    Graveyard,
+};
+use core::fmt::{self, Formatter, Debug};
+
+// internals
+// ---------
+//
+// a `CallbackCellArgs<(), O>`, `repr(transparent)`, so that the two can be
+// viewed as each other. as with `CallbackCellInput`, the unused half of the
+// `raw` module's input/output union is zero-sized, here the input, so this
+// adds no code of its own, and shares its instantiations with any
+// `CallbackCellArgs<(), O>`.
+
+/// Like an `Atomic<Option<Box<dyn FnOnce() -> O + Send + 'static>>>`.
+///
+/// The same as a [`CallbackCellArgs<(), O>`][crate::CallbackCellArgs], for
+/// callbacks which take no input but produce an output, such as deferred
+/// computations which a consumer may or may not collect. `take_call` takes
+/// no input, and returns the output if a callback was present. Either cell
+/// can be viewed as the other with [`as_args`][Self::as_args] and
+/// [`from_args`][Self::from_args].
+#[repr(transparent)]
+pub struct CallbackCellOutput<O>(CallbackCellArgs<(), O>);
+
+impl<O> CallbackCellOutput<O> {
+    const_fn! {
+        /// Construct with no callback.
+        ///
+        /// This is a `const fn`, so cells can be placed in statics.
+        pub fn new() -> Self {
+            CallbackCellOutput(CallbackCellArgs::new())
+        }
+    }
+
+    /// Construct with the given callback already present.
+    ///
+    /// See [`CallbackCellArgs::new_with`].
+    pub fn new_with<F: FnOnce() -> O + Send + 'static>(f: F) -> Self {
+        CallbackCellOutput(CallbackCellArgs::new_with(move |()| f()))
+    }
+
+    /// Atomically set the callback.
+    ///
+    /// See [`CallbackCellArgs::put`].
+    pub fn put<F: FnOnce() -> O + Send + 'static>(&self, f: F) {
+        self.0.put(move |()| f())
+    }
+
+    /// Atomically set the callback, handing any callback previously present
+    /// to the sink rather than dropping it inline.
+    ///
+    /// See [`CallbackCellArgs::put_defer_drop`].
+    pub fn put_defer_drop<F, S>(&self, f: F, sink: &S)
+    where
+        F: FnOnce() -> O + Send + 'static,
+        S: DropSink + ?Sized,
+    {
+        self.0.put_defer_drop(move |()| f(), sink)
+    }
+
+    /// Whether a callback is currently present.
+    ///
+    /// See [`CallbackCellArgs::is_set`].
+    pub fn is_set(&self) -> bool {
+        self.0.is_set()
+    }
+
+    /// The size, in bytes, of the heap allocation holding the callback, or 0
+    /// if no callback is present.
+    ///
+    /// See [`CallbackCellArgs::allocated_bytes`].
+    pub fn allocated_bytes(&mut self) -> usize {
+        self.0.allocated_bytes()
+    }
+
+    /// Atomically take the callback then run it.
+    ///
+    /// Returns the output if a callback was present.
+    pub fn take_call(&self) -> Option<O> {
+        self.0.take_call(()).ok()
+    }
+
+    /// Like [`take_call`][Self::take_call], but leaves the callback's heap
+    /// allocation in the graveyard to be deallocated later.
+    ///
+    /// See [`CallbackCellArgs::take_call_defer_free`].
+    pub fn take_call_defer_free(&self, graveyard: &Graveyard) -> Option<O> {
+        self.0.take_call_defer_free((), graveyard).ok()
+    }
+
+    /// Like [`take_call`][Self::take_call], but aborts the process if the
+    /// callback panics.
+    ///
+    /// See [`CallbackCellArgs::take_call_abort_on_panic`].
+    #[cfg(feature = "std")]
+    pub fn take_call_abort_on_panic(&self) -> Option<O> {
+        self.0.take_call_abort_on_panic(()).ok()
+    }
+
+    /// Atomically take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        self.0.clear()
+    }
+
+    /// View this cell as a `CallbackCellArgs<(), O>`.
+    ///
+    /// Both refer to the same callback.
+    pub fn as_args(&self) -> &CallbackCellArgs<(), O> {
+        &self.0
+    }
+
+    /// View a `CallbackCellArgs<(), O>` as a `CallbackCellOutput<O>`.
+    ///
+    /// Both refer to the same callback.
+    pub fn from_args(cell: &CallbackCellArgs<(), O>) -> &Self {
+        // safety: see the internals comment.
+        unsafe { &*(cell as *const CallbackCellArgs<(), O> as *const Self) }
+    }
+
+    /// Convert into a `CallbackCellArgs<(), O>`, moving any callback across.
+    pub fn into_args(self) -> CallbackCellArgs<(), O> {
+        self.0
+    }
+}
+
+/// Moves any callback across, without running it.
+impl<O> From<CallbackCellArgs<(), O>> for CallbackCellOutput<O> {
+    fn from(cell: CallbackCellArgs<(), O>) -> Self {
+        CallbackCellOutput(cell)
+    }
+}
+
+/// Moves any callback across, without running it.
+impl<O> From<CallbackCellOutput<O>> for CallbackCellArgs<(), O> {
+    fn from(cell: CallbackCellOutput<O>) -> Self {
+        cell.0
+    }
+}
+
+/// Same as [`CallbackCellOutput::new_with`].
+impl<O, F: FnOnce() -> O + Send + 'static> From<F> for CallbackCellOutput<O> {
+    fn from(f: F) -> Self {
+        Self::new_with(f)
+    }
+}
+
+impl<O> Default for CallbackCellOutput<O> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<O> Debug for CallbackCellOutput<O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_set() {
+            f.write_str("CallbackCellOutput(NOT NULL)")
+        } else {
+            f.write_str("CallbackCellOutput(NULL)")
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+#[allow(clippy::if_same_then_else)]
+impl<O> defmt::Format for CallbackCellOutput<O> {
+    fn format(&self, f: defmt::Formatter) {
+        if self.is_set() {
+            defmt::write!(f, "CallbackCellOutput(NOT NULL)")
+        } else {
+            defmt::write!(f, "CallbackCellOutput(NULL)")
+        }
+    }
+}
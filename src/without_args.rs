@@ -1,112 +1,657 @@
 
-use std::{
-    sync::atomic::{
-        AtomicUsize,
-        Ordering,
-    },
-    alloc::{
-        Layout,
-        alloc,
-        dealloc,
-        handle_alloc_error,
-    },
+use crate::{
+    CallbackCellArgs,
+    sync::{CellPtr, LoadMut},
+    raw,
+    trace,
+    StaticCallback,
+};
+#[cfg(feature = "alloc")]
+use crate::{
+    with_args::OnDiscard,
+    Spawn,
+    DropSink,
+    DisplacedCallback,
+    Graveyard,
+};
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+use core::{
+    sync::atomic::Ordering,
+    ptr,
+    ffi::c_void,
+    any::type_name,
     fmt::{self, Formatter, Debug},
 };
+#[cfg(all(feature = "alloc", target_has_atomic = "ptr"))]
+use alloc::{
+    sync::Arc,
+    task::Wake,
+};
+#[cfg(all(feature = "alloc", target_has_atomic = "ptr"))]
+use core::task::Waker;
+#[cfg(all(feature = "alloc", target_has_atomic = "ptr"))]
+use crate::SharedCallback;
 
 // internals
 // ---------
 //
-// the inner atomic usize is a nullable pointer to a heap allocation.
-// the pointed-to data consists of:
-//
-// - an `unsafe fn(bool, *mut u8)` which, when called with the pointer:
-//
-//   - if the bool is true, runs the callback (dropping it)
-//   - if the bool is false, drops the callback without running it
-//   - deallocates the heap allocation
-// - padding
-// - the `F: FnOnce() + Send + 'static` value
+// the inner atomic pointer is a nullable pointer to an erased callback, as
+// described in the `raw` module, with `I = ()` and `O = ()`.
 
 /// Like an `Atomic<Option<Box<dyn FnOnce() + Send + 'static>>>`.
 ///
-/// See [`CallbackCellArgs`][crate::CallbackCellArgs] for a version with args.
-pub struct CallbackCell(AtomicUsize);
+/// See [`CallbackCellArgs`][crate::CallbackCellArgs] for a version with args,
+/// and [`CallbackCellOutput`][crate::CallbackCellOutput] for callbacks which
+/// take no input but return an output.
+///
+/// Without the `alloc` feature, only the methods which never allocate are
+/// available, so static callbacks, put with [`put_ref`][Self::put_ref], are
+/// the only ones a cell can hold.
+///
+/// # Layout
+///
+/// The cell is `repr(transparent)` over an atomic pointer, so it has the same
+/// size and alignment as an `AtomicPtr<()>`, and an empty cell is all zero
+/// bytes. That is part of the API and will stay so, so cells can be embedded
+/// in `repr(C)` structs, and constructed in place with
+/// [`init_at`][Self::init_at]. The exception is with the `disabled` feature,
+/// under which the cell is zero-sized, and never holds a callback.
+#[repr(transparent)]
+pub struct CallbackCell(CellPtr);
+
+#[cfg(all(target_has_atomic = "ptr", not(loom), not(feature = "disabled")))]
+const _: () = {
+    use core::{mem, sync::atomic};
+    assert!(mem::size_of::<CallbackCell>() == mem::size_of::<atomic::AtomicPtr<()>>());
+    assert!(mem::align_of::<CallbackCell>() == mem::align_of::<atomic::AtomicPtr<()>>());
+};
 
 impl CallbackCell {
-    /// Construct with no callback.
-    pub fn new() -> Self {
-        CallbackCell(AtomicUsize::new(0))
+    const_fn! {
+        /// Construct with no callback.
+        ///
+        /// This is a `const fn`, so cells can be placed in statics. That is part of
+        /// the API and will stay so.
+        pub fn new() -> Self {
+            CallbackCell(CellPtr::new(ptr::null_mut()))
+        }
+    }
+
+    /// Construct with the given callback already present.
+    ///
+    /// Makes only one heap allocation, and leaves the cell in the same state
+    /// as [`new`][Self::new] followed by [`put`][Self::put].
+    #[cfg(feature = "alloc")]
+    pub fn new_with<F: FnOnce() + Send + 'static>(f: F) -> Self {
+        #[cfg(feature = "disabled")]
+        {
+            drop(f);
+            Self::new()
+        }
+        #[cfg(not(feature = "disabled"))]
+        CallbackCell(CellPtr::new(raw::alloc_raw(move |()| f())))
+    }
+
+    /// Construct with no callback, gated by the given gate, so that takes do
+    /// nothing while it's disabled.
+    ///
+    /// The same as [`GatedCallbackCell::new`][crate::GatedCallbackCell::new].
+    /// A `CallbackCell` itself is only a pointer, with no room for the gate,
+    /// so this constructs a `GatedCallbackCell`, which holds both.
+    #[cfg(all(feature = "alloc", target_has_atomic = "ptr"))]
+    pub fn new_gated(gate: crate::Gate) -> crate::GatedCallbackCell {
+        crate::GatedCallbackCell::new(gate)
+    }
+
+    /// Construct an empty cell in place, in memory owned by the caller,
+    /// returning a reference to it.
+    ///
+    /// Whatever `ptr` pointed to is overwritten without being dropped.
+    /// Writing zero bytes to the memory does the same.
+    ///
+    /// If the memory is released without dropping the cell, such as by
+    /// unmapping an arena, every operation is still allowed on the cell
+    /// until then, but a callback left in the cell is leaked: neither it nor
+    /// its heap allocation is dropped. [`clear`][Self::clear] the cell, or
+    /// take and run the callback, before releasing the memory to avoid that.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for writes and aligned for a `CallbackCell`, and
+    /// must stay valid, and not be written to other than through the cell,
+    /// for `'a`.
+    pub unsafe fn init_at<'a>(ptr: *mut Self) -> &'a Self {
+        ptr.write(Self::new());
+        &*ptr
+    }
+
+    /// A reference to a cell in memory owned by the caller.
+    ///
+    /// See [`init_at`][Self::init_at] for cells which are never dropped.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a live cell, such as one constructed with
+    /// `init_at`, or zeroed memory aligned for a `CallbackCell`, and must
+    /// stay valid, and not be written to other than through the cell, for
+    /// `'a`.
+    pub unsafe fn from_ptr<'a>(ptr: *const Self) -> &'a Self {
+        &*ptr
     }
 
     /// Atomically set the callback.
+    #[cfg(feature = "alloc")]
     pub fn put<F: FnOnce() + Send + 'static>(&self, f: F) {
+        self.put_replacing(f, None);
+    }
+
+    /// Atomically set the callback, along with a cleanup to run if the
+    /// callback is dropped without running.
+    ///
+    /// Makes only one heap allocation, holding both. `on_discard` runs if,
+    /// and only if, `f` is dropped without being called: when it's replaced
+    /// by a later put, cleared, or dropped with the cell, or by anything
+    /// else which drops an untaken callback. Once `f` is called, `on_discard`
+    /// is dropped without running, even if `f` panics. So exactly one of the
+    /// two runs, which lets whoever waits on `f` learn that it never will.
+    ///
+    /// `on_discard` runs on whichever thread drops `f`, and shouldn't panic,
+    /// since it may run while the cell drops the callback.
+    #[cfg(feature = "alloc")]
+    pub fn put_with_cleanup<F, G>(&self, f: F, on_discard: G)
+    where
+        F: FnOnce() + Send + 'static,
+        G: FnOnce() + Send + 'static,
+    {
+        let mut guard = OnDiscard(Some(on_discard));
+        self.put(move || {
+            guard.disarm();
+            f()
+        })
+    }
+
+    /// Atomically set the callback, along with a copy of some bytes, which
+    /// it's given mutable access to when it runs.
+    ///
+    /// Makes only one heap allocation, holding both the callback and the
+    /// bytes, rather than one for the callback and another for a buffer it
+    /// captures. The bytes are freed with the callback, after it returns. Any
+    /// callback previously present is dropped.
+    #[cfg(feature = "alloc")]
+    pub fn put_with_bytes<F: FnOnce(&mut [u8]) + Send + 'static>(&self, data: &[u8], f: F) {
+        #[cfg(feature = "disabled")]
+        {
+            let _ = data;
+            drop(f);
+            return;
+        }
+        #[allow(unreachable_code)]
+        let ptr = raw::alloc_raw_with_bytes(data, move |bytes: &mut [u8], ()| f(bytes));
+        let old_ptr = self.0.swap(ptr, Ordering::Release);
+        trace::put("CallbackCell", self.addr(), None, type_name::<F>(), None, !old_ptr.is_null());
+        unsafe { raw::drop_raw::<(), ()>(old_ptr) };
+    }
+
+    /// Atomically set the callback, with a label for diagnostics.
+    ///
+    /// Any callback previously present is dropped. For cells which receive
+    /// callbacks from many places, to tell which put the callback present:
+    /// the label appears as the `label` of `tracing` events and
+    /// [`Hooks`][crate::Hooks], in the `Debug` output, and, with `std`, in a message printed to
+    /// stderr if the callback panics when taken. It's also returned by
+    /// [`label`][Self::label].
+    ///
+    /// The label is kept in the callback's heap allocation, after the
+    /// callback's function pointer, so this makes only one heap allocation,
+    /// as `put` does, with room for one more pointer.
+    #[cfg(feature = "alloc")]
+    pub fn put_named<F: FnOnce() + Send + 'static>(&self, label: &'static str, f: F) {
+        #[cfg(feature = "disabled")]
+        {
+            let _ = label;
+            drop(f);
+            return;
+        }
+        #[allow(unreachable_code)]
+        let ptr = raw::alloc_raw_labeled(move |()| f(), label);
+        let old_ptr = self.0.swap(ptr, Ordering::Release);
+        trace::put("CallbackCell", self.addr(), None, type_name::<F>(), Some(label), !old_ptr.is_null());
+        unsafe { raw::drop_raw::<(), ()>(old_ptr) };
+    }
+
+    /// The label of the callback currently present, if it was put with
+    /// [`put_named`][Self::put_named].
+    ///
+    /// The label is read from the callback's heap allocation. So that another
+    /// thread can't free the callback while it's being read, the callback is
+    /// taken meanwhile, then put back: a take on another thread meanwhile
+    /// finds the cell empty. If a callback is put meanwhile, it's kept, and
+    /// the taken one is dropped, as if the put replaced it. The label also
+    /// appears in the `Debug` output, which reads it the same way.
+    #[cfg(feature = "alloc")]
+    pub fn label(&self) -> Option<&'static str> {
+        self.as_args().label()
+    }
+
+    /// Atomically set a static callback, without allocating.
+    ///
+    /// See [`CallbackCellArgs::put_ref`][crate::CallbackCellArgs::put_ref].
+    pub fn put_ref(&self, f: &'static StaticCallback<(), ()>) {
+        #[cfg(feature = "disabled")]
+        {
+            let _ = f;
+            return;
+        }
+        #[allow(unreachable_code)]
+        let old_ptr = self.0.swap(f.as_raw(), Ordering::Release);
+        trace::put("CallbackCell", self.addr(), None, type_name::<StaticCallback<(), ()>>(), None, !old_ptr.is_null());
+        unsafe { raw::drop_raw::<(), ()>(old_ptr) };
+    }
+
+    /// Atomically set the callback, handing any callback previously present
+    /// to the sink rather than dropping it inline.
+    ///
+    /// This is for when dropping a displaced callback is too slow to do on
+    /// the putting thread.
+    #[cfg(feature = "alloc")]
+    pub fn put_defer_drop<F, S>(&self, f: F, sink: &S)
+    where
+        F: FnOnce() + Send + 'static,
+        S: DropSink + ?Sized,
+    {
+        #[cfg(feature = "disabled")]
+        {
+            let _ = sink;
+            drop(f);
+            return;
+        }
+        #[allow(unreachable_code)]
+        let ptr = raw::alloc_raw(move |()| f());
+        let old_ptr = self.0.swap(ptr, Ordering::Release);
+        trace::put("CallbackCell", self.addr(), None, type_name::<F>(), None, !old_ptr.is_null());
+        if !old_ptr.is_null() {
+            sink.accept(unsafe { DisplacedCallback::new::<(), ()>(old_ptr) });
+        }
+    }
+
+    // atomically set the callback, tracing with the given name. returns
+    // whether it replaced a callback.
+    #[cfg(feature = "alloc")]
+    pub(crate) fn put_replacing<F: FnOnce() + Send + 'static>(&self, f: F, name: Option<&'static str>) -> bool {
+        #[cfg(feature = "disabled")]
+        {
+            let _ = name;
+            drop(f);
+            return false;
+        }
+        #[allow(unreachable_code)]
         unsafe {
             // allocate and initialize heap allocation
-            let (layout, callback_offset) = Layout::new::<unsafe fn(bool, *mut u8)>()
-                .extend(Layout::new::<F>()).unwrap();
-            let ptr = alloc(layout);
-            if ptr.is_null() {
-                handle_alloc_error(layout);
-            }
-            (ptr as *mut unsafe fn(bool, *mut u8)).write(fn_ptr_impl::<F>);
-            (ptr.add(callback_offset) as *mut F).write(f);
+            let ptr = raw::alloc_raw(move |()| f());
 
             // atomic put
-            let old_ptr = self.0.swap(ptr as usize, Ordering::Release);
+            let old_ptr = self.0.swap(ptr, Ordering::Release);
+            trace::put("CallbackCell", self.addr(), name, type_name::<F>(), None, !old_ptr.is_null());
 
             // clean up previous value
-            drop_raw(old_ptr as *mut u8);
+            raw::drop_raw::<(), ()>(old_ptr);
+            !old_ptr.is_null()
         }
     }
 
+    // address of the cell, for tracing.
+    fn addr(&self) -> *const () {
+        self as *const Self as *const ()
+    }
+
+    /// Whether a callback is currently present.
+    ///
+    /// This is only a snapshot: another thread may put or take a callback
+    /// immediately afterwards.
+    pub fn is_set(&self) -> bool {
+        !self.0.load(Ordering::Acquire).is_null()
+    }
+
+    /// The size, in bytes, of the heap allocation holding the callback, or 0
+    /// if no callback is present.
+    ///
+    /// This takes `&mut self` since, through a shared reference, another
+    /// thread could free the callback while it's being measured. See the
+    /// `alloc_stats` module, with the `alloc-stats` feature, for a
+    /// process-wide total.
+    pub fn allocated_bytes(&mut self) -> usize {
+        unsafe { raw::size_raw::<(), ()>(self.0.load_mut()) }
+    }
+
     /// Atomically take the callback then run it.
     ///
     /// Returns true if a callback was present.
     pub fn take_call(&self) -> bool {
+        self.take_call_named(None)
+    }
+
+    /// Like [`take_call`][Self::take_call], but first loads the cell, and
+    /// returns false without writing to it if it's empty.
+    ///
+    /// For cells polled far more often than they're put into: an empty
+    /// `take_call` still swaps, which takes the cache line exclusively, so
+    /// it bounces between the polling thread and the putting thread. This
+    /// only swaps once the load has seen a callback, and that swap still
+    /// decides who takes it, so no callback is run twice or lost.
+    ///
+    /// The difference is when an empty load races a put. A put which
+    /// happens before this, such as one on the same thread, or one whose
+    /// completion was signalled to this thread, is always seen. But a
+    /// concurrent put may be missed where a swap would have seen it, since
+    /// a load doesn't take part in the cell's total order of writes. So
+    /// code which relies on that order, rather than retrying, should use
+    /// `take_call`: for example, a producer which publishes an item then
+    /// takes the callback, racing a consumer which puts its callback then
+    /// checks for items, as in the crate's `seg_queue` example.
+    pub fn take_call_fast(&self) -> bool {
+        if self.0.load(Ordering::Relaxed).is_null() {
+            trace::take_empty("CallbackCell", self.addr(), None);
+            return false;
+        }
+        self.take_call_named(None)
+    }
+
+    // take_call, tracing with the given name.
+    pub(crate) fn take_call_named(&self, name: Option<&'static str>) -> bool {
         unsafe {
             // atomic take
-            let ptr = self.0.swap(0, Ordering::Acquire) as *mut u8;
+            let ptr = self.0.swap(ptr::null_mut(), Ordering::Acquire);
 
             // run it
             if !ptr.is_null() {
-                let fn_ptr = (ptr as *mut unsafe fn(bool, *mut u8)).read();
-                fn_ptr(true, ptr);
+                trace::call("CallbackCell", self.addr(), name, || raw::call_raw::<(), ()>(ptr, ()));
+                true
+            } else {
+                trace::take_empty("CallbackCell", self.addr(), name);
+                false
+            }
+        }
+    }
+
+    /// Like [`take_call`][Self::take_call], but leaves the callback's heap
+    /// allocation in the graveyard to be deallocated later, rather than
+    /// deallocating it on this thread.
+    ///
+    /// The callback still runs on this thread. For threads where
+    /// deallocating may block, such as real-time audio threads. See
+    /// [`Graveyard`].
+    #[cfg(feature = "alloc")]
+    pub fn take_call_defer_free(&self, graveyard: &Graveyard) -> bool {
+        unsafe {
+            let ptr = self.0.swap(ptr::null_mut(), Ordering::Acquire);
+            if !ptr.is_null() {
+                trace::call("CallbackCell", self.addr(), None, || {
+                    raw::call_raw_keep_alloc::<(), ()>(ptr, (), |freed| graveyard.bury(freed))
+                });
                 true
             } else {
+                trace::take_empty("CallbackCell", self.addr(), None);
                 false
             }
         }
     }
+
+    /// Like [`take_call`][Self::take_call], but aborts the process if the
+    /// callback panics, rather than unwinding.
+    ///
+    /// Use this where unwinding would be undefined behavior, such as when
+    /// called from an `extern "C"` function. A message naming the cell is
+    /// printed to stderr before aborting.
+    #[cfg(feature = "std")]
+    pub fn take_call_abort_on_panic(&self) -> bool {
+        self.take_call_abort_on_panic_named(None)
+    }
+
+    // take_call_abort_on_panic, tracing and aborting with the given name.
+    #[cfg(feature = "std")]
+    pub(crate) fn take_call_abort_on_panic_named(&self, name: Option<&'static str>) -> bool {
+        crate::abort::call("CallbackCell", name, || self.take_call_named(name))
+    }
+
+    /// Atomically take the callback and drop it without running it.
+    ///
+    /// Returns true if a callback was present.
+    pub fn clear(&self) -> bool {
+        self.clear_named(None)
+    }
+
+    // clear, tracing with the given name.
+    pub(crate) fn clear_named(&self, name: Option<&'static str>) -> bool {
+        unsafe {
+            let ptr = self.0.swap(ptr::null_mut(), Ordering::Acquire);
+            trace::clear("CallbackCell", self.addr(), name, !ptr.is_null());
+            raw::drop_raw::<(), ()>(ptr);
+            !ptr.is_null()
+        }
+    }
+
+    /// Atomically take the callback, then hand it to the spawner to run in the
+    /// background rather than running it inline.
+    ///
+    /// Returns true if a callback was present. If the spawner drops the
+    /// callback without running it, it is dropped as usual.
+    #[cfg(feature = "alloc")]
+    pub fn take_spawn<S: Spawn + ?Sized>(&self, spawner: &S) -> bool {
+        let ptr = self.0.swap(ptr::null_mut(), Ordering::Acquire);
+        if !ptr.is_null() {
+            let callback = unsafe { raw::Owned::<(), ()>::from_erased(ptr) };
+            spawner.spawn_boxed(Box::new(move || callback.call(())));
+            true
+        } else {
+            false
+        }
+    }
+
+    // atomically set the callback to the given non-null erased callback if no callback is present.
+    // returns whether it was set.
+    #[cfg(feature = "alloc")]
+    pub(crate) fn put_raw_if_empty(&self, ptr: *mut u8) -> bool {
+        #[cfg(feature = "disabled")]
+        {
+            unsafe { raw::drop_raw::<(), ()>(ptr) };
+            return true;
+        }
+        #[allow(unreachable_code)]
+        self.0
+            .compare_exchange(ptr::null_mut(), ptr, Ordering::Release, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Atomically take the callback without running it, as a raw pointer.
+    ///
+    /// Returns null if no callback was present. Otherwise, the pointer is the
+    /// crate's internal heap allocation for the callback, and must be passed
+    /// to exactly one of [`put_raw`][Self::put_raw],
+    /// [`run_raw`][Self::run_raw], or [`drop_raw_ptr`][Self::drop_raw_ptr],
+    /// exactly once, or else the callback is leaked.
+    pub fn take_raw(&self) -> *mut c_void {
+        self.0.swap(ptr::null_mut(), Ordering::Acquire) as *mut c_void
+    }
+
+    /// Atomically set the callback from a raw pointer, as returned by
+    /// [`take_raw`][Self::take_raw].
+    ///
+    /// Any callback previously present is dropped. If `ptr` is null, this
+    /// just clears the cell.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be null or a pointer returned by `take_raw` on a
+    /// `CallbackCell`, which has not yet been passed to any of these
+    /// functions.
+    pub unsafe fn put_raw(&self, ptr: *mut c_void) {
+        let old_ptr = self.0.swap(ptr as *mut u8, Ordering::Release);
+        raw::drop_raw::<(), ()>(old_ptr);
+    }
+
+    /// Run the callback behind a raw pointer, as returned by
+    /// [`take_raw`][Self::take_raw].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a non-null pointer returned by `take_raw` on a
+    /// `CallbackCell`, which has not yet been passed to any of these
+    /// functions.
+    pub unsafe fn run_raw(ptr: *mut c_void) {
+        raw::call_raw::<(), ()>(ptr as *mut u8, ());
+    }
+
+    /// Drop the callback behind a raw pointer, as returned by
+    /// [`take_raw`][Self::take_raw], without running it.
+    ///
+    /// Does nothing if `ptr` is null.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be null or a pointer returned by `take_raw` on a
+    /// `CallbackCell`, which has not yet been passed to any of these
+    /// functions.
+    pub unsafe fn drop_raw_ptr(ptr: *mut c_void) {
+        raw::drop_raw::<(), ()>(ptr as *mut u8);
+    }
+
+    /// Get a C-style callback which calls [`take_call`][Self::take_call] on
+    /// this cell, in the form of a function pointer and context pointer, as
+    /// in `void (*cb)(void *ctx)` and `void *ctx`.
+    ///
+    /// The function pointer is only valid to call with the returned context
+    /// pointer. Since `self` is `'static`, it may be called any number of
+    /// times, from any thread.
+    ///
+    /// If the callback panics, the process is aborted, since unwinding out of
+    /// an `extern "C"` function is not possible.
+    pub fn as_c_trampoline(&'static self) -> (unsafe extern "C" fn(*mut c_void), *mut c_void) {
+        unsafe extern "C" fn trampoline(ctx: *mut c_void) {
+            (*(ctx as *const CallbackCell)).take_call();
+        }
+
+        (trampoline, self as *const CallbackCell as *mut c_void)
+    }
 }
 
-impl Drop for CallbackCell {
-    fn drop(&mut self) {
+#[cfg(all(feature = "alloc", target_has_atomic = "ptr"))]
+impl CallbackCell {
+    /// Convert into a [`Waker`] which calls [`take_call`][Self::take_call]
+    /// when woken.
+    ///
+    /// See the [`Wake`] impl.
+    pub fn into_waker(self: Arc<Self>) -> Waker {
+        Waker::from(self)
+    }
+
+    /// Atomically set the callback to call `f` with `receiver`.
+    ///
+    /// This is the same as putting `move || f(receiver)`, but the callback's
+    /// type depends only on `T`, so putting many different methods of the
+    /// same receiver type doesn't generate separate code for each. This
+    /// makes one heap allocation, of four pointers whatever the receiver
+    /// type, holding the `Arc`'s pointer and the function pointer. The `Arc`
+    /// is released exactly once, whether the callback runs or is dropped.
+    pub fn put_method<T: Send + Sync + 'static>(&self, receiver: Arc<T>, f: fn(Arc<T>)) {
+        #[cfg(feature = "disabled")]
+        {
+            let _ = f;
+            drop(receiver);
+            return;
+        }
+        #[allow(unreachable_code)]
+        let ptr = raw::alloc_raw_method(receiver, f);
+        let old_ptr = self.0.swap(ptr, Ordering::Release);
+        trace::put("CallbackCell", self.addr(), None, type_name::<fn(Arc<T>)>(), None, !old_ptr.is_null());
+        unsafe { raw::drop_raw::<(), ()>(old_ptr) };
+    }
+
+    /// Atomically set the callback to a shared handler, without allocating.
+    ///
+    /// The handler is called through the `Arc`, which is then released, when
+    /// the callback runs. If the callback is dropped instead, the `Arc` is
+    /// just released. The handler itself isn't copied, and nothing is
+    /// allocated: the cell holds the `Arc`'s pointer. See [`SharedCallback`].
+    pub fn put_arc<F: ?Sized>(&self, f: Arc<SharedCallback<(), (), F>>) {
+        #[cfg(feature = "disabled")]
+        {
+            drop(f);
+            return;
+        }
+        #[allow(unreachable_code)]
+        let old_ptr = self.0.swap(SharedCallback::into_raw(f), Ordering::Release);
+        trace::put("CallbackCell", self.addr(), None, type_name::<SharedCallback<(), (), F>>(), None, !old_ptr.is_null());
+        unsafe { raw::drop_raw::<(), ()>(old_ptr) };
+    }
+}
+
+/// Waking takes and runs the callback currently present, if any.
+///
+/// Since the callback is one-shot, only the first wake after a `put` runs
+/// anything; later wakes do nothing until another callback is put. This is
+/// true of `wake_by_ref` as well as `wake`.
+#[cfg(all(feature = "alloc", target_has_atomic = "ptr"))]
+impl Wake for CallbackCell {
+    fn wake(self: Arc<Self>) {
+        self.take_call();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.take_call();
+    }
+}
+
+impl CallbackCell {
+    // drop any callback present, as when dropping the cell, tracing with the
+    // given name.
+    pub(crate) fn drop_named(&mut self, name: Option<&'static str>) {
+        let ptr = self.0.load_mut();
+        if !ptr.is_null() {
+            trace::drop_pending("CallbackCell", self.addr(), name);
+        }
         unsafe {
-            drop_raw(*self.0.get_mut() as *mut u8);
+            raw::drop_raw::<(), ()>(ptr);
         }
+        self.0 = CellPtr::new(ptr::null_mut());
+    }
+}
+
+impl CallbackCell {
+    /// View this cell as a [`CallbackCellArgs<(), ()>`].
+    ///
+    /// Both refer to the same callback.
+    pub fn as_args(&self) -> &CallbackCellArgs<(), ()> {
+        // safety: see the internals comment in the `with_args` module.
+        unsafe { &*(self as *const Self as *const CallbackCellArgs<(), ()>) }
+    }
+
+    // take the callback pointer out of the cell, leaving it empty.
+    pub(crate) fn into_ptr(mut self) -> *mut u8 {
+        let ptr = self.0.load_mut();
+        self.0 = CellPtr::new(ptr::null_mut());
+        ptr
     }
 }
 
-// implementation for the function pointer for a given callback type F.
-unsafe fn fn_ptr_impl<F: FnOnce() + Send + 'static>(run: bool, ptr: *mut u8) {
-    // extract callback value from heap allocation and free heap allocation
-    let (layout, callback_offset) = Layout::new::<unsafe fn(bool, *mut u8)>()
-        .extend(Layout::new::<F>()).unwrap();
-    let f = (ptr.add(callback_offset) as *mut F).read();
-    dealloc(ptr, layout);
+/// Moves any callback across, without running it.
+impl From<CallbackCellArgs<(), ()>> for CallbackCell {
+    fn from(cell: CallbackCellArgs<(), ()>) -> Self {
+        CallbackCell(CellPtr::new(cell.into_ptr()))
+    }
+}
 
-    // this part is basically safe code
-    if run {
-        f();
+impl Drop for CallbackCell {
+    fn drop(&mut self) {
+        self.drop_named(None);
     }
 }
 
-// drop the pointed to data, including freeing the heap allocation, without running the callback,
-// if the pointer is non-null.
-unsafe fn drop_raw(ptr: *mut u8) {
-    if !ptr.is_null() {
-        let fn_ptr = (ptr as *mut unsafe fn(bool, *mut u8)).read();
-        fn_ptr(false, ptr);
+/// Same as [`CallbackCell::new_with`].
+#[cfg(feature = "alloc")]
+impl<F: FnOnce() + Send + 'static> From<F> for CallbackCell {
+    fn from(f: F) -> Self {
+        Self::new_with(f)
     }
 }
 
@@ -116,12 +661,16 @@ impl Default for CallbackCell {
     }
 }
 
+/// Same as for [`CallbackCellArgs`].
 impl Debug for CallbackCell {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        if (self.0.load(Ordering::Relaxed) as *const ()).is_null() {
-            f.write_str("CallbackCell(NULL)")
-        } else {
-            f.write_str("CallbackCell(NOT NULL)")
-        }
+        self.as_args().fmt_named(f, "CallbackCell")
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for CallbackCell {
+    fn format(&self, f: defmt::Formatter) {
+        self.as_args().format_named(f, "CallbackCell")
     }
 }
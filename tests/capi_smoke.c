@@ -0,0 +1,24 @@
+/* compiled (but not linked) by the `capi_header_test` test, to check that the
+ * header is usable from C. */
+
+#include <stdlib.h>
+#include "callback_cell.h"
+
+static void increment(void *data) {
+    *(int *)data += 1;
+}
+
+static void free_data(void *data) {
+    free(data);
+}
+
+int callback_cell_smoke(void) {
+    callback_cell_t *cell = callback_cell_new();
+    int counter = 0;
+    int *boxed = malloc(sizeof(int));
+    callback_cell_put(cell, increment, &counter, NULL);
+    callback_cell_take_call(cell);
+    callback_cell_put(cell, increment, boxed, free_data);
+    callback_cell_free(cell);
+    return counter;
+}
@@ -0,0 +1,211 @@
+// tests for the `disabled` feature, under which `CallbackCell` and
+// `CallbackCellArgs` are zero-sized no-ops:
+//
+//     cargo test --features disabled --test disabled
+//
+// the crate's unit tests cover the cells as they are without it.
+
+use callback_cell::{
+    ArgsSlot,
+    CallbackCell,
+    CallbackCellArgs,
+    CallbackCellInput,
+    CallbackCellOutput,
+    DisplacedCallback,
+    Notifier,
+    NotifyCallbackCell,
+    SharedCallback,
+    StaticCallback,
+};
+use std::{
+    mem,
+    sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    },
+};
+
+// a callback which counts its runs, holding a reference to the count.
+fn tracked(ran: &Arc<AtomicU32>) -> impl FnOnce() + Send + 'static {
+    let ran = Arc::clone(ran);
+    move || {
+        ran.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn zero_sized() {
+    assert_eq!(mem::size_of::<CallbackCell>(), 0);
+    assert_eq!(mem::size_of::<CallbackCellArgs<[u64; 4], String>>(), 0);
+    assert_eq!(mem::size_of::<CallbackCellInput<u32>>(), 0);
+    assert_eq!(mem::size_of::<CallbackCellOutput<u32>>(), 0);
+}
+
+#[test]
+fn callback_cell_is_a_no_op() {
+    static CELL: CallbackCell = CallbackCell::new();
+    let ran = Arc::new(AtomicU32::new(0));
+
+    // puts drop the callback immediately, and takes find the cell empty
+    CELL.put(tracked(&ran));
+    assert_eq!(Arc::strong_count(&ran), 1);
+    assert!(!CELL.is_set());
+    assert!(!CELL.take_call());
+    assert!(!CELL.take_call_fast());
+    assert!(!CELL.clear());
+    assert!(CELL.take_raw().is_null());
+
+    let cell = CallbackCell::new_with(tracked(&ran));
+    assert_eq!(Arc::strong_count(&ran), 1);
+    cell.put_with_cleanup(tracked(&ran), tracked(&ran));
+    assert_eq!(ran.load(Ordering::SeqCst), 1, "the cleanup runs, as the callback is discarded");
+    assert_eq!(Arc::strong_count(&ran), 1);
+    assert!(!cell.take_call());
+    assert_eq!(format!("{:?}", cell), "CallbackCell(NULL)");
+    assert_eq!(format!("{:?}", CallbackCell::default()), "CallbackCell(NULL)");
+    assert_eq!(ran.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn callback_cell_args_is_a_no_op() {
+    let ran = Arc::new(AtomicU32::new(0));
+    let cell = CallbackCellArgs::<u32, u32>::new();
+    let f = tracked(&ran);
+    cell.put(move |n| {
+        f();
+        n + 1
+    });
+    assert_eq!(Arc::strong_count(&ran), 1);
+    assert_eq!(cell.take_call(1), Err(1));
+    assert!(!cell.is_set());
+
+    let token = cell.put_tracked(|n| n);
+    assert!(cell.replace_if_current(&token, |n| n).is_err());
+    assert!(!cell.put_around(|inner, n| inner(n)));
+    assert_eq!(format!("{:?}", cell), "CallbackCellArgs(NULL)");
+
+    let output = CallbackCellOutput::<u32>::new();
+    output.put(|| 1);
+    assert_eq!(output.take_call(), None);
+    let input = CallbackCellInput::<u32>::new();
+    input.put(|_| ());
+    assert_eq!(input.take_call(1), Err(1));
+    assert_eq!(ran.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn callback_cell_put_variants_drop_immediately() {
+    static STATIC: StaticCallback<(), ()> = StaticCallback::new(&|()| panic!("never runs"));
+    let ran = Arc::new(AtomicU32::new(0));
+    let cell = CallbackCell::new();
+
+    let f = tracked(&ran);
+    cell.put_with_bytes(&[1, 2, 3], move |_| f());
+    assert_eq!(Arc::strong_count(&ran), 1);
+    cell.put_named("named", tracked(&ran));
+    assert_eq!(Arc::strong_count(&ran), 1);
+    cell.put_ref(&STATIC);
+    let sink = |_: DisplacedCallback| panic!("nothing is displaced");
+    cell.put_defer_drop(tracked(&ran), &sink);
+    assert_eq!(Arc::strong_count(&ran), 1);
+    cell.put_method(Arc::clone(&ran), |ran| {
+        ran.fetch_add(1, Ordering::SeqCst);
+    });
+    assert_eq!(Arc::strong_count(&ran), 1);
+    let shared = Arc::new(SharedCallback::new(|()| panic!("never runs")));
+    cell.put_arc(Arc::clone(&shared));
+    assert_eq!(Arc::strong_count(&shared), 1);
+
+    let mut cell = cell;
+    assert!(!cell.is_set());
+    assert_eq!(cell.allocated_bytes(), 0);
+    assert_eq!(cell.label(), None);
+    assert!(!cell.take_call());
+    assert_eq!(ran.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn callback_cell_args_put_variants_drop_immediately() {
+    static STATIC: StaticCallback<u32, u32> = StaticCallback::new(&|_| panic!("never runs"));
+    let ran = Arc::new(AtomicU32::new(0));
+    let cell = CallbackCellArgs::<u32, u32>::new();
+    // a callback which holds a reference to the count.
+    let holding = |ran: &Arc<AtomicU32>| {
+        let f = tracked(ran);
+        move |n: u32| {
+            f();
+            n
+        }
+    };
+
+    let f = holding(&ran);
+    cell.put_with_bytes(&[1, 2, 3], move |_, n| f(n));
+    assert_eq!(Arc::strong_count(&ran), 1);
+    cell.put_named("named", holding(&ran));
+    assert_eq!(Arc::strong_count(&ran), 1);
+    cell.put_ref(&STATIC);
+    let f = holding(&ran);
+    assert!(cell.put_prealloc(ArgsSlot::with_capacity(64, 8), f).is_ok());
+    assert_eq!(Arc::strong_count(&ran), 1);
+    let sink = |_: DisplacedCallback| panic!("nothing is displaced");
+    cell.put_defer_drop(holding(&ran), &sink);
+    assert_eq!(Arc::strong_count(&ran), 1);
+    cell.put_with_cleanup(holding(&ran), tracked(&ran));
+    assert_eq!(ran.load(Ordering::SeqCst), 1, "the cleanup runs, as the callback is discarded");
+    assert_eq!(Arc::strong_count(&ran), 1);
+    cell.put_map_input(|n: u32| n + 1, holding(&ran));
+    assert_eq!(Arc::strong_count(&ran), 1);
+    cell.put_map_output(holding(&ran), |n| n + 1);
+    assert_eq!(Arc::strong_count(&ran), 1);
+
+    let token = cell.put_tracked(holding(&ran));
+    assert_eq!(Arc::strong_count(&ran), 1);
+    // the callback is handed back, as for a token whose callback is gone
+    let f = cell.replace_if_current(&token, holding(&ran)).expect_err("nothing is current");
+    assert_eq!(Arc::strong_count(&ran), 2);
+    drop(f);
+
+    cell.put_method(Arc::clone(&ran), |ran, n| {
+        ran.fetch_add(1, Ordering::SeqCst);
+        n
+    });
+    assert_eq!(Arc::strong_count(&ran), 1);
+    let shared = Arc::new(SharedCallback::new(|_| panic!("never runs")));
+    cell.put_arc(Arc::clone(&shared));
+    assert_eq!(Arc::strong_count(&shared), 1);
+
+    let mut cell = cell;
+    assert!(!cell.is_set());
+    assert_eq!(cell.allocated_bytes(), 0);
+    assert_eq!(cell.label(), None);
+    assert_eq!(cell.take_call(1), Err(1));
+    assert_eq!(Arc::strong_count(&ran), 1);
+    assert_eq!(ran.load(Ordering::SeqCst), 1);
+
+    let input = CallbackCellInput::<u32>::new();
+    let f = holding(&ran);
+    input.put_defer_drop(move |n| { f(n); }, &sink);
+    let output = CallbackCellOutput::<u32>::new();
+    let f = holding(&ran);
+    output.put_defer_drop(move || f(1), &sink);
+    assert_eq!(Arc::strong_count(&ran), 1);
+}
+
+#[test]
+fn notify_callback_cell_never_notifies() {
+    static NOTIFIED: AtomicU32 = AtomicU32::new(0);
+    let ran = Arc::new(AtomicU32::new(0));
+    let mut cell = NotifyCallbackCell::new(Notifier::from_fn(|| {
+        NOTIFIED.fetch_add(1, Ordering::SeqCst);
+    }));
+    cell.put(tracked(&ran));
+    assert_eq!(Arc::strong_count(&ran), 1);
+    assert!(!cell.is_set());
+    cell.put(tracked(&ran));
+    assert!(!cell.take_call());
+    cell.put(tracked(&ran));
+    let _ = cell.set_notifier(Notifier::from_fn(|| panic!("never notified")));
+    cell.put(tracked(&ran));
+    assert_eq!(NOTIFIED.load(Ordering::SeqCst), 0);
+    assert_eq!(ran.load(Ordering::SeqCst), 0);
+}
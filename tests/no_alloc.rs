@@ -0,0 +1,85 @@
+// tests for what's left without the `alloc` feature, which build and pass
+// with any features:
+//
+//     cargo test --no-default-features --test no_alloc
+//
+// only the crate is built without `alloc`: the tests themselves use std. the
+// cells' tests need them to hold callbacks, so not with the `disabled` feature.
+
+use callback_cell::{WakerCell, RegisterOutcome};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    },
+    task::{Wake, Waker},
+    thread,
+};
+
+struct CountingWaker(AtomicU32);
+
+impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+fn counting_waker() -> (Arc<CountingWaker>, Waker) {
+    let arc = Arc::new(CountingWaker(AtomicU32::new(0)));
+    (Arc::clone(&arc), Waker::from(arc))
+}
+
+#[test]
+fn waker_cell_no_alloc() {
+    static CELL: WakerCell = WakerCell::new();
+
+    let (count, waker) = counting_waker();
+    assert_eq!(CELL.register(&waker), RegisterOutcome::Registered);
+    assert!(CELL.wake());
+    assert_eq!(count.0.load(Ordering::SeqCst), 1);
+    assert!(!CELL.wake());
+
+    // a wake from another thread is never lost
+    assert_eq!(CELL.register(&waker), RegisterOutcome::Registered);
+    thread::spawn(|| CELL.wake()).join().unwrap();
+    assert_eq!(count.0.load(Ordering::SeqCst), 2);
+}
+
+#[cfg(not(feature = "disabled"))]
+#[test]
+fn static_callbacks_no_alloc() {
+    use callback_cell::{CallbackCell, CallbackCellArgs, StaticCallback};
+
+    static RAN: AtomicU32 = AtomicU32::new(0);
+    static BUMP: StaticCallback<(), ()> = StaticCallback::new(&|()| {
+        RAN.fetch_add(1, Ordering::SeqCst);
+    });
+    static DOUBLE: StaticCallback<u32, u32> = StaticCallback::new(&|i| i * 2);
+
+    fn triple(i: u32) -> u32 {
+        i * 3
+    }
+
+    let mut cell = CallbackCell::new();
+    cell.put_ref(&BUMP);
+    assert!(cell.is_set());
+    assert_eq!(cell.allocated_bytes(), 0);
+    assert!(cell.take_call());
+    assert!(!cell.take_call());
+    assert_eq!(RAN.load(Ordering::SeqCst), 1);
+
+    // replacing or clearing a static callback drops nothing
+    cell.put_ref(&BUMP);
+    cell.put_ref(&BUMP);
+    assert!(cell.clear());
+    cell.put_ref(&BUMP);
+    let ptr = cell.take_raw();
+    unsafe { CallbackCell::run_raw(ptr) };
+    assert_eq!(RAN.load(Ordering::SeqCst), 2);
+
+    static CELL: CallbackCellArgs<u32, u32> = CallbackCellArgs::new_with_fn(triple);
+    assert_eq!(CELL.take_call(2), Ok(6));
+    assert_eq!(CELL.take_call(2), Err(2));
+    CELL.put_ref(&DOUBLE);
+    assert_eq!(CELL.take_call_fast(4), Ok(8));
+}
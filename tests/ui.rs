@@ -0,0 +1,12 @@
+// compile tests for the cells' Send, Sync, and 'static requirements, for
+// their variance, and for the types generated by `define_callback!`.
+
+// the pass tests run their callbacks, which the `disabled` feature compiles
+// out.
+#[test]
+#[cfg_attr(feature = "disabled", ignore)]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+    t.pass("tests/ui/pass/*.rs");
+}
@@ -0,0 +1,11 @@
+use callback_cell::AsyncCallbackCell;
+use std::rc::Rc;
+
+fn main() {
+    let cell = AsyncCallbackCell::new();
+    cell.put(|| async {
+        let rc = Rc::new(());
+        std::future::ready(()).await;
+        drop(rc);
+    });
+}
@@ -0,0 +1,9 @@
+callback_cell::define_callback! {
+    OnFrame: Fn<(u32,), ()>;
+}
+
+fn main() {
+    let on_frame = OnFrame::new();
+    let mut frames = Vec::new();
+    on_frame.put(move |i| frames.push(i));
+}
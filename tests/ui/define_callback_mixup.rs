@@ -0,0 +1,13 @@
+callback_cell::define_callback! {
+    OnOpen: FnOnce<(u32,), ()>;
+    OnClose: FnOnce<(u32,), ()>;
+}
+
+fn register_on_open(cell: &OnOpen) {
+    cell.put(|_| ());
+}
+
+fn main() {
+    let on_close = OnClose::new();
+    register_on_open(&on_close);
+}
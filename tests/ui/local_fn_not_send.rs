@@ -0,0 +1,6 @@
+use callback_cell::LocalCallbackCellFn;
+
+fn main() {
+    let cell = LocalCallbackCellFn::<(), ()>::new();
+    std::thread::spawn(move || cell.call(()));
+}
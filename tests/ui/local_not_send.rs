@@ -0,0 +1,6 @@
+use callback_cell::LocalCallbackCell;
+
+fn main() {
+    let cell = LocalCallbackCell::new();
+    std::thread::spawn(move || cell.take_call());
+}
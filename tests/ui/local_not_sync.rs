@@ -0,0 +1,8 @@
+use callback_cell::LocalCallbackCellArgs;
+
+fn main() {
+    let cell = LocalCallbackCellArgs::<u32, u32>::new();
+    std::thread::scope(|s| {
+        s.spawn(|| cell.take_call(1));
+    });
+}
@@ -0,0 +1,9 @@
+use callback_cell::AtomicOptionBox;
+use std::rc::Rc;
+
+fn main() {
+    let cell = AtomicOptionBox::<Rc<()>>::new();
+    std::thread::scope(|s| {
+        s.spawn(|| cell.take());
+    });
+}
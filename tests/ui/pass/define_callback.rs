@@ -0,0 +1,21 @@
+// generated types can be public, generic, and documented, in a crate with
+// missing docs denied.
+
+#![deny(missing_docs)]
+//! Hooks.
+
+callback_cell::define_callback! {
+    /// Called after each frame.
+    pub OnFrame: Fn<(u64, &'static str), ()>;
+    /// Called once, with the payload.
+    pub OnPayload<T>: FnOnce<(T,), T>;
+}
+
+fn main() {
+    let on_frame = OnFrame::new();
+    on_frame.put(|_, _| ());
+    assert_eq!(on_frame.call((1, "one")), Ok(()));
+    let on_payload = OnPayload::<String>::new();
+    on_payload.put(|s| s + "!");
+    assert_eq!(on_payload.take_call(("hi".to_owned(),)).unwrap(), "hi!");
+}
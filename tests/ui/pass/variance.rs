@@ -0,0 +1,13 @@
+// the args cells can be used at a single non-'static lifetime.
+
+use callback_cell::CallbackCellArgs;
+
+fn same<'a>(cell: &CallbackCellArgs<&'a str, &'a str>, s: &'a str) -> &'a str {
+    cell.take_call(s).unwrap_or_else(|s| s)
+}
+
+fn main() {
+    let s = String::from("hello");
+    let cell = CallbackCellArgs::new();
+    assert_eq!(same(&cell, &s), "hello");
+}
@@ -0,0 +1,8 @@
+use callback_cell::CallbackCell;
+use std::rc::Rc;
+
+fn main() {
+    let cell = CallbackCell::new();
+    let rc = Rc::new(());
+    cell.put(move || drop(rc));
+}
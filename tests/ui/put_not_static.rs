@@ -0,0 +1,7 @@
+use callback_cell::CallbackCellArgs;
+
+fn main() {
+    let cell = CallbackCellArgs::<u32, u32>::new();
+    let n = 1;
+    cell.put(|i| i + n);
+}
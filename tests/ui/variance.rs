@@ -0,0 +1,29 @@
+// the args cells are invariant in both `I` and `O`.
+
+use callback_cell::{CallbackCellArgs, AsyncCallbackCellArgs, LocalCallbackCellArgs};
+
+fn input_covariant<'a>(cell: CallbackCellArgs<&'static str, ()>) -> CallbackCellArgs<&'a str, ()> {
+    cell
+}
+
+fn input_contravariant<'a>(cell: CallbackCellArgs<&'a str, ()>) -> CallbackCellArgs<&'static str, ()> {
+    cell
+}
+
+fn output_covariant<'a>(cell: CallbackCellArgs<(), &'static str>) -> CallbackCellArgs<(), &'a str> {
+    cell
+}
+
+fn output_contravariant<'a>(cell: CallbackCellArgs<(), &'a str>) -> CallbackCellArgs<(), &'static str> {
+    cell
+}
+
+fn async_covariant<'a>(cell: AsyncCallbackCellArgs<&'static str, &'static str>) -> AsyncCallbackCellArgs<&'a str, &'a str> {
+    cell
+}
+
+fn local_covariant<'a>(cell: LocalCallbackCellArgs<&'static str, &'static str>) -> LocalCallbackCellArgs<&'a str, &'a str> {
+    cell
+}
+
+fn main() {}
@@ -0,0 +1,96 @@
+// tests for the `auto` module, through code generic over its cells and
+// `MaybeSend`, which build and pass on any target. on single-threaded
+// WebAssembly, where the cells are the local ones, they also put callbacks
+// capturing an `Rc`, which isn't `Send`:
+//
+//     cargo check --tests --target wasm32-unknown-unknown
+//     CARGO_TARGET_WASM32_WASIP1_RUNNER=wasmtime cargo test --target wasm32-wasip1 --test wasm
+//
+// `wasm32-unknown-unknown` has no runner for plain `#[test]`s, so it's only
+// checked there, and run on `wasm32-wasip1`, which is single-threaded too.
+// the tests need the cells to hold callbacks, so not with the `disabled`
+// feature.
+
+#![cfg(not(feature = "disabled"))]
+
+use callback_cell::auto::{CallbackCell, CallbackCellArgs, MaybeSend};
+
+// a widget whose handlers are put from the same code on every target.
+#[derive(Default)]
+struct Button {
+    on_click: CallbackCell,
+    on_key: CallbackCellArgs<char, bool>,
+}
+
+impl Button {
+    fn on_click<F: FnOnce() + MaybeSend + 'static>(&self, f: F) {
+        self.on_click.put(f);
+    }
+
+    fn on_key<F: FnOnce(char) -> bool + MaybeSend + 'static>(&self, f: F) {
+        self.on_key.put(f);
+    }
+
+    fn click(&self) -> bool {
+        self.on_click.take_call()
+    }
+
+    fn press(&self, key: char) -> Result<bool, char> {
+        self.on_key.take_call(key)
+    }
+}
+
+// puts `count` as both handlers, then fires each twice, checking only the
+// first of each runs.
+fn fire_twice<C: Fn() + Clone + MaybeSend + 'static>(count: C) {
+    let button = Button::default();
+    button.on_click(count.clone());
+    button.on_key(move |key| {
+        count();
+        key == 'q'
+    });
+    assert!(button.click());
+    assert!(!button.click());
+    assert_eq!(button.press('q'), Ok(true));
+    assert_eq!(button.press('q'), Err('q'));
+}
+
+#[test]
+fn arc_callbacks() {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    };
+
+    let count = Arc::new(AtomicU32::new(0));
+    fire_twice({
+        let count = Arc::clone(&count);
+        move || {
+            count.fetch_add(1, Ordering::SeqCst);
+        }
+    });
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+}
+
+#[cfg(all(target_family = "wasm", not(target_feature = "atomics")))]
+#[test]
+fn rc_callbacks() {
+    use std::{cell::Cell, rc::Rc};
+
+    let count = Rc::new(Cell::new(0));
+    fire_twice({
+        let count = Rc::clone(&count);
+        move || count.set(count.get() + 1)
+    });
+    assert_eq!(count.get(), 2);
+
+    // dropped along with the cell, without running
+    let dropped = Rc::new(());
+    let button = Button::default();
+    button.on_click({
+        let dropped = Rc::clone(&dropped);
+        move || drop(dropped)
+    });
+    drop(button);
+    assert_eq!(Rc::strong_count(&dropped), 1);
+}